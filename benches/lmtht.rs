@@ -15,7 +15,7 @@ use lmtht::{Hash, Index, HASH_SIZE, LMTHT, MemStorage};
 fn bench_append(c: &mut Criterion) {
   let file = temp_file("bench", ".db");
   let _db = LMTHT::new(file.clone()).unwrap();
-  let mut db = LMTHT::new(MemStorage::new()).unwrap();
+  let db = LMTHT::new(MemStorage::new()).unwrap();
   let data = &[0u8; 1024];
   c.bench_function("LMTHT append", |b| b.iter(|| db.append(data).unwrap()));
   remove_file(&file).unwrap();
@@ -0,0 +1,133 @@
+//! 複数のスレッドからの値の追加を、LMTHT を所有する単一の書き込みスレッドに集約するキューです。
+//!
+//! [`Appender::spawn()`] は LMTHT の所有権を書き込みスレッドに移し、複製可能なハンドル [`Appender`] を返します。
+//! 各スレッドは [`Appender::append()`] で値をキューに投入し、返された [`Receipt`] から追加後のルートノードを受け
+//! 取ります。書き込みスレッドはキューに溜まっている値をまとめて 1 つのバッチとして追加します。
+//!
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{spawn, JoinHandle};
+
+use crate::error::Detail::{self, AppenderClosed, BackgroundAppendFailed};
+use crate::{Node, Result, Storage, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// 書き込みスレッドが 1 つのバッチとしてまとめて追加する値の最大数のデフォルト値です。
+pub const DEFAULT_APPENDER_BATCH_SIZE: usize = 1024;
+
+/// 書き込みスレッドに値の追加を依頼するハンドルです。複製したハンドルは同じ書き込みスレッドを共有します。
+///
+/// すべてのハンドルが `drop()` されるとキューに残っている値を追加した後に書き込みスレッドが終了し、
+/// [`Appender::spawn()`] が返した [`JoinHandle`] から LMTHT を取り戻すことができます。
+#[derive(Clone)]
+pub struct Appender {
+  queue: SyncSender<Request>,
+}
+
+/// [`Appender::append()`] で投入した値の追加結果を受け取るための受領証です。
+pub struct Receipt {
+  reply: Receiver<Reply>,
+}
+
+/// 書き込みスレッドから返される追加結果です。バッチ全体の失敗は同じエラーを複数の受領証で共有します。
+type Reply = std::result::Result<Node, Arc<Detail>>;
+
+struct Request {
+  value: Vec<u8>,
+  reply: Sender<Reply>,
+}
+
+impl Appender {
+  /// 指定された LMTHT を所有する書き込みスレッドを開始します。`capacity` はキューに投入できる未処理の値の最大数で
+  /// あり、キューが満杯の場合 [`Appender::append()`] は空きができるまで待機します。
+  pub fn spawn<S: Storage + 'static>(db: LMTHT<S>, capacity: usize) -> (Appender, JoinHandle<LMTHT<S>>)
+  where
+    LMTHT<S>: Send,
+  {
+    let (queue, requests) = sync_channel(capacity);
+    let handle = spawn(move || run(db, requests));
+    (Appender { queue }, handle)
+  }
+
+  /// 指定された値の追加をキューに投入します。キューが満杯の場合は空きができるまで待機します。
+  ///
+  /// # Returns
+  /// 追加が完了した時点でルートノードを受け取ることができる [`Receipt`] を返します。
+  pub fn append(&self, value: Vec<u8>) -> Result<Receipt> {
+    let (reply, receiver) = channel();
+    self.queue.send(Request { value, reply }).map_err(|_| AppenderClosed)?;
+    Ok(Receipt { reply: receiver })
+  }
+}
+
+impl Receipt {
+  /// 追加が完了するまで待機し、追加後のルートノードを返します。
+  ///
+  /// 書き込みスレッドでの追加に失敗した場合は、その原因となったエラーを `source` に保持する
+  /// [`Detail::BackgroundAppendFailed`] を返します。
+  pub fn wait(self) -> Result<Node> {
+    match self.reply.recv() {
+      Ok(Ok(root)) => Ok(root),
+      Ok(Err(source)) => Err(BackgroundAppendFailed { source }),
+      Err(_) => Err(AppenderClosed),
+    }
+  }
+
+  /// 追加が完了している場合はそのルートノードを返します。まだ完了していない場合は `None` を返します。
+  pub fn try_wait(&self) -> Result<Option<Node>> {
+    match self.reply.try_recv() {
+      Ok(Ok(root)) => Ok(Some(root)),
+      Ok(Err(source)) => Err(BackgroundAppendFailed { source }),
+      Err(TryRecvError::Empty) => Ok(None),
+      Err(TryRecvError::Disconnected) => Err(AppenderClosed),
+    }
+  }
+}
+
+fn run<S: Storage>(db: LMTHT<S>, requests: Receiver<Request>) -> LMTHT<S> {
+  while let Ok(request) = requests.recv() {
+    // キューに溜まっている値をまとめて 1 つのバッチとする
+    let mut pending = vec![request];
+    while pending.len() < DEFAULT_APPENDER_BATCH_SIZE {
+      match requests.try_recv() {
+        Ok(request) => pending.push(request),
+        Err(_) => break,
+      }
+    }
+
+    let mut batch = match db.begin_batch() {
+      Ok(batch) => batch,
+      Err(err) => {
+        reply_all(pending.iter().map(|request| &request.reply), err);
+        continue;
+      }
+    };
+    let mut appended = Vec::with_capacity(pending.len());
+    for Request { value, reply } in pending {
+      match batch.append(&value) {
+        Ok(root) => appended.push((reply, root)),
+        Err(err) => {
+          let _ = reply.send(Err(Arc::new(err)));
+        }
+      }
+    }
+    match batch.commit() {
+      Ok(_) => {
+        for (reply, root) in appended {
+          let _ = reply.send(Ok(root));
+        }
+      }
+      Err(err) => reply_all(appended.iter().map(|(reply, _)| reply), err),
+    }
+  }
+  db
+}
+
+fn reply_all<'a>(replies: impl Iterator<Item = &'a Sender<Reply>>, err: Detail) {
+  let err = Arc::new(err);
+  for reply in replies {
+    let _ = reply.send(Err(err.clone()));
+  }
+}
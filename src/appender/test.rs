@@ -0,0 +1,55 @@
+use std::thread::spawn;
+
+use crate::error::Detail;
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// 複数のスレッドから Appender を経由して追加した値がすべて LMTHT に含まれることを検証します。
+#[test]
+fn test_appender() {
+  const THREADS: Index = 8;
+  const N: Index = 50;
+  let (appender, handle) = Appender::spawn(LMTHT::new(MemStorage::new()).unwrap(), 16);
+  let producers = (0..THREADS)
+    .map(|t| {
+      let appender = appender.clone();
+      spawn(move || {
+        let receipts = (0..N).map(|k| appender.append(random_payload(PAYLOAD_SIZE, t * N + k)).unwrap());
+        receipts.collect::<Vec<_>>().into_iter().map(|receipt| receipt.wait().unwrap()).collect::<Vec<_>>()
+      })
+    })
+    .collect::<Vec<_>>();
+  let mut roots = producers.into_iter().flat_map(|producer| producer.join().unwrap()).collect::<Vec<_>>();
+  drop(appender);
+  let db = handle.join().unwrap();
+
+  // すべての値がいずれかの世代として 1 度ずつ追加されている
+  assert_eq!(THREADS * N, db.n());
+  roots.sort_by_key(|root| root.i);
+  assert!(roots.iter().enumerate().all(|(k, root)| root.i == k as Index + 1));
+  assert_eq!(db.root(), roots.last().copied());
+  let mut query = db.query().unwrap();
+  let mut values = (1..=db.n()).map(|i| query.get(i).unwrap().unwrap()).collect::<Vec<_>>();
+  let mut expected = (0..THREADS * N).map(|k| random_payload(PAYLOAD_SIZE, k)).collect::<Vec<_>>();
+  values.sort();
+  expected.sort();
+  assert_eq!(expected, values);
+}
+
+/// 書き込みスレッドでの追加に失敗した場合に、受領証が原因となったエラーをそのまま保持していることを検証します。
+#[test]
+fn test_appender_failure_detail() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  db.append(&random_payload(PAYLOAD_SIZE, 0)).unwrap();
+  db.seal().unwrap();
+  let (appender, handle) = Appender::spawn(db, 16);
+  let receipts = (1..=3).map(|k| appender.append(random_payload(PAYLOAD_SIZE, k)).unwrap()).collect::<Vec<_>>();
+  for receipt in receipts {
+    match receipt.wait() {
+      Err(Detail::BackgroundAppendFailed { source }) => assert!(matches!(*source, Detail::Sealed { n: 1 })),
+      unexpected => panic!("{:?}", unexpected),
+    }
+  }
+  drop(appender);
+  assert_eq!(1, handle.join().unwrap().n());
+}
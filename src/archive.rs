@@ -0,0 +1,258 @@
+//! 古いエントリの値を外部に書き出した後にストレージから削除するアーカイブです。
+//!
+//! [`LMTHT::archive()`] は前回のアーカイブの次から指定されたインデックスまでの値を、それぞれの値がその時点の
+//! ルートハッシュに対して検証できる証明の形式でシンク (`io::Write`) に書き出し、その範囲をアーカイブカタログに記録
+//! した後に [`LMTHT::prune_payloads()`] で値を削除します。アーカイブした値を参照すると [`Detail::Archived`] が
+//! 返され、値を含むアーカイブの範囲を知ることができます。
+//!
+//! ストレージの先頭のヘッダは固定長でハッシュ木のエントリがその直後から始まるため、アーカイブした範囲は
+//! [`LMTHTOptions::archive_catalog`](crate::LMTHTOptions::archive_catalog) に指定したサイドカーのストレージに記録
+//! します。カタログは範囲 (from, to)、書き出した時点の世代 n とそのルートハッシュ、チェックサムからなる固定長
+//! レコードの列です。
+//!
+//! シンクに書き出すアーカイブは次の形式です。整数はすべてリトルエンディアンで、インデックスは
+//! [`INDEX_SIZE`](crate::INDEX_SIZE) のビット幅で表されます。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | [`ARCHIVE_IDENTIFIER`] | 8 |
+//! | [`ARCHIVE_VERSION`] | 1 |
+//! | from · to · n | インデックス × 3 |
+//! | 世代 n のルートハッシュ | [`HASH_SIZE`](crate::HASH_SIZE) |
+//! | 値ごとに証明のバイトサイズ (u32) と [`ValuesWithBranches::to_bytes()`] | 可変長 |
+//!
+//! アーカイブは [`ArchiveReader`] で読み込むことができ、それぞれの値は証明から算出したルートハッシュを検証してから
+//! 返されます。
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::sync::Arc;
+//!
+//! use lmtht::{LMTHTOptions, LMTHT};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let options = LMTHTOptions { archive_catalog: Some(Arc::new("audit.archives")), ..Default::default() };
+//! let db = LMTHT::with_options("audit.db", options)?;
+//! db.archive(1_000_000, &mut File::create("audit-0001.arc")?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::convert::TryInto;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+use highway::{HighwayBuilder, Key};
+
+use crate::error::Detail;
+use crate::{
+  lock2io, DynStorage, Hash, Index, Result, Storage, Value, ValuesWithBranches, CHECKSUM_HW64_KEY, HASH_SIZE,
+  INDEX_BYTES, LMTHT,
+};
+
+#[cfg(test)]
+mod test;
+
+/// アーカイブの先頭に配置される識別子です。
+pub const ARCHIVE_IDENTIFIER: [u8; 8] = *b"LMTHTARC";
+
+/// 識別子に続いて配置される、アーカイブの形式のバージョンです。
+pub const ARCHIVE_VERSION: u8 = 1;
+
+/// アーカイブカタログの 1 レコードのバイトサイズ (from, to, n, hash, checksum)。
+const RECORD_SIZE: usize = INDEX_BYTES * 3 + HASH_SIZE + 8;
+
+/// アーカイブした値の範囲です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArchivedRange {
+  /// 範囲の最初のインデックス。
+  pub from: Index,
+  /// 範囲の最後のインデックス。
+  pub to: Index,
+  /// アーカイブを書き出した時点の世代。
+  pub n: Index,
+  /// 世代 `n` のルートハッシュ。アーカイブに含まれる証明はこのハッシュ値に対して検証されます。
+  pub root: Hash,
+}
+
+/// サイドカーストレージに保存されたアーカイブカタログです。
+pub(crate) struct ArchiveCatalog {
+  storage: Arc<dyn DynStorage + Send + Sync>,
+  /// 記録されている範囲。アーカイブの書き出し中は他のアーカイブを排除するためにロックを保持する。
+  ranges: Mutex<Vec<ArchivedRange>>,
+}
+
+impl ArchiveCatalog {
+  /// 指定されたストレージに記録されている範囲を読み込みます。書き込み途中で中断した末尾のレコードは無視されます。
+  pub fn open(storage: Arc<dyn DynStorage + Send + Sync>) -> Result<ArchiveCatalog> {
+    let mut cursor = storage.open_dyn(false)?;
+    let count = cursor.seek(SeekFrom::End(0))? / RECORD_SIZE as u64;
+    cursor.seek(SeekFrom::Start(0))?;
+    let mut ranges = Vec::with_capacity(count as usize);
+    let mut record = [0u8; RECORD_SIZE];
+    for k in 0..count {
+      cursor.read_exact(&mut record)?;
+      let (body, checksum) = record.split_at(RECORD_SIZE - 8);
+      if checksum != checksum_of(body) {
+        return Err(Detail::DamagedStorage(format!("the record {} of the archive catalog is broken", k)));
+      }
+      let index = |k: usize| Index::from_le_bytes(body[k * INDEX_BYTES..(k + 1) * INDEX_BYTES].try_into().unwrap());
+      let root = Hash::new(body[INDEX_BYTES * 3..].try_into().unwrap());
+      ranges.push(ArchivedRange { from: index(0), to: index(1), n: index(2), root });
+    }
+    Ok(ArchiveCatalog { storage, ranges: Mutex::new(ranges) })
+  }
+
+  /// インデックス `i` を含むアーカイブの範囲を参照します。
+  pub fn find(&self, i: Index) -> Result<Option<ArchivedRange>> {
+    Ok(lock2io(self.ranges.lock())?.iter().find(|range| range.from <= i && i <= range.to).copied())
+  }
+
+  /// カタログの末尾に範囲を追加し、デバイスに同期します。
+  fn append(&self, ranges: &mut Vec<ArchivedRange>, range: ArchivedRange) -> Result<()> {
+    let mut record = Vec::with_capacity(RECORD_SIZE);
+    for i in [range.from, range.to, range.n] {
+      record.extend_from_slice(&i.to_le_bytes());
+    }
+    record.extend_from_slice(&range.root.value);
+    let checksum = checksum_of(&record);
+    record.extend_from_slice(&checksum);
+    let mut cursor = self.storage.open_dyn(true)?;
+    cursor.seek(SeekFrom::Start((ranges.len() * RECORD_SIZE) as u64))?;
+    cursor.write_all(&record)?;
+    cursor.sync_data()?;
+    ranges.push(range);
+    Ok(())
+  }
+}
+
+impl<S: Storage> LMTHT<S> {
+  /// 前回のアーカイブの次から `up_to` までの値を証明の形式で `sink` に書き出し、その範囲をアーカイブカタログに
+  /// 記録した後にストレージから値を削除します。`up_to` が現在の世代を超える場合は現在の世代までを対象とします。
+  ///
+  /// アーカイブした値を参照すると [`Detail::Archived`] が返されます。書き出しに失敗した場合は何も記録されず、値も
+  /// 削除されません。カタログへの記録の後に中断した場合は同じ `up_to` で再び実行すると値の削除を完了します。
+  ///
+  /// # Returns
+  /// 新たにアーカイブした範囲を返します。アーカイブする値がない場合は `None` です。
+  ///
+  /// # Errors
+  /// [`LMTHTOptions::archive_catalog`](crate::LMTHTOptions::archive_catalog) が指定されていない場合は
+  /// [`Detail::NoArchiveCatalog`] を返します。
+  pub fn archive<W: Write + ?Sized>(&self, up_to: Index, sink: &mut W) -> Result<Option<ArchivedRange>> {
+    self.check_writable()?;
+    let catalog = self.archive_catalog.as_ref().ok_or(Detail::NoArchiveCatalog)?;
+    let mut ranges = lock2io(catalog.ranges.lock())?;
+    let archived = ranges.last().map(|range| range.to).unwrap_or(0);
+    let mut query = self.query()?;
+    let n = query.n();
+    let up_to = up_to.min(n);
+    if up_to <= archived {
+      drop(query);
+      self.prune_payloads(archived)?;
+      return Ok(None);
+    }
+    let from = archived + 1;
+    let root = match query.get_root(n)? {
+      Some(root) => root.hash,
+      None => return Ok(None),
+    };
+
+    let mut head = Vec::with_capacity(ARCHIVE_IDENTIFIER.len() + 1 + INDEX_BYTES * 3 + HASH_SIZE);
+    head.extend_from_slice(&ARCHIVE_IDENTIFIER);
+    head.push(ARCHIVE_VERSION);
+    for i in [from, up_to, n] {
+      head.extend_from_slice(&i.to_le_bytes());
+    }
+    head.extend_from_slice(&root.value);
+    sink.write_all(&head)?;
+    for i in from..=up_to {
+      let proof = match query.get_with_hashes(i)? {
+        Some(proof) => proof.to_bytes(),
+        None => return crate::inconsistency(format!("the entry i={} is not found in generation {}", i, n)),
+      };
+      sink.write_all(&(proof.len() as u32).to_le_bytes())?;
+      sink.write_all(&proof)?;
+    }
+    sink.flush()?;
+    drop(query);
+
+    let range = ArchivedRange { from, to: up_to, n, root };
+    catalog.append(&mut ranges, range)?;
+    self.prune_payloads(up_to)?;
+    Ok(Some(range))
+  }
+
+  /// アーカイブカタログに記録されている範囲を古い順に参照します。カタログが指定されていない場合は空です。
+  pub fn archived_ranges(&self) -> Result<Vec<ArchivedRange>> {
+    match &self.archive_catalog {
+      Some(catalog) => Ok(lock2io(catalog.ranges.lock())?.clone()),
+      None => Ok(Vec::new()),
+    }
+  }
+}
+
+/// [`LMTHT::archive()`] で書き出したアーカイブから値を検証しながら読み込みます。
+pub struct ArchiveReader<R: Read> {
+  r: R,
+  range: ArchivedRange,
+  /// 次に読み込む値のインデックス。
+  next: Index,
+}
+
+impl<R: Read> ArchiveReader<R> {
+  /// アーカイブの先頭を読み込みます。識別子やバージョンが一致しない場合は
+  /// [`Detail::FileIsNotContentsOfLMTHTree`] を返します。
+  pub fn new(mut r: R) -> Result<ArchiveReader<R>> {
+    let mut head = [0u8; ARCHIVE_IDENTIFIER.len() + 1 + INDEX_BYTES * 3 + HASH_SIZE];
+    r.read_exact(&mut head)?;
+    let (identifier, head) = head.split_at(ARCHIVE_IDENTIFIER.len());
+    if identifier != ARCHIVE_IDENTIFIER || head[0] != ARCHIVE_VERSION {
+      return Err(Detail::FileIsNotContentsOfLMTHTree { message: "bad archive identifier" });
+    }
+    let index =
+      |k: usize| Index::from_le_bytes(head[1 + k * INDEX_BYTES..1 + (k + 1) * INDEX_BYTES].try_into().unwrap());
+    let root = Hash::new(head[1 + INDEX_BYTES * 3..].try_into().unwrap());
+    let range = ArchivedRange { from: index(0), to: index(1), n: index(2), root };
+    Ok(ArchiveReader { r, range, next: range.from })
+  }
+
+  /// このアーカイブに含まれている範囲を参照します。
+  pub fn range(&self) -> &ArchivedRange {
+    &self.range
+  }
+
+  fn read_value(&mut self) -> Result<Value> {
+    let mut length = [0u8; 4];
+    self.r.read_exact(&mut length)?;
+    let mut proof = vec![0u8; u32::from_le_bytes(length) as usize];
+    self.r.read_exact(&mut proof)?;
+    let proof =
+      ValuesWithBranches::from_bytes(&proof).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.message))?;
+    let i = self.next;
+    match proof.values.as_slice() {
+      [value] if value.i == i && proof.root().hash == self.range.root => Ok(Value::new(i, value.value.clone())),
+      _ => Err(Detail::ArchiveVerificationFailed { i }),
+    }
+  }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+  type Item = Result<Value>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.next > self.range.to {
+      return None;
+    }
+    let value = self.read_value();
+    self.next = if value.is_ok() { self.next + 1 } else { Index::MAX };
+    Some(value)
+  }
+}
+
+fn checksum_of(body: &[u8]) -> [u8; 8] {
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, body);
+  std::hash::Hasher::finish(&hasher).to_le_bytes()
+}
@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use crate::error::Detail;
+use crate::*;
+
+/// `archive()` が値を検証可能な形式で書き出した後に削除し、参照が `Archived` を返すことを検証します。
+#[test]
+fn test_archive() {
+  use crate::archive::ArchiveReader;
+
+  let catalog: Arc<dyn DynStorage + Send + Sync> = Arc::new(MemStorage::new());
+  let buffer = Arc::new(std::sync::RwLock::new(Vec::new()));
+  let options = LMTHTOptions { archive_catalog: Some(catalog.clone()), ..Default::default() };
+  let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options.clone()).unwrap();
+  for i in 1..=20u8 {
+    db.append(&[i; 64]).unwrap();
+  }
+  let root = db.root().unwrap();
+
+  let (mut first, mut second) = (Vec::new(), Vec::new());
+  let range = db.archive(8, &mut first).unwrap().unwrap();
+  assert_eq!((1, 8, 20, root.hash), (range.from, range.to, range.n, range.root));
+  assert_eq!(9..=15, db.archive(15, &mut second).unwrap().map(|r| r.from..=r.to).unwrap());
+  assert_eq!(None, db.archive(15, &mut Vec::new()).unwrap());
+  assert_eq!(Some(root), db.root());
+
+  let mut query = db.query().unwrap();
+  assert!(matches!(query.get(3), Err(Detail::Archived { i: 3, from: 1, to: 8 })));
+  assert!(matches!(query.get_with_hashes(12), Err(Detail::Archived { i: 12, from: 9, to: 15 })));
+  assert_eq!(Some(vec![16; 64]), query.get(16).unwrap());
+  drop(query);
+
+  // アーカイブの値は証明を検証して読み込まれる
+  let reader = ArchiveReader::new(&first[..]).unwrap();
+  assert_eq!(range, *reader.range());
+  let values = reader.collect::<Result<Vec<_>>>().unwrap();
+  assert_eq!((1..=8u8).map(|i| Value::new(i as Index, vec![i; 64])).collect::<Vec<_>>(), values);
+  let last = second.len() - 1;
+  second[last] ^= 0x01;
+  let results = ArchiveReader::new(&second[..]).unwrap().collect::<Vec<_>>();
+  assert!(matches!(results.last(), Some(Err(Detail::ArchiveVerificationFailed { i: 15 }))));
+
+  // カタログはオープンし直した後も参照される
+  drop(db);
+  let db = LMTHT::with_options(MemStorage::with(buffer), options).unwrap();
+  assert_eq!(2, db.archived_ranges().unwrap().len());
+  assert!(matches!(db.query().unwrap().get(9), Err(Detail::Archived { i: 9, from: 9, to: 15 })));
+  assert!(matches!(LMTHT::new(MemStorage::new()).unwrap().archive(1, &mut Vec::new()), Err(Detail::NoArchiveCatalog)));
+}
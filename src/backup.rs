@@ -0,0 +1,267 @@
+//! 追加を継続している LMTHT のストレージを一貫した状態で複製するバックアップです。
+//!
+//! LMTHT のストレージは追記のみで更新されるため、コミット済みの世代のエントリが終わる位置までの先頭部分はその世代の
+//! 完全なストレージとなります。[`LMTHT::backup_to()`] は追加のためのロックを短時間だけ獲得してコミット済みの世代と
+//! その終端を取得し、ロックを解放した後にその位置までをコピーします。ファイルを単純にコピーすると追加中のエントリの
+//! 途中で切れた状態を複製してしまう可能性がありますが、この方法ではコピーの間も追加を継続することができます。
+//!
+//! [`LMTHT::export()`] は同じ一貫した先頭部分を任意の [`Write`] (ソケット、圧縮パイプ、オブジェクトストレージへの
+//! マルチパートアップロードなど) に逐次出力します。出力する各エントリはチェックサムを検証してから書き出されるため、
+//! ローカルのディスクに一時ファイルを作成することなく検証済みの完全なストレージを転送することができます。
+//!
+//! 出力したストリームは [`LMTHT::import()`] でシークを行わずに先頭から順に読み込むことができ、パイプやオブジェクト
+//! ストレージからの復元にシーク可能な一時ファイルを必要としません。また [`LMTHT::fork_to()`] は同じ方法で世代 n
+//! までのエントリのみを含む単独のストレージを作成します。
+//!
+//! ```rust,no_run
+//! use std::io::stdin;
+//! use std::net::TcpStream;
+//!
+//! use lmtht::{FileStorage, LMTHT};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let db = LMTHT::new("ledger.db")?;
+//! let root = db.backup_to(&FileStorage::new("ledger.db.bak"))?;
+//! db.export(&mut TcpStream::connect("backup.example.com:9000")?, None)?;
+//! let restored = LMTHT::import("restored.db", stdin(), root.map(|root| root.hash))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::Ordering;
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::error::Detail;
+use crate::{
+  back_to_safety, check_header, lock2io, read_entry, read_entry_from, set_continued, Cache, Cursor, Entry, Hash, Index,
+  Node, Result, Storage, CONTINUED_FLAG, INDEX_BYTES, INODE_SIZE, LMTHT, STORAGE_IDENTIFIER,
+};
+
+#[cfg(test)]
+mod test;
+
+/// バックアップのコピーで一度に読み書きするバイトサイズ。
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+impl<S: Storage> LMTHT<S> {
+  /// 現在コミットされている世代までのストレージを `target` に複製し、複製した世代のルートノードを返します。コピー
+  /// の間もこの LMTHT への追加は継続することができ、コピーを開始した後に追加されたエントリは複製に含まれません。
+  ///
+  /// `target` の既存の内容は置き換えられます。コピーの後に `target` の末尾のエントリを読み込み、そのチェックサムと
+  /// ルートハッシュが複製した世代と一致することを検証してから終了します。
+  ///
+  /// # Errors
+  /// 複製の末尾のエントリが複製した世代と一致しない場合は [`Detail::BackupVerificationFailed`] を返します。
+  pub fn backup_to<T: Storage>(&self, target: &T) -> Result<Option<Node>> {
+    // 世代とその終端の組をロックを保持している間に取得する
+    let (latest, end) = {
+      let _writer = lock2io(self.writer.lock())?;
+      (self.latest(), self.loaded_end.load(Ordering::Acquire))
+    };
+
+    let mut source = self.storage.open(false)?;
+    let mut cursor = target.open(true)?;
+    source.seek(SeekFrom::Start(0))?;
+    cursor.seek(SeekFrom::Start(0))?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut remaining = end;
+    while remaining > 0 {
+      let length = remaining.min(buffer.len() as u64) as usize;
+      source.read_exact(&mut buffer[..length])?;
+      cursor.write_all(&buffer[..length])?;
+      remaining -= length as u64;
+    }
+    cursor.flush()?;
+    if cursor.len()? > end {
+      cursor.set_len(end)?;
+    }
+    cursor.sync_data()?;
+
+    verify_tail(&mut cursor, end, &latest)?;
+    Ok(latest.root())
+  }
+
+  /// 現在コミットされている世代まで、または `up_to` を指定した場合は世代 `up_to` までの木構造を、それ単体で
+  /// LMTHT のストレージとして開くことのできる形式で `writer` に出力し、出力した世代のルートノードを返します。
+  /// `up_to` が現在の世代を超える場合は現在の世代までを出力します。
+  ///
+  /// 出力はストレージの先頭から順に行われ、各エントリはチェックサムを検証してから書き出されます。世代 `up_to` が
+  /// バッチの途中のエントリであっても、出力の最後のエントリはコミットを表すエントリとして書き出されます。出力の
+  /// 間もこの LMTHT への追加は継続することができます。
+  ///
+  /// # Errors
+  /// 出力の末尾のエントリが現在の世代と一致しない場合は [`Detail::BackupVerificationFailed`] を返します。
+  pub fn export<W: Write + ?Sized>(&self, writer: &mut W, up_to: Option<Index>) -> Result<Option<Node>> {
+    let (latest, end) = {
+      let _writer = lock2io(self.writer.lock())?;
+      (self.latest(), self.loaded_end.load(Ordering::Acquire))
+    };
+    let n = up_to.unwrap_or(Index::MAX).min(latest.n());
+
+    let mut source = self.open_cursor(false)?;
+    source.seek(SeekFrom::Start(0))?;
+    let mut header = vec![0u8; (STORAGE_IDENTIFIER.len() + 1).min(end as usize)];
+    source.read_exact(&mut header)?;
+    writer.write_all(&header)?;
+
+    let mut position = header.len() as u64;
+    let mut root = None;
+    let mut raw = Vec::new();
+    for i in 1..=n {
+      raw.clear();
+      let entry = read_entry_from(&mut Tee { input: &mut source, output: &mut raw }, position, i)?;
+      if i == n {
+        set_continued(&mut raw, false);
+        root = Cache::from_entry(Some(entry)).root();
+      }
+      writer.write_all(&raw)?;
+      position += raw.len() as u64;
+    }
+    writer.flush()?;
+
+    if n == latest.n() && (position != end.max(header.len() as u64) || root != latest.root()) {
+      return Err(Detail::BackupVerificationFailed { n });
+    }
+    Ok(root)
+  }
+
+  /// 最初の `n` 個のエントリのみを含むストレージを `storage` に作成し、世代 `n` の木構造を持つ LMTHT として返し
+  /// ます。テスト環境やリーガルホールドのために「世代 `n` 時点のログ」を単独のストレージとして切り出すために使用
+  /// します。`storage` の既存の内容は置き換えられます。
+  ///
+  /// # Errors
+  /// 世代 `n` がまだ追加されていない場合は [`Detail::GenerationNotAppended`] を返します。
+  pub fn fork_to<T: Storage>(&self, n: Index, storage: T) -> Result<LMTHT<T>> {
+    if n > self.n() {
+      return Err(Detail::GenerationNotAppended { n, current: self.n() });
+    }
+    let root = {
+      let mut cursor = storage.open(true)?;
+      cursor.seek(SeekFrom::Start(0))?;
+      let root = self.export(&mut cursor, Some(n))?;
+      let end = cursor.stream_position()?;
+      if cursor.len()? > end {
+        cursor.set_len(end)?;
+      }
+      cursor.sync_data()?;
+      root
+    };
+    let fork = LMTHT::new(storage)?;
+    if fork.root() != root {
+      return Err(Detail::BackupVerificationFailed { n });
+    }
+    Ok(fork)
+  }
+
+  /// [`LMTHT::export()`] で出力したストリーム `reader` を先頭から順に読み込んで `storage` に復元し、復元した
+  /// ストレージの LMTHT を返します。`storage` の既存の内容は置き換えられます。
+  ///
+  /// 各エントリはチェックサムを検証してから書き込まれ、`expected_root` を指定した場合は最後のエントリのルート
+  /// ハッシュと比較されます。検証に失敗した場合、`storage` は空に切り詰められます。
+  ///
+  /// # Errors
+  /// 復元したルートハッシュが `expected_root` と一致しない場合は [`Detail::ImportRootMismatch`] を返します。
+  pub fn import<R: Read>(storage: S, mut reader: R, expected_root: Option<Hash>) -> Result<LMTHT<S>> {
+    let mut cursor = storage.open(true)?;
+    if let Err(err) = restore(&mut cursor, &mut reader, expected_root.as_ref()) {
+      let _ = cursor.set_len(0);
+      return Err(err);
+    }
+    drop(cursor);
+    LMTHT::new(storage)
+  }
+}
+
+/// ストリーム `reader` のヘッダとエントリを検証しながら `cursor` の先頭から書き込みます。
+fn restore<C: Cursor>(cursor: &mut C, reader: &mut dyn Read, expected_root: Option<&Hash>) -> Result<()> {
+  let mut header = [0u8; STORAGE_IDENTIFIER.len() + 1];
+  reader.read_exact(&mut header)?;
+  check_header(&mut io::Cursor::new(&header[..]), header.len() as u64)?;
+  cursor.seek(SeekFrom::Start(0))?;
+  cursor.write_all(&header)?;
+
+  let mut position = header.len() as u64;
+  let mut n: Index = 0;
+  let mut last: Option<(Entry, bool)> = None;
+  let mut pending = Vec::with_capacity(COPY_BUFFER_SIZE);
+  let mut first = [0u8; 1];
+  loop {
+    // ストリームの終端はエントリの境界でのみ許される
+    if reader.read(&mut first)? == 0 {
+      break;
+    }
+    n += 1;
+    let start = pending.len();
+    let mut input = (&first[..]).chain(&mut *reader);
+    let entry = read_entry_from(&mut Tee { input: &mut input, output: &mut pending }, position, n)?;
+    position += (pending.len() - start) as u64;
+    last = Some((entry, is_continued(&pending[start..])));
+    if pending.len() >= COPY_BUFFER_SIZE {
+      cursor.write_all(&pending)?;
+      pending.clear();
+    }
+  }
+  cursor.write_all(&pending)?;
+  cursor.flush()?;
+  if cursor.len()? > position {
+    cursor.set_len(position)?;
+  }
+
+  let root = match last {
+    Some((_, true)) => return Err(Detail::DamagedStorage("the stream ends in the middle of a batch".to_string())),
+    Some((entry, false)) => Cache::from_entry(Some(entry)).root(),
+    None => None,
+  };
+  if let Some(expected_root) = expected_root {
+    if root.map(|root| root.hash != *expected_root).unwrap_or(true) {
+      return Err(Detail::ImportRootMismatch { n });
+    }
+  }
+  cursor.sync_data()?;
+  Ok(())
+}
+
+/// 直列化されたエントリ `entry` にバッチの継続を表すフラグが設定されているかを判定します。
+fn is_continued(entry: &[u8]) -> bool {
+  let position = INDEX_BYTES + 1 + entry[INDEX_BYTES] as usize * INODE_SIZE;
+  LittleEndian::read_u32(&entry[position..position + 4]) & CONTINUED_FLAG != 0
+}
+
+/// 読み込んだバイト列を `output` に複製する [`Read`] です。
+struct Tee<'a, R: Read> {
+  input: &'a mut R,
+  output: &'a mut Vec<u8>,
+}
+
+impl<'a, R: Read> Read for Tee<'a, R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let size = self.input.read(buf)?;
+    self.output.extend_from_slice(&buf[..size]);
+    Ok(size)
+  }
+}
+
+/// ストレージの `end` で終わるエントリを読み込み、それが `expected` の世代の末尾のエントリと一致することを検証
+/// します。
+pub(crate) fn verify_tail<C: Cursor>(cursor: &mut C, end: u64, expected: &Cache) -> Result<()> {
+  let n = expected.n();
+  if n == 0 {
+    return Ok(());
+  }
+  let failed = || Detail::BackupVerificationFailed { n };
+  cursor.seek(SeekFrom::Start(end))?;
+  back_to_safety(cursor, 4 + 8, "The last entry is corrupted.")?;
+  let offset = cursor.read_u32::<LittleEndian>()?;
+  back_to_safety(cursor, offset + 4, "The last entry is corrupted.")?;
+  let entry = read_entry(cursor, n)?;
+  if cursor.stream_position()? != end {
+    return Err(failed());
+  }
+  if Cache::from_entry(Some(entry)).root() != expected.root() {
+    return Err(failed());
+  }
+  Ok(())
+}
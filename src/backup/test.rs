@@ -0,0 +1,147 @@
+use std::sync::{Arc, RwLock};
+use std::thread::spawn;
+
+use crate::error::Detail;
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// 追加を継続している LMTHT のバックアップがコミット済みの世代で一貫しており、そのまま LMTHT として開けることを
+/// 検証します。
+#[test]
+fn test_backup_to() {
+  let db = Arc::new(LMTHT::new(MemStorage::new()).unwrap());
+  let backup = Arc::new(RwLock::new(Vec::new()));
+  assert_eq!(None, db.backup_to(&MemStorage::with(backup.clone())).unwrap());
+  assert_eq!(4, backup.read().unwrap().len());
+  for i in 0..50 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+
+  // 別のスレッドが追加を継続している間にバックアップを繰り返す
+  let writer = {
+    let db = db.clone();
+    spawn(move || {
+      for i in 50..300 {
+        db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+      }
+    })
+  };
+  for _ in 0..10 {
+    let root = db.backup_to(&MemStorage::with(backup.clone())).unwrap().unwrap();
+    let copy = LMTHT::new(MemStorage::with(backup.clone())).unwrap();
+    assert_eq!(Some(root), copy.root());
+    assert_eq!(db.query().unwrap().get_root(root.i).unwrap(), Some(root));
+  }
+  writer.join().unwrap();
+
+  // 既存の長い内容は切り詰められる
+  let small = LMTHT::new(MemStorage::new()).unwrap();
+  small.append(b"only").unwrap();
+  let root = small.backup_to(&MemStorage::with(backup.clone())).unwrap();
+  let copy = LMTHT::new(MemStorage::with(backup.clone())).unwrap();
+  assert_eq!((1, root), (copy.n(), copy.root()));
+}
+
+/// 任意の世代までを出力したストリームがそれ単体で LMTHT として開くことができ、その世代のルートを持つことを検証
+/// します。
+#[test]
+fn test_export() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let mut empty = Vec::new();
+  assert_eq!(None, db.export(&mut empty, None).unwrap());
+  assert_eq!(4, empty.len());
+  for i in 0..20 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut batch = db.begin_batch().unwrap();
+  for i in 20..40 {
+    batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  batch.commit().unwrap();
+
+  let mut query = db.query().unwrap();
+  for up_to in [None, Some(1), Some(20), Some(27), Some(40), Some(1000)] {
+    let mut exported = Vec::new();
+    let root = db.export(&mut exported, up_to).unwrap();
+    let n = up_to.unwrap_or(40).min(40);
+    assert_eq!(query.get_root(n).unwrap(), root);
+    let copy = LMTHT::new(MemStorage::with(Arc::new(RwLock::new(exported)))).unwrap();
+    assert_eq!((n, root), (copy.n(), copy.root()), "up_to={:?}", up_to);
+    assert_eq!(query.get(n).unwrap(), copy.query().unwrap().get(n).unwrap());
+  }
+
+  // 破損したエントリは出力されない
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 0..5 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  buffer.write().unwrap()[40] ^= 0xFF;
+  assert!(db.export(&mut Vec::new(), None).is_err());
+}
+
+/// 出力したストリームを逐次的に読み込んで復元し、エントリのチェックサムと最終的なルートハッシュが検証されることを
+/// 検証します。
+#[test]
+fn test_import_stream() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 0..30 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut exported = Vec::new();
+  let root = db.export(&mut exported, None).unwrap().unwrap();
+
+  // &[u8] はシークできない入力として扱う
+  let buffer = Arc::new(RwLock::new(vec![0xFFu8; exported.len() * 2]));
+  let restored = LMTHT::import(MemStorage::with(buffer.clone()), &exported[..], Some(root.hash)).unwrap();
+  assert_eq!(Some(root), restored.root());
+  assert_eq!(exported, *buffer.read().unwrap());
+  drop(restored);
+
+  // ルートハッシュの不一致、破損したエントリ、途中で途切れたストリーム
+  let other = Hash::hash(b"other");
+  let result = LMTHT::import(MemStorage::with(buffer.clone()), &exported[..], Some(other));
+  assert!(matches!(result, Err(Detail::ImportRootMismatch { n: 30 })));
+  assert!(buffer.read().unwrap().is_empty());
+  let mut corrupted = exported.clone();
+  corrupted[exported.len() / 2] ^= 0x01;
+  assert!(LMTHT::import(MemStorage::new(), &corrupted[..], None).is_err());
+  assert!(LMTHT::import(MemStorage::new(), &exported[..exported.len() - 3], None).is_err());
+  assert!(LMTHT::import(MemStorage::new(), &b"bad!"[..], None).is_err());
+
+  // コミットされていないバッチで終わるストリーム
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  let first_end = 4 + db.estimate_append_size(b"first".len()) as usize;
+  let mut batch = db.begin_batch().unwrap();
+  batch.append(b"first").unwrap();
+  batch.append(b"second").unwrap();
+  batch.commit().unwrap();
+  let committed = buffer.read().unwrap().clone();
+  let result = LMTHT::import(MemStorage::new(), &committed[..first_end], None);
+  assert!(matches!(result, Err(Detail::DamagedStorage(_))));
+  assert_eq!(2, LMTHT::import(MemStorage::new(), &committed[..], None).unwrap().n());
+}
+
+/// 世代 n で切り出したストレージが最初の n 個のエントリのみを含み、その世代のルートを持つことを検証します。
+#[test]
+fn test_fork_to() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 0..25 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut query = db.query().unwrap();
+  for n in [0, 1, 13, 16, 25] {
+    let fork = db.fork_to(n, MemStorage::new()).unwrap();
+    assert_eq!((n, query.get_root(n).unwrap()), (fork.n(), fork.root()));
+    if n > 0 {
+      assert_eq!(query.get(n).unwrap(), fork.query().unwrap().get(n).unwrap());
+    }
+
+    // 切り出したストレージには独立して追加できる
+    fork.append(b"what-if").unwrap();
+    assert_eq!(n + 1, fork.n());
+  }
+  assert_eq!(25, db.n());
+  assert!(matches!(db.fork_to(26, MemStorage::new()), Err(Detail::GenerationNotAppended { n: 26, current: 25 })));
+}
@@ -0,0 +1,419 @@
+//! 複数の値の追加をメモリ上に蓄積し、一度の連続した書き込みとしてストレージに出力するバッチです。
+//!
+//! バッチに追加したエントリはストレージの末尾に続くメモリ上の領域に直列化されます。後続のエントリが参照する左枝側の
+//! ノードはストレージとメモリ上の領域を連結したカーソルから読み込むため、バッチ内のエントリは出力前であっても
+//! ストレージ上のエントリと同じように扱うことができます。
+//!
+//! バッチの最後のエントリ以外はペイロード長の最上位ビットに継続フラグが設定され、フラグのない最後のエントリが
+//! バッチ全体のコミットを表します。バッチの書き込み中に障害が発生した場合、次のオープン時にコミットされていない
+//! エントリは破棄されるため、バッチに追加した値はすべて参照できるか、いずれも参照できないかのどちらかとなります。
+//!
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, MutexGuard};
+use std::time::Instant;
+
+use crate::error::Detail;
+use crate::metrics::OperationKind;
+use crate::record::{check_record, timestamp_of};
+use crate::subscription::Appended;
+use crate::{
+  build_entry, set_continued, write_entry, AppendOutcome, BufferedCursor, Cache, CacheInner, Cursor, DuplicatePolicy,
+  Hash, INode, Index, Node, Result, Storage, Writer, HASH_SIZE, INDEX_BYTES, INODE_SIZE, LMTHT,
+};
+
+#[cfg(test)]
+mod test;
+
+/// [`LMTHT::begin_batch()`] で開始した、まだストレージに出力されていない値の追加です。
+///
+/// [`Batch::commit()`] を呼び出すことでバッチに追加したすべての値がストレージに出力されます。コミットせずに
+/// `drop()` した場合、追加した値はストレージに出力されずに破棄されます。
+pub struct Batch<'a, S: Storage> {
+  db: &'a LMTHT<S>,
+  /// バッチが完了するまで保持する追加のためのロック。
+  writer: MutexGuard<'a, Writer>,
+  cursor: Overlay<BufferedCursor<S::Cursor>>,
+  /// バッチに追加した値を含む最新の世代。
+  latest: Arc<Cache>,
+  /// バッチに追加したエントリ。
+  entries: Vec<Pending>,
+  /// バッチを開始した時点の世代。
+  base_n: Index,
+  /// 重複を検査する場合にバッチに追加した値のハッシュ値とその最初のインデックス。
+  hashes: HashMap<[u8; HASH_SIZE], Index>,
+  /// タイムスタンプを付加する LMTHT でバッチに最後に追加した値、またはコミット済みの最後のレコードの時刻。
+  last_timestamp: Option<u64>,
+  committed: bool,
+}
+
+impl<'a, S: Storage> Batch<'a, S> {
+  pub(crate) fn new(
+    db: &'a LMTHT<S>,
+    writer: MutexGuard<'a, Writer>,
+    cursor: BufferedCursor<S::Cursor>,
+  ) -> Result<Batch<'a, S>> {
+    // ストレージの長さではなく読み込み済みの末尾から続ける (末尾にコミットされていないバイトが残っていても上書きする)
+    let base = db.loaded_end.load(Ordering::Acquire);
+    let latest = db.latest();
+    let base_n = latest.n();
+    let cursor = Overlay { inner: cursor, base, pending: Vec::new(), position: base };
+    let (entries, hashes) = (Vec::new(), HashMap::new());
+    Ok(Batch { db, writer, cursor, latest, entries, base_n, hashes, last_timestamp: None, committed: false })
+  }
+
+  /// 指定された値をバッチに追加します。
+  ///
+  /// [`LMTHTOptions::duplicates`](crate::LMTHTOptions::duplicates) に [`DuplicatePolicy::Skip`] を指定している場合、
+  /// 同じ値がコミット済みのエントリかこのバッチに含まれていれば追加せずに現在のバッチのルートノードを返します。
+  ///
+  /// # Returns
+  /// バッチをコミットした時点でこの値までを含む木構造のルートノードを返します。
+  pub fn append(&mut self, value: &[u8]) -> Result<Node> {
+    Ok(self.append_dedup(value)?.root)
+  }
+
+  /// [`LMTHTOptions::duplicates`](crate::LMTHTOptions::duplicates) に従って値の重複を検査してバッチに追加します。
+  /// 重複はバッチを開始した時点の世代のエントリと、このバッチにすでに追加した値の双方から検査されます。
+  pub fn append_dedup(&mut self, value: &[u8]) -> Result<AppendOutcome> {
+    let duplicates = self.db.duplicates;
+    let hash = match (&self.db.hash_index, duplicates) {
+      (_, DuplicatePolicy::Allow) => None,
+      (Some(hash_index), _) => {
+        let hash = Hash::hash(value);
+        let committed = hash_index.find(&hash, self.base_n)?.first().copied();
+        Some((hash, committed.or_else(|| self.hashes.get(&hash.value).copied())))
+      }
+      (None, _) => return Err(Detail::NoHashIndex),
+    };
+    let duplicate_of = hash.and_then(|(_, duplicate_of)| duplicate_of);
+    if let (Some(_), DuplicatePolicy::Skip) = (duplicate_of, duplicates) {
+      return Ok(AppendOutcome { root: self.latest.root().unwrap(), i: None, duplicate_of });
+    }
+    let root = self.append_entry(value)?;
+    if let Some((hash, _)) = hash {
+      self.hashes.entry(hash.value).or_insert(root.i);
+    }
+    Ok(AppendOutcome { root, i: Some(root.i), duplicate_of })
+  }
+
+  fn append_entry(&mut self, value: &[u8]) -> Result<Node> {
+    // バッチ内のエントリは位置索引に含まれていないため、左枝側のノードは木構造を探索して参照する
+    let position = self.cursor.seek(SeekFrom::End(0))?;
+    let previous = self.entries.last().map(|pending| pending.position);
+    let (entry, gen, root) =
+      build_entry(&self.latest, &self.db.node_cache, &mut None, &mut self.cursor, position, value)?;
+    if self.db.timestamps {
+      self.check_timestamp(value, root.i)?;
+    } else if self.db.records {
+      check_record(value, root.i)?;
+    }
+    self.cursor.seek(SeekFrom::Start(position))?;
+    write_entry(&mut self.cursor, &entry)?;
+
+    // 直前のエントリはこのエントリでコミットされるバッチの途中であることを記録する
+    if let Some(previous) = previous {
+      let (start, end) = ((previous - self.cursor.base) as usize, (position - self.cursor.base) as usize);
+      set_continued(&mut self.cursor.pending[start..end], true);
+    }
+    let cache = Cache::new(entry, gen);
+    if let Some(CacheInner { last_inodes, .. }) = &cache.0 {
+      let inodes = last_inodes.clone();
+      self.entries.push(Pending { root, position, inodes, payload_size: value.len() });
+    }
+    self.latest = Arc::new(cache);
+    Ok(root)
+  }
+
+  /// タイムスタンプを付加する LMTHT に i 番目のエントリとして追加する値 `value` が、直前の値以降の時刻を持つ
+  /// レコードであることを確認します。
+  fn check_timestamp(&mut self, value: &[u8], i: Index) -> Result<()> {
+    let timestamp = timestamp_of(value, i)?.ok_or(Detail::NoTimestamp { i })?;
+    let last = self.last_timestamp()?;
+    if timestamp < last {
+      return Err(Detail::TimestampOutOfOrder { i, timestamp, last });
+    }
+    self.last_timestamp = Some(timestamp);
+    Ok(())
+  }
+
+  /// バッチに最後に追加した値、またはコミット済みの最後のレコードのタイムスタンプを返します。
+  pub(crate) fn last_timestamp(&mut self) -> Result<u64> {
+    match self.last_timestamp {
+      Some(timestamp) => Ok(timestamp),
+      None => {
+        let timestamp = self.db.last_timestamp()?;
+        self.last_timestamp = Some(timestamp);
+        Ok(timestamp)
+      }
+    }
+  }
+
+  /// バッチに追加した値を含む木構造のルートノードを参照します。
+  pub fn root(&self) -> Option<Node> {
+    self.latest.root()
+  }
+
+  /// バッチに追加した値を含む木構造の世代を返します。
+  pub fn n(&self) -> Index {
+    self.latest.n()
+  }
+
+  /// バッチに追加した値の数を返します。
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  /// バッチに値が追加されていない場合に true を返します。
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// バッチに追加したすべての値を一度の書き込みでストレージに出力し、LMTHT から参照できるようにします。
+  ///
+  /// # Returns
+  /// コミット後の木構造のルートノードを返します。
+  pub fn commit(mut self) -> Result<Option<Node>> {
+    let staged = self.stage();
+    self.committed = true;
+    let Overlay { inner: cursor, .. } = &mut self.cursor;
+    write_staged(self.db, &mut self.writer, cursor, staged)
+  }
+
+  /// バッチに追加したエントリをストレージに出力せずに取り出し、[`LMTHT::commit()`] で後から出力できる状態にします。
+  pub(crate) fn prepare(mut self) -> Result<Prepared> {
+    let staged = self.stage();
+    self.db.node_cache.forget_after(staged.base, self.db.n())?;
+    Ok(Prepared { previous: self.db.latest(), staged })
+  }
+
+  fn stage(&mut self) -> Staged {
+    Staged {
+      base: self.cursor.base,
+      pending: std::mem::take(&mut self.cursor.pending),
+      latest: self.latest.clone(),
+      entries: std::mem::take(&mut self.entries),
+      last_timestamp: self.last_timestamp,
+    }
+  }
+}
+
+impl<'a, S: Storage> Drop for Batch<'a, S> {
+  fn drop(&mut self) {
+    if !self.committed && !self.entries.is_empty() {
+      // バッチ内のエントリの位置は後続の追加で再利用されるためキャッシュから破棄する
+      let _ = self.db.node_cache.forget_after(self.cursor.base, self.db.n());
+    }
+  }
+}
+
+/// [`LMTHT::prepare_append()`] で直列化され、まだストレージに出力されていない値の追加です。
+///
+/// 追加後のルートノード [`Prepared::root()`] を外部のシステム (データベースのトランザクションや合意形成など) と
+/// 合意した後に [`LMTHT::commit()`] で出力するか、[`LMTHT::abort()`] で破棄します。準備から出力までの間に LMTHT
+/// に別の値が追加された場合、この追加は出力できません。
+pub struct Prepared {
+  /// 準備した時点の LMTHT の最新の世代。
+  previous: Arc<Cache>,
+  staged: Staged,
+}
+
+impl Prepared {
+  /// この追加を出力した後の木構造のルートノードを参照します。
+  pub fn root(&self) -> Node {
+    self.staged.latest.root().unwrap()
+  }
+
+  /// この追加を出力した後の木構造の世代を返します。
+  pub fn n(&self) -> Index {
+    self.staged.latest.n()
+  }
+
+  /// この追加が前提としている LMTHT の世代を返します。
+  pub fn base_n(&self) -> Index {
+    self.previous.n()
+  }
+
+  /// 準備した時点の最新の世代が `latest` と同一であり、この追加を出力できる場合に true を返します。
+  pub(crate) fn is_based_on(&self, latest: &Arc<Cache>) -> bool {
+    Arc::ptr_eq(&self.previous, latest)
+  }
+
+  pub(crate) fn into_staged(self) -> Staged {
+    self.staged
+  }
+}
+
+/// 直列化済みでストレージに出力されていないエントリです。
+pub(crate) struct Staged {
+  /// エントリを出力するストレージ上の位置。
+  base: u64,
+  pending: Vec<u8>,
+  /// エントリを出力した後の最新の世代。
+  latest: Arc<Cache>,
+  entries: Vec<Pending>,
+  /// タイムスタンプを付加する LMTHT で最後のエントリが持つ時刻。
+  last_timestamp: Option<u64>,
+}
+
+/// ストレージに出力されていない 1 つのエントリです。
+struct Pending {
+  /// エントリを追加した後のルートノード。
+  root: Node,
+  /// エントリのストレージ上の位置。
+  position: u64,
+  inodes: Arc<[INode]>,
+  payload_size: usize,
+}
+
+/// 直列化済みのエントリを `cursor` を使用してストレージに出力し、LMTHT のキャッシュと位置索引を更新します。
+///
+/// 出力や同期に失敗した場合はストレージを出力前の長さに切り詰めてからエラーを返します。切り詰めや索引の更新に
+/// 失敗した場合は `writer` を失敗した状態とし、再オープンするまで以降の追加を拒否します。
+pub(crate) fn write_staged<S: Storage>(
+  db: &LMTHT<S>,
+  writer: &mut Writer,
+  cursor: &mut BufferedCursor<S::Cursor>,
+  staged: Staged,
+) -> Result<Option<Node>> {
+  if !staged.entries.is_empty() {
+    let start = Instant::now();
+    if let Err(err) = write_pending(db, writer, cursor, &staged) {
+      // 出力したエントリを取り消す
+      let rollback = cursor.set_len(staged.base).and_then(|_| cursor.flush());
+      if let Err(rollback) = rollback {
+        writer.failure = Some(format!("{}; the written entries cannot be truncated: {}", err, rollback));
+      }
+      db.node_cache.forget_after(staged.base, db.n())?;
+      return Err(err);
+    }
+    let (count, bytes) = (staged.entries.len() as u64, staged.pending.len() as u64);
+    db.node_cache.metrics.appended(count, bytes, start.elapsed());
+    db.node_cache.report_if_slow(OperationKind::Append, start, cursor.io_counts());
+
+    // キャッシュと索引を更新 (途中で失敗した索引は再オープン時に再構築される)
+    if let Err(err) = update_indices(db, &staged) {
+      writer.failure = Some(err.to_string());
+      return Err(err);
+    }
+    db.set_latest(staged.latest.clone());
+    db.loaded_end.store(staged.base + staged.pending.len() as u64, Ordering::Release);
+    if let Some(timestamp) = staged.last_timestamp {
+      db.last_timestamp.store(timestamp, Ordering::SeqCst);
+    }
+    db.record_watermark(staged.latest.n())?;
+
+    // 購読者に追加を通知 (追加はすでに完了しているため通知の成否はコミットの結果に影響しない)
+    let events = staged.entries.iter().map(|e| Appended { root: e.root, i: e.root.i, payload_size: e.payload_size });
+    db.subscribers.notify(&events.collect::<Vec<_>>());
+  }
+  Ok(staged.latest.root())
+}
+
+/// 直列化済みのエントリを `staged.base` の位置に書き込み、同期方針に従って同期します。
+fn write_pending<S: Storage>(
+  db: &LMTHT<S>,
+  writer: &mut Writer,
+  cursor: &mut BufferedCursor<S::Cursor>,
+  staged: &Staged,
+) -> Result<()> {
+  cursor.seek(SeekFrom::Start(staged.base))?;
+  cursor.write_all(&staged.pending)?;
+  cursor.flush()?;
+  db.sync_if_needed(writer, cursor, staged.entries.len() as u64, staged.latest.n())
+}
+
+/// 出力したエントリをキャッシュと位置索引、キー索引、ハッシュ索引、ブルームフィルタに登録します。
+fn update_indices<S: Storage>(db: &LMTHT<S>, staged: &Staged) -> Result<()> {
+  for Pending { root, position, inodes, payload_size } in &staged.entries {
+    db.node_cache.put_inodes(*position, inodes)?;
+    db.node_cache.put_position(root.i, *position)?;
+    if let Some(position_index) = &db.position_index {
+      position_index.append(root.i, *position)?;
+    }
+    if db.key_index.is_some() || db.hash_index.is_some() || db.bloom_filter.is_some() {
+      let offset = (*position - staged.base) as usize + INDEX_BYTES + 1 + inodes.len() * INODE_SIZE + 4;
+      let payload = &staged.pending[offset..offset + *payload_size];
+      if let Some(key_index) = &db.key_index {
+        key_index.append(root.i, payload)?;
+      }
+      let hash = Hash::hash(payload);
+      if let Some(hash_index) = &db.hash_index {
+        hash_index.append(root.i, &hash)?;
+      }
+      if let Some(bloom_filter) = &db.bloom_filter {
+        bloom_filter.append(root.i, &hash)?;
+      }
+    }
+  }
+  Ok(())
+}
+
+/// ストレージのカーソルの末尾にメモリ上の領域を連結したカーソルです。`base` 以降の位置に対する読み書きはメモリ上の
+/// 領域に対して行われます。
+struct Overlay<C: Cursor> {
+  inner: C,
+  /// メモリ上の領域が始まるストレージ上の位置。
+  base: u64,
+  pending: Vec<u8>,
+  position: u64,
+}
+
+impl<C: Cursor> Cursor for Overlay<C> {}
+
+impl<C: Cursor> Seek for Overlay<C> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => (position, 0),
+      SeekFrom::Current(offset) => (self.position, offset),
+      SeekFrom::End(offset) => (self.base + self.pending.len() as u64, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl<C: Cursor> Read for Overlay<C> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let length = if self.position < self.base {
+      let length = buf.len().min((self.base - self.position) as usize);
+      self.inner.seek(SeekFrom::Start(self.position))?;
+      self.inner.read(&mut buf[..length])?
+    } else {
+      let offset = ((self.position - self.base) as usize).min(self.pending.len());
+      let length = buf.len().min(self.pending.len() - offset);
+      buf[..length].copy_from_slice(&self.pending[offset..offset + length]);
+      length
+    };
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<C: Cursor> Write for Overlay<C> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self.position < self.base {
+      return Err(io::Error::new(io::ErrorKind::PermissionDenied, "cannot overwrite committed entries in a batch"));
+    }
+    let offset = (self.position - self.base) as usize;
+    let end = offset + buf.len();
+    if self.pending.len() < end {
+      self.pending.resize(end, 0u8);
+    }
+    self.pending[offset..end].copy_from_slice(buf);
+    self.position += buf.len() as u64;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
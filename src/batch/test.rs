@@ -0,0 +1,187 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::test::{random_payload, CountingStorage, PAYLOAD_SIZE};
+use crate::*;
+
+/// バッチで追加した値が一度の書き込みで出力され、個別に追加した場合と同じストレージの内容となることを検証します。
+#[test]
+fn test_batch() {
+  const N: Index = 50;
+  let expected = LMTHT::new(MemStorage::new()).unwrap();
+  let expected_roots = (1..=N).map(|i| expected.append(&random_payload(PAYLOAD_SIZE, i)).unwrap()).collect::<Vec<_>>();
+
+  for batch_size in [1, 3, 16, N] {
+    let storage = CountingStorage::default();
+    let buffer = storage.storage.buffer.clone();
+    let writes = storage.writes.clone();
+    let options = LMTHTOptions { write_buffer_size: 0, in_memory_position_index: true, ..Default::default() };
+    let db = LMTHT::with_options(storage, options).unwrap();
+    let mut i = 1;
+    while i <= N {
+      // コミットしないバッチは破棄され、ストレージにもキャッシュにも残らない
+      let mut discarded = db.begin_batch().unwrap();
+      for k in 0..batch_size {
+        discarded.append(&random_payload(PAYLOAD_SIZE, N + k)).unwrap();
+      }
+      drop(discarded);
+
+      let mut batch = db.begin_batch().unwrap();
+      while i <= N && batch.len() < batch_size as usize {
+        assert_eq!(expected_roots[i as usize - 1], batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap());
+        i += 1;
+      }
+
+      let n = batch.n();
+      let before = writes.load(Ordering::SeqCst);
+      assert_eq!(Some(expected_roots[n as usize - 1]), batch.commit().unwrap());
+      assert_eq!(before + 1, writes.load(Ordering::SeqCst));
+      assert_eq!(n, db.n());
+      let mut query = db.query().unwrap();
+      for k in 1..=n {
+        assert_eq!(Some(random_payload(PAYLOAD_SIZE, k)), query.get(k).unwrap());
+      }
+    }
+    assert_eq!(expected.root(), db.root());
+    drop(db);
+
+    // 継続フラグ以外は個別に追加した場合と同じ内容となる
+    let expected_bytes = expected.storage().buffer.read().unwrap().clone();
+    assert_eq!(expected_bytes.len(), buffer.read().unwrap().len());
+    let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+    assert_eq!(expected.root(), db.root());
+  }
+}
+
+/// 準備した追加が出力するまで LMTHT に反映されず、出力後は通常の追加と同じ結果となることを検証します。
+#[test]
+fn test_prepare_append() {
+  let expected = LMTHT::new(MemStorage::new()).unwrap();
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=10 {
+    let root = expected.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+
+    // 破棄した追加はストレージにもキャッシュにも残らない
+    let length = buffer.read().unwrap().len();
+    let aborted = db.prepare_append(&random_payload(PAYLOAD_SIZE, i + 100)).unwrap();
+    assert_eq!(i, aborted.n());
+    db.abort(aborted);
+    assert_eq!(length, buffer.read().unwrap().len());
+
+    let prepared = db.prepare_append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    assert_eq!(root, prepared.root());
+    assert_eq!(i - 1, prepared.base_n());
+    assert_eq!(i - 1, db.n());
+    assert_eq!(length, buffer.read().unwrap().len());
+    assert_eq!(root, db.commit(prepared).unwrap());
+    assert_eq!(expected.root(), db.root());
+  }
+
+  // 準備した後に別の値が追加された場合は出力できない
+  let stale = db.prepare_append(&random_payload(PAYLOAD_SIZE, 11)).unwrap();
+  db.append(&random_payload(PAYLOAD_SIZE, 12)).unwrap();
+  assert!(matches!(db.commit(stale), Err(Detail::StalePreparedAppend { prepared: 10, current: 11 })));
+  let mut query = db.query().unwrap();
+  assert_eq!(Some(random_payload(PAYLOAD_SIZE, 12)), query.get(11).unwrap());
+}
+
+/// 書き込み途中で中断したバッチが次のオープン時に破棄され、直前のコミットの状態に戻ることを検証します。
+#[test]
+fn test_batch_recovery() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=5 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let committed_root = db.root();
+  let committed = buffer.read().unwrap().clone();
+  let mut batch = db.begin_batch().unwrap();
+  for i in 6..=10 {
+    batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  batch.commit().unwrap();
+  let complete_root = db.root();
+  drop(db);
+  let complete = buffer.read().unwrap().clone();
+
+  // バッチの途中までの書き込みはコミットされていないものとして破棄される
+  for length in (committed.len()..complete.len()).step_by(97) {
+    *buffer.write().unwrap() = complete[..length].to_vec();
+    let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+    assert_eq!(committed_root, db.root(), "length={}", length);
+    assert_eq!(committed, *buffer.read().unwrap());
+    drop(db);
+  }
+
+  // バッチの最後のエントリまで書き込まれていればすべての値が参照できる
+  *buffer.write().unwrap() = complete.clone();
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  assert_eq!(complete_root, db.root());
+  assert_eq!(10, db.n());
+}
+
+/// コミット済みのエントリの破損が書き込みの中断として切り詰められず、オープン時にエラーとなることを検証します。
+#[test]
+fn test_damaged_committed_entry() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=5 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let batch_start = buffer.read().unwrap().len();
+  let mut batch = db.begin_batch().unwrap();
+  for i in 6..=10 {
+    batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  batch.commit().unwrap();
+  let end = buffer.read().unwrap().len();
+  drop(db);
+  let complete = buffer.read().unwrap().clone();
+
+  // 最後のエントリ、コミット済みのバッチの途中のエントリ、およびトレイラーのいずれの破損も検出する
+  for position in [end - 20, end - 4, batch_start + 20, end - 4 - 8] {
+    let mut damaged = complete.clone();
+    damaged[position] ^= 0x01;
+    *buffer.write().unwrap() = damaged.clone();
+    let result = LMTHT::new(MemStorage::with(buffer.clone()));
+    assert!(matches!(result, Err(Detail::DamagedStorage(_))), "position={}", position);
+    assert_eq!(damaged, *buffer.read().unwrap());
+  }
+}
+
+/// 出力したエントリの同期に失敗した場合にストレージが出力前の長さに戻り、以降の追加が同じ位置に続くことを検証します。
+#[test]
+fn test_batch_rollback_on_sync_failure() {
+  use crate::fault::FaultyStorage;
+
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let hashes = Arc::new(MemStorage::new());
+  let options =
+    LMTHTOptions { sync_policy: SyncPolicy::Always, hash_index: Some(hashes.clone()), ..Default::default() };
+  let db = LMTHT::with_options(FaultyStorage::new(MemStorage::with(buffer.clone())), options.clone()).unwrap();
+  db.append(b"a").unwrap();
+  db.append(b"b").unwrap();
+  let length = buffer.read().unwrap().len();
+
+  // 同期に失敗した追加はストレージにもキャッシュにも残らない
+  db.storage().fail_nth_sync(1);
+  assert!(db.append(b"x").is_err());
+  assert_eq!(length, buffer.read().unwrap().len());
+  assert_eq!(2, db.n());
+
+  // 後続の追加は失敗した追加の位置に続き、索引も重複しない
+  let root = db.append(b"c").unwrap();
+  assert_eq!(3, root.i);
+  let query = db.query().unwrap();
+  assert_eq!(vec![3], query.find_by_hash(&Hash::hash(b"c")).unwrap());
+  assert!(query.find_by_hash(&Hash::hash(b"x")).unwrap().is_empty());
+  drop(query);
+  drop(db);
+
+  let db = LMTHT::with_options(MemStorage::with(buffer), options).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  assert_eq!(Some(b"c".to_vec()), query.get(3).unwrap());
+}
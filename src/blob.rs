@@ -0,0 +1,238 @@
+//! 大きな値をハッシュ木とは別のブロブファイルに保存するモードです。
+//!
+//! [`DetachedLMTHT`] は閾値を超える値をブロブファイルの末尾に書き込み、ハッシュ木のエントリには値のハッシュ値、長さ、
+//! ブロブファイル上の位置からなる参照のみを保存します。ハッシュ木のストレージは小さくシークしやすいまま、数 GB の値を
+//! 扱うことができます。値のハッシュ値は参照としてハッシュ木に含まれるため、ブロブファイルの内容の改ざんは読み出し時に
+//! 検出されます。
+//!
+//! ハッシュ木に保存される値は先頭の 1 バイトで種類を区別します。
+//!
+//! | 種類 | 内容 |
+//! |:-----|:-----|
+//! | 0 (インライン) | 値そのもの |
+//! | 1 (ブロブ参照) | ブロブファイル上の位置 (u64 LE) · 長さ (u64 LE) · 値のハッシュ値 |
+//!
+//! ブロブは参照をハッシュ木に追加する前にデバイスに同期されるため、ハッシュ木が存在しないブロブを参照することは
+//! ありません。参照を追加する前に中断した場合、ブロブファイルには参照されない領域が残ります。
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//!
+//! use lmtht::blob::{DetachedLMTHT, DEFAULT_DETACH_THRESHOLD};
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let db = DetachedLMTHT::new(LMTHT::new("audit.db")?, "audit.blobs", DEFAULT_DETACH_THRESHOLD)?;
+//! db.append_from(&mut File::open("firmware.img")?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::Detail;
+use crate::{lock2io, Hash, Index, Node, Result, Storage, HASH_SIZE, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// [`DetachedLMTHT`] が値をブロブファイルに保存する閾値のデフォルト値です。
+pub const DEFAULT_DETACH_THRESHOLD: usize = 64 * 1024;
+
+/// 値をハッシュ木に直接保存していることを表す種類。
+const INLINE: u8 = 0;
+
+/// 値をブロブファイルに保存していることを表す種類。
+const BLOB: u8 = 1;
+
+/// ブロブ参照のバイトサイズ。
+const REFERENCE_SIZE: usize = 1 + 8 + 8 + HASH_SIZE;
+
+/// 閾値を超える値をブロブファイルに保存する LMTHT です。
+pub struct DetachedLMTHT<S: Storage> {
+  db: LMTHT<S>,
+  path: PathBuf,
+  blobs: Mutex<File>,
+  threshold: usize,
+}
+
+/// ブロブファイルに保存された値の参照です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlobRef {
+  /// ブロブファイル上の位置。
+  pub offset: u64,
+  /// 値のバイトサイズ。
+  pub length: u64,
+  /// 値のハッシュ値。
+  pub hash: Hash,
+}
+
+impl<S: Storage> DetachedLMTHT<S> {
+  /// 指定された LMTHT に `threshold` バイトを超える値をブロブファイル `path` に保存するモードを適用します。ブロブ
+  /// ファイルが存在しない場合は作成します。
+  pub fn new<P: AsRef<Path>>(db: LMTHT<S>, path: P, threshold: usize) -> Result<DetachedLMTHT<S>> {
+    let path = path.as_ref().to_path_buf();
+    let blobs = OpenOptions::new().read(true).append(true).create(true).open(&path).map_err(|err| {
+      Detail::FailedToOpenLocalFile { file: path.to_string_lossy().to_string(), message: err.to_string() }
+    })?;
+    Ok(DetachedLMTHT { db, path, blobs: Mutex::new(blobs), threshold })
+  }
+
+  /// 値を保存しているハッシュ木を参照します。
+  pub fn lmtht(&self) -> &LMTHT<S> {
+    &self.db
+  }
+
+  /// 値を追加します。値が閾値を超える場合はブロブファイルに保存し、ハッシュ木にはその参照を追加します。
+  pub fn append(&self, value: &[u8]) -> Result<Node> {
+    if value.len() <= self.threshold {
+      let mut record = Vec::with_capacity(1 + value.len());
+      record.push(INLINE);
+      record.extend_from_slice(value);
+      return self.db.append(&record);
+    }
+    self.append_from(&mut &value[..])
+  }
+
+  /// `reader` から読み込んだ値を、長さに関わらずブロブファイルに保存して追加します。値全体をメモリ上に保持しないため
+  /// メモリに収まらない大きさの値を追加することができます。
+  pub fn append_from<R: Read + ?Sized>(&self, reader: &mut R) -> Result<Node> {
+    let mut blobs = lock2io(self.blobs.lock())?;
+    let offset = blobs.seek(SeekFrom::End(0))?;
+    let mut hasher = ValueHasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut length = 0u64;
+    loop {
+      let size = match reader.read(&mut buffer) {
+        Ok(0) => break,
+        Ok(size) => size,
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err.into()),
+      };
+      hasher.update(&buffer[..size]);
+      blobs.write_all(&buffer[..size])?;
+      length += size as u64;
+    }
+    blobs.sync_data()?;
+    drop(blobs);
+    let reference = BlobRef { offset, length, hash: hasher.finish() };
+    self.db.append(&encode_reference(&reference))
+  }
+
+  /// インデックス `i` の値を参照します。ブロブファイルに保存されている値はハッシュ値を検証してから返します。
+  pub fn get(&self, i: Index) -> Result<Option<Vec<u8>>> {
+    let record = match self.db.query()?.get(i)? {
+      Some(record) => record,
+      None => return Ok(None),
+    };
+    let reference = match decode(&record)? {
+      Decoded::Inline => return Ok(Some(record[1..].to_vec())),
+      Decoded::Blob(reference) => reference,
+    };
+    let mut value = Vec::with_capacity(reference.length as usize);
+    self.open_blob(&reference)?.read_to_end(&mut value)?;
+    if value.len() as u64 != reference.length || Hash::hash(&value) != reference.hash {
+      let msg = format!("the blob of the value {} does not match the hash recorded in the tree", i);
+      return Err(Detail::DamagedStorage(msg));
+    }
+    Ok(Some(value))
+  }
+
+  /// インデックス `i` の値がブロブファイルに保存されている場合にその参照を返します。
+  pub fn blob_ref(&self, i: Index) -> Result<Option<BlobRef>> {
+    match self.db.query()?.get(i)? {
+      Some(record) => match decode(&record)? {
+        Decoded::Blob(reference) => Ok(Some(reference)),
+        Decoded::Inline => Ok(None),
+      },
+      None => Ok(None),
+    }
+  }
+
+  /// 参照されたブロブを先頭から読み込む `Read` を返します。値全体をメモリに読み込まずに処理するために使用します。
+  /// 読み込んだ内容の検証は呼び出し側で [`BlobRef::hash`] と比較して行う必要があります。
+  pub fn open_blob(&self, reference: &BlobRef) -> Result<io::Take<File>> {
+    let mut file = File::open(&self.path)?;
+    file.seek(SeekFrom::Start(reference.offset))?;
+    Ok(file.take(reference.length))
+  }
+}
+
+enum Decoded {
+  Inline,
+  Blob(BlobRef),
+}
+
+fn encode_reference(reference: &BlobRef) -> Vec<u8> {
+  let mut record = Vec::with_capacity(REFERENCE_SIZE);
+  record.push(BLOB);
+  record.extend_from_slice(&reference.offset.to_le_bytes());
+  record.extend_from_slice(&reference.length.to_le_bytes());
+  record.extend_from_slice(&reference.hash.value);
+  record
+}
+
+fn decode(record: &[u8]) -> Result<Decoded> {
+  match record.first() {
+    Some(&INLINE) => Ok(Decoded::Inline),
+    Some(&BLOB) if record.len() == REFERENCE_SIZE => {
+      let offset = u64::from_le_bytes(record[1..9].try_into().unwrap());
+      let length = u64::from_le_bytes(record[9..17].try_into().unwrap());
+      let hash = Hash::new(record[17..].try_into().unwrap());
+      Ok(Decoded::Blob(BlobRef { offset, length, hash }))
+    }
+    _ => Err(Detail::DamagedStorage("the value is neither inline nor a blob reference".to_string())),
+  }
+}
+
+/// 分割して与えられた値から [`Hash::hash()`] と同じハッシュ値を算出します。
+struct ValueHasher {
+  #[cfg(feature = "highwayhash64")]
+  builder: highway::HighwayBuilder,
+  #[cfg(not(feature = "highwayhash64"))]
+  digest: Sha2,
+}
+
+#[cfg(feature = "sha224")]
+type Sha2 = sha2::Sha224;
+#[cfg(feature = "sha256")]
+type Sha2 = sha2::Sha256;
+#[cfg(feature = "sha512")]
+type Sha2 = sha2::Sha512;
+#[cfg(feature = "sha512_224")]
+type Sha2 = sha2::Sha512Trunc224;
+#[cfg(feature = "sha512_256")]
+type Sha2 = sha2::Sha512Trunc256;
+
+impl ValueHasher {
+  fn new() -> ValueHasher {
+    #[cfg(feature = "highwayhash64")]
+    return ValueHasher { builder: highway::HighwayBuilder::default() };
+    #[cfg(not(feature = "highwayhash64"))]
+    return ValueHasher { digest: <Sha2 as sha2::Digest>::new() };
+  }
+
+  fn update(&mut self, bytes: &[u8]) {
+    #[cfg(feature = "highwayhash64")]
+    highway::HighwayHash::append(&mut self.builder, bytes);
+    #[cfg(not(feature = "highwayhash64"))]
+    sha2::Digest::update(&mut self.digest, bytes);
+  }
+
+  fn finish(self) -> Hash {
+    #[cfg(feature = "highwayhash64")]
+    return Hash::new(highway::HighwayHash::finalize64(self.builder).to_le_bytes());
+    #[cfg(not(feature = "highwayhash64"))]
+    {
+      let output = sha2::Digest::finalize(self.digest);
+      let mut hash = [0u8; HASH_SIZE];
+      hash.copy_from_slice(&output);
+      Hash::new(hash)
+    }
+  }
+}
@@ -0,0 +1,40 @@
+use std::fs::{remove_file, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::{Arc, RwLock};
+
+use crate::test::{random_payload, temp_file};
+use crate::*;
+
+/// 閾値を超える値がブロブファイルに保存されてハッシュ木には参照のみが残り、ブロブの改ざんが読み出し時に検出される
+/// ことを検証します。
+#[test]
+fn test_detached_blobs() {
+  use blob::DetachedLMTHT;
+  let path = temp_file("lmtht-blobs", ".blobs");
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = DetachedLMTHT::new(LMTHT::new(MemStorage::with(buffer.clone())).unwrap(), &path, 16).unwrap();
+  let large = random_payload(100 * 1024, 1);
+  db.append(b"small").unwrap();
+  db.append(&large).unwrap();
+  db.append_from(&mut &b"streamed"[..]).unwrap();
+  assert!(buffer.read().unwrap().len() < 1024);
+
+  assert_eq!(Some(b"small".to_vec()), db.get(1).unwrap());
+  assert_eq!(Some(large.clone()), db.get(2).unwrap());
+  assert_eq!(Some(b"streamed".to_vec()), db.get(3).unwrap());
+  assert_eq!(None, db.get(4).unwrap());
+  assert_eq!(None, db.blob_ref(1).unwrap());
+  let reference = db.blob_ref(2).unwrap().unwrap();
+  assert_eq!((0, large.len() as u64, Hash::hash(&large)), (reference.offset, reference.length, reference.hash));
+  assert_eq!(Hash::hash(b"streamed"), db.blob_ref(3).unwrap().unwrap().hash);
+
+  // ブロブファイルの改ざんは検出される
+  let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+  file.seek(SeekFrom::Start(10)).unwrap();
+  file.write_all(&[!large[10]]).unwrap();
+  drop(file);
+  assert!(db.get(2).is_err());
+  assert_eq!(Some(b"streamed".to_vec()), db.get(3).unwrap());
+  drop(db);
+  remove_file(&path).unwrap();
+}
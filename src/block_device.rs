@@ -0,0 +1,442 @@
+//! ファイルシステムを介さずにブロックデバイスへ直接バイト列を保存するストレージです。
+//!
+//! ブロックデバイスはファイルと異なり長さを持たないため、デバイスの先頭に 2 つのスーパーブロックを置き、バイト列の
+//! 末尾の位置をそこに記録します。スーパーブロックはそれぞれ 2 ブロックの領域を持ち、識別子、ブロックサイズ、通番、
+//! バイト列の長さ、チェックサムに続いてブロックの境界に満たない末尾のバイト列を保持します。バイト列のブロックの境界
+//! までの部分は 5 番目のブロックから始まり、デバイスへの書き込みは常にブロックの境界に揃えた単位で行われます。
+//!
+//! スーパーブロックは [`Cursor::sync_data()`] の時点でデータのブロックを同期した後に、通番を増やして 2 つの領域へ
+//! 交互に書き込まれます。オープン時にはチェックサムが一致する通番の大きい方を使用するため、スーパーブロックの書き
+//! 込み中に中断した場合は 1 つ前の同期の状態に戻ります。同期済みのバイト列はその場で書き換えられず、末尾のブロック
+//! への追記もスーパーブロックの末尾のバイト列を更新することで行われるため、スーパーブロックが同期されていないブロック
+//! や同期後に書き換えられたブロックを参照することはありません。同期済みの範囲への書き込みはエラーとなります。同期
+//! されていない追記はストレージの破棄時にも書き込まれますが、プロセスが異常終了した場合は最後の同期以降の追記が
+//! 失われます。
+//!
+//! ```rust,no_run
+//! use lmtht::block_device::{BlockDeviceStorage, DEFAULT_BLOCK_SIZE};
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! BlockDeviceStorage::format("/dev/nvme0n1p3", DEFAULT_BLOCK_SIZE)?;
+//! let db = LMTHT::new(BlockDeviceStorage::new("/dev/nvme0n1p3"))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::hash::Hasher;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use highway::{HighwayBuilder, Key};
+
+use crate::error::Detail;
+use crate::{lock2io, Capabilities, Cursor, Result, Storage, CHECKSUM_HW64_KEY};
+
+#[cfg(test)]
+mod test;
+
+/// [`BlockDeviceStorage`] のブロックサイズのデフォルト値です。
+pub const DEFAULT_BLOCK_SIZE: usize = 4 * 1024;
+
+/// スーパーブロックの識別子。
+const SUPERBLOCK_MAGIC: [u8; 8] = *b"LMTHTBLK";
+
+/// スーパーブロックのヘッダのバイトサイズ。識別子、ブロックサイズ (u32)、通番 (u64)、バイト列の長さ (u64)、
+/// チェックサム (u64) で構成され、末尾のバイト列が続く。
+const SUPERBLOCK_HEADER_SIZE: usize = SUPERBLOCK_MAGIC.len() + 4 + 8 + 8 + 8;
+
+/// 1 つのスーパーブロックが使用するブロック数。
+const SUPERBLOCK_BLOCKS: u64 = 2;
+
+/// バイト列を保存する最初のブロック番号。
+const DATA_BLOCK: u64 = 2 * SUPERBLOCK_BLOCKS;
+
+/// ブロックデバイスを直接使用するストレージです。
+pub struct BlockDeviceStorage {
+  path: PathBuf,
+  block_size: usize,
+  device: Mutex<Option<Arc<Device>>>,
+}
+
+impl BlockDeviceStorage {
+  /// 指定されたパスのブロックデバイスを [`DEFAULT_BLOCK_SIZE`] で使用するストレージを構築します。
+  pub fn new<P: AsRef<Path>>(path: P) -> BlockDeviceStorage {
+    Self::with_block_size(path, DEFAULT_BLOCK_SIZE)
+  }
+
+  /// 指定されたパスのブロックデバイスを `block_size` 単位で使用するストレージを構築します。`block_size` はデバイス
+  /// の論理ブロックサイズの倍数であり、同じデバイスに対して常に同じ値を指定する必要があります。
+  pub fn with_block_size<P: AsRef<Path>>(path: P, block_size: usize) -> BlockDeviceStorage {
+    assert!(block_size >= SUPERBLOCK_HEADER_SIZE);
+    BlockDeviceStorage { path: path.as_ref().to_path_buf(), block_size, device: Mutex::new(None) }
+  }
+
+  /// 指定されたブロックデバイスに空のバイト列を表すスーパーブロックを書き込みます。デバイスに保存されていた LMTHT
+  /// は失われます。先頭ブロックがすべて 0 のデバイスは初期化せずに使用することもできます。
+  pub fn format<P: AsRef<Path>>(path: P, block_size: usize) -> Result<()> {
+    assert!(block_size >= SUPERBLOCK_HEADER_SIZE);
+    let mut file = open_device(path.as_ref())?;
+    write_superblock(&mut file, block_size, 0, 0, &[])?;
+    file.seek(SeekFrom::Start(SUPERBLOCK_BLOCKS * block_size as u64))?;
+    file.write_all(&vec![0u8; SUPERBLOCK_BLOCKS as usize * block_size])?;
+    file.sync_data()?;
+    Ok(())
+  }
+
+  /// このストレージが使用しているブロックデバイスのパスを参照します。
+  pub fn path(&self) -> &Path {
+    self.path.as_path()
+  }
+}
+
+impl Storage for BlockDeviceStorage {
+  type Cursor = BlockDeviceCursor;
+  fn open(&self, writable: bool) -> Result<BlockDeviceCursor> {
+    let mut device = lock2io(self.device.lock())?;
+    if device.is_none() {
+      *device = Some(Arc::new(Device::open(&self.path, self.block_size)?));
+    }
+    Ok(BlockDeviceCursor { device: device.as_ref().unwrap().clone(), writable, position: 0 })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::ALL
+  }
+}
+
+/// カーソル間で共有されるブロックデバイスとバイト列の長さ。
+struct Device {
+  state: Mutex<DeviceState>,
+  block_size: usize,
+  /// デバイスのバイトサイズ。
+  capacity: u64,
+  /// バイト列の長さ。
+  length: AtomicU64,
+  /// スーパーブロックに記録されているバイト列の長さ。
+  persisted: AtomicU64,
+}
+
+/// デバイスのファイルとスーパーブロックに記録する内容。
+struct DeviceState {
+  file: File,
+  /// ブロックの境界に満たないバイト列の末尾。
+  tail: Vec<u8>,
+  /// 最後に書き込んだスーパーブロックの通番。
+  seq: u64,
+}
+
+/// スーパーブロックの領域から読み込んだ内容。
+enum Superblock {
+  /// 領域がすべて 0 で、スーパーブロックが書き込まれていない。
+  Empty,
+  /// 識別子を持たない。
+  Foreign,
+  /// チェックサムが一致しない。
+  Broken,
+  /// ブロックサイズ、通番、バイト列の長さ、末尾のバイト列。
+  Valid(usize, u64, u64, Vec<u8>),
+}
+
+impl Device {
+  fn open(path: &Path, block_size: usize) -> Result<Device> {
+    let mut file = open_device(path)?;
+    let capacity = file.seek(SeekFrom::End(0))?;
+    let mut slots = Vec::with_capacity(2);
+    for slot in 0..2 {
+      let mut bytes = vec![0u8; SUPERBLOCK_BLOCKS as usize * block_size];
+      file.seek(SeekFrom::Start(slot * SUPERBLOCK_BLOCKS * block_size as u64))?;
+      file.read_exact(&mut bytes)?;
+      slots.push(read_superblock(&bytes));
+    }
+
+    // チェックサムの一致するスーパーブロックのうち通番の大きいものを使用する
+    let latest = slots
+      .iter()
+      .filter_map(|slot| match slot {
+        Superblock::Valid(recorded, seq, length, tail) => Some((*recorded, *seq, *length, tail)),
+        _ => None,
+      })
+      .max_by_key(|(_, seq, _, _)| *seq);
+    let (seq, length, tail) = match latest {
+      Some((recorded, _, _, _)) if recorded != block_size => {
+        let message = "the block device was formatted with a different block size";
+        return Err(Detail::FileIsNotContentsOfLMTHTree { message });
+      }
+      Some((_, seq, length, tail)) => (seq, length, tail.clone()),
+      None if slots.iter().all(|slot| matches!(slot, Superblock::Empty)) => (0, 0, Vec::new()),
+      None if slots.iter().any(|slot| matches!(slot, Superblock::Broken)) => {
+        return Err(Detail::DamagedStorage("the superblock of the block device is broken".to_string()));
+      }
+      None => {
+        let message = "the block device does not have an LMTHT superblock";
+        return Err(Detail::FileIsNotContentsOfLMTHTree { message });
+      }
+    };
+    Ok(Device {
+      state: Mutex::new(DeviceState { file, tail, seq }),
+      block_size,
+      capacity,
+      length: AtomicU64::new(length),
+      persisted: AtomicU64::new(length),
+    })
+  }
+
+  /// バイト列の `position` から読み込みます。
+  fn read_at(&self, buf: &mut [u8], position: u64) -> io::Result<usize> {
+    let mut state = lock2io(self.state.lock())?;
+    let size = self.block_size as u64;
+    let complete = self.length.load(Ordering::Acquire) / size * size;
+    if position >= complete {
+      let offset = (position - complete) as usize;
+      let length = buf.len().min(state.tail.len().saturating_sub(offset));
+      buf[..length].copy_from_slice(&state.tail[offset..offset + length]);
+      return Ok(length);
+    }
+    let length = buf.len().min((complete - position).min(usize::MAX as u64) as usize);
+    state.file.seek(SeekFrom::Start(size * DATA_BLOCK + position))?;
+    state.file.read(&mut buf[..length])
+  }
+
+  /// バイト列の `position` から `data` を書き込みます。デバイスへの書き込みはブロックの境界に揃えて行い、ブロック
+  /// の境界に満たない末尾は次の同期でスーパーブロックに記録します。同期済みの範囲へは書き込めません。
+  fn write_at(&self, data: &[u8], position: u64) -> io::Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    if position < self.persisted.load(Ordering::Acquire) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "the synced bytes on the block device cannot be rewritten",
+      ));
+    }
+    let size = self.block_size as u64;
+    let length = self.length.load(Ordering::Acquire);
+
+    // 末尾より後ろからの書き込みは間を 0 で埋める
+    let mut filled = Vec::new();
+    let (data, position) = if position > length {
+      filled.resize((position - length) as usize, 0);
+      filled.extend_from_slice(data);
+      (filled.as_slice(), length)
+    } else {
+      (data, position)
+    };
+    if data.is_empty() {
+      return Ok(());
+    }
+    let end = position + data.len() as u64;
+    let new_length = length.max(end);
+    if size * DATA_BLOCK + new_length.div_ceil(size) * size > self.capacity {
+      return Err(io::Error::other("no space left on the block device"));
+    }
+
+    // 書き込む範囲を含むブロックの列に既存の内容を読み込んでから書き込む内容を重ねる
+    let (first, last) = (position / size, end.div_ceil(size));
+    let tail_block = length / size;
+    let mut blocks = vec![0u8; ((last - first) * size) as usize];
+    for k in [first, last - 1] {
+      let block = &mut blocks[((k - first) * size) as usize..][..self.block_size];
+      if k < tail_block {
+        state.file.seek(SeekFrom::Start(size * (DATA_BLOCK + k)))?;
+        state.file.read_exact(block)?;
+      } else if k == tail_block {
+        block[..state.tail.len()].copy_from_slice(&state.tail);
+      }
+    }
+    let offset = (position - first * size) as usize;
+    blocks[offset..offset + data.len()].copy_from_slice(data);
+
+    // 境界まで埋まったブロックのみをデバイスに書き込み、残りを末尾のバイト列とする
+    let new_tail_block = new_length / size;
+    let complete = new_tail_block.clamp(first, last) - first;
+    if complete > 0 {
+      state.file.seek(SeekFrom::Start(size * (DATA_BLOCK + first)))?;
+      state.file.write_all(&blocks[..(complete * size) as usize])?;
+    }
+    if new_tail_block < last {
+      let start = ((new_tail_block - first) * size) as usize;
+      state.tail = blocks[start..start + (new_length % size) as usize].to_vec();
+    } else if new_length > length {
+      state.tail.clear();
+    }
+    self.length.store(new_length, Ordering::Release);
+    Ok(())
+  }
+
+  /// バイト列を `length` に切り詰めます。
+  fn truncate(&self, length: u64) -> io::Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    let size = self.block_size as u64;
+    let current = self.length.load(Ordering::Acquire);
+    if length / size < current / size {
+      let mut tail = vec![0u8; (length % size) as usize];
+      state.file.seek(SeekFrom::Start(size * (DATA_BLOCK + length / size)))?;
+      state.file.read_exact(&mut tail)?;
+      state.tail = tail;
+    } else {
+      state.tail.truncate((length % size) as usize);
+    }
+    self.length.store(length, Ordering::Release);
+    Ok(())
+  }
+
+  /// データのブロックを同期した後に現在の長さと末尾のバイト列を次のスーパーブロックに記録して同期します。
+  fn persist(&self) -> io::Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    let length = self.length.load(Ordering::Acquire);
+    state.file.sync_data()?;
+    if length != self.persisted.load(Ordering::Acquire) {
+      let seq = state.seq + 1;
+      let DeviceState { file, tail, .. } = &mut *state;
+      write_superblock(file, self.block_size, seq, length, tail)?;
+      state.seq = seq;
+      self.persisted.store(length, Ordering::Release);
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Device {
+  fn drop(&mut self) {
+    // 破棄時の書き込みはエラーを報告できないため、確実に記録するには事前に同期する必要がある
+    if self.length.load(Ordering::Acquire) != self.persisted.load(Ordering::Acquire) {
+      let _ = self.persist();
+    }
+  }
+}
+
+/// [`BlockDeviceStorage`] が使用するカーソルです。
+pub struct BlockDeviceCursor {
+  device: Arc<Device>,
+  writable: bool,
+  position: u64,
+}
+
+impl Cursor for BlockDeviceCursor {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.device.persist()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let current = self.device.length.load(Ordering::Acquire);
+    if length > current {
+      self.device.write_at(&vec![0u8; (length - current) as usize], current)?;
+    } else if length < current {
+      self.device.truncate(length)?;
+    }
+    self.device.persist()
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    Ok(self.device.length.load(Ordering::Acquire))
+  }
+}
+
+impl io::Seek for BlockDeviceCursor {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      io::SeekFrom::Start(position) => (position, 0),
+      io::SeekFrom::End(offset) => (self.device.length.load(Ordering::Acquire), offset),
+      io::SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl io::Read for BlockDeviceCursor {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    // スーパーブロックの長さを超える範囲はデバイス上に残っている過去の内容であるため読み込まない
+    let remaining = self.device.length.load(Ordering::Acquire).saturating_sub(self.position);
+    let length = buf.len().min(remaining.min(usize::MAX as u64) as usize);
+    if length == 0 {
+      return Ok(0);
+    }
+    let length = self.device.read_at(&mut buf[..length], self.position)?;
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl io::Write for BlockDeviceCursor {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    self.device.write_at(buf, self.position)?;
+    self.position += buf.len() as u64;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// ブロックデバイスを読み書き用にオープンします。ブロックデバイスは作成しません。
+fn open_device(path: &Path) -> Result<File> {
+  OpenOptions::new()
+    .read(true)
+    .write(true)
+    .open(path)
+    .map_err(|err| Detail::FailedToOpenLocalFile { file: path.to_string_lossy().to_string(), message: err.to_string() })
+}
+
+/// 通番 `seq` に対応する領域にスーパーブロックを書き込んで同期します。
+fn write_superblock(file: &mut File, block_size: usize, seq: u64, length: u64, tail: &[u8]) -> io::Result<()> {
+  let mut block = vec![0u8; SUPERBLOCK_BLOCKS as usize * block_size];
+  block[..8].copy_from_slice(&SUPERBLOCK_MAGIC);
+  block[8..12].copy_from_slice(&(block_size as u32).to_le_bytes());
+  block[12..20].copy_from_slice(&seq.to_le_bytes());
+  block[20..28].copy_from_slice(&length.to_le_bytes());
+  block[SUPERBLOCK_HEADER_SIZE..SUPERBLOCK_HEADER_SIZE + tail.len()].copy_from_slice(tail);
+  let checksum = superblock_checksum(&block[..28], tail);
+  block[28..SUPERBLOCK_HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+  file.seek(SeekFrom::Start((seq % 2) * SUPERBLOCK_BLOCKS * block_size as u64))?;
+  file.write_all(&block)?;
+  file.sync_data()
+}
+
+/// スーパーブロックの領域のバイト列を解析します。
+fn read_superblock(bytes: &[u8]) -> Superblock {
+  if bytes.iter().all(|b| *b == 0) {
+    return Superblock::Empty;
+  } else if bytes[..SUPERBLOCK_MAGIC.len()] != SUPERBLOCK_MAGIC {
+    return Superblock::Foreign;
+  }
+  let recorded = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+  let seq = u64::from_le_bytes(bytes[12..20].try_into().unwrap());
+  let length = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+  if recorded == 0 {
+    return Superblock::Broken;
+  }
+  let tail_length = (length % recorded as u64) as usize;
+  let tail = match bytes.get(SUPERBLOCK_HEADER_SIZE..SUPERBLOCK_HEADER_SIZE + tail_length) {
+    Some(tail) => tail,
+    None => return Superblock::Broken,
+  };
+  if bytes[28..SUPERBLOCK_HEADER_SIZE] != superblock_checksum(&bytes[..28], tail).to_le_bytes() {
+    return Superblock::Broken;
+  }
+  Superblock::Valid(recorded, seq, length, tail.to_vec())
+}
+
+fn superblock_checksum(header: &[u8], tail: &[u8]) -> u64 {
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  Hasher::write(&mut hasher, header);
+  Hasher::write(&mut hasher, tail);
+  hasher.finish()
+}
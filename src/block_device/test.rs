@@ -0,0 +1,90 @@
+use std::convert::TryInto;
+use std::fs::{read, remove_file, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::test::temp_file;
+use crate::*;
+
+/// ブロックデバイスのスーパーブロックに記録した長さから LMTHT を再オープンでき、デバイスの末尾に残る過去の内容や
+/// 同期されていない追記を参照しないことを検証します。
+#[test]
+fn test_block_device_storage() {
+  use block_device::BlockDeviceStorage;
+  let file = temp_file("lmtht-block-device", ".img");
+  OpenOptions::new().write(true).open(&file).unwrap().set_len(1024 * 1024).unwrap();
+  BlockDeviceStorage::format(&file, 512).unwrap();
+
+  let db = LMTHT::new(BlockDeviceStorage::with_block_size(&file, 512)).unwrap();
+  for i in 0..50u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  db.sync().unwrap();
+  let root = db.root().unwrap();
+  drop(db);
+
+  let db = LMTHT::new(BlockDeviceStorage::with_block_size(&file, 512)).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..50u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+  drop((query, db));
+
+  // 異なるブロックサイズや LMTHT ではない内容はオープンできない
+  assert!(LMTHT::new(BlockDeviceStorage::with_block_size(&file, 4096)).is_err());
+  let mut device = OpenOptions::new().write(true).open(&file).unwrap();
+  for slot in 0..2 {
+    device.seek(SeekFrom::Start(slot * 1024)).unwrap();
+    device.write_all(b"not a superblock").unwrap();
+  }
+  assert!(LMTHT::new(BlockDeviceStorage::with_block_size(&file, 512)).is_err());
+  remove_file(&file).unwrap();
+}
+
+/// 同期済みのバイト列がその場で書き換えられず、最新のスーパーブロックが破損している場合は 1 つ前の同期の状態で
+/// オープンされることを検証します。
+#[test]
+fn test_block_device_superblocks() {
+  use block_device::BlockDeviceStorage;
+  let file = temp_file("lmtht-block-device-superblocks", ".img");
+  OpenOptions::new().write(true).open(&file).unwrap().set_len(1024 * 1024).unwrap();
+  BlockDeviceStorage::format(&file, 512).unwrap();
+
+  let db = LMTHT::new(BlockDeviceStorage::with_block_size(&file, 512)).unwrap();
+  for i in 0..30u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  db.sync().unwrap();
+  let (root, length) = (db.root().unwrap(), db.storage().open(false).unwrap().len().unwrap());
+  let synced = read(&file).unwrap();
+
+  // 同期済みの範囲への書き込みはエラーとなり、追記はブロックの境界に満たない同期済みの末尾を書き換えない
+  let mut cursor = db.storage().open(true).unwrap();
+  cursor.seek(SeekFrom::Start(length - 1)).unwrap();
+  assert!(cursor.write_all(&[0xFF]).is_err());
+  drop(cursor);
+  for i in 30..60u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  db.sync().unwrap();
+  let image = read(&file).unwrap();
+  let (data, complete) = (4 * 512, length as usize / 512 * 512);
+  assert_eq!(synced[data..data + complete], image[data..data + complete]);
+  drop(db);
+
+  // 最新のスーパーブロックを破損させると 1 つ前の同期の状態に戻る
+  let seq = |slot: usize| u64::from_le_bytes(image[slot * 1024 + 12..slot * 1024 + 20].try_into().unwrap());
+  let latest = if seq(0) > seq(1) { 0 } else { 1 };
+  let mut device = OpenOptions::new().write(true).open(&file).unwrap();
+  device.seek(SeekFrom::Start(latest * 1024 + 40)).unwrap();
+  device.write_all(&[0xFF; 8]).unwrap();
+  drop(device);
+  let db = LMTHT::new(BlockDeviceStorage::with_block_size(&file, 512)).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..30u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+  drop((query, db));
+  remove_file(&file).unwrap();
+}
@@ -0,0 +1,195 @@
+//! 値のハッシュ値がハッシュ木に含まれていないことを高速に判定するためのブルームフィルタです。
+//!
+//! ブルームフィルタはすべてのエントリの葉ノードのハッシュ値を登録したビット列で、
+//! [`LMTHTOptions::bloom_filter`](crate::LMTHTOptions::bloom_filter) に指定したサイドカーのストレージに保存され、
+//! 追加のたびに更新されます。[`Query::probably_contains()`] は木構造を探索することなく、値がハッシュ木に含まれて
+//! いないことを確定的に、含まれていることを確率的に判定します。ほとんどの問い合わせが含まれていない値に対するもので
+//! ある場合、含まれている可能性のある値のみをハッシュ索引や証明の取得で確認することで読み込みを大幅に削減できます。
+//!
+//! サイドカーは次の形式です。整数はすべてリトルエンディアンです。ヘッダはビット列を更新した後に書き込まれるため、
+//! 書き込み途中で中断した場合もビット列に登録漏れが生じることはありません。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | ビット数 m (u64) | 8 |
+//! | ハッシュ関数の数 k (u8) | 1 |
+//! | 登録済みの最後のエントリのインデックス | [`INDEX_SIZE`](crate::INDEX_SIZE) のビット幅 |
+//! | 登録済みの最後のエントリのハッシュ値 | [`HASH_SIZE`](crate::HASH_SIZE) |
+//! | ヘッダのチェックサム | 8 |
+//! | ビット列 | m / 8 |
+//!
+//! ブルームフィルタはハッシュ木から再構築可能な補助情報です。オープン時にビット数が異なる場合やハッシュ木と一致
+//! しない場合はすべてのエントリから再構築されます。
+//!
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+use highway::{HighwayBuilder, HighwayHash, Key};
+
+use crate::error::Detail;
+use crate::hash_index::{read_hash, scan_leaf_hashes};
+use crate::{lock2io, Cursor, DynStorage, Hash, Index, Query, Result, CHECKSUM_HW64_KEY, HASH_SIZE, INDEX_BYTES};
+
+#[cfg(test)]
+mod test;
+
+/// 1 つのハッシュ値に対して設定するビットの数です。
+const HASHES: u8 = 7;
+
+/// サイドカーのヘッダのバイトサイズ。
+const HEADER_SIZE: usize = 8 + 1 + INDEX_BYTES + HASH_SIZE + 8;
+
+/// サイドカーストレージに保存されたブルームフィルタです。
+pub(crate) struct BloomFilter {
+  storage: Arc<dyn DynStorage + Send + Sync>,
+  state: Mutex<State>,
+}
+
+/// メモリ上に読み込んだブルームフィルタ。
+struct State {
+  bits: Vec<u8>,
+  /// 登録済みの最後のエントリのインデックス。
+  indexed: Index,
+  /// 登録済みの最後のエントリのハッシュ値。
+  last: [u8; HASH_SIZE],
+  /// サイドカーに書き込まれていない変更があるか。
+  dirty: bool,
+}
+
+impl BloomFilter {
+  /// 指定されたストレージに保存されている `bits` ビットのブルームフィルタを読み込みます。ストレージが空であるか
+  /// ビット数が異なる場合は空のブルームフィルタを作成します。
+  pub fn open(storage: Arc<dyn DynStorage + Send + Sync>, bits: u64) -> Result<BloomFilter> {
+    let length = bits.max(8).div_ceil(8) as usize;
+    let mut bytes = Vec::new();
+    storage.open_dyn(false)?.read_to_end(&mut bytes)?;
+    let state = match parse_header(&bytes) {
+      Some((m, indexed, last)) if m == length as u64 * 8 && bytes.len() == HEADER_SIZE + length => {
+        State { bits: bytes.split_off(HEADER_SIZE), indexed, last, dirty: false }
+      }
+      _ => State { bits: vec![0u8; length], indexed: 0, last: [0u8; HASH_SIZE], dirty: true },
+    };
+    Ok(BloomFilter { storage, state: Mutex::new(state) })
+  }
+
+  /// ブルームフィルタが `query` の世代のすべてのエントリを登録していることを確認します。登録済みの最後のエントリが
+  /// ハッシュ木と一致しない場合はすべてのエントリから再構築し、未登録のエントリは葉ノードのハッシュ値を読み込んで
+  /// 登録します。
+  pub fn prepare<C: Cursor>(&self, query: &mut Query<C>) -> Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    let n = query.n();
+    let consistent = match state.indexed {
+      0 => true,
+      indexed if indexed <= n => read_hash(query, indexed)?.value == state.last,
+      _ => false,
+    };
+    if !consistent {
+      state.bits.iter_mut().for_each(|b| *b = 0);
+      state.indexed = 0;
+      state.dirty = true;
+    }
+    let state = &mut *state;
+    scan_leaf_hashes(query, state.indexed + 1, n, |i, hash| {
+      for bit in bit_positions(&hash, state.bits.len() as u64 * 8) {
+        state.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+      }
+      state.indexed = i;
+      state.last = hash.value;
+      state.dirty = true;
+      Ok(())
+    })?;
+
+    // 変更があればビット列とヘッダを書き込む
+    if state.dirty {
+      let mut cursor = self.storage.open_dyn(true)?;
+      cursor.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+      cursor.write_all(&state.bits)?;
+      write_header(&mut cursor, state)?;
+      state.dirty = false;
+    }
+    Ok(())
+  }
+
+  /// i 番目のエントリの葉ノードのハッシュ値を登録します。
+  pub fn append(&self, i: Index, hash: &Hash) -> Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    let mut cursor = self.storage.open_dyn(true)?;
+    for bit in bit_positions(hash, state.bits.len() as u64 * 8) {
+      let k = (bit / 8) as usize;
+      state.bits[k] |= 1 << (bit % 8);
+      cursor.seek(SeekFrom::Start((HEADER_SIZE + k) as u64))?;
+      cursor.write_all(&state.bits[k..=k])?;
+    }
+    state.indexed = i;
+    state.last = hash.value;
+    write_header(&mut cursor, &state)
+  }
+
+  /// ハッシュ値 `hash` が登録されている可能性がある場合に true を返します。
+  pub fn contains(&self, hash: &Hash) -> Result<bool> {
+    let state = lock2io(self.state.lock())?;
+    Ok(
+      bit_positions(hash, state.bits.len() as u64 * 8)
+        .all(|bit| state.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0),
+    )
+  }
+}
+
+/// ハッシュ値 `hash` に対して設定する `m` ビット中の [`HASHES`] 個のビットの位置を返します。
+fn bit_positions(hash: &Hash, m: u64) -> impl Iterator<Item = u64> {
+  let [h1, h2] = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY)).hash128(&hash.value);
+  (0..HASHES as u64).map(move |j| h1.wrapping_add(j.wrapping_mul(h2)) % m)
+}
+
+/// サイドカーの先頭のヘッダを読み込み、ビット数と登録済みの最後のエントリを返します。
+fn parse_header(bytes: &[u8]) -> Option<(u64, Index, [u8; HASH_SIZE])> {
+  if bytes.len() < HEADER_SIZE || bytes[HEADER_SIZE - 8..HEADER_SIZE] != checksum_of(&bytes[..HEADER_SIZE - 8]) {
+    return None;
+  }
+  if bytes[8] != HASHES {
+    return None;
+  }
+  let m = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+  let indexed = Index::from_le_bytes(bytes[9..9 + INDEX_BYTES].try_into().unwrap());
+  let last = bytes[9 + INDEX_BYTES..9 + INDEX_BYTES + HASH_SIZE].try_into().unwrap();
+  Some((m, indexed, last))
+}
+
+/// ビット列を書き込んだ後にヘッダを書き込みます。
+fn write_header(cursor: &mut Box<dyn Cursor>, state: &State) -> Result<()> {
+  let mut header = Vec::with_capacity(HEADER_SIZE);
+  header.extend_from_slice(&(state.bits.len() as u64 * 8).to_le_bytes());
+  header.push(HASHES);
+  header.extend_from_slice(&state.indexed.to_le_bytes());
+  header.extend_from_slice(&state.last);
+  let checksum = checksum_of(&header);
+  header.extend_from_slice(&checksum);
+  cursor.flush()?;
+  cursor.seek(SeekFrom::Start(0))?;
+  cursor.write_all(&header)?;
+  cursor.flush()?;
+  Ok(())
+}
+
+fn checksum_of(body: &[u8]) -> [u8; 8] {
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, body);
+  std::hash::Hasher::finish(&hasher).to_le_bytes()
+}
+
+impl<C: Cursor> Query<C> {
+  /// ハッシュ値 `hash` を持つ値がハッシュ木に含まれている可能性がある場合に true を返します。false の場合、その値は
+  /// 確実に含まれていません。true の場合も一定の確率で含まれていないことがあり、またこのクエリーより新しい世代で
+  /// 追加された値に対しても true を返すため、[`Query::find_by_hash()`] や証明によって確認する必要があります。
+  ///
+  /// # Errors
+  /// [`LMTHTOptions::bloom_filter`](crate::LMTHTOptions::bloom_filter) が指定されていない場合は
+  /// [`Detail::NoBloomFilter`] を返します。
+  pub fn probably_contains(&self, hash: &Hash) -> Result<bool> {
+    match &self.bloom {
+      Some(bloom) => bloom.contains(hash),
+      None => Err(Detail::NoBloomFilter),
+    }
+  }
+}
@@ -0,0 +1,51 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::*;
+
+/// ブルームフィルタが追加したすべての値を含まれている可能性があると判定し、含まれていない値の多くを除外することを
+/// 検証します。
+#[test]
+fn test_bloom_filter() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let bloom = Arc::new(RwLock::new(Vec::new()));
+  let open = |bloom: &Arc<RwLock<Vec<u8>>>, bits: u64| {
+    let storage = Arc::new(MemStorage::with(bloom.clone()));
+    let hashes = Arc::new(MemStorage::new());
+    LMTHT::builder(MemStorage::with(buffer.clone())).bloom_filter(storage, bits).hash_index(hashes).open().unwrap()
+  };
+  let db = open(&bloom, 4096);
+  for i in 0..200u32 {
+    db.append(&i.to_le_bytes()).unwrap();
+  }
+  let contains_all = |db: &LMTHT<MemStorage>| {
+    let query = db.query().unwrap();
+    (0..200u32).all(|i| query.probably_contains(&Hash::hash(&i.to_le_bytes())).unwrap())
+  };
+  let false_positives = |db: &LMTHT<MemStorage>| {
+    let query = db.query().unwrap();
+    (200..1200u32).filter(|i| query.probably_contains(&Hash::hash(&i.to_le_bytes())).unwrap()).count()
+  };
+  assert!(contains_all(&db));
+  assert!(false_positives(&db) < 100);
+  assert!(db.query().unwrap().find_by_hash(&Hash::hash(&5000u32.to_le_bytes())).unwrap().is_empty());
+  assert_eq!(vec![8], db.query().unwrap().find_by_hash(&Hash::hash(&7u32.to_le_bytes())).unwrap());
+  drop(db);
+
+  // 再オープン後も登録した値を保持し、ビット数の変更や失われたサイドカーは再構築される
+  let db = open(&bloom, 4096);
+  assert!(contains_all(&db));
+  db.append(&200u32.to_le_bytes()).unwrap();
+  assert!(db.query().unwrap().probably_contains(&Hash::hash(&200u32.to_le_bytes())).unwrap());
+  drop(db);
+  let db = open(&bloom, 8192);
+  assert!(contains_all(&db));
+  assert_eq!(1024 + 8 + 1 + INDEX_SIZE as usize / 8 + HASH_SIZE + 8, bloom.read().unwrap().len());
+  drop(db);
+  let db = open(&Arc::new(RwLock::new(Vec::new())), 4096);
+  assert!(contains_all(&db));
+  drop(db);
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  assert!(matches!(db.query().unwrap().probably_contains(&Hash::hash(b"")), Err(Detail::NoBloomFilter)));
+}
@@ -0,0 +1,276 @@
+//! 変更されることのないベースのストレージを共有し、分岐後に追加したエントリのみを別のストレージに保存するブランチ
+//! です。
+//!
+//! [`BranchStorage`] はベースのストレージの世代 n までを読み込み専用で参照し、それ以降の位置への書き込みをオーバー
+//! レイのストレージに保存します。エントリが参照する左枝のノードはストレージ上の位置で表されるため、オーバーレイに
+//! 追加したエントリはベースのエントリをそのまま参照することができ、実験や what-if の処理のために巨大な履歴を複製
+//! する必要がありません。
+//!
+//! バッチの途中のエントリで分岐できるよう、世代 n のエントリはバッチの継続を表すフラグを除いてオーバーレイの先頭に
+//! 複製されます。オーバーレイは次の形式のヘッダから始まります。整数はすべてリトルエンディアンです。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | 識別子 `lmtht-br` | 8 |
+//! | ベースから参照する領域のバイトサイズ (u64) | 8 |
+//! | 分岐した世代 n | [`INDEX_SIZE`](crate::INDEX_SIZE) のビット幅 |
+//! | 世代 n のルートハッシュ (n = 0 の場合はすべて 0) | [`HASH_SIZE`] |
+//! | ヘッダのチェックサム | 8 |
+//!
+//! 既存のオーバーレイをオープンするとベースの世代 n のルートハッシュが記録されている値と一致することを検証するため、
+//! ベースが置き換えられた場合は [`Detail::BranchBaseMismatch`] となります。
+//!
+//! ```rust,no_run
+//! use lmtht::branch::BranchStorage;
+//! use lmtht::{FileStorage, LMTHT};
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let branch = LMTHT::new(BranchStorage::open(FileStorage::new("ledger.db"), "what-if.overlay", 1_000_000)?)?;
+//! branch.append(b"hypothetical")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::convert::TryInto;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use highway::{HighwayBuilder, Key};
+
+use crate::error::Detail;
+use crate::{
+  read_entry, set_continued, Capabilities, Cursor, Hash, Index, LMTHTOptions, Query, Result, Storage,
+  CHECKSUM_HW64_KEY, HASH_SIZE, INDEX_BYTES, LMTHT, STORAGE_IDENTIFIER,
+};
+
+#[cfg(test)]
+mod test;
+
+/// オーバーレイのストレージを識別するための先頭のバイト列です。
+const BRANCH_IDENTIFIER: &[u8; 8] = b"lmtht-br";
+
+/// オーバーレイのヘッダのバイトサイズ。
+const HEADER_SIZE: u64 = (8 + 8 + INDEX_BYTES + HASH_SIZE + 8) as u64;
+
+/// ベースのストレージの世代 n までを共有し、以降のエントリをオーバーレイに保存するストレージです。
+pub struct BranchStorage<B: Storage, O: Storage> {
+  shared: Arc<Shared<B, O>>,
+}
+
+struct Shared<B: Storage, O: Storage> {
+  base: B,
+  overlay: O,
+  /// ベースから参照する領域のバイトサイズ。
+  base_end: u64,
+  /// 分岐した世代。
+  n: Index,
+}
+
+impl<B: Storage, O: Storage> BranchStorage<B, O> {
+  /// ベースのストレージ `base` の世代 `n` から分岐したブランチをオープンします。`overlay` が空の場合は世代 `n` の
+  /// エントリの位置を探索してオーバーレイを初期化し、既存のオーバーレイの場合は記録されている分岐点がベースと一致
+  /// することを検証します。
+  ///
+  /// # Errors
+  /// ベースの世代が `n` に満たない場合は [`Detail::GenerationNotAppended`] を、既存のオーバーレイが別の世代や別の
+  /// ベースから分岐している場合は [`Detail::BranchBaseMismatch`] を返します。
+  pub fn open(base: B, overlay: O, n: Index) -> Result<BranchStorage<B, O>> {
+    let options = LMTHTOptions { read_only: true, ..Default::default() };
+    let db = LMTHT::with_options(Borrowed(&base), options)?;
+    if n > db.n() {
+      return Err(Detail::GenerationNotAppended { n, current: db.n() });
+    }
+    let mut query = db.query()?;
+    let root = query.get_root(n)?.map(|root| root.hash);
+
+    let mut cursor = overlay.open(true)?;
+    let base_end = if cursor.len()? == 0 {
+      // 世代 n のエントリをコミットを表すエントリとしてオーバーレイに複製する
+      let (base_end, mut entry) = entry_at(&mut query, n)?;
+      if !entry.is_empty() {
+        set_continued(&mut entry, false);
+      }
+      cursor.seek(SeekFrom::Start(0))?;
+      cursor.write_all(&header(base_end, n, root.as_ref()))?;
+      cursor.write_all(&entry)?;
+      cursor.flush()?;
+      cursor.sync_data()?;
+      base_end
+    } else {
+      let mut bytes = [0u8; HEADER_SIZE as usize];
+      cursor.seek(SeekFrom::Start(0))?;
+      cursor.read_exact(&mut bytes)?;
+      let base_end = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+      if &bytes[..8] != BRANCH_IDENTIFIER || header(base_end, n, root.as_ref())[..] != bytes[..] {
+        return Err(Detail::BranchBaseMismatch { n });
+      }
+      base_end
+    };
+    drop(cursor);
+    drop(query);
+    drop(db);
+    Ok(BranchStorage { shared: Arc::new(Shared { base, overlay, base_end, n }) })
+  }
+
+  /// 分岐した世代を参照します。
+  pub fn base_generation(&self) -> Index {
+    self.shared.n
+  }
+
+  /// ベースのストレージを参照します。
+  pub fn base(&self) -> &B {
+    &self.shared.base
+  }
+
+  /// オーバーレイのストレージを参照します。
+  pub fn overlay(&self) -> &O {
+    &self.shared.overlay
+  }
+}
+
+impl<B: Storage, O: Storage> Storage for BranchStorage<B, O> {
+  type Cursor = BranchCursor<B::Cursor, O::Cursor>;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    let base = self.shared.base.open(false)?;
+    let overlay = self.shared.overlay.open(writable)?;
+    Ok(BranchCursor { base, overlay, base_end: self.shared.base_end, position: 0 })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.shared.overlay.capabilities()
+  }
+}
+
+/// [`BranchStorage`] が使用するカーソルです。
+pub struct BranchCursor<B: Cursor, O: Cursor> {
+  base: B,
+  overlay: O,
+  base_end: u64,
+  position: u64,
+}
+
+impl<B: Cursor, O: Cursor> BranchCursor<B, O> {
+  /// 位置 `position` がベースの領域に含まれる場合はエラーを返します。
+  fn check_overlay(&self, position: u64) -> io::Result<()> {
+    if position < self.base_end {
+      return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the base of the branch is immutable"));
+    }
+    Ok(())
+  }
+}
+
+impl<B: Cursor, O: Cursor> Cursor for BranchCursor<B, O> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.overlay.sync_data()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.check_overlay(length)?;
+    self.overlay.set_len(HEADER_SIZE + length - self.base_end)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    Ok(self.base_end + self.overlay.len()?.saturating_sub(HEADER_SIZE))
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.check_overlay(position)?;
+    self.overlay.discard(HEADER_SIZE + position - self.base_end, length)?;
+    self.position = position + length;
+    Ok(())
+  }
+}
+
+impl<B: Cursor, O: Cursor> io::Seek for BranchCursor<B, O> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => (position, 0),
+      SeekFrom::End(offset) => (self.len()?, offset),
+      SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl<B: Cursor, O: Cursor> io::Read for BranchCursor<B, O> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    // 1 回の読み込みはベースとオーバーレイの境界を越えない
+    let length = if self.position < self.base_end {
+      let length = buf.len().min((self.base_end - self.position) as usize);
+      self.base.seek(SeekFrom::Start(self.position))?;
+      self.base.read(&mut buf[..length])?
+    } else {
+      self.overlay.seek(SeekFrom::Start(HEADER_SIZE + self.position - self.base_end))?;
+      self.overlay.read(buf)?
+    };
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<B: Cursor, O: Cursor> io::Write for BranchCursor<B, O> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.check_overlay(self.position)?;
+    self.overlay.seek(SeekFrom::Start(HEADER_SIZE + self.position - self.base_end))?;
+    let length = self.overlay.write(buf)?;
+    self.position += length as u64;
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.overlay.flush()
+  }
+}
+
+/// 世代 `n` のエントリの先頭の位置とそのエントリのバイト列を返します。`n` が 0 の場合はストレージのヘッダの終端と
+/// 空のバイト列を返します。
+fn entry_at<C: Cursor>(query: &mut Query<C>, n: Index) -> Result<(u64, Vec<u8>)> {
+  let head = STORAGE_IDENTIFIER.len() as u64 + 1;
+  if n == 0 {
+    return Ok((head, Vec::new()));
+  }
+  let position =
+    Query::<C>::get_entry_position(&query.gen, &query.node_cache, &mut query.index, &mut query.cursor, n, false)?;
+  let position = match position {
+    Some((position, _)) => position,
+    None => return Err(Detail::DamagedStorage(format!("the entry {} is not found", n))),
+  };
+  query.cursor.seek(SeekFrom::Start(position))?;
+  read_entry(&mut query.cursor, n)?;
+  let end = query.cursor.stream_position()?;
+  let mut entry = vec![0u8; (end - position) as usize];
+  query.cursor.seek(SeekFrom::Start(position))?;
+  query.cursor.read_exact(&mut entry)?;
+  Ok((position, entry))
+}
+
+/// オーバーレイのヘッダを直列化します。
+fn header(base_end: u64, n: Index, root: Option<&Hash>) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(HEADER_SIZE as usize);
+  bytes.extend_from_slice(BRANCH_IDENTIFIER);
+  bytes.extend_from_slice(&base_end.to_le_bytes());
+  bytes.extend_from_slice(&n.to_le_bytes());
+  bytes.extend_from_slice(&root.map(|root| root.value).unwrap_or([0u8; HASH_SIZE]));
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, &bytes);
+  bytes.extend_from_slice(&std::hash::Hasher::finish(&hasher).to_le_bytes());
+  bytes
+}
+
+/// 所有権を移さずにベースのストレージを LMTHT に渡すための参照。
+struct Borrowed<'a, S: Storage>(&'a S);
+
+impl<S: Storage> Storage for Borrowed<'_, S> {
+  type Cursor = S::Cursor;
+  fn open(&self, _writable: bool) -> Result<Self::Cursor> {
+    self.0.open(false)
+  }
+}
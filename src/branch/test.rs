@@ -0,0 +1,65 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// ベースのストレージを変更せずに世代 n から分岐したブランチに追加でき、その結果が世代 n で切り出したストレージへの
+/// 追加と一致することを検証します。
+#[test]
+fn test_branch_storage() {
+  use crate::branch::BranchStorage;
+  let base = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(base.clone())).unwrap();
+  for i in 0..10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut batch = db.begin_batch().unwrap();
+  for i in 10..30 {
+    batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  batch.commit().unwrap();
+  let original = base.read().unwrap().clone();
+
+  for n in [0, 7, 20, 30] {
+    let overlay = Arc::new(RwLock::new(Vec::new()));
+    let storage = BranchStorage::open(MemStorage::with(base.clone()), MemStorage::with(overlay.clone()), n).unwrap();
+    assert_eq!(n, storage.base_generation());
+    let branch = LMTHT::new(storage).unwrap();
+    let fork = db.fork_to(n, MemStorage::new()).unwrap();
+    assert_eq!((n, fork.root()), (branch.n(), branch.root()));
+    for i in 100..105 {
+      branch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+      fork.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    }
+    assert_eq!(fork.root(), branch.root());
+    let mut query = branch.query().unwrap();
+    for i in 1..=branch.n() {
+      let expected = fork.query().unwrap().get_with_hashes(i).unwrap().unwrap();
+      assert_eq!(expected.to_bytes(), query.get_with_hashes(i).unwrap().unwrap().to_bytes());
+    }
+    let root = branch.root();
+    drop(query);
+    drop(branch);
+    assert_eq!(original, *base.read().unwrap());
+
+    // 既存のオーバーレイを再びオープン
+    let storage = BranchStorage::open(MemStorage::with(base.clone()), MemStorage::with(overlay.clone()), n).unwrap();
+    assert_eq!(root, LMTHT::new(storage).unwrap().root());
+    let result = BranchStorage::open(MemStorage::with(base.clone()), MemStorage::with(overlay.clone()), (n + 1) % 30);
+    assert!(matches!(result, Err(Detail::BranchBaseMismatch { .. })));
+  }
+
+  // ベースが置き換えられた場合
+  let overlay = Arc::new(RwLock::new(Vec::new()));
+  BranchStorage::open(MemStorage::with(base.clone()), MemStorage::with(overlay.clone()), 5).unwrap();
+  let other = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(other.clone())).unwrap();
+  for i in 0..10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i + 1000)).unwrap();
+  }
+  let result = BranchStorage::open(MemStorage::with(other), MemStorage::with(overlay), 5);
+  assert!(matches!(result, Err(Detail::BranchBaseMismatch { n: 5 })));
+  let result = BranchStorage::open(MemStorage::with(base.clone()), MemStorage::new(), 31);
+  assert!(matches!(result, Err(Detail::GenerationNotAppended { n: 31, current: 30 })));
+}
@@ -0,0 +1,302 @@
+//! ストレージのカーソルに読み込みと書き込みのバッファリングを追加します。
+//!
+//! エントリの直列化は多数の小さな `write_u*` 呼び出しで構成されており、中間ノードの探索も数十バイト単位の読み込みを
+//! 繰り返します。これらを OS やデバイスに一つずつ発行しないよう、連続した書き込みはバッファに蓄積してまとめて出力し、
+//! 読み込みはバッファの大きさ単位で先読みします。
+//!
+use std::io;
+use std::io::{IoSlice, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::retry::RetryPolicy;
+use crate::{Cursor, IoCounts};
+
+#[cfg(test)]
+mod test;
+
+/// 読み込みと書き込みをバッファリングするカーソルです。
+///
+/// 書き込みバッファはシーク先が連続していない書き込み、読み込み、末尾からのシーク、および `flush()` の時点で下位の
+/// カーソルに出力されます。`drop()` 時にも出力されますがエラーは無視されるため、書き込んだ内容を確定させるには明示的
+/// に `flush()` を呼び出す必要があります。バッファサイズに 0 を指定した場合はその方向のバッファリングを行いません。
+pub struct BufferedCursor<C: Cursor> {
+  inner: C,
+  /// このカーソルの論理的な位置。
+  position: u64,
+  /// 下位のカーソルの現在の位置。不明な場合は `None`。
+  inner_position: Option<u64>,
+  read_capacity: usize,
+  read_buffer: Vec<u8>,
+  /// `read_buffer[0]` に対応する論理的な位置。
+  read_start: u64,
+  write_capacity: usize,
+  write_buffer: Vec<u8>,
+  /// `write_buffer[0]` を書き込む論理的な位置。
+  write_start: u64,
+  /// 下位のカーソルに対して行ったシークと読み込みの累計。
+  counts: IoCounts,
+  /// 下位のカーソルに対する操作が一時的なエラーで失敗した場合の再実行のポリシー。
+  retry: Option<RetryPolicy>,
+  /// ストレージ上のエントリを書き換えた回数と、読み込みバッファを読み込んだ時点のその値。
+  rewrites: Option<(Arc<AtomicU64>, u64)>,
+}
+
+impl<C: Cursor> BufferedCursor<C> {
+  /// 指定されたカーソルを読み込みバッファ `read_capacity` バイト、書き込みバッファ `write_capacity` バイトで
+  /// バッファリングします。
+  pub fn new(inner: C, read_capacity: usize, write_capacity: usize) -> BufferedCursor<C> {
+    BufferedCursor {
+      inner,
+      position: 0,
+      inner_position: None,
+      read_capacity,
+      read_buffer: Vec::with_capacity(read_capacity),
+      read_start: 0,
+      write_capacity,
+      write_buffer: Vec::with_capacity(write_capacity),
+      write_start: 0,
+      counts: IoCounts::default(),
+      retry: None,
+      rewrites: None,
+    }
+  }
+
+  /// 下位のカーソルに対する操作が一時的なエラーで失敗した場合に `retry` に従って再実行します。
+  pub fn with_retry(mut self, retry: Option<RetryPolicy>) -> BufferedCursor<C> {
+    self.retry = retry;
+    self
+  }
+
+  /// 他のカーソルによるストレージ上の書き換えの回数 `rewrites` が変化した場合に読み込みバッファを破棄します。
+  pub fn with_rewrites(mut self, rewrites: Arc<AtomicU64>) -> BufferedCursor<C> {
+    let current = rewrites.load(Ordering::Acquire);
+    self.rewrites = Some((rewrites, current));
+    self
+  }
+
+  /// 下位のカーソルを参照します。
+  pub fn get_ref(&self) -> &C {
+    &self.inner
+  }
+
+  /// 下位のカーソルを指定された位置に移動します。すでにその位置にある場合はシークを行いません。
+  fn seek_inner(&mut self, position: u64) -> io::Result<()> {
+    if self.inner_position != Some(position) {
+      self.counts.seeks += 1;
+      self.inner_position = Some(self.inner.seek(SeekFrom::Start(position))?);
+    }
+    Ok(())
+  }
+
+  /// 下位のカーソルに対して `f` を実行します。`position` が指定された場合は実行の前に下位のカーソルをその位置に
+  /// 移動します。リトライポリシーが指定されている場合、一時的なエラーで失敗した操作は位置を移動し直して再実行します。
+  fn run_inner<T, F: FnMut(&mut C) -> io::Result<T>>(&mut self, position: Option<u64>, mut f: F) -> io::Result<T> {
+    let retry = self.retry.clone();
+    let mut once = || {
+      if let Some(position) = position {
+        self.seek_inner(position)?;
+        self.inner_position = None;
+      }
+      f(&mut self.inner)
+    };
+    match retry {
+      Some(retry) => retry.run(once),
+      None => once(),
+    }
+  }
+
+  /// 書き込みバッファの内容を下位のカーソルに出力します。
+  fn flush_write_buffer(&mut self) -> io::Result<()> {
+    if !self.write_buffer.is_empty() {
+      let buffer = std::mem::take(&mut self.write_buffer);
+      let result = self.run_inner(Some(self.write_start), |inner| inner.write_all(&buffer));
+      self.write_buffer = buffer;
+      result?;
+      self.inner_position = Some(self.write_start + self.write_buffer.len() as u64);
+      self.write_buffer.clear();
+    }
+    Ok(())
+  }
+}
+
+impl<C: Cursor> Cursor for BufferedCursor<C> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.flush_write_buffer()?;
+    self.run_inner(None, |inner| inner.sync_data())
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.flush_write_buffer()?;
+    self.read_buffer.clear();
+    self.run_inner(None, |inner| inner.set_len(length))
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.flush_write_buffer()?;
+    self.read_buffer.clear();
+    self.inner_position = None;
+    self.run_inner(None, |inner| inner.discard(position, length))?;
+    self.inner_position = Some(position + length);
+    self.position = position + length;
+    Ok(())
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    let length = self.run_inner(None, |inner| inner.len())?;
+    // バッファリングしている書き込みは下位のストレージの末尾を越えている可能性がある
+    if self.write_buffer.is_empty() {
+      Ok(length)
+    } else {
+      Ok(length.max(self.write_start + self.write_buffer.len() as u64))
+    }
+  }
+
+  fn io_counts(&self) -> IoCounts {
+    self.counts
+  }
+
+  fn duplicate(&self) -> io::Result<Option<Self>> {
+    let cursor = match self.inner.duplicate()? {
+      Some(inner) => BufferedCursor::new(inner, self.read_capacity, self.write_capacity).with_retry(self.retry.clone()),
+      None => return Ok(None),
+    };
+    Ok(Some(match &self.rewrites {
+      Some((rewrites, _)) => cursor.with_rewrites(rewrites.clone()),
+      None => cursor,
+    }))
+  }
+}
+
+impl<C: Cursor> Seek for BufferedCursor<C> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => (position, 0),
+      SeekFrom::Current(offset) => (self.position, offset),
+      SeekFrom::End(offset) => {
+        // 末尾の位置はバッファリングしている書き込みによって変化する
+        self.flush_write_buffer()?;
+        self.inner_position = None;
+        self.counts.seeks += 1;
+        let end = self.run_inner(None, |inner| inner.seek(SeekFrom::End(0)))?;
+        self.inner_position = Some(end);
+        (end, offset)
+      }
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl<C: Cursor> Read for BufferedCursor<C> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.flush_write_buffer()?;
+
+    // 他のカーソルが書き換えた内容は読み込みバッファに反映されていない
+    if let Some((rewrites, seen)) = &mut self.rewrites {
+      let current = rewrites.load(Ordering::Acquire);
+      if current != *seen {
+        *seen = current;
+        self.read_buffer.clear();
+      }
+    }
+
+    // 読み込みバッファに含まれていない位置であれば下位のカーソルから読み込む
+    let end = self.read_start + self.read_buffer.len() as u64;
+    if self.position < self.read_start || self.position >= end {
+      if buf.len() >= self.read_capacity {
+        self.read_buffer.clear();
+        let length = self.run_inner(Some(self.position), |inner| inner.read(buf))?;
+        self.counts.bytes_read += length as u64;
+        self.position += length as u64;
+        self.inner_position = Some(self.position);
+        return Ok(length);
+      }
+      let mut buffer = std::mem::take(&mut self.read_buffer);
+      buffer.resize(self.read_capacity, 0u8);
+      let result = self.run_inner(Some(self.position), |inner| inner.read(&mut buffer));
+      self.read_buffer = buffer;
+      let length = match result {
+        Ok(length) => length,
+        Err(err) => {
+          self.read_buffer.clear();
+          return Err(err);
+        }
+      };
+      self.read_buffer.truncate(length);
+      self.counts.bytes_read += length as u64;
+      self.read_start = self.position;
+      self.inner_position = Some(self.position + length as u64);
+      if length == 0 {
+        return Ok(0);
+      }
+    }
+
+    let offset = (self.position - self.read_start) as usize;
+    let length = buf.len().min(self.read_buffer.len() - offset);
+    buf[..length].copy_from_slice(&self.read_buffer[offset..offset + length]);
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<C: Cursor> Write for BufferedCursor<C> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    // 書き込む範囲と重なる可能性のある読み込みバッファは破棄する
+    self.read_buffer.clear();
+
+    // 書き込みバッファと連続していない位置への書き込みであれば先にバッファを出力する
+    if !self.write_buffer.is_empty() && self.write_start + self.write_buffer.len() as u64 != self.position {
+      self.flush_write_buffer()?;
+    }
+    if self.write_buffer.len() + buf.len() > self.write_capacity {
+      self.flush_write_buffer()?;
+      if buf.len() >= self.write_capacity {
+        let length = self.run_inner(Some(self.position), |inner| inner.write(buf))?;
+        self.position += length as u64;
+        self.inner_position = Some(self.position);
+        return Ok(length);
+      }
+    }
+    if self.write_buffer.is_empty() {
+      self.write_start = self.position;
+    }
+    self.write_buffer.extend_from_slice(buf);
+    self.position += buf.len() as u64;
+    Ok(buf.len())
+  }
+
+  fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+    // バッファに収まらない場合は各バッファを連結せずに下位のカーソルの write_vectored() へ委譲する
+    let length = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+    if self.write_buffer.len() + length <= self.write_capacity {
+      for buf in bufs {
+        self.write_all(buf)?;
+      }
+      return Ok(length);
+    }
+    self.read_buffer.clear();
+    self.flush_write_buffer()?;
+    let length = self.run_inner(Some(self.position), |inner| inner.write_vectored(bufs))?;
+    self.position += length as u64;
+    self.inner_position = Some(self.position);
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.flush_write_buffer()?;
+    self.run_inner(None, |inner| inner.flush())
+  }
+}
+
+impl<C: Cursor> Drop for BufferedCursor<C> {
+  fn drop(&mut self) {
+    let _ = self.flush_write_buffer();
+  }
+}
@@ -0,0 +1,62 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use mt19937::MT19937;
+use rand::RngCore;
+
+use crate::test::random_payload;
+use crate::*;
+
+/// バッファ付きカーソルの読み書きとシークが、バッファリングしないカーソルと同じ結果となることを検証します。
+#[test]
+fn test_buffered_cursor() {
+  for (read_capacity, write_capacity) in [(0, 0), (1, 1), (7, 5), (64, 0), (0, 64), (4096, 4096)] {
+    let storage = MemStorage::new();
+    let mut cursor = BufferedCursor::new(storage.open(true).unwrap(), read_capacity, write_capacity);
+    let mut expected = io::Cursor::new(Vec::<u8>::new());
+    let mut rand = MT19937::new_with_slice_seed(&[read_capacity as u32, write_capacity as u32]);
+    for _ in 0..2000 {
+      let length = (rand.next_u32() % 32) as usize;
+      match rand.next_u32() % 4 {
+        0 => {
+          let bytes = random_payload(length, rand.next_u32() as Index);
+          cursor.write_all(&bytes).unwrap();
+          expected.write_all(&bytes).unwrap();
+        }
+        1 => {
+          let mut actual_bytes = vec![0u8; length];
+          let mut expected_bytes = vec![0u8; length];
+          let actual_length = read_fully(&mut cursor, &mut actual_bytes);
+          let expected_length = read_fully(&mut expected, &mut expected_bytes);
+          assert_eq!(expected_length, actual_length);
+          assert_eq!(expected_bytes, actual_bytes);
+        }
+        2 => {
+          let end = expected.seek(SeekFrom::End(0)).unwrap();
+          let position = rand.next_u32() as u64 % (end + 1);
+          assert_eq!(position, cursor.seek(SeekFrom::Start(position)).unwrap());
+          expected.seek(SeekFrom::Start(position)).unwrap();
+        }
+        _ => {
+          assert_eq!(expected.seek(SeekFrom::End(0)).unwrap(), cursor.seek(SeekFrom::End(0)).unwrap());
+        }
+      }
+      assert_eq!(expected.stream_position().unwrap(), cursor.stream_position().unwrap());
+    }
+    cursor.flush().unwrap();
+    let mut actual = Vec::<u8>::new();
+    storage.open(false).unwrap().read_to_end(&mut actual).unwrap();
+    assert_eq!(expected.into_inner(), actual);
+  }
+
+  fn read_fully(r: &mut dyn Read, buf: &mut [u8]) -> usize {
+    let mut length = 0;
+    while length < buf.len() {
+      match r.read(&mut buf[length..]).unwrap() {
+        0 => break,
+        len => length += len,
+      }
+    }
+    length
+  }
+}
@@ -0,0 +1,193 @@
+//! [`LMTHTOptions`] を段階的に設定して [`LMTHT`] をオープンするビルダーです。
+//!
+//! ```rust
+//! use lmtht::{LMTHT, MemStorage, SyncPolicy};
+//!
+//! let db = LMTHT::builder(MemStorage::new())
+//!   .inode_cache_size(4096)
+//!   .sync_policy(SyncPolicy::EveryNAppends(100))
+//!   .in_memory_position_index(true)
+//!   .open()
+//!   .unwrap();
+//! assert_eq!(1, db.append(b"hello").unwrap().i);
+//! ```
+//!
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::metrics::MetricsSink;
+use crate::retry::RetryPolicy;
+use crate::watermark::WatermarkStore;
+use crate::{DuplicatePolicy, DynStorage, Hash, Index, LMTHTOptions, Result, Storage, SyncPolicy, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// [`LMTHT::builder()`] で作成する、オプションを指定して LMTHT をオープンするためのビルダーです。指定しなかった
+/// オプションは [`LMTHTOptions::default()`] の値となります。
+pub struct LMTHTBuilder<S: Storage> {
+  storage: S,
+  options: LMTHTOptions,
+}
+
+impl<S: Storage> LMTHTBuilder<S> {
+  pub(crate) fn new(storage: S) -> LMTHTBuilder<S> {
+    LMTHTBuilder { storage, options: LMTHTOptions::default() }
+  }
+
+  /// すべてのオプションを `options` で置き換えます。
+  pub fn options(mut self, options: LMTHTOptions) -> Self {
+    self.options = options;
+    self
+  }
+
+  /// [`LMTHTOptions::inode_cache_size`] を指定します。
+  pub fn inode_cache_size(mut self, size: usize) -> Self {
+    self.options.inode_cache_size = size;
+    self
+  }
+
+  /// [`LMTHTOptions::position_cache_size`] を指定します。
+  pub fn position_cache_size(mut self, size: usize) -> Self {
+    self.options.position_cache_size = size;
+    self
+  }
+
+  /// [`LMTHTOptions::path_cache_size`] を指定します。
+  pub fn path_cache_size(mut self, size: usize) -> Self {
+    self.options.path_cache_size = size;
+    self
+  }
+
+  /// [`LMTHTOptions::position_index`] を指定します。
+  pub fn position_index(mut self, storage: Arc<dyn DynStorage + Send + Sync>) -> Self {
+    self.options.position_index = Some(storage);
+    self
+  }
+
+  /// [`LMTHTOptions::in_memory_position_index`] を指定します。
+  pub fn in_memory_position_index(mut self, enabled: bool) -> Self {
+    self.options.in_memory_position_index = enabled;
+    self
+  }
+
+  /// [`LMTHTOptions::query_pool_size`] を指定します。
+  pub fn query_pool_size(mut self, size: usize) -> Self {
+    self.options.query_pool_size = size;
+    self
+  }
+
+  /// [`LMTHTOptions::read_buffer_size`] を指定します。
+  pub fn read_buffer_size(mut self, size: usize) -> Self {
+    self.options.read_buffer_size = size;
+    self
+  }
+
+  /// [`LMTHTOptions::write_buffer_size`] を指定します。
+  pub fn write_buffer_size(mut self, size: usize) -> Self {
+    self.options.write_buffer_size = size;
+    self
+  }
+
+  /// [`LMTHTOptions::sync_policy`] を指定します。
+  pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+    self.options.sync_policy = policy;
+    self
+  }
+
+  /// [`LMTHTOptions::metrics`] を指定します。
+  pub fn metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+    self.options.metrics = Some(metrics);
+    self
+  }
+
+  /// [`LMTHTOptions::slow_operation_threshold`] を指定します。
+  pub fn slow_operation_threshold(mut self, threshold: Duration) -> Self {
+    self.options.slow_operation_threshold = Some(threshold);
+    self
+  }
+
+  /// [`LMTHTOptions::read_only`] を指定します。
+  pub fn read_only(mut self, read_only: bool) -> Self {
+    self.options.read_only = read_only;
+    self
+  }
+
+  /// [`LMTHTOptions::auto_refresh`] を指定します。
+  pub fn auto_refresh(mut self, auto_refresh: bool) -> Self {
+    self.options.auto_refresh = auto_refresh;
+    self
+  }
+
+  /// [`LMTHTOptions::trusted_root`] を指定します。
+  pub fn trusted_root(mut self, n: Index, hash: Hash) -> Self {
+    self.options.trusted_root = Some((n, hash));
+    self
+  }
+
+  /// [`LMTHTOptions::watermark`] を指定します。
+  pub fn watermark(mut self, watermark: Arc<dyn WatermarkStore>) -> Self {
+    self.options.watermark = Some(watermark);
+    self
+  }
+
+  /// [`LMTHTOptions::retry`] を指定します。
+  pub fn retry(mut self, retry: RetryPolicy) -> Self {
+    self.options.retry = Some(retry);
+    self
+  }
+
+  /// [`LMTHTOptions::archive_catalog`] を指定します。
+  pub fn archive_catalog(mut self, storage: Arc<dyn DynStorage + Send + Sync>) -> Self {
+    self.options.archive_catalog = Some(storage);
+    self
+  }
+
+  /// [`LMTHTOptions::timestamps`] を指定します。
+  pub fn timestamps(mut self, timestamps: bool) -> Self {
+    self.options.timestamps = timestamps;
+    self
+  }
+
+  /// [`LMTHTOptions::records`] を指定します。
+  pub fn records(mut self, records: bool) -> Self {
+    self.options.records = records;
+    self
+  }
+
+  /// [`LMTHTOptions::key_index`] を指定します。
+  pub fn key_index(mut self, storage: Arc<dyn DynStorage + Send + Sync>) -> Self {
+    self.options.key_index = Some(storage);
+    self
+  }
+
+  /// [`LMTHTOptions::hash_index`] を指定します。
+  pub fn hash_index(mut self, storage: Arc<dyn DynStorage + Send + Sync>) -> Self {
+    self.options.hash_index = Some(storage);
+    self
+  }
+
+  /// [`LMTHTOptions::bloom_filter`] と [`LMTHTOptions::bloom_filter_bits`] を指定します。
+  pub fn bloom_filter(mut self, storage: Arc<dyn DynStorage + Send + Sync>, bits: u64) -> Self {
+    self.options.bloom_filter = Some(storage);
+    self.options.bloom_filter_bits = bits;
+    self
+  }
+
+  /// [`LMTHTOptions::duplicates`] を指定します。
+  pub fn duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+    self.options.duplicates = duplicates;
+    self
+  }
+
+  /// [`LMTHTOptions::allow_truncate`] を指定します。
+  pub fn allow_truncate(mut self, allow_truncate: bool) -> Self {
+    self.options.allow_truncate = allow_truncate;
+    self
+  }
+
+  /// 指定されたオプションでストレージをオープンします。
+  pub fn open(self) -> Result<LMTHT<S>> {
+    LMTHT::with_options(self.storage, self.options)
+  }
+}
@@ -0,0 +1,28 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// ビルダーで指定したオプションが LMTHT に反映されることを検証します。
+#[test]
+fn test_builder() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::builder(MemStorage::with(buffer.clone()))
+    .inode_cache_size(16)
+    .position_cache_size(16)
+    .path_cache_size(16)
+    .in_memory_position_index(true)
+    .sync_policy(SyncPolicy::Manual)
+    .open()
+    .unwrap();
+  for i in 1..=10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  assert_eq!(Some(random_payload(PAYLOAD_SIZE, 5)), db.query().unwrap().get(5).unwrap());
+
+  // 読み込み専用でオープンしたビルダーは追加を拒否する
+  let reader = LMTHT::builder(MemStorage::with(buffer)).read_only(true).open().unwrap();
+  assert_eq!(db.root(), reader.root());
+  assert!(matches!(reader.append(&[0u8]), Err(Detail::ReadOnly)));
+}
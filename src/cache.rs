@@ -0,0 +1,230 @@
+//! 読み込んだ範囲をブロック単位でメモリ上に保持するストレージのラッパーです。
+//!
+//! [`CachedStorage`] は任意の [`Storage`] を包み、カーソルが読み込んだ範囲を固定長のブロックとして LRU キャッシュに
+//! 保存します。キャッシュはストレージから作成されたすべてのカーソルで共有されるため、あるクエリーが読み込んだ
+//! ブロックを別のクエリーが参照する場合は下位のストレージにアクセスしません。1 回の読み込みがネットワークの往復と
+//! なる HTTP やオブジェクトストレージのバックエンドの前段で使用することを想定しています。
+//!
+//! 書き込みと切り詰めは下位のストレージに直接行われ、影響を受けるブロックはキャッシュから破棄されます。末尾の
+//! ブロックのように長さがブロックサイズに満たないブロックは、以降の追記で内容が変わるためキャッシュしません。
+//!
+//! ```rust
+//! use lmtht::{LMTHT, MemStorage};
+//! use lmtht::cache::{CachedStorage, CachedStorageOptions};
+//! let db = LMTHT::new(CachedStorage::with_options(MemStorage::new(), CachedStorageOptions::default())).unwrap();
+//! ```
+//!
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::lru::Lru;
+use crate::{lock2io, Capabilities, Cursor, IoCounts, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// [`CachedStorageOptions::block_size`] のデフォルト値です。
+pub const DEFAULT_CACHE_BLOCK_SIZE: usize = 4 * 1024;
+
+/// [`CachedStorageOptions::capacity`] のデフォルト値です。
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// [`CachedStorage`] の動作を調整するためのオプションです。
+#[derive(Clone, Debug)]
+pub struct CachedStorageOptions {
+  /// キャッシュするブロックのバイトサイズです。下位のストレージからはこの単位で読み込みます。デフォルトは
+  /// [`DEFAULT_CACHE_BLOCK_SIZE`] です。
+  pub block_size: usize,
+  /// キャッシュに保持するブロックの最大数です。0 を指定した場合はキャッシュしません。デフォルトは
+  /// [`DEFAULT_CACHE_CAPACITY`] です。
+  pub capacity: usize,
+}
+
+impl Default for CachedStorageOptions {
+  fn default() -> Self {
+    CachedStorageOptions { block_size: DEFAULT_CACHE_BLOCK_SIZE, capacity: DEFAULT_CACHE_CAPACITY }
+  }
+}
+
+/// 読み込んだブロックをメモリ上にキャッシュするストレージのラッパーです。
+pub struct CachedStorage<S: Storage> {
+  inner: S,
+  shared: Arc<Shared>,
+}
+
+struct Shared {
+  block_size: usize,
+  blocks: Mutex<Lru<u64, Arc<[u8]>>>,
+  hits: AtomicU64,
+  misses: AtomicU64,
+}
+
+impl<S: Storage> CachedStorage<S> {
+  /// 指定されたストレージをデフォルトのオプションで包みます。
+  pub fn new(inner: S) -> CachedStorage<S> {
+    Self::with_options(inner, CachedStorageOptions::default())
+  }
+
+  /// 指定されたストレージを `options` に従ってキャッシュするように包みます。
+  pub fn with_options(inner: S, options: CachedStorageOptions) -> CachedStorage<S> {
+    assert!(options.block_size > 0);
+    let shared = Shared {
+      block_size: options.block_size,
+      blocks: Mutex::new(Lru::new(options.capacity)),
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+    };
+    CachedStorage { inner, shared: Arc::new(shared) }
+  }
+
+  /// 包んでいるストレージを参照します。
+  pub fn get_ref(&self) -> &S {
+    &self.inner
+  }
+
+  /// これまでにキャッシュから読み込んだ回数を参照します。
+  pub fn hits(&self) -> u64 {
+    self.shared.hits.load(Ordering::Relaxed)
+  }
+
+  /// これまでに下位のストレージからブロックを読み込んだ回数を参照します。
+  pub fn misses(&self) -> u64 {
+    self.shared.misses.load(Ordering::Relaxed)
+  }
+
+  /// キャッシュしているすべてのブロックを破棄します。下位のストレージが外部で変更された場合に使用します。
+  pub fn invalidate(&self) {
+    if let Ok(mut blocks) = self.shared.blocks.lock() {
+      blocks.retain(|_, _| false);
+    }
+  }
+}
+
+impl<S: Storage> Storage for CachedStorage<S> {
+  type Cursor = CachedCursor<S::Cursor>;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    Ok(CachedCursor { inner: self.inner.open(writable)?, shared: self.shared.clone(), position: 0 })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.inner.capabilities()
+  }
+}
+
+impl Shared {
+  /// 位置 `from` 以降を含むブロックをキャッシュから破棄します。`to` が指定された場合はその位置より前のブロックのみ
+  /// を破棄します。
+  fn invalidate(&self, from: u64, to: Option<u64>) -> io::Result<()> {
+    let size = self.block_size as u64;
+    let (first, last) = (from / size, to.map(|to| to.div_ceil(size)));
+    lock2io(self.blocks.lock())?.retain(|k, _| *k < first || last.map(|last| *k >= last).unwrap_or(false));
+    Ok(())
+  }
+}
+
+/// [`CachedStorage`] が使用するカーソルです。
+pub struct CachedCursor<C: Cursor> {
+  inner: C,
+  shared: Arc<Shared>,
+  position: u64,
+}
+
+impl<C: Cursor> CachedCursor<C> {
+  /// 番号 `k` のブロックを参照します。キャッシュに存在しない場合は下位のストレージから読み込みます。
+  fn block(&mut self, k: u64) -> io::Result<Arc<[u8]>> {
+    if let Some(block) = lock2io(self.shared.blocks.lock())?.get(&k) {
+      self.shared.hits.fetch_add(1, Ordering::Relaxed);
+      return Ok(block.clone());
+    }
+    self.shared.misses.fetch_add(1, Ordering::Relaxed);
+    let size = self.shared.block_size;
+    let mut block = Vec::with_capacity(size);
+    self.inner.seek(SeekFrom::Start(k * size as u64))?;
+    (&mut self.inner).take(size as u64).read_to_end(&mut block)?;
+    let block: Arc<[u8]> = Arc::from(block);
+    if block.len() == size {
+      lock2io(self.shared.blocks.lock())?.put(k, block.clone());
+    }
+    Ok(block)
+  }
+}
+
+impl<C: Cursor> Cursor for CachedCursor<C> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.inner.sync_data()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.inner.set_len(length)?;
+    self.shared.invalidate(length, None)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.inner.len()
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.inner.discard(position, length)?;
+    self.position = position + length;
+    self.shared.invalidate(position, Some(position + length))
+  }
+
+  fn io_counts(&self) -> IoCounts {
+    self.inner.io_counts()
+  }
+
+  fn duplicate(&self) -> io::Result<Option<Self>> {
+    let inner = self.inner.duplicate()?;
+    Ok(inner.map(|inner| CachedCursor { inner, shared: self.shared.clone(), position: 0 }))
+  }
+}
+
+impl<C: Cursor> Seek for CachedCursor<C> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => (position, 0),
+      SeekFrom::End(offset) => (self.inner.seek(SeekFrom::End(0))?, offset),
+      SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl<C: Cursor> Read for CachedCursor<C> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let size = self.shared.block_size as u64;
+    let (k, offset) = (self.position / size, (self.position % size) as usize);
+    let block = self.block(k)?;
+    if offset >= block.len() {
+      return Ok(0);
+    }
+    // 1 回の読み込みはブロックの境界を越えない
+    let length = buf.len().min(block.len() - offset);
+    buf[..length].copy_from_slice(&block[offset..offset + length]);
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<C: Cursor> Write for CachedCursor<C> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.inner.seek(SeekFrom::Start(self.position))?;
+    let length = self.inner.write(buf)?;
+    self.shared.invalidate(self.position, Some(self.position + length as u64))?;
+    self.position += length as u64;
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
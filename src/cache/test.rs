@@ -0,0 +1,44 @@
+use crate::*;
+
+/// `CachedStorage` が読み込んだブロックをカーソル間で共有し、書き込みによって破棄されることを検証します。
+#[test]
+fn test_cached_storage() {
+  use crate::cache::{CachedStorage, CachedStorageOptions};
+
+  let options = CachedStorageOptions { block_size: 64, capacity: 1024 };
+  let db = LMTHT::new(CachedStorage::with_options(MemStorage::new(), options)).unwrap();
+  for i in 1..50u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+
+  // 末尾の不完全なブロックはキャッシュされないため、ハッシュ値のサイズによらずストレージの長さがブロック境界と
+  // 一致するように 50 番目の値の長さを調整する
+  let length = db.storage().get_ref().buffer.read().unwrap().len() as u64 + db.estimate_append_size(0);
+  let padding = vec![0u8; ((64 - length % 64) % 64) as usize];
+  db.append(&padding).unwrap();
+  assert_eq!(0, db.storage().get_ref().buffer.read().unwrap().len() % 64);
+  let expected = |i: u8| if i == 50 { padding.clone() } else { vec![i; 16] };
+
+  let mut query = db.query().unwrap();
+  for i in 1..=50u8 {
+    assert_eq!(Some(expected(i)), query.get(i as Index).unwrap());
+  }
+
+  // 2 つ目のクエリーは下位のストレージから読み込まない
+  let misses = db.storage().misses();
+  let mut query = db.query().unwrap();
+  for i in 1..=50u8 {
+    assert_eq!(Some(expected(i)), query.get(i as Index).unwrap());
+  }
+  assert_eq!(misses, db.storage().misses());
+  assert!(db.storage().hits() > 0);
+
+  // 書き込んだ範囲のブロックは破棄され、追加した値を参照できる
+  for i in 51..=60u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  let mut query = db.query().unwrap();
+  for i in 1..=60u8 {
+    assert_eq!(Some(expected(i)), query.get(i as Index).unwrap());
+  }
+}
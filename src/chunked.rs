@@ -0,0 +1,253 @@
+//! 直列化されたバイト列を固定長のチャンクに分割し、キーと値の組として保存するストレージです。
+//!
+//! 組み込みのキーバリューストアやブラウザの IndexedDB のようにファイルのシークと追記を持たない保存先は、
+//! [`ChunkStore`] を実装することで [`ChunkedStorage`] を通じて LMTHT のストレージとして使用することができます。
+//! チャンクの分割と再構成、カーソルの位置管理はこのモジュールが行うため、保存先はチャンク番号をキーとした読み書きと
+//! バイト列全体の長さの記録のみを実装します。
+//!
+//! ```rust
+//! use lmtht::chunked::{ChunkedStorage, MemChunkStore};
+//! use lmtht::LMTHT;
+//!
+//! let db = LMTHT::new(ChunkedStorage::new(MemChunkStore::new(256))).unwrap();
+//! db.append(b"hello").unwrap();
+//! assert_eq!(b"hello".to_vec(), db.query().unwrap().get(1).unwrap().unwrap());
+//! ```
+//!
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use crate::{lock2io, Capabilities, Cursor, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// 固定長のチャンクを保存する保存先です。チャンク番号 `k` のチャンクはバイト列の `k * chunk_size()` から
+/// `chunk_size()` バイトを保持します。
+///
+/// 保存先は書き込みを行うカーソルと読み込みを行う複数のカーソルから同時に参照されます。チャンクの書き込みと長さの
+/// 更新は [`ChunkStore::put()`] の 1 回の呼び出しで行われるため、保存先のトランザクションやバッチ書き込みを使用して
+/// 同時に反映することで、中断した書き込みが長さの範囲に不完全なチャンクを残すことを防ぐことができます。
+pub trait ChunkStore: Send + Sync {
+  /// チャンクのバイトサイズです。同じ保存先に対しては常に同じ値を返す必要があります。
+  fn chunk_size(&self) -> usize;
+
+  /// 保存されているバイト列の長さを参照します。何も保存されていない場合は 0 を返します。
+  fn length(&self) -> io::Result<u64>;
+
+  /// チャンク番号 `k` のチャンクを参照します。存在しない場合は `None` を返します。返されるチャンクは
+  /// [`ChunkStore::chunk_size()`] より短くても構いません。
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>>;
+
+  /// チャンク番号 `k` のチャンクを `chunk` で置き換え、バイト列の長さを `length` に更新します。
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()>;
+
+  /// チャンク番号 `k` 以降のチャンクを削除し、バイト列の長さを `length` に更新します。
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()>;
+
+  /// これまでの書き込みを保存先のデバイスに同期します。デフォルトの実装は何も行いません。
+  fn sync(&self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl<B: ChunkStore + ?Sized> ChunkStore for Arc<B> {
+  fn chunk_size(&self) -> usize {
+    self.as_ref().chunk_size()
+  }
+  fn length(&self) -> io::Result<u64> {
+    self.as_ref().length()
+  }
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>> {
+    self.as_ref().get(k)
+  }
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    self.as_ref().put(k, chunk, length)
+  }
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()> {
+    self.as_ref().truncate(k, length)
+  }
+  fn sync(&self) -> io::Result<()> {
+    self.as_ref().sync()
+  }
+}
+
+/// [`ChunkStore`] を LMTHT のストレージとして使用するアダプタです。
+pub struct ChunkedStorage<B: ChunkStore> {
+  store: Arc<B>,
+}
+
+impl<B: ChunkStore> ChunkedStorage<B> {
+  /// 指定された保存先を使用するストレージを構築します。
+  pub fn new(store: B) -> ChunkedStorage<B> {
+    ChunkedStorage { store: Arc::new(store) }
+  }
+
+  /// このストレージが使用する保存先を参照します。
+  pub fn store(&self) -> &B {
+    &self.store
+  }
+}
+
+impl<B: ChunkStore + 'static> Storage for ChunkedStorage<B> {
+  type Cursor = ChunkedCursor<B>;
+  fn open(&self, writable: bool) -> Result<ChunkedCursor<B>> {
+    Ok(ChunkedCursor { store: self.store.clone(), writable, position: 0, cached: None })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    // 同期によって永続化されるかは保存先に依存する
+    Capabilities { writable: true, truncatable: true, durable: false }
+  }
+}
+
+/// [`ChunkedStorage`] が使用するカーソルです。
+pub struct ChunkedCursor<B: ChunkStore> {
+  store: Arc<B>,
+  writable: bool,
+  position: u64,
+  /// 直前に読み込んだチャンク番号とその内容。追記によって既存のバイトが変更されることはないため、読み込んだ範囲は
+  /// 他のカーソルが追記した後も有効である。
+  cached: Option<(u64, Vec<u8>)>,
+}
+
+impl<B: ChunkStore> ChunkedCursor<B> {
+  /// チャンク番号 `k` のチャンクのうちバイト列の長さ `length` の範囲に含まれる部分を読み込みます。
+  fn load(&self, k: u64, length: u64) -> io::Result<Vec<u8>> {
+    let size = self.store.chunk_size() as u64;
+    let valid = min(length.saturating_sub(k * size), size) as usize;
+    let mut chunk = self.store.get(k)?.unwrap_or_default();
+    chunk.truncate(valid);
+    Ok(chunk)
+  }
+}
+
+impl<B: ChunkStore> Cursor for ChunkedCursor<B> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.store.sync()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let size = self.store.chunk_size() as u64;
+    self.cached = None;
+    self.store.truncate(length.div_ceil(size), length)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.store.length()
+  }
+}
+
+impl<B: ChunkStore> io::Seek for ChunkedCursor<B> {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      io::SeekFrom::Start(position) => (position, 0),
+      io::SeekFrom::End(offset) => (self.store.length()?, offset),
+      io::SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl<B: ChunkStore> io::Read for ChunkedCursor<B> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let size = self.store.chunk_size() as u64;
+    let (k, offset) = (self.position / size, (self.position % size) as usize);
+    let hit = matches!(&self.cached, Some((cached, chunk)) if *cached == k && offset < chunk.len());
+    if !hit {
+      let chunk = self.load(k, self.store.length()?)?;
+      self.cached = Some((k, chunk));
+    }
+    let chunk = &self.cached.as_ref().unwrap().1;
+    let length = min(buf.len(), chunk.len().saturating_sub(offset));
+    buf[..length].copy_from_slice(&chunk[offset..offset + length]);
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<B: ChunkStore> io::Write for ChunkedCursor<B> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    // 現在の位置を含むチャンクに書き込める範囲のみを書き込む
+    let size = self.store.chunk_size() as u64;
+    let (k, offset) = (self.position / size, (self.position % size) as usize);
+    let length = min(buf.len(), size as usize - offset);
+    let current = self.store.length()?;
+    let mut chunk = self.load(k, current)?;
+    if chunk.len() < offset + length {
+      chunk.resize(offset + length, 0u8);
+    }
+    chunk[offset..offset + length].copy_from_slice(&buf[..length]);
+    self.position += length as u64;
+    self.store.put(k, &chunk, current.max(self.position))?;
+    self.cached = None;
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// メモリ上にチャンクを保持する [`ChunkStore`] です。テストや、独自の保存先を実装する際の参考としての使用を想定して
+/// います。
+pub struct MemChunkStore {
+  chunk_size: usize,
+  state: RwLock<(BTreeMap<u64, Vec<u8>>, u64)>,
+}
+
+impl MemChunkStore {
+  /// 指定されたチャンクサイズでメモリ上の保存先を構築します。
+  pub fn new(chunk_size: usize) -> MemChunkStore {
+    assert!(chunk_size > 0);
+    MemChunkStore { chunk_size, state: RwLock::new((BTreeMap::new(), 0)) }
+  }
+
+  /// 保存されているチャンクの数を参照します。
+  pub fn chunks(&self) -> usize {
+    self.state.read().map(|state| state.0.len()).unwrap_or(0)
+  }
+}
+
+impl ChunkStore for MemChunkStore {
+  fn chunk_size(&self) -> usize {
+    self.chunk_size
+  }
+
+  fn length(&self) -> io::Result<u64> {
+    Ok(lock2io(self.state.read())?.1)
+  }
+
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>> {
+    Ok(lock2io(self.state.read())?.0.get(&k).cloned())
+  }
+
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    let mut state = lock2io(self.state.write())?;
+    state.0.insert(k, chunk.to_vec());
+    state.1 = length;
+    Ok(())
+  }
+
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()> {
+    let mut state = lock2io(self.state.write())?;
+    state.0.retain(|key, _| *key < k);
+    state.1 = length;
+    Ok(())
+  }
+}
@@ -0,0 +1,39 @@
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::*;
+
+/// チャンクの境界をまたぐエントリを含む `ChunkedStorage` の内容が再オープン後も同じ値とルートハッシュを返すことを
+/// 検証します。
+#[test]
+fn test_chunked_storage() {
+  use chunked::{ChunkStore, ChunkedStorage, MemChunkStore};
+  let store = Arc::new(MemChunkStore::new(7));
+  let db = LMTHT::new(ChunkedStorage::new(store.clone())).unwrap();
+  for i in 1..=100u8 {
+    db.append(&vec![i; i as usize % 17]).unwrap();
+  }
+  let root = db.root().unwrap();
+  drop(db);
+  assert_eq!((store.length().unwrap() as usize).div_ceil(7), store.chunks());
+
+  let db = LMTHT::new(ChunkedStorage::new(store.clone())).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 1..=100u8 {
+    assert_eq!(Some(vec![i; i as usize % 17]), query.get(i as Index).unwrap());
+    assert_eq!(root.hash, query.get_values_with_hashes(i as Index, 0).unwrap().unwrap().root().hash);
+  }
+
+  // 切り詰めた後の書き込みは以前のチャンクの内容を残さない
+  let mut cursor = ChunkedStorage::new(store.clone()).open(true).unwrap();
+  cursor.set_len(10).unwrap();
+  assert_eq!(2, store.chunks());
+  cursor.seek(SeekFrom::Start(12)).unwrap();
+  cursor.write_all(&[0xFF]).unwrap();
+  let mut buf = vec![0u8; 13];
+  cursor.seek(SeekFrom::Start(0)).unwrap();
+  io::Read::read_exact(&mut cursor, &mut buf).unwrap();
+  assert_eq!([0u8, 0, 0xFF], buf[10..]);
+}
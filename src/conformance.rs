@@ -0,0 +1,175 @@
+//! 他の言語で実装された LMTHT との互換性を検証するためのテストベクターを生成します。
+//!
+//! テストベクターは決定論的に生成した値を 1 から順に追加したときの、各世代のルートノード、ストレージに直列化された
+//! エントリのバイト列、およびすべての葉ノードに対するハッシュ付きの値 (証明) を JSON 形式で表したものです。
+//! 他の実装はこれらをバイト単位で比較することで直列化形式やハッシュ値の算出方法が一致していることを確認できます。
+//!
+//! また同じ値を使用した既知解テスト [`self_test()`] によって、このクレート自体のビルドが正しいかを確認できます。
+//!
+use std::hash::Hasher;
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+
+use highway::{HighwayBuilder, Key};
+
+use crate::error::Detail::SelfTestFailed;
+use crate::{
+  hex, inconsistency, lock2io, Index, MemStorage, Node, Result, CHECKSUM_HW64_KEY, HASH_ALGORITHM, HASH_SIZE,
+  INDEX_SIZE, INDEX_WIDTH, LMTHT, STORAGE_IDENTIFIER, STORAGE_VERSION,
+};
+
+#[cfg(test)]
+mod test;
+
+/// 既知解テストで追加する値の個数です。
+const SELF_TEST_SIZE: Index = 16;
+
+/// 既知解テストの期待値です。[`payload()`] で生成した値を [`SELF_TEST_SIZE`] 個追加したときのルートハッシュと、
+/// ストレージの長さおよびストレージ全体のチェックサムを有効なハッシュアルゴリズムごとに定義しています。ストレージの
+/// 長さとチェックサムはインデックスのビット幅によって異なるため 32、64、128 ビットの順に定義しています。
+#[cfg(feature = "highwayhash64")]
+const SELF_TEST_EXPECTED: (&str, [(u64, u64); 3]) =
+  ("C57FAD45D28FA7CF", [(1346, 0x44BCB1155087EA85), (1538, 0x38AEA5108BD3EB7A), (1922, 0xB6702091A9FBD63A)]);
+#[cfg(feature = "sha224")]
+const SELF_TEST_EXPECTED: (&str, [(u64, u64); 3]) = (
+  "BC359C0C447A90589A0F794714C04EAE269E7BDEC8A39B283C6C2E21",
+  [(2306, 0x92354DFD741369BC), (2498, 0x6C3ED11089657FB9), (2882, 0x351311E2E88C08B4)],
+);
+#[cfg(feature = "sha256")]
+const SELF_TEST_EXPECTED: (&str, [(u64, u64); 3]) = (
+  "DA9B7D88F88C5A1DA8B91ACB93FCE155F1CFD3658C121E095510FC5730F4FE8F",
+  [(2498, 0xA8E54CF3C28A4D4B), (2690, 0x34E530C1C868ED3B), (3074, 0x5420D6DAA0F00444)],
+);
+#[cfg(feature = "sha512")]
+const SELF_TEST_EXPECTED: (&str, [(u64, u64); 3]) = (
+  "D72E57C251E1F27624DFC8FDE93E3A598F87ABE4E9E91BF307855B80B08D40FF294E567CB875256848DACA87B4339B8B32E8641ECAE6849C76CCDB7ABAB5859E",
+  [(4034, 0x1AC7536F25162A68), (4226, 0x612B9A700824CB85), (4610, 0x9FAD4A2A09840349)],
+);
+#[cfg(feature = "sha512_224")]
+const SELF_TEST_EXPECTED: (&str, [(u64, u64); 3]) = (
+  "237DE56A948CB346D8BED13D582088A13C2E26750B541C1935F9B41A",
+  [(2306, 0x0EBBFD2116CF9999), (2498, 0xB5A3DCD937BC9DEC), (2882, 0x5394EEF43FEDA4FB)],
+);
+#[cfg(feature = "sha512_256")]
+const SELF_TEST_EXPECTED: (&str, [(u64, u64); 3]) = (
+  "11EABCA4E81E55E562CED09B1621ACB38B2C7F40520616BAC8251D93EC4752DF",
+  [(2498, 0x2ECC3038A2CD0B92), (2690, 0x85AF475F8C0E4E9D), (3074, 0x4B84F1F7FF7E1042)],
+);
+
+/// 固定の値を [`MemStorage`] に追加し、得られたルートハッシュと直列化されたバイト列を有効な feature に対する既知の
+/// 値と比較します。誤ったコンパイルや feature の指定によってハッシュ値や直列化形式が想定と異なるビルドを、実際の
+/// データを破損させる前に検出することを目的としています。
+///
+/// 結果が期待値と異なる場合は [`SelfTestFailed`](crate::error::Detail::SelfTestFailed) を返します。
+pub fn self_test() -> Result<()> {
+  let buffer = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
+  let db = LMTHT::new(MemStorage::with(buffer.clone()))?;
+  for i in 1..=SELF_TEST_SIZE {
+    db.append(&payload(i))?;
+  }
+  let root = db.root().unwrap();
+
+  // ルートハッシュと直列化されたバイト列の検証
+  let (expected_root, storages) = SELF_TEST_EXPECTED;
+  let (expected_length, expected_checksum) = storages[match INDEX_SIZE {
+    32 => 0,
+    64 => 1,
+    _ => 2,
+  }];
+  if root.hash.to_str() != expected_root {
+    let message = format!("root hash {} is not the known answer {}", root.hash.to_str(), expected_root);
+    return Err(SelfTestFailed { message });
+  }
+  let bytes = lock2io(buffer.read())?;
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  hasher.write_all(&bytes)?;
+  let checksum = hasher.finish();
+  if bytes.len() as u64 != expected_length || checksum != expected_checksum {
+    let message = format!(
+      "serialized storage ({} bytes, checksum {:016X}) is not the known answer ({} bytes, checksum {:016X})",
+      bytes.len(),
+      checksum,
+      expected_length,
+      expected_checksum
+    );
+    return Err(SelfTestFailed { message });
+  }
+
+  // 読み出した値がルートハッシュで検証できることを確認
+  let mut query = db.query()?;
+  for i in 1..=SELF_TEST_SIZE {
+    match query.get_with_hashes(i)? {
+      Some(values) if values.root() == root && values.values[0].value == payload(i) => (),
+      _ => {
+        let message = format!("the value b_{} cannot be verified with the root {}", i, root);
+        return Err(SelfTestFailed { message });
+      }
+    }
+  }
+  Ok(())
+}
+
+/// テストベクターで i 番目に追加する値を生成します。値の長さは 0 バイトを含めて i ごとに変化します。
+pub fn payload(i: Index) -> Vec<u8> {
+  let length = (i * 5 % 23) as usize;
+  (0..length).map(|k| (i as usize + k) as u8).collect()
+}
+
+/// [`payload()`] で生成した値を 1 から `n` 番目まで順に追加したときのテストベクターを JSON 形式で出力します。
+///
+/// 各世代には追加した値、その時点のルートノード、追加によってストレージに書き込まれたエントリの位置とバイト列、
+/// およびその世代に含まれるすべての葉ノードに対するハッシュ付きの値が含まれます。
+pub fn write_test_vectors(n: Index, w: &mut dyn Write) -> Result<()> {
+  let buffer = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
+  let db = LMTHT::new(MemStorage::with(buffer.clone()))?;
+
+  writeln!(w, "{{")?;
+  writeln!(w, "  \"hash_algorithm\": \"{}\",", HASH_ALGORITHM)?;
+  writeln!(w, "  \"hash_size\": {},", HASH_SIZE)?;
+  writeln!(w, "  \"header\": \"{}{}\",", hex(&STORAGE_IDENTIFIER), hex(&[STORAGE_VERSION | INDEX_WIDTH]))?;
+  writeln!(w, "  \"generations\": [")?;
+  for i in 1..=n {
+    let position = lock2io(buffer.read())?.len();
+    let value = payload(i);
+    let root = db.append(&value)?;
+    let entry = lock2io(buffer.read())?[position..].to_vec();
+
+    writeln!(w, "    {{")?;
+    writeln!(w, "      \"n\": {},", i)?;
+    writeln!(w, "      \"payload\": \"{}\",", hex(&value))?;
+    writeln!(w, "      \"root\": {},", node_to_json(&root))?;
+    writeln!(w, "      \"entry\": {{ \"position\": {}, \"bytes\": \"{}\" }},", position, hex(&entry))?;
+    writeln!(w, "      \"proofs\": [")?;
+    let mut query = db.query()?;
+    for k in 1..=i {
+      let proof = match query.get_with_hashes(k)? {
+        Some(proof) if proof.root() == root => proof,
+        _ => return inconsistency(format!("the value b_{} cannot be verified with the root {} of T_{}", k, root, i)),
+      };
+      let values = proof
+        .values
+        .iter()
+        .map(|value| format!("{{ \"i\": {}, \"value\": \"{}\" }}", value.i, hex(&value.value)))
+        .collect::<Vec<String>>();
+      let branches = proof.branches.iter().map(node_to_json).collect::<Vec<String>>();
+      let separator = if k < i { "," } else { "" };
+      writeln!(
+        w,
+        "        {{ \"i\": {}, \"values\": [{}], \"branches\": [{}] }}{}",
+        k,
+        values.join(", "),
+        branches.join(", "),
+        separator
+      )?;
+    }
+    writeln!(w, "      ]")?;
+    writeln!(w, "    }}{}", if i < n { "," } else { "" })?;
+  }
+  writeln!(w, "  ]")?;
+  writeln!(w, "}}")?;
+  Ok(())
+}
+
+fn node_to_json(node: &Node) -> String {
+  format!("{{ \"i\": {}, \"j\": {}, \"hash\": \"{}\" }}", node.i, node.j, hex(&node.hash.value))
+}
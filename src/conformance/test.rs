@@ -0,0 +1,34 @@
+use std::sync::{Arc, RwLock};
+
+use crate::*;
+
+/// テストベクターに各世代のルートノードとストレージに直列化されたエントリが含まれていることを検証します。
+#[test]
+fn test_conformance_vectors() {
+  const N: Index = 20;
+  let mut output = Vec::<u8>::new();
+  conformance::write_test_vectors(N, &mut output).unwrap();
+  let json = String::from_utf8(output).unwrap();
+
+  let buffer = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=N {
+    let root = db.append(&conformance::payload(i)).unwrap();
+    let expected =
+      format!("\"root\": {{ \"i\": {}, \"j\": {}, \"hash\": \"{}\" }}", root.i, root.j, root.hash.to_str());
+    assert!(json.contains(&expected), "{}", expected);
+  }
+
+  // すべてのエントリを連結するとストレージの内容と一致する
+  let entries = json
+    .lines()
+    .filter_map(|line| line.split("\"bytes\": \"").nth(1))
+    .map(|bytes| bytes.split('"').next().unwrap())
+    .collect::<String>();
+  assert_eq!(hex(&buffer.read().unwrap()[4..]), entries);
+}
+
+#[test]
+fn known_answer_self_test() {
+  self_test().unwrap();
+}
@@ -0,0 +1,290 @@
+//! `std` を使用できない `no_std + alloc` 環境のための最小限の入出力の抽象化です。
+//!
+//! `std` feature を無効にしたビルドで利用できるのは [`model`](crate::model) と [`Hash`](crate::Hash)、
+//! [`Node`](crate::Node)、[`ValuesWithBranches`](crate::ValuesWithBranches) による値の検証のみです。ハッシュ木を
+//! ストレージに保存する `LMTHT` 本体は `std::io` やスレッドを前提としているため `std` feature を必要とします。この
+//! モジュールはフラッシュメモリなどのデバイスを扱うための [`Read`]、[`Write`]、[`Seek`] と、その上に定義された
+//! [`Storage`] と [`Cursor`]、証明の復元で発生するエラーを提供します。
+//!
+//! `std` feature が有効な場合、`std::io` の各トレイトを実装するすべての型はこのモジュールの対応するトレイトも実装
+//! します。このためこれらのトレイトに対して記述した処理は std 環境のファイルやバッファに対してもそのまま使用する
+//! ことができます。
+//!
+//! ```rust
+//! use lmtht::core_io::{Read, Seek, SeekFrom, VecCursor, Write};
+//!
+//! let mut cursor = VecCursor::new();
+//! cursor.write_all(b"hello, world").unwrap();
+//! cursor.seek(SeekFrom::Start(7)).unwrap();
+//! let mut buf = [0u8; 5];
+//! cursor.read_exact(&mut buf).unwrap();
+//! assert_eq!(b"world", &buf);
+//! ```
+//!
+use core::fmt::{Display, Formatter};
+
+use alloc::vec::Vec;
+
+#[cfg(all(test, feature = "std"))]
+mod test;
+
+/// このモジュールの入出力で発生したエラーの種類です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+  /// 要求したバイト数を読み込む前にストレージの終端に達した。
+  UnexpectedEof,
+  /// 書き込みが 1 バイトも受け付けられなかった。
+  WriteZero,
+  /// 不正な引数が指定された。
+  InvalidInput,
+  /// 読み込んだデータの形式が不正である。
+  InvalidData,
+  /// ストレージが要求された操作をサポートしていない。
+  Unsupported,
+  /// その他のエラー。
+  Other,
+}
+
+/// このモジュールの入出力で発生したエラーです。`alloc` のみの環境でも使用できるようにメッセージは静的な文字列で
+/// 表します。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Error {
+  /// エラーの種類。
+  pub kind: ErrorKind,
+  /// エラーの内容を表すメッセージ。
+  pub message: &'static str,
+}
+
+impl Error {
+  pub const fn new(kind: ErrorKind, message: &'static str) -> Error {
+    Error { kind, message }
+  }
+}
+
+impl Display for Error {
+  fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{:?}: {}", self.kind, self.message)
+  }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+  fn from(err: std::io::Error) -> Self {
+    let kind = match err.kind() {
+      std::io::ErrorKind::UnexpectedEof => ErrorKind::UnexpectedEof,
+      std::io::ErrorKind::WriteZero => ErrorKind::WriteZero,
+      std::io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+      std::io::ErrorKind::InvalidData => ErrorKind::InvalidData,
+      std::io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+      _ => ErrorKind::Other,
+    };
+    Error::new(kind, "I/O error")
+  }
+}
+
+/// このモジュールの入出力で使用する Result です。
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// [`Seek::seek()`] で移動する位置の基準です。`std::io::SeekFrom` と同じ意味を持ちます。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekFrom {
+  /// 先頭からのバイト位置。
+  Start(u64),
+  /// 終端からの相対位置。
+  End(i64),
+  /// 現在の位置からの相対位置。
+  Current(i64),
+}
+
+/// バイト列を読み込むためのトレイトです。
+pub trait Read {
+  /// `buf` に読み込み、読み込んだバイト数を返します。終端に達している場合は 0 を返します。
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+  /// `buf` を満たすまで読み込みます。途中で終端に達した場合は [`ErrorKind::UnexpectedEof`] を返します。
+  fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+    while !buf.is_empty() {
+      match self.read(buf)? {
+        0 => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+        n => buf = &mut buf[n..],
+      }
+    }
+    Ok(())
+  }
+}
+
+/// バイト列を書き込むためのトレイトです。
+pub trait Write {
+  /// `buf` を書き込み、書き込んだバイト数を返します。
+  fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+  /// バッファリングされている内容を出力します。
+  fn flush(&mut self) -> Result<()>;
+
+  /// `buf` のすべてを書き込みます。書き込みが受け付けられなかった場合は [`ErrorKind::WriteZero`] を返します。
+  fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+    while !buf.is_empty() {
+      match self.write(buf)? {
+        0 => return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+        n => buf = &buf[n..],
+      }
+    }
+    Ok(())
+  }
+}
+
+/// 読み書きの位置を移動するためのトレイトです。
+pub trait Seek {
+  /// 位置を `pos` に移動し、先頭からの新しいバイト位置を返します。
+  fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+}
+
+/// `no_std` 環境のストレージからデータの入出力を行うためのカーソルです。[`crate::Cursor`] と同様に永続化と
+/// 切り詰めのための操作を持ちます。
+pub trait Cursor: Read + Write + Seek {
+  /// これまでに書き込んだ内容をストレージのデバイスに同期します。デフォルトの実装は `flush()` のみを行います。
+  fn sync_data(&mut self) -> Result<()> {
+    self.flush()
+  }
+
+  /// ストレージの長さを `length` バイトに切り詰めます。デフォルトの実装は [`ErrorKind::Unsupported`] を返します。
+  fn set_len(&mut self, length: u64) -> Result<()> {
+    let _ = length;
+    Err(Error::new(ErrorKind::Unsupported, "this cursor cannot truncate the storage"))
+  }
+}
+
+/// `no_std` 環境でハッシュ木を保存する抽象化されたストレージです。[`crate::Storage`] と同様に read 用または
+/// read + write 用のカーソルを作成します。
+pub trait Storage {
+  /// このストレージが使用するカーソルの型です。
+  type Cursor: Cursor;
+
+  /// このストレージに対する read または read + write 用のカーソルを作成します。
+  fn open(&self, writable: bool) -> Result<Self::Cursor>;
+}
+
+/// メモリ上のバイト列を読み書きするカーソルです。書き込みによって必要に応じて拡張されます。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VecCursor {
+  buffer: Vec<u8>,
+  position: u64,
+}
+
+impl VecCursor {
+  /// 空のバイト列に対するカーソルを構築します。
+  pub fn new() -> VecCursor {
+    Self::with(Vec::new())
+  }
+
+  /// 指定されたバイト列の先頭に位置するカーソルを構築します。
+  pub fn with(buffer: Vec<u8>) -> VecCursor {
+    VecCursor { buffer, position: 0 }
+  }
+
+  /// このカーソルのバイト列を参照します。
+  pub fn get_ref(&self) -> &[u8] {
+    &self.buffer
+  }
+
+  /// このカーソルを破棄してバイト列を返します。
+  pub fn into_inner(self) -> Vec<u8> {
+    self.buffer
+  }
+
+  fn start(&self) -> usize {
+    core::cmp::min(self.position, self.buffer.len() as u64) as usize
+  }
+}
+
+impl Read for VecCursor {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+    let start = self.start();
+    let length = core::cmp::min(buf.len(), self.buffer.len() - start);
+    buf[..length].copy_from_slice(&self.buffer[start..start + length]);
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl Write for VecCursor {
+  fn write(&mut self, buf: &[u8]) -> Result<usize> {
+    let start = self.position as usize;
+    if self.buffer.len() < start + buf.len() {
+      self.buffer.resize(start + buf.len(), 0);
+    }
+    self.buffer[start..start + buf.len()].copy_from_slice(buf);
+    self.position += buf.len() as u64;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    Ok(())
+  }
+}
+
+impl Seek for VecCursor {
+  fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => (position, 0),
+      SeekFrom::End(offset) => (self.buffer.len() as u64, offset),
+      SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl Cursor for VecCursor {
+  fn set_len(&mut self, length: u64) -> Result<()> {
+    self.buffer.resize(length as usize, 0);
+    Ok(())
+  }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> Read for R {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+    Ok(std::io::Read::read(self, buf)?)
+  }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> Write for W {
+  fn write(&mut self, buf: &[u8]) -> Result<usize> {
+    Ok(std::io::Write::write(self, buf)?)
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    Ok(std::io::Write::flush(self)?)
+  }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::io::Seek + ?Sized> Seek for S {
+  fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+    let pos = match pos {
+      SeekFrom::Start(position) => std::io::SeekFrom::Start(position),
+      SeekFrom::End(offset) => std::io::SeekFrom::End(offset),
+      SeekFrom::Current(offset) => std::io::SeekFrom::Current(offset),
+    };
+    Ok(std::io::Seek::seek(self, pos)?)
+  }
+}
+
+#[cfg(feature = "std")]
+impl<C: crate::Cursor + ?Sized> Cursor for C {
+  fn sync_data(&mut self) -> Result<()> {
+    Ok(crate::Cursor::sync_data(self)?)
+  }
+
+  fn set_len(&mut self, length: u64) -> Result<()> {
+    Ok(crate::Cursor::set_len(self, length)?)
+  }
+}
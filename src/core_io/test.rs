@@ -0,0 +1,37 @@
+use std::fs::remove_file;
+
+use crate::test::temp_file;
+use crate::*;
+
+/// `no_std` 環境向けの入出力トレイトが `VecCursor` と `std::io` の実装の双方で同じように動作することを検証します。
+#[test]
+fn test_core_io() {
+  fn round_trip<C: core_io::Cursor>(cursor: &mut C) -> Vec<u8> {
+    use core_io::SeekFrom;
+    cursor.write_all(b"hello, world").unwrap();
+    assert_eq!(7, cursor.seek(SeekFrom::End(-5)).unwrap());
+    let mut buf = [0u8; 5];
+    cursor.read_exact(&mut buf).unwrap();
+    assert_eq!(b"world", &buf);
+    assert_eq!(core_io::ErrorKind::UnexpectedEof, cursor.read_exact(&mut buf).unwrap_err().kind);
+    cursor.seek(SeekFrom::Start(0)).unwrap();
+    cursor.set_len(5).unwrap();
+    let mut all = vec![0u8; 5];
+    cursor.read_exact(&mut all).unwrap();
+    all
+  }
+
+  let mut cursor = core_io::VecCursor::new();
+  assert_eq!(b"hello".to_vec(), round_trip(&mut cursor));
+  assert_eq!(b"hello", cursor.get_ref());
+  let err = core_io::Seek::seek(&mut cursor, core_io::SeekFrom::Current(-6)).unwrap_err();
+  assert_eq!(core_io::ErrorKind::InvalidInput, err.kind);
+
+  // std::io の実装を持つカーソルはそのまま使用できる
+  let file = temp_file("lmtht-core-io", ".db");
+  assert_eq!(b"hello".to_vec(), round_trip(&mut file.open(true).unwrap()));
+  let mut mem = std::io::Cursor::new(Vec::new());
+  core_io::Write::write_all(&mut mem, b"hello").unwrap();
+  assert_eq!(b"hello".to_vec(), mem.into_inner());
+  remove_file(&file).unwrap();
+}
@@ -0,0 +1,159 @@
+//! 追加したエントリがどの世代までストレージのデバイスに同期されたかを管理します。
+//!
+//! [`SyncPolicy::GroupCommit`](crate::SyncPolicy::GroupCommit) が指定された場合、追加は OS への書き込みが完了した
+//! 時点で終了し、同期はバックグラウンドのスレッドが複数の追加をまとめて行います。呼び出し側は
+//! [`LMTHT::wait_durable()`](crate::LMTHT::wait_durable) で特定の世代が同期されるまで待機することができます。
+//!
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+
+use crate::error::Detail::BackgroundSyncFailed;
+use crate::{lock2io, Cursor, Index, Result};
+
+/// 書き込み済みの世代と同期済みの世代です。
+pub(crate) struct Durability {
+  state: Mutex<State>,
+  changed: Condvar,
+}
+
+struct State {
+  /// OS への書き込みが完了している最新の世代。
+  written: Index,
+  /// デバイスへの同期が完了している最新の世代。
+  durable: Index,
+  /// バックグラウンドでの同期に失敗した場合のエラーメッセージ。
+  failure: Option<String>,
+  /// バックグラウンドのスレッドの終了が要求されているか。
+  closed: bool,
+}
+
+impl Durability {
+  /// 世代 `n` までが書き込み済みかつ同期済みである状態を構築します。
+  pub fn new(n: Index) -> Durability {
+    let state = State { written: n, durable: n, failure: None, closed: false };
+    Durability { state: Mutex::new(state), changed: Condvar::new() }
+  }
+
+  /// バックグラウンドでの同期が失敗している場合はエラーを返します。
+  pub fn check(&self) -> Result<()> {
+    match &self.lock()?.failure {
+      Some(message) => Err(BackgroundSyncFailed { message: message.clone() }),
+      None => Ok(()),
+    }
+  }
+
+  /// 世代 `n` までの書き込みが完了したことを通知します。
+  pub fn written(&self, n: Index) -> Result<()> {
+    let mut state = self.lock()?;
+    state.written = state.written.max(n);
+    self.changed.notify_all();
+    Ok(())
+  }
+
+  /// 世代 `n` までの同期が完了したことを通知します。
+  pub fn durable(&self, n: Index) -> Result<()> {
+    let mut state = self.lock()?;
+    state.written = state.written.max(n);
+    state.durable = state.durable.max(n);
+    self.changed.notify_all();
+    Ok(())
+  }
+
+  /// ストレージが世代 `n` まで切り詰められたことを通知します。
+  pub fn rewind(&self, n: Index) -> Result<()> {
+    let mut state = self.lock()?;
+    state.written = state.written.min(n);
+    state.durable = state.durable.min(n);
+    Ok(())
+  }
+
+  /// 世代 `n` までの同期が完了するまで待機します。バックグラウンドでの同期が失敗した場合はエラーを返します。
+  pub fn wait(&self, n: Index) -> Result<()> {
+    let mut state = self.lock()?;
+    loop {
+      if state.durable >= n {
+        return Ok(());
+      } else if let Some(message) = &state.failure {
+        return Err(BackgroundSyncFailed { message: message.clone() });
+      }
+      state = lock2io(self.changed.wait(state))?;
+    }
+  }
+
+  fn lock(&self) -> Result<MutexGuard<'_, State>> {
+    Ok(lock2io(self.state.lock())?)
+  }
+}
+
+/// 書き込み済みのエントリをまとめて同期するバックグラウンドのスレッドです。`drop()` 時には書き込み済みのすべての
+/// エントリを同期してからスレッドを終了します。
+pub(crate) struct GroupCommit {
+  durability: Arc<Durability>,
+  handle: Option<JoinHandle<()>>,
+}
+
+impl GroupCommit {
+  /// 指定されたカーソルで同期を行うスレッドを開始します。スレッドは未同期の書き込みを検出すると `delay` だけ後続の
+  /// 書き込みを待ってから同期します。
+  pub fn start<C: Cursor + 'static>(cursor: C, durability: Arc<Durability>, delay: Duration) -> GroupCommit {
+    let state = durability.clone();
+    let handle = spawn(move || run(cursor, &state, delay));
+    GroupCommit { durability, handle: Some(handle) }
+  }
+}
+
+impl Drop for GroupCommit {
+  fn drop(&mut self) {
+    if let Ok(mut state) = self.durability.state.lock() {
+      state.closed = true;
+      self.durability.changed.notify_all();
+    }
+    if let Some(handle) = self.handle.take() {
+      let _ = handle.join();
+    }
+  }
+}
+
+fn run<C: Cursor>(mut cursor: C, durability: &Durability, delay: Duration) {
+  loop {
+    // 未同期の書き込みが発生するまで待機
+    let mut state = match durability.state.lock() {
+      Ok(state) => state,
+      Err(_) => return,
+    };
+    while state.written == state.durable && !state.closed {
+      state = match durability.changed.wait(state) {
+        Ok(state) => state,
+        Err(_) => return,
+      };
+    }
+    if state.written == state.durable {
+      return;
+    }
+    let closed = state.closed;
+    drop(state);
+
+    // 後続の書き込みを待ってからまとめて同期する
+    if !closed && !delay.is_zero() {
+      sleep(delay);
+    }
+    let target = match durability.state.lock() {
+      Ok(state) => state.written,
+      Err(_) => return,
+    };
+    let result = cursor.sync_data();
+    let mut state = match durability.state.lock() {
+      Ok(state) => state,
+      Err(_) => return,
+    };
+    match result {
+      Ok(()) => state.durable = state.durable.max(target),
+      Err(err) => state.failure = Some(err.to_string()),
+    }
+    durability.changed.notify_all();
+    if state.failure.is_some() {
+      return;
+    }
+  }
+}
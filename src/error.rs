@@ -1,11 +1,19 @@
+use std::sync::Arc;
+
 use thiserror::Error;
 
+use crate::Index;
+
 #[derive(Error, Debug)]
 pub enum Detail {
   // ローカルファイルのオープンに失敗
   #[error("Failed to open local file {file}; {message}")]
   FailedToOpenLocalFile { file: String, message: String },
 
+  // 他の書き込みがストレージのロックを保持している
+  #[error("The storage {file} is locked by another writer")]
+  StorageLocked { file: String },
+
   // ストレージの内容が LMTHT ではない
   #[error("The contents of storage are not for LMTHT: {message}")]
   FileIsNotContentsOfLMTHTree { message: &'static str },
@@ -14,10 +22,18 @@ pub enum Detail {
   #[error("LMTHT storage version is incompatible: {0}.{1}")]
   IncompatibleVersion(u8, u8),
 
+  // ストレージのインデックスのビット幅がこのビルドの INDEX_SIZE と異なる
+  #[error("The storage uses {actual}-bit indices, but this build uses {expected}-bit indices")]
+  IncompatibleIndexSize { expected: u8, actual: u8 },
+
   // ペイロードのサイズが大きすぎる
   #[error("Payload size is too large: {size}")]
   TooLargePayload { size: usize },
 
+  // 要素数がインデックスの最大値に達している
+  #[error("The LMTHT cannot hold any more values; it already contains {n} values")]
+  CapacityExhausted { n: Index },
+
   // ストレージ破損に対する一般メッセージ
   #[error("DAMAGED STORAGE: {0}")]
   DamagedStorage(String),
@@ -42,6 +58,169 @@ pub enum Detail {
   #[error("INCONSISTENCY STATE: between the internally state and the data in storage; {message}")]
   InternalStateInconsistency { message: String },
 
+  // 組み込みの既知解テストで期待値と異なる結果を検出した
+  #[error("SELF TEST FAILED: {message}")]
+  SelfTestFailed { message: String },
+
+  // バックグラウンドでのストレージの同期に失敗した
+  #[error("Failed to sync storage in background: {message}")]
+  BackgroundSyncFailed { message: String },
+
+  // 指定された世代がまだ追加されていない
+  #[error("Generation {n} has not been appended yet; current generation is {current}")]
+  GenerationNotAppended { n: Index, current: Index },
+
+  // 読み込み専用でオープンした LMTHT に対する書き込み操作
+  #[error("The LMTHT is opened as read-only")]
+  ReadOnly,
+
+  // 封印されたストレージに対する追加
+  #[error("The storage has been sealed at generation {n}")]
+  Sealed { n: Index },
+
+  // ストレージが信頼できるルートハッシュを持つ世代を含んでいない
+  #[error("The storage is not consistent with the trusted root of generation {n}; current generation is {current}")]
+  TrustedRootMismatch { n: Index, current: Index },
+
+  // ストレージの世代が外部に記録されている世代より古い
+  #[error("The storage has been rolled back to generation {current}; generation {recorded} was previously observed")]
+  RolledBack { recorded: Index, current: Index },
+
+  // 準備した追加の後に別の値が追加されている
+  #[error("The prepared append based on generation {prepared} is stale; current generation is {current}")]
+  StalePreparedAppend { prepared: Index, current: Index },
+
+  // 値の追加を依頼した書き込みスレッドが終了している
+  #[error("The appender thread has already terminated")]
+  AppenderClosed,
+
+  // 書き込みスレッドでの値の追加に失敗した
+  #[error("Failed to append in the appender thread: {source}")]
+  BackgroundAppendFailed {
+    #[source]
+    source: Arc<Detail>,
+  },
+
+  // ミラーリングする 2 つのストレージの内容が食い違っている
+  #[error("The mirrored storages have diverged; the primary has {primary} bytes, the secondary {secondary} bytes")]
+  MirrorDiverged { primary: u64, secondary: u64 },
+
+  // 同期済みのレプリカの数がクォーラムに満たない
+  #[error("Only {available} replicas are in sync, less than the quorum of {quorum}")]
+  QuorumUnavailable { available: usize, quorum: usize },
+
+  // 値が削除されており、ハッシュ値のみが残っている
+  #[error("The value of entry {i} has been pruned; only its hash remains")]
+  PayloadPruned { i: Index },
+
+  // 値がアーカイブされ、ストレージから削除されている
+  #[error("The value of entry {i} has been archived with the entries {from}..={to}")]
+  Archived { i: Index, from: Index, to: Index },
+
+  // アーカイブカタログが指定されていない
+  #[error("No archive catalog is configured")]
+  NoArchiveCatalog,
+
+  // アーカイブに含まれている値が証明と一致しない
+  #[error("The value of entry {i} in the archive does not match its proof")]
+  ArchiveVerificationFailed { i: Index },
+
+  // 値の直列化または復元に失敗した
+  #[error("Failed to serialize or deserialize the value: {message}")]
+  Serialization { message: String },
+
+  // 値がレコード形式ではない
+  #[error("The value of entry {i} is not a record")]
+  InvalidRecord { i: Index },
+
+  // レコードがタイムスタンプを持たない
+  #[error("The record of entry {i} has no timestamp")]
+  NoTimestamp { i: Index },
+
+  // レコードのタイムスタンプが直前のレコードの時刻より前である
+  #[error("The timestamp {timestamp} of entry {i} is earlier than the last timestamp {last}")]
+  TimestampOutOfOrder { i: Index, timestamp: u64, last: u64 },
+
+  // 値を持つストレージにレコード形式が記録されていない
+  #[error("The storage already has values and is not reserved for records")]
+  RecordsNotReserved,
+
+  // キー索引が指定されていない
+  #[error("No key index is configured")]
+  NoKeyIndex,
+
+  // ハッシュ索引が指定されていない
+  #[error("No hash index is configured")]
+  NoHashIndex,
+
+  // ブルームフィルタが指定されていない
+  #[error("No bloom filter is configured")]
+  NoBloomFilter,
+
+  // マップの値またはその後の更新の証明が検証できない
+  #[error("The map entry {i} does not match its proof")]
+  MapVerificationFailed { i: Index },
+
+  // イベントのスキーマのバージョンに対応する変換関数が登録されていない
+  #[error("The event of entry {i} has schema version {version} which cannot be upcast")]
+  UnknownEventVersion { i: Index, version: u32 },
+
+  // インポートする入力の行が正しくない
+  #[error("Invalid line {line} in the imported data: {message}")]
+  InvalidImportLine { line: u64, message: String },
+
+  // インポート後のルートハッシュが期待するルートハッシュと一致しない
+  #[error("The root hash of generation {n} after the import does not match the expected root")]
+  ImportRootMismatch { n: Index },
+
+  // 書き込みの終了時に完成していないフレームが残っている
+  #[error("{length} bytes of an incomplete frame remain")]
+  IncompleteFrame { length: usize },
+
+  // フレームの長さが値の最大サイズを超えている
+  #[error("The frame length {length} exceeds the maximum payload size")]
+  FrameTooLarge { length: usize },
+
+  // フォレストのメンバーの値が証明と一致しない
+  #[error("The proof for the forest member {name:?} does not match the forest root")]
+  ForestVerificationFailed { name: String },
+
+  // 名前空間のディレクトリがディレクトリ領域に収まらない
+  #[error("The namespace {name:?} cannot be added because the directory is full")]
+  NamespaceDirectoryFull { name: String },
+
+  // バックアップの末尾のエントリが複製した世代と一致しない
+  #[error("The backup does not end with the entry of generation {n}")]
+  BackupVerificationFailed { n: Index },
+
+  // ブランチのオーバーレイが記録している分岐点がベースのストレージと一致しない
+  #[error("The overlay of the branch does not match generation {n} of the base storage")]
+  BranchBaseMismatch { n: Index },
+
+  // 切り詰めが許可されていない LMTHT で切り詰めを行った
+  #[error("Truncating the history is not allowed; open the storage with allow_truncate")]
+  TruncateNotAllowed,
+
+  // 範囲が空であるか追加済みのエントリの範囲に含まれない
+  #[error("The range {i0}..={i1} is not within the appended entries 1..={n}")]
+  InvalidRange { i0: Index, i1: Index, n: Index },
+
+  // 範囲の値から算出したルートハッシュが期待するルートハッシュと一致しない
+  #[error("The values {i0}..={i1} do not match the expected root")]
+  RangeVerificationFailed { i0: Index, i1: Index },
+
+  // まとめて検証した証明のいずれかがルートハッシュと一致しない
+  #[error("The proof at position {index} in the batch does not match the root")]
+  BatchVerificationFailed { index: usize },
+
+  // 自己記述的な証明が記述している世代やハッシュアルゴリズム、ルートハッシュと一致しない
+  #[error("The proof for generation {n} does not match the root")]
+  ProofVerificationFailed { n: Index },
+
+  // 以前の追加が失敗してストレージと索引の状態が一致していない (再オープンによって復旧する)
+  #[error("A previous append failed and left the storage inconsistent; reopen it: {message}")]
+  WriterPoisoned { message: String },
+
   #[error("I/O error: {source}")]
   Io {
     #[from]
@@ -51,6 +230,6 @@ pub enum Detail {
   #[error("{source}")]
   Otherwise {
     #[from]
-    source: Box<dyn std::error::Error>,
+    source: Box<dyn std::error::Error + Send + Sync>,
   },
 }
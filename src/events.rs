@@ -0,0 +1,194 @@
+//! serde で直列化したイベントをハッシュ木に記録するイベントソーシングのための API です。
+//!
+//! [`EventLog`] はイベントを [`Codec`] で直列化し、スキーマのバージョンをタグ ([`Record::tag`]) としたレコードとして
+//! 追加します。イベントの型を変更した場合はバージョンを上げ、古いバージョンのイベントを現在の型に変換する関数を
+//! [`EventLog::upcast()`] で登録します。読み込み時には記録されたバージョンに応じて直接復元するか変換関数を適用する
+//! ため、ハッシュ木に記録済みのイベントを書き換えることなく型を変更することができます。
+//!
+//! [`EventLog::fold()`] と [`EventLog::replay()`] はイベントを先頭から順に読み込み、状態を更新する関数に渡します。
+//! すべてのイベントをメモリ上に読み込むことはありません。
+//!
+//! ```rust,no_run
+//! use lmtht::events::EventLog;
+//! use lmtht::typed::Codec;
+//! use lmtht::LMTHT;
+//!
+//! # #[cfg(any(feature = "typed_bincode", feature = "typed_cbor"))]
+//! # fn main() -> lmtht::Result<()> {
+//! // バージョン 1 の符号付きの金額をバージョン 2 の操作と金額の組に変換する
+//! let log = EventLog::<_, (String, i64), _>::new(LMTHT::new("account.db")?, 2).upcast(1, |codec, bytes| {
+//!   let amount: i64 = codec.decode(bytes)?;
+//!   Ok((if amount < 0 { "withdraw" } else { "deposit" }.to_string(), amount.abs()))
+//! });
+//! log.append(&("deposit".to_string(), 100))?;
+//! let balance = log.fold(0i64, |balance, (kind, amount)| {
+//!   if kind == "deposit" {
+//!     balance + amount
+//!   } else {
+//!     balance - amount
+//!   }
+//! })?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "typed_bincode", feature = "typed_cbor")))]
+//! # fn main() {}
+//! ```
+//!
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::Detail;
+use crate::record::Record;
+use crate::typed::Codec;
+#[cfg(any(feature = "bincode", feature = "ciborium"))]
+use crate::typed::DefaultCodec;
+use crate::{BufferedCursor, Index, Node, Query, Result, Storage, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// 古いバージョンのイベントのバイト列を現在の型に変換する関数です。
+type Upcaster<D, E> = Box<dyn Fn(&D, &[u8]) -> Result<E> + Send + Sync>;
+
+/// 型 `E` のイベントをスキーマのバージョンとともに記録するイベントログです。
+pub struct EventLog<S: Storage, E, D: Codec> {
+  db: LMTHT<S>,
+  codec: D,
+  /// 追加するイベントのスキーマのバージョン。
+  version: u32,
+  /// バージョンごとの変換関数。
+  upcasters: HashMap<u32, Upcaster<D, E>>,
+  _event: PhantomData<fn() -> E>,
+}
+
+#[cfg(any(feature = "bincode", feature = "ciborium"))]
+impl<S: Storage, E: Serialize + DeserializeOwned> EventLog<S, E, DefaultCodec> {
+  /// 指定された LMTHT にバージョン `version` のイベントを [`DefaultCodec`] で記録するイベントログを作成します。
+  pub fn new(db: LMTHT<S>, version: u32) -> EventLog<S, E, DefaultCodec> {
+    EventLog::with_codec(db, DefaultCodec::default(), version)
+  }
+}
+
+impl<S: Storage, E: Serialize + DeserializeOwned, D: Codec> EventLog<S, E, D> {
+  /// 指定された LMTHT にバージョン `version` のイベントを指定された [`Codec`] で記録するイベントログを作成します。
+  pub fn with_codec(db: LMTHT<S>, codec: D, version: u32) -> EventLog<S, E, D> {
+    EventLog { db, codec, version, upcasters: HashMap::new(), _event: PhantomData }
+  }
+
+  /// バージョン `version` のイベントを読み込むときに適用する変換関数を登録します。変換関数は記録されたイベントの
+  /// バイト列を受け取り、現在の型のイベントを返します。
+  pub fn upcast<F>(mut self, version: u32, f: F) -> EventLog<S, E, D>
+  where
+    F: Fn(&D, &[u8]) -> Result<E> + Send + Sync + 'static,
+  {
+    self.upcasters.insert(version, Box::new(f));
+    self
+  }
+
+  /// このイベントログが記録している LMTHT を参照します。
+  pub fn lmtht(&self) -> &LMTHT<S> {
+    &self.db
+  }
+
+  /// このイベントログを破棄して LMTHT を返します。
+  pub fn into_inner(self) -> LMTHT<S> {
+    self.db
+  }
+
+  /// イベントを現在のバージョンで直列化して追加します。
+  pub fn append(&self, event: &E) -> Result<Node> {
+    self.db.append_tagged(self.version, &self.codec.encode(event)?)
+  }
+
+  /// インデックス `i` のイベントを参照します。範囲外のインデックスを指定した場合は `None` を返します。
+  pub fn get(&self, i: Index) -> Result<Option<E>> {
+    let mut query = self.db.query()?;
+    query.get_record(i)?.map(|record| self.decode(i, record)).transpose()
+  }
+
+  /// 現在の世代に含まれるイベントをインデックス順に返すイテレータを作成します。
+  pub fn iter(&self) -> Result<Events<'_, S, E, D>> {
+    self.iter_from(1)
+  }
+
+  /// 現在の世代に含まれるイベントのうちインデックス `from` 以降のものをインデックス順に返すイテレータを作成します。
+  pub fn iter_from(&self, from: Index) -> Result<Events<'_, S, E, D>> {
+    let query = self.db.query()?;
+    let n = query.n();
+    Ok(Events { log: self, query, next: from.max(1), n })
+  }
+
+  /// 現在の世代に含まれるすべてのイベントを順に `f` に渡して状態 `init` を更新し、最終的な状態を返します。
+  pub fn fold<T, F>(&self, init: T, mut f: F) -> Result<T>
+  where
+    F: FnMut(T, E) -> T,
+  {
+    let mut state = init;
+    for event in self.iter()? {
+      state = f(state, event?.1);
+    }
+    Ok(state)
+  }
+
+  /// インデックス `from` 以降のイベントを順に `f` に渡して `state` を更新し、反映した最後のイベントのインデックスを
+  /// 返します。保存しておいた状態とインデックスから、その後に追加されたイベントのみを反映することができます。反映
+  /// するイベントがない場合は `from - 1` を返します。
+  pub fn replay<T, F>(&self, from: Index, state: &mut T, mut f: F) -> Result<Index>
+  where
+    F: FnMut(&mut T, Index, E),
+  {
+    let mut last = from.max(1) - 1;
+    for event in self.iter_from(from)? {
+      let (i, event) = event?;
+      f(state, i, event);
+      last = i;
+    }
+    Ok(last)
+  }
+
+  /// i 番目のエントリのレコードを記録されたバージョンに応じてイベントに復元します。
+  fn decode(&self, i: Index, record: Record) -> Result<E> {
+    match record.tag {
+      Some(version) if version == self.version => self.codec.decode(&record.payload),
+      Some(version) => match self.upcasters.get(&version) {
+        Some(upcaster) => upcaster(&self.codec, &record.payload),
+        None => Err(Detail::UnknownEventVersion { i, version }),
+      },
+      None => Err(Detail::InvalidRecord { i }),
+    }
+  }
+}
+
+/// [`EventLog::iter()`] で作成した、イベントをインデックス順に返すイテレータです。
+pub struct Events<'a, S: Storage, E, D: Codec> {
+  log: &'a EventLog<S, E, D>,
+  query: Query<BufferedCursor<S::Cursor>>,
+  /// 次に読み込むイベントのインデックス。
+  next: Index,
+  /// イテレータを作成したときの世代。
+  n: Index,
+}
+
+impl<'a, S: Storage, E: Serialize + DeserializeOwned, D: Codec> Iterator for Events<'a, S, E, D> {
+  type Item = Result<(Index, E)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.next > self.n {
+      return None;
+    }
+    let i = self.next;
+    self.next += 1;
+    let result = match self.query.get_record(i) {
+      Ok(Some(record)) => self.log.decode(i, record),
+      Ok(None) => Err(Detail::DamagedStorage(format!("the entry {} is not found", i))),
+      Err(err) => Err(err),
+    };
+    if result.is_err() {
+      self.next = self.n + 1;
+    }
+    Some(result.map(|event| (i, event)))
+  }
+}
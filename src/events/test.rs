@@ -0,0 +1,47 @@
+use crate::error::Detail;
+use crate::*;
+
+/// イベントログが古いバージョンのイベントを変換して復元し、イベントを順に状態へ反映できることを検証します。
+#[cfg(any(feature = "typed_bincode", feature = "typed_cbor"))]
+#[test]
+fn test_event_log() {
+  use crate::events::EventLog;
+  use crate::typed::Codec;
+  let v1 = EventLog::<_, i64, _>::new(LMTHT::new(MemStorage::new()).unwrap(), 1);
+  v1.append(&100).unwrap();
+  v1.append(&-30).unwrap();
+
+  // バージョン 2 では金額に操作の種類を付加する
+  let log = EventLog::<_, (String, i64), _>::new(v1.into_inner(), 2).upcast(1, |codec, bytes| {
+    let amount: i64 = codec.decode(bytes)?;
+    Ok((if amount < 0 { "withdraw" } else { "deposit" }.to_string(), amount.abs()))
+  });
+  log.append(&("deposit".to_string(), 50)).unwrap();
+  assert_eq!(Some(("withdraw".to_string(), 30)), log.get(2).unwrap());
+  assert_eq!(None, log.get(4).unwrap());
+  let apply = |balance: i64, (kind, amount): (String, i64)| {
+    if kind == "deposit" {
+      balance + amount
+    } else {
+      balance - amount
+    }
+  };
+  assert_eq!(120, log.fold(0, apply).unwrap());
+
+  // 保存した状態からその後のイベントのみを反映する
+  let mut balance = 0;
+  let last = log.replay(1, &mut balance, |balance, _, event| *balance = apply(*balance, event)).unwrap();
+  assert_eq!((3, 120), (last, balance));
+  log.append(&("withdraw".to_string(), 20)).unwrap();
+  let last = log.replay(last + 1, &mut balance, |balance, _, event| *balance = apply(*balance, event)).unwrap();
+  assert_eq!((4, 100), (last, balance));
+  assert_eq!(4, log.replay(5, &mut balance, |_, _, _| unreachable!()).unwrap());
+  assert_eq!(vec![3, 4], log.iter_from(3).unwrap().map(|e| e.unwrap().0).collect::<Vec<_>>());
+
+  // 変換関数が登録されていないバージョンやレコードでない値は復元できない
+  let log = EventLog::<_, (String, i64), _>::new(log.into_inner(), 2);
+  assert!(matches!(log.get(1), Err(Detail::UnknownEventVersion { i: 1, version: 1 })));
+  log.lmtht().append(b"raw").unwrap();
+  assert!(matches!(log.get(5), Err(Detail::InvalidRecord { i: 5 })));
+  assert!(log.iter_from(5).unwrap().next().unwrap().is_err());
+}
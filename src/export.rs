@@ -0,0 +1,166 @@
+//! LMTHT に保存されている値を他のシステムと交換するための形式で出力するエクスポータです。
+//!
+//! [`length_prefixed()`] は各値をバイト長 (u32 リトルエンディアン) とそれに続くバイト列として順に書き出した
+//! 単純なレコードファイルを出力します。この形式は [`import::length_prefixed()`](crate::import::length_prefixed) で
+//! 読み込むことができ、エクスポート時のルートハッシュと比較することで同じ木構造が再構築されたことを確認できます。
+//!
+//! `parquet_export` feature を指定した場合、[`parquet()`] は各エントリのインデックス、タイムスタンプ、タグ、
+//! ペイロード、葉ノードのハッシュ値を Arrow を経由して Parquet 形式で出力します。DuckDB や Spark などの分析基盤から
+//! 独自のリーダーを使用せずに参照することができ、ファイルのメタデータに記録したルートハッシュによって出力が世代 n の
+//! 木構造と一致していたことを証明することができます。
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//!
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let db = LMTHT::new("ledger.db")?;
+//! let root = lmtht::export::length_prefixed(&mut db.query()?, File::create("ledger.records")?)?;
+//! let copy = LMTHT::new("copy.db")?;
+//! lmtht::import::length_prefixed(File::open("ledger.records")?, &copy, root.map(|root| root.hash).as_ref())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io::Write;
+#[cfg(feature = "parquet_export")]
+use std::sync::Arc;
+
+#[cfg(feature = "parquet_export")]
+use arrow::array::{ArrayRef, BinaryBuilder, FixedSizeBinaryBuilder, UInt32Builder, UInt64Builder};
+#[cfg(feature = "parquet_export")]
+use arrow::datatypes::{DataType, Field, Schema};
+#[cfg(feature = "parquet_export")]
+use arrow::record_batch::RecordBatch;
+#[cfg(feature = "parquet_export")]
+use parquet::arrow::ArrowWriter;
+#[cfg(feature = "parquet_export")]
+use parquet::file::properties::WriterProperties;
+#[cfg(feature = "parquet_export")]
+use parquet::format::KeyValue;
+
+use crate::error::Detail;
+#[cfg(feature = "parquet_export")]
+use crate::record::Record;
+#[cfg(feature = "parquet_export")]
+use crate::{hex, Hash, HASH_SIZE};
+use crate::{Cursor, Node, Query, Result};
+
+#[cfg(test)]
+mod test;
+
+/// Parquet ファイルのメタデータに記録する、出力した木構造の世代のキーです。
+#[cfg(feature = "parquet_export")]
+pub const PARQUET_GENERATION_KEY: &str = "lmtht.n";
+
+/// Parquet ファイルのメタデータに記録する、出力した木構造のルートハッシュ (16 進数) のキーです。
+#[cfg(feature = "parquet_export")]
+pub const PARQUET_ROOT_KEY: &str = "lmtht.root";
+
+/// Parquet ファイルの 1 つの行グループに出力するエントリの数です。
+#[cfg(feature = "parquet_export")]
+const PARQUET_ROWS_PER_BATCH: usize = 8192;
+
+/// `query` の世代に含まれるすべての値を長さ付きのレコードとして `writer` に出力し、その世代のルートノードを返し
+/// ます。値が存在しない場合は何も出力せずに `None` を返します。
+///
+/// # Errors
+/// [`LMTHT::prune_payloads()`](crate::LMTHT::prune_payloads) で値が削除されているエントリを含む場合は
+/// [`Detail::PayloadPruned`] を返します。
+pub fn length_prefixed<C: Cursor, W: Write>(query: &mut Query<C>, mut writer: W) -> Result<Option<Node>> {
+  for i in 1..=query.n() {
+    let value = match query.get(i)? {
+      Some(value) => value,
+      None => return Err(Detail::DamagedStorage(format!("the entry {} is not found", i))),
+    };
+    writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    writer.write_all(&value)?;
+  }
+  writer.flush()?;
+  Ok(query.gen.root())
+}
+
+/// `query` の世代に含まれるすべてのエントリを Parquet 形式で `writer` に出力し、その世代のルートノードを返します。
+/// 各行は次の列を持ちます。値が [`Record`] の場合はそのタイムスタンプ、タグ、ペイロードを、そうでない場合は値全体を
+/// ペイロードとして出力します。
+///
+/// | 列 | 型 |
+/// |:---|:---|
+/// | `index` | UInt64 |
+/// | `timestamp` | UInt64 (null 可) |
+/// | `tag` | UInt32 (null 可) |
+/// | `payload` | Binary |
+/// | `leaf_hash` | FixedSizeBinary([`HASH_SIZE`]) |
+///
+/// ファイルのメタデータには [`PARQUET_GENERATION_KEY`] と [`PARQUET_ROOT_KEY`] で世代とルートハッシュを記録します。
+/// 葉ノードのハッシュ値から [`Node`] を再計算してルートハッシュと比較することで、出力された値が改変されていない
+/// ことを検証できます。
+///
+/// # Errors
+/// Parquet の出力に失敗した場合は [`Detail::Serialization`] を返します。
+#[cfg(feature = "parquet_export")]
+pub fn parquet<C: Cursor, W: Write + Send>(query: &mut Query<C>, writer: W) -> Result<Option<Node>> {
+  let n = query.n();
+  let root = query.gen.root();
+  let schema = Arc::new(Schema::new(vec![
+    Field::new("index", DataType::UInt64, false),
+    Field::new("timestamp", DataType::UInt64, true),
+    Field::new("tag", DataType::UInt32, true),
+    Field::new("payload", DataType::Binary, false),
+    Field::new("leaf_hash", DataType::FixedSizeBinary(HASH_SIZE as i32), false),
+  ]));
+  let mut metadata = vec![KeyValue::new(PARQUET_GENERATION_KEY.to_string(), n.to_string())];
+  if let Some(root) = &root {
+    metadata.push(KeyValue::new(PARQUET_ROOT_KEY.to_string(), hex(&root.hash.value)));
+  }
+  let properties = WriterProperties::builder().set_key_value_metadata(Some(metadata)).build();
+  let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties)).map_err(serialization)?;
+
+  let mut i = 1;
+  while i <= n {
+    let rows = ((n - i + 1) as usize).min(PARQUET_ROWS_PER_BATCH);
+    let mut index = UInt64Builder::with_capacity(rows);
+    let mut timestamp = UInt64Builder::with_capacity(rows);
+    let mut tag = UInt32Builder::with_capacity(rows);
+    let mut payload = BinaryBuilder::new();
+    let mut leaf_hash = FixedSizeBinaryBuilder::with_capacity(rows, HASH_SIZE as i32);
+    for _ in 0..rows {
+      let value = match query.get(i)? {
+        Some(value) => value,
+        None => return Err(Detail::DamagedStorage(format!("the entry {} is not found", i))),
+      };
+      leaf_hash.append_value(Hash::hash(&value).value).map_err(serialization)?;
+      index.append_value(i as u64);
+      match Record::from_bytes(i, value.clone()) {
+        Ok(record) => {
+          timestamp.append_option(record.timestamp);
+          tag.append_option(record.tag);
+          payload.append_value(&record.payload);
+        }
+        Err(_) => {
+          timestamp.append_null();
+          tag.append_null();
+          payload.append_value(&value);
+        }
+      }
+      i += 1;
+    }
+    let columns: Vec<ArrayRef> = vec![
+      Arc::new(index.finish()),
+      Arc::new(timestamp.finish()),
+      Arc::new(tag.finish()),
+      Arc::new(payload.finish()),
+      Arc::new(leaf_hash.finish()),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(serialization)?;
+    writer.write(&batch).map_err(serialization)?;
+  }
+  writer.close().map_err(serialization)?;
+  Ok(root)
+}
+
+#[cfg(feature = "parquet_export")]
+fn serialization<E: std::fmt::Display>(err: E) -> Detail {
+  Detail::Serialization { message: err.to_string() }
+}
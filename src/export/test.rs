@@ -0,0 +1,58 @@
+use crate::error::Detail;
+use crate::*;
+
+/// 長さ付きのレコードファイルに出力した値を読み込み、同じルートハッシュの木構造が再構築されることを検証します。
+#[test]
+fn test_length_prefixed_records() {
+  use crate::{export, import};
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let mut file = Vec::new();
+  assert_eq!(None, export::length_prefixed(&mut db.query().unwrap(), &mut file).unwrap());
+  assert!(file.is_empty());
+  for i in 0..20u32 {
+    db.append(&vec![i as u8; i as usize]).unwrap();
+  }
+  let root = export::length_prefixed(&mut db.query().unwrap(), &mut file).unwrap().unwrap();
+  assert_eq!(db.root().unwrap(), root);
+  assert_eq!(20 * 4 + (0..20).sum::<usize>(), file.len());
+
+  let copy = LMTHT::new(MemStorage::new()).unwrap();
+  let progress = import::length_prefixed_with(&file[..], &copy, Some(&root.hash), 7, |_| ()).unwrap();
+  assert_eq!((20, 20, Some(root)), (progress.lines, progress.entries, progress.root));
+  let mut query = copy.query().unwrap();
+  assert_eq!(Some(vec![5u8; 5]), query.get(6).unwrap());
+
+  // 異なる木構造への追加や途中で終わっているファイルは検出される
+  let result = import::length_prefixed(&file[..], &copy, Some(&root.hash));
+  assert!(matches!(result, Err(Detail::ImportRootMismatch { n: 40 })));
+  let copy = LMTHT::new(MemStorage::new()).unwrap();
+  let result = import::length_prefixed(&file[..file.len() - 1], &copy, None);
+  assert!(matches!(result, Err(Detail::InvalidImportLine { line: 20, .. })));
+  let result = import::length_prefixed(&[1u8, 0][..], &copy, None);
+  assert!(matches!(result, Err(Detail::InvalidImportLine { line: 1, .. })));
+}
+
+/// Parquet 形式で出力したエントリの行数と、メタデータに記録した世代とルートハッシュを検証します。
+#[cfg(feature = "parquet_export")]
+#[test]
+fn test_parquet_export() {
+  use crate::export::{parquet, PARQUET_GENERATION_KEY, PARQUET_ROOT_KEY};
+  use ::parquet::file::reader::{FileReader, SerializedFileReader};
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 0..10u8 {
+    db.append_tagged(i as u32 % 3, &[i; 4]).unwrap();
+  }
+  db.append(b"raw").unwrap();
+  let path = crate::test::temp_file("lmtht-export", ".parquet");
+  let root = parquet(&mut db.query().unwrap(), File::create(&path).unwrap()).unwrap().unwrap();
+  assert_eq!(db.root().unwrap(), root);
+
+  let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+  let metadata = reader.metadata().file_metadata();
+  assert_eq!(11, metadata.num_rows());
+  let kv = metadata.key_value_metadata().unwrap();
+  let value_of = |key: &str| kv.iter().find(|kv| kv.key == key).and_then(|kv| kv.value.clone());
+  assert_eq!(Some("11".to_string()), value_of(PARQUET_GENERATION_KEY));
+  assert_eq!(Some(root.hash.to_str()), value_of(PARQUET_ROOT_KEY));
+  remove_file(&path).unwrap();
+}
@@ -0,0 +1,246 @@
+//! 指定した回数目の入出力を失敗させたり、読み込んだ内容を破損させるストレージのラッパーです。
+//!
+//! [`FaultyStorage`] は任意の [`Storage`] を包み、そのストレージから作成されたすべてのカーソルの読み込み、書き込み、
+//! 同期の回数を数えます。あらかじめ指定した回数目の操作をエラーにしたり、1 回の読み込みのバイト数を制限したり、
+//! 特定の位置のバイトを反転して返すことができるため、LMTHT を使用するアプリケーションの障害からの回復処理を検証
+//! するために使用します。
+//!
+//! ```rust
+//! use lmtht::{LMTHT, MemStorage};
+//! use lmtht::fault::FaultyStorage;
+//! let db = LMTHT::new(FaultyStorage::new(MemStorage::new())).unwrap();
+//! db.storage().fail_nth_write(1);
+//! assert!(db.append(b"hello").is_err());
+//! ```
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::{lock2io, Capabilities, Cursor, IoCounts, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// 障害を注入するストレージのラッパーです。
+pub struct FaultyStorage<S: Storage> {
+  inner: S,
+  plan: Arc<Mutex<Plan>>,
+}
+
+/// 注入する障害の予定と、これまでに行われた操作の回数。
+#[derive(Default)]
+struct Plan {
+  reads: u64,
+  writes: u64,
+  syncs: u64,
+  failing_reads: BTreeSet<u64>,
+  failing_writes: BTreeSet<u64>,
+  failing_syncs: BTreeSet<u64>,
+  max_read: Option<usize>,
+  /// 読み込んだ内容を反転する位置とその XOR マスク。
+  flips: BTreeMap<u64, u8>,
+}
+
+impl<S: Storage> FaultyStorage<S> {
+  /// 指定されたストレージを障害を注入していない状態で包みます。
+  pub fn new(inner: S) -> FaultyStorage<S> {
+    FaultyStorage { inner, plan: Arc::new(Mutex::new(Plan::default())) }
+  }
+
+  /// 包んでいるストレージを参照します。
+  pub fn get_ref(&self) -> &S {
+    &self.inner
+  }
+
+  /// この呼び出し以降の `n` 回目 (1 が次の操作) の読み込みをエラーにします。
+  pub fn fail_nth_read(&self, n: u64) {
+    assert!(n > 0);
+    self.update(|plan| {
+      let target = plan.reads + n;
+      plan.failing_reads.insert(target);
+    });
+  }
+
+  /// この呼び出し以降の `n` 回目 (1 が次の操作) の書き込みをエラーにします。エラーになった書き込みの内容は下位の
+  /// ストレージに出力されません。
+  pub fn fail_nth_write(&self, n: u64) {
+    assert!(n > 0);
+    self.update(|plan| {
+      let target = plan.writes + n;
+      plan.failing_writes.insert(target);
+    });
+  }
+
+  /// この呼び出し以降の `n` 回目 (1 が次の操作) の同期をエラーにします。
+  pub fn fail_nth_sync(&self, n: u64) {
+    assert!(n > 0);
+    self.update(|plan| {
+      let target = plan.syncs + n;
+      plan.failing_syncs.insert(target);
+    });
+  }
+
+  /// 1 回の読み込みで返すバイト数を最大 `max` バイトに制限します。`None` を指定すると制限を解除します。
+  pub fn limit_reads(&self, max: Option<usize>) {
+    assert!(max != Some(0));
+    self.update(|plan| plan.max_read = max);
+  }
+
+  /// 位置 `offset` のバイトを読み込んだときに `mask` との XOR を返します。ストレージの内容は変更されません。
+  pub fn flip_byte(&self, offset: u64, mask: u8) {
+    self.update(|plan| {
+      plan.flips.insert(offset, mask);
+    });
+  }
+
+  /// 予定しているすべての障害を取り消します。操作の回数は維持されます。
+  pub fn clear(&self) {
+    self.update(|plan| {
+      plan.failing_reads.clear();
+      plan.failing_writes.clear();
+      plan.failing_syncs.clear();
+      plan.max_read = None;
+      plan.flips.clear();
+    });
+  }
+
+  /// これまでにすべてのカーソルで行われた読み込みの回数を参照します。
+  pub fn reads(&self) -> u64 {
+    self.plan.lock().map(|plan| plan.reads).unwrap_or(0)
+  }
+
+  /// これまでにすべてのカーソルで行われた書き込みの回数を参照します。
+  pub fn writes(&self) -> u64 {
+    self.plan.lock().map(|plan| plan.writes).unwrap_or(0)
+  }
+
+  fn update<F: FnOnce(&mut Plan)>(&self, f: F) {
+    match self.plan.lock() {
+      Ok(mut plan) => f(&mut plan),
+      Err(err) => f(&mut err.into_inner()),
+    }
+  }
+}
+
+impl<S: Storage> Storage for FaultyStorage<S> {
+  type Cursor = FaultyCursor<S::Cursor>;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    Ok(FaultyCursor { inner: self.inner.open(writable)?, plan: self.plan.clone(), position: None })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.inner.capabilities()
+  }
+}
+
+/// [`FaultyStorage`] が使用するカーソルです。
+pub struct FaultyCursor<C: Cursor> {
+  inner: C,
+  plan: Arc<Mutex<Plan>>,
+  /// 下位のカーソルの現在の位置。不明な場合は `None`。
+  position: Option<u64>,
+}
+
+impl<C: Cursor> FaultyCursor<C> {
+  fn position(&mut self) -> io::Result<u64> {
+    match self.position {
+      Some(position) => Ok(position),
+      None => {
+        let position = self.inner.stream_position()?;
+        self.position = Some(position);
+        Ok(position)
+      }
+    }
+  }
+}
+
+impl<C: Cursor> Cursor for FaultyCursor<C> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    let mut plan = lock2io(self.plan.lock())?;
+    plan.syncs += 1;
+    let n = plan.syncs;
+    if plan.failing_syncs.remove(&n) {
+      return Err(injected("sync"));
+    }
+    drop(plan);
+    self.inner.sync_data()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.inner.set_len(length)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.inner.len()
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.position = None;
+    self.inner.discard(position, length)?;
+    self.position = Some(position + length);
+    Ok(())
+  }
+
+  fn io_counts(&self) -> IoCounts {
+    self.inner.io_counts()
+  }
+
+  fn duplicate(&self) -> io::Result<Option<Self>> {
+    let inner = self.inner.duplicate()?;
+    Ok(inner.map(|inner| FaultyCursor { inner, plan: self.plan.clone(), position: None }))
+  }
+}
+
+impl<C: Cursor> io::Seek for FaultyCursor<C> {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    self.position = None;
+    let position = self.inner.seek(pos)?;
+    self.position = Some(position);
+    Ok(position)
+  }
+}
+
+impl<C: Cursor> io::Read for FaultyCursor<C> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let start = self.position()?;
+    let mut plan = lock2io(self.plan.lock())?;
+    plan.reads += 1;
+    let n = plan.reads;
+    if plan.failing_reads.remove(&n) {
+      return Err(injected("read"));
+    }
+    let length = plan.max_read.map(|max| max.min(buf.len())).unwrap_or(buf.len());
+    drop(plan);
+    self.position = None;
+    let length = self.inner.read(&mut buf[..length])?;
+    self.position = Some(start + length as u64);
+    let plan = lock2io(self.plan.lock())?;
+    for (offset, mask) in plan.flips.range(start..start + length as u64) {
+      buf[(offset - start) as usize] ^= mask;
+    }
+    Ok(length)
+  }
+}
+
+impl<C: Cursor> io::Write for FaultyCursor<C> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut plan = lock2io(self.plan.lock())?;
+    plan.writes += 1;
+    let n = plan.writes;
+    if plan.failing_writes.remove(&n) {
+      return Err(injected("write"));
+    }
+    drop(plan);
+    self.position = None;
+    self.inner.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+fn injected(operation: &str) -> io::Error {
+  io::Error::other(format!("injected {} fault", operation))
+}
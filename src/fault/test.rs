@@ -0,0 +1,46 @@
+use std::io::Read;
+
+use crate::*;
+
+/// `FaultyStorage` が指定した回数目の操作を失敗させ、読み込みの制限やバイトの反転を行うことを検証します。
+#[test]
+fn test_faulty_storage() {
+  use crate::fault::FaultyStorage;
+
+  let db = LMTHT::new(FaultyStorage::new(MemStorage::new())).unwrap();
+  for i in 1..=10u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+
+  // 指定した回数目の書き込みと同期が失敗する
+  db.storage().fail_nth_write(1);
+  assert!(db.append(&[0u8; 16]).is_err());
+  db.storage().fail_nth_sync(1);
+  assert!(db.sync().is_err());
+
+  // 1 回の読み込みが制限されても同じ値を参照できる
+  db.storage().limit_reads(Some(3));
+  let reads = db.storage().reads();
+  let mut query = db.query().unwrap();
+  for i in 1..=10u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+  assert!(db.storage().reads() > reads);
+  db.storage().clear();
+
+  // 指定した回数目の読み込みが失敗する
+  db.storage().fail_nth_read(1);
+  assert!(query.get(5).is_err());
+  assert_eq!(Some(vec![5u8; 16]), query.get(5).unwrap());
+
+  // 反転したバイトを含む値は元の値として読み込まれない
+  let position = {
+    let mut cursor = db.storage().get_ref().open(false).unwrap();
+    let mut bytes = Vec::new();
+    cursor.read_to_end(&mut bytes).unwrap();
+    bytes.windows(16).position(|w| w == [3u8; 16]).unwrap() as u64
+  };
+  db.storage().flip_byte(position, 0xFF);
+  let mut query = db.query().unwrap();
+  assert_ne!(Some(vec![3u8; 16]), query.get(3).ok().flatten());
+}
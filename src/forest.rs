@@ -0,0 +1,195 @@
+//! 複数の LMTHT のルートハッシュを 1 つのルートハッシュに集約するフォレストです。
+//!
+//! [`Forest`] は名前を付けた複数の LMTHT (テナントごとのログなど) を保持し、名前の順に並べたそれぞれのルートから
+//! 構築した二分木のルートハッシュを [`Forest::root_hash()`] として算出します。外部のアンカー (タイムスタンプ局や
+//! ブロックチェーンなど) にはこのルートハッシュのみを記録すれば、すべてのメンバーの木構造を同時に固定することが
+//! できます。
+//!
+//! [`Forest::prove()`] はメンバーの値の証明にメンバーのルートからフォレストのルートまでの経路を加えた
+//! [`ForestProof`] を返し、検証者はフォレストのルートハッシュのみから値を検証することができます。フォレストの二分木
+//! の葉は次のバイト列のハッシュ値です。整数はすべてリトルエンディアンで、値を持たないメンバーのルートハッシュは
+//! すべて 0 とします。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | 名前のバイト長 (u32) | 4 |
+//! | 名前 (UTF-8) | 名前のバイト長 |
+//! | 世代 | [`INDEX_SIZE`](crate::INDEX_SIZE) のビット幅 |
+//! | ルートハッシュ | [`HASH_SIZE`] |
+//!
+//! 二分木の中間ノードは左右の子のハッシュ値を [`Hash::combine()`] で連結したハッシュ値で、奇数個のノードが並ぶ段の
+//! 右端のノードはそのまま上の段に持ち越されます。
+//!
+use std::collections::BTreeMap;
+
+use crate::error::Detail;
+use crate::{Hash, Index, Node, Result, Storage, ValuesWithBranches, HASH_SIZE, INDEX_BYTES, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// 名前を付けた複数の LMTHT をまとめて 1 つのルートハッシュで表すフォレストです。
+pub struct Forest<S: Storage> {
+  members: BTreeMap<String, LMTHT<S>>,
+}
+
+/// フォレストの二分木でメンバーのルートからフォレストのルートまでの経路から分岐したノードです。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForestBranch {
+  /// 分岐したノードのハッシュ値。
+  pub hash: Hash,
+  /// 分岐したノードが経路の左側にある場合は true。
+  pub left: bool,
+}
+
+/// [`Forest::prove()`] で取得した、メンバーの値がフォレストのルートに含まれていることの証明です。
+#[derive(Debug)]
+pub struct ForestProof {
+  /// 値を含むメンバーの名前。
+  pub name: String,
+  /// 証明の対象となるメンバーの木構造の世代。
+  pub n: Index,
+  /// メンバーの世代 `n` の木構造に値が含まれていることの証明。
+  pub proof: ValuesWithBranches,
+  /// メンバーのルートからフォレストのルートまでの経路から分岐したノード (葉に近い順)。
+  pub path: Vec<ForestBranch>,
+}
+
+impl<S: Storage> Forest<S> {
+  /// メンバーを持たないフォレストを作成します。
+  pub fn new() -> Forest<S> {
+    Forest { members: BTreeMap::new() }
+  }
+
+  /// 名前 `name` のメンバーとして LMTHT を追加します。同じ名前のメンバーがすでに存在する場合は置き換え、以前の
+  /// LMTHT を返します。
+  pub fn insert(&mut self, name: &str, db: LMTHT<S>) -> Option<LMTHT<S>> {
+    self.members.insert(name.to_string(), db)
+  }
+
+  /// 名前 `name` のメンバーをフォレストから取り除いて返します。
+  pub fn remove(&mut self, name: &str) -> Option<LMTHT<S>> {
+    self.members.remove(name)
+  }
+
+  /// 名前 `name` のメンバーを参照します。
+  pub fn get(&self, name: &str) -> Option<&LMTHT<S>> {
+    self.members.get(name)
+  }
+
+  /// メンバーの名前をフォレストのルートに集約される順に返します。
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.members.keys().map(|name| name.as_str())
+  }
+
+  /// メンバーの数を返します。
+  pub fn len(&self) -> usize {
+    self.members.len()
+  }
+
+  /// メンバーが存在しない場合に true を返します。
+  pub fn is_empty(&self) -> bool {
+    self.members.is_empty()
+  }
+
+  /// すべてのメンバーの現在のルートを集約したフォレストのルートハッシュを算出します。メンバーが存在しない場合は
+  /// `None` を返します。
+  pub fn root_hash(&self) -> Option<Hash> {
+    let leaves = self.members.iter().map(|(name, db)| leaf_hash(name, db.root())).collect::<Vec<_>>();
+    fold(leaves)
+  }
+
+  /// メンバー `name` のインデックス `i` の値について、メンバーの木構造の証明とフォレストのルートまでの経路を返し
+  /// ます。証明はすべてのメンバーの同じ時点の世代に対して作成されるため、並行して値が追加されていても
+  /// [`ForestProof::root()`] はその時点のフォレストのルートハッシュと一致します。メンバーが存在しない場合や範囲外の
+  /// インデックスを指定した場合は `None` を返します。
+  pub fn prove(&self, name: &str, i: Index) -> Result<Option<ForestProof>> {
+    let mut queries = Vec::with_capacity(self.members.len());
+    for (member, db) in self.members.iter() {
+      queries.push((member, db.query()?));
+    }
+    let position = match queries.iter().position(|(member, _)| member.as_str() == name) {
+      Some(position) => position,
+      None => return Ok(None),
+    };
+    let leaves = queries.iter().map(|(member, query)| leaf_hash(member, query.gen.root())).collect::<Vec<_>>();
+    let query = &mut queries[position].1;
+    let n = query.n();
+    let proof = match query.get_with_hashes(i)? {
+      Some(proof) => proof,
+      None => return Ok(None),
+    };
+    Ok(Some(ForestProof { name: name.to_string(), n, proof, path: path(leaves, position) }))
+  }
+}
+
+impl<S: Storage> Default for Forest<S> {
+  fn default() -> Self {
+    Forest::new()
+  }
+}
+
+impl ForestProof {
+  /// この証明から得られるフォレストのルートハッシュを算出します。
+  pub fn root(&self) -> Hash {
+    let mut hash = leaf_hash(&self.name, Some(self.proof.root()));
+    for branch in self.path.iter() {
+      hash = if branch.left { branch.hash.combine(&hash) } else { hash.combine(&branch.hash) };
+    }
+    hash
+  }
+
+  /// この証明の値がメンバーの世代 `n` の木構造に含まれ、そのメンバーのルートがルートハッシュ `root` のフォレストに
+  /// 含まれていることを検証します。
+  ///
+  /// # Errors
+  /// 検証に失敗した場合は [`Detail::ForestVerificationFailed`] を返します。
+  pub fn verify(&self, root: &Hash) -> Result<()> {
+    if self.proof.root().i != self.n || self.root() != *root {
+      return Err(Detail::ForestVerificationFailed { name: self.name.clone() });
+    }
+    Ok(())
+  }
+}
+
+/// メンバー `name` のルート `root` を表すフォレストの葉のハッシュ値を算出します。
+fn leaf_hash(name: &str, root: Option<Node>) -> Hash {
+  let mut bytes = Vec::with_capacity(4 + name.len() + INDEX_BYTES + HASH_SIZE);
+  bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+  bytes.extend_from_slice(name.as_bytes());
+  match root {
+    Some(root) => {
+      bytes.extend_from_slice(&root.i.to_le_bytes());
+      bytes.extend_from_slice(&root.hash.value);
+    }
+    None => bytes.resize(bytes.len() + INDEX_BYTES + HASH_SIZE, 0),
+  }
+  Hash::hash(&bytes)
+}
+
+/// 葉のハッシュ値から二分木のルートハッシュを算出します。
+fn fold(mut hashes: Vec<Hash>) -> Option<Hash> {
+  while hashes.len() > 1 {
+    hashes = parents(&hashes);
+  }
+  hashes.pop()
+}
+
+/// 葉 `position` から二分木のルートまでの経路から分岐したノードを葉に近い順に返します。
+fn path(mut hashes: Vec<Hash>, mut position: usize) -> Vec<ForestBranch> {
+  let mut path = Vec::new();
+  while hashes.len() > 1 {
+    let sibling = position ^ 1;
+    if sibling < hashes.len() {
+      path.push(ForestBranch { hash: hashes[sibling], left: sibling < position });
+    }
+    hashes = parents(&hashes);
+    position /= 2;
+  }
+  path
+}
+
+/// 二分木の 1 つの段のノードから上の段のノードを算出します。
+fn parents(hashes: &[Hash]) -> Vec<Hash> {
+  hashes.chunks(2).map(|pair| if pair.len() == 2 { pair[0].combine(&pair[1]) } else { pair[0] }).collect()
+}
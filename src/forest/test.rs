@@ -0,0 +1,42 @@
+use crate::error::Detail;
+use crate::*;
+
+/// フォレストのルートハッシュがすべてのメンバーのルートを集約し、メンバーの値をフォレストのルートから検証できる
+/// ことを検証します。
+#[test]
+fn test_forest() {
+  use crate::forest::Forest;
+  let mut forest = Forest::new();
+  assert_eq!(None, forest.root_hash());
+  for (k, name) in ["tenant-c", "tenant-a", "tenant-e", "tenant-b", "tenant-d"].iter().enumerate() {
+    let db = LMTHT::new(MemStorage::new()).unwrap();
+    for i in 0..k * 3 {
+      db.append(format!("{}-{}", name, i).as_bytes()).unwrap();
+    }
+    forest.insert(name, db);
+  }
+  assert_eq!(vec!["tenant-a", "tenant-b", "tenant-c", "tenant-d", "tenant-e"], forest.names().collect::<Vec<_>>());
+  let root = forest.root_hash().unwrap();
+
+  for name in ["tenant-a", "tenant-b", "tenant-c", "tenant-d", "tenant-e"] {
+    let n = forest.get(name).unwrap().n();
+    for i in 1..=n {
+      let proof = forest.prove(name, i).unwrap().unwrap();
+      assert_eq!((n, format!("{}-{}", name, i - 1).into_bytes()), (proof.n, proof.proof.values[0].value.clone()));
+      proof.verify(&root).unwrap();
+    }
+  }
+  assert!(forest.prove("tenant-c", 0).unwrap().is_none());
+  assert!(forest.prove("unknown", 1).unwrap().is_none());
+
+  // メンバーへの追加はフォレストのルートを変更し、古いルートでは検証できない
+  let mut proof = forest.prove("tenant-b", 1).unwrap().unwrap();
+  forest.get("tenant-a").unwrap().append(b"late").unwrap();
+  let new_root = forest.root_hash().unwrap();
+  assert_ne!(root, new_root);
+  proof.verify(&root).unwrap();
+  assert!(matches!(proof.verify(&new_root), Err(Detail::ForestVerificationFailed { .. })));
+  forest.prove("tenant-b", 1).unwrap().unwrap().verify(&new_root).unwrap();
+  proof.name = "tenant-a".to_string();
+  assert!(proof.verify(&root).is_err());
+}
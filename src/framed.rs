@@ -0,0 +1,148 @@
+//! ログファイルへの書き込みを LMTHT への追加に置き換えるための [`std::io::Write`] アダプタです。
+//!
+//! [`RecordWriter`] は書き込まれたバイト列を [`Framing`] で指定した境界でフレームに分割し、完成したフレームを
+//! 1 つのエントリとして追加します。ログファイルに書き込んでいる既存のコード (`writeln!()` やロガーの出力先など) は
+//! 出力先を [`RecordWriter`] に差し替えるだけで LMTHT に記録することができます。1 回の `write()` で複数のフレームが
+//! 完成した場合は 1 つのバッチとして追加されます。
+//!
+//! ```rust,no_run
+//! use std::io::Write;
+//!
+//! use lmtht::framed::{Framing, RecordWriter};
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let db = LMTHT::new("audit.db")?;
+//! let user = "alice";
+//! let mut writer = RecordWriter::new(&db, Framing::Delimited(b'\n'));
+//! writeln!(writer, "user {} logged in", user)?;
+//! writer.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io;
+
+use crate::error::Detail;
+use crate::{Node, Result, Storage, LMTHT, MAX_PAYLOAD_SIZE};
+
+#[cfg(test)]
+mod test;
+
+/// [`RecordWriter`] が書き込まれたバイト列をフレームに分割する境界です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+  /// 各フレームの先頭にフレームのバイト長 (u32 リトルエンディアン) が付加されています。
+  LengthPrefixed,
+  /// 各フレームは指定されたバイトで終端されています。終端のバイトはエントリに含まれません。
+  Delimited(u8),
+}
+
+/// 書き込まれたバイト列をフレームごとにエントリとして追加する [`std::io::Write`] の実装です。
+pub struct RecordWriter<'a, S: Storage> {
+  db: &'a LMTHT<S>,
+  framing: Framing,
+  /// まだ完成していないフレーム。
+  buffer: Vec<u8>,
+  /// 最後にフレームを追加した後の木構造のルートノード。
+  root: Option<Node>,
+}
+
+impl<'a, S: Storage> RecordWriter<'a, S> {
+  /// 指定された LMTHT にフレームを追加するライターを作成します。
+  pub fn new(db: &'a LMTHT<S>, framing: Framing) -> RecordWriter<'a, S> {
+    RecordWriter { db, framing, buffer: Vec::new(), root: None }
+  }
+
+  /// このライターが最後にフレームを追加した後の木構造のルートノードを参照します。
+  pub fn root(&self) -> Option<Node> {
+    self.root
+  }
+
+  /// 書き込まれたがまだ完成していないフレームのバイト数を返します。
+  pub fn pending(&self) -> usize {
+    self.buffer.len()
+  }
+
+  /// 書き込みを終了し、最後にフレームを追加した後の木構造のルートノードを返します。[`Framing::Delimited`] の場合、
+  /// 終端されていない末尾のバイト列は 1 つのフレームとして追加されます。
+  ///
+  /// # Errors
+  /// [`Framing::LengthPrefixed`] で途中までしか書き込まれていないフレームが残っている場合は
+  /// [`Detail::IncompleteFrame`] を返します。
+  pub fn finish(mut self) -> Result<Option<Node>> {
+    if !self.buffer.is_empty() {
+      match self.framing {
+        Framing::LengthPrefixed => return Err(Detail::IncompleteFrame { length: self.buffer.len() }),
+        Framing::Delimited(_) => self.root = Some(self.db.append(&self.buffer)?),
+      }
+    }
+    Ok(self.root)
+  }
+
+  /// バッファから完成したフレームを取り出して追加します。
+  fn append_frames(&mut self) -> Result<()> {
+    let mut frames = Vec::new();
+    let mut start = 0;
+    loop {
+      let rest = &self.buffer[start..];
+      let frame = match self.framing {
+        Framing::LengthPrefixed if rest.len() >= 4 => {
+          let length = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+          if length > MAX_PAYLOAD_SIZE {
+            return Err(Detail::FrameTooLarge { length });
+          }
+          if rest.len() < 4 + length {
+            break;
+          }
+          start += 4 + length;
+          (start - length, start)
+        }
+        Framing::Delimited(delimiter) => match rest.iter().position(|b| *b == delimiter) {
+          Some(length) => {
+            start += length + 1;
+            (start - length - 1, start - 1)
+          }
+          None => break,
+        },
+        _ => break,
+      };
+      frames.push(frame);
+    }
+    if frames.is_empty() {
+      return Ok(());
+    }
+    let mut batch = self.db.begin_batch()?;
+    for (begin, end) in frames {
+      batch.append(&self.buffer[begin..end])?;
+    }
+    self.root = batch.commit()?;
+    self.buffer.drain(..start);
+    Ok(())
+  }
+}
+
+impl<'a, S: Storage> io::Write for RecordWriter<'a, S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.buffer.extend_from_slice(buf);
+    match self.append_frames() {
+      Ok(()) => Ok(buf.len()),
+      Err(err) => {
+        // 追加できなかったバイト列は書き込まれなかったものとする
+        self.buffer.truncate(self.buffer.len() - buf.len());
+        Err(into_io_error(err))
+      }
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn into_io_error(err: Detail) -> io::Error {
+  match err {
+    Detail::Io { source } => source,
+    err => io::Error::other(err.to_string()),
+  }
+}
@@ -0,0 +1,41 @@
+use std::io::Write;
+
+use crate::error::Detail;
+use crate::*;
+
+/// io::Write として書き込んだバイト列がフレームの境界で分割され、それぞれがエントリとして追加されることを検証
+/// します。
+#[test]
+fn test_record_writer() {
+  use crate::framed::{Framing, RecordWriter};
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let mut writer = RecordWriter::new(&db, Framing::Delimited(b'\n'));
+  writeln!(writer, "first").unwrap();
+  writer.write_all(b"sec").unwrap();
+  assert_eq!((1, 3), (db.n(), writer.pending()));
+  writer.write_all(b"ond\nthird\n\nlast").unwrap();
+  assert_eq!(4, db.n());
+  let root = writer.finish().unwrap().unwrap();
+  assert_eq!(db.root().unwrap(), root);
+  let mut query = db.query().unwrap();
+  let values = (1..=5).map(|i| query.get(i).unwrap().unwrap()).collect::<Vec<_>>();
+  assert_eq!(vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec(), vec![], b"last".to_vec()], values);
+
+  // 長さ付きのフレームは 1 バイトずつ書き込まれても正しく分割される
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let mut writer = RecordWriter::new(&db, Framing::LengthPrefixed);
+  let mut stream = Vec::new();
+  for value in [&b"ab"[..], b"", b"c\nd"] {
+    stream.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    stream.extend_from_slice(value);
+  }
+  for b in stream.iter() {
+    writer.write_all(&[*b]).unwrap();
+  }
+  writer.write_all(&[9, 0, 0]).unwrap();
+  assert_eq!(Some(b"c\nd".to_vec()), db.query().unwrap().get(3).unwrap());
+  assert!(matches!(writer.finish(), Err(Detail::IncompleteFrame { length: 3 })));
+  let mut writer = RecordWriter::new(&db, Framing::LengthPrefixed);
+  assert!(writer.write_all(&[0xFF; 4]).is_err());
+  assert_eq!((3, 0), (db.n(), writer.pending()));
+}
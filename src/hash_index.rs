@@ -0,0 +1,202 @@
+//! 値のハッシュ値からその値を持つエントリのインデックスを参照するためのハッシュ索引です。
+//!
+//! ハッシュ索引は i 番目のエントリの葉ノードのハッシュ値を `(i - 1) * HASH_SIZE` バイト目に記録した固定長レコードの
+//! 列で、[`LMTHTOptions::hash_index`](crate::LMTHTOptions::hash_index) に指定したサイドカーのストレージに保存され
+//! ます。オープン時にメモリ上の表として読み込まれ、[`Query::find_by_hash()`] は値の内容だけを知っている利用者が
+//! その値がハッシュ木に含まれているか、含まれているならどのインデックスかをストレージを読み込まずに判定します。
+//!
+//! ハッシュ索引はハッシュ木から再構築可能な補助情報です。オープン時に欠損や不整合を検出した場合は自動的に再構築され
+//! ます。
+//!
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::error::Detail;
+use crate::{
+  lock2io, read_entry_header, Cursor, DynStorage, Hash, Index, Node, Query, Result, Storage, HASH_SIZE, LMTHT,
+};
+
+#[cfg(test)]
+mod test;
+
+/// 追加する値と同じ値がすでにハッシュ木に含まれている場合の扱いを指定します。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+  /// 重複を検査せずに追加します。
+  Allow,
+  /// 重複していても追加し、[`LMTHT::append_dedup()`] は既存のインデックスを返します。
+  Flag,
+  /// 重複している値は追加せず、[`LMTHT::append()`] や [`Batch::append()`](crate::Batch::append) は現在のルート
+  /// ノードを、[`LMTHT::append_dedup()`] は既存のインデックスを返します。同じバッチ内で重複する値も追加されません。
+  Skip,
+}
+
+/// [`LMTHT::append_dedup()`] の結果です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppendOutcome {
+  /// 追加後の木構造のルートノード。値を追加しなかった場合は現在のルートノードです。
+  pub root: Node,
+  /// 追加した値のインデックス。[`DuplicatePolicy::Skip`] によって追加しなかった場合は `None` です。
+  pub i: Option<Index>,
+  /// 同じ値を持つ既存のエントリのうち最初のもののインデックス。重複していない場合は `None` です。
+  pub duplicate_of: Option<Index>,
+}
+
+/// サイドカーストレージに保存されたハッシュ索引です。
+pub(crate) struct HashIndex {
+  storage: Arc<dyn DynStorage + Send + Sync>,
+  state: Mutex<State>,
+}
+
+/// メモリ上に読み込んだハッシュ索引。
+#[derive(Default)]
+struct State {
+  /// 葉ノードのハッシュ値とその値を持つエントリのインデックスの昇順の列。
+  hashes: HashMap<[u8; HASH_SIZE], Vec<Index>>,
+  /// 索引に反映済みの最後のエントリのインデックス。
+  indexed: Index,
+  /// 索引に反映済みの最後のエントリのハッシュ値。
+  last: Option<[u8; HASH_SIZE]>,
+}
+
+impl HashIndex {
+  /// 指定されたストレージに記録されているハッシュ索引を読み込みます。書き込み途中で中断した末尾のレコードは無視
+  /// されます。
+  pub fn open(storage: Arc<dyn DynStorage + Send + Sync>) -> Result<HashIndex> {
+    let mut bytes = Vec::new();
+    storage.open_dyn(false)?.read_to_end(&mut bytes)?;
+    let mut state = State::default();
+    for record in bytes.chunks_exact(HASH_SIZE) {
+      let hash: [u8; HASH_SIZE] = record.try_into().unwrap();
+      state.indexed += 1;
+      state.hashes.entry(hash).or_default().push(state.indexed);
+      state.last = Some(hash);
+    }
+    Ok(HashIndex { storage, state: Mutex::new(state) })
+  }
+
+  /// ハッシュ索引が `query` の世代のすべてのエントリを反映していることを確認します。索引に記録されている最後の
+  /// エントリのハッシュ値がハッシュ木と一致しない場合はすべてのエントリから再構築し、未反映のエントリは葉ノードの
+  /// ハッシュ値を読み込んで索引に追加します。
+  #[allow(clippy::unnecessary_cast)] // Index は feature によって u64 以外の型となる
+  pub fn prepare<C: Cursor>(&self, query: &mut Query<C>) -> Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    let n = query.n();
+    let consistent = match state.last {
+      Some(last) if state.indexed <= n => read_hash(query, state.indexed)?.value == last,
+      Some(_) => false,
+      None => true,
+    };
+    if !consistent {
+      let mut cursor = self.storage.open_dyn(true)?;
+      cursor.set_len(0)?;
+      cursor.flush()?;
+      *state = State::default();
+    }
+
+    // 未反映のエントリの葉ノードのハッシュ値を読み込む
+    if state.indexed < n {
+      let mut cursor = self.storage.open_dyn(true)?;
+      cursor.set_len(state.indexed as u64 * HASH_SIZE as u64)?;
+      cursor.seek(SeekFrom::End(0))?;
+      scan_leaf_hashes(query, state.indexed + 1, n, |i, hash| {
+        cursor.write_all(&hash.value)?;
+        state.hashes.entry(hash.value).or_default().push(i);
+        state.indexed = i;
+        state.last = Some(hash.value);
+        Ok(())
+      })?;
+      cursor.flush()?;
+    }
+    Ok(())
+  }
+
+  /// i 番目のエントリの葉ノードのハッシュ値を索引に追加します。
+  #[allow(clippy::unnecessary_cast)] // Index は feature によって u64 以外の型となる
+  pub fn append(&self, i: Index, hash: &Hash) -> Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    let mut cursor = self.storage.open_dyn(true)?;
+    cursor.seek(SeekFrom::Start((i - 1) as u64 * HASH_SIZE as u64))?;
+    cursor.write_all(&hash.value)?;
+    cursor.flush()?;
+    state.hashes.entry(hash.value).or_default().push(i);
+    state.indexed = i;
+    state.last = Some(hash.value);
+    Ok(())
+  }
+
+  /// ハッシュ値 `hash` を持つエントリのうち世代 `n` に含まれるもののインデックスを昇順で返します。
+  pub fn find(&self, hash: &Hash, n: Index) -> Result<Vec<Index>> {
+    let state = lock2io(self.state.lock())?;
+    let indices = state.hashes.get(&hash.value).map(|indices| indices.as_slice()).unwrap_or(&[]);
+    Ok(indices.iter().copied().take_while(|i| *i <= n).collect())
+  }
+}
+
+/// ハッシュ木から i 番目のエントリの葉ノードのハッシュ値を読み込みます。
+pub(crate) fn read_hash<C: Cursor>(query: &mut Query<C>, i: Index) -> Result<Hash> {
+  let position = match query.entry_position(i)? {
+    Some(position) => position,
+    None => return Err(Detail::DamagedStorage(format!("the entry {} is not found", i))),
+  };
+  query.cursor.seek(SeekFrom::Start(position))?;
+  Ok(read_entry_header(&mut query.cursor, position, i)?.0.hash)
+}
+
+/// `from` から `to` までのエントリの葉ノードのハッシュ値をペイロードを読み込まずに順に読み込み、`f` に渡します。
+pub(crate) fn scan_leaf_hashes<C, F>(query: &mut Query<C>, from: Index, to: Index, mut f: F) -> Result<()>
+where
+  C: Cursor,
+  F: FnMut(Index, Hash) -> Result<()>,
+{
+  let mut position = match query.entry_position(from)? {
+    Some(position) if from <= to => position,
+    _ => return Ok(()),
+  };
+  for i in from..=to {
+    query.cursor.seek(SeekFrom::Start(position))?;
+    let (meta, _) = read_entry_header(&mut query.cursor, position, i)?;
+    position = query.cursor.seek(SeekFrom::Current(4 /* offset */ + 8 /* checksum */))?;
+    f(i, meta.hash)?;
+  }
+  Ok(())
+}
+
+impl<S: Storage> LMTHT<S> {
+  /// [`LMTHTOptions::duplicates`](crate::LMTHTOptions::duplicates) に従って値の重複を検査して追加します。重複の検査と
+  /// 追加は追加のためのロックを保持したまま行われるため、並行して追加された同じ値を見落とすことはありません。
+  /// [`DuplicatePolicy::Allow`] の場合は重複を検査せずに追加します。
+  pub fn append_dedup(&self, value: &[u8]) -> Result<AppendOutcome> {
+    let mut batch = self.begin_batch()?;
+    let outcome = batch.append_dedup(value)?;
+    batch.commit()?;
+    Ok(outcome)
+  }
+}
+
+impl<C: Cursor> Query<C> {
+  /// 葉ノードのハッシュ値が `hash` であるエントリのうちこのクエリーの世代に含まれるもののインデックスを昇順で返し
+  /// ます。`hash` は値から [`Hash::hash()`] で算出します。同じ値が複数回追加されている場合はそのすべてのインデックス
+  /// を返し、含まれていない場合は空の列を返します。返されたインデックスの証明を [`Query::get_with_hashes()`] で取得
+  /// することで、値がハッシュ木に含まれていることを検証することができます。
+  ///
+  /// # Errors
+  /// [`LMTHTOptions::hash_index`](crate::LMTHTOptions::hash_index) が指定されていない場合は [`Detail::NoHashIndex`]
+  /// を返します。
+  pub fn find_by_hash(&self, hash: &Hash) -> Result<Vec<Index>> {
+    match &self.hashes {
+      Some(hashes) => {
+        // ブルームフィルタが含まれていないと判定した値は索引を参照しない
+        if let Some(bloom) = &self.bloom {
+          if !bloom.contains(hash)? {
+            return Ok(Vec::new());
+          }
+        }
+        hashes.find(hash, self.n())
+      }
+      None => Err(Detail::NoHashIndex),
+    }
+  }
+}
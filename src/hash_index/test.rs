@@ -0,0 +1,126 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::*;
+
+/// 値のハッシュ値からその値を持つエントリのインデックスを参照でき、ハッシュ索引がハッシュ木から再構築されることを
+/// 検証します。
+#[test]
+fn test_find_by_hash() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let hashes = Arc::new(RwLock::new(Vec::new()));
+  let open = |hashes: &Arc<RwLock<Vec<u8>>>| {
+    let storage = Arc::new(MemStorage::with(hashes.clone()));
+    LMTHT::builder(MemStorage::with(buffer.clone())).hash_index(storage).open().unwrap()
+  };
+  let db = open(&hashes);
+  for i in 1..=20u32 {
+    db.append(format!("value-{}", i % 15).as_bytes()).unwrap();
+  }
+  let query = db.query().unwrap();
+  assert_eq!(vec![3, 18], query.find_by_hash(&Hash::hash(b"value-3")).unwrap());
+  assert_eq!(vec![10], query.find_by_hash(&Hash::hash(b"value-10")).unwrap());
+  assert!(query.find_by_hash(&Hash::hash(b"value-15")).unwrap().is_empty());
+
+  // 古い世代のクエリーはその世代に含まれるインデックスのみを返す
+  db.append(b"value-10").unwrap();
+  assert_eq!(vec![10], query.find_by_hash(&Hash::hash(b"value-10")).unwrap());
+  let mut query = db.query().unwrap();
+  assert_eq!(vec![10, 21], query.find_by_hash(&Hash::hash(b"value-10")).unwrap());
+  let values = query.get_with_hashes(21).unwrap().unwrap();
+  assert_eq!(db.root().unwrap().hash, values.root().hash);
+  drop(query);
+  drop(db);
+
+  // 末尾が欠けた索引、失われた索引、ハッシュ木と一致しない索引は再構築される
+  let length = hashes.read().unwrap().len();
+  hashes.write().unwrap().truncate(length - HASH_SIZE - 1);
+  let db = open(&hashes);
+  assert_eq!(vec![10, 21], db.query().unwrap().find_by_hash(&Hash::hash(b"value-10")).unwrap());
+  drop(db);
+  assert_eq!(21 * HASH_SIZE, hashes.read().unwrap().len());
+  let db = open(&Arc::new(RwLock::new(Vec::new())));
+  assert_eq!(vec![3, 18], db.query().unwrap().find_by_hash(&Hash::hash(b"value-3")).unwrap());
+  drop(db);
+  let length = hashes.read().unwrap().len();
+  hashes.write().unwrap()[length - 1] ^= 0xFF;
+  let db = open(&hashes);
+  assert_eq!(vec![10, 21], db.query().unwrap().find_by_hash(&Hash::hash(b"value-10")).unwrap());
+  drop(db);
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  assert!(matches!(db.query().unwrap().find_by_hash(&Hash::hash(b"value-3")), Err(Detail::NoHashIndex)));
+}
+
+/// 追加する値の重複を検出し、指定された扱いに従って既存のインデックスを返すか追加を省略することを検証します。
+#[test]
+fn test_duplicate_payloads() {
+  use crate::{AppendOutcome, DuplicatePolicy};
+  let open = |duplicates: DuplicatePolicy| {
+    let hashes = Arc::new(MemStorage::new());
+    LMTHT::builder(MemStorage::new()).hash_index(hashes).duplicates(duplicates).open().unwrap()
+  };
+
+  let db = open(DuplicatePolicy::Flag);
+  let first = db.append_dedup(b"event-1").unwrap();
+  assert_eq!((Some(1), None), (first.i, first.duplicate_of));
+  db.append_dedup(b"event-2").unwrap();
+  let again = db.append_dedup(b"event-1").unwrap();
+  assert_eq!(AppendOutcome { root: db.root().unwrap(), i: Some(3), duplicate_of: Some(1) }, again);
+  assert_eq!(3, db.n());
+
+  let db = open(DuplicatePolicy::Skip);
+  db.append(b"event-1").unwrap();
+  let root = db.append(b"event-2").unwrap();
+  assert_eq!(root, db.append(b"event-1").unwrap());
+  let skipped = db.append_dedup(b"event-2").unwrap();
+  assert_eq!(AppendOutcome { root, i: None, duplicate_of: Some(2) }, skipped);
+  assert_eq!(2, db.n());
+  assert_eq!(Some(3), db.append_dedup(b"event-3").unwrap().i);
+
+  let db = open(DuplicatePolicy::Allow);
+  db.append(b"event-1").unwrap();
+  assert_eq!(None, db.append_dedup(b"event-1").unwrap().duplicate_of);
+  assert_eq!(2, db.n());
+
+  let result = LMTHT::builder(MemStorage::new()).duplicates(DuplicatePolicy::Skip).open();
+  assert!(matches!(result, Err(Detail::NoHashIndex)));
+}
+
+/// [`DuplicatePolicy::Skip`] がバッチや一括追加、準備した追加でも適用され、同じバッチ内の重複も追加されないことを
+/// 検証します。
+#[test]
+fn test_duplicate_payloads_in_batch() {
+  let open = || {
+    let hashes = Arc::new(MemStorage::new());
+    LMTHT::builder(MemStorage::new()).hash_index(hashes).duplicates(DuplicatePolicy::Skip).open().unwrap()
+  };
+
+  let db = open();
+  db.try_extend(["x", "x", "x"]).unwrap();
+  assert_eq!(1, db.n());
+
+  // コミット済みの値とバッチ内で追加済みの値のいずれとも重複する値は追加されない
+  let mut batch = db.begin_batch().unwrap();
+  let root = batch.append(b"y").unwrap();
+  assert_eq!(root, batch.append(b"y").unwrap());
+  let outcome = batch.append_dedup(b"x").unwrap();
+  assert_eq!(AppendOutcome { root, i: None, duplicate_of: Some(1) }, outcome);
+  assert_eq!(Some(2), batch.append_dedup(b"y").unwrap().duplicate_of);
+  assert_eq!(1, batch.len());
+  batch.commit().unwrap();
+  assert_eq!(2, db.n());
+
+  let prepared = db.prepare_append(b"y").unwrap();
+  assert_eq!(2, prepared.n());
+  db.commit(prepared).unwrap();
+  let root = db.append(b"z").unwrap();
+  assert_eq!(3, root.i);
+  assert_eq!(vec![2], db.query().unwrap().find_by_hash(&Hash::hash(b"y")).unwrap());
+
+  // 破棄したバッチの値は重複として扱われない
+  let mut batch = db.begin_batch().unwrap();
+  batch.append(b"w").unwrap();
+  drop(batch);
+  assert_eq!(4, db.append(b"w").unwrap().i);
+}
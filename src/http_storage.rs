@@ -0,0 +1,175 @@
+//! 静的ファイルのホストに公開された LMTHT を HTTP の `Range` 要求で参照する読み込み専用のストレージです。
+//!
+//! 読み込みはカーソルの位置から [`HttpStorage::with_fetch_size()`] で指定したバイト数をまとめて要求し、直前に受信した
+//! 範囲をカーソルに保持します。ファイル全体をダウンロードすることなく、監査人が公開されたハッシュ木から値の参照や
+//! 証明の生成を行うことができます。ストレージの長さは末尾からのシークのたびに `HEAD` 要求の `Content-Length` から
+//! 取得するため、[`LMTHT::reload()`](crate::LMTHT::reload) によって公開後に追加されたエントリを取り込むことも
+//! できます。
+//!
+//! 書き込み用のカーソルはオープンできないため [`LMTHTOptions::read_only`](crate::LMTHTOptions::read_only) を指定して
+//! 使用します。
+//!
+//! ```rust,no_run
+//! use lmtht::http_storage::HttpStorage;
+//! use lmtht::{LMTHTOptions, LMTHT};
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let options = LMTHTOptions { read_only: true, ..Default::default() };
+//! let db = LMTHT::with_options(HttpStorage::new("https://example.com/audit.lmtht"), options)?;
+//! let proof = db.query()?.get_values_with_hashes(1, 0)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::cmp::min;
+use std::io;
+use std::io::Read;
+
+use ureq::Agent;
+
+use crate::error::Detail;
+use crate::{Capabilities, Cursor, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// [`HttpStorage`] が 1 回の要求で受信するバイトサイズのデフォルト値です。
+pub const DEFAULT_HTTP_FETCH_SIZE: usize = 64 * 1024;
+
+/// HTTP の `Range` 要求でバイト列を参照する読み込み専用のストレージです。
+pub struct HttpStorage {
+  agent: Agent,
+  url: String,
+  fetch_size: usize,
+}
+
+impl HttpStorage {
+  /// 指定された URL のファイルを参照するストレージを構築します。
+  pub fn new(url: &str) -> HttpStorage {
+    Self::with_fetch_size(url, DEFAULT_HTTP_FETCH_SIZE)
+  }
+
+  /// 1 回の要求で `fetch_size` バイトを受信するストレージを構築します。
+  pub fn with_fetch_size(url: &str, fetch_size: usize) -> HttpStorage {
+    assert!(fetch_size > 0);
+    HttpStorage { agent: Agent::new(), url: url.to_string(), fetch_size }
+  }
+
+  /// このストレージが参照する URL です。
+  pub fn url(&self) -> &str {
+    &self.url
+  }
+}
+
+impl Storage for HttpStorage {
+  type Cursor = HttpCursor;
+  fn open(&self, writable: bool) -> Result<HttpCursor> {
+    if writable {
+      return Err(Detail::ReadOnly);
+    }
+    Ok(HttpCursor {
+      agent: self.agent.clone(),
+      url: self.url.clone(),
+      fetch_size: self.fetch_size,
+      position: 0,
+      fetched: None,
+    })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::READ_ONLY
+  }
+}
+
+/// [`HttpStorage`] が使用するカーソルです。
+pub struct HttpCursor {
+  agent: Agent,
+  url: String,
+  fetch_size: usize,
+  position: u64,
+  /// 直前に受信した範囲の開始位置とその内容。公開されたファイルは追記のみが行われるため受信済みの範囲は変化しない。
+  fetched: Option<(u64, Vec<u8>)>,
+}
+
+impl HttpCursor {
+  /// `HEAD` 要求でファイルの長さを取得します。
+  fn content_length(&self) -> io::Result<u64> {
+    let response = self.agent.head(&self.url).call().map_err(http2io)?;
+    match response.header("Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+      Some(length) => Ok(length),
+      None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has no Content-Length", self.url))),
+    }
+  }
+
+  /// `start` から最大 `length` バイトを要求します。ファイルの終端を超える範囲は返されません。
+  fn fetch(&self, start: u64, length: usize) -> io::Result<Vec<u8>> {
+    let range = format!("bytes={}-{}", start, start + length as u64 - 1);
+    let response = match self.agent.get(&self.url).set("Range", &range).call() {
+      Ok(response) => response,
+      Err(ureq::Error::Status(416, _)) => return Ok(Vec::new()),
+      Err(err) => return Err(http2io(err)),
+    };
+    if response.status() != 206 {
+      let msg = format!("{} does not support range requests (status {})", self.url, response.status());
+      return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+    }
+    let mut bytes = Vec::with_capacity(length);
+    response.into_reader().take(length as u64).read_to_end(&mut bytes)?;
+    Ok(bytes)
+  }
+}
+
+impl Cursor for HttpCursor {}
+
+impl io::Seek for HttpCursor {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      io::SeekFrom::Start(position) => (position, 0),
+      io::SeekFrom::End(offset) => (self.content_length()?, offset),
+      io::SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl io::Read for HttpCursor {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    let position = self.position;
+    let hit =
+      matches!(&self.fetched, Some((start, bytes)) if *start <= position && position < *start + bytes.len() as u64);
+    if !hit {
+      let bytes = self.fetch(position, self.fetch_size.max(buf.len()))?;
+      self.fetched = Some((position, bytes));
+    }
+    let (start, bytes) = self.fetched.as_ref().unwrap();
+    let offset = min((position - start) as usize, bytes.len());
+    let length = min(buf.len(), bytes.len() - offset);
+    buf[..length].copy_from_slice(&bytes[offset..offset + length]);
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl io::Write for HttpCursor {
+  fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+    Err(io::Error::from(io::ErrorKind::PermissionDenied))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn http2io(err: ureq::Error) -> io::Error {
+  io::Error::other(err.to_string())
+}
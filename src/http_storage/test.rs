@@ -0,0 +1,70 @@
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+use std::thread::spawn;
+
+use crate::*;
+
+/// 静的ファイルとして公開したストレージを HTTP の Range 要求のみで参照できることを検証します。
+#[cfg(feature = "http_storage")]
+#[test]
+fn test_http_storage() {
+  use http_storage::HttpStorage;
+  use std::io::{BufRead, BufReader};
+  use std::net::TcpListener;
+
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 0..50u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  let root = db.root().unwrap();
+  drop(db);
+
+  // HEAD と単一範囲の GET のみに応答する静的ファイルのホスト
+  let content = buffer.read().unwrap().clone();
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let url = format!("http://{}/audit.lmtht", listener.local_addr().unwrap());
+  spawn(move || {
+    for stream in listener.incoming() {
+      let mut stream = stream.unwrap();
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let mut request = Vec::new();
+      loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+          break;
+        }
+        request.push(line.trim().to_string());
+      }
+      let range = request.iter().find_map(|line| line.strip_prefix("Range: bytes=")).map(|range| {
+        let (start, end) = range.split_once('-').unwrap();
+        (start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap() + 1)
+      });
+      let response = match range {
+        _ if request[0].starts_with("HEAD") => {
+          format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", content.len()).into_bytes()
+        }
+        Some((start, _)) if start >= content.len() => b"HTTP/1.1 416 Range Not Satisfiable\r\n\r\n".to_vec(),
+        Some((start, end)) => {
+          let (len, end) = (content.len(), end.min(content.len()));
+          let header =
+            format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", start, end - 1, len);
+          [header.as_bytes(), &content[start..end]].concat()
+        }
+        None => b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec(),
+      };
+      stream.write_all(&response).unwrap();
+    }
+  });
+
+  let storage = HttpStorage::with_fetch_size(&url, 256);
+  assert!(storage.open(true).is_err());
+  let options = LMTHTOptions { read_only: true, ..Default::default() };
+  let db = LMTHT::with_options(storage, options).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..50u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+}
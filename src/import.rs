@@ -0,0 +1,427 @@
+//! 既存のデータセットを LMTHT に一括して追加するためのインポータです。
+//!
+//! インポータは入力を先頭から順に読み込み、[`LMTHT::begin_batch()`] のバッチに一定数ずつまとめて追加します。
+//! バッチをコミットするたびに進捗 [`Progress`] を通知するため、大きなデータセットの初期ロードの状況を表示することが
+//! できます。バッチの途中で中断した場合、最後にコミットしたバッチまでの値が追加された状態となります。
+//!
+//! 入力形式として NDJSON ([`ndjson()`])、CSV ([`csv()`])、長さ付きのレコードファイル ([`length_prefixed()`]) を読み込む
+//! ことができます。
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let db = LMTHT::new("audit.db")?;
+//! let progress = lmtht::import::ndjson(BufReader::new(File::open("audit.ndjson")?), &db)?;
+//! println!("{} entries, root = {}", progress.entries, progress.root.unwrap());
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io::{BufRead, Read};
+
+use crate::error::Detail;
+use crate::{Batch, Hash, Node, Result, Storage, LMTHT, MAX_PAYLOAD_SIZE};
+
+#[cfg(test)]
+mod test;
+
+/// 1 つのバッチにまとめて追加する値の数のデフォルト値です。
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// インポートの進捗です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+  /// 読み込んだ行の数。長さ付きのレコードファイルではレコードの数です。
+  pub lines: u64,
+  /// 追加した値の数。
+  pub entries: u64,
+  /// 最後にコミットしたバッチの後の木構造のルートノード。値を追加していない場合は LMTHT の現在のルートノードです。
+  pub root: Option<Node>,
+}
+
+/// NDJSON (改行区切りの JSON) の各行を 1 つの値として追加し、最終的な進捗を返します。行の前後の空白と改行文字は
+/// 取り除かれ、空行は無視されます。行の内容は JSON として解析せずにそのまま保存されます。
+///
+/// # Errors
+/// UTF-8 として正しくない行を検出した場合は [`Detail::InvalidImportLine`] を返します。
+pub fn ndjson<R: BufRead, S: Storage>(reader: R, db: &LMTHT<S>) -> Result<Progress> {
+  ndjson_with(reader, db, DEFAULT_BATCH_SIZE, |_| ())
+}
+
+/// [`ndjson()`] と同様に NDJSON の各行を追加し、`batch_size` 個の値ごとにバッチをコミットして `progress` に進捗を
+/// 通知します。
+pub fn ndjson_with<R, S, F>(mut reader: R, db: &LMTHT<S>, batch_size: usize, progress: F) -> Result<Progress>
+where
+  R: BufRead,
+  S: Storage,
+  F: FnMut(&Progress),
+{
+  let mut importer = Importer::new(db, batch_size, progress);
+  let mut line = Vec::new();
+  loop {
+    line.clear();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+      break;
+    }
+    importer.progress.lines += 1;
+    let value = line.trim_ascii();
+    if value.is_empty() {
+      continue;
+    }
+    if let Err(err) = std::str::from_utf8(value) {
+      return Err(Detail::InvalidImportLine { line: importer.progress.lines, message: err.to_string() });
+    }
+    importer.append(value)?;
+  }
+  importer.finish()
+}
+
+/// CSV の行から値として追加する列です。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CsvColumns {
+  /// 行全体のバイト列をそのまま追加します。
+  All,
+  /// 指定された位置 (0 から始まる) の列を追加します。
+  Indices(Vec<usize>),
+  /// ヘッダ行で指定された名前の列を追加します。
+  Names(Vec<String>),
+}
+
+/// [`csv()`] の入力形式です。
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+  /// 列の区切り文字。
+  pub delimiter: u8,
+  /// 先頭の行がヘッダ行である場合は true。ヘッダ行は値として追加されません。
+  pub header: bool,
+  /// 値として追加する列。
+  pub columns: CsvColumns,
+}
+
+impl Default for CsvOptions {
+  fn default() -> Self {
+    CsvOptions { delimiter: b',', header: true, columns: CsvColumns::All }
+  }
+}
+
+/// CSV (RFC 4180) の各行を 1 つの値として追加し、最終的な進捗を返します。[`CsvColumns::All`] の場合は改行文字を
+/// 除いた行のバイト列を、列を選択した場合は選択した列のみを同じ区切り文字で連結した行を値とします。引用符で囲まれた
+/// 列は改行を含むことができます。空行は無視されます。
+///
+/// # Errors
+/// 引用符が閉じていない行や選択した列を持たない行を検出した場合は [`Detail::InvalidImportLine`] を返します。
+pub fn csv<R: BufRead, S: Storage>(reader: R, db: &LMTHT<S>, options: &CsvOptions) -> Result<Progress> {
+  csv_with(reader, db, options, DEFAULT_BATCH_SIZE, |_| ())
+}
+
+/// [`csv()`] と同様に CSV の各行を追加し、`batch_size` 個の値ごとにバッチをコミットして `progress` に進捗を通知
+/// します。
+pub fn csv_with<R, S, F>(
+  mut reader: R,
+  db: &LMTHT<S>,
+  options: &CsvOptions,
+  batch_size: usize,
+  progress: F,
+) -> Result<Progress>
+where
+  R: BufRead,
+  S: Storage,
+  F: FnMut(&Progress),
+{
+  let mut importer = Importer::new(db, batch_size, progress);
+  let mut columns = match &options.columns {
+    CsvColumns::All => None,
+    CsvColumns::Indices(indices) => Some(indices.clone()),
+    CsvColumns::Names(_) if !options.header => {
+      let message = "column names require a header row".to_string();
+      return Err(Detail::InvalidImportLine { line: 1, message });
+    }
+    CsvColumns::Names(_) => None,
+  };
+  let mut header = options.header;
+  let mut row = Vec::new();
+  loop {
+    let line = importer.progress.lines + 1;
+    let lines = read_csv_row(&mut reader, &mut row)?;
+    if lines == 0 {
+      break;
+    }
+    importer.progress.lines += lines;
+    if row.is_empty() {
+      continue;
+    }
+    let fields =
+      split_csv_row(&row, options.delimiter).map_err(|message| Detail::InvalidImportLine { line, message })?;
+
+    // ヘッダ行から選択した列の位置を決定する
+    if header {
+      header = false;
+      if let CsvColumns::Names(names) = &options.columns {
+        let mut indices = Vec::with_capacity(names.len());
+        for name in names {
+          match fields.iter().position(|field| field == name.as_bytes()) {
+            Some(index) => indices.push(index),
+            None => {
+              let message = format!("column {:?} is not found in the header", name);
+              return Err(Detail::InvalidImportLine { line, message });
+            }
+          }
+        }
+        columns = Some(indices);
+      }
+      continue;
+    }
+
+    match &columns {
+      None => importer.append(&row)?,
+      Some(indices) => {
+        let mut value = Vec::with_capacity(row.len());
+        for (k, index) in indices.iter().enumerate() {
+          let field = match fields.get(*index) {
+            Some(field) => field,
+            None => {
+              let message = format!("the row has only {} columns", fields.len());
+              return Err(Detail::InvalidImportLine { line, message });
+            }
+          };
+          if k > 0 {
+            value.push(options.delimiter);
+          }
+          write_csv_field(&mut value, field, options.delimiter);
+        }
+        importer.append(&value)?;
+      }
+    }
+  }
+  importer.finish()
+}
+
+/// 引用符で囲まれた改行を含む CSV の 1 行を改行文字を除いて `row` に読み込み、読み込んだ物理行の数を返します。
+/// 入力の終端に達した場合は 0 を返します。
+fn read_csv_row<R: BufRead>(reader: &mut R, row: &mut Vec<u8>) -> Result<u64> {
+  row.clear();
+  let mut lines = 0;
+  while reader.read_until(b'\n', row)? > 0 {
+    lines += 1;
+    // 引用符の数が奇数の場合は引用符の中の改行
+    if row.iter().filter(|b| **b == b'"').count() % 2 == 0 {
+      break;
+    }
+  }
+  if row.ends_with(b"\n") {
+    row.pop();
+    if row.ends_with(b"\r") {
+      row.pop();
+    }
+  }
+  Ok(lines)
+}
+
+/// CSV の 1 行を列に分割し、引用符を取り除いた列の内容を返します。
+fn split_csv_row(row: &[u8], delimiter: u8) -> std::result::Result<Vec<Vec<u8>>, String> {
+  let mut fields = vec![Vec::new()];
+  let mut quoted = false;
+  let mut k = 0;
+  while k < row.len() {
+    let b = row[k];
+    let field = fields.last_mut().unwrap();
+    if quoted {
+      if b == b'"' && row.get(k + 1) == Some(&b'"') {
+        field.push(b'"');
+        k += 1;
+      } else if b == b'"' {
+        quoted = false;
+      } else {
+        field.push(b);
+      }
+    } else if b == delimiter {
+      fields.push(Vec::new());
+    } else if b == b'"' {
+      quoted = true;
+    } else {
+      field.push(b);
+    }
+    k += 1;
+  }
+  if quoted {
+    return Err("unterminated quoted field".to_string());
+  }
+  Ok(fields)
+}
+
+/// 列の内容を必要に応じて引用符で囲んで `value` に書き込みます。
+fn write_csv_field(value: &mut Vec<u8>, field: &[u8], delimiter: u8) {
+  if field.iter().any(|b| *b == delimiter || *b == b'"' || *b == b'\r' || *b == b'\n') {
+    value.push(b'"');
+    for b in field {
+      if *b == b'"' {
+        value.push(b'"');
+      }
+      value.push(*b);
+    }
+    value.push(b'"');
+  } else {
+    value.extend_from_slice(field);
+  }
+}
+
+/// [`export::length_prefixed()`](crate::export::length_prefixed) で出力した長さ付きのレコードファイルの各レコードを
+/// 1 つの値として追加し、最終的な進捗を返します。`expected_root` を指定した場合は、追加後のルートハッシュがエクス
+/// ポート時のルートハッシュと一致することを検証します。
+///
+/// # Errors
+/// 途中で終わっているレコードを検出した場合は [`Detail::InvalidImportLine`] を、追加後のルートハッシュが
+/// `expected_root` と一致しない場合は [`Detail::ImportRootMismatch`] を返します。ルートハッシュの検証はすべての値を
+/// 追加した後に行われるため、一致しない場合も値は追加された状態となります。
+pub fn length_prefixed<R: Read, S: Storage>(
+  reader: R,
+  db: &LMTHT<S>,
+  expected_root: Option<&Hash>,
+) -> Result<Progress> {
+  length_prefixed_with(reader, db, expected_root, DEFAULT_BATCH_SIZE, |_| ())
+}
+
+/// [`length_prefixed()`] と同様に各レコードを追加し、`batch_size` 個の値ごとにバッチをコミットして `progress` に
+/// 進捗を通知します。
+pub fn length_prefixed_with<R, S, F>(
+  mut reader: R,
+  db: &LMTHT<S>,
+  expected_root: Option<&Hash>,
+  batch_size: usize,
+  progress: F,
+) -> Result<Progress>
+where
+  R: Read,
+  S: Storage,
+  F: FnMut(&Progress),
+{
+  let mut importer = Importer::new(db, batch_size, progress);
+  let mut length = [0u8; 4];
+  let mut value = Vec::new();
+  loop {
+    let line = importer.progress.lines + 1;
+    match read_fully(&mut reader, &mut length)? {
+      0 => break,
+      4 => (),
+      _ => return Err(Detail::InvalidImportLine { line, message: "truncated length".to_string() }),
+    }
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_PAYLOAD_SIZE {
+      let message = format!("the length {} exceeds the maximum payload size", length);
+      return Err(Detail::InvalidImportLine { line, message });
+    }
+    value.resize(length, 0);
+    if read_fully(&mut reader, &mut value)? != length {
+      return Err(Detail::InvalidImportLine { line, message: "truncated value".to_string() });
+    }
+    importer.progress.lines = line;
+    importer.append(&value)?;
+  }
+  let progress = importer.finish()?;
+  if let Some(expected) = expected_root {
+    if progress.root.map(|root| root.hash) != Some(*expected) {
+      return Err(Detail::ImportRootMismatch { n: db.n() });
+    }
+  }
+  Ok(progress)
+}
+
+/// `buf` がいっぱいになるか入力の終端に達するまで読み込み、読み込んだバイト数を返します。
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+  let mut read = 0;
+  while read < buf.len() {
+    match reader.read(&mut buf[read..]) {
+      Ok(0) => break,
+      Ok(size) => read += size,
+      Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+      Err(err) => return Err(err.into()),
+    }
+  }
+  Ok(read)
+}
+
+impl<S: Storage> LMTHT<S> {
+  /// 指定されたストレージの LMTHT をオープンし、`values` のすべての値を追加して返します。
+  pub fn from_iter<I, V>(storage: S, values: I) -> Result<LMTHT<S>>
+  where
+    I: IntoIterator<Item = V>,
+    V: AsRef<[u8]>,
+  {
+    let db = LMTHT::new(storage)?;
+    db.try_extend(values)?;
+    Ok(db)
+  }
+
+  /// `values` のすべての値を [`DEFAULT_BATCH_SIZE`] 個ずつのバッチにまとめて追加し、追加後の木構造のルートノードを
+  /// 返します。途中で失敗した場合、直前にコミットしたバッチまでの値が追加された状態となります。
+  pub fn try_extend<I, V>(&self, values: I) -> Result<Option<Node>>
+  where
+    I: IntoIterator<Item = V>,
+    V: AsRef<[u8]>,
+  {
+    let mut importer = Importer::new(self, DEFAULT_BATCH_SIZE, |_| ());
+    for value in values {
+      importer.append(value.as_ref())?;
+    }
+    Ok(importer.finish()?.root)
+  }
+}
+
+/// [`LMTHT::try_extend()`] と同様に値をバッチにまとめて追加します。追加に失敗した場合はパニックするため、エラーを
+/// 扱う場合は [`LMTHT::try_extend()`] を使用してください。
+impl<S: Storage, V: AsRef<[u8]>> Extend<V> for LMTHT<S> {
+  fn extend<I: IntoIterator<Item = V>>(&mut self, values: I) {
+    if let Err(err) = self.try_extend(values) {
+      panic!("failed to extend the LMTHT: {}", err);
+    }
+  }
+}
+
+/// 値を一定数ごとのバッチにまとめて追加し、コミットのたびに進捗を通知します。
+pub(crate) struct Importer<'a, S: Storage, F: FnMut(&Progress)> {
+  db: &'a LMTHT<S>,
+  batch: Option<Batch<'a, S>>,
+  batch_size: usize,
+  pub progress: Progress,
+  callback: F,
+}
+
+impl<'a, S: Storage, F: FnMut(&Progress)> Importer<'a, S, F> {
+  pub fn new(db: &'a LMTHT<S>, batch_size: usize, callback: F) -> Importer<'a, S, F> {
+    let progress = Progress { lines: 0, entries: 0, root: db.root() };
+    Importer { db, batch: None, batch_size: batch_size.max(1), progress, callback }
+  }
+
+  /// 値をバッチに追加し、バッチが `batch_size` 個に達した場合はコミットします。
+  pub fn append(&mut self, value: &[u8]) -> Result<()> {
+    let batch = match &mut self.batch {
+      Some(batch) => batch,
+      None => self.batch.insert(self.db.begin_batch()?),
+    };
+    if batch.append_dedup(value)?.i.is_some() {
+      self.progress.entries += 1;
+    }
+    if batch.len() >= self.batch_size {
+      self.commit()?;
+    }
+    Ok(())
+  }
+
+  /// 残りのバッチをコミットして最終的な進捗を返します。
+  pub fn finish(mut self) -> Result<Progress> {
+    self.commit()?;
+    Ok(self.progress)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    if let Some(batch) = self.batch.take() {
+      self.progress.root = batch.commit()?;
+      (self.callback)(&self.progress);
+    }
+    Ok(())
+  }
+}
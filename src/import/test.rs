@@ -0,0 +1,77 @@
+use crate::error::Detail;
+use crate::*;
+
+/// NDJSON の各行が 1 つの値として追加され、バッチごとに進捗が通知されることを検証します。
+#[test]
+fn test_import_ndjson() {
+  use crate::import::{ndjson, ndjson_with};
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let input = b"{\"id\":1}\n\n  {\"id\":2}\r\n[3]\n{\"id\":4}\n\"five\"";
+  let mut reports = Vec::new();
+  let progress = ndjson_with(&input[..], &db, 2, |p| reports.push((p.lines, p.entries))).unwrap();
+  assert_eq!(vec![(3, 2), (5, 4), (6, 5)], reports);
+  assert_eq!((6, 5, db.root()), (progress.lines, progress.entries, progress.root));
+  let mut query = db.query().unwrap();
+  assert_eq!(Some(b"{\"id\":2}".to_vec()), query.get(2).unwrap());
+  assert_eq!(Some(b"\"five\"".to_vec()), query.get(5).unwrap());
+
+  // 不正な行の前にコミットしたバッチまでが追加される
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let result = ndjson(&b"1\n2\n\xFF\n"[..], &db);
+  assert!(matches!(result, Err(Detail::InvalidImportLine { line: 3, .. })));
+  assert_eq!(0, db.n());
+  assert_eq!(None, ndjson(&b""[..], &db).unwrap().root);
+}
+
+/// CSV の各行または選択した列が 1 つの値として追加され、引用符で囲まれた列が正しく扱われることを検証します。
+#[test]
+fn test_import_csv() {
+  use crate::import::{csv, CsvColumns, CsvOptions};
+  let input = b"id,user,note\r\n1,alice,\"said \"\"hi\"\"\"\n\n2,bob,\"multi\nline\"\n3,carol,\"a,b\"\n";
+  let get_all = |db: &LMTHT<MemStorage>| {
+    let mut query = db.query().unwrap();
+    (1..=db.n()).map(|i| String::from_utf8(query.get(i).unwrap().unwrap()).unwrap()).collect::<Vec<_>>()
+  };
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let progress = csv(&input[..], &db, &CsvOptions::default()).unwrap();
+  assert_eq!((6, 3), (progress.lines, progress.entries));
+  assert_eq!(vec!["1,alice,\"said \"\"hi\"\"\"", "2,bob,\"multi\nline\"", "3,carol,\"a,b\""], get_all(&db));
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let columns = CsvColumns::Names(vec!["note".to_string(), "id".to_string()]);
+  let options = CsvOptions { columns, ..Default::default() };
+  csv(&input[..], &db, &options).unwrap();
+  assert_eq!(vec!["\"said \"\"hi\"\"\",1", "\"multi\nline\",2", "\"a,b\",3"], get_all(&db));
+
+  // ヘッダ行のない区切り文字の異なる入力
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let options = CsvOptions { delimiter: b'\t', header: false, columns: CsvColumns::Indices(vec![1]) };
+  csv(&b"1\ta,b\n2\tc\n"[..], &db, &options).unwrap();
+  assert_eq!(vec!["a,b", "c"], get_all(&db));
+  let result = csv(&b"1\n"[..], &db, &options);
+  assert!(matches!(result, Err(Detail::InvalidImportLine { line: 1, .. })));
+  let options = CsvOptions { columns: CsvColumns::Names(vec!["unknown".to_string()]), ..Default::default() };
+  assert!(matches!(csv(&input[..], &db, &options), Err(Detail::InvalidImportLine { line: 1, .. })));
+  let result = csv(&b"id\n1\n\"open\n"[..], &db, &CsvOptions::default());
+  assert!(matches!(result, Err(Detail::InvalidImportLine { line: 3, .. })));
+}
+
+/// 反復子の値がバッチにまとめて追加され、1 つずつ追加した場合と同じ木構造となることを検証します。
+#[test]
+fn test_extend_values() {
+  let values = (0..3000u32).map(|i| i.to_le_bytes().to_vec()).collect::<Vec<_>>();
+  let expected = LMTHT::new(MemStorage::new()).unwrap();
+  for value in values.iter() {
+    expected.append(value).unwrap();
+  }
+
+  let db = LMTHT::from_iter(MemStorage::new(), values.iter()).unwrap();
+  assert_eq!(expected.root(), db.root());
+  let mut db = LMTHT::new(MemStorage::new()).unwrap();
+  assert_eq!(None, db.try_extend(Vec::<Vec<u8>>::new()).unwrap());
+  assert_eq!(Some(1000), db.try_extend(&values[..1000]).unwrap().map(|root| root.i));
+  db.extend(values[1000..].iter().cloned());
+  assert_eq!(expected.root(), db.root());
+  assert_eq!(Some(values[2999].clone()), db.query().unwrap().get(3000).unwrap());
+}
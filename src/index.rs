@@ -0,0 +1,166 @@
+//! インデックス i からストレージ上のエントリの位置を参照するための位置索引です。
+//!
+//! 位置索引は i 番目のエントリの位置を `(i - 1) * 8` バイト目に u64 (LE) で記録した固定長レコードの列で、ハッシュ木と
+//! は別のストレージ (サイドカー) に保存されます。位置索引を使用すると `get(i)` はルートノードからの対数回の探索を
+//! 行うことなく一度のシークでエントリを読み出すことができます。
+//!
+//! 位置索引はハッシュ木から再構築可能な補助情報です。オープン時に欠損や不整合を検出した場合は自動的に再構築されます。
+//! サイドカーを指定せずにメモリ上に位置索引を構築する場合は [`MemStorage`](crate::MemStorage) を使用します。
+//!
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::error::Detail;
+use crate::{
+  inconsistency, read_entry_header, read_index, Cursor, DynStorage, Entry, Index, MemStorage, Result, INDEX_BYTES,
+  STORAGE_IDENTIFIER,
+};
+
+#[cfg(test)]
+mod test;
+
+/// 位置索引の 1 レコードのバイトサイズです。
+const RECORD_SIZE: u64 = 8;
+
+/// サイドカーストレージに保存された位置索引です。
+pub(crate) struct PositionIndex {
+  storage: Arc<dyn DynStorage + Send + Sync>,
+  /// メモリ上に構築する位置索引か。読み込み専用の LMTHT でも再構築することができる。
+  in_memory: bool,
+  /// 位置索引がハッシュ木の最新の世代と一致しており、参照に使用できるか。
+  usable: AtomicBool,
+}
+
+impl PositionIndex {
+  pub fn new(storage: Arc<dyn DynStorage + Send + Sync>) -> PositionIndex {
+    PositionIndex { storage, in_memory: false, usable: AtomicBool::new(false) }
+  }
+
+  /// メモリ上に構築する位置索引を作成します。
+  pub fn in_memory() -> PositionIndex {
+    PositionIndex { storage: Arc::new(MemStorage::new()), in_memory: true, usable: AtomicBool::new(false) }
+  }
+
+  /// 位置索引がハッシュ木の `last` エントリまでの正しい位置を保持していることを確認します。索引が存在しない、
+  /// 末尾のレコードが欠けている、あるいはハッシュ木と一致しない場合は `cursor` からエントリを読み出して再構築します。
+  ///
+  /// `read_only` が true の場合はサイドカーに書き込まず、正しい位置を保持していない位置索引は参照に使用しません。
+  pub fn prepare<C: Read + Seek>(&self, cursor: &mut C, last: Option<&Entry>, read_only: bool) -> Result<()> {
+    let writable = !read_only || self.in_memory;
+    self.usable.store(false, Ordering::Release);
+    let n = last.map(|e| e.enode.meta.address.i).unwrap_or(0);
+    let storage_length = cursor.seek(SeekFrom::End(0))?;
+    let mut index = match self.storage.open_dyn(writable) {
+      Ok(index) => index,
+      Err(_) if !writable => return Ok(()),
+      Err(err) => return Err(err),
+    };
+    let index_length = index.seek(SeekFrom::End(0))?;
+    let records = index_length / RECORD_SIZE;
+
+    // 索引に記録されている最後の正しいレコードを特定する
+    let mut valid = n.min(records as Index);
+    if valid > 0 && !points_to(&mut index, cursor, storage_length, valid)? {
+      valid = 0;
+    }
+    if valid == n && n > 0 {
+      let position = lookup(&mut index, n)?;
+      if Some(position) != last.map(|e| e.enode.meta.address.position) {
+        valid = 0;
+      }
+    }
+    if !writable {
+      self.usable.store(valid == n, Ordering::Release);
+      return Ok(());
+    }
+
+    // 正しいレコードの次から末尾までのエントリの位置を再構築する
+    let mut position = if valid == 0 {
+      STORAGE_IDENTIFIER.len() as u64 + 1
+    } else {
+      let position = lookup(&mut index, valid)?;
+      cursor.seek(SeekFrom::Start(position))?;
+      skip_entry(cursor, position, valid)?
+    };
+    // 正しいレコードより後ろに残っている古いレコードを切り詰めてから追記する
+    let length = record_position(valid + 1);
+    if index_length > length {
+      index.set_len(length)?;
+    }
+    index.seek(SeekFrom::Start(length))?;
+    for i in valid + 1..=n {
+      index.write_u64::<LittleEndian>(position)?;
+      cursor.seek(SeekFrom::Start(position))?;
+      position = skip_entry(cursor, position, i)?;
+    }
+    index.flush()?;
+    self.usable.store(true, Ordering::Release);
+    Ok(())
+  }
+
+  /// i 番目のエントリの位置を位置索引に追加します。
+  pub fn append(&self, i: Index, position: u64) -> Result<()> {
+    let mut index = self.storage.open_dyn(true)?;
+    index.seek(SeekFrom::Start(record_position(i)))?;
+    index.write_u64::<LittleEndian>(position)?;
+    index.flush()?;
+    Ok(())
+  }
+
+  /// 位置索引を参照するための読み込み用カーソルをオープンします。参照に使用できない場合は `None` を返します。
+  pub fn open(&self) -> Result<Option<Box<dyn Cursor>>> {
+    if !self.usable.load(Ordering::Acquire) {
+      return Ok(None);
+    }
+    self.storage.open_dyn(false).map(Some)
+  }
+}
+
+/// 指定された位置索引のカーソルから i 番目のエントリの位置を参照します。
+pub(crate) fn lookup(index: &mut Box<dyn Cursor>, i: Index) -> Result<u64> {
+  debug_assert_ne!(0, i);
+  index.seek(SeekFrom::Start(record_position(i)))?;
+  match index.read_u64::<LittleEndian>() {
+    Ok(position) => Ok(position),
+    Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+      // ハッシュ木に存在するエントリの位置が位置索引に記録されていない
+      inconsistency(format!("position index doesn't contain the entry i={}", i))
+    }
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// i 番目のエントリの位置を記録しているレコードの位置索引上の位置を返します。
+#[allow(clippy::unnecessary_cast)] // Index は feature によって u64 以外の型となる
+fn record_position(i: Index) -> u64 {
+  (i - 1) as u64 * RECORD_SIZE
+}
+
+/// 位置索引に記録されている i 番目の位置がハッシュ木上の i 番目のエントリを指しているかを判定します。その位置から
+/// i 番目のエントリとして読み込むことができ、エントリがストレージの長さ `length` に収まっている必要があります。
+fn points_to<C: Read + Seek>(index: &mut Box<dyn Cursor>, cursor: &mut C, length: u64, i: Index) -> Result<bool> {
+  let position = lookup(index, i)?;
+  if position < STORAGE_IDENTIFIER.len() as u64 + 1 || position + INDEX_BYTES as u64 > length {
+    return Ok(false);
+  }
+  cursor.seek(SeekFrom::Start(position))?;
+  if read_index(cursor)? != i {
+    return Ok(false);
+  }
+  cursor.seek(SeekFrom::Start(position))?;
+  match skip_entry(cursor, position, i) {
+    Ok(end) => Ok(end <= length),
+    Err(Detail::IncorrectNodeBoundary { .. }) => Ok(false),
+    Err(Detail::Io { source }) if source.kind() == ErrorKind::UnexpectedEof => Ok(false),
+    Err(err) => Err(err),
+  }
+}
+
+/// `position` に位置する i 番目のエントリをペイロードを読み込まずに読み飛ばし、次のエントリの位置を返します。
+fn skip_entry<C: Read + Seek>(cursor: &mut C, position: u64, i: Index) -> Result<u64> {
+  read_entry_header(cursor, position, i)?;
+  Ok(cursor.seek(SeekFrom::Current(4 /* offset */ + 8 /* checksum */))?)
+}
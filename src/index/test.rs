@@ -0,0 +1,152 @@
+use std::sync::{Arc, RwLock};
+
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// 位置索引を使用した参照と、欠損または不整合のある位置索引がオープン時に再構築されることを検証します。
+#[test]
+fn test_position_index() {
+  const N: Index = 50;
+  let buffer = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
+  let index = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
+  let open = |index: &Arc<RwLock<Vec<u8>>>| {
+    let position_index: Arc<dyn DynStorage + Send + Sync> = Arc::new(MemStorage::with(index.clone()));
+    let options = LMTHTOptions { position_index: Some(position_index), ..Default::default() };
+    LMTHT::with_options(MemStorage::with(buffer.clone()), options).unwrap()
+  };
+  let verify = |db: &LMTHT<MemStorage>, n: Index| {
+    let mut query = db.query().unwrap();
+    for i in 1..=n {
+      assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+      assert_eq!(db.root().unwrap(), query.get_with_hashes(i).unwrap().unwrap().root());
+    }
+    assert_eq!(None, query.get(0).unwrap());
+    assert_eq!(None, query.get(n + 1).unwrap());
+  };
+
+  let db = open(&index);
+  for i in 1..=N {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  verify(&db, N);
+  assert_eq!(N as usize * 8, index.read().unwrap().len());
+  let expected = index.read().unwrap().clone();
+
+  // 位置索引が存在しない場合
+  let empty = Arc::new(RwLock::new(Vec::<u8>::new()));
+  verify(&open(&empty), N);
+  assert_eq!(expected, *empty.read().unwrap());
+
+  // 位置索引の末尾のレコードが欠けている場合
+  let truncated = Arc::new(RwLock::new(expected[..expected.len() / 2 + 3].to_vec()));
+  verify(&open(&truncated), N);
+  assert_eq!(expected, *truncated.read().unwrap());
+
+  // 位置索引がハッシュ木と一致しない場合
+  let broken = Arc::new(RwLock::new(expected.iter().map(|b| !b).collect::<Vec<u8>>()));
+  let db = open(&broken);
+  verify(&db, N);
+  assert_eq!(expected, *broken.read().unwrap());
+
+  // 位置索引がハッシュ木より多くのレコードを持つ場合
+  let longer = Arc::new(RwLock::new([&expected[..], &[0xFF; 3 * 8]].concat()));
+  verify(&open(&longer), N);
+  assert_eq!(expected, *longer.read().unwrap());
+
+  // 再構築した位置索引に追加できる
+  db.append(&random_payload(PAYLOAD_SIZE, N + 1)).unwrap();
+  verify(&db, N + 1);
+}
+
+/// メモリ上の位置索引を使用した参照を検証します。
+#[test]
+fn test_in_memory_position_index() {
+  const N: Index = 50;
+  let buffer = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
+  let options = LMTHTOptions { in_memory_position_index: true, ..Default::default() };
+  let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options.clone()).unwrap();
+  for i in 1..=N / 2 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+
+  // オープン時に既存のエントリから位置索引を構築する
+  let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options).unwrap();
+  for i in N / 2 + 1..=N {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut query = db.query().unwrap();
+  for i in 1..=N {
+    assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+  }
+  assert_eq!(None, query.get(0).unwrap());
+  assert_eq!(None, query.get(N + 1).unwrap());
+}
+
+/// 切り詰めに対応しないサイドカーにも位置索引を保存でき、読み込み専用の LMTHT は位置索引に書き込まずに一致しない
+/// 位置索引を使用しないことを検証します。
+#[test]
+fn test_position_index_without_writes() {
+  use std::io::{Read, Seek, SeekFrom, Write};
+
+  // Cursor::set_len() を実装しないカーソル
+  struct AppendOnly(MemStorage);
+  struct AppendOnlyCursor(<MemStorage as Storage>::Cursor);
+  impl Storage for AppendOnly {
+    type Cursor = AppendOnlyCursor;
+    fn open(&self, writable: bool) -> Result<AppendOnlyCursor> {
+      Ok(AppendOnlyCursor(self.0.open(writable)?))
+    }
+  }
+  impl Cursor for AppendOnlyCursor {}
+  impl Read for AppendOnlyCursor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      self.0.read(buf)
+    }
+  }
+  impl Write for AppendOnlyCursor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.0.write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+      self.0.flush()
+    }
+  }
+  impl Seek for AppendOnlyCursor {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+      self.0.seek(pos)
+    }
+  }
+
+  const N: Index = 20;
+  let buffer = Arc::new(RwLock::new(Vec::<u8>::new()));
+  let index = Arc::new(RwLock::new(Vec::<u8>::new()));
+  let open = |index: &Arc<RwLock<Vec<u8>>>, read_only: bool| {
+    let position_index: Arc<dyn DynStorage + Send + Sync> = Arc::new(AppendOnly(MemStorage::with(index.clone())));
+    let options = LMTHTOptions { position_index: Some(position_index), read_only, ..Default::default() };
+    LMTHT::with_options(MemStorage::with(buffer.clone()), options).unwrap()
+  };
+  let verify = |db: &LMTHT<MemStorage>| {
+    let mut query = db.query().unwrap();
+    for i in 1..=N {
+      assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+    }
+  };
+  let db = open(&index, false);
+  for i in 1..=N {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  drop(db);
+  verify(&open(&index, false));
+  let expected = index.read().unwrap().clone();
+  assert_eq!(N as usize * 8, expected.len());
+
+  // 読み込み専用の LMTHT は欠けているか一致しない位置索引を再構築せず、木構造を探索して参照する
+  for broken in [expected[..expected.len() / 2].to_vec(), expected.iter().map(|b| !b).collect::<Vec<u8>>()] {
+    let broken = Arc::new(RwLock::new(broken));
+    let before = broken.read().unwrap().clone();
+    verify(&open(&broken, true));
+    assert_eq!(before, *broken.read().unwrap());
+  }
+  verify(&open(&index, true));
+  assert_eq!(expected, *index.read().unwrap());
+}
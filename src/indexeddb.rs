@@ -0,0 +1,271 @@
+//! ブラウザの IndexedDB にバイト列を保存するストレージです。
+//!
+//! IndexedDB の操作は非同期であるため、[`IndexedDbStorage::open()`] はオープン時にすべてのチャンクをメモリ上に読み
+//! 込み、以降の読み書きはメモリ上の内容に対して同期的に行います。書き込まれたチャンクは
+//! [`IndexedDbStorage::persist()`] を呼び出した時点で 1 つのトランザクションとして IndexedDB に保存されます。
+//! `persist()` を呼び出す前にページが閉じられた場合、最後に保存した時点以降の追加は失われます。
+//!
+//! ```rust,no_run
+//! use lmtht::indexeddb::{IndexedDbStorage, DEFAULT_INDEXEDDB_CHUNK_SIZE};
+//! use lmtht::LMTHT;
+//!
+//! # async fn run() -> lmtht::Result<()> {
+//! let storage = IndexedDbStorage::open("audit-log", DEFAULT_INDEXEDDB_CHUNK_SIZE).await?;
+//! let db = LMTHT::new(storage)?;
+//! db.append(b"hello")?;
+//! db.storage().persist().await?;
+//! # Ok(())
+//! # }
+//! # fn main() {}
+//! ```
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::sync::Mutex;
+
+use js_sys::{Array, Function, Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Event, IdbDatabase, IdbRequest, IdbTransaction, IdbTransactionMode};
+
+use crate::chunked::{ChunkStore, ChunkedCursor, ChunkedStorage};
+use crate::error::Detail;
+use crate::{lock2io, Capabilities, Result, Storage};
+
+/// [`IndexedDbStorage`] のチャンクサイズのデフォルト値です。
+pub const DEFAULT_INDEXEDDB_CHUNK_SIZE: usize = 64 * 1024;
+
+/// チャンクを保存するオブジェクトストアの名前。
+const OBJECT_STORE: &str = "chunks";
+
+/// バイト列の長さを保存するキー。チャンクは数値のキーで保存される。
+const LENGTH_KEY: &str = "length";
+
+/// IndexedDB のデータベースに永続化されるストレージです。
+pub struct IndexedDbStorage {
+  db: IdbDatabase,
+  storage: ChunkedStorage<IndexedDbImage>,
+}
+
+impl IndexedDbStorage {
+  /// 指定された名前の IndexedDB データベースをオープンし、保存されているチャンクをメモリ上に読み込みます。データベース
+  /// が存在しない場合は作成します。`chunk_size` は 1 つのレコードに保存するバイトサイズであり、同じデータベースに対して
+  /// 常に同じ値を指定する必要があります。
+  pub async fn open(name: &str, chunk_size: usize) -> Result<IndexedDbStorage> {
+    let factory = web_sys::window()
+      .ok_or_else(|| unavailable("no window object"))?
+      .indexed_db()
+      .map_err(js2err)?
+      .ok_or_else(|| unavailable("IndexedDB is not supported"))?;
+    let request = factory.open_with_u32(name, 1).map_err(js2err)?;
+    let upgrading = request.clone();
+    let upgrade = Closure::once_into_js(move |_: Event| {
+      if let Ok(db) = upgrading.result() {
+        let _ = db.unchecked_into::<IdbDatabase>().create_object_store(OBJECT_STORE);
+      }
+    });
+    request.set_onupgradeneeded(Some(upgrade.unchecked_ref()));
+    let db = completion(&request).await?.unchecked_into::<IdbDatabase>();
+
+    // キーと値を一度に要求し、数値のキーをチャンク、LENGTH_KEY を長さとして復元する
+    let transaction = db.transaction_with_str(OBJECT_STORE).map_err(js2err)?;
+    let store = transaction.object_store(OBJECT_STORE).map_err(js2err)?;
+    let keys = JsFuture::from(promise(&store.get_all_keys().map_err(js2err)?));
+    let values = JsFuture::from(promise(&store.get_all().map_err(js2err)?));
+    let keys = keys.await.map_err(js2err)?.unchecked_into::<Array>();
+    let values = values.await.map_err(js2err)?.unchecked_into::<Array>();
+    let mut chunks = BTreeMap::new();
+    let mut length = 0;
+    for (key, value) in keys.iter().zip(values.iter()) {
+      match key.as_f64() {
+        Some(k) => {
+          chunks.insert(k as u64, Uint8Array::new(&value).to_vec());
+        }
+        None if key.as_string().as_deref() == Some(LENGTH_KEY) => length = value.as_f64().unwrap_or(0.0) as u64,
+        None => (),
+      }
+    }
+
+    let stored = chunks.keys().copied().collect::<BTreeSet<u64>>();
+    let state = State { chunks, length, stored, dirty: BTreeSet::new(), length_changed: false };
+    let image = IndexedDbImage { chunk_size, state: Mutex::new(state) };
+    Ok(IndexedDbStorage { db, storage: ChunkedStorage::new(image) })
+  }
+
+  /// 前回の保存以降に書き込まれたチャンクとバイト列の長さを 1 つのトランザクションで IndexedDB に保存します。
+  /// 保存に失敗した場合、書き込まれたチャンクは次回の呼び出しで再び保存されます。
+  pub async fn persist(&self) -> Result<()> {
+    let image = self.storage.store();
+    let changes = image.take_changes()?;
+    if changes.is_empty() {
+      return Ok(());
+    }
+    let result = self.write(&changes).await;
+    if result.is_err() {
+      image.restore_changes(changes)?;
+    }
+    result
+  }
+
+  /// 前回の保存以降に保存されていない書き込みが存在するかを判定します。
+  pub fn is_dirty(&self) -> bool {
+    self.storage.store().state.lock().map(|state| state.length_changed || !state.dirty.is_empty()).unwrap_or(true)
+  }
+
+  async fn write(&self, changes: &Changes) -> Result<()> {
+    let transaction =
+      self.db.transaction_with_str_and_mode(OBJECT_STORE, IdbTransactionMode::Readwrite).map_err(js2err)?;
+    let completed = JsFuture::from(transaction_promise(&transaction));
+    let store = transaction.object_store(OBJECT_STORE).map_err(js2err)?;
+    for k in changes.deleted.iter() {
+      store.delete(&JsValue::from_f64(*k as f64)).map_err(js2err)?;
+    }
+    for (k, chunk) in changes.chunks.iter() {
+      store.put_with_key(&Uint8Array::from(&chunk[..]), &JsValue::from_f64(*k as f64)).map_err(js2err)?;
+    }
+    store.put_with_key(&JsValue::from_f64(changes.length as f64), &JsValue::from_str(LENGTH_KEY)).map_err(js2err)?;
+    completed.await.map_err(js2err)?;
+    Ok(())
+  }
+}
+
+impl Storage for IndexedDbStorage {
+  type Cursor = ChunkedCursor<IndexedDbImage>;
+  fn open(&self, writable: bool) -> Result<ChunkedCursor<IndexedDbImage>> {
+    self.storage.open(writable)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.storage.capabilities()
+  }
+}
+
+/// IndexedDB の内容をメモリ上に保持する [`ChunkStore`] です。
+pub struct IndexedDbImage {
+  chunk_size: usize,
+  state: Mutex<State>,
+}
+
+struct State {
+  chunks: BTreeMap<u64, Vec<u8>>,
+  length: u64,
+  /// IndexedDB に保存されているチャンク番号。
+  stored: BTreeSet<u64>,
+  /// 前回の保存以降に書き込まれたチャンク番号。
+  dirty: BTreeSet<u64>,
+  length_changed: bool,
+}
+
+/// 1 回の保存で IndexedDB に反映する変更。
+struct Changes {
+  chunks: Vec<(u64, Vec<u8>)>,
+  deleted: Vec<u64>,
+  length: u64,
+  length_changed: bool,
+}
+
+impl Changes {
+  fn is_empty(&self) -> bool {
+    self.chunks.is_empty() && self.deleted.is_empty() && !self.length_changed
+  }
+}
+
+impl IndexedDbImage {
+  fn take_changes(&self) -> io::Result<Changes> {
+    let mut state = lock2io(self.state.lock())?;
+    let dirty = std::mem::take(&mut state.dirty);
+    let chunks = dirty.iter().filter_map(|k| state.chunks.get(k).map(|chunk| (*k, chunk.clone()))).collect::<Vec<_>>();
+    let deleted = state.stored.iter().filter(|k| !state.chunks.contains_key(k)).copied().collect::<Vec<_>>();
+    let length_changed = std::mem::take(&mut state.length_changed);
+    state.stored = state.chunks.keys().copied().collect();
+    Ok(Changes { chunks, deleted, length: state.length, length_changed })
+  }
+
+  fn restore_changes(&self, changes: Changes) -> io::Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    state.dirty.extend(changes.chunks.iter().map(|(k, _)| *k));
+    state.stored.extend(changes.deleted);
+    state.length_changed |= changes.length_changed;
+    Ok(())
+  }
+}
+
+impl ChunkStore for IndexedDbImage {
+  fn chunk_size(&self) -> usize {
+    self.chunk_size
+  }
+
+  fn length(&self) -> io::Result<u64> {
+    Ok(lock2io(self.state.lock())?.length)
+  }
+
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>> {
+    Ok(lock2io(self.state.lock())?.chunks.get(&k).cloned())
+  }
+
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    state.chunks.insert(k, chunk.to_vec());
+    state.dirty.insert(k);
+    state.length_changed |= state.length != length;
+    state.length = length;
+    Ok(())
+  }
+
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    state.chunks.retain(|key, _| *key < k);
+    state.dirty = state.dirty.range(..k).copied().collect();
+    state.length_changed |= state.length != length;
+    state.length = length;
+    Ok(())
+  }
+}
+
+/// IndexedDB の要求が完了するまで待機し、その結果を返します。
+async fn completion(request: &IdbRequest) -> Result<JsValue> {
+  JsFuture::from(promise(request)).await.map_err(js2err)
+}
+
+/// IndexedDB の要求の完了を表す `Promise` を作成します。要求が完了する前に呼び出す必要があります。
+fn promise(request: &IdbRequest) -> Promise {
+  Promise::new(&mut |resolve: Function, reject: Function| {
+    let succeeded = request.clone();
+    let onsuccess = Closure::once_into_js(move |_: Event| {
+      let _ = resolve.call1(&JsValue::NULL, &succeeded.result().unwrap_or(JsValue::UNDEFINED));
+    });
+    let onerror = Closure::once_into_js(move |_: Event| {
+      let _ = reject.call1(&JsValue::NULL, &JsValue::from_str("IndexedDB request failed"));
+    });
+    request.set_onsuccess(Some(onsuccess.unchecked_ref()));
+    request.set_onerror(Some(onerror.unchecked_ref()));
+  })
+}
+
+/// トランザクションの完了を表す `Promise` を作成します。
+fn transaction_promise(transaction: &IdbTransaction) -> Promise {
+  Promise::new(&mut |resolve: Function, reject: Function| {
+    let oncomplete = Closure::once_into_js(move |_: Event| {
+      let _ = resolve.call0(&JsValue::NULL);
+    });
+    let aborted = reject.clone();
+    let onerror = Closure::once_into_js(move |_: Event| {
+      let _ = reject.call1(&JsValue::NULL, &JsValue::from_str("IndexedDB transaction failed"));
+    });
+    let onabort = Closure::once_into_js(move |_: Event| {
+      let _ = aborted.call1(&JsValue::NULL, &JsValue::from_str("IndexedDB transaction aborted"));
+    });
+    transaction.set_oncomplete(Some(oncomplete.unchecked_ref()));
+    transaction.set_onerror(Some(onerror.unchecked_ref()));
+    transaction.set_onabort(Some(onabort.unchecked_ref()));
+  })
+}
+
+fn js2err(err: JsValue) -> Detail {
+  Detail::Io { source: io::Error::other(format!("IndexedDB: {:?}", err)) }
+}
+
+fn unavailable(message: &str) -> Detail {
+  Detail::Io { source: io::Error::new(io::ErrorKind::Unsupported, message.to_string()) }
+}
@@ -6,13 +6,18 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use highway::{HighwayBuilder, Key};
 
 use crate::checksum::HashRead;
-use crate::{hex, is_version_compatible, Hash, Result, CHECKSUM_HW64_KEY, HASH_SIZE, STORAGE_IDENTIFIER};
+use crate::{
+  hex, index_size_of, is_version_compatible, read_index, read_seal, Hash, Index, Result, CHECKSUM_HW64_KEY, HASH_SIZE,
+  INDEX_SIZE, INDEX_WIDTH_MASK, MAX_PAYLOAD_SIZE, STORAGE_IDENTIFIER,
+};
 
 pub trait SeekRead: Seek + std::io::Read {}
 
 /// 指定されたカーソルから読み出される直列化された木構造を人の見やすい形式で出力します。
 pub fn report<T: AsRef<[u8]>>(cursor: &mut std::io::Cursor<T>) -> Result<()> {
-  let eof = cursor.seek(SeekFrom::End(0))?;
+  let length = cursor.seek(SeekFrom::End(0))?;
+  let seal = read_seal(cursor, length)?;
+  let eof = seal.map(|(start, _)| start).unwrap_or(length);
   cursor.seek(SeekFrom::Start(0))?;
 
   let eval = |f: bool| if f { '✔' } else { '❌' };
@@ -25,13 +30,14 @@ pub fn report<T: AsRef<[u8]>>(cursor: &mut std::io::Cursor<T>) -> Result<()> {
   println!("IDENTIFIER: {} {}", hex(&identifier[0..3]), eval(identifier[0..3] == STORAGE_IDENTIFIER));
   println!(
     "VERSION   : {}.{} {}",
-    identifier[3] >> 4,
+    (identifier[3] & !INDEX_WIDTH_MASK) >> 4,
     identifier[3] & 0x0F,
     eval(is_version_compatible(identifier[3]))
   );
+  println!("INDEX     : {}-bit {}", index_size_of(identifier[3]), eval(index_size_of(identifier[3]) == INDEX_SIZE));
 
-  let mut location = HashMap::<u64, u64>::new();
-  let mut hashes = HashMap::<(u64, u8), Hash>::new();
+  let mut location = HashMap::<Index, u64>::new();
+  let mut hashes = HashMap::<(Index, u8), Hash>::new();
   let mut hash = [0u8; HASH_SIZE];
   while cursor.stream_position()? < eof {
     let position = cursor.stream_position()?;
@@ -39,15 +45,15 @@ pub fn report<T: AsRef<[u8]>>(cursor: &mut std::io::Cursor<T>) -> Result<()> {
     let mut r = HashRead::new(cursor, &mut hasher);
 
     // エントリ
-    let i = r.read_u64::<LittleEndian>()?;
+    let i = read_index(&mut r)?;
     location.insert(i, position);
     let inode_size = r.read_u8()?;
-    let mut inodes = Vec::<(u8, u64, u64, u8, Hash)>::new();
+    let mut inodes = Vec::<(u8, u64, Index, u8, Hash)>::new();
     for _ in 0..inode_size {
       // 中間ノード
       let j = r.read_u8()? + 1;
       let left_position = r.read_u64::<LittleEndian>()?;
-      let left_i = r.read_u64::<LittleEndian>()?;
+      let left_i = read_index(&mut r)?;
       let left_j = r.read_u8()?;
       r.read_exact(&mut hash)?;
 
@@ -57,7 +63,7 @@ pub fn report<T: AsRef<[u8]>>(cursor: &mut std::io::Cursor<T>) -> Result<()> {
     }
 
     // 葉ノード
-    let payload_len = r.read_u32::<LittleEndian>()?;
+    let payload_len = r.read_u32::<LittleEndian>()? & MAX_PAYLOAD_SIZE as u32;
     let mut payload = Vec::<u8>::with_capacity(payload_len as usize);
     unsafe { payload.set_len(payload_len as usize) };
     r.read_exact(payload.as_mut_slice())?;
@@ -123,6 +129,14 @@ pub fn report<T: AsRef<[u8]>>(cursor: &mut std::io::Cursor<T>) -> Result<()> {
     println!("CHECKSUM : {} {}", hex(&checksum.to_le_bytes()), eval(checksum == actual_checksum));
   }
 
+  if let Some((start, root)) = seal {
+    println!("--------");
+    match root {
+      Some(root) => println!("SEAL: {} @{}", root, start),
+      None => println!("SEAL: (empty) @{}", start),
+    }
+  }
+
   Ok(())
 }
 
@@ -0,0 +1,221 @@
+//! キーからそのキーを持つレコードのインデックスを参照するためのキー索引です。
+//!
+//! [`LMTHT::append_with_key()`] はキーを付加したレコード ([`Record::key`]) を追加します。キーは値の一部として
+//! ハッシュ木に保存されるため、キーごとの値の履歴はそれぞれの値と同様に証明によって検証することができます。
+//! [`Query::get_by_key()`] はキーを持つレコードのインデックスを履歴の順に返します。
+//!
+//! キー索引はキーを持つエントリのインデックスとキーの組を
+//! [`LMTHTOptions::key_index`](crate::LMTHTOptions::key_index) に指定したサイドカーのストレージに追記し、オープン時
+//! にメモリ上の表として読み込みます。サイドカーは次のレコードの列です。整数はすべてリトルエンディアンです。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | インデックス | [`INDEX_SIZE`](crate::INDEX_SIZE) のビット幅 |
+//! | キー長 (u32) | 4 |
+//! | キー | キー長 |
+//! | チェックサム | 8 |
+//!
+//! キー索引はハッシュ木から再構築可能な補助情報です。オープン時には最後に記録したエントリより後のエントリのキーを
+//! 走査して索引に追加し、記録がハッシュ木と一致しない場合はすべてのエントリから再構築します。
+//! キー索引を指定したストレージはレコード形式であるため、[`LMTHT::prune_payloads()`] はキーを残して値を削除し、値を
+//! 削除したエントリのキーも再構築することができます。
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let db = LMTHT::builder("ledger.db").key_index(Arc::new("ledger.keys")).open()?;
+//! db.append_with_key(b"account-42", b"balance=100")?;
+//! let history = db.query()?.get_by_key(b"account-42")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use highway::{HighwayBuilder, Key};
+
+use crate::error::Detail;
+use crate::record::{key_of, Record};
+use crate::{lock2io, Cursor, DynStorage, Index, Node, Query, Result, Storage, CHECKSUM_HW64_KEY, INDEX_BYTES, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// サイドカーストレージに保存されたキー索引です。
+pub(crate) struct KeyIndex {
+  storage: Arc<dyn DynStorage + Send + Sync>,
+  state: Mutex<State>,
+}
+
+/// メモリ上に読み込んだキー索引。
+#[derive(Default)]
+struct State {
+  /// キーとそのキーを持つエントリのインデックスの昇順の列。
+  keys: HashMap<Vec<u8>, Vec<Index>>,
+  /// 索引に反映済みの最後のエントリのインデックス。
+  indexed: Index,
+  /// サイドカーに最後に記録したエントリのインデックスとキー。
+  last: Option<(Index, Vec<u8>)>,
+  /// サイドカー上の正しいレコードの終端。
+  end: u64,
+}
+
+impl KeyIndex {
+  /// 指定されたストレージに記録されているキー索引を読み込みます。書き込み途中で中断した末尾のレコードは無視されます。
+  pub fn open(storage: Arc<dyn DynStorage + Send + Sync>) -> Result<KeyIndex> {
+    let mut bytes = Vec::new();
+    storage.open_dyn(false)?.read_to_end(&mut bytes)?;
+    let mut state = State::default();
+    let mut r = &bytes[..];
+    while let Some((i, key, length)) = parse_record(r) {
+      r = &r[length..];
+      state.end += length as u64;
+      state.keys.entry(key.clone()).or_default().push(i);
+      state.indexed = i;
+      state.last = Some((i, key));
+    }
+    Ok(KeyIndex { storage, state: Mutex::new(state) })
+  }
+
+  /// キー索引が `query` の世代のすべてのエントリを反映していることを確認します。最後に記録したエントリが
+  /// ハッシュ木と一致しない場合はすべてのエントリから再構築し、未反映のエントリはキーを走査して索引に追加します。
+  pub fn prepare<C: Cursor>(&self, query: &mut Query<C>) -> Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    let n = query.n();
+    let consistent = match &state.last {
+      Some((i, key)) if *i <= n => match query.entry_position(*i)? {
+        Some(position) => match query.read_key(position, *i) {
+          Ok((actual, _)) => actual.as_ref() == Some(key),
+          Err(Detail::InvalidRecord { .. }) => false,
+          Err(Detail::PayloadPruned { .. }) | Err(Detail::Archived { .. }) => true,
+          Err(err) => return Err(err),
+        },
+        None => false,
+      },
+      Some(_) => false,
+      None => true,
+    };
+    if !consistent {
+      let mut cursor = self.storage.open_dyn(true)?;
+      cursor.set_len(0)?;
+      cursor.flush()?;
+      *state = State::default();
+    }
+    state.indexed = state.indexed.min(n);
+
+    // 未反映のエントリのキーを走査する
+    let mut position = None;
+    for i in state.indexed + 1..=n {
+      let current = match position {
+        Some(position) => position,
+        None => match query.entry_position(i)? {
+          Some(position) => position,
+          None => break,
+        },
+      };
+      match query.read_key(current, i) {
+        Ok((key, next)) => {
+          if let Some(key) = key {
+            self.record(&mut state, i, key)?;
+          }
+          position = Some(next);
+        }
+        Err(Detail::InvalidRecord { .. }) | Err(Detail::PayloadPruned { .. }) | Err(Detail::Archived { .. }) => {
+          position = None
+        }
+        Err(err) => return Err(err),
+      }
+      state.indexed = i;
+    }
+    Ok(())
+  }
+
+  /// 追加した i 番目のエントリの値 `value` がキーを持つレコードの場合にそのキーを索引に追加します。
+  pub fn append(&self, i: Index, value: &[u8]) -> Result<()> {
+    let mut state = lock2io(self.state.lock())?;
+    if let Some(key) = key_of(value) {
+      self.record(&mut state, i, key)?;
+    }
+    state.indexed = i;
+    Ok(())
+  }
+
+  /// キー `key` を持つエントリのうち世代 `n` に含まれるもののインデックスを昇順で返します。
+  pub fn find(&self, key: &[u8], n: Index) -> Result<Vec<Index>> {
+    let state = lock2io(self.state.lock())?;
+    let indices = state.keys.get(key).map(|indices| indices.as_slice()).unwrap_or(&[]);
+    Ok(indices.iter().copied().take_while(|i| *i <= n).collect())
+  }
+
+  /// i 番目のエントリのキーをサイドカーの末尾に記録し、メモリ上の表に追加します。
+  fn record(&self, state: &mut MutexGuard<'_, State>, i: Index, key: Vec<u8>) -> Result<()> {
+    let mut record = Vec::with_capacity(INDEX_BYTES + 4 + key.len() + 8);
+    record.extend_from_slice(&i.to_le_bytes());
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(&key);
+    let checksum = checksum_of(&record);
+    record.extend_from_slice(&checksum);
+    let mut cursor = self.storage.open_dyn(true)?;
+    cursor.seek(SeekFrom::Start(state.end))?;
+    cursor.write_all(&record)?;
+    cursor.flush()?;
+    state.end += record.len() as u64;
+    state.keys.entry(key.clone()).or_default().push(i);
+    state.last = Some((i, key));
+    Ok(())
+  }
+}
+
+/// `r` の先頭のレコードを読み込み、インデックスとキー、レコードのバイトサイズを返します。レコードが不完全であるか
+/// チェックサムが一致しない場合は `None` を返します。
+fn parse_record(r: &[u8]) -> Option<(Index, Vec<u8>, usize)> {
+  let head = INDEX_BYTES + 4;
+  if r.len() < head {
+    return None;
+  }
+  let i = Index::from_le_bytes(r[..INDEX_BYTES].try_into().unwrap());
+  let key_length = u32::from_le_bytes(r[INDEX_BYTES..head].try_into().unwrap()) as usize;
+  let length = head.checked_add(key_length)?.checked_add(8)?;
+  if r.len() < length || r[length - 8..length] != checksum_of(&r[..length - 8]) {
+    return None;
+  }
+  Some((i, r[head..length - 8].to_vec(), length))
+}
+
+fn checksum_of(body: &[u8]) -> [u8; 8] {
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, body);
+  std::hash::Hasher::finish(&hasher).to_le_bytes()
+}
+
+impl<S: Storage> LMTHT<S> {
+  /// キー `key` を付加した値を追加します。キーを指定したレコードを [`LMTHT::append_record()`] で追加することと同じ
+  /// です。
+  pub fn append_with_key(&self, key: &[u8], value: &[u8]) -> Result<Node> {
+    self.append_record(&Record { key: Some(key.to_vec()), payload: value.to_vec(), ..Default::default() })
+  }
+}
+
+impl<C: Cursor> Query<C> {
+  /// キー `key` を持つレコードのうちこのクエリーの世代に含まれるもののインデックスを追加された順に返します。最後の
+  /// 要素がそのキーの最新の値です。キーを持つレコードが存在しない場合は空の列を返します。
+  ///
+  /// [`LMTHT::prune_payloads()`] で値を削除したエントリのインデックスも含まれます。それらの値の参照は
+  /// [`Detail::PayloadPruned`] を返します。
+  ///
+  /// # Errors
+  /// [`LMTHTOptions::key_index`](crate::LMTHTOptions::key_index) が指定されていない場合は [`Detail::NoKeyIndex`]
+  /// を返します。
+  pub fn get_by_key(&self, key: &[u8]) -> Result<Vec<Index>> {
+    match &self.keys {
+      Some(keys) => keys.find(key, self.n()),
+      None => Err(Detail::NoKeyIndex),
+    }
+  }
+}
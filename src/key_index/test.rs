@@ -0,0 +1,85 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::record::{Record, FLAG_KEY, RECORD_MARKER};
+use crate::*;
+
+/// キーを付加したレコードのインデックスの履歴をキー索引から参照でき、キー索引がハッシュ木から再構築されることを
+/// 検証します。
+#[test]
+fn test_key_index() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let keys = Arc::new(RwLock::new(Vec::new()));
+  let open = |keys: &Arc<RwLock<Vec<u8>>>| {
+    let storage = Arc::new(MemStorage::with(keys.clone()));
+    LMTHT::builder(MemStorage::with(buffer.clone())).key_index(storage).open().unwrap()
+  };
+  let db = open(&keys);
+  for i in 1..=30u32 {
+    if i % 4 == 0 {
+      db.append_record(&Record { payload: b"raw".to_vec(), ..Default::default() }).unwrap();
+    } else {
+      db.append_with_key(format!("key-{}", i % 3).as_bytes(), &i.to_le_bytes()).unwrap();
+    }
+  }
+  let expected = |k: u32, n: Index| (1..=n).filter(|i| i % 4 != 0 && i % 3 == k as Index).collect::<Vec<_>>();
+  let query = db.query().unwrap();
+  for k in 0..3 {
+    assert_eq!(expected(k, 30), query.get_by_key(format!("key-{}", k).as_bytes()).unwrap());
+  }
+  assert!(query.get_by_key(b"unknown").unwrap().is_empty());
+
+  // 古い世代のクエリーはその世代に含まれるインデックスのみを返す
+  db.append_with_key(b"key-1", b"latest").unwrap();
+  assert_eq!(expected(1, 30), query.get_by_key(b"key-1").unwrap());
+  let history = db.query().unwrap().get_by_key(b"key-1").unwrap();
+  assert_eq!(Some(&31), history.last());
+  drop(query);
+  drop(db);
+
+  // 末尾が欠けた索引は走査によって、失われた索引はすべてのエントリから再構築される
+  let length = keys.read().unwrap().len();
+  keys.write().unwrap().truncate(length - 20);
+  let db = open(&keys);
+  assert_eq!(history, db.query().unwrap().get_by_key(b"key-1").unwrap());
+  drop(db);
+  let db = open(&Arc::new(RwLock::new(Vec::new())));
+  assert_eq!(history, db.query().unwrap().get_by_key(b"key-1").unwrap());
+  let mut query = db.query().unwrap();
+  let record = query.get_record(31).unwrap().unwrap();
+  assert_eq!((Some(b"key-1".to_vec()), b"latest".to_vec()), (record.key, record.payload));
+  drop(query);
+  drop(db);
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  assert!(matches!(db.query().unwrap().get_by_key(b"key-1"), Err(Detail::NoKeyIndex)));
+}
+
+/// キー索引を指定したストレージにはレコード形式の値のみを追加でき、レコード形式が記録されていないストレージの
+/// レコードに似た値がキーとして索引されないことを検証します。
+#[test]
+fn test_key_index_rejects_plain_values() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let keys = Arc::new(MemStorage::new());
+  let db = LMTHT::builder(MemStorage::with(buffer.clone())).key_index(keys.clone()).open().unwrap();
+  db.append_with_key(b"key", b"value").unwrap();
+  assert!(matches!(db.append(b"raw"), Err(Detail::InvalidRecord { i: 2 })));
+  assert_eq!(vec![1], db.query().unwrap().get_by_key(b"key").unwrap());
+  assert_eq!(1, db.n());
+  drop(db);
+
+  // ヘッダに記録されたレコード形式はオプションを指定しなくても有効となる
+  let db = LMTHT::new(MemStorage::with(buffer)).unwrap();
+  assert!(matches!(db.append(b"raw"), Err(Detail::InvalidRecord { i: 2 })));
+  db.append_tagged(1, b"tagged").unwrap();
+  drop(db);
+
+  // レコード形式が記録されていない値を持つストレージには索引を指定できない
+  let mut plain = vec![RECORD_MARKER, FLAG_KEY];
+  plain.extend_from_slice(&3u32.to_le_bytes());
+  plain.extend_from_slice(b"key");
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  LMTHT::new(MemStorage::with(buffer.clone())).unwrap().append(&plain).unwrap();
+  let db = LMTHT::builder(MemStorage::with(buffer)).key_index(keys).open();
+  assert!(matches!(db, Err(Detail::RecordsNotReserved)));
+}
@@ -0,0 +1,99 @@
+//! ストレージのラッパーを積み重ねるための共通の方法です。
+//!
+//! [`Layer`] はストレージを受け取ってそれを包んだ新しいストレージを返す変換です。[`StorageExt::layer()`] を使用すると
+//! 下位のストレージから順にラッパーを積み重ねることができ、例えばリモートのストレージの上に再実行を、その上に
+//! キャッシュを配置するといった構成を一貫した方法で記述できます。
+//!
+//! ```rust
+//! use lmtht::{LMTHT, MemStorage, Storage};
+//! use lmtht::layer::{CacheLayer, RetryLayer, StorageExt};
+//!
+//! let storage = MemStorage::new().layer(RetryLayer::default()).layer(CacheLayer::default());
+//! assert!(storage.capabilities().truncatable);
+//! let db = LMTHT::new(storage).unwrap();
+//! ```
+//!
+//! すべてのラッパーは下位のストレージの [`Capabilities`](crate::Capabilities) をそのまま報告するため、積み重ねた
+//! 結果のストレージも最下位のストレージと同じ機能を報告します。複数のストレージを組み合わせる
+//! [`MirroredStorage`](crate::mirror::MirroredStorage) や [`QuorumStorage`](crate::quorum::QuorumStorage) は
+//! レイヤーではなく、それぞれのストレージを積み重ねた後に組み合わせます。
+//!
+use crate::cache::{CachedStorage, CachedStorageOptions};
+use crate::fault::FaultyStorage;
+use crate::retry::{RetryPolicy, RetryStorage};
+use crate::slow::{SlowStorage, SlowStorageOptions};
+use crate::Storage;
+
+#[cfg(test)]
+mod test;
+
+/// ストレージを包んだ新しいストレージを構築する変換です。
+pub trait Layer<S: Storage> {
+  /// 包んだ結果のストレージの型です。
+  type Storage: Storage;
+
+  /// `inner` を包んだストレージを構築します。
+  fn layer(self, inner: S) -> Self::Storage;
+}
+
+/// 2 つのレイヤーを順に適用します。最初のレイヤーが下位となります。
+impl<S: Storage, A: Layer<S>, B: Layer<A::Storage>> Layer<S> for (A, B) {
+  type Storage = B::Storage;
+  fn layer(self, inner: S) -> Self::Storage {
+    self.1.layer(self.0.layer(inner))
+  }
+}
+
+/// [`Storage`] にレイヤーを適用するメソッドを追加します。
+pub trait StorageExt: Storage + Sized {
+  /// このストレージを `layer` で包みます。
+  fn layer<L: Layer<Self>>(self, layer: L) -> L::Storage {
+    layer.layer(self)
+  }
+}
+
+impl<S: Storage> StorageExt for S {}
+
+/// [`CachedStorage`] で包むレイヤーです。
+#[derive(Clone, Debug, Default)]
+pub struct CacheLayer(pub CachedStorageOptions);
+
+impl<S: Storage> Layer<S> for CacheLayer {
+  type Storage = CachedStorage<S>;
+  fn layer(self, inner: S) -> CachedStorage<S> {
+    CachedStorage::with_options(inner, self.0)
+  }
+}
+
+/// [`RetryStorage`] で包むレイヤーです。
+#[derive(Clone, Debug, Default)]
+pub struct RetryLayer(pub RetryPolicy);
+
+impl<S: Storage> Layer<S> for RetryLayer {
+  type Storage = RetryStorage<S>;
+  fn layer(self, inner: S) -> RetryStorage<S> {
+    RetryStorage::new(inner, self.0)
+  }
+}
+
+/// [`SlowStorage`] で包むレイヤーです。
+#[derive(Clone, Debug, Default)]
+pub struct SlowLayer(pub SlowStorageOptions);
+
+impl<S: Storage> Layer<S> for SlowLayer {
+  type Storage = SlowStorage<S>;
+  fn layer(self, inner: S) -> SlowStorage<S> {
+    SlowStorage::with_options(inner, self.0)
+  }
+}
+
+/// [`FaultyStorage`] で包むレイヤーです。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FaultLayer;
+
+impl<S: Storage> Layer<S> for FaultLayer {
+  type Storage = FaultyStorage<S>;
+  fn layer(self, inner: S) -> FaultyStorage<S> {
+    FaultyStorage::new(inner)
+  }
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use crate::*;
+
+/// レイヤーで積み重ねたストレージのラッパーが互いに協調して動作し、最下位のストレージの機能を報告することを検証
+/// します。
+#[test]
+fn test_storage_layers() {
+  use crate::cache::CachedStorageOptions;
+  use crate::layer::{CacheLayer, FaultLayer, RetryLayer, StorageExt};
+  use crate::retry::RetryPolicy;
+
+  let retry = RetryPolicy { initial_backoff: Duration::from_millis(1), retryable: |_| true, ..Default::default() };
+  let cache = CacheLayer(CachedStorageOptions { block_size: 64, capacity: 1024 });
+  let storage = MemStorage::new().layer((FaultLayer, RetryLayer(retry))).layer(cache);
+  assert_eq!(MemStorage::new().capabilities(), storage.capabilities());
+  let db = LMTHT::new(storage).unwrap();
+  let faulty = db.storage().get_ref().get_ref();
+  for i in 1..=10u8 {
+    faulty.fail_nth_write(1);
+    db.append(&[i; 16]).unwrap();
+  }
+  faulty.fail_nth_read(1);
+  let mut query = db.query().unwrap();
+  for i in 1..=10u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+  let mut query = db.query().unwrap();
+  for i in 1..=10u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+  assert!(db.storage().hits() > 0);
+}
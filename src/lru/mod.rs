@@ -0,0 +1,76 @@
+//! 容量を超えた時点で最も長く参照されていない要素から破棄する LRU (Least Recently Used) キャッシュです。
+//!
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+#[cfg(test)]
+mod test;
+
+/// 最大 `capacity` 個の要素を保持する LRU キャッシュです。容量に 0 を指定した場合は何も保持しません。
+///
+/// 参照順序は単調増加するカウンタ値で管理しているため、要素の参照、追加、破棄はいずれも O(log n) で動作します。
+#[derive(Debug)]
+pub struct Lru<K: Hash + Eq + Clone, V> {
+  capacity: usize,
+  /// キーに対する値と最後に参照されたときのカウンタ値。
+  entries: HashMap<K, (V, u64)>,
+  /// カウンタ値に対するキー。最も小さいカウンタ値を持つキーが最も長く参照されていない要素です。
+  order: BTreeMap<u64, K>,
+  tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+  /// 指定された容量を持つ LRU キャッシュを構築します。
+  pub fn new(capacity: usize) -> Lru<K, V> {
+    Lru { capacity, entries: HashMap::new(), order: BTreeMap::new(), tick: 0 }
+  }
+
+  /// 指定されたキーに対する値を参照します。値が存在する場合、その要素は最も新しく参照されたものとして扱われます。
+  pub fn get(&mut self, key: &K) -> Option<&V> {
+    let tick = self.next_tick();
+    match self.entries.get_mut(key) {
+      Some((value, last)) => {
+        let key = self.order.remove(last).unwrap();
+        self.order.insert(tick, key);
+        *last = tick;
+        Some(value)
+      }
+      None => None,
+    }
+  }
+
+  /// 指定されたキーと値を保存します。同じキーの値がすでに存在する場合は置き換えます。容量を超える場合は最も長く参照
+  /// されていない要素を破棄します。
+  pub fn put(&mut self, key: K, value: V) {
+    if self.capacity == 0 {
+      return;
+    }
+    let tick = self.next_tick();
+    if let Some((_, last)) = self.entries.insert(key.clone(), (value, tick)) {
+      self.order.remove(&last);
+    }
+    self.order.insert(tick, key);
+    while self.entries.len() > self.capacity {
+      let oldest = *self.order.keys().next().unwrap();
+      let key = self.order.remove(&oldest).unwrap();
+      self.entries.remove(&key);
+    }
+  }
+
+  /// 指定された条件を満たさない要素をすべて破棄します。
+  pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) {
+    let order = &mut self.order;
+    self.entries.retain(|key, (value, last)| {
+      let retain = f(key, value);
+      if !retain {
+        order.remove(last);
+      }
+      retain
+    });
+  }
+
+  fn next_tick(&mut self) -> u64 {
+    self.tick += 1;
+    self.tick
+  }
+}
@@ -0,0 +1,30 @@
+use crate::lru::Lru;
+
+#[test]
+fn test_lru_evicts_least_recently_used() {
+  let mut lru = Lru::<u64, &str>::new(3);
+  lru.put(1, "a");
+  lru.put(2, "b");
+  lru.put(3, "c");
+
+  // 参照した要素は最も新しいものとして扱われる
+  assert_eq!(Some(&"a"), lru.get(&1));
+  lru.put(4, "d");
+  assert_eq!(None, lru.get(&2));
+  assert_eq!(Some(&"a"), lru.get(&1));
+  assert_eq!(Some(&"c"), lru.get(&3));
+  assert_eq!(Some(&"d"), lru.get(&4));
+
+  // 同じキーで置き換えた値は新しいものとして扱われる
+  lru.put(3, "e");
+  assert_eq!(Some(&"e"), lru.get(&3));
+  lru.put(5, "f");
+  assert_eq!(None, lru.get(&1));
+}
+
+#[test]
+fn test_lru_with_zero_capacity() {
+  let mut lru = Lru::<u64, u64>::new(0);
+  lru.put(1, 1);
+  assert_eq!(None, lru.get(&1));
+}
@@ -4,13 +4,64 @@ fn main() {
   let matches = clap::App::new("Logarithmic Multi-Tier Hash Tree")
     .version("1.0.0")
     .author("TAKAMI Torao <koiroha@gmail.com>")
+    .setting(clap::AppSettings::SubcommandsNegateReqs)
     .arg(
       clap::Arg::with_name("DATABASE")
         .required(true)
         .help("database")
     )
+    .subcommand(
+      clap::SubCommand::with_name("vectors")
+        .about("Writes conformance test vectors for other implementations as JSON")
+        .arg(clap::Arg::with_name("COUNT").long("count").takes_value(true).default_value("32").help("number of appends")),
+    )
+    .subcommand(
+      clap::SubCommand::with_name("import")
+        .about("Appends one entry per line of a newline-delimited JSON file to the database")
+        .arg(clap::Arg::with_name("FILE").required(true).help("input file, or - for standard input")),
+    )
     .get_matches();
-  if let Some(db) = matches.value_of("DATABASE") {
+  if let Some(matches) = matches.subcommand_matches("vectors") {
+    let n = match matches.value_of("COUNT").unwrap().parse::<lmtht::Index>() {
+      Ok(n) => n,
+      Err(err) => {
+        eprintln!("ERROR: invalid --count: {}", err);
+        std::process::exit(1);
+      }
+    };
+    let stdout = std::io::stdout();
+    if let Err(err) = lmtht::conformance::write_test_vectors(n, &mut stdout.lock()) {
+      eprintln!("ERROR: {}", err);
+      std::process::exit(1);
+    }
+  } else if let Some(sub) = matches.subcommand_matches("import") {
+    let db = match matches.value_of("DATABASE") {
+      Some(db) => db,
+      None => {
+        eprintln!("ERROR: DATABASE is required");
+        std::process::exit(1);
+      }
+    };
+    if let Err(err) = import(db, sub.value_of("FILE").unwrap()) {
+      eprintln!("ERROR: {}", err);
+      std::process::exit(1);
+    }
+  } else if let Some(db) = matches.value_of("DATABASE") {
     println!("DATABASE: {}", db);
   }
 }
+
+fn import(db: &str, file: &str) -> lmtht::Result<()> {
+  let db = lmtht::LMTHT::new(db)?;
+  let reader: Box<dyn std::io::BufRead> = match file {
+    "-" => Box::new(std::io::BufReader::new(std::io::stdin())),
+    file => Box::new(std::io::BufReader::new(std::fs::File::open(file)?)),
+  };
+  let report = |progress: &lmtht::import::Progress| eprintln!("{} lines, {} entries", progress.lines, progress.entries);
+  let progress = lmtht::import::ndjson_with(reader, &db, lmtht::import::DEFAULT_BATCH_SIZE, report)?;
+  match progress.root {
+    Some(root) => println!("{}", root),
+    None => println!("(empty)"),
+  }
+  Ok(())
+}
@@ -0,0 +1,160 @@
+//! ハッシュ木の上に構築した、検証可能なキー・バリューマップです。
+//!
+//! [`VerifiableMap::put()`] はキーを付加したレコード ([`Record::key`]) としてキーの更新をハッシュ木に追加し、
+//! [`VerifiableMap::get()`] はキーの最新の値をその更新がハッシュ木に含まれていることの証明とともに返します。
+//! キーの最新の更新は [`LMTHTOptions::key_index`](crate::LMTHTOptions::key_index) のキー索引から参照するため、
+//! キー索引を指定した LMTHT が必要です。
+//!
+//! [`VerifiableMap::get_with_absence()`] はさらに、その更新より後の世代 n までのすべてのエントリの証明を含め、
+//! 返された値より新しい更新が存在しないことを検証できるようにします。この証明は更新より後に追加されたエントリの
+//! 数に比例した大きさとなります。
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use lmtht::map::VerifiableMap;
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let db = LMTHT::builder("registry.db").key_index(Arc::new("registry.keys")).open()?;
+//! let map = VerifiableMap::new(db)?;
+//! map.put(b"alice", b"pubkey-1")?;
+//! # let trusted_root = map.lmtht().root_hash().unwrap();
+//! let entry = map.get(b"alice")?.unwrap();
+//! entry.verify(b"alice", &trusted_root)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use crate::error::Detail;
+use crate::record::Record;
+use crate::{Hash, Index, Node, Result, Storage, ValuesWithBranches, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// キーの更新をハッシュ木に追加する検証可能なキー・バリューマップです。
+pub struct VerifiableMap<S: Storage> {
+  db: LMTHT<S>,
+}
+
+/// [`VerifiableMap::get()`] で取得した、キーの最新の値とその証明です。
+#[derive(Debug)]
+pub struct MapEntry {
+  /// 証明の対象となる木構造の世代。
+  pub n: Index,
+  /// 値を更新したエントリのインデックス。
+  pub i: Index,
+  /// キーの最新の値。
+  pub value: Vec<u8>,
+  /// 更新したエントリが世代 `n` に含まれていることの証明。
+  pub proof: ValuesWithBranches,
+  /// [`VerifiableMap::get_with_absence()`] で取得した場合、`i + 1` から `n` までのそれぞれのエントリの証明。
+  pub later: Option<Vec<ValuesWithBranches>>,
+}
+
+impl<S: Storage> VerifiableMap<S> {
+  /// 指定された LMTHT の上にマップを構築します。
+  ///
+  /// # Errors
+  /// LMTHT にキー索引が指定されていない場合は [`Detail::NoKeyIndex`] を返します。
+  pub fn new(db: LMTHT<S>) -> Result<VerifiableMap<S>> {
+    if db.key_index.is_none() {
+      return Err(Detail::NoKeyIndex);
+    }
+    Ok(VerifiableMap { db })
+  }
+
+  /// このマップが値を保存している LMTHT を参照します。
+  pub fn lmtht(&self) -> &LMTHT<S> {
+    &self.db
+  }
+
+  /// このマップを破棄して LMTHT を返します。
+  pub fn into_inner(self) -> LMTHT<S> {
+    self.db
+  }
+
+  /// キー `key` の値を `value` に更新するエントリを追加し、追加後のルートノードを返します。
+  pub fn put(&self, key: &[u8], value: &[u8]) -> Result<Node> {
+    self.db.append_with_key(key, value)
+  }
+
+  /// キー `key` の最新の値を、その更新が現在の世代に含まれていることの証明とともに返します。キーが一度も更新されて
+  /// いない場合は `None` を返します。
+  pub fn get(&self, key: &[u8]) -> Result<Option<MapEntry>> {
+    self.get_entry(key, false)
+  }
+
+  /// [`VerifiableMap::get()`] の結果に加えて、更新より後のすべてのエントリの証明を含めて返します。
+  pub fn get_with_absence(&self, key: &[u8]) -> Result<Option<MapEntry>> {
+    self.get_entry(key, true)
+  }
+
+  fn get_entry(&self, key: &[u8], with_absence: bool) -> Result<Option<MapEntry>> {
+    let mut query = self.db.query()?;
+    let n = query.n();
+    let i = match query.get_by_key(key)?.last() {
+      Some(i) => *i,
+      None => return Ok(None),
+    };
+    let proof = query.get_with_hashes(i)?.ok_or(Detail::MapVerificationFailed { i })?;
+    let value = match proof.values.as_slice() {
+      [value] => Record::from_bytes(i, value.value.clone())?.payload,
+      _ => return Err(Detail::MapVerificationFailed { i }),
+    };
+    let later = if with_absence {
+      let mut later = Vec::with_capacity((n - i) as usize);
+      for k in i + 1..=n {
+        later.push(query.get_with_hashes(k)?.ok_or(Detail::MapVerificationFailed { i: k })?);
+      }
+      Some(later)
+    } else {
+      None
+    };
+    Ok(Some(MapEntry { n, i, value, proof, later }))
+  }
+}
+
+impl MapEntry {
+  /// この値がキー `key` の更新として、ルートハッシュが `root` である世代 `n` の木構造に含まれていることを検証
+  /// します。[`VerifiableMap::get_with_absence()`] で取得した場合は、更新より後のエントリにキー `key` の更新が
+  /// 含まれていないことも検証します。
+  ///
+  /// # Errors
+  /// 検証に失敗した場合は、一致しなかったエントリのインデックスとともに [`Detail::MapVerificationFailed`] を
+  /// 返します。
+  pub fn verify(&self, key: &[u8], root: &Hash) -> Result<()> {
+    let record = verify_proof(&self.proof, self.i, self.n, root)?;
+    match record {
+      Some(record) if record.key.as_deref() == Some(key) && record.payload == self.value => (),
+      _ => return Err(Detail::MapVerificationFailed { i: self.i }),
+    }
+    if let Some(later) = &self.later {
+      if later.len() != (self.n - self.i) as usize {
+        return Err(Detail::MapVerificationFailed { i: self.n });
+      }
+      for (k, proof) in later.iter().enumerate() {
+        let i = self.i + 1 + k as Index;
+        if let Some(record) = verify_proof(proof, i, self.n, root)? {
+          if record.key.as_deref() == Some(key) {
+            return Err(Detail::MapVerificationFailed { i });
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// `proof` がエントリ `i` の値のみを含み、ルートハッシュが `root` である世代 `n` の証明であることを検証し、値を
+/// レコードとして復元します。値がレコード形式でない場合は `None` を返します。
+fn verify_proof(proof: &ValuesWithBranches, i: Index, n: Index, root: &Hash) -> Result<Option<Record>> {
+  let computed = proof.root();
+  match proof.values.as_slice() {
+    [value] if value.i == i && computed.i == n && computed.hash == *root => {
+      Ok(Record::from_bytes(i, value.value.clone()).ok())
+    }
+    _ => Err(Detail::MapVerificationFailed { i }),
+  }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use crate::error::Detail;
+use crate::*;
+
+/// 検証可能なマップがキーの最新の値とその証明を返し、より新しい更新が存在しないことを検証できることを検証します。
+#[test]
+fn test_verifiable_map() {
+  use crate::map::VerifiableMap;
+  let result = VerifiableMap::new(LMTHT::builder(MemStorage::new()).open().unwrap());
+  assert!(matches!(result, Err(Detail::NoKeyIndex)));
+
+  let db = LMTHT::builder(MemStorage::new()).key_index(Arc::new(MemStorage::new())).open().unwrap();
+  let map = VerifiableMap::new(db).unwrap();
+  assert!(map.get(b"alice").unwrap().is_none());
+  map.put(b"alice", b"pubkey-1").unwrap();
+  map.put(b"bob", b"pubkey-2").unwrap();
+  map.put(b"alice", b"pubkey-3").unwrap();
+  map.lmtht().append_tagged(1, b"raw").unwrap();
+  map.put(b"bob", b"pubkey-4").unwrap();
+  let root = map.lmtht().root_hash().unwrap();
+
+  let entry = map.get(b"alice").unwrap().unwrap();
+  assert_eq!((5, 3, b"pubkey-3".to_vec()), (entry.n, entry.i, entry.value.clone()));
+  assert!(entry.later.is_none());
+  entry.verify(b"alice", &root).unwrap();
+  assert!(matches!(entry.verify(b"bob", &root), Err(Detail::MapVerificationFailed { i: 3 })));
+  assert!(matches!(entry.verify(b"alice", &Hash::hash(b"x")), Err(Detail::MapVerificationFailed { i: 3 })));
+
+  // 更新より後のエントリの証明によって新しい更新が存在しないことを検証する
+  let mut entry = map.get_with_absence(b"alice").unwrap().unwrap();
+  assert_eq!(2, entry.later.as_ref().unwrap().len());
+  entry.verify(b"alice", &root).unwrap();
+  entry.later.as_mut().unwrap().pop();
+  assert!(matches!(entry.verify(b"alice", &root), Err(Detail::MapVerificationFailed { i: 5 })));
+
+  // 古い値に新しい更新の証明を組み合わせても検証できない
+  let mut stale = map.get_with_absence(b"bob").unwrap().unwrap();
+  let mut query = map.lmtht().query().unwrap();
+  stale.i = 2;
+  stale.value = b"pubkey-2".to_vec();
+  stale.proof = query.get_with_hashes(2).unwrap().unwrap();
+  stale.later = Some((3..=5).map(|i| query.get_with_hashes(i).unwrap().unwrap()).collect());
+  assert!(matches!(stale.verify(b"bob", &root), Err(Detail::MapVerificationFailed { i: 5 })));
+}
@@ -0,0 +1,90 @@
+//! LMTHT とクエリーの動作を外部の監視システムに通知するためのフックです。
+//!
+//! [`LMTHTOptions::metrics`](crate::LMTHTOptions::metrics) に [`MetricsSink`] の実装を指定すると、追加や読み出し、
+//! シーク、キャッシュの参照、チェックサムの検証失敗のたびに対応するメソッドが呼び出されます。すべてのメソッドは
+//! 何もしないデフォルトの実装を持つため、必要なものだけを実装して任意のメトリクスライブラリに転送できます。
+//!
+//! メソッドは追加や読み出しを行うスレッドから同期的に呼び出されるため、実装は短時間で終了する必要があります。
+//!
+use std::time::Duration;
+
+use crate::error::Detail;
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// LMTHT の動作に関するカウンタとタイマーを受け取るトレイトです。
+pub trait MetricsSink: Send + Sync {
+  /// `count` 個の値の追加が合計 `bytes` バイトの書き込みとしてストレージに出力されたときに呼び出されます。`elapsed`
+  /// は書き込みと同期に要した時間です。
+  fn appended(&self, _count: u64, _bytes: u64, _elapsed: Duration) {}
+
+  /// クエリーが `bytes` バイトの値を読み出したときに呼び出されます。`elapsed` はエントリの探索と読み出しに要した
+  /// 時間です。
+  fn read(&self, _bytes: u64, _elapsed: Duration) {}
+
+  /// 読み出しのためにストレージのカーソルを移動したときに呼び出されます。
+  fn seek(&self) {}
+
+  /// `cache` の参照でキャッシュに存在する値が見つかったときに呼び出されます。
+  fn cache_hit(&self, _cache: CacheKind) {}
+
+  /// `cache` の参照でキャッシュに値が存在しなかったときに呼び出されます。
+  fn cache_miss(&self, _cache: CacheKind) {}
+
+  /// ストレージ上の `position` に位置するエントリのチェックサムの検証に失敗したときに呼び出されます。
+  fn checksum_failure(&self, _position: u64) {}
+
+  /// 操作の所要時間が [`LMTHTOptions::slow_operation_threshold`](crate::LMTHTOptions::slow_operation_threshold)
+  /// 以上であったときに呼び出されます。
+  fn slow_operation(&self, _operation: &SlowOperation) {}
+}
+
+/// 閾値を超えて時間を要した操作の詳細です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlowOperation {
+  /// 操作の種類。
+  pub kind: OperationKind,
+  /// 操作の所要時間。
+  pub elapsed: Duration,
+  /// 操作の間にストレージに対して行ったシークの回数。
+  pub seeks: u64,
+  /// 操作の間にストレージから読み込んだバイト数。
+  pub bytes_read: u64,
+}
+
+/// [`SlowOperation`] として通知される操作の種類です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+  /// 値の追加またはバッチのコミット。シークと読み込みには追加する値のエントリの構築に要したものを含みます。
+  Append,
+  /// [`Query::get_values_with_hashes()`](crate::Query::get_values_with_hashes) による証明の生成。
+  Proof,
+  /// LMTHT のオープン。
+  Open,
+}
+
+/// [`MetricsSink`] に通知されるキャッシュの種類です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+  /// エントリの位置をキーとした中間ノードのキャッシュ。
+  INodes,
+  /// インデックス i をキーとしたエントリの位置のキャッシュ。
+  Positions,
+  /// 世代とノードの組をキーとしたルートノードからの経路のキャッシュ。
+  Paths,
+}
+
+/// 何も通知しない [`MetricsSink`] です。メトリクスが指定されていない場合に使用します。
+pub(crate) struct NoMetrics;
+
+impl MetricsSink for NoMetrics {}
+
+/// `result` がチェックサムの検証失敗であれば `metrics` に通知します。
+pub(crate) fn observe<T>(metrics: &dyn MetricsSink, result: Result<T>) -> Result<T> {
+  if let Err(Detail::ChecksumVerificationFailed { at, .. }) = &result {
+    metrics.checksum_failure(*at);
+  }
+  result
+}
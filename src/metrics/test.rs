@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::error::Detail;
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// MetricsSink に追加、読み出し、キャッシュの参照、チェックサムの検証失敗が通知されることを検証します。
+#[test]
+fn test_metrics() {
+  use crate::metrics::{CacheKind, MetricsSink};
+
+  #[derive(Default)]
+  struct Recorder {
+    appended: AtomicUsize,
+    bytes: AtomicUsize,
+    reads: AtomicUsize,
+    seeks: AtomicUsize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    checksum_failures: AtomicUsize,
+  }
+  impl MetricsSink for Recorder {
+    fn appended(&self, count: u64, bytes: u64, _elapsed: Duration) {
+      self.appended.fetch_add(count as usize, Ordering::SeqCst);
+      self.bytes.fetch_add(bytes as usize, Ordering::SeqCst);
+    }
+    fn read(&self, _bytes: u64, _elapsed: Duration) {
+      self.reads.fetch_add(1, Ordering::SeqCst);
+    }
+    fn seek(&self) {
+      self.seeks.fetch_add(1, Ordering::SeqCst);
+    }
+    fn cache_hit(&self, _cache: CacheKind) {
+      self.hits.fetch_add(1, Ordering::SeqCst);
+    }
+    fn cache_miss(&self, _cache: CacheKind) {
+      self.misses.fetch_add(1, Ordering::SeqCst);
+    }
+    fn checksum_failure(&self, _position: u64) {
+      self.checksum_failures.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  let recorder = Arc::new(Recorder::default());
+  let options = LMTHTOptions { metrics: Some(recorder.clone()), ..Default::default() };
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options.clone()).unwrap();
+  for i in 1..=10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  assert_eq!(10, recorder.appended.load(Ordering::SeqCst));
+  assert_eq!(buffer.read().unwrap().len() - 4, recorder.bytes.load(Ordering::SeqCst));
+  drop(db);
+
+  // 再オープンした直後のキャッシュは空であり、2 度目の参照はキャッシュから行われる
+  let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options.clone()).unwrap();
+  let mut query = db.query().unwrap();
+  for _ in 0..2 {
+    for i in 1..=10 {
+      query.get(i).unwrap();
+    }
+  }
+  assert_eq!(20, recorder.reads.load(Ordering::SeqCst));
+  assert!(recorder.seeks.load(Ordering::SeqCst) >= 20);
+  assert!(recorder.hits.load(Ordering::SeqCst) > 0);
+  assert!(recorder.misses.load(Ordering::SeqCst) > 0);
+  assert_eq!(0, recorder.checksum_failures.load(Ordering::SeqCst));
+  drop(query);
+  drop(db);
+
+  // 末尾のエントリが破損していればオープンは失敗し、その検出が通知される
+  let length = buffer.read().unwrap().len();
+  buffer.write().unwrap()[length - 1] ^= 0xFF;
+  let result = LMTHT::with_options(MemStorage::with(buffer.clone()), options);
+  assert!(matches!(result, Err(Detail::DamagedStorage(_))));
+  assert_eq!(1, recorder.checksum_failures.load(Ordering::SeqCst));
+}
+
+/// 閾値を超えた追加、証明の生成、オープンがその詳細とともに通知されることを検証します。
+#[test]
+fn test_slow_operation() {
+  use crate::metrics::{MetricsSink, OperationKind, SlowOperation};
+
+  #[derive(Default)]
+  struct Recorder(Mutex<Vec<SlowOperation>>);
+  impl MetricsSink for Recorder {
+    fn slow_operation(&self, operation: &SlowOperation) {
+      self.0.lock().unwrap().push(*operation);
+    }
+  }
+  let take = |recorder: &Recorder| std::mem::take(&mut *recorder.0.lock().unwrap());
+
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  drop(db);
+
+  // 閾値を超えない操作は通知されない
+  let recorder = Arc::new(Recorder::default());
+  let options = LMTHTOptions {
+    metrics: Some(recorder.clone()),
+    slow_operation_threshold: Some(Duration::from_secs(3600)),
+    ..Default::default()
+  };
+  let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options).unwrap();
+  db.append(&random_payload(PAYLOAD_SIZE, 11)).unwrap();
+  db.query().unwrap().get_values_with_hashes(1, 0).unwrap();
+  assert!(take(&recorder).is_empty());
+  drop(db);
+
+  let options = LMTHTOptions {
+    metrics: Some(recorder.clone()),
+    slow_operation_threshold: Some(Duration::ZERO),
+    ..Default::default()
+  };
+  let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options).unwrap();
+  let operations = take(&recorder);
+  assert_eq!(vec![OperationKind::Open], operations.iter().map(|op| op.kind).collect::<Vec<_>>());
+  assert!(operations[0].seeks > 0 && operations[0].bytes_read > 0);
+
+  db.append(&random_payload(PAYLOAD_SIZE, 12)).unwrap();
+  let operations = take(&recorder);
+  assert_eq!(vec![OperationKind::Append], operations.iter().map(|op| op.kind).collect::<Vec<_>>());
+
+  let mut query = db.query().unwrap();
+  query.get_values_with_hashes(1, 0).unwrap().unwrap();
+  let operations = take(&recorder);
+  assert_eq!(vec![OperationKind::Proof], operations.iter().map(|op| op.kind).collect::<Vec<_>>());
+  assert!(operations[0].seeks > 0 && operations[0].bytes_read > 0);
+}
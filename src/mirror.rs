@@ -0,0 +1,250 @@
+//! すべての書き込みを 2 つのストレージに行い、読み込みをプライマリから行うストレージです。
+//!
+//! [`MirroredStorage`] は書き込み用のカーソルに対する書き込み、切り詰め、同期をプライマリとセカンダリの両方に行い
+//! ます。読み込みはプライマリからのみ行われます。レプリケーションを運用できない環境で、別の媒体に安価な第二の複製を
+//! 保持するために使用します。
+//!
+//! セカンダリへの書き込みが失敗した場合の扱いは [`MirrorPolicy`] で指定します。[`MirrorPolicy::DegradeSecondary`]
+//! ではセカンダリを縮退状態として以降の書き込みを停止するため、[`MirroredStorage::resync()`] でプライマリの内容を
+//! 複製し直すまでセカンダリは古い内容のまま残ります。[`MirrorPolicy::FailOnDivergence`] では最初の失敗以降も
+//! 再同期するまですべての書き込みがエラーとなります。
+//!
+//! ```rust
+//! use lmtht::{LMTHT, MemStorage};
+//! use lmtht::mirror::{MirroredStorage, MirrorPolicy};
+//! let storage = MirroredStorage::new(MemStorage::new(), MemStorage::new(), MirrorPolicy::FailOnDivergence).unwrap();
+//! let db = LMTHT::new(storage).unwrap();
+//! ```
+//!
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::Detail;
+use crate::{Capabilities, Cursor, IoCounts, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// プライマリとセカンダリの内容が食い違った場合の扱いです。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MirrorPolicy {
+  /// セカンダリへの書き込みが失敗した場合は操作をエラーにし、[`MirroredStorage::resync()`] を呼び出すまで以降の
+  /// 書き込みもエラーにします。また構築時や書き込み用のオープン時に 2 つのストレージが食い違っている場合は
+  /// [`Detail::MirrorDiverged`] で失敗します。
+  FailOnDivergence,
+  /// セカンダリへの書き込みが失敗した場合や構築時に長さが異なる場合はセカンダリを縮退状態とし、プライマリのみで
+  /// 動作を続けます。
+  DegradeSecondary,
+}
+
+/// 2 つのストレージに同じ内容を書き込むストレージです。
+pub struct MirroredStorage<A: Storage, B: Storage> {
+  primary: A,
+  secondary: B,
+  policy: MirrorPolicy,
+  degraded: Arc<AtomicBool>,
+}
+
+impl<A: Storage, B: Storage> MirroredStorage<A, B> {
+  /// `primary` と `secondary` に書き込むストレージを構築します。2 つのストレージの長さが異なる場合は `policy` に
+  /// 従ってエラーとするかセカンダリを縮退状態とします。
+  pub fn new(primary: A, secondary: B, policy: MirrorPolicy) -> Result<MirroredStorage<A, B>> {
+    let length = primary.open(false)?.seek(SeekFrom::End(0))?;
+    let secondary_length = secondary.open(false)?.seek(SeekFrom::End(0))?;
+    let degraded = length != secondary_length;
+    if degraded && policy == MirrorPolicy::FailOnDivergence {
+      return Err(Detail::MirrorDiverged { primary: length, secondary: secondary_length });
+    }
+    Ok(MirroredStorage { primary, secondary, policy, degraded: Arc::new(AtomicBool::new(degraded)) })
+  }
+
+  /// プライマリのストレージを参照します。
+  pub fn primary(&self) -> &A {
+    &self.primary
+  }
+
+  /// セカンダリのストレージを参照します。
+  pub fn secondary(&self) -> &B {
+    &self.secondary
+  }
+
+  /// セカンダリが縮退状態にあり、プライマリと同じ内容を保持していない可能性があるかを判定します。
+  pub fn is_degraded(&self) -> bool {
+    self.degraded.load(Ordering::Acquire)
+  }
+
+  /// プライマリの内容をセカンダリに複製し直し、縮退状態を解除します。複製の間に書き込みが行われないよう、LMTHT を
+  /// 使用していない時点で呼び出す必要があります。
+  pub fn resync(&self) -> Result<()> {
+    let mut source = self.primary.open(false)?;
+    let mut target = self.secondary.open(true)?;
+    source.seek(SeekFrom::Start(0))?;
+    target.set_len(0)?;
+    target.seek(SeekFrom::Start(0))?;
+    io::copy(&mut source, &mut target)?;
+    target.sync_data()?;
+    self.degraded.store(false, Ordering::Release);
+    Ok(())
+  }
+}
+
+impl<A: Storage, B: Storage> Storage for MirroredStorage<A, B> {
+  type Cursor = MirroredCursor<A::Cursor, B::Cursor>;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    if writable && self.is_degraded() && self.policy == MirrorPolicy::FailOnDivergence {
+      let length = self.primary.open(false)?.seek(SeekFrom::End(0))?;
+      let secondary_length = self.secondary.open(false)?.seek(SeekFrom::End(0))?;
+      return Err(Detail::MirrorDiverged { primary: length, secondary: secondary_length });
+    }
+    let primary = self.primary.open(writable)?;
+    let secondary = if writable && !self.is_degraded() {
+      match self.secondary.open(true) {
+        Ok(cursor) => Some(cursor),
+        Err(_) if self.policy == MirrorPolicy::DegradeSecondary => {
+          self.degraded.store(true, Ordering::Release);
+          None
+        }
+        Err(err) => return Err(err),
+      }
+    } else {
+      None
+    };
+    Ok(MirroredCursor {
+      primary,
+      secondary,
+      writable,
+      policy: self.policy,
+      degraded: self.degraded.clone(),
+      position: 0,
+    })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    let (primary, secondary) = (self.primary.capabilities(), self.secondary.capabilities());
+    Capabilities {
+      writable: primary.writable && secondary.writable,
+      truncatable: primary.truncatable && secondary.truncatable,
+      durable: primary.durable,
+    }
+  }
+}
+
+/// [`MirroredStorage`] が使用するカーソルです。
+pub struct MirroredCursor<A: Cursor, B: Cursor> {
+  primary: A,
+  /// 書き込み用のカーソルでのみ使用するセカンダリのカーソル。
+  secondary: Option<B>,
+  /// 書き込み用にオープンされたカーソルか。
+  writable: bool,
+  policy: MirrorPolicy,
+  degraded: Arc<AtomicBool>,
+  /// プライマリのカーソルの現在の位置。
+  position: u64,
+}
+
+impl<A: Cursor, B: Cursor> MirroredCursor<A, B> {
+  /// セカンダリに対して `f` を実行します。セカンダリが縮退状態の場合は [`MirrorPolicy::FailOnDivergence`] であれば
+  /// エラーを返し、そうでなければ何も行いません。失敗した場合はポリシーに従ってエラーを返すか、セカンダリを縮退
+  /// 状態にします。
+  fn mirror<F: FnOnce(&mut B) -> io::Result<()>>(&mut self, f: F) -> io::Result<()> {
+    if self.degraded.load(Ordering::Acquire) {
+      self.secondary = None;
+      if self.writable && self.policy == MirrorPolicy::FailOnDivergence {
+        return Err(io::Error::other("the secondary storage has diverged; resync is required"));
+      }
+    }
+    let secondary = match self.secondary.as_mut() {
+      Some(secondary) => secondary,
+      None => return Ok(()),
+    };
+    match f(secondary) {
+      Ok(()) => Ok(()),
+      Err(err) => {
+        self.secondary = None;
+        self.degraded.store(true, Ordering::Release);
+        match self.policy {
+          MirrorPolicy::FailOnDivergence => Err(err),
+          MirrorPolicy::DegradeSecondary => Ok(()),
+        }
+      }
+    }
+  }
+}
+
+impl<A: Cursor, B: Cursor> Cursor for MirroredCursor<A, B> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.primary.sync_data()?;
+    self.mirror(|secondary| secondary.sync_data())
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.primary.set_len(length)?;
+    self.mirror(|secondary| secondary.set_len(length))
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.primary.len()
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.primary.discard(position, length)?;
+    self.position = position + length;
+    self.mirror(|secondary| secondary.discard(position, length))
+  }
+
+  fn io_counts(&self) -> IoCounts {
+    self.primary.io_counts()
+  }
+
+  fn duplicate(&self) -> io::Result<Option<Self>> {
+    // プライマリとセカンダリの双方が複製に対応している場合のみ複製できる
+    let primary = match self.primary.duplicate()? {
+      Some(primary) => primary,
+      None => return Ok(None),
+    };
+    let secondary = match &self.secondary {
+      Some(secondary) => match secondary.duplicate()? {
+        Some(secondary) => Some(secondary),
+        None => return Ok(None),
+      },
+      None => None,
+    };
+    let (writable, policy, degraded) = (self.writable, self.policy, self.degraded.clone());
+    Ok(Some(MirroredCursor { primary, secondary, writable, policy, degraded, position: 0 }))
+  }
+}
+
+impl<A: Cursor, B: Cursor> Seek for MirroredCursor<A, B> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    self.position = self.primary.seek(pos)?;
+    Ok(self.position)
+  }
+}
+
+impl<A: Cursor, B: Cursor> Read for MirroredCursor<A, B> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let length = self.primary.read(buf)?;
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<A: Cursor, B: Cursor> Write for MirroredCursor<A, B> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let position = self.position;
+    let length = self.primary.write(buf)?;
+    self.position += length as u64;
+    self.mirror(|secondary| {
+      secondary.seek(SeekFrom::Start(position))?;
+      secondary.write_all(&buf[..length])
+    })?;
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.primary.flush()?;
+    self.mirror(|secondary| secondary.flush())
+  }
+}
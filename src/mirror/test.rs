@@ -0,0 +1,114 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::*;
+
+/// `MirroredStorage` が 2 つのストレージに同じ内容を書き込み、セカンダリの障害をポリシーに従って扱うことを検証します。
+#[test]
+fn test_mirrored_storage() {
+  use crate::fault::FaultyStorage;
+  use crate::mirror::{MirrorPolicy, MirroredStorage};
+
+  let (primary, secondary) = (Arc::new(RwLock::new(Vec::new())), Arc::new(RwLock::new(Vec::new())));
+  let storage = MirroredStorage::new(
+    MemStorage::with(primary.clone()),
+    FaultyStorage::new(MemStorage::with(secondary.clone())),
+    MirrorPolicy::DegradeSecondary,
+  )
+  .unwrap();
+  let db = LMTHT::new(storage).unwrap();
+  for i in 1..=10u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  db.sync().unwrap();
+  assert!(!db.storage().is_degraded());
+  assert_eq!(*primary.read().unwrap(), *secondary.read().unwrap());
+
+  // セカンダリへの書き込みが失敗してもプライマリへの追加は継続する
+  db.storage().secondary().fail_nth_write(1);
+  for i in 11..=20u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  assert!(db.storage().is_degraded());
+  assert_ne!(*primary.read().unwrap(), *secondary.read().unwrap());
+  let mut query = db.query().unwrap();
+  for i in 1..=20u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+
+  // 再同期によってセカンダリはプライマリと同じ内容になる
+  db.storage().resync().unwrap();
+  assert!(!db.storage().is_degraded());
+  assert_eq!(*primary.read().unwrap(), *secondary.read().unwrap());
+
+  // 長さの異なるストレージは食い違いとして扱われる
+  let diverged =
+    MirroredStorage::new(MemStorage::with(primary.clone()), MemStorage::new(), MirrorPolicy::FailOnDivergence);
+  assert!(matches!(diverged, Err(Detail::MirrorDiverged { secondary: 0, .. })));
+}
+
+/// `MirrorPolicy::FailOnDivergence` ではセカンダリへの書き込みが一度失敗すると、再同期するまで以降の書き込みも
+/// エラーとなることを検証します。
+#[test]
+fn test_mirror_fail_on_divergence() {
+  use std::io::{Seek, SeekFrom, Write};
+
+  use crate::fault::FaultyStorage;
+  use crate::mirror::{MirrorPolicy, MirroredStorage};
+
+  let (primary, secondary) = (Arc::new(RwLock::new(Vec::new())), Arc::new(RwLock::new(Vec::new())));
+  let storage = MirroredStorage::new(
+    MemStorage::with(primary.clone()),
+    FaultyStorage::new(MemStorage::with(secondary.clone())),
+    MirrorPolicy::FailOnDivergence,
+  )
+  .unwrap();
+  let mut cursor = storage.open(true).unwrap();
+  cursor.write_all(&[1; 16]).unwrap();
+
+  // 最初の失敗の後も書き込みはセカンダリに反映されないままエラーとなり続ける
+  storage.secondary().fail_nth_write(1);
+  assert!(cursor.write_all(&[2; 16]).is_err());
+  assert!(storage.is_degraded());
+  assert!(cursor.write_all(&[3; 16]).is_err());
+  assert!(cursor.flush().is_err());
+  assert!(matches!(storage.open(true), Err(Detail::MirrorDiverged { secondary: 16, .. })));
+  assert!(storage.open(false).is_ok());
+  drop(cursor);
+
+  // 再同期の後は再び両方に書き込まれる
+  storage.resync().unwrap();
+  assert!(!storage.is_degraded());
+  let mut cursor = storage.open(true).unwrap();
+  cursor.seek(SeekFrom::End(0)).unwrap();
+  cursor.write_all(&[4; 16]).unwrap();
+  cursor.flush().unwrap();
+  assert_eq!(*primary.read().unwrap(), *secondary.read().unwrap());
+
+  // LMTHT への追加も失敗した後は再同期するまで失敗し続ける
+  let (primary, secondary) = (Arc::new(RwLock::new(Vec::new())), Arc::new(RwLock::new(Vec::new())));
+  let storage = MirroredStorage::new(
+    MemStorage::with(primary.clone()),
+    FaultyStorage::new(MemStorage::with(secondary.clone())),
+    MirrorPolicy::FailOnDivergence,
+  )
+  .unwrap();
+  let db = LMTHT::new(storage).unwrap();
+  db.append(&[1; 16]).unwrap();
+  db.storage().secondary().fail_nth_write(1);
+  assert!(db.append(&[2; 16]).is_err());
+  assert!(db.append(&[3; 16]).is_err());
+  assert!(db.storage().is_degraded());
+  db.storage().resync().unwrap();
+  drop(db);
+  let storage = MirroredStorage::new(
+    MemStorage::with(primary.clone()),
+    FaultyStorage::new(MemStorage::with(secondary.clone())),
+    MirrorPolicy::FailOnDivergence,
+  )
+  .unwrap();
+  let db = LMTHT::new(storage).unwrap();
+  db.append(&[4; 16]).unwrap();
+  db.sync().unwrap();
+  assert_eq!(*primary.read().unwrap(), *secondary.read().unwrap());
+}
@@ -1,30 +1,44 @@
-use std::fmt::Debug;
-use std::ops::RangeInclusive;
+use core::fmt::Debug;
+use core::ops::RangeInclusive;
 
-#[cfg(test)]
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+#[cfg(all(test, feature = "std"))]
 mod test;
 
 /// LMTHT がインデックス i として使用する整数の型です。`u64` を表しています。
 ///
 /// 64-bit がアプリケーションへの適用に大きすぎる場合 `small_index` feature を指定することで `u32` に変更する
-/// ことができます。
+/// ことができます。複数のソースを 1 つのログに集約して 2⁶⁴ 個を超える要素を扱う場合や疎な識別子を使用する場合は
+/// `large_index` feature を指定することで `u128` に変更することができます。
 ///
-#[cfg(not(feature = "small_index"))]
+#[cfg(not(any(feature = "small_index", feature = "large_index")))]
 pub type Index = u64;
 
 #[cfg(feature = "small_index")]
 pub type Index = u32;
 
+#[cfg(feature = "large_index")]
+pub type Index = u128;
+
+#[cfg(all(feature = "small_index", feature = "large_index"))]
+compile_error!("features `small_index` and `large_index` are mutually exclusive");
+
 /// [`Index`] 型のビット幅です。定数 64 を表しています。
 ///
-/// コンパイル時に `small_index` feature を指定することでこの定数は 32 となります。
+/// コンパイル時に `small_index` feature を指定することでこの定数は 32、`large_index` feature を指定することで
+/// 128 となります。
 ///
-#[cfg(not(feature = "small_index"))]
+#[cfg(not(any(feature = "small_index", feature = "large_index")))]
 pub const INDEX_SIZE: u8 = 64;
 
 #[cfg(feature = "small_index")]
 pub const INDEX_SIZE: u8 = 32;
 
+#[cfg(feature = "large_index")]
+pub const INDEX_SIZE: u8 = 128;
+
 /// LMTHT のアルゴリズムで使用する任意のノード b_{i,j} を表すための構造体です。
 ///
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
@@ -127,76 +141,27 @@ impl NthGenHashTree {
     inodes
   }
 
-  /// 一過性の中間ノードをたどって b_{i,j} を含む完全二分木のルートノードを検索します。ノードを 1 ステップ進むたびに
-  /// `on_step(from, to)` のコールバックが行われます。b_{i,j} が一過性の中間ノードの場合 `Either::Right(node)`
-  /// を返し、b_{i,j} を含む完全二分木のルートノードの場合 `Either::Left(root)` を返します。
-  pub fn path_to(&self, i: Index, j: u8) -> Option<Path> {
-    let root = self.root();
-    if !contains(root.i, root.j, i) {
-      return None;
-    }
-
-    // 一過性の中間ノードをたどって b_{i,j} を含む完全二分木のルートノードを検索する
-    let mut steps = Vec::<Step>::with_capacity(INDEX_SIZE as usize);
-    let pbst = if let Some(pbst) = self.ephemeral_nodes.last().map(|i| i.right) {
-      let mut pbst = pbst;
-      for x in 0..self.ephemeral_nodes.len() {
-        let node = &self.ephemeral_nodes[x];
-
-        // 目的のノードを検出した場合
-        if node.node.i == i && node.node.j == j {
-          return Some(Path { root, steps });
-        }
-
-        // 左枝側 (完全二分木) に含まれている場合は左枝を始点に設定
-        if contains(node.left.i, node.left.j, i) {
-          steps.push(Step { step: node.left, neighbor: node.right });
-          pbst = node.left;
-          break;
-        }
-
-        // このノードの次のステップを保存
-        steps.push(Step { step: node.right, neighbor: node.left });
+  /// この木構造 𝑇ₙ に含まれるすべてのノードをルートから左枝優先の深さ優先順で列挙します。それぞれの要素はノード
+  /// b_{i,j} とその左右の子ノードの組で、葉ノードの場合は子ノードが `None` となります。ストレージを参照せずに木構造
+  /// の可視化やストレージ上のデータとの照合を行うことができます。
+  pub fn nodes(&self) -> impl Iterator<Item = (Node, Option<(Node, Node)>)> + '_ {
+    let mut stack = vec![self.root()];
+    core::iter::from_fn(move || {
+      let node = stack.pop()?;
+      let children = children(node.i, node.j).map(|inode| (inode.left, inode.right));
+      if let Some((left, right)) = children {
+        stack.push(right);
+        stack.push(left);
       }
-      pbst
-    } else {
-      root
-    };
-
-    if pbst.i == i && pbst.j == j {
-      return Some(Path { root, steps });
-    } else if pbst.j < j {
-      return None;
-    }
+      Some((node, children))
+    })
+  }
 
-    // 完全二分木上の経路を構築
-    let mut mover = Self::pbst_inode(pbst.i, pbst.j);
-    for _ in 0..INDEX_SIZE {
-      // 目的のノードを検出した場合
-      if mover.node.i == i && mover.node.j == j {
-        return Some(Path { root, steps });
-      }
-      let (next, neighbor) = if contains(mover.left.i, mover.left.j, i) {
-        (mover.left, mover.right)
-      } else {
-        debug_assert!(
-          contains(mover.right.i, mover.right.j, i),
-          "the subtree T_{{{},{}}} doesn't contain node b_{{{},{}}}",
-          mover.right.i,
-          mover.right.j,
-          i,
-          j
-        );
-        (mover.right, mover.left)
-      };
-      steps.push(Step { step: next, neighbor });
-      if next.j != 0 {
-        mover = Self::pbst_inode(next.i, next.j);
-      } else {
-        return Some(Path { root, steps });
-      }
-    }
-    unreachable!("maximum step was reached in searching the route to ({}, {}) -> {:?}", i, j, steps)
+  /// この木構造のルートノードから b_{i,j} までの経路を算出します。経路の各ステップには経路から分岐したノードが含まれて
+  /// います。b_{i,j} がこの木構造に含まれていない場合は `None` を返します。
+  pub fn path_to(&self, i: Index, j: u8) -> Option<Path> {
+    let root = self.root();
+    path((root.i, root.j), (i, j))
   }
 
   /// 指定された中間ノード b_{i,j} を返します。該当する中間ノードが存在しない場合は `None` を返します。
@@ -250,11 +215,72 @@ impl NthGenHashTree {
   }
 }
 
+/// ノード b_{i,j} から、その部分木に含まれるノード b_{k,l} までの経路を算出します。返値の `root` は b_{i,j} で、
+/// 各ステップは経路上の次のノードとそのステップで経路から分岐したノードを持ちます。b_{i,j} と b_{k,l} が同一の場合
+/// ステップは空となります。
+///
+/// ある世代の木構造に含まれる部分木の形はその部分木のルートノードのみで決定するため、この経路は世代 n に依存しません。
+/// b_{i,j} または b_{k,l} がどの世代の木構造にも存在しないノードの場合や、b_{k,l} が b_{i,j} の部分木に含まれて
+/// いない場合は `None` を返します。
+pub fn path(from: (Index, u8), to: (Index, u8)) -> Option<Path> {
+  let ((i, j), (k, l)) = (from, to);
+  let root = Node::new(i, j);
+  if i == 0 || (j != 0 && children(i, j).is_none()) {
+    return None;
+  }
+
+  let mut steps = Vec::<Step>::with_capacity(j as usize);
+  let mut mover = root;
+  loop {
+    // 目的のノードを検出した場合
+    if mover.i == k && mover.j == l {
+      return Some(Path { root, steps });
+    } else if mover.j <= l {
+      return None;
+    }
+
+    // 目的のノードを含む枝を次のステップとして保存
+    let inode = children(mover.i, mover.j)?;
+    let (next, neighbor) = if contains(inode.left.i, inode.left.j, k) {
+      (inode.left, inode.right)
+    } else if contains(inode.right.i, inode.right.j, k) {
+      (inode.right, inode.left)
+    } else {
+      return None;
+    };
+    steps.push(Step { step: next, neighbor });
+    mover = next;
+  }
+}
+
+/// 中間ノード b_{i,j} の左右の枝を算出します。b_{i,j} が葉ノードの場合やどの世代の木構造にも存在しないノードの
+/// 場合は `None` を返します。
+///
+/// b_{i,j} をルートとする部分木が完全二分木の場合は左右の枝も同じ高さの完全二分木となります。完全二分木でない場合
+/// b_{i,j} は 𝑇ᵢ の一過性の中間ノードであり、左枝は高さ j-1 の完全二分木、右枝は残りの葉ノードを含む部分木です。
+pub fn children(i: Index, j: u8) -> Option<INode> {
+  if i == 0 || j == 0 {
+    None
+  } else if is_pbst(i, j) {
+    Some(NthGenHashTree::pbst_inode(i, j))
+  } else {
+    // 一過性の中間ノードは高さ j-1 の完全二分木を左枝に、空でない部分木を右枝に持つ
+    let half = low_bits(j - 1) + 1;
+    if i & half == 0 || i & (half - 1) == 0 {
+      return None;
+    }
+    let left_i = (i & !low_bits(j)) + half;
+    let left = Node::new(left_i, j - 1);
+    let right = Node::new(i, ceil_log2(i - left_i));
+    Some(INode::new(Node::new(i, j), left, right))
+  }
+}
+
 /// 指定されたノード b_{i,j} をルートとする部分木に含まれる葉ノード b_ℓ の範囲を算出します。
 #[inline]
 pub fn range(i: Index, j: u8) -> RangeInclusive<Index> {
-  debug_assert!(j <= 64); // i=u64::MAX のとき j=64
-  let i_min = (((i as u128 >> j) - (if is_pbst(i, j) { 1 } else { 0 })) << j) as u64 + 1;
+  debug_assert!(j <= INDEX_SIZE); // i=Index::MAX のとき j=INDEX_SIZE
+  let i_min = if is_pbst(i, j) { i - low_bits(j) } else { (i & !low_bits(j)) + 1 };
   let i_max = i;
   i_min..=i_max
 }
@@ -263,14 +289,24 @@ pub fn range(i: Index, j: u8) -> RangeInclusive<Index> {
 /// T_{i,j} の部分木かを判定することと意味的に同じです。
 #[inline]
 pub fn contains(i: Index, j: u8, k: Index) -> bool {
-  debug_assert!(j <= 64); // i=u64::MAX のとき j=64
+  debug_assert!(j <= INDEX_SIZE); // i=Index::MAX のとき j=INDEX_SIZE
   range(i, j).contains(&k)
 }
 
 /// 指定されたノード b_{i,j} をルートとする部分木が完全二分木であるかを判定します。
 #[inline]
 pub fn is_pbst(i: Index, j: u8) -> bool {
-  i & (((1u128 << j) - 1) as u64) == 0
+  i & low_bits(j) == 0
+}
+
+/// 下位 `j` ビットがすべて 1 の値を返します。`j` が [`INDEX_SIZE`] 以上の場合はすべてのビットが 1 となります。
+#[inline]
+fn low_bits(j: u8) -> Index {
+  if j >= INDEX_SIZE {
+    Index::MAX
+  } else {
+    (1 << j) - 1
+  }
 }
 
 /// 指定された `x` に対して `𝑦=⌈log₂ 𝑥⌉` を求めます。返値は 0 (x=1) から 64 (x=u64::MAX) の範囲となります。
@@ -285,30 +321,7 @@ pub fn ceil_log2(x: Index) -> u8 {
 /// `x` に 0 を指定することはできません。
 #[inline]
 pub fn floor_log2(x: Index) -> u8 {
-  // まずビット列の中で最も上位に存在する 1 の位置より右側のすべてのビットが 1 となるようにビット論理和を繰り返し、
-  // 次に数値内で 1 となっているビットの数を数えるというアプローチ (可能であれば後半は POPCNT CPU 命令が使う方が
-  // 良いかもしれない)。
-  // See also: https://qiita.com/pochman/items/d74930a62613bb8d3d00,
-  // http://www.nminoru.jp/~nminoru/programming/bitcount.html
+  // Index のビット幅は feature によって異なるため、最上位に存在する 1 の位置を leading_zeros() から求める
   debug_assert!(x > 0);
-  let mut bits = x;
-  bits = bits | (bits >> 1);
-  bits = bits | (bits >> 2);
-  bits = bits | (bits >> 4);
-  bits = bits | (bits >> 8);
-  bits = bits | (bits >> 16);
-  bits = bits | (bits >> 32);
-  bits = (bits & 0b0101010101010101010101010101010101010101010101010101010101010101)
-    + (bits >> 1 & 0b0101010101010101010101010101010101010101010101010101010101010101);
-  bits = (bits & 0b0011001100110011001100110011001100110011001100110011001100110011)
-    + (bits >> 2 & 0b0011001100110011001100110011001100110011001100110011001100110011);
-  bits = (bits & 0b0000111100001111000011110000111100001111000011110000111100001111)
-    + (bits >> 4 & 0b0000111100001111000011110000111100001111000011110000111100001111);
-  bits = (bits & 0b0000000011111111000000001111111100000000111111110000000011111111)
-    + (bits >> 8 & 0b0000000011111111000000001111111100000000111111110000000011111111);
-  bits = (bits & 0b0000000000000000111111111111111100000000000000001111111111111111)
-    + (bits >> 16 & 0b0000000000000000111111111111111100000000000000001111111111111111);
-  bits = (bits & 0b0000000000000000000000000000000011111111111111111111111111111111)
-    + (bits >> 32 & 0b0000000000000000000000000000000011111111111111111111111111111111);
-  bits as u8 - 1
+  INDEX_SIZE - 1 - x.leading_zeros() as u8
 }
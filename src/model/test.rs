@@ -1,7 +1,7 @@
 use std::iter::FromIterator;
 
-use crate::model::{ceil_log2, floor_log2, Node, NthGenHashTree, Path, Step};
-use crate::Index;
+use crate::model::{ceil_log2, children, floor_log2, path, range, Node, NthGenHashTree, Path, Step};
+use crate::{Index, INDEX_SIZE};
 
 #[test]
 #[should_panic]
@@ -15,17 +15,17 @@ fn test_generation() {
   let pbt = |j: u8| (1 << j, (1..=j).rev().collect::<Vec<u8>>(), vec![], vec![(1 << j, j)]);
 
   // 高さ j の完全二分木となる手前のケース
-  let pre_pbt = |j: u8| -> (u64, Vec<u8>, Vec<u8>, Vec<(u64, u8)>) {
+  let pre_pbt = |j: u8| -> (Index, Vec<u8>, Vec<u8>, Vec<(Index, u8)>) {
     let mut ephemerals = (1..=j).rev().collect::<Vec<u8>>();
     ephemerals.remove(ephemerals.len() - 1);
     let pbsts = (0..j)
       .rev()
       .map(|j2| {
-        let offset = ((j2 + 1)..j).map(|x| 1u64 << x).sum::<u64>();
+        let offset = ((j2 + 1)..j).map(|x| 1 << x).sum::<Index>();
         (offset + (1 << j2), j2)
       })
       .collect();
-    let i = if j == 64 { 0xFFFFFFFFFFFFFFFFu64 } else { (1u64 << j) - 1 };
+    let i = if j == INDEX_SIZE { Index::MAX } else { (1 << j) - 1 };
     (i, ephemerals.clone(), ephemerals, pbsts)
   };
 
@@ -34,8 +34,8 @@ fn test_generation() {
     ((1 << j) + 1, vec![j + 1], vec![j + 1], vec![(1 << j, j), ((1 << j) + 1, 0)])
   };
 
-  for (n, inode_js, ephemeral_js, pbst_roots) in vec![
-    (1, vec![], vec![], vec![(1u64, 0u8)]),
+  let mut cases = vec![
+    (1, vec![], vec![], vec![(1, 0u8)]),
     (2, vec![1u8], vec![], vec![(2, 1)]),
     (3, vec![2], vec![2u8], vec![(2, 1), (3, 0)]),
     (4, vec![2, 1], vec![], vec![(4, 2)]),
@@ -51,20 +51,12 @@ fn test_generation() {
     (14, vec![4, 3, 1], vec![4, 3], vec![(8, 3), (12, 2), (14, 1)]),
     (15, vec![4, 3, 2], vec![4, 3, 2], vec![(8, 3), (12, 2), (14, 1), (15, 0)]),
     (16, vec![4, 3, 2, 1], vec![], vec![(16, 4)]),
-    pre_pbt(16),
-    pbt(16),
-    post_pbt(16),
-    pre_pbt(31),
-    pbt(31),
-    post_pbt(31),
-    pre_pbt(32),
-    pbt(32),
-    post_pbt(32),
-    pre_pbt(33),
-    pbt(33),
-    post_pbt(33),
-    pre_pbt(64),
-  ] {
+  ];
+  for j in [16, 31, 32, 33].iter().copied().filter(|j| *j < INDEX_SIZE) {
+    cases.extend(vec![pre_pbt(j), pbt(j), post_pbt(j)]);
+  }
+  cases.push(pre_pbt(INDEX_SIZE));
+  for (n, inode_js, ephemeral_js, pbst_roots) in cases {
     let gen = NthGenHashTree::new(n);
     assert_eq!(n, gen.n());
 
@@ -94,9 +86,31 @@ fn test_generation_root() {
   }
 }
 
+#[test]
+fn test_generation_nodes() {
+  for n in (1..=256).chain(vec![1023, 1024, 1025]) {
+    let gen = NthGenHashTree::new(n);
+    let nodes = gen.nodes().collect::<Vec<(Node, Option<(Node, Node)>)>>();
+
+    // n 個の葉ノードと n-1 個の中間ノードで構成されている
+    assert_eq!((2 * n - 1) as usize, nodes.len());
+    assert_eq!(gen.root(), nodes[0].0);
+
+    // 葉ノードは左から順に列挙されている
+    let expected = (1..=n).map(|i| Node::new(i, 0)).collect::<Vec<Node>>();
+    let actual = nodes.iter().filter(|(_, children)| children.is_none()).map(|(node, _)| *node).collect::<Vec<Node>>();
+    assert_eq!(expected, actual);
+
+    // この世代で追加される中間ノードはすべて含まれている
+    for inode in gen.inodes() {
+      assert!(nodes.contains(&(inode.node, Some((inode.left, inode.right)))), "{:?} in T_{}", inode, n);
+    }
+  }
+}
+
 #[test]
 fn test_generation_path_to() {
-  let path = |i: u64, steps: Vec<((Index, u8), (Index, u8))>| -> Path {
+  let path = |i: Index, steps: Vec<((Index, u8), (Index, u8))>| -> Path {
     let steps =
       steps.iter().map(|s| Step { step: Node::new(s.0 .0, s.0 .1), neighbor: Node::new(s.1 .0, s.1 .1) }).collect();
     Path { root: Node::new(i, ceil_log2(i)), steps }
@@ -113,7 +127,9 @@ fn test_generation_path_to() {
     (13, (13, 3), path(13, vec![((13, 3), (8, 3))])),
     (13, (13, 0), path(13, vec![((13, 3), (8, 3)), ((13, 0), (12, 2))])),
   ];
-  cases.append(ns().map(|i| (i, (i, ceil_log2(i)), path(i, vec![]))).collect::<Vec<(u64, (u64, u8), Path)>>().as_mut());
+  cases.append(
+    ns().map(|i| (i, (i, ceil_log2(i)), path(i, vec![]))).collect::<Vec<(Index, (Index, u8), Path)>>().as_mut(),
+  );
   for (n, (i, j), expected) in cases {
     let gen = NthGenHashTree::new(n);
     let actual = gen.path_to(i, j).unwrap();
@@ -150,6 +166,85 @@ fn test_generation_path_to() {
   }
 }
 
+#[test]
+fn test_path() {
+  let path_from = |(i, j): (Index, u8), steps: Vec<((Index, u8), (Index, u8))>| -> Path {
+    let steps =
+      steps.iter().map(|s| Step { step: Node::new(s.0 .0, s.0 .1), neighbor: Node::new(s.1 .0, s.1 .1) }).collect();
+    Path { root: Node::new(i, j), steps }
+  };
+
+  // 任意のノードから始まる部分経路
+  for (from, to, expected) in [
+    ((8, 3), (6, 0), vec![((8, 2), (4, 2)), ((6, 1), (8, 1)), ((6, 0), (5, 0))]),
+    ((8, 2), (6, 1), vec![((6, 1), (8, 1))]),
+    ((13, 3), (13, 0), vec![((13, 0), (12, 2))]),
+    ((13, 3), (9, 0), vec![((12, 2), (13, 0)), ((10, 1), (12, 1)), ((9, 0), (10, 0))]),
+    ((6, 0), (6, 0), vec![]),
+  ] {
+    assert_eq!(Some(path_from(from, expected)), path(from, to));
+  }
+
+  // 存在しないノードや部分木に含まれないノードを指定した場合
+  for (from, to) in [((13, 2), (13, 0)), ((0, 0), (0, 0)), ((4, 2), (5, 0)), ((8, 3), (8, 4)), ((13, 3), (13, 2))] {
+    assert_eq!(None, path(from, to), "{:?} -> {:?}", from, to);
+  }
+
+  // ルートからの経路は経路上の任意のノードで分割した部分経路を連結したものと一致する
+  for n in 1..=64 {
+    let gen = NthGenHashTree::new(n);
+    let root = gen.root();
+    for (node, _) in gen.nodes() {
+      let whole = path((root.i, root.j), (node.i, node.j)).unwrap();
+      assert_eq!(gen.path_to(node.i, node.j), Some(whole.clone()));
+      for (x, step) in whole.steps.iter().enumerate() {
+        let sub = path((step.step.i, step.step.j), (node.i, node.j)).unwrap();
+        assert_eq!(&whole.steps[x + 1..], &sub.steps[..]);
+      }
+    }
+  }
+}
+
+#[test]
+fn test_children() {
+  for (i, j, expected) in [
+    (2, 1, Some(((1, 0), (2, 0)))),
+    (8, 3, Some(((4, 2), (8, 2)))),
+    (13, 4, Some(((8, 3), (13, 3)))),
+    (13, 3, Some(((12, 2), (13, 0)))),
+    (14, 3, Some(((12, 2), (14, 1)))),
+    (13, 0, None),
+    (13, 1, None),
+    (13, 2, None),
+    (0, 1, None),
+  ] {
+    let expected = expected.map(|((li, lj), (ri, rj))| (Node::new(li, lj), Node::new(ri, rj)));
+    assert_eq!(expected, children(i, j).map(|inode| (inode.left, inode.right)), "b_{{{},{}}}", i, j);
+  }
+
+  // 最大のインデックスを持つ木構造のルートノード
+  let root = NthGenHashTree::new(Index::MAX).root();
+  let inode = children(root.i, root.j).unwrap();
+  assert_eq!(Node::new(1 << (INDEX_SIZE - 1), INDEX_SIZE - 1), inode.left);
+  assert_eq!(Node::new(Index::MAX, INDEX_SIZE - 1), inode.right);
+}
+
+/// インデックスのビット幅に依存せず、最大のインデックスを持つ木構造を算出できることを確認します。
+#[test]
+fn test_max_index() {
+  let gen = NthGenHashTree::new(Index::MAX);
+  let root = gen.root();
+  assert_eq!(Node::new(Index::MAX, INDEX_SIZE), root);
+  assert_eq!(1..=Index::MAX, range(root.i, root.j));
+  let inode = children(root.i, root.j).unwrap();
+  assert_eq!(Node::new(1 << (INDEX_SIZE - 1), INDEX_SIZE - 1), inode.left);
+  assert_eq!(Node::new(Index::MAX, INDEX_SIZE - 1), inode.right);
+  assert_eq!(INDEX_SIZE as usize - 1, gen.inodes().len());
+  assert_eq!(INDEX_SIZE as usize, gen.path_to(1, 0).unwrap().steps.len());
+  assert_eq!(INDEX_SIZE - 1, floor_log2(Index::MAX));
+  assert_eq!(INDEX_SIZE, ceil_log2(Index::MAX));
+}
+
 #[test]
 fn test_floor_and_ceil_log2() {
   fn expected_floor(mut n: Index) -> u8 {
@@ -165,8 +260,9 @@ fn test_floor_and_ceil_log2() {
     rank + (if n == (1 << rank) { 0 } else { 1 })
   }
 
-  for x in vec![1u64, 2, 3, 4, 5, 7, 8, 9, 0xFFFFFFFF, 0x100000000, 0x100000001, 0xFFFFFFFFFFFFFFFE, 0xFFFFFFFFFFFFFFFF]
-  {
+  let xs =
+    vec![1u128, 2, 3, 4, 5, 7, 8, 9, 0xFFFFFFFF, 0x100000000, 0x100000001, 0xFFFFFFFFFFFFFFFE, 0xFFFFFFFFFFFFFFFF];
+  for x in xs.into_iter().filter(|x| *x <= Index::MAX as u128).map(|x| x as Index) {
     println!("floor(log₂ {}) = {}, ceil(log₂ {}) = {}", x, floor_log2(x), x, ceil_log2(x));
     assert_eq!(expected_floor(x), floor_log2(x));
     assert_eq!(expected_ceil(x), ceil_log2(x));
@@ -185,10 +281,10 @@ fn test_ceil_log2_with_zero() {
   ceil_log2(0);
 }
 
-fn ns() -> impl Iterator<Item = u64> {
-  (1u64..1024).chain((10..63).map(|i| vec![(1 << i) - 1, 1 << i, (1 << i) + 1]).flatten()).chain(vec![
-    u64::MAX - 2,
-    u64::MAX - 1,
-    u64::MAX,
+fn ns() -> impl Iterator<Item = Index> {
+  (1..1024).chain((10..INDEX_SIZE - 1).map(|i| vec![(1 << i) - 1, 1 << i, (1 << i) + 1]).flatten()).chain(vec![
+    Index::MAX - 2,
+    Index::MAX - 1,
+    Index::MAX,
   ])
 }
@@ -0,0 +1,397 @@
+//! 1 つの物理ストレージに名前を付けた複数の独立した LMTHT を多重化するための名前空間です。
+//!
+//! 顧客ごとに小さなファイルを数百個運用する代わりに、[`Namespaces`] は 1 つのストレージの先頭にディレクトリを持ち、
+//! 名前で指定された論理的なストレージをそれぞれ [`ChunkedStorage`] として提供します。各名前空間のバイト列は固定長の
+//! ブロックに分割され、ディレクトリに続く領域に他の名前空間のブロックと混在して保存されます。
+//!
+//! ストレージは次の形式です。整数はすべてリトルエンディアンです。ディレクトリ領域の大きさは作成時に指定し、名前の
+//! 追加によってディレクトリがこの領域に収まらなくなった場合は [`Detail::NamespaceDirectoryFull`] となります。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | 識別子 `lmtht-ns` | 8 |
+//! | チャンクサイズ (u32) | 4 |
+//! | ディレクトリ領域のバイトサイズ (u32) | 4 |
+//! | 名前の数 (u32) | 4 |
+//! | 名前のバイト長 (u16) と名前 (UTF-8) の列 | 可変長 |
+//! | ディレクトリのチェックサム | 8 |
+//! | (ディレクトリ領域の残り) | |
+//! | ブロックの列 | ブロックごとに 40 + チャンクサイズ |
+//!
+//! 各ブロックは名前空間の番号 (ディレクトリ上の位置, u32)、チャンク番号 (u64)、書き込みの通番 (u64)、書き込み時の
+//! 名前空間のバイト長 (u64)、チャンクの有効なバイト数 (u32)、ヘッダとチャンクのチェックサム (8 バイト) のヘッダに
+//! 続いてチャンクを保持します。名前空間の長さは通番が最も大きい有効なブロックに記録された長さです。
+//!
+//! 書き込み済みのブロックはその場で書き換えられません。チャンクの更新は通番を増やした新しいブロックに書き込まれ、
+//! 置き換えられた古いブロックはストレージを同期した後に名前空間の番号を `u32::MAX` として解放され、再利用されます。
+//! このため書き込みの途中で中断してチェックサムが一致しないブロックは無視され、同じチャンクの古いブロックが使用
+//! されます。
+//!
+//! ```rust,no_run
+//! use lmtht::namespace::Namespaces;
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let namespaces = Namespaces::open("customers.db")?;
+//! let acme = namespaces.open_tree("acme")?;
+//! acme.append(b"invoice #1")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+
+use highway::{HighwayBuilder, Key};
+
+use crate::chunked::{ChunkStore, ChunkedStorage};
+use crate::error::Detail;
+use crate::{lock2io, Cursor, Result, Storage, CHECKSUM_HW64_KEY, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// 名前空間のストレージを識別するための先頭のバイト列です。
+const NAMESPACE_IDENTIFIER: &[u8; 8] = b"lmtht-ns";
+
+/// 名前空間のバイト列を分割するチャンクのバイトサイズのデフォルト値です。
+pub const DEFAULT_NAMESPACE_CHUNK_SIZE: usize = 4 * 1024;
+
+/// ディレクトリ領域のバイトサイズのデフォルト値です。
+pub const DEFAULT_DIRECTORY_SIZE: usize = 64 * 1024;
+
+/// ブロックのヘッダのバイトサイズ。
+const BLOCK_HEADER_SIZE: u64 = 4 + 8 + 8 + 8 + 4 + 8;
+
+/// 解放されたブロックの名前空間の番号。
+const FREE: u32 = u32::MAX;
+
+/// 1 つのストレージに多重化された名前付きのストレージの集合です。
+pub struct Namespaces<S: Storage> {
+  shared: Arc<Mutex<State<S::Cursor>>>,
+}
+
+/// [`Namespaces`] の 1 つの名前空間をチャンクの保存先として参照する [`ChunkStore`] です。
+pub struct NamespaceStore<C: Cursor> {
+  shared: Arc<Mutex<State<C>>>,
+  id: u32,
+}
+
+/// 物理ストレージのカーソルとメモリ上に読み込んだブロックの配置。
+struct State<C: Cursor> {
+  cursor: C,
+  chunk_size: usize,
+  directory_size: u64,
+  names: Vec<String>,
+  /// 名前空間の番号とチャンク番号に対するブロック番号とチャンクの有効なバイト数。
+  blocks: HashMap<(u32, u64), (u64, u32)>,
+  /// 名前空間ごとの長さとその長さを記録したブロックの通番。
+  lengths: Vec<(u64, u64)>,
+  /// 解放されたブロック番号。
+  free: Vec<u64>,
+  /// 新しいブロックに置き換えられ、ストレージの同期後に解放するブロック番号。
+  retired: Vec<u64>,
+  /// 次に末尾へ追加するブロック番号。
+  next_block: u64,
+  /// 最後に書き込んだブロックの通番。
+  seq: u64,
+}
+
+impl<S: Storage> Namespaces<S> {
+  /// 指定されたストレージの名前空間を読み込みます。ストレージが空の場合はデフォルトのチャンクサイズと
+  /// ディレクトリ領域で初期化します。
+  pub fn open(storage: S) -> Result<Namespaces<S>> {
+    Namespaces::open_with(storage, DEFAULT_NAMESPACE_CHUNK_SIZE, DEFAULT_DIRECTORY_SIZE)
+  }
+
+  /// 指定されたストレージの名前空間を読み込みます。ストレージが空の場合は指定されたチャンクサイズとディレクトリ
+  /// 領域のバイトサイズで初期化します。既存のストレージではストレージに記録されている値が使用されます。
+  pub fn open_with(storage: S, chunk_size: usize, directory_size: usize) -> Result<Namespaces<S>> {
+    assert!(chunk_size > 0);
+    let mut cursor = storage.open(true)?;
+    let length = cursor.len()?;
+    let mut state = State {
+      cursor,
+      chunk_size,
+      directory_size: directory_size as u64,
+      names: Vec::new(),
+      blocks: HashMap::new(),
+      lengths: Vec::new(),
+      free: Vec::new(),
+      retired: Vec::new(),
+      next_block: 0,
+      seq: 0,
+    };
+    if length == 0 {
+      state.write_directory()?;
+    } else {
+      state.read_directory()?;
+      state.scan_blocks(length)?;
+    }
+    Ok(Namespaces { shared: Arc::new(Mutex::new(state)) })
+  }
+
+  /// ディレクトリに登録されている名前を登録された順に返します。
+  pub fn names(&self) -> Result<Vec<String>> {
+    Ok(lock2io(self.shared.lock())?.names.clone())
+  }
+
+  /// 名前 `name` の名前空間をストレージとして返します。名前がディレクトリに登録されていない場合は登録します。
+  ///
+  /// # Errors
+  /// 名前を登録するとディレクトリがディレクトリ領域に収まらない場合は [`Detail::NamespaceDirectoryFull`] を返し
+  /// ます。
+  pub fn storage(&self, name: &str) -> Result<ChunkedStorage<NamespaceStore<S::Cursor>>> {
+    let mut state = lock2io(self.shared.lock())?;
+    let id = match state.names.iter().position(|n| n == name) {
+      Some(id) => id,
+      None => {
+        if name.len() > u16::MAX as usize {
+          return Err(Detail::NamespaceDirectoryFull { name: name.to_string() });
+        }
+        state.names.push(name.to_string());
+        state.lengths.push((0, 0));
+        if let Err(err) = state.write_directory() {
+          state.names.pop();
+          state.lengths.pop();
+          return Err(err);
+        }
+        state.names.len() - 1
+      }
+    };
+    Ok(ChunkedStorage::new(NamespaceStore { shared: self.shared.clone(), id: id as u32 }))
+  }
+
+  /// 名前 `name` の名前空間を LMTHT としてオープンします。
+  pub fn open_tree(&self, name: &str) -> Result<LMTHT<ChunkedStorage<NamespaceStore<S::Cursor>>>> {
+    LMTHT::new(self.storage(name)?)
+  }
+}
+
+impl<C: Cursor> State<C> {
+  fn block_size(&self) -> u64 {
+    BLOCK_HEADER_SIZE + self.chunk_size as u64
+  }
+
+  fn block_position(&self, block: u64) -> u64 {
+    self.directory_size + block * self.block_size()
+  }
+
+  /// ディレクトリをストレージの先頭に書き込みます。
+  fn write_directory(&mut self) -> Result<()> {
+    let mut bytes = Vec::with_capacity(self.directory_size as usize);
+    bytes.extend_from_slice(NAMESPACE_IDENTIFIER);
+    bytes.extend_from_slice(&(self.chunk_size as u32).to_le_bytes());
+    bytes.extend_from_slice(&(self.directory_size as u32).to_le_bytes());
+    bytes.extend_from_slice(&(self.names.len() as u32).to_le_bytes());
+    for name in self.names.iter() {
+      bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+      bytes.extend_from_slice(name.as_bytes());
+    }
+    let checksum = checksum_of(&bytes);
+    bytes.extend_from_slice(&checksum);
+    if bytes.len() as u64 > self.directory_size {
+      let name = self.names.last().cloned().unwrap_or_default();
+      return Err(Detail::NamespaceDirectoryFull { name });
+    }
+    bytes.resize(self.directory_size as usize, 0);
+    self.cursor.seek(SeekFrom::Start(0))?;
+    self.cursor.write_all(&bytes)?;
+    self.cursor.flush()?;
+    Ok(())
+  }
+
+  /// ストレージの先頭からディレクトリを読み込みます。
+  fn read_directory(&mut self) -> Result<()> {
+    let damaged = || Detail::DamagedStorage("the namespace directory is broken".to_string());
+    let mut head = [0u8; 20];
+    self.cursor.seek(SeekFrom::Start(0))?;
+    self.cursor.read_exact(&mut head)?;
+    if &head[..8] != NAMESPACE_IDENTIFIER {
+      return Err(Detail::DamagedStorage("the storage is not a namespace storage".to_string()));
+    }
+    self.chunk_size = u32::from_le_bytes(head[8..12].try_into().unwrap()) as usize;
+    self.directory_size = u32::from_le_bytes(head[12..16].try_into().unwrap()) as u64;
+    if self.chunk_size == 0 || self.directory_size < head.len() as u64 + 8 {
+      return Err(damaged());
+    }
+    let mut bytes = head.to_vec();
+    bytes.resize(self.directory_size as usize, 0);
+    self.cursor.read_exact(&mut bytes[head.len()..])?;
+    let count = u32::from_le_bytes(head[16..20].try_into().unwrap());
+    let mut position = head.len();
+    for _ in 0..count {
+      let length = match bytes.get(position..position + 2) {
+        Some(length) => u16::from_le_bytes(length.try_into().unwrap()) as usize,
+        None => return Err(damaged()),
+      };
+      let name = bytes.get(position + 2..position + 2 + length).ok_or_else(damaged)?;
+      self.names.push(String::from_utf8(name.to_vec()).map_err(|_| damaged())?);
+      position += 2 + length;
+    }
+    match bytes.get(position..position + 8) {
+      Some(checksum) if checksum == checksum_of(&bytes[..position]) => (),
+      _ => return Err(damaged()),
+    }
+    self.lengths = vec![(0, 0); self.names.len()];
+    Ok(())
+  }
+
+  /// ディレクトリ領域に続くすべてのブロックを読み込みます。書き込み途中で中断した末尾のブロックやチェックサムの
+  /// 一致しないブロックは無視されます。
+  fn scan_blocks(&mut self, length: u64) -> Result<()> {
+    let blocks = length.saturating_sub(self.directory_size) / self.block_size();
+    let mut seqs = HashMap::new();
+    let mut bytes = vec![0u8; self.block_size() as usize];
+    for block in 0..blocks {
+      self.cursor.seek(SeekFrom::Start(self.block_position(block)))?;
+      self.cursor.read_exact(&mut bytes)?;
+      let id = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+      let k = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+      let seq = u64::from_le_bytes(bytes[12..20].try_into().unwrap());
+      let length = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+      let used = u32::from_le_bytes(bytes[28..32].try_into().unwrap());
+      if id == FREE || id as usize >= self.names.len() || bytes[32..40] != block_checksum(&bytes) {
+        self.free.push(block);
+        continue;
+      }
+      self.seq = self.seq.max(seq);
+      // 同じチャンクのブロックが複数ある場合は通番の大きいものを使用し、古いものは次の同期で解放する
+      match seqs.get(&(id, k)) {
+        Some((_, previous)) if *previous > seq => self.retired.push(block),
+        previous => {
+          if let Some((old, _)) = previous {
+            self.retired.push(*old);
+          }
+          seqs.insert((id, k), (block, seq));
+          self.blocks.insert((id, k), (block, used.min(self.chunk_size as u32)));
+        }
+      }
+      if seq >= self.lengths[id as usize].1 {
+        self.lengths[id as usize] = (length, seq);
+      }
+    }
+    self.next_block = blocks;
+    Ok(())
+  }
+
+  /// 名前空間 `id` のチャンク `k` を新しいブロックに書き込みます。それまでチャンクを保持していたブロックは次の
+  /// 同期の後に解放されます。
+  fn write_block(&mut self, id: u32, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    let block = match self.free.pop() {
+      Some(block) => block,
+      None => {
+        self.next_block += 1;
+        self.next_block - 1
+      }
+    };
+    let used = chunk.len().min(self.chunk_size);
+    let mut bytes = Vec::with_capacity(self.block_size() as usize);
+    bytes.extend_from_slice(&id.to_le_bytes());
+    bytes.extend_from_slice(&k.to_le_bytes());
+    bytes.extend_from_slice(&(self.seq + 1).to_le_bytes());
+    bytes.extend_from_slice(&length.to_le_bytes());
+    bytes.extend_from_slice(&(used as u32).to_le_bytes());
+    bytes.extend_from_slice(&[0u8; 8]);
+    bytes.extend_from_slice(&chunk[..used]);
+    bytes.resize(self.block_size() as usize, 0);
+    let checksum = block_checksum(&bytes);
+    bytes[32..40].copy_from_slice(&checksum);
+    let position = self.block_position(block);
+    let result = self.cursor.seek(SeekFrom::Start(position)).and_then(|_| self.cursor.write_all(&bytes));
+    if let Err(err) = result.and_then(|_| self.cursor.flush()) {
+      self.free.push(block);
+      return Err(err);
+    }
+    self.seq += 1;
+    if let Some((old, _)) = self.blocks.insert((id, k), (block, used as u32)) {
+      self.retired.push(old);
+    }
+    self.lengths[id as usize] = (length, self.seq);
+    Ok(())
+  }
+}
+
+impl<C: Cursor> ChunkStore for NamespaceStore<C> {
+  fn chunk_size(&self) -> usize {
+    lock2io(self.shared.lock()).map(|state| state.chunk_size).unwrap_or(DEFAULT_NAMESPACE_CHUNK_SIZE)
+  }
+
+  fn length(&self) -> io::Result<u64> {
+    Ok(lock2io(self.shared.lock())?.lengths[self.id as usize].0)
+  }
+
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut state = lock2io(self.shared.lock())?;
+    let (block, used) = match state.blocks.get(&(self.id, k)) {
+      Some(block) => *block,
+      None => return Ok(None),
+    };
+    let mut chunk = vec![0u8; used as usize];
+    let position = state.block_position(block) + BLOCK_HEADER_SIZE;
+    state.cursor.seek(SeekFrom::Start(position))?;
+    state.cursor.read_exact(&mut chunk)?;
+    Ok(Some(chunk))
+  }
+
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    lock2io(self.shared.lock())?.write_block(self.id, k, chunk, length)
+  }
+
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()> {
+    let mut state = lock2io(self.shared.lock())?;
+
+    // 解放するブロックより先に、残る最後のチャンクを新しい長さとともに新しいブロックへ書き込む
+    if let Some(last) = k.checked_sub(1) {
+      if let Some((block, used)) = state.blocks.get(&(self.id, last)).copied() {
+        let mut chunk = vec![0u8; used as usize];
+        let position = state.block_position(block) + BLOCK_HEADER_SIZE;
+        state.cursor.seek(SeekFrom::Start(position))?;
+        state.cursor.read_exact(&mut chunk)?;
+        state.write_block(self.id, last, &chunk, length)?;
+      }
+    }
+    // 切り詰めたチャンクのブロックは同期するまで残し、その後に解放する
+    let mut released = state.blocks.keys().filter(|(id, j)| *id == self.id && *j >= k).copied().collect::<Vec<_>>();
+    released.sort();
+    for key in released {
+      let (block, _) = state.blocks.remove(&key).unwrap();
+      state.retired.push(block);
+    }
+    let seq = state.seq;
+    state.lengths[self.id as usize] = (length, seq);
+    Ok(())
+  }
+
+  fn sync(&self) -> io::Result<()> {
+    let mut state = lock2io(self.shared.lock())?;
+    state.cursor.sync_data()?;
+
+    // 新しいブロックが永続化されたため置き換えられたブロックを解放する
+    let retired = std::mem::take(&mut state.retired);
+    for block in retired.iter() {
+      let position = state.block_position(*block);
+      state.cursor.seek(SeekFrom::Start(position))?;
+      state.cursor.write_all(&FREE.to_le_bytes())?;
+    }
+    state.cursor.flush()?;
+    state.free.extend(retired);
+    Ok(())
+  }
+}
+
+fn checksum_of(body: &[u8]) -> [u8; 8] {
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, body);
+  std::hash::Hasher::finish(&hasher).to_le_bytes()
+}
+
+/// ブロックのチェックサム欄を除いたヘッダとチャンクのチェックサムを算出します。
+fn block_checksum(block: &[u8]) -> [u8; 8] {
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, &block[..32]);
+  std::hash::Hasher::write(&mut hasher, &block[40..]);
+  std::hash::Hasher::finish(&hasher).to_le_bytes()
+}
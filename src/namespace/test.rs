@@ -0,0 +1,107 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::*;
+
+/// 1 つのストレージに多重化した名前付きの木構造が互いに独立して値を保持し、再オープン後も名前とルートが維持される
+/// ことを検証します。
+#[test]
+fn test_namespaces() {
+  use crate::namespace::Namespaces;
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let namespaces = Namespaces::open_with(MemStorage::with(buffer.clone()), 64, 256).unwrap();
+  assert!(namespaces.names().unwrap().is_empty());
+  let names = ["acme", "globex", "initech"];
+  let mut roots = Vec::new();
+  for (k, name) in names.iter().enumerate() {
+    let db = namespaces.open_tree(name).unwrap();
+    for i in 0..(k + 1) * 7 {
+      db.append(format!("{}-{}", name, i).as_bytes()).unwrap();
+    }
+  }
+  for name in names.iter() {
+    roots.push(namespaces.open_tree(name).unwrap().root());
+  }
+  assert_eq!(names.to_vec(), namespaces.names().unwrap());
+  assert_eq!(vec![7, 14, 21], roots.iter().map(|root| root.unwrap().i).collect::<Vec<_>>());
+  drop(namespaces);
+
+  // 再オープンしても名前と各木構造の値が維持される
+  let namespaces = Namespaces::open(MemStorage::with(buffer.clone())).unwrap();
+  assert_eq!(names.to_vec(), namespaces.names().unwrap());
+  for (k, name) in names.iter().enumerate() {
+    let db = namespaces.open_tree(name).unwrap();
+    assert_eq!(roots[k], db.root());
+    let mut query = db.query().unwrap();
+    for i in 1..=db.n() {
+      assert_eq!(format!("{}-{}", name, i - 1).into_bytes(), query.get(i).unwrap().unwrap());
+    }
+  }
+
+  // 名前の追加でディレクトリ領域を超える場合はエラー
+  let long = "x".repeat(256);
+  assert!(matches!(namespaces.storage(&long), Err(Detail::NamespaceDirectoryFull { .. })));
+  assert_eq!(names.to_vec(), namespaces.names().unwrap());
+
+  // 名前空間ではないストレージは開けない
+  let other = Arc::new(RwLock::new(vec![0u8; 512]));
+  assert!(matches!(Namespaces::open(MemStorage::with(other)), Err(Detail::DamagedStorage(_))));
+}
+
+/// チャンクの更新が新しいブロックに書き込まれ、書き込みの途中で中断した新しいブロックを再オープン時に無視して同期済みの
+/// 内容が読み込まれることを検証します。
+#[test]
+fn test_namespace_torn_block() {
+  use std::convert::TryInto;
+
+  use crate::chunked::ChunkStore;
+  use crate::namespace::Namespaces;
+
+  let (chunk_size, directory_size) = (64, 256);
+  let block_size = 40 + chunk_size;
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let namespaces = Namespaces::open_with(MemStorage::with(buffer.clone()), chunk_size, directory_size).unwrap();
+  let storage = namespaces.storage("acme").unwrap();
+  let store = storage.store();
+  store.put(0, &[1; 32], 32).unwrap();
+  store.sync().unwrap();
+  let synced = buffer.read().unwrap().clone();
+
+  // 更新は既存のブロックを書き換えずに新しいブロックへ書き込まれる
+  store.put(0, &[2; 48], 48).unwrap();
+  let written = buffer.read().unwrap().clone();
+  assert_eq!(synced[..], written[..synced.len()]);
+  assert_eq!(directory_size + 2 * block_size, written.len());
+  drop(storage);
+  drop(namespaces);
+
+  let reopen = |bytes: Vec<u8>| {
+    let namespaces = Namespaces::open_with(MemStorage::with(Arc::new(RwLock::new(bytes))), chunk_size, directory_size);
+    let storage = namespaces.unwrap().storage("acme").unwrap();
+    let store = storage.store();
+    (store.length().unwrap(), store.get(0).unwrap())
+  };
+  assert_eq!((48, Some(vec![2; 48])), reopen(written.clone()));
+
+  // 途中までしか書き込まれていない新しいブロックは無視される
+  let mut truncated = written.clone();
+  truncated.truncate(written.len() - 10);
+  assert_eq!((32, Some(vec![1; 32])), reopen(truncated));
+
+  // チャンクが破損した新しいブロックは無視される
+  let mut torn = written.clone();
+  let position = directory_size + block_size;
+  assert_eq!(2, u64::from_le_bytes(torn[position + 12..position + 20].try_into().unwrap()));
+  torn[position + 40 + 16] ^= 0xFF;
+  assert_eq!((32, Some(vec![1; 32])), reopen(torn));
+
+  // 同期によって置き換えられたブロックが解放され、次の更新で再利用される
+  let buffer = Arc::new(RwLock::new(written));
+  let namespaces = Namespaces::open_with(MemStorage::with(buffer.clone()), chunk_size, directory_size).unwrap();
+  let storage = namespaces.storage("acme").unwrap();
+  let store = storage.store();
+  store.sync().unwrap();
+  store.put(0, &[3; 16], 16).unwrap();
+  assert_eq!(directory_size + 2 * block_size, buffer.read().unwrap().len());
+  assert_eq!(Some(vec![3; 16]), store.get(0).unwrap());
+}
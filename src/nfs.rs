@@ -0,0 +1,343 @@
+//! NFS や SMB のようなネットワークファイルシステム上のファイルを安全に使用するためのストレージです。
+//!
+//! ネットワークファイルシステムでは [`FileStorage`](crate::FileStorage) が使用する `flock` が他のホストに対して
+//! 機能しないことがあり、複数のホストの書き込みが同じファイルの末尾に交互に追記してハッシュ木を破損する可能性が
+//! あります。[`NetworkFileStorage`] は次の方法でこれを防ぎます。
+//!
+//! * 書き込み用のカーソルを最初にオープンした時点で、排他的な作成 (`O_EXCL`) によってファイルと同じディレクトリに
+//!   ロックファイル `<path>.lock` を作成します。ロックファイルには保持者のトークンとリース (有効期限) を記録し、
+//!   書き込みと同期のたびに期限の半分が経過していればリースを更新します。期限を過ぎたロックファイルは異常終了した
+//!   保持者のものとして引き継ぐことができます。
+//! * リースを更新する前にロックファイルのトークンを確認し、他のホストに引き継がれていた場合は書き込みを拒否します。
+//! * ロックを獲得した後にファイルをオープンし直すため、LMTHT がオープン時に行う末尾のエントリの検証は他のホストが
+//!   最後に書き込んだ内容に対して行われます。
+//! * サーバ側でファイルが置き換えられたことを表す `ESTALE` で操作が失敗した場合は、パスからファイルをオープンし
+//!   直して操作を再実行します。
+//!
+//! リースの期限は各ホストの時計で判断するため、ホスト間の時計のずれはリースの期間より十分に小さい必要があります。
+//!
+//! ```rust,no_run
+//! use lmtht::nfs::NetworkFileStorage;
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let db = LMTHT::new(NetworkFileStorage::new("/mnt/filer/audit.lmtht"))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::fs::{remove_file, rename, File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::Detail;
+use crate::{lock2io, Capabilities, Cursor, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// [`NetworkFileStorageOptions::lease`] のデフォルト値です。
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(30);
+
+/// [`NetworkFileStorage`] の動作を調整するためのオプションです。
+#[derive(Clone, Debug)]
+pub struct NetworkFileStorageOptions {
+  /// ロックファイルのリースの期間です。この期間を過ぎても更新されないロックは他のホストが引き継ぐことができます。
+  /// デフォルトは [`DEFAULT_LEASE`] です。
+  pub lease: Duration,
+}
+
+impl Default for NetworkFileStorageOptions {
+  fn default() -> Self {
+    NetworkFileStorageOptions { lease: DEFAULT_LEASE }
+  }
+}
+
+/// ネットワークファイルシステム上のファイルをロックファイルとリースで保護して使用するストレージです。
+pub struct NetworkFileStorage {
+  path: PathBuf,
+  shared: Arc<Shared>,
+}
+
+struct Shared {
+  lock_path: PathBuf,
+  lease: Duration,
+  /// 獲得したロックのトークンと最後にリースを更新した時刻。
+  held: Mutex<Option<(String, Instant)>>,
+}
+
+impl NetworkFileStorage {
+  /// 指定されたパスのファイルをデフォルトのオプションで使用するストレージを構築します。
+  pub fn new<P: AsRef<Path>>(path: P) -> NetworkFileStorage {
+    Self::with_options(path, NetworkFileStorageOptions::default())
+  }
+
+  /// 指定されたオプションでパスのファイルを使用するストレージを構築します。
+  pub fn with_options<P: AsRef<Path>>(path: P, options: NetworkFileStorageOptions) -> NetworkFileStorage {
+    assert!(options.lease > Duration::ZERO);
+    let path = path.as_ref().to_path_buf();
+    let mut lock_path = path.clone().into_os_string();
+    lock_path.push(".lock");
+    let shared = Shared { lock_path: PathBuf::from(lock_path), lease: options.lease, held: Mutex::new(None) };
+    NetworkFileStorage { path, shared: Arc::new(shared) }
+  }
+
+  /// このストレージが使用しているファイルのパスを参照します。
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// このストレージが使用するロックファイルのパスを参照します。
+  pub fn lock_path(&self) -> &Path {
+    &self.shared.lock_path
+  }
+}
+
+impl Storage for NetworkFileStorage {
+  type Cursor = NetworkFileCursor;
+  fn open(&self, writable: bool) -> Result<NetworkFileCursor> {
+    if writable {
+      let acquired = self.shared.acquire().map_err(|err| failed_to_open(&self.shared.lock_path, err))?;
+      if !acquired {
+        return Err(Detail::StorageLocked { file: self.path.to_string_lossy().to_string() });
+      }
+    }
+    // ロックの獲得後にオープンすることで他のホストが書き込んだ内容を参照する
+    let file = open(&self.path, writable).map_err(|err| failed_to_open(&self.path, err))?;
+    Ok(NetworkFileCursor { path: self.path.clone(), file, writable, position: 0, shared: self.shared.clone() })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::ALL
+  }
+}
+
+impl Drop for NetworkFileStorage {
+  fn drop(&mut self) {
+    // 破棄の時点で解放できなくてもリースの期限が過ぎれば他のホストが引き継ぐことができる
+    let _ = self.shared.release();
+  }
+}
+
+impl Shared {
+  /// ロックファイルを作成してロックを獲得します。すでに獲得している場合は何も行いません。他の保持者が有効な
+  /// リースを持つ場合は false を返します。
+  fn acquire(&self) -> io::Result<bool> {
+    let mut held = lock2io(self.held.lock())?;
+    if held.is_some() {
+      return Ok(true);
+    }
+    let token = new_token();
+    loop {
+      match OpenOptions::new().write(true).create_new(true).open(&self.lock_path) {
+        Ok(mut file) => {
+          file.write_all(self.lock_contents(&token).as_bytes())?;
+          file.sync_all()?;
+          break;
+        }
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+          match read_lock(&self.lock_path)? {
+            Some((_, expiry)) if expiry > now_millis() => return Ok(false),
+            // 期限を過ぎたロックファイルを固有の名前に退避してから作成し直す
+            _ => {
+              let mut stale = self.lock_path.clone().into_os_string();
+              stale.push(format!(".{}.stale", token));
+              match rename(&self.lock_path, &stale) {
+                Ok(()) => {
+                  let _ = remove_file(&stale);
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err),
+              }
+            }
+          }
+        }
+        Err(err) => return Err(err),
+      }
+    }
+    // 期限切れのロックを同時に引き継いだ他のホストに置き換えられていないことを確認する
+    if read_lock(&self.lock_path)?.map(|(owner, _)| owner != token).unwrap_or(true) {
+      return Ok(false);
+    }
+    *held = Some((token, Instant::now()));
+    Ok(true)
+  }
+
+  /// リースの期限の半分が経過していればロックファイルを更新します。ロックが他の保持者に引き継がれていた場合は
+  /// エラーを返します。
+  fn renew(&self) -> io::Result<()> {
+    let mut held = lock2io(self.held.lock())?;
+    let (token, renewed) = match held.as_mut() {
+      Some(held) => held,
+      None => return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the lock file is not held")),
+    };
+    if renewed.elapsed() < self.lease / 2 {
+      return Ok(());
+    }
+    if read_lock(&self.lock_path)?.map(|(owner, _)| owner != *token).unwrap_or(true) {
+      *held = None;
+      let msg = format!("the lease of {} has been taken over by another writer", self.lock_path.to_string_lossy());
+      return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg));
+    }
+    let mut file = OpenOptions::new().write(true).truncate(true).open(&self.lock_path)?;
+    file.write_all(self.lock_contents(token).as_bytes())?;
+    file.sync_all()?;
+    *renewed = Instant::now();
+    Ok(())
+  }
+
+  /// 獲得しているロックを解放します。
+  fn release(&self) -> io::Result<()> {
+    let mut held = lock2io(self.held.lock())?;
+    if let Some((token, _)) = held.take() {
+      if read_lock(&self.lock_path)?.map(|(owner, _)| owner == token).unwrap_or(false) {
+        remove_file(&self.lock_path)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn lock_contents(&self, token: &str) -> String {
+    format!("{}\n{}\n", token, now_millis().saturating_add(self.lease.as_millis() as u64))
+  }
+}
+
+/// [`NetworkFileStorage`] が使用するカーソルです。
+pub struct NetworkFileCursor {
+  path: PathBuf,
+  file: File,
+  writable: bool,
+  position: u64,
+  shared: Arc<Shared>,
+}
+
+impl NetworkFileCursor {
+  /// ファイルに対して `f` を実行します。ファイルハンドルが無効になっていた場合はオープンし直して再実行します。
+  fn with_file<T, F: FnMut(&mut File) -> io::Result<T>>(&mut self, mut f: F) -> io::Result<T> {
+    match f(&mut self.file) {
+      Err(err) if is_stale(&err) => {
+        self.file = open(&self.path, self.writable)?;
+        f(&mut self.file)
+      }
+      result => result,
+    }
+  }
+
+  /// 書き込みの前にリースを確認します。
+  fn check_lease(&self) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    self.shared.renew()
+  }
+}
+
+impl Cursor for NetworkFileCursor {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.check_lease()?;
+    self.with_file(|file| file.sync_data())
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.check_lease()?;
+    self.with_file(|file| file.set_len(length))
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.with_file(|file| Ok(file.metadata()?.len()))
+  }
+}
+
+impl Seek for NetworkFileCursor {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => (position, 0),
+      SeekFrom::End(offset) => (self.len()?, offset),
+      SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl Read for NetworkFileCursor {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let position = self.position;
+    let length = self.with_file(|file| {
+      file.seek(SeekFrom::Start(position))?;
+      file.read(buf)
+    })?;
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl Write for NetworkFileCursor {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.check_lease()?;
+    let position = self.position;
+    let length = self.with_file(|file| {
+      file.seek(SeekFrom::Start(position))?;
+      file.write(buf)
+    })?;
+    self.position += length as u64;
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.with_file(|file| file.flush())
+  }
+}
+
+fn open(path: &Path, writable: bool) -> io::Result<File> {
+  OpenOptions::new().read(true).write(writable).create(writable).truncate(false).open(path)
+}
+
+/// ロックファイルに記録されている保持者のトークンとリースの期限 (UNIX 時間のミリ秒) を読み込みます。ロックファイル
+/// が存在しない場合は `None` を返します。記録が壊れている場合は期限切れとして扱います。
+fn read_lock(path: &Path) -> io::Result<Option<(String, u64)>> {
+  let text = match std::fs::read_to_string(path) {
+    Ok(text) => text,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(err) => return Err(err),
+  };
+  let mut lines = text.lines();
+  let token = lines.next().unwrap_or_default().to_string();
+  let expiry = lines.next().and_then(|line| line.trim().parse::<u64>().ok()).unwrap_or(0);
+  Ok(Some((token, expiry)))
+}
+
+fn new_token() -> String {
+  static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+  format!("{}-{}-{}", std::process::id(), nanos, SEQUENCE.fetch_add(1, Ordering::Relaxed))
+}
+
+fn now_millis() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// サーバ側でファイルが削除または置き換えられ、ファイルハンドルが無効になったことを表すエラーかを判定します。
+fn is_stale(err: &io::Error) -> bool {
+  #[cfg(unix)]
+  return err.raw_os_error() == Some(libc::ESTALE);
+  #[cfg(not(unix))]
+  return {
+    let _ = err;
+    false
+  };
+}
+
+fn failed_to_open(path: &Path, err: io::Error) -> Detail {
+  Detail::FailedToOpenLocalFile { file: path.to_string_lossy().to_string(), message: err.to_string() }
+}
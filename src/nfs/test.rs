@@ -0,0 +1,51 @@
+use std::fs::remove_file;
+use std::time::Duration;
+
+use crate::error::Detail;
+use crate::test::temp_file;
+use crate::*;
+
+/// `NetworkFileStorage` がロックファイルで他の書き込みを排除し、期限を過ぎたロックを引き継げることを検証します。
+#[test]
+fn test_network_file_storage() {
+  use crate::nfs::{NetworkFileStorage, NetworkFileStorageOptions};
+
+  let file = temp_file("lmtht-nfs", ".db");
+  let db = LMTHT::new(NetworkFileStorage::new(&file)).unwrap();
+  let lock = db.storage().lock_path().to_path_buf();
+  assert!(lock.exists());
+  for i in 1..=5u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+
+  // 同じファイルに対する別の書き込みはロックを獲得できないが読み込みは可能
+  assert!(matches!(LMTHT::new(NetworkFileStorage::new(&file)), Err(Detail::StorageLocked { .. })));
+  assert!(NetworkFileStorage::new(&file).open(false).is_ok());
+
+  // ロックを保持していたストレージを破棄するとロックファイルが削除される
+  drop(db);
+  assert!(!lock.exists());
+
+  // 期限を過ぎたロックファイルは引き継ぐことができる
+  std::fs::write(&lock, "crashed-writer\n0\n").unwrap();
+  let options = NetworkFileStorageOptions { lease: Duration::from_millis(10) };
+  let db = LMTHT::new(NetworkFileStorage::with_options(&file, options)).unwrap();
+  assert_eq!(6, db.append(&[6; 16]).unwrap().i);
+
+  // リースを他の書き込みに引き継がれた後の書き込みは失敗する
+  std::thread::sleep(Duration::from_millis(20));
+  std::fs::write(&lock, "another-writer\n18446744073709551615\n").unwrap();
+  assert!(db.append(&[7; 16]).is_err());
+  drop(db);
+  assert!(lock.exists());
+  remove_file(&lock).unwrap();
+
+  let db = LMTHT::new(NetworkFileStorage::new(&file)).unwrap();
+  let mut query = db.query().unwrap();
+  for i in 1..=6u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+  drop(query);
+  drop(db);
+  remove_file(&file).unwrap();
+}
@@ -0,0 +1,357 @@
+//! S3 や GCS のようなオブジェクトストアにバイト列を保存するストレージです。
+//!
+//! 追記されたバイト列はまずメモリ上の書き込みバッファに蓄積され、バッファが [`ObjectStorageOptions::segment_size`]
+//! に達するか [`Cursor::sync_data()`] が呼び出された時点で、不変のセグメントとして 1 つのオブジェクトに保存されます。
+//! それぞれのセグメントがバイト列のどの範囲を保持するかは索引オブジェクトに記録され、読み込みは該当するセグメントに
+//! 対する範囲指定の GET で行われます。ブロックボリュームを管理することなくクラウドのサービスから検証可能なログを
+//! 使用するための実装です。
+//!
+//! オブジェクトストアの SDK に依存しないよう、実際の保存先へのアクセスは [`ObjectStore`] を実装して提供します。
+//!
+//! ```rust
+//! use lmtht::object_storage::{MemObjectStore, ObjectStorage, ObjectStorageOptions};
+//! use lmtht::LMTHT;
+//!
+//! let storage = ObjectStorage::new(MemObjectStore::new(), ObjectStorageOptions::default()).unwrap();
+//! let db = LMTHT::new(storage).unwrap();
+//! db.append(b"hello").unwrap();
+//! assert_eq!(b"hello".to_vec(), db.query().unwrap().get(1).unwrap().unwrap());
+//! ```
+//!
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use crate::{lock2io, Capabilities, Cursor, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// [`ObjectStorageOptions::segment_size`] のデフォルト値です。
+pub const DEFAULT_OBJECT_SEGMENT_SIZE: usize = 8 * 1024 * 1024;
+
+/// セグメントの範囲を記録する索引オブジェクトのキー (接頭辞を除く)。
+const INDEX_KEY: &str = "index";
+
+/// オブジェクトを保存する保存先です。S3 や GCS などのクライアントをこのトレイトでラップして使用します。
+pub trait ObjectStore: Send + Sync {
+  /// 指定されたキーのオブジェクト全体を参照します。存在しない場合は `None` を返します。
+  fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+  /// 指定されたキーのオブジェクトの `offset` から `length` バイトを参照します (HTTP の `Range` 指定の GET に相当)。
+  fn get_range(&self, key: &str, offset: u64, length: usize) -> io::Result<Vec<u8>>;
+
+  /// 指定されたキーにオブジェクトを保存します。同じキーのオブジェクトが存在する場合は置き換えます。
+  fn put(&self, key: &str, data: &[u8]) -> io::Result<()>;
+
+  /// 指定されたキーのオブジェクトを削除します。存在しない場合は何も行いません。
+  fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+impl<O: ObjectStore + ?Sized> ObjectStore for Arc<O> {
+  fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+    self.as_ref().get(key)
+  }
+  fn get_range(&self, key: &str, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+    self.as_ref().get_range(key, offset, length)
+  }
+  fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+    self.as_ref().put(key, data)
+  }
+  fn delete(&self, key: &str) -> io::Result<()> {
+    self.as_ref().delete(key)
+  }
+}
+
+/// [`ObjectStorage`] の動作を調整するためのオプションです。
+#[derive(Clone, Debug)]
+pub struct ObjectStorageOptions {
+  /// このストレージが使用するオブジェクトのキーの接頭辞です。1 つのバケットに複数の LMTHT を保存する場合に
+  /// `"logs/audit/"` のように指定します。デフォルトは空文字列です。
+  pub prefix: String,
+  /// 書き込みバッファをセグメントとして保存するバイトサイズです。デフォルトは [`DEFAULT_OBJECT_SEGMENT_SIZE`] です。
+  pub segment_size: usize,
+}
+
+impl Default for ObjectStorageOptions {
+  fn default() -> Self {
+    ObjectStorageOptions { prefix: String::new(), segment_size: DEFAULT_OBJECT_SEGMENT_SIZE }
+  }
+}
+
+/// オブジェクトストアに保存されるストレージです。
+pub struct ObjectStorage<O: ObjectStore> {
+  shared: Arc<Shared<O>>,
+}
+
+struct Shared<O: ObjectStore> {
+  store: O,
+  options: ObjectStorageOptions,
+  state: RwLock<State>,
+}
+
+struct State {
+  /// 保存済みのセグメント。バイト列の先頭から連続して並んでいる。
+  segments: Vec<Segment>,
+  /// セグメントとして保存されていない末尾のバイト列。`segments` の終端から始まる。
+  buffer: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct Segment {
+  offset: u64,
+  length: u64,
+  key: String,
+}
+
+impl State {
+  fn flushed(&self) -> u64 {
+    self.segments.last().map(|s| s.offset + s.length).unwrap_or(0)
+  }
+}
+
+impl<O: ObjectStore> ObjectStorage<O> {
+  /// 指定されたオブジェクトストアを使用するストレージを構築します。索引オブジェクトが存在する場合はその内容から
+  /// 保存済みのセグメントを復元します。
+  pub fn new(store: O, options: ObjectStorageOptions) -> Result<ObjectStorage<O>> {
+    assert!(options.segment_size > 0);
+    let segments = match store.get(&format!("{}{}", options.prefix, INDEX_KEY))? {
+      Some(index) => parse_index(&index)?,
+      None => Vec::new(),
+    };
+    let state = RwLock::new(State { segments, buffer: Vec::new() });
+    Ok(ObjectStorage { shared: Arc::new(Shared { store, options, state }) })
+  }
+
+  /// このストレージが使用するオブジェクトストアを参照します。
+  pub fn store(&self) -> &O {
+    &self.shared.store
+  }
+
+  /// 書き込みバッファの内容をセグメントとして保存し、索引オブジェクトを更新します。
+  pub fn flush(&self) -> Result<()> {
+    Ok(self.shared.flush_segment(&mut *lock2io(self.shared.state.write())?)?)
+  }
+}
+
+impl<O: ObjectStore + 'static> Storage for ObjectStorage<O> {
+  type Cursor = ObjectCursor<O>;
+  fn open(&self, writable: bool) -> Result<ObjectCursor<O>> {
+    Ok(ObjectCursor { shared: self.shared.clone(), writable, position: 0 })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::ALL
+  }
+}
+
+impl<O: ObjectStore> Shared<O> {
+  fn key(&self, name: &str) -> String {
+    format!("{}{}", self.options.prefix, name)
+  }
+
+  fn flush_segment(&self, state: &mut State) -> io::Result<()> {
+    if state.buffer.is_empty() {
+      return Ok(());
+    }
+    let offset = state.flushed();
+    let key = self.key(&format!("segment-{:020}", offset));
+    self.store.put(&key, &state.buffer)?;
+    state.segments.push(Segment { offset, length: state.buffer.len() as u64, key });
+    state.buffer.clear();
+    self.store.put(&self.key(INDEX_KEY), &format_index(&state.segments))
+  }
+}
+
+/// [`ObjectStorage`] が使用するカーソルです。
+pub struct ObjectCursor<O: ObjectStore> {
+  shared: Arc<Shared<O>>,
+  writable: bool,
+  position: u64,
+}
+
+impl<O: ObjectStore> Cursor for ObjectCursor<O> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.shared.flush_segment(&mut *lock2io(self.shared.state.write())?)
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let mut state = lock2io(self.shared.state.write())?;
+    let flushed = state.flushed();
+    if length >= flushed {
+      state.buffer.resize((length - flushed) as usize, 0u8);
+      return Ok(());
+    }
+
+    // 保存済みのセグメントは変更できないため、索引上の範囲を切り詰めてから不要になったセグメントを削除する
+    let mut dropped = Vec::new();
+    state.segments.retain(|s| {
+      if s.offset < length {
+        true
+      } else {
+        dropped.push(s.key.clone());
+        false
+      }
+    });
+    if let Some(last) = state.segments.last_mut() {
+      last.length = min(last.length, length - last.offset);
+    }
+    state.buffer.clear();
+    self.shared.store.put(&self.shared.key(INDEX_KEY), &format_index(&state.segments))?;
+    for key in dropped {
+      self.shared.store.delete(&key)?;
+    }
+    Ok(())
+  }
+}
+
+impl<O: ObjectStore> io::Seek for ObjectCursor<O> {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      io::SeekFrom::Start(position) => (position, 0),
+      io::SeekFrom::End(offset) => {
+        let state = lock2io(self.shared.state.read())?;
+        (state.flushed() + state.buffer.len() as u64, offset)
+      }
+      io::SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl<O: ObjectStore> io::Read for ObjectCursor<O> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let state = lock2io(self.shared.state.read())?;
+    let flushed = state.flushed();
+    if self.position >= flushed {
+      let offset = min((self.position - flushed) as usize, state.buffer.len());
+      let length = min(buf.len(), state.buffer.len() - offset);
+      buf[..length].copy_from_slice(&state.buffer[offset..offset + length]);
+      self.position += length as u64;
+      return Ok(length);
+    }
+
+    // 現在の位置を含むセグメントに対して範囲指定で読み込む
+    let i = state.segments.partition_point(|s| s.offset + s.length <= self.position);
+    let segment = state.segments[i].clone();
+    drop(state);
+    let length = min(buf.len() as u64, segment.offset + segment.length - self.position) as usize;
+    let bytes = self.shared.store.get_range(&segment.key, self.position - segment.offset, length)?;
+    if bytes.len() != length {
+      let msg = format!("segment {} is shorter than the index records", segment.key);
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+    }
+    buf[..length].copy_from_slice(&bytes);
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<O: ObjectStore> io::Write for ObjectCursor<O> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let mut state = lock2io(self.shared.state.write())?;
+    let flushed = state.flushed();
+    if self.position < flushed {
+      let msg = "the segments already saved in the object store cannot be overwritten";
+      return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+    }
+    let offset = (self.position - flushed) as usize;
+    let end = offset + buf.len();
+    if state.buffer.len() < end {
+      state.buffer.resize(end, 0u8);
+    }
+    state.buffer[offset..end].copy_from_slice(buf);
+    self.position += buf.len() as u64;
+    if state.buffer.len() >= self.shared.options.segment_size {
+      self.shared.flush_segment(&mut state)?;
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// 索引オブジェクトを `offset length key` の行として直列化します。
+fn format_index(segments: &[Segment]) -> Vec<u8> {
+  let mut index = String::new();
+  for s in segments {
+    index.push_str(&format!("{} {} {}\n", s.offset, s.length, s.key));
+  }
+  index.into_bytes()
+}
+
+/// 索引オブジェクトを復元します。セグメントがバイト列の先頭から連続していない場合はエラーとなります。
+fn parse_index(index: &[u8]) -> io::Result<Vec<Segment>> {
+  let broken = || io::Error::new(io::ErrorKind::InvalidData, "the index object of the segments is broken");
+  let index = std::str::from_utf8(index).map_err(|_| broken())?;
+  let mut segments = Vec::<Segment>::new();
+  for line in index.lines() {
+    let mut fields = line.splitn(3, ' ');
+    let offset = fields.next().and_then(|f| f.parse::<u64>().ok()).ok_or_else(broken)?;
+    let length = fields.next().and_then(|f| f.parse::<u64>().ok()).ok_or_else(broken)?;
+    let key = fields.next().ok_or_else(broken)?.to_string();
+    if offset != segments.last().map(|s| s.offset + s.length).unwrap_or(0) {
+      return Err(broken());
+    }
+    segments.push(Segment { offset, length, key });
+  }
+  Ok(segments)
+}
+
+/// メモリ上にオブジェクトを保持する [`ObjectStore`] です。テストや、独自の保存先を実装する際の参考としての使用を
+/// 想定しています。
+#[derive(Default)]
+pub struct MemObjectStore {
+  objects: RwLock<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemObjectStore {
+  /// 空のオブジェクトストアを構築します。
+  pub fn new() -> MemObjectStore {
+    Self::default()
+  }
+
+  /// 保存されているオブジェクトのキーを参照します。
+  pub fn keys(&self) -> Vec<String> {
+    self.objects.read().map(|objects| objects.keys().cloned().collect()).unwrap_or_default()
+  }
+}
+
+impl ObjectStore for MemObjectStore {
+  fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+    Ok(lock2io(self.objects.read())?.get(key).cloned())
+  }
+
+  fn get_range(&self, key: &str, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+    let objects = lock2io(self.objects.read())?;
+    let object = objects.get(key).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, key.to_string()))?;
+    let start = min(offset as usize, object.len());
+    Ok(object[start..min(start + length, object.len())].to_vec())
+  }
+
+  fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+    lock2io(self.objects.write())?.insert(key.to_string(), data.to_vec());
+    Ok(())
+  }
+
+  fn delete(&self, key: &str) -> io::Result<()> {
+    lock2io(self.objects.write())?.remove(key);
+    Ok(())
+  }
+}
@@ -0,0 +1,43 @@
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::*;
+
+/// オブジェクトストアに保存したセグメントと索引から LMTHT を再構築でき、末尾の切り詰めが索引に反映されることを検証
+/// します。
+#[test]
+fn test_object_storage() {
+  use object_storage::{MemObjectStore, ObjectStorage, ObjectStorageOptions};
+  let store = Arc::new(MemObjectStore::new());
+  let options = ObjectStorageOptions { prefix: "audit/".to_string(), segment_size: 64 };
+  let db = LMTHT::new(ObjectStorage::new(store.clone(), options.clone()).unwrap()).unwrap();
+  for i in 0..50u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  db.sync().unwrap();
+  let root = db.root().unwrap();
+  drop(db);
+  assert!(store.keys().len() > 2);
+  assert!(store.keys().iter().all(|key| key.starts_with("audit/")));
+
+  let storage = ObjectStorage::new(store.clone(), options.clone()).unwrap();
+  let db = LMTHT::new(storage).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..50u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+  drop((query, db));
+
+  // 保存済みのセグメントの途中で切り詰めると以降のセグメントは削除される
+  let storage = ObjectStorage::new(store.clone(), options.clone()).unwrap();
+  let segments = store.keys().len();
+  let mut cursor = storage.open(true).unwrap();
+  cursor.set_len(100).unwrap();
+  assert_eq!(100, cursor.seek(SeekFrom::End(0)).unwrap());
+  assert!(store.keys().len() < segments);
+  assert!(cursor.write_all(&[0u8; 4]).is_ok());
+  cursor.seek(SeekFrom::Start(10)).unwrap();
+  assert_eq!(io::ErrorKind::Unsupported, cursor.write(&[0u8; 4]).unwrap_err().kind());
+}
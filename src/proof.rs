@@ -0,0 +1,443 @@
+//! サーバから検証者に受け渡す証明の検証と直列化を行います。
+//!
+//! 同じ木構造に対する多数の証明は、ルートに近い経路の多くのノードを共有しています。[`verify_batch()`] は証明ごとに
+//! ルートハッシュを算出する代わりに、一度算出した左右の子の組に対する中間ノードのハッシュ値を記憶し、同じ組が別の
+//! 証明に現れた場合はハッシュ関数を呼び出さずにその値を再利用します。それぞれの証明は個別に
+//! [`ValuesWithBranches::root()`] で検証した場合と同じ条件で検証されます。
+//!
+//! 多数の証明をまとめて転送する場合は [`proofs_to_bytes()`] で直列化することで、複数の証明に含まれる同じ分岐ノード
+//! や値を一度だけ格納し、各証明からはその参照のみを記録することができます。[`proofs_from_bytes()`] は参照を展開して
+//! 元の証明を復元します。直列化形式は次の通りです。整数はすべてリトルエンディアンで、インデックスは
+//! [`INDEX_SIZE`](crate::INDEX_SIZE) のビット幅で表されます。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | 分岐ノードの数 (u32) | 4 |
+//! | 各分岐ノードのインデックス、高さ (u8)、ハッシュ値 | ノードの数 × (インデックス + 1 + [`HASH_SIZE`]) |
+//! | 値の数 (u32) | 4 |
+//! | 各値のインデックス、バイトサイズ (u32)、バイナリ値 | 値ごとに可変 |
+//! | 証明の数 (u32) | 4 |
+//! | 各証明の値の数 (u32)、最初の値の参照 (u32)、分岐ノードの数 (u32)、各分岐ノードの参照 (u32) | 証明ごとに可変 |
+//!
+//! 1 つの証明に含まれる値は連続しているため、値は出現順に格納し各証明からは最初の値の参照のみを記録します。
+//!
+//! [`Proof`] は検証に必要な文脈をすべて含む自己記述的な証明です。[`ValuesWithBranches`] の検証者は分岐ノードを左右
+//! のどちらに連結するかをインデックスの比較から導出し、証明がどの世代のどのハッシュアルゴリズムによるものかを別の
+//! 手段で知っている必要があります。[`Proof`] はこれらを明示的に記録し、検証の際には記録された方向の通りに連結した
+//! ルートノードの世代と高さが記録された値と一致することも確認します。
+//!
+//! [`Proof`] の直列化形式は先頭に形式のバージョンを持ち、[`Proof::from_bytes()`] は
+//! [`SUPPORTED_PROOF_FORMAT_VERSIONS`] のすべてのバージョンを復元することができます。異なるバージョンのクレートを
+//! 使用するサーバと検証者は、互いの対応バージョンから [`negotiate_proof_version()`] で決定したバージョンで証明を
+//! 受け渡します。バージョン 1 はバージョンを持たない [`ValuesWithBranches::to_bytes()`] の形式そのもので、
+//! バージョン 2 以降はバージョン 1 の値の数としては現れない `u32::MAX` を先頭に置いて区別します。バージョン 2 は
+//! 次の形式です。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | バージョン付きの形式の識別子 (`u32::MAX`) | 4 |
+//! | 形式のバージョン (2) | 1 |
+//! | ハッシュアルゴリズムの識別子 [`HASH_ALGORITHM_ID`] | 1 |
+//! | 世代 n | インデックスのビット幅 |
+//! | 木構造の高さ (u8) | 1 |
+//! | 値の数 (u32) | 4 |
+//! | 各値のインデックス、バイトサイズ (u32)、バイナリ値 | 値ごとに可変 |
+//! | 分岐ノードの数 (u32) | 4 |
+//! | 各分岐ノードの方向 (左: 1、右: 0)、インデックス、高さ (u8)、ハッシュ値 | ノードの数 × (1 + インデックス + 1 + [`HASH_SIZE`]) |
+//!
+//! ```rust,no_run
+//! use lmtht::{ValuesWithBranches, LMTHT};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let db = LMTHT::new("audit.db")?;
+//! # let trusted_root = db.root_hash().unwrap();
+//! # let requests = vec![db.query()?.get_with_hashes(1)?.unwrap().to_bytes()];
+//! let proofs = requests.iter().map(|bytes| ValuesWithBranches::from_bytes(bytes)).collect::<Result<Vec<_>, _>>();
+//! let proofs = proofs.map_err(|err| err.to_string())?;
+//! lmtht::verify_batch(&proofs, &trusted_root)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use crate::error::Detail;
+#[cfg(feature = "std")]
+use crate::Result;
+use crate::{
+  core_io, take, take_array, Hash, Index, Node, Value, ValuesWithBranches, HASH_ALGORITHM_ID, HASH_SIZE, INDEX_BYTES,
+};
+
+#[cfg(all(test, feature = "std"))]
+mod test;
+
+/// [`Proof::to_bytes()`] が出力する証明の直列化形式のバージョンです。
+pub const PROOF_FORMAT_VERSION: u8 = 2;
+
+/// [`Proof::from_bytes()`] が復元することのできる証明の直列化形式のバージョンです (古い順)。
+pub const SUPPORTED_PROOF_FORMAT_VERSIONS: &[u8] = &[1, 2];
+
+/// バージョン 2 以降の証明の先頭に置く識別子。バージョン 1 の形式では値の数が置かれる位置であり、`u32::MAX` 個の
+/// 値を持つ証明は現れない。
+const VERSIONED_PROOF_MARKER: [u8; 4] = u32::MAX.to_le_bytes();
+
+/// 世代、高さ、ハッシュアルゴリズム、分岐ノードの方向を含む自己記述的な証明です。
+#[derive(Debug)]
+pub struct Proof {
+  /// 証明の対象となる木構造の世代。
+  pub n: Index,
+  /// 世代 `n` の木構造の高さ。
+  pub height: u8,
+  /// 証明のハッシュ値を算出したハッシュアルゴリズムの識別子 ([`HASH_ALGORITHM_ID`])。
+  pub algorithm: u8,
+  /// 証明に含まれる連続した値。
+  pub values: Vec<Value>,
+  /// 経路から分岐したノード (ルートに近い順)。
+  pub branches: Vec<ProofBranch>,
+}
+
+/// [`Proof`] の経路から分岐したノードとその方向です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProofBranch {
+  /// 分岐したノード。
+  pub node: Node,
+  /// 分岐したノードが経路の左側にある場合は true。
+  pub left: bool,
+}
+
+impl Proof {
+  /// [`ValuesWithBranches`] から、その証明が示す世代と高さ、それぞれの分岐ノードの方向を記録した証明を作成します。
+  pub fn new(proof: ValuesWithBranches) -> Proof {
+    // 値を含む部分木とそこからルートまでの経路をインデックスと高さのみで折りたたむ
+    let mut nodes = proof.values.iter().map(|value| (value.i, 0u8)).collect::<Vec<_>>();
+    while nodes.len() > 1 {
+      nodes = nodes
+        .chunks(2)
+        .map(|pair| if pair.len() == 2 { (pair[1].0, pair[0].1.saturating_add(1)) } else { pair[0] })
+        .collect();
+    }
+    let (mut i, mut j) = nodes.pop().unwrap_or((0, 0));
+    let mut branches = Vec::with_capacity(proof.branches.len());
+    for node in proof.branches.iter().rev() {
+      let left = node.i < i;
+      branches.push(ProofBranch { node: *node, left });
+      j = if left { node.j } else { j }.saturating_add(1);
+      i = i.max(node.i);
+    }
+    branches.reverse();
+    Proof { n: i, height: j, algorithm: HASH_ALGORITHM_ID, values: proof.values, branches }
+  }
+
+  /// 記録された方向に従って分岐ノードを連結し、この証明から得られるルートノードを算出します。値を含まない場合や
+  /// ノードの並びが木構造として正しくない場合は `None` を返します。
+  pub fn root(&self) -> Option<Node> {
+    let mut memo = Memo::new();
+    let mut folding = memo.fold(&self.values)?;
+    for branch in self.branches.iter().rev() {
+      folding = if branch.left { memo.parent(&branch.node, &folding)? } else { memo.parent(&folding, &branch.node)? };
+    }
+    Some(folding)
+  }
+
+  /// この証明の値がルートハッシュ `root` を持つ世代 `n` の木構造に含まれていることを検証します。
+  ///
+  /// # Errors
+  /// ハッシュアルゴリズムが異なる場合や、算出したルートノードの世代、高さ、ハッシュ値のいずれかが一致しない場合は
+  /// [`Detail::ProofVerificationFailed`] を返します。
+  #[cfg(feature = "std")]
+  pub fn verify(&self, root: &Hash) -> Result<()> {
+    let matched = self.algorithm == HASH_ALGORITHM_ID
+      && self.root().map(|node| node.i == self.n && node.j == self.height && node.hash == *root).unwrap_or(false);
+    if !matched {
+      return Err(Detail::ProofVerificationFailed { n: self.n });
+    }
+    Ok(())
+  }
+
+  /// この証明を検証者に受け渡すためのバイト列に現在の形式 [`PROOF_FORMAT_VERSION`] で直列化します。
+  pub fn to_bytes(&self) -> Vec<u8> {
+    self.to_versioned_bytes(PROOF_FORMAT_VERSION).unwrap()
+  }
+
+  /// この証明を形式のバージョン `version` で直列化します。古いバージョンのみに対応した検証者に証明を受け渡す場合は
+  /// [`negotiate_proof_version()`] で決定したバージョンを指定します。バージョン 1 の形式は
+  /// [`ValuesWithBranches::to_bytes()`] と同じであり、世代や分岐の方向を含まないためそれらの情報は失われます。
+  ///
+  /// # Errors
+  /// [`SUPPORTED_PROOF_FORMAT_VERSIONS`] に含まれないバージョンを指定した場合は
+  /// [`core_io::ErrorKind::InvalidInput`] を返します。
+  pub fn to_versioned_bytes(&self, version: u8) -> core_io::Result<Vec<u8>> {
+    let values = self.values.iter().map(|value| INDEX_BYTES + 4 + value.value.len()).sum::<usize>();
+    let branches = self.branches.len() * (1 + INDEX_BYTES + 1 + HASH_SIZE);
+    let mut bytes = Vec::with_capacity(VERSIONED_PROOF_MARKER.len() + 2 + INDEX_BYTES + 1 + 4 + values + 4 + branches);
+    match version {
+      1 => (),
+      2 => {
+        bytes.extend_from_slice(&VERSIONED_PROOF_MARKER);
+        bytes.push(version);
+        bytes.push(self.algorithm);
+        bytes.extend_from_slice(&self.n.to_le_bytes());
+        bytes.push(self.height);
+      }
+      _ => return Err(core_io::Error::new(core_io::ErrorKind::InvalidInput, "unsupported proof format version")),
+    }
+    bytes.extend_from_slice(&(self.values.len() as u32).to_le_bytes());
+    for value in self.values.iter() {
+      bytes.extend_from_slice(&value.i.to_le_bytes());
+      bytes.extend_from_slice(&(value.value.len() as u32).to_le_bytes());
+      bytes.extend_from_slice(&value.value);
+    }
+    bytes.extend_from_slice(&(self.branches.len() as u32).to_le_bytes());
+    for branch in self.branches.iter() {
+      if version >= 2 {
+        bytes.push(branch.left as u8);
+      }
+      bytes.extend_from_slice(&branch.node.i.to_le_bytes());
+      bytes.push(branch.node.j);
+      bytes.extend_from_slice(&branch.node.hash.value);
+    }
+    Ok(bytes)
+  }
+
+  /// [`Proof::to_bytes()`] または [`Proof::to_versioned_bytes()`] で直列化された証明を、先頭のバージョンに従って
+  /// 復元します。バージョン付きの形式の識別子で始まらないバイト列はバージョン 1、つまり
+  /// [`ValuesWithBranches::to_bytes()`] の形式として復元し、世代と分岐の方向は [`Proof::new()`] と同様にインデックス
+  /// から導出されます。
+  ///
+  /// # Errors
+  /// 対応していないバージョンの場合、このビルドと異なるハッシュアルゴリズムの証明の場合、直列化形式が不正な場合、値
+  /// が含まれていないか連続していない場合、末尾に余分なバイトが存在する場合は [`core_io::ErrorKind::InvalidData`]
+  /// を返します。
+  pub fn from_bytes(mut bytes: &[u8]) -> core_io::Result<Proof> {
+    let invalid = |message: &'static str| core_io::Error::new(core_io::ErrorKind::InvalidData, message);
+    if !bytes.starts_with(&VERSIONED_PROOF_MARKER) {
+      return ValuesWithBranches::from_bytes(bytes).map(Proof::new);
+    }
+    bytes = &bytes[VERSIONED_PROOF_MARKER.len()..];
+    match take_array::<1>(&mut bytes)?[0] {
+      2 => {
+        let algorithm = take_array::<1>(&mut bytes)?[0];
+        if algorithm != HASH_ALGORITHM_ID {
+          return Err(invalid("the proof uses a different hash algorithm"));
+        }
+        let n = Index::from_le_bytes(take_array(&mut bytes)?);
+        let height = take_array::<1>(&mut bytes)?[0];
+        let count = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+        let mut values = Vec::<Value>::with_capacity(count.min(bytes.len() / (INDEX_BYTES + 4)));
+        for _ in 0..count {
+          let i = Index::from_le_bytes(take_array(&mut bytes)?);
+          let length = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+          if values.last().map(|last| last.i.checked_add(1) != Some(i)).unwrap_or(false) {
+            return Err(invalid("values in the proof are not contiguous"));
+          }
+          values.push(Value::new(i, take(&mut bytes, length)?.to_vec()));
+        }
+        if values.is_empty() {
+          return Err(invalid("the proof contains no values"));
+        }
+        let count = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+        let mut branches = Vec::with_capacity(count.min(bytes.len() / (1 + INDEX_BYTES + 1 + HASH_SIZE)));
+        for _ in 0..count {
+          let left = match take_array::<1>(&mut bytes)?[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid("invalid direction of the branch")),
+          };
+          let i = Index::from_le_bytes(take_array(&mut bytes)?);
+          let j = take_array::<1>(&mut bytes)?[0];
+          let hash = Hash::new(take_array(&mut bytes)?);
+          branches.push(ProofBranch { node: Node::new(i, j, hash), left });
+        }
+        if !bytes.is_empty() {
+          return Err(invalid("trailing bytes after the proof"));
+        }
+        Ok(Proof { n, height, algorithm, values, branches })
+      }
+      _ => Err(invalid("unsupported proof format version")),
+    }
+  }
+}
+
+/// 互いに対応している証明の形式のバージョンのうち最も新しいものを返します。`peer` は通信相手が対応している
+/// バージョンの集合 (相手の [`SUPPORTED_PROOF_FORMAT_VERSIONS`]) です。共通のバージョンが存在しない場合は `None`
+/// を返します。
+pub fn negotiate_proof_version(peer: &[u8]) -> Option<u8> {
+  SUPPORTED_PROOF_FORMAT_VERSIONS.iter().rev().find(|version| peer.contains(version)).copied()
+}
+
+/// 証明 `proofs` から算出されるルートハッシュがすべて `root` と一致することを検証します。
+///
+/// # Errors
+/// ルートハッシュが一致しない証明や値を含まない証明が存在する場合は、最初のその証明の位置を示す
+/// [`Detail::BatchVerificationFailed`] を返します。
+#[cfg(feature = "std")]
+pub fn verify_batch(proofs: &[ValuesWithBranches], root: &Hash) -> Result<()> {
+  let mut memo = Memo::new();
+  for (index, proof) in proofs.iter().enumerate() {
+    if memo.root(proof).map(|node| node.hash != *root).unwrap_or(true) {
+      return Err(Detail::BatchVerificationFailed { index });
+    }
+  }
+  Ok(())
+}
+
+/// 複数の証明 `proofs` を、共通する分岐ノードと値を一度だけ格納する形式で直列化します。
+pub fn proofs_to_bytes(proofs: &[ValuesWithBranches]) -> Vec<u8> {
+  let mut branches = Vec::<&Node>::new();
+  let mut branch_refs = BTreeMap::<(Index, u8, [u8; HASH_SIZE]), u32>::new();
+  let mut values = Vec::<&Value>::new();
+  let mut value_refs = BTreeMap::<(Index, &[u8]), u32>::new();
+  let mut refs = Vec::<u8>::new();
+  for proof in proofs.iter() {
+    // 値の並びがすでに格納されている場合はそれを参照する
+    let first = proof.values.first().and_then(|first| value_refs.get(&(first.i, &first.value[..])));
+    let start = match first {
+      Some(&start)
+        if proof.values.iter().enumerate().all(|(k, value)| {
+          values.get(start as usize + k).map(|stored| stored.i == value.i && stored.value == value.value) == Some(true)
+        }) =>
+      {
+        start
+      }
+      _ => {
+        let start = values.len() as u32;
+        for value in proof.values.iter() {
+          value_refs.entry((value.i, &value.value[..])).or_insert(values.len() as u32);
+          values.push(value);
+        }
+        start
+      }
+    };
+    refs.extend_from_slice(&(proof.values.len() as u32).to_le_bytes());
+    refs.extend_from_slice(&start.to_le_bytes());
+    refs.extend_from_slice(&(proof.branches.len() as u32).to_le_bytes());
+    for branch in proof.branches.iter() {
+      let reference = *branch_refs.entry((branch.i, branch.j, branch.hash.value)).or_insert_with(|| {
+        branches.push(branch);
+        branches.len() as u32 - 1
+      });
+      refs.extend_from_slice(&reference.to_le_bytes());
+    }
+  }
+
+  let mut bytes = Vec::new();
+  bytes.extend_from_slice(&(branches.len() as u32).to_le_bytes());
+  for branch in branches.iter() {
+    bytes.extend_from_slice(&branch.i.to_le_bytes());
+    bytes.push(branch.j);
+    bytes.extend_from_slice(&branch.hash.value);
+  }
+  bytes.extend_from_slice(&(values.len() as u32).to_le_bytes());
+  for value in values.iter() {
+    bytes.extend_from_slice(&value.i.to_le_bytes());
+    bytes.extend_from_slice(&(value.value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&value.value);
+  }
+  bytes.extend_from_slice(&(proofs.len() as u32).to_le_bytes());
+  bytes.extend_from_slice(&refs);
+  bytes
+}
+
+/// [`proofs_to_bytes()`] で直列化された証明の参照を展開して復元します。
+///
+/// # Errors
+/// 直列化形式が不正な場合、範囲外の参照を含む場合、証明の値が含まれていないか連続していない場合、末尾に余分な
+/// バイトが存在する場合は [`core_io::ErrorKind::InvalidData`] を返します。
+pub fn proofs_from_bytes(mut bytes: &[u8]) -> core_io::Result<Vec<ValuesWithBranches>> {
+  let invalid = |message: &'static str| core_io::Error::new(core_io::ErrorKind::InvalidData, message);
+  let count = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+  let mut branches = Vec::with_capacity(count.min(bytes.len() / (INDEX_BYTES + 1 + HASH_SIZE)));
+  for _ in 0..count {
+    let i = Index::from_le_bytes(take_array(&mut bytes)?);
+    let j = take_array::<1>(&mut bytes)?[0];
+    let hash = Hash::new(take_array(&mut bytes)?);
+    branches.push(Node::new(i, j, hash));
+  }
+  let count = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+  let mut values = Vec::with_capacity(count.min(bytes.len() / (INDEX_BYTES + 4)));
+  for _ in 0..count {
+    let i = Index::from_le_bytes(take_array(&mut bytes)?);
+    let length = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+    values.push(Value::new(i, take(&mut bytes, length)?.to_vec()));
+  }
+
+  let count = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+  let mut proofs = Vec::with_capacity(count.min(bytes.len() / 12));
+  for _ in 0..count {
+    let length = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+    let start = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+    let proof_values = match start.checked_add(length).and_then(|end| values.get(start..end)) {
+      Some(proof_values) if !proof_values.is_empty() => {
+        proof_values.iter().map(|value| Value::new(value.i, value.value.clone())).collect::<Vec<_>>()
+      }
+      _ => return Err(invalid("the values referenced by the proof are out of range")),
+    };
+    if proof_values.windows(2).any(|pair| pair[0].i.checked_add(1) != Some(pair[1].i)) {
+      return Err(invalid("values in the proof are not contiguous"));
+    }
+    let length = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+    let mut proof_branches = Vec::with_capacity(length.min(bytes.len() / 4));
+    for _ in 0..length {
+      let reference = u32::from_le_bytes(take_array(&mut bytes)?) as usize;
+      match branches.get(reference) {
+        Some(branch) => proof_branches.push(*branch),
+        None => return Err(invalid("the branch referenced by the proof is out of range")),
+      }
+    }
+    proofs.push(ValuesWithBranches::new(proof_values, proof_branches));
+  }
+  if !bytes.is_empty() {
+    return Err(invalid("trailing bytes after the proofs"));
+  }
+  Ok(proofs)
+}
+
+/// 算出した中間ノードのハッシュ値を左右の子のハッシュ値の組で記憶します。
+struct Memo {
+  parents: BTreeMap<([u8; HASH_SIZE], [u8; HASH_SIZE]), Hash>,
+}
+
+impl Memo {
+  fn new() -> Memo {
+    Memo { parents: BTreeMap::new() }
+  }
+
+  /// [`ValuesWithBranches::root()`] と同じ手順で証明のルートノードを算出します。値を含まない場合や、ノードの
+  /// 並びが木構造として正しくない場合は `None` を返します。
+  #[cfg(feature = "std")]
+  fn root(&mut self, proof: &ValuesWithBranches) -> Option<Node> {
+    let mut folding = self.fold(&proof.values)?;
+
+    // 経路から分岐したノードのハッシュ値と統合する
+    for branch in proof.branches.iter().rev() {
+      folding = if folding.i < branch.i { self.parent(&folding, branch)? } else { self.parent(branch, &folding)? };
+    }
+    Some(folding)
+  }
+
+  /// 連続した値から算出したハッシュ値を折りたたみ、値を含む部分木のルートノードを算出します。
+  fn fold(&mut self, values: &[Value]) -> Option<Node> {
+    // 要素数が奇数の場合は最も右のノードを次に持ち越す
+    let mut nodes = values.iter().map(|value| value.to_node()).collect::<Vec<_>>();
+    while nodes.len() > 1 {
+      let mut parents = Vec::with_capacity(nodes.len().div_ceil(2));
+      for pair in nodes.chunks(2) {
+        parents.push(if pair.len() == 2 { self.parent(&pair[0], &pair[1])? } else { pair[0] });
+      }
+      nodes = parents;
+    }
+    nodes.pop()
+  }
+
+  /// [`Node::parent()`] と同じ中間ノードを算出します。左右の子の位置関係が正しくない場合は `None` を返します。
+  fn parent(&mut self, left: &Node, right: &Node) -> Option<Node> {
+    if left.i >= right.i || left.j < right.j {
+      return None;
+    }
+    let hash =
+      *self.parents.entry((left.hash.value, right.hash.value)).or_insert_with(|| left.hash.combine(&right.hash));
+    Some(Node::new(right.i, left.j.checked_add(1)?, hash))
+  }
+}
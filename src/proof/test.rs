@@ -0,0 +1,172 @@
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// 多数の証明をまとめて検証した結果が個別に検証した結果と一致し、不正な証明の位置が報告されることを検証します。
+#[test]
+fn test_verify_batch() {
+  use crate::error::Detail;
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 0..100 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let root = db.root_hash().unwrap();
+  let mut query = db.query().unwrap();
+  let mut proofs = Vec::new();
+  for i in 1..=query.n() {
+    proofs.push(query.get_with_hashes(i).unwrap().unwrap());
+  }
+  proofs.push(query.get_values_with_hashes(64, 5).unwrap().unwrap());
+  proofs.push(query.get_values_with_hashes(100, 2).unwrap().unwrap());
+  for proof in proofs.iter() {
+    assert_eq!(root, proof.root().hash);
+  }
+  verify_batch(&proofs, &root).unwrap();
+  verify_batch(&[], &root).unwrap();
+  assert!(matches!(verify_batch(&proofs, &Hash::hash(b"other")), Err(Detail::BatchVerificationFailed { index: 0 })));
+
+  // 改ざんした値や分岐ノードを含む証明
+  proofs[41].values[0].value[0] ^= 0x01;
+  assert!(matches!(verify_batch(&proofs, &root), Err(Detail::BatchVerificationFailed { index: 41 })));
+  proofs[41].values[0].value[0] ^= 0x01;
+  let last = proofs[77].branches.len() - 1;
+  let branch = proofs[77].branches[last];
+  proofs[77].branches[last].hash = Hash::hash(b"forged");
+  assert!(matches!(verify_batch(&proofs, &root), Err(Detail::BatchVerificationFailed { index: 77 })));
+  proofs[77].branches[last] = branch;
+  verify_batch(&proofs, &root).unwrap();
+  proofs[77].branches.swap(0, last);
+  assert!(matches!(verify_batch(&proofs, &root), Err(Detail::BatchVerificationFailed { index: 77 })));
+}
+
+/// 複数の証明を共通する分岐ノードと値を共有して直列化し、元の証明に復元できることを検証します。
+#[test]
+fn test_proofs_to_bytes() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 0..100 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut query = db.query().unwrap();
+  let mut proofs = Vec::new();
+  for i in (1..=query.n()).step_by(3) {
+    proofs.push(query.get_with_hashes(i).unwrap().unwrap());
+  }
+  proofs.push(query.get_values_with_hashes(64, 5).unwrap().unwrap());
+  proofs.push(query.get_with_hashes(40).unwrap().unwrap());
+  proofs.push(query.get_with_hashes(40).unwrap().unwrap());
+
+  // 分岐ノードは (i, j, ハッシュ値) ごとに一度だけ格納され、既出の値の並びと一致する末尾 2 つの証明の値は格納
+  // されない
+  let bytes = proofs_to_bytes(&proofs);
+  let branches = proofs.iter().flat_map(|proof| proof.branches.iter().map(|b| (b.i, b.j, b.hash.value)));
+  let branches = branches.collect::<std::collections::BTreeSet<_>>().len();
+  let values = proofs[..proofs.len() - 2].iter().flat_map(|proof| proof.values.iter());
+  let expected = 4
+    + branches * (INDEX_BYTES + 1 + HASH_SIZE)
+    + 4
+    + values.map(|value| INDEX_BYTES + 4 + value.value.len()).sum::<usize>()
+    + 4
+    + proofs.iter().map(|proof| 4 * 3 + 4 * proof.branches.len()).sum::<usize>();
+  assert_eq!(expected, bytes.len());
+  let individual = proofs.iter().map(|proof| proof.to_bytes().len()).sum::<usize>();
+  assert!(bytes.len() < individual, "{} >= {}", bytes.len(), individual);
+  let restored = proofs_from_bytes(&bytes).unwrap();
+  assert_eq!(proofs.len(), restored.len());
+  for (proof, restored) in proofs.iter().zip(restored.iter()) {
+    assert_eq!(proof.to_bytes(), restored.to_bytes());
+  }
+  assert!(proofs_from_bytes(&proofs_to_bytes(&[])).unwrap().is_empty());
+
+  // 切り詰められたバイト列や範囲外の参照、余分なバイト列
+  assert!(proofs_from_bytes(&bytes[..bytes.len() - 1]).is_err());
+  let mut broken = bytes.clone();
+  let length = broken.len();
+  broken[length - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+  assert!(proofs_from_bytes(&broken).is_err());
+  let mut trailing = bytes.clone();
+  trailing.push(0);
+  assert!(proofs_from_bytes(&trailing).is_err());
+}
+
+/// 自己記述的な証明が世代や分岐の方向を記録し、直列化の後も検証でき、方向や世代の改ざんが検出されることを検証します。
+#[test]
+fn test_self_describing_proof() {
+  use crate::error::Detail;
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 0..45 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let root = db.root().unwrap();
+  let mut query = db.query().unwrap();
+  for (i, j) in [(1, 0), (17, 0), (45, 0), (32, 5), (44, 2)] {
+    let proof = Proof::new(query.get_values_with_hashes(i, j).unwrap().unwrap());
+    assert_eq!((root.i, root.j, HASH_ALGORITHM_ID), (proof.n, proof.height, proof.algorithm));
+    proof.verify(&root.hash).unwrap();
+    let restored = Proof::from_bytes(&proof.to_bytes()).unwrap();
+    assert_eq!(proof.to_bytes(), restored.to_bytes());
+    restored.verify(&root.hash).unwrap();
+  }
+
+  // 方向や世代を改ざんした証明
+  let mut proof = Proof::new(query.get_with_hashes(17).unwrap().unwrap());
+  proof.branches[0].left = !proof.branches[0].left;
+  assert!(matches!(proof.verify(&root.hash), Err(Detail::ProofVerificationFailed { n: 45 })));
+  proof.branches[0].left = !proof.branches[0].left;
+  proof.n = 44;
+  assert!(proof.verify(&root.hash).is_err());
+  proof.n = 45;
+  proof.height += 1;
+  assert!(proof.verify(&root.hash).is_err());
+  proof.height -= 1;
+  proof.verify(&root.hash).unwrap();
+
+  let mut bytes = proof.to_bytes();
+  bytes[5] = bytes[5].wrapping_add(1);
+  assert!(Proof::from_bytes(&bytes).is_err());
+  bytes[5] = bytes[5].wrapping_sub(1);
+  bytes.push(0);
+  assert!(Proof::from_bytes(&bytes).is_err());
+}
+
+/// 証明を対応バージョンの交渉で決定した形式で直列化し、古い形式を含むすべての対応バージョンから復元できることを検証
+/// します。
+#[test]
+fn test_versioned_proof_format() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 0..45 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let root = db.root().unwrap();
+  let mut query = db.query().unwrap();
+  let proof = Proof::new(query.get_values_with_hashes(44, 2).unwrap().unwrap());
+
+  assert_eq!(Some(PROOF_FORMAT_VERSION), negotiate_proof_version(SUPPORTED_PROOF_FORMAT_VERSIONS));
+  assert_eq!(Some(1), negotiate_proof_version(&[0, 1]));
+  assert_eq!(None, negotiate_proof_version(&[0, 99]));
+  assert_eq!(PROOF_FORMAT_VERSION, proof.to_bytes()[4]);
+
+  for &version in SUPPORTED_PROOF_FORMAT_VERSIONS {
+    let bytes = proof.to_versioned_bytes(version).unwrap();
+    let restored = Proof::from_bytes(&bytes).unwrap();
+    assert_eq!((proof.n, proof.height), (restored.n, restored.height));
+    assert_eq!(proof.branches, restored.branches);
+    restored.verify(&root.hash).unwrap();
+  }
+
+  // バージョン 1 は ValuesWithBranches の直列化形式そのものであり、既存の証明をそのまま復元できる
+  let legacy = query.get_values_with_hashes(44, 2).unwrap().unwrap().to_bytes();
+  assert_eq!(proof.to_versioned_bytes(1).unwrap(), legacy);
+  let restored = Proof::from_bytes(&legacy).unwrap();
+  assert_eq!((proof.n, proof.height), (restored.n, restored.height));
+  restored.verify(&root.hash).unwrap();
+  for i in [1, 2, 45] {
+    let legacy = query.get_with_hashes(i).unwrap().unwrap().to_bytes();
+    Proof::from_bytes(&legacy).unwrap().verify(&root.hash).unwrap();
+  }
+
+  assert!(proof.to_versioned_bytes(99).is_err());
+  let mut bytes = proof.to_bytes();
+  bytes[4] = 99;
+  assert!(Proof::from_bytes(&bytes).is_err());
+}
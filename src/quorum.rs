@@ -0,0 +1,300 @@
+//! 複数のストレージに同じ内容を書き込み、指定した数のストレージへの書き込みが成功した時点で成功とするストレージ
+//! です。
+//!
+//! [`QuorumStorage`] は構築時にすべてのレプリカを読み込み専用の LMTHT としてオープンし、末尾のエントリのチェック
+//! サムを検証したうえで最も多くのエントリを持つレプリカを最新とします。最新のレプリカと同じ世代とルートハッシュを
+//! 持つレプリカのみが同期済みとして読み書きの対象となり、古いレプリカや破損したレプリカは
+//! [`QuorumStorage::repair()`] で最新の内容を複製するまで使用されません。
+//!
+//! 書き込み、切り詰め、同期は同期済みのすべてのレプリカに行われ、失敗したレプリカは以降の対象から外されます。
+//! 成功したレプリカの数がクォーラムに満たない場合、その操作はエラーとなります。読み込みは最新のレプリカから行い、
+//! 失敗した場合は他の同期済みのレプリカから読み込みます。別の複製サービスを運用することなく、単一のディスクを
+//! 超える可用性を得るために使用します。
+//!
+//! ```rust
+//! use lmtht::{LMTHT, MemStorage};
+//! use lmtht::quorum::QuorumStorage;
+//! let replicas = vec![MemStorage::new(), MemStorage::new(), MemStorage::new()];
+//! let db = LMTHT::new(QuorumStorage::new(replicas, 2).unwrap()).unwrap();
+//! ```
+//!
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::Detail;
+use crate::{Capabilities, Cursor, Hash, Index, LMTHTOptions, Result, Storage, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// 複数のレプリカに書き込み、クォーラムに達した時点で成功とするストレージです。
+pub struct QuorumStorage<S: Storage> {
+  replicas: Vec<S>,
+  shared: Arc<Shared>,
+}
+
+struct Shared {
+  quorum: usize,
+  /// レプリカごとの最新の内容を保持しているかのフラグ。
+  in_sync: Vec<AtomicBool>,
+  /// 構築時に最新と判断したレプリカの番号。
+  preferred: usize,
+}
+
+impl<S: Storage> QuorumStorage<S> {
+  /// 指定されたレプリカのうち `quorum` 個への書き込みが成功した時点で成功とするストレージを構築します。同期済みの
+  /// レプリカがクォーラムに満たない場合は [`Detail::QuorumUnavailable`] で失敗します。
+  pub fn new(replicas: Vec<S>, quorum: usize) -> Result<QuorumStorage<S>> {
+    assert!(quorum > 0 && quorum <= replicas.len());
+    let states = replicas.iter().map(validate).collect::<Vec<_>>();
+    let latest = states.iter().flatten().max_by_key(|(n, _)| *n).cloned();
+    let preferred = states.iter().position(|state| state.is_some() && *state == latest).unwrap_or(0);
+    let in_sync = states.iter().map(|state| AtomicBool::new(state.is_some() && *state == latest)).collect::<Vec<_>>();
+    let storage = QuorumStorage { replicas, shared: Arc::new(Shared { quorum, in_sync, preferred }) };
+    let available = storage.shared.available();
+    if available < quorum {
+      return Err(Detail::QuorumUnavailable { available, quorum });
+    }
+    Ok(storage)
+  }
+
+  /// レプリカを参照します。
+  pub fn replicas(&self) -> &[S] {
+    &self.replicas
+  }
+
+  /// それぞれのレプリカが最新の内容を保持しているかを参照します。
+  pub fn in_sync(&self) -> Vec<bool> {
+    self.shared.in_sync.iter().map(|in_sync| in_sync.load(Ordering::Acquire)).collect()
+  }
+
+  /// 同期済みのレプリカの内容を同期済みでないすべてのレプリカに複製します。複製の間に書き込みが行われないよう、
+  /// LMTHT を使用していない時点で呼び出す必要があります。
+  pub fn repair(&self) -> Result<()> {
+    let source = match self.shared.order().find(|i| self.shared.is_in_sync(*i)) {
+      Some(source) => source,
+      None => return Err(Detail::QuorumUnavailable { available: 0, quorum: self.shared.quorum }),
+    };
+    for (i, replica) in self.replicas.iter().enumerate() {
+      if self.shared.is_in_sync(i) {
+        continue;
+      }
+      let mut source = self.replicas[source].open(false)?;
+      let mut target = replica.open(true)?;
+      source.seek(SeekFrom::Start(0))?;
+      target.set_len(0)?;
+      target.seek(SeekFrom::Start(0))?;
+      io::copy(&mut source, &mut target)?;
+      target.sync_data()?;
+      self.shared.in_sync[i].store(true, Ordering::Release);
+    }
+    Ok(())
+  }
+}
+
+impl<S: Storage> Storage for QuorumStorage<S> {
+  type Cursor = QuorumCursor<S::Cursor>;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    let mut cursors = Vec::with_capacity(self.replicas.len());
+    for (i, replica) in self.replicas.iter().enumerate() {
+      let cursor = if self.shared.is_in_sync(i) {
+        match replica.open(writable) {
+          Ok(cursor) => Some(cursor),
+          // 他の書き込みがロックを保持しているレプリカは故障として扱わない
+          Err(err @ Detail::StorageLocked { .. }) => return Err(err),
+          Err(_) => None,
+        }
+      } else {
+        None
+      };
+      if cursor.is_none() {
+        self.shared.in_sync[i].store(false, Ordering::Release);
+      }
+      cursors.push(cursor);
+    }
+    let available = self.shared.available();
+    if available < self.shared.quorum {
+      return Err(Detail::QuorumUnavailable { available, quorum: self.shared.quorum });
+    }
+    Ok(QuorumCursor { cursors, shared: self.shared.clone(), position: 0 })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.replicas[0].capabilities()
+  }
+}
+
+impl Shared {
+  fn is_in_sync(&self, i: usize) -> bool {
+    self.in_sync[i].load(Ordering::Acquire)
+  }
+
+  fn available(&self) -> usize {
+    (0..self.in_sync.len()).filter(|i| self.is_in_sync(*i)).count()
+  }
+
+  /// 読み込みを試みるレプリカの順序。最新と判断したレプリカを先頭とします。
+  fn order(&self) -> impl Iterator<Item = usize> + '_ {
+    std::iter::once(self.preferred).chain((0..self.in_sync.len()).filter(move |i| *i != self.preferred))
+  }
+}
+
+/// レプリカを読み込み専用の LMTHT としてオープンし、末尾のエントリが正しい場合にその世代とルートハッシュを返します。
+fn validate<S: Storage>(replica: &S) -> Option<(Index, Option<Hash>)> {
+  let options = LMTHTOptions { read_only: true, ..Default::default() };
+  LMTHT::with_options(Borrowed(replica), options).ok().map(|db| (db.n(), db.root_hash()))
+}
+
+/// 所有権を移さずにストレージを LMTHT に渡すための参照。
+struct Borrowed<'a, S: Storage>(&'a S);
+
+impl<S: Storage> Storage for Borrowed<'_, S> {
+  type Cursor = S::Cursor;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    self.0.open(writable)
+  }
+}
+
+/// [`QuorumStorage`] が使用するカーソルです。
+pub struct QuorumCursor<C: Cursor> {
+  /// レプリカごとのカーソル。同期済みでないレプリカは `None`。
+  cursors: Vec<Option<C>>,
+  shared: Arc<Shared>,
+  position: u64,
+}
+
+impl<C: Cursor> QuorumCursor<C> {
+  /// 同期済みのすべてのレプリカに `f` を実行します。失敗したレプリカは同期済みから外され、成功したレプリカの数が
+  /// クォーラムに満たない場合はエラーを返します。
+  fn replicate<F: FnMut(&mut C) -> io::Result<()>>(&mut self, mut f: F) -> io::Result<()> {
+    let mut succeeded = 0;
+    let mut last_error = None;
+    for (i, cursor) in self.cursors.iter_mut().enumerate() {
+      if !self.shared.is_in_sync(i) {
+        *cursor = None;
+      }
+      match cursor.as_mut().map(&mut f) {
+        Some(Ok(())) => succeeded += 1,
+        Some(Err(err)) => {
+          self.shared.in_sync[i].store(false, Ordering::Release);
+          *cursor = None;
+          last_error = Some(err);
+        }
+        // このカーソルのオープン後に修復されたレプリカには書き込めないため再び同期済みから外す
+        None => self.shared.in_sync[i].store(false, Ordering::Release),
+      }
+    }
+    if succeeded < self.shared.quorum {
+      let msg = format!(
+        "only {} replicas succeeded, less than the quorum of {}: {}",
+        succeeded,
+        self.shared.quorum,
+        last_error.map(|err| err.to_string()).unwrap_or_default()
+      );
+      return Err(io::Error::other(msg));
+    }
+    Ok(())
+  }
+
+  /// 最新のレプリカから順に `f` を実行し、最初に成功した結果を返します。失敗したレプリカは同期済みから外されます。
+  fn first<T, F: FnMut(&mut C) -> io::Result<T>>(&mut self, mut f: F) -> io::Result<T> {
+    let mut last_error = None;
+    let order = self.shared.order().collect::<Vec<_>>();
+    for i in order {
+      if !self.shared.is_in_sync(i) {
+        continue;
+      }
+      if let Some(cursor) = self.cursors[i].as_mut() {
+        match f(cursor) {
+          Ok(value) => return Ok(value),
+          Err(err) => {
+            self.shared.in_sync[i].store(false, Ordering::Release);
+            self.cursors[i] = None;
+            last_error = Some(err);
+          }
+        }
+      }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no replica is in sync")))
+  }
+}
+
+impl<C: Cursor> Cursor for QuorumCursor<C> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.replicate(|cursor| cursor.sync_data())
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.replicate(|cursor| cursor.set_len(length))
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.first(|cursor| cursor.len())
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.replicate(|cursor| cursor.discard(position, length))?;
+    self.position = position + length;
+    Ok(())
+  }
+
+  fn duplicate(&self) -> io::Result<Option<Self>> {
+    // 同期済みのすべてのレプリカのカーソルが複製に対応している場合のみ複製できる
+    let mut cursors = Vec::with_capacity(self.cursors.len());
+    for cursor in &self.cursors {
+      match cursor.as_ref().map(|cursor| cursor.duplicate()).transpose()? {
+        Some(None) => return Ok(None),
+        duplicated => cursors.push(duplicated.flatten()),
+      }
+    }
+    Ok(Some(QuorumCursor { cursors, shared: self.shared.clone(), position: 0 }))
+  }
+}
+
+impl<C: Cursor> Seek for QuorumCursor<C> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => (position, 0),
+      SeekFrom::End(offset) => (self.first(|cursor| cursor.seek(SeekFrom::End(0)))?, offset),
+      SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl<C: Cursor> Read for QuorumCursor<C> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let position = self.position;
+    let length = self.first(|cursor| {
+      cursor.seek(SeekFrom::Start(position))?;
+      cursor.read(buf)
+    })?;
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl<C: Cursor> Write for QuorumCursor<C> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let position = self.position;
+    self.replicate(|cursor| {
+      cursor.seek(SeekFrom::Start(position))?;
+      cursor.write_all(buf)
+    })?;
+    self.position += buf.len() as u64;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.replicate(|cursor| cursor.flush())
+  }
+}
@@ -0,0 +1,43 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::*;
+
+/// `QuorumStorage` がクォーラムに達した書き込みを成功とし、古いレプリカを除外して修復できることを検証します。
+#[test]
+fn test_quorum_storage() {
+  use crate::fault::FaultyStorage;
+  use crate::quorum::QuorumStorage;
+
+  let buffers = (0..3).map(|_| Arc::new(RwLock::new(Vec::new()))).collect::<Vec<_>>();
+  let replicas = buffers.iter().map(|buffer| FaultyStorage::new(MemStorage::with(buffer.clone()))).collect();
+  let db = LMTHT::new(QuorumStorage::new(replicas, 2).unwrap()).unwrap();
+  for i in 1..=10u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  assert_eq!(vec![true, true, true], db.storage().in_sync());
+
+  // 1 つのレプリカへの書き込みが失敗してもクォーラムに達していれば追加は成功する
+  db.storage().replicas()[0].fail_nth_write(1);
+  for i in 11..=20u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  assert_eq!(vec![false, true, true], db.storage().in_sync());
+  drop(db);
+
+  // 古いレプリカは構築時に除外され、最新のレプリカから読み込む
+  let replicas = buffers.iter().map(|buffer| MemStorage::with(buffer.clone())).collect();
+  let db = LMTHT::new(QuorumStorage::new(replicas, 2).unwrap()).unwrap();
+  assert_eq!(vec![false, true, true], db.storage().in_sync());
+  let mut query = db.query().unwrap();
+  for i in 1..=20u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+  db.storage().repair().unwrap();
+  assert_eq!(vec![true, true, true], db.storage().in_sync());
+  assert_eq!(*buffers[0].read().unwrap(), *buffers[1].read().unwrap());
+
+  // 同期済みのレプリカがクォーラムに満たない
+  let replicas = vec![MemStorage::with(buffers[0].clone()), MemStorage::new()];
+  assert!(matches!(QuorumStorage::new(replicas, 2), Err(Detail::QuorumUnavailable { available: 1, quorum: 2 })));
+}
@@ -0,0 +1,438 @@
+//! 値にタグやタイムスタンプ、キー、メタデータなどの属性を付加して保存するレコード形式です。
+//!
+//! [`LMTHT::append_record()`] は [`Record`] を次の形式に直列化して値として追加します。属性は値の一部としてハッシュ木
+//! に保存されるため葉ノードのハッシュ値に含まれ、値と同様に証明によって検証することができます。
+//!
+//! | 内容 | バイトサイズ |
+//! |:-----|:-------------|
+//! | [`RECORD_MARKER`] | 1 |
+//! | 属性の有無を表すフラグ | 1 |
+//! | タグ (u32 LE、[`FLAG_TAG`] の場合) | 4 |
+//! | タイムスタンプ (u64 LE、[`FLAG_TIMESTAMP`] の場合) | 8 |
+//! | キー長 (u32 LE、[`FLAG_KEY`] の場合) | 4 |
+//! | キー ([`FLAG_KEY`] の場合) | キー長 |
+//! | メタデータ長 (u32 LE、[`FLAG_METADATA`] の場合) | 4 |
+//! | メタデータ ([`FLAG_METADATA`] の場合) | メタデータ長 |
+//! | ペイロード | 残り |
+//!
+//! 属性は値の先頭に配置されるため、[`Query::iter_tagged()`] や [`Query::get_timestamp()`] はエントリの値の先頭のみを
+//! 読み込み、ペイロードを読み込まずに属性を参照します。返す値やメタデータはエントリ全体を読み込んで検証します。
+//!
+//! [`LMTHTOptions::timestamps`](crate::LMTHTOptions::timestamps) を指定した LMTHT では、追加したレコードに UNIX エポッ
+//! クからのミリ秒で表した時刻が付加されます。時刻はインデックスの順に単調増加するため、[`Query::range_by_time()`] は二
+//! 分探索によって時刻の範囲に含まれるレコードのインデックスの範囲を求めます。
+//!
+//! [`LMTHTOptions::records`](crate::LMTHTOptions::records) を指定した LMTHT は、すべての値がレコード形式であることを
+//! ストレージのヘッダに記録し、レコード形式でない値の追加を [`Detail::InvalidRecord`] で拒否します。時刻やキーは値の
+//! 先頭から読み込むため、タイムスタンプやキー索引を指定した LMTHT では常に有効です。記録されていないストレージでは
+//! 任意の値を追加でき、レコード形式でない値の参照は [`Detail::InvalidRecord`] を返します。
+//!
+//! ```rust,no_run
+//! use lmtht::record::Record;
+//! use lmtht::LMTHT;
+//!
+//! const LOGIN: u32 = 1;
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let db = LMTHT::new("audit.db")?;
+//! let metadata = Some(b"actor=42".to_vec());
+//! db.append_record(&Record { tag: Some(LOGIN), metadata, payload: b"alice".to_vec(), ..Default::default() })?;
+//! for record in db.query()?.iter_tagged(LOGIN) {
+//!   let (i, record) = record?;
+//!   println!("{}: {:?}", i, record.payload);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io::{ErrorKind, Read, SeekFrom};
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::error::Detail;
+use crate::metrics::observe;
+use crate::{
+  check_pruned, read_entry, read_entry_without_check, read_inodes, Cursor, ENode, Hash, Index, Node, Query, Result,
+  Storage, HASH_SIZE, INDEX_BYTES, INODE_SIZE, LMTHT, MAX_PAYLOAD_SIZE, STORAGE_IDENTIFIER,
+};
+
+#[cfg(test)]
+mod test;
+
+/// レコード形式の値の先頭に配置される識別子です。
+pub const RECORD_MARKER: u8 = 0xD7;
+
+/// レコードがタグを持つことを表すフラグです。
+pub const FLAG_TAG: u8 = 0x01;
+
+/// レコードがメタデータを持つことを表すフラグです。
+pub const FLAG_METADATA: u8 = 0x02;
+
+/// レコードがタイムスタンプを持つことを表すフラグです。
+pub const FLAG_TIMESTAMP: u8 = 0x04;
+
+/// レコードがキーを持つことを表すフラグです。
+pub const FLAG_KEY: u8 = 0x08;
+
+/// この実装が解釈できるフラグ。
+const KNOWN_FLAGS: u8 = FLAG_TAG | FLAG_METADATA | FLAG_TIMESTAMP | FLAG_KEY;
+
+/// 属性を付加した値です。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Record {
+  /// アプリケーションが値の種類を区別するためのタグ。
+  pub tag: Option<u32>,
+  /// UNIX エポックからのミリ秒で表したレコードの時刻。
+  pub timestamp: Option<u64>,
+  /// [`LMTHT::append_with_key()`] で追加した値のキー。
+  pub key: Option<Vec<u8>>,
+  /// 操作者の ID やスキーマのバージョンなど、ペイロードとは別に参照するためのメタデータ。
+  pub metadata: Option<Vec<u8>>,
+  /// 値の本体。
+  pub payload: Vec<u8>,
+}
+
+impl Record {
+  /// レコードをハッシュ木に保存する値に直列化します。
+  pub fn to_bytes(&self) -> Vec<u8> {
+    self.encode(self.timestamp)
+  }
+
+  /// タイムスタンプを `timestamp` としてレコードを直列化します。
+  fn encode(&self, timestamp: Option<u64>) -> Vec<u8> {
+    let key_size = self.key.as_ref().map(|k| 4 + k.len()).unwrap_or(0);
+    let metadata_size = self.metadata.as_ref().map(|m| 4 + m.len()).unwrap_or(0);
+    let mut bytes = Vec::with_capacity(2 + 4 + 8 + key_size + metadata_size + self.payload.len());
+    let mut flags = 0u8;
+    if self.tag.is_some() {
+      flags |= FLAG_TAG;
+    }
+    if timestamp.is_some() {
+      flags |= FLAG_TIMESTAMP;
+    }
+    if self.key.is_some() {
+      flags |= FLAG_KEY;
+    }
+    if self.metadata.is_some() {
+      flags |= FLAG_METADATA;
+    }
+    bytes.push(RECORD_MARKER);
+    bytes.push(flags);
+    if let Some(tag) = self.tag {
+      bytes.extend_from_slice(&tag.to_le_bytes());
+    }
+    if let Some(timestamp) = timestamp {
+      bytes.extend_from_slice(&timestamp.to_le_bytes());
+    }
+    if let Some(key) = &self.key {
+      bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+      bytes.extend_from_slice(key);
+    }
+    if let Some(metadata) = &self.metadata {
+      bytes.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+      bytes.extend_from_slice(metadata);
+    }
+    bytes.extend_from_slice(&self.payload);
+    bytes
+  }
+
+  /// インデックス `i` の値として保存されていたバイト列からレコードを復元します。
+  pub fn from_bytes(i: Index, mut bytes: Vec<u8>) -> Result<Record> {
+    let header = Header::read(&mut bytes.as_slice(), i, bytes.len())?;
+    bytes.drain(..header.length);
+    Ok(Record {
+      tag: header.tag,
+      timestamp: header.timestamp,
+      key: header.key,
+      metadata: header.metadata,
+      payload: bytes,
+    })
+  }
+}
+
+/// レコードの先頭に配置された属性。
+struct Header {
+  tag: Option<u32>,
+  timestamp: Option<u64>,
+  key: Option<Vec<u8>>,
+  metadata: Option<Vec<u8>>,
+  /// 属性を含む先頭部分のバイトサイズ。
+  length: usize,
+}
+
+impl Header {
+  /// バイトサイズ `size` の値の先頭から属性を読み込みます。ペイロードは読み込みません。
+  fn read(r: &mut dyn Read, i: Index, size: usize) -> Result<Header> {
+    let mut r = r.take(size as u64);
+    let header = Self::read_fields(&mut r, i).map_err(|err| match err {
+      // 値の終端を越える属性はレコード形式ではない
+      Detail::Io { source } if source.kind() == ErrorKind::UnexpectedEof => Detail::InvalidRecord { i },
+      err => err,
+    })?;
+    Ok(Header { length: size - r.limit() as usize, ..header })
+  }
+
+  fn read_fields(r: &mut dyn Read, i: Index) -> Result<Header> {
+    let marker = r.read_u8()?;
+    let flags = r.read_u8()?;
+    if marker != RECORD_MARKER || flags & !KNOWN_FLAGS != 0 {
+      return Err(Detail::InvalidRecord { i });
+    }
+    let tag = if flags & FLAG_TAG != 0 { Some(r.read_u32::<LittleEndian>()?) } else { None };
+    let timestamp = if flags & FLAG_TIMESTAMP != 0 { Some(r.read_u64::<LittleEndian>()?) } else { None };
+    let key = if flags & FLAG_KEY != 0 { Some(read_bytes(r, i)?) } else { None };
+    let metadata = if flags & FLAG_METADATA != 0 { Some(read_bytes(r, i)?) } else { None };
+    Ok(Header { tag, timestamp, key, metadata, length: 0 })
+  }
+}
+
+/// u32 のバイトサイズに続くバイト列を読み込みます。
+fn read_bytes(r: &mut dyn Read, i: Index) -> Result<Vec<u8>> {
+  let length = r.read_u32::<LittleEndian>()? as usize;
+  let mut bytes = Vec::with_capacity(length.min(MAX_PAYLOAD_SIZE));
+  if r.take(length as u64).read_to_end(&mut bytes)? != length {
+    return Err(Detail::InvalidRecord { i });
+  }
+  Ok(bytes)
+}
+
+/// レコード形式のストレージに i 番目のエントリとして追加する値 `value` がレコード形式であることを確認します。
+pub(crate) fn check_record(value: &[u8], i: Index) -> Result<()> {
+  Header::read(&mut &value[..], i, value.len()).map(|_| ())
+}
+
+/// i 番目のエントリとして追加する値 `value` がレコード形式であることを確認し、そのタイムスタンプを返します。
+pub(crate) fn timestamp_of(value: &[u8], i: Index) -> Result<Option<u64>> {
+  Header::read(&mut &value[..], i, value.len()).map(|header| header.timestamp)
+}
+
+/// 値がレコード形式の場合にペイロードより前の属性のバイトサイズを返します。
+pub(crate) fn header_length(value: &[u8]) -> Option<usize> {
+  Header::read(&mut &value[..], 0, value.len()).ok().map(|header| header.length)
+}
+
+/// 値がキーを持つレコードの場合にそのキーを返します。
+pub(crate) fn key_of(value: &[u8]) -> Option<Vec<u8>> {
+  Header::read(&mut &value[..], 0, value.len()).ok().and_then(|header| header.key)
+}
+
+impl<S: Storage> LMTHT<S> {
+  /// レコードを直列化して追加します。[`LMTHTOptions::timestamps`](crate::LMTHTOptions::timestamps) を指定している
+  /// 場合、タイムスタンプを持たないレコードには追加時の時刻を付加します。
+  ///
+  /// # Errors
+  /// [`LMTHTOptions::timestamps`](crate::LMTHTOptions::timestamps) を指定している場合、直前のレコードより前の時刻を
+  /// 持つレコードは [`Detail::TimestampOutOfOrder`] を返します。
+  pub fn append_record(&self, record: &Record) -> Result<Node> {
+    if !self.timestamps || record.timestamp.is_some() {
+      return self.append(&record.to_bytes());
+    }
+    // 時刻の単調性を保つため追加のロックを保持したまま時刻を決定する
+    let mut batch = self.begin_batch()?;
+    let timestamp = now().max(batch.last_timestamp()?);
+    let root = batch.append(&record.encode(Some(timestamp)))?;
+    batch.commit()?;
+    Ok(root)
+  }
+
+  /// タグ `tag` を付加した値を追加します。
+  pub fn append_tagged(&self, tag: u32, payload: &[u8]) -> Result<Node> {
+    self.append_record(&Record { tag: Some(tag), payload: payload.to_vec(), ..Default::default() })
+  }
+
+  /// 最後に追加したレコードのタイムスタンプを参照します。オープン後の最初の参照ではストレージ上の最後のエントリから
+  /// 読み込みます。
+  pub(crate) fn last_timestamp(&self) -> Result<u64> {
+    let last = self.last_timestamp.load(Ordering::SeqCst);
+    let n = self.n();
+    if last != 0 || n == 0 {
+      return Ok(last);
+    }
+    match self.query()?.get_timestamp(n) {
+      Ok(timestamp) => Ok(timestamp.unwrap_or(0)),
+      Err(Detail::InvalidRecord { .. }) | Err(Detail::PayloadPruned { .. }) | Err(Detail::Archived { .. }) => Ok(0),
+      Err(err) => Err(err),
+    }
+  }
+}
+
+/// 現在の時刻を UNIX エポックからのミリ秒で返します。
+fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+impl<C: Cursor> Query<C> {
+  /// インデックス `i` の値をレコードとして参照します。範囲外のインデックスを指定した場合は `None` を返します。
+  pub fn get_record(&mut self, i: Index) -> Result<Option<Record>> {
+    self.get(i)?.map(|bytes| Record::from_bytes(i, bytes)).transpose()
+  }
+
+  /// インデックス `i` のレコードのメタデータを参照します。範囲外のインデックスを指定した場合やメタデータを持たない
+  /// レコードの場合は `None` を返します。
+  ///
+  /// メタデータはエントリのチェックサムと葉ノードのハッシュ値を検証してから返すため、ペイロードも読み込みます。
+  /// [`LMTHT::prune_payloads()`] で値を削除したレコードのメタデータはハッシュ値で検証できないため、チェックサムのみを
+  /// 検証します。
+  pub fn get_metadata(&mut self, i: Index) -> Result<Option<Vec<u8>>> {
+    match self.entry_position(i)? {
+      Some(position) => {
+        self.read_verified(position, i)?;
+        Ok(self.read_header(position, i)?.0.metadata)
+      }
+      None => Ok(None),
+    }
+  }
+
+  /// インデックス `i` のレコードのタイムスタンプをペイロードを読み込まずに参照します。範囲外のインデックスを指定した
+  /// 場合やタイムスタンプを持たないレコードの場合は `None` を返します。
+  pub fn get_timestamp(&mut self, i: Index) -> Result<Option<u64>> {
+    match self.entry_position(i)? {
+      Some(position) => Ok(self.read_header(position, i)?.0.timestamp),
+      None => Ok(None),
+    }
+  }
+
+  /// タイムスタンプが `time` の範囲に含まれるレコードのインデックスの範囲を返します。タイムスタンプはインデックスの
+  /// 順に単調増加している必要があり、エントリ数 n に対して O(log n) 個のレコードの属性のみを読み込みます。範囲に
+  /// 含まれるレコードが存在しない場合は空の範囲を返します。タイムスタンプを持たないレコードを参照した場合は
+  /// [`Detail::NoTimestamp`] を返します。
+  pub fn range_by_time(&mut self, time: Range<u64>) -> Result<Range<Index>> {
+    let start = self.partition_by_time(time.start)?;
+    let end = if time.end <= time.start { start } else { self.partition_by_time(time.end)? };
+    Ok(start..end)
+  }
+
+  /// タイムスタンプが `time` 以上である最初のレコードのインデックスを返します。存在しない場合は n + 1 を返します。
+  fn partition_by_time(&mut self, time: u64) -> Result<Index> {
+    let (mut low, mut high) = (1, self.n() + 1);
+    while low < high {
+      let mid = low + (high - low) / 2;
+      match self.get_timestamp(mid)? {
+        Some(timestamp) if timestamp < time => low = mid + 1,
+        Some(_) => high = mid,
+        None => return Err(Detail::NoTimestamp { i: mid }),
+      }
+    }
+    Ok(low)
+  }
+
+  /// インデックス `i` のエントリのストレージ上の位置を参照します。
+  pub(crate) fn entry_position(&mut self, i: Index) -> Result<Option<u64>> {
+    let position =
+      Self::get_entry_position(self.gen.as_ref(), &self.node_cache, &mut self.index, &mut self.cursor, i, false)?;
+    Ok(position.map(|(position, _)| position))
+  }
+
+  /// 位置 `position` のエントリ `i` のレコードのキーをペイロードを読み込まずに参照し、キーと次のエントリの位置を
+  /// 返します。
+  pub(crate) fn read_key(&mut self, position: u64, i: Index) -> Result<(Option<Vec<u8>>, u64)> {
+    let (header, _, next) = self.read_header(position, i)?;
+    Ok((header.key, next))
+  }
+
+  /// 位置 `position` のエントリ `i` の値の先頭からレコードの属性を読み込み、属性と値のバイトサイズ、および次のエントリ
+  /// の位置を返します。カーソルは属性の直後を指します。
+  fn read_header(&mut self, position: u64, i: Index) -> Result<(Header, usize, u64)> {
+    let (size, next) = self.read_payload_size(position)?;
+    match Header::read(&mut self.cursor, i, size) {
+      Ok(header) => Ok((header, size, next)),
+      Err(Detail::InvalidRecord { i }) => {
+        // 削除された値は 0 で置き換えられているためレコードとして解釈できない
+        self.cursor.seek(SeekFrom::Start(position))?;
+        let entry = read_entry_without_check(&mut self.cursor, position, i)?;
+        self.archived(check_pruned(&entry.enode.meta, &entry.enode.payload))?;
+        Err(Detail::InvalidRecord { i })
+      }
+      Err(err) => Err(err),
+    }
+  }
+
+  /// 位置 `position` のエントリ `i` をチェックサムを検証して読み込み、値が削除されていなければ葉ノードのハッシュ値と
+  /// 一致することを確認します。
+  fn read_verified(&mut self, position: u64, i: Index) -> Result<ENode> {
+    self.cursor.seek(SeekFrom::Start(position))?;
+    let enode = observe(self.node_cache.metrics.as_ref(), read_entry(&mut self.cursor, i))?.enode;
+    if check_pruned(&enode.meta, &enode.payload).is_ok() && Hash::hash(&enode.payload) != enode.meta.hash {
+      return Err(Detail::DamagedStorage(format!("The value of entry {} does not match its hash.", i)));
+    }
+    Ok(enode)
+  }
+
+  /// 位置 `position` のエントリの値のバイトサイズと次のエントリの位置を返します。カーソルは値の先頭を指します。
+  fn read_payload_size(&mut self, position: u64) -> Result<(usize, u64)> {
+    self.cursor.seek(SeekFrom::Start(position))?;
+    let inodes = read_inodes(&mut self.cursor, position)?;
+    let size = (self.cursor.read_u32::<LittleEndian>()? as usize) & MAX_PAYLOAD_SIZE;
+    let length = INDEX_BYTES + 1 + inodes.len() * INODE_SIZE + 4 + size + HASH_SIZE + 4 + 8;
+    Ok((size, position + length as u64))
+  }
+
+  /// このクエリーの世代に含まれている値のうちタグが `tag` と一致するレコードをインデックス順に返すイテレータを作成
+  /// します。エントリを先頭から順に走査し、タグが一致しないエントリのペイロードは読み込みません。
+  ///
+  /// 値を削除したエントリは [`Detail::PayloadPruned`] (アーカイブした範囲では [`Detail::Archived`]) を返し、走査は
+  /// 次のエントリから続きます。[`LMTHTOptions::records`](crate::LMTHTOptions::records) を指定したストレージでは
+  /// 属性が残っているため、タグが一致するエントリのみがこのエラーを返します。
+  pub fn iter_tagged(&mut self, tag: u32) -> Tagged<'_, C> {
+    Tagged { query: self, tag, next: 1, position: STORAGE_IDENTIFIER.len() as u64 + 1 }
+  }
+}
+
+/// [`Query::iter_tagged()`] で作成した、タグが一致するレコードを返すイテレータです。
+pub struct Tagged<'a, C: Cursor> {
+  query: &'a mut Query<C>,
+  tag: u32,
+  /// 次に走査するエントリのインデックス。
+  next: Index,
+  /// 次に走査するエントリの位置。
+  position: u64,
+}
+
+impl<'a, C: Cursor> Tagged<'a, C> {
+  /// 次のエントリを走査し、タグが一致する場合はそのレコードを返します。
+  fn scan(&mut self) -> Result<Option<(Index, Record)>> {
+    let (i, position) = (self.next, self.position);
+    let (header, _, next) = match self.query.read_header(position, i) {
+      Ok(header) => header,
+      Err(err @ Detail::PayloadPruned { .. }) | Err(err @ Detail::Archived { .. }) => {
+        // 値を削除したエントリのタグは参照できない
+        self.position = self.query.read_payload_size(position)?.1;
+        self.next += 1;
+        return Err(err);
+      }
+      Err(err) => return Err(err),
+    };
+    self.position = next;
+    self.next += 1;
+    if header.tag != Some(self.tag) {
+      return Ok(None);
+    }
+
+    // タグが一致したエントリはチェックサムと葉ノードのハッシュ値を検証して読み込む
+    let ENode { meta, payload } = self.query.read_verified(position, i)?;
+    self.query.archived(check_pruned(&meta, &payload))?;
+    Ok(Some((i, Record::from_bytes(i, payload)?)))
+  }
+}
+
+impl<'a, C: Cursor> Iterator for Tagged<'a, C> {
+  type Item = Result<(Index, Record)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.next <= self.query.n() {
+      match self.scan() {
+        Ok(Some(record)) => return Some(Ok(record)),
+        Ok(None) => (),
+        Err(err @ Detail::PayloadPruned { .. }) | Err(err @ Detail::Archived { .. }) => return Some(Err(err)),
+        Err(err) => {
+          self.next = Index::MAX;
+          return Some(Err(err));
+        }
+      }
+    }
+    None
+  }
+}
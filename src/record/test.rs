@@ -0,0 +1,219 @@
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::*;
+
+/// タグを付加した値がハッシュ木に含まれ、タグが一致するレコードのみを走査できることを検証します。
+#[test]
+fn test_tagged_records() {
+  use crate::record::Record;
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 1..=20u32 {
+    db.append_tagged(i % 3, format!("value-{}", i).as_bytes()).unwrap();
+  }
+  let untagged = Record { payload: b"untagged".to_vec(), ..Default::default() };
+  db.append_record(&untagged).unwrap();
+
+  let mut query = db.query().unwrap();
+  let expected = (1..=20u32).filter(|i| i % 3 == 1).map(|i| (i as Index, format!("value-{}", i).into_bytes()));
+  let actual = query.iter_tagged(1).map(|r| r.map(|(i, r)| (i, r.payload))).collect::<Result<Vec<_>>>().unwrap();
+  assert_eq!(expected.collect::<Vec<_>>(), actual);
+  assert_eq!(0, query.iter_tagged(7).count());
+  assert_eq!(Some(untagged), query.get_record(21).unwrap());
+  assert_eq!(None, query.get_record(22).unwrap());
+
+  // タグは葉ノードのハッシュ値に含まれている
+  let values = query.get_with_hashes(4).unwrap().unwrap();
+  let record = Record { tag: Some(1), payload: b"value-4".to_vec(), ..Default::default() };
+  assert_eq!(vec![Value::new(4, record.to_bytes())], values.values);
+
+  db.append(b"raw").unwrap();
+  let mut query = db.query().unwrap();
+  assert!(matches!(query.get_record(22), Err(Detail::InvalidRecord { i: 22 })));
+  assert!(matches!(query.iter_tagged(1).last(), Some(Err(Detail::InvalidRecord { i: 22 }))));
+}
+
+/// タグが一致するレコードはチェックサムを検証して返され、属性の読み込みの I/O エラーがレコード形式でない値として
+/// 扱われないことを検証します。
+#[test]
+fn test_tagged_records_verified() {
+  use crate::record::Header;
+  use std::io::{Error, ErrorKind, Read};
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=6u32 {
+    db.append_tagged(i % 3, format!("value-{}", i).as_bytes()).unwrap();
+  }
+  let position = buffer.read().unwrap().windows(7).position(|w| w == b"value-4").unwrap();
+  buffer.write().unwrap()[position + 6] ^= 0x01;
+  let mut query = db.query().unwrap();
+  let tagged = query.iter_tagged(1).collect::<Vec<_>>();
+  assert!(matches!(tagged[..], [Ok((1, _)), Err(Detail::ChecksumVerificationFailed { .. })]));
+  assert_eq!(vec![2, 5], query.iter_tagged(2).map(|r| r.unwrap().0).collect::<Vec<_>>());
+
+  struct Failing;
+  impl Read for Failing {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+      Err(Error::new(ErrorKind::BrokenPipe, "failing"))
+    }
+  }
+  assert!(matches!(Header::read(&mut Failing, 1, 8), Err(Detail::Io { .. })));
+  assert!(matches!(Header::read(&mut &[0xD7u8][..], 1, 1), Err(Detail::InvalidRecord { i: 1 })));
+}
+
+/// レコードのメタデータがハッシュ木に含まれ、ペイロードとは別に参照できることを検証します。
+#[test]
+fn test_record_metadata() {
+  use crate::record::Record;
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  let records = [
+    Record { tag: Some(1), metadata: Some(b"actor=42".to_vec()), payload: b"login".to_vec(), ..Default::default() },
+    Record { tag: None, metadata: Some(Vec::new()), payload: Vec::new(), ..Default::default() },
+    Record { tag: Some(2), metadata: None, payload: b"logout".to_vec(), ..Default::default() },
+  ];
+  let mut root = None;
+  for record in records.iter() {
+    root = Some(db.append_record(record).unwrap());
+  }
+
+  let mut query = db.query().unwrap();
+  for (i, record) in records.iter().enumerate() {
+    let i = i as Index + 1;
+    assert_eq!(Some(record.clone()), query.get_record(i).unwrap());
+    assert_eq!(record.metadata, query.get_metadata(i).unwrap());
+    let values = query.get_with_hashes(i).unwrap().unwrap();
+    assert_eq!(vec![Value::new(i, record.to_bytes())], values.values);
+    assert_eq!(root.unwrap().hash, values.root().hash);
+  }
+  assert_eq!(None, query.get_metadata(4).unwrap());
+  let tagged = query.iter_tagged(1).collect::<Result<Vec<_>>>().unwrap();
+  assert_eq!(vec![(1, records[0].clone())], tagged);
+
+  // メタデータの改変はチェックサムによって検出される
+  let position = buffer.read().unwrap().windows(8).position(|w| w == b"actor=42").unwrap();
+  buffer.write().unwrap()[position + 7] = b'3';
+  assert!(matches!(db.query().unwrap().get_metadata(1), Err(Detail::ChecksumVerificationFailed { .. })));
+  buffer.write().unwrap()[position + 7] = b'2';
+
+  // メタデータの改変はルートハッシュを変化させる
+  let mut altered = records[0].clone();
+  altered.metadata = Some(b"actor=43".to_vec());
+  assert_ne!(Hash::hash(&records[0].to_bytes()), Hash::hash(&altered.to_bytes()));
+
+  db.prune_payloads(1).unwrap();
+  let mut query = db.query().unwrap();
+  assert!(matches!(query.get_metadata(1), Err(Detail::PayloadPruned { i: 1 })));
+  assert_eq!(Some(records[2].clone()), query.get_record(3).unwrap());
+}
+
+/// 追加時の時刻を付加したレコードを時刻の範囲から検索できることを検証します。
+#[test]
+fn test_timestamped_records() {
+  use crate::record::Record;
+  use std::time::{SystemTime, UNIX_EPOCH};
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::builder(MemStorage::with(buffer.clone())).timestamps(true).open().unwrap();
+  let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+  for i in 1..=10 {
+    let timestamp = if i <= 5 { Some(1000 * i) } else { None };
+    let record = Record { tag: Some(i as u32), timestamp, payload: vec![i as u8], ..Default::default() };
+    db.append_record(&record).unwrap();
+  }
+
+  let mut query = db.query().unwrap();
+  assert_eq!(Some(3000), query.get_timestamp(3).unwrap());
+  assert!(query.get_timestamp(6).unwrap().unwrap() >= start);
+  assert_eq!(None, query.get_timestamp(11).unwrap());
+  assert_eq!(2..4, query.range_by_time(2000..4000).unwrap());
+  assert_eq!(2..4, query.range_by_time(1500..3500).unwrap());
+  assert_eq!(1..1, query.range_by_time(0..1000).unwrap());
+  assert_eq!(6..11, query.range_by_time(start..u64::MAX).unwrap());
+  assert_eq!(11..11, query.range_by_time(u64::MAX - 1..u64::MAX).unwrap());
+  let timestamps = (1..=10).map(|i| query.get_timestamp(i).unwrap().unwrap()).collect::<Vec<_>>();
+  assert!(timestamps.windows(2).all(|w| w[0] <= w[1]));
+  let record = Record { tag: Some(2), timestamp: Some(2000), payload: vec![2], ..Default::default() };
+  assert_eq!(Some(record), query.get_record(2).unwrap());
+
+  // 再オープン後も直前のレコードより前の時刻は付加されない
+  drop(query);
+  drop(db);
+  let db = LMTHT::builder(MemStorage::with(buffer.clone())).timestamps(true).open().unwrap();
+  let future = start + 3_600_000;
+  db.append_record(&Record { timestamp: Some(future), ..Default::default() }).unwrap();
+  drop(db);
+  let db = LMTHT::builder(MemStorage::with(buffer)).timestamps(true).open().unwrap();
+  db.append_tagged(1, b"value").unwrap();
+  assert_eq!(Some(future), db.query().unwrap().get_timestamp(12).unwrap());
+
+  // 直前より前の時刻を持つレコードや、タイムスタンプを持たない値は追加の方法に関わらず拒否される
+  let past = Record { timestamp: Some(future - 1), ..Default::default() };
+  let err = db.append_record(&past);
+  assert!(matches!(err, Err(Detail::TimestampOutOfOrder { i: 13, last, .. }) if last == future));
+  assert!(matches!(db.append(&Record::default().to_bytes()), Err(Detail::NoTimestamp { i: 13 })));
+  let mut batch = db.begin_batch().unwrap();
+  batch.append(&Record { timestamp: Some(future + 2), ..Default::default() }.to_bytes()).unwrap();
+  let err = batch.append(&Record { timestamp: Some(future + 1), ..Default::default() }.to_bytes());
+  assert!(matches!(err, Err(Detail::TimestampOutOfOrder { i: 14, .. })));
+  batch.commit().unwrap();
+  db.append_record(&Record { timestamp: Some(future + 2), ..Default::default() }).unwrap();
+  db.append_tagged(2, b"value").unwrap();
+  assert_eq!(Some(future + 2), db.query().unwrap().get_timestamp(15).unwrap());
+  assert_eq!(15, db.n());
+
+  // タイムスタンプを持たないレコードは時刻で検索できない
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  db.append_tagged(1, b"value").unwrap();
+  assert!(matches!(db.query().unwrap().range_by_time(0..1), Err(Detail::NoTimestamp { i: 1 })));
+}
+
+/// 値を削除したレコードも属性によって走査や検索ができ、キー索引が再構築できることを検証します。
+#[test]
+fn test_pruned_records() {
+  use crate::record::Record;
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let open = |keys: Arc<MemStorage>| {
+    LMTHT::builder(MemStorage::with(buffer.clone())).timestamps(true).key_index(keys).open().unwrap()
+  };
+  let db = open(Arc::new(MemStorage::new()));
+  for i in 1..=10u64 {
+    let metadata = Some(format!("meta-{}", i).into_bytes());
+    let key = Some(format!("key-{}", i % 2).into_bytes());
+    let record =
+      Record { tag: Some((i % 3) as u32), timestamp: Some(1000 * i), key, metadata, payload: vec![i as u8; 8] };
+    db.append_record(&record).unwrap();
+  }
+  let mut before = db.query().unwrap();
+  assert_eq!(vec![3, 6, 9], before.iter_tagged(0).map(|r| r.unwrap().0).collect::<Vec<_>>());
+  assert_eq!(10 * 8, db.prune_payloads(10).unwrap());
+
+  // 属性は残り、本体の参照のみが PayloadPruned となる
+  let tagged = before.iter_tagged(0).collect::<Vec<_>>();
+  assert_eq!(3, tagged.len());
+  assert!(tagged.iter().all(|r| matches!(r, Err(Detail::PayloadPruned { .. }))));
+  drop(before);
+  let mut query = db.query().unwrap();
+  assert!(matches!(query.get_record(4), Err(Detail::PayloadPruned { i: 4 })));
+  assert_eq!(Some(b"meta-4".to_vec()), query.get_metadata(4).unwrap());
+  assert_eq!(3..6, query.range_by_time(3000..6000).unwrap());
+  assert_eq!(vec![2, 4, 6, 8, 10], query.get_by_key(b"key-0").unwrap());
+  drop(query);
+  drop(db);
+
+  // キー索引は値を削除したエントリからも再構築される
+  let db = open(Arc::new(MemStorage::new()));
+  assert_eq!(vec![1, 3, 5, 7, 9], db.query().unwrap().get_by_key(b"key-1").unwrap());
+  drop(db);
+
+  // レコード形式でないストレージでは属性ごと削除され、走査はそのエントリを PayloadPruned として続ける
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 1..=4u32 {
+    db.append_tagged(1, &i.to_le_bytes()).unwrap();
+  }
+  db.prune_payloads(2).unwrap();
+  let tagged = db.query().unwrap().iter_tagged(1).map(|r| r.map(|(i, _)| i)).collect::<Vec<_>>();
+  assert!(matches!(
+    tagged[..],
+    [Err(Detail::PayloadPruned { i: 1 }), Err(Detail::PayloadPruned { i: 2 }), Ok(3), Ok(4)]
+  ));
+}
@@ -0,0 +1,73 @@
+//! 複数のレプリカが同じ履歴を保持しているかを値を転送せずに判定します。
+//!
+//! 世代 g のルートハッシュは 1 から g までのすべての値を要約しているため、2 つの LMTHT が同じ世代で同じルートハッシュ
+//! を持てばそれ以前のすべての世代も一致します。[`compare_roots()`] はこの性質を使用して、短い方の世代のルートハッシュ
+//! のみを比較することで 2 つの LMTHT が同一か、一方が他方の先頭部分か、あるいは分岐しているかを判定します。分岐して
+//! いる場合は二分探索によって共通する最後の世代を特定します。比較に必要な読み込みはルートハッシュを記録したエントリ
+//! の O(log n) 回で、ペイロードを読み込むことはありません。
+//!
+//! ```rust,no_run
+//! use lmtht::{compare_roots, ReplicaComparison, LMTHT};
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let (primary, replica) = (LMTHT::new("primary.db")?, LMTHT::new("replica.db")?);
+//! match compare_roots(&primary, &replica)? {
+//!   ReplicaComparison::Identical { .. } | ReplicaComparison::APrefixOfB { .. } => (),
+//!   ReplicaComparison::BPrefixOfA { n } => println!("resume replication from {}", n + 1),
+//!   ReplicaComparison::Forked { common } => eprintln!("the replica forked after generation {}", common),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+use crate::{Cursor, Hash, Index, Query, Result, Storage, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// [`compare_roots()`] による 2 つの LMTHT の比較結果です。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplicaComparison {
+  /// 2 つの LMTHT は同じ世代 `n` で同じルートハッシュを持ちます。
+  Identical { n: Index },
+  /// a は b の世代 `n` までと一致し、b はその後に追加された値を持ちます。
+  APrefixOfB { n: Index },
+  /// b は a の世代 `n` までと一致し、a はその後に追加された値を持ちます。
+  BPrefixOfA { n: Index },
+  /// 2 つの LMTHT は世代 `common` までは一致しますが、その次の世代から異なる値を持ちます。
+  Forked { common: Index },
+}
+
+/// 2 つの LMTHT の現在の世代をルートハッシュのみを使用して比較します。比較はそれぞれの LMTHT から取得した時点の
+/// 世代に対して行われるため、並行して値が追加されていても一貫した結果を返します。
+pub fn compare_roots<A: Storage, B: Storage>(a: &LMTHT<A>, b: &LMTHT<B>) -> Result<ReplicaComparison> {
+  let (mut a, mut b) = (a.query()?, b.query()?);
+  let (na, nb) = (a.n(), b.n());
+  let n = na.min(nb);
+  if root_hash(&mut a, n)? == root_hash(&mut b, n)? {
+    return Ok(if na == nb {
+      ReplicaComparison::Identical { n }
+    } else if na < nb {
+      ReplicaComparison::APrefixOfB { n }
+    } else {
+      ReplicaComparison::BPrefixOfA { n }
+    });
+  }
+
+  // 一致する世代 low と一致しない世代 high の間を二分探索する
+  let (mut low, mut high) = (0, n);
+  while high - low > 1 {
+    let mid = low + (high - low) / 2;
+    if root_hash(&mut a, mid)? == root_hash(&mut b, mid)? {
+      low = mid;
+    } else {
+      high = mid;
+    }
+  }
+  Ok(ReplicaComparison::Forked { common: low })
+}
+
+/// 世代 `n` のルートハッシュを参照します。世代 0 は `None` です。
+fn root_hash<C: Cursor>(query: &mut Query<C>, n: Index) -> Result<Option<Hash>> {
+  Ok(query.get_root(n)?.map(|root| root.hash))
+}
@@ -0,0 +1,33 @@
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// 2 つの LMTHT のルートハッシュから同一、先頭部分、分岐のいずれであるかと分岐した世代を判定できることを検証します。
+#[test]
+fn test_compare_roots() {
+  let a = LMTHT::new(MemStorage::new()).unwrap();
+  let b = LMTHT::new(MemStorage::new()).unwrap();
+  assert_eq!(ReplicaComparison::Identical { n: 0 }, compare_roots(&a, &b).unwrap());
+  for i in 0..20 {
+    a.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  assert_eq!(ReplicaComparison::BPrefixOfA { n: 0 }, compare_roots(&a, &b).unwrap());
+  for i in 0..13 {
+    b.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  assert_eq!(ReplicaComparison::BPrefixOfA { n: 13 }, compare_roots(&a, &b).unwrap());
+  assert_eq!(ReplicaComparison::APrefixOfB { n: 13 }, compare_roots(&b, &a).unwrap());
+  for i in 13..20 {
+    b.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  assert_eq!(ReplicaComparison::Identical { n: 20 }, compare_roots(&a, &b).unwrap());
+
+  // 世代 common の次から分岐したレプリカ
+  for common in [0, 1, 7, 16, 19] {
+    let c = a.fork_to(common, MemStorage::new()).unwrap();
+    for i in common..25 {
+      c.append(&random_payload(PAYLOAD_SIZE, i + 1000)).unwrap();
+    }
+    assert_eq!(ReplicaComparison::Forked { common }, compare_roots(&a, &c).unwrap());
+    assert_eq!(ReplicaComparison::Forked { common }, compare_roots(&c, &a).unwrap());
+  }
+}
@@ -0,0 +1,142 @@
+//! 一時的な入出力エラーで失敗したストレージの操作を再実行するためのポリシーです。
+//!
+//! ネットワーク越しのストレージでは接続の切断やタイムアウト、スロットリングのように、時間を置いて再実行すれば成功
+//! するエラーが頻繁に発生します。[`LMTHTOptions::retry`](crate::LMTHTOptions::retry) に [`RetryPolicy`] を指定
+//! すると、LMTHT はストレージのカーソルに対する読み込み、書き込み、シーク、同期が [`RetryPolicy::retryable`] と
+//! 判定されるエラーで失敗した場合に、指数的に延長する間隔を置いて再実行します。読み込みと書き込みは再実行のたびに
+//! 操作の開始位置へシークし直すため、途中まで書き込まれた内容は同じ内容で上書きされます。
+//!
+use std::io;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::error::Detail;
+use crate::{BufferedCursor, Capabilities, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// [`RetryPolicy::max_retries`] のデフォルト値です。
+pub const DEFAULT_RETRY_MAX_RETRIES: u32 = 5;
+
+/// [`RetryPolicy::initial_backoff`] のデフォルト値です。
+pub const DEFAULT_RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// [`RetryPolicy::max_backoff`] のデフォルト値です。
+pub const DEFAULT_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// 失敗した操作を再実行する回数と間隔です。
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+  /// 最初の実行の後に再実行する最大の回数です。デフォルトは [`DEFAULT_RETRY_MAX_RETRIES`] です。
+  pub max_retries: u32,
+  /// 最初の再実行までの間隔です。以降の間隔は再実行のたびに 2 倍となります。デフォルトは
+  /// [`DEFAULT_RETRY_INITIAL_BACKOFF`] です。
+  pub initial_backoff: Duration,
+  /// 再実行までの間隔の上限です。デフォルトは [`DEFAULT_RETRY_MAX_BACKOFF`] です。
+  pub max_backoff: Duration,
+  /// エラーが再実行によって回復する可能性のある一時的なものかを判定する関数です。デフォルトは [`is_transient()`]
+  /// です。
+  pub retryable: fn(&io::Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy {
+      max_retries: DEFAULT_RETRY_MAX_RETRIES,
+      initial_backoff: DEFAULT_RETRY_INITIAL_BACKOFF,
+      max_backoff: DEFAULT_RETRY_MAX_BACKOFF,
+      retryable: is_transient,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// `attempt` 回目 (0 が最初の実行) の操作が `err` で失敗したときに、再実行までの間隔を返します。再実行しない場合は
+  /// `None` を返します。
+  pub fn backoff(&self, attempt: u32, err: &io::Error) -> Option<Duration> {
+    if attempt >= self.max_retries || !(self.retryable)(err) {
+      return None;
+    }
+    let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+    Some(self.initial_backoff.saturating_mul(factor).min(self.max_backoff))
+  }
+
+  /// 操作 `f` を実行し、一時的なエラーで失敗した場合は間隔を置いて再実行します。
+  pub fn run<T, F: FnMut() -> io::Result<T>>(&self, mut f: F) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+      match f() {
+        Ok(value) => return Ok(value),
+        Err(err) => match self.backoff(attempt, &err) {
+          Some(delay) => {
+            sleep(delay);
+            attempt += 1;
+          }
+          None => return Err(err),
+        },
+      }
+    }
+  }
+}
+
+/// 割り込み、タイムアウト、接続の切断のように再実行によって回復する可能性のあるエラーを判定します。
+pub fn is_transient(err: &io::Error) -> bool {
+  matches!(
+    err.kind(),
+    io::ErrorKind::Interrupted
+      | io::ErrorKind::WouldBlock
+      | io::ErrorKind::TimedOut
+      | io::ErrorKind::ConnectionReset
+      | io::ErrorKind::ConnectionAborted
+      | io::ErrorKind::ConnectionRefused
+      | io::ErrorKind::NotConnected
+      | io::ErrorKind::BrokenPipe
+  )
+}
+
+/// 下位のストレージに対する操作が一時的なエラーで失敗した場合に [`RetryPolicy`] に従って再実行するストレージの
+/// ラッパーです。[`LMTHTOptions::retry`](crate::LMTHTOptions::retry) と異なり、キャッシュなどの他のラッパーの
+/// 下に配置することができます。
+pub struct RetryStorage<S: Storage> {
+  inner: S,
+  policy: RetryPolicy,
+}
+
+impl<S: Storage> RetryStorage<S> {
+  /// 指定されたストレージに対する操作を `policy` に従って再実行するように包みます。
+  pub fn new(inner: S, policy: RetryPolicy) -> RetryStorage<S> {
+    RetryStorage { inner, policy }
+  }
+
+  /// 包んでいるストレージを参照します。
+  pub fn get_ref(&self) -> &S {
+    &self.inner
+  }
+}
+
+impl<S: Storage> Storage for RetryStorage<S> {
+  type Cursor = BufferedCursor<S::Cursor>;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    let mut attempt = 0;
+    let cursor = loop {
+      match self.inner.open(writable) {
+        Ok(cursor) => break cursor,
+        Err(Detail::Io { source }) => match self.policy.backoff(attempt, &source) {
+          Some(delay) => {
+            sleep(delay);
+            attempt += 1;
+          }
+          None => return Err(Detail::Io { source }),
+        },
+        Err(err) => return Err(err),
+      }
+    };
+    // バッファサイズを 0 とした BufferedCursor はすべての操作を位置を指定して下位のカーソルへ委譲する
+    Ok(BufferedCursor::new(cursor, 0, 0).with_retry(Some(self.policy.clone())))
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.inner.capabilities()
+  }
+}
@@ -0,0 +1,49 @@
+use std::io;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use crate::*;
+
+/// `RetryPolicy` を指定した LMTHT が一時的なエラーで失敗した操作を再実行し、再実行しないエラーは返すことを検証
+/// します。
+#[test]
+fn test_retry_policy() {
+  use crate::fault::FaultyStorage;
+  use crate::retry::{is_transient, RetryPolicy};
+
+  let retry = RetryPolicy { initial_backoff: Duration::from_millis(1), retryable: |_| true, ..Default::default() };
+  let db = LMTHT::builder(FaultyStorage::new(MemStorage::new())).retry(retry).open().unwrap();
+  for i in 1..=5u8 {
+    db.storage().fail_nth_write(1);
+    db.append(&[i; 16]).unwrap();
+  }
+  db.storage().fail_nth_read(1);
+  db.storage().fail_nth_read(2);
+  db.storage().fail_nth_sync(1);
+  db.sync().unwrap();
+  let mut query = db.query().unwrap();
+  for i in 1..=5u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+
+  // 再実行の回数を超えた場合や再実行しないエラーは返される
+  for n in 1..=6 {
+    db.storage().fail_nth_sync(n);
+  }
+  assert!(db.sync().is_err());
+  db.storage().clear();
+  let db = LMTHT::with_options(FaultyStorage::new(MemStorage::new()), LMTHTOptions::default()).unwrap();
+  db.storage().fail_nth_write(1);
+  assert!(db.append(&[0u8; 16]).is_err());
+
+  let policy = RetryPolicy::default();
+  let interrupted = io::Error::from(ErrorKind::Interrupted);
+  assert!(is_transient(&interrupted));
+  assert!(!is_transient(&io::Error::from(ErrorKind::PermissionDenied)));
+  assert_eq!(Some(Duration::from_millis(10)), policy.backoff(0, &interrupted));
+  assert_eq!(Some(Duration::from_millis(40)), policy.backoff(2, &interrupted));
+  assert_eq!(Some(Duration::from_millis(160)), policy.backoff(4, &interrupted));
+  assert_eq!(None, policy.backoff(5, &interrupted));
+  let policy = RetryPolicy { max_backoff: Duration::from_millis(100), ..Default::default() };
+  assert_eq!(Some(Duration::from_millis(100)), policy.backoff(4, &interrupted));
+}
@@ -0,0 +1,121 @@
+//! [RocksDB](https://docs.rs/rocksdb) にバイト列を保存するストレージです。
+//!
+//! 直列化されたバイト列は [`ChunkedStorage`] によって固定長のチャンクに分割され、キーの接頭辞にチャンク番号をビッグ
+//! エンディアンで表した 8 バイトを連結したキーで保存されます。接頭辞を変えることで 1 つのデータベースに複数の LMTHT
+//! を保存することができます。チャンクとバイト列の長さは 1 つの `WriteBatch` として書き込まれるため、書き込みの途中で
+//! プロセスが終了しても長さの範囲に不完全なチャンクが残ることはありません。
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use lmtht::rocksdb_storage::{RocksDbChunkStore, DEFAULT_ROCKSDB_CHUNK_SIZE};
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let db = Arc::new(rocksdb::DB::open_default("app.db")?);
+//! let lmtht = LMTHT::new(RocksDbChunkStore::storage(db, b"lmtht/", DEFAULT_ROCKSDB_CHUNK_SIZE))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io;
+use std::sync::Arc;
+
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+
+use crate::chunked::{ChunkStore, ChunkedStorage};
+
+#[cfg(test)]
+mod test;
+
+/// [`RocksDbChunkStore`] のチャンクサイズのデフォルト値です。
+pub const DEFAULT_ROCKSDB_CHUNK_SIZE: usize = 4 * 1024;
+
+/// バイト列の長さを保存するキーの接尾辞。チャンクのキーの接尾辞は常に 8 バイトであるため衝突しない。
+const LENGTH_KEY: &[u8] = b"length";
+
+/// RocksDB のデータベースにチャンクを保存する [`ChunkStore`] です。
+pub struct RocksDbChunkStore {
+  db: Arc<DB>,
+  prefix: Vec<u8>,
+  chunk_size: usize,
+}
+
+impl RocksDbChunkStore {
+  /// 指定されたデータベースのキー接頭辞 `prefix` の範囲にチャンクを保存する保存先を構築します。`chunk_size` は同じ
+  /// 接頭辞に対して常に同じ値を指定する必要があります。
+  pub fn new(db: Arc<DB>, prefix: &[u8], chunk_size: usize) -> RocksDbChunkStore {
+    assert!(chunk_size > 0);
+    RocksDbChunkStore { db, prefix: prefix.to_vec(), chunk_size }
+  }
+
+  /// 指定されたデータベースを使用する LMTHT のストレージを構築します。
+  pub fn storage(db: Arc<DB>, prefix: &[u8], chunk_size: usize) -> ChunkedStorage<RocksDbChunkStore> {
+    ChunkedStorage::new(Self::new(db, prefix, chunk_size))
+  }
+
+  /// この保存先が使用するデータベースを参照します。
+  pub fn db(&self) -> &Arc<DB> {
+    &self.db
+  }
+
+  fn key(&self, suffix: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(self.prefix.len() + suffix.len());
+    key.extend_from_slice(&self.prefix);
+    key.extend_from_slice(suffix);
+    key
+  }
+}
+
+impl ChunkStore for RocksDbChunkStore {
+  fn chunk_size(&self) -> usize {
+    self.chunk_size
+  }
+
+  fn length(&self) -> io::Result<u64> {
+    match self.db.get(self.key(LENGTH_KEY)).map_err(rocksdb2io)? {
+      Some(length) if length.len() == 8 => {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&length);
+        Ok(u64::from_le_bytes(bytes))
+      }
+      Some(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "the length record of the RocksDB is broken")),
+      None => Ok(0),
+    }
+  }
+
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>> {
+    self.db.get(self.key(&k.to_be_bytes())).map_err(rocksdb2io)
+  }
+
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    let mut batch = WriteBatch::default();
+    batch.put(self.key(&k.to_be_bytes()), chunk);
+    batch.put(self.key(LENGTH_KEY), length.to_le_bytes());
+    self.db.write(batch).map_err(rocksdb2io)
+  }
+
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()> {
+    let mut batch = WriteBatch::default();
+    let from = self.key(&k.to_be_bytes());
+    for entry in self.db.iterator(IteratorMode::From(&from, Direction::Forward)) {
+      let (key, _) = entry.map_err(rocksdb2io)?;
+      if !key.starts_with(&self.prefix) {
+        break;
+      }
+      if key.len() == self.prefix.len() + 8 {
+        batch.delete(key);
+      }
+    }
+    batch.put(self.key(LENGTH_KEY), length.to_le_bytes());
+    self.db.write(batch).map_err(rocksdb2io)
+  }
+
+  fn sync(&self) -> io::Result<()> {
+    self.db.flush_wal(true).map_err(rocksdb2io)
+  }
+}
+
+fn rocksdb2io(err: rocksdb::Error) -> io::Error {
+  io::Error::other(err)
+}
@@ -0,0 +1,34 @@
+use std::fs::remove_file;
+use std::sync::Arc;
+
+use crate::test::temp_file;
+use crate::*;
+
+/// RocksDB に保存した内容を再オープンしたデータベースから参照でき、同じデータベースの別の接頭辞と干渉しないことを
+/// 検証します。
+#[cfg(feature = "rocksdb_storage")]
+#[test]
+fn test_rocksdb_storage() {
+  use rocksdb_storage::RocksDbChunkStore;
+  let path = temp_file("lmtht-rocksdb", ".db");
+  remove_file(&path).unwrap();
+  let rocksdb = Arc::new(rocksdb::DB::open_default(&path).unwrap());
+  let db1 = LMTHT::new(RocksDbChunkStore::storage(rocksdb.clone(), b"a/", 16)).unwrap();
+  let db2 = LMTHT::new(RocksDbChunkStore::storage(rocksdb.clone(), b"b/", 16)).unwrap();
+  for i in 0..50u8 {
+    db1.append(&[i; 5]).unwrap();
+    db2.append(&[i; 3]).unwrap();
+  }
+  let root = db1.root().unwrap();
+  drop((db1, db2, rocksdb));
+
+  let rocksdb = Arc::new(rocksdb::DB::open_default(&path).unwrap());
+  let db = LMTHT::new(RocksDbChunkStore::storage(rocksdb.clone(), b"a/", 16)).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..50u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+  drop((query, db, rocksdb));
+  rocksdb::DB::destroy(&rocksdb::Options::default(), &path).unwrap();
+}
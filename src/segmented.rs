@@ -0,0 +1,329 @@
+//! 論理的なバイト列を固定長の複数のファイルに分割して保存するストレージです。
+//!
+//! バイト列は [`SegmentedStorageOptions::segment_size`] ごとに別のセグメントファイルに保存され、末尾のセグメントが
+//! 一杯になった時点で新しいセグメントファイルを作成します。セグメントファイルの一覧はディレクトリの `MANIFEST` に
+//! 記録されます。ファイルシステムのファイルサイズの制限を超えるログを保存できるほか、追記されることのない古い
+//! セグメントを別のデバイスに移動したり、セグメント単位で差分のバックアップを取ることができます。
+//!
+//! `MANIFEST` は 1 行目にセグメントのバイトサイズ、2 行目以降に先頭から順にセグメントファイルのパスを記録した
+//! テキストファイルです。相対パスはディレクトリからの位置を表します。古いセグメントを移動した場合は `MANIFEST` の
+//! パスを移動先の絶対パスに書き換えてください。
+//!
+//! ```rust,no_run
+//! use lmtht::segmented::{SegmentedStorage, SegmentedStorageOptions};
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> lmtht::Result<()> {
+//! let options = SegmentedStorageOptions { segment_size: 1024 * 1024 * 1024 };
+//! let db = LMTHT::new(SegmentedStorage::with_options("/var/lib/audit", options)?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, remove_file, File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::error::Detail;
+use crate::{lock2io, punch_hole, replace_file, Capabilities, Cursor, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// [`SegmentedStorageOptions::segment_size`] のデフォルト値です。
+pub const DEFAULT_SEGMENT_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// セグメントファイルの一覧を記録するファイルの名前。
+const MANIFEST: &str = "MANIFEST";
+
+/// [`SegmentedStorage`] の動作を調整するためのオプションです。
+#[derive(Clone, Debug)]
+pub struct SegmentedStorageOptions {
+  /// 1 つのセグメントファイルに保存するバイトサイズです。既存のディレクトリをオープンする場合は `MANIFEST` に記録
+  /// されている値が使用されます。デフォルトは [`DEFAULT_SEGMENT_SIZE`] です。
+  pub segment_size: u64,
+}
+
+impl Default for SegmentedStorageOptions {
+  fn default() -> Self {
+    SegmentedStorageOptions { segment_size: DEFAULT_SEGMENT_SIZE }
+  }
+}
+
+/// 複数のセグメントファイルにバイト列を分割して保存するストレージです。
+pub struct SegmentedStorage {
+  shared: Arc<Shared>,
+}
+
+struct Shared {
+  dir: PathBuf,
+  segment_size: u64,
+  segments: RwLock<Vec<Segment>>,
+  /// 前回の同期以降に書き込まれたセグメントの番号。
+  dirty: Mutex<BTreeSet<usize>>,
+}
+
+struct Segment {
+  path: PathBuf,
+  file: File,
+}
+
+impl SegmentedStorage {
+  /// 指定されたディレクトリをデフォルトのオプションで使用するストレージを構築します。
+  pub fn new<P: AsRef<Path>>(dir: P) -> Result<SegmentedStorage> {
+    Self::with_options(dir, SegmentedStorageOptions::default())
+  }
+
+  /// 指定されたディレクトリを使用するストレージを構築します。ディレクトリや `MANIFEST` が存在しない場合は作成し、
+  /// 存在する場合は記録されているすべてのセグメントファイルをオープンします。
+  pub fn with_options<P: AsRef<Path>>(dir: P, options: SegmentedStorageOptions) -> Result<SegmentedStorage> {
+    assert!(options.segment_size > 0);
+    let dir = dir.as_ref().to_path_buf();
+    create_dir_all(&dir).map_err(|err| failed_to_open(&dir, err))?;
+    let manifest = dir.join(MANIFEST);
+    let (segment_size, paths) = if manifest.exists() {
+      read_manifest(&manifest)?
+    } else {
+      write_manifest(&dir, options.segment_size, &[])?;
+      (options.segment_size, Vec::new())
+    };
+    let mut segments = Vec::with_capacity(paths.len());
+    for path in paths {
+      let file = OpenOptions::new().read(true).write(true).open(dir.join(&path));
+      segments.push(Segment { file: file.map_err(|err| failed_to_open(&path, err))?, path });
+    }
+    let shared = Shared { dir, segment_size, segments: RwLock::new(segments), dirty: Mutex::new(BTreeSet::new()) };
+    Ok(SegmentedStorage { shared: Arc::new(shared) })
+  }
+
+  /// このストレージが使用しているディレクトリのパスを参照します。
+  pub fn dir(&self) -> &Path {
+    &self.shared.dir
+  }
+
+  /// セグメントのバイトサイズを参照します。
+  pub fn segment_size(&self) -> u64 {
+    self.shared.segment_size
+  }
+
+  /// 現在のセグメントファイルのパスを先頭から順に参照します。末尾を除くセグメントは以降変更されることはありません。
+  pub fn segments(&self) -> Vec<PathBuf> {
+    self.shared.segments.read().map(|s| s.iter().map(|s| self.shared.dir.join(&s.path)).collect()).unwrap_or_default()
+  }
+}
+
+impl Storage for SegmentedStorage {
+  type Cursor = SegmentedCursor;
+  fn open(&self, writable: bool) -> Result<SegmentedCursor> {
+    Ok(SegmentedCursor { shared: self.shared.clone(), writable, position: 0 })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::ALL
+  }
+}
+
+impl Shared {
+  fn length(&self, segments: &[Segment]) -> io::Result<u64> {
+    match segments.last() {
+      Some(last) => Ok((segments.len() as u64 - 1) * self.segment_size + last.file.metadata()?.len()),
+      None => Ok(0),
+    }
+  }
+
+  /// 新しいセグメントファイルを作成し、`MANIFEST` に追加します。
+  fn rotate(&self, segments: &mut Vec<Segment>) -> io::Result<()> {
+    let path = PathBuf::from(format!("segment-{:08}.lmtht", segments.len()));
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(self.dir.join(&path))?;
+    segments.push(Segment { path, file });
+    write_manifest(&self.dir, self.segment_size, segments)
+  }
+}
+
+/// [`SegmentedStorage`] が使用するカーソルです。
+pub struct SegmentedCursor {
+  shared: Arc<Shared>,
+  writable: bool,
+  position: u64,
+}
+
+impl Cursor for SegmentedCursor {
+  fn sync_data(&mut self) -> io::Result<()> {
+    let segments = lock2io(self.shared.segments.read())?;
+    let dirty = std::mem::take(&mut *lock2io(self.shared.dirty.lock())?);
+    for k in dirty {
+      if let Some(segment) = segments.get(k) {
+        segment.file.sync_data()?;
+      }
+    }
+    Ok(())
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let mut segments = lock2io(self.shared.segments.write())?;
+    let size = self.shared.segment_size;
+    let count = length.div_ceil(size) as usize;
+    if count < segments.len() {
+      // MANIFEST から除いた後にセグメントファイルを削除する
+      let removed = segments.split_off(count);
+      write_manifest(&self.shared.dir, size, &segments)?;
+      for segment in removed {
+        drop(segment.file);
+        remove_file(self.shared.dir.join(&segment.path))?;
+      }
+    }
+    while segments.len() < count {
+      if let Some(last) = segments.last() {
+        last.file.set_len(size)?;
+      }
+      self.shared.rotate(&mut segments)?;
+    }
+    if let Some(last) = segments.last() {
+      last.file.set_len(length - (segments.len() as u64 - 1) * size)?;
+      lock2io(self.shared.dirty.lock())?.insert(segments.len() - 1);
+    }
+    Ok(())
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.shared.length(&lock2io(self.shared.segments.read())?)
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let segments = lock2io(self.shared.segments.read())?;
+    let size = self.shared.segment_size;
+    let (mut position, end) = (position, position + length);
+    while position < end {
+      let (k, offset) = ((position / size) as usize, position % size);
+      let length = (end - position).min(size - offset);
+      // セグメント単位で領域を解放し、解放できないファイルシステムでは 0 を書き込む
+      if let Some(segment) = segments.get(k) {
+        if !punch_hole(&segment.file, offset, length)? {
+          let zeros = [0u8; 8 * 1024];
+          let mut written = 0u64;
+          while written < length {
+            let size = (length - written).min(zeros.len() as u64) as usize;
+            written += write_at(&segment.file, &zeros[..size], offset + written)? as u64;
+          }
+        }
+        lock2io(self.shared.dirty.lock())?.insert(k);
+      }
+      position += length;
+    }
+    self.position = end;
+    Ok(())
+  }
+}
+
+impl io::Seek for SegmentedCursor {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      io::SeekFrom::Start(position) => (position, 0),
+      io::SeekFrom::End(offset) => (self.shared.length(&lock2io(self.shared.segments.read())?)?, offset),
+      io::SeekFrom::Current(offset) => (self.position, offset),
+    };
+    let position = if offset >= 0 { base.checked_add(offset as u64) } else { base.checked_sub(offset.unsigned_abs()) };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+impl io::Read for SegmentedCursor {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let segments = lock2io(self.shared.segments.read())?;
+    let size = self.shared.segment_size;
+    let (k, offset) = ((self.position / size) as usize, self.position % size);
+    let segment = match segments.get(k) {
+      Some(segment) => segment,
+      None => return Ok(0),
+    };
+    // 1 回の読み込みはセグメントの境界を越えない
+    let length = buf.len().min((size - offset) as usize);
+    let length = read_at(&segment.file, &mut buf[..length], offset)?;
+    self.position += length as u64;
+    Ok(length)
+  }
+}
+
+impl io::Write for SegmentedCursor {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let size = self.shared.segment_size;
+    let (k, offset) = ((self.position / size) as usize, self.position % size);
+    let mut segments = lock2io(self.shared.segments.write())?;
+    while segments.len() <= k {
+      if let Some(last) = segments.last() {
+        last.file.set_len(size)?;
+      }
+      self.shared.rotate(&mut segments)?;
+    }
+    let length = buf.len().min((size - offset) as usize);
+    let length = write_at(&segments[k].file, &buf[..length], offset)?;
+    lock2io(self.shared.dirty.lock())?.insert(k);
+    self.position += length as u64;
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// `MANIFEST` を読み込み、セグメントのバイトサイズとセグメントファイルのパスを返します。
+fn read_manifest(manifest: &Path) -> Result<(u64, Vec<PathBuf>)> {
+  let text = std::fs::read_to_string(manifest).map_err(|err| failed_to_open(manifest, err))?;
+  let mut lines = text.lines();
+  match lines.next().and_then(|line| line.trim().parse::<u64>().ok()) {
+    Some(segment_size) if segment_size > 0 => {
+      Ok((segment_size, lines.filter(|line| !line.trim().is_empty()).map(PathBuf::from).collect()))
+    }
+    _ => Err(Detail::DamagedStorage(format!("the segment size of {} is broken", manifest.to_string_lossy()))),
+  }
+}
+
+/// `MANIFEST` を一時ファイルに書き込んだ後に置き換えます。
+fn write_manifest(dir: &Path, segment_size: u64, segments: &[Segment]) -> io::Result<()> {
+  let mut text = format!("{}\n", segment_size);
+  for segment in segments {
+    text.push_str(&format!("{}\n", segment.path.to_string_lossy()));
+  }
+  let temp = dir.join(format!("{}.tmp", MANIFEST));
+  let mut file = File::create(&temp)?;
+  file.write_all(text.as_bytes())?;
+  file.sync_all()?;
+  replace_file(&temp, dir.join(MANIFEST))
+}
+
+fn read_at(file: &File, buf: &mut [u8], position: u64) -> io::Result<usize> {
+  #[cfg(unix)]
+  return std::os::unix::fs::FileExt::read_at(file, buf, position);
+  #[cfg(windows)]
+  return std::os::windows::fs::FileExt::seek_read(file, buf, position);
+}
+
+fn write_at(file: &File, buf: &[u8], position: u64) -> io::Result<usize> {
+  #[cfg(unix)]
+  return std::os::unix::fs::FileExt::write_at(file, buf, position);
+  #[cfg(windows)]
+  return std::os::windows::fs::FileExt::seek_write(file, buf, position);
+}
+
+fn failed_to_open(path: &Path, err: io::Error) -> Detail {
+  Detail::FailedToOpenLocalFile { file: path.to_string_lossy().to_string(), message: err.to_string() }
+}
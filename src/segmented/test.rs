@@ -0,0 +1,41 @@
+use std::fs::remove_file;
+
+use crate::test::temp_file;
+use crate::*;
+
+/// セグメントファイルに分割して保存した LMTHT を再オープンでき、別の場所に移動した古いセグメントを `MANIFEST` の
+/// パスから参照できることを検証します。
+#[test]
+fn test_segmented_storage() {
+  use segmented::{SegmentedStorage, SegmentedStorageOptions};
+  let dir = temp_file("lmtht-segmented", "");
+  remove_file(&dir).unwrap();
+  let options = SegmentedStorageOptions { segment_size: 256 };
+  let db = LMTHT::new(SegmentedStorage::with_options(&dir, options).unwrap()).unwrap();
+  for i in 0..50u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  let root = db.root().unwrap();
+  let segments = db.storage().segments();
+  assert!(segments.len() > 2);
+  assert!(segments[..segments.len() - 1].iter().all(|s| std::fs::metadata(s).unwrap().len() == 256));
+  drop(db);
+
+  // 先頭のセグメントを別の場所に移動して MANIFEST を書き換える
+  let moved = temp_file("lmtht-segmented-cold", ".lmtht");
+  std::fs::rename(&segments[0], &moved).unwrap();
+  let manifest = std::fs::read_to_string(dir.join("MANIFEST")).unwrap();
+  let manifest = manifest.replacen("segment-00000000.lmtht", &moved.to_string_lossy(), 1);
+  std::fs::write(dir.join("MANIFEST"), manifest).unwrap();
+
+  let db = LMTHT::new(SegmentedStorage::new(&dir).unwrap()).unwrap();
+  assert_eq!(256, db.storage().segment_size());
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..50u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+  drop((query, db));
+  std::fs::remove_dir_all(&dir).unwrap();
+  remove_file(&moved).unwrap();
+}
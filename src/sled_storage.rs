@@ -0,0 +1,102 @@
+//! [sled](https://docs.rs/sled) の `Tree` にバイト列を保存するストレージです。
+//!
+//! 直列化されたバイト列は [`ChunkedStorage`] によって固定長のチャンクに分割され、チャンク番号をビッグエンディアンで
+//! 表した 8 バイトのキーで `Tree` に保存されます。チャンクとバイト列の長さは 1 つのバッチとして適用されるため、書き込み
+//! の途中でプロセスが終了しても長さの範囲に不完全なチャンクが残ることはありません。
+//!
+//! ```rust,no_run
+//! use lmtht::sled_storage::{SledChunkStore, DEFAULT_SLED_CHUNK_SIZE};
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let tree = sled::open("app.db")?.open_tree("lmtht")?;
+//! let db = LMTHT::new(SledChunkStore::storage(tree, DEFAULT_SLED_CHUNK_SIZE))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io;
+
+use sled::{Batch, Tree};
+
+use crate::chunked::{ChunkStore, ChunkedStorage};
+
+#[cfg(test)]
+mod test;
+
+/// [`SledChunkStore`] のチャンクサイズのデフォルト値です。
+pub const DEFAULT_SLED_CHUNK_SIZE: usize = 4 * 1024;
+
+/// バイト列の長さを保存するキー。チャンクのキーは常に 8 バイトであるため衝突しない。
+const LENGTH_KEY: &[u8] = b"length";
+
+/// sled の `Tree` にチャンクを保存する [`ChunkStore`] です。
+pub struct SledChunkStore {
+  tree: Tree,
+  chunk_size: usize,
+}
+
+impl SledChunkStore {
+  /// 指定された `Tree` にチャンクを保存する保存先を構築します。`chunk_size` は同じ `Tree` に対して常に同じ値を指定
+  /// する必要があります。
+  pub fn new(tree: Tree, chunk_size: usize) -> SledChunkStore {
+    assert!(chunk_size > 0);
+    SledChunkStore { tree, chunk_size }
+  }
+
+  /// 指定された `Tree` を使用する LMTHT のストレージを構築します。
+  pub fn storage(tree: Tree, chunk_size: usize) -> ChunkedStorage<SledChunkStore> {
+    ChunkedStorage::new(Self::new(tree, chunk_size))
+  }
+
+  /// この保存先が使用する `Tree` を参照します。
+  pub fn tree(&self) -> &Tree {
+    &self.tree
+  }
+}
+
+impl ChunkStore for SledChunkStore {
+  fn chunk_size(&self) -> usize {
+    self.chunk_size
+  }
+
+  fn length(&self) -> io::Result<u64> {
+    match self.tree.get(LENGTH_KEY)? {
+      Some(length) if length.len() == 8 => {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&length);
+        Ok(u64::from_le_bytes(bytes))
+      }
+      Some(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "the length record of the sled tree is broken")),
+      None => Ok(0),
+    }
+  }
+
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>> {
+    Ok(self.tree.get(k.to_be_bytes())?.map(|chunk| chunk.to_vec()))
+  }
+
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    let mut batch = Batch::default();
+    batch.insert(&k.to_be_bytes()[..], chunk);
+    batch.insert(LENGTH_KEY, &length.to_le_bytes()[..]);
+    Ok(self.tree.apply_batch(batch)?)
+  }
+
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()> {
+    let mut batch = Batch::default();
+    for entry in self.tree.range(k.to_be_bytes()..) {
+      let (key, _) = entry?;
+      if key.len() == 8 {
+        batch.remove(key);
+      }
+    }
+    batch.insert(LENGTH_KEY, &length.to_le_bytes()[..]);
+    Ok(self.tree.apply_batch(batch)?)
+  }
+
+  fn sync(&self) -> io::Result<()> {
+    self.tree.flush()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,22 @@
+use crate::*;
+
+/// sled の `Tree` に保存した内容を別の `SledChunkStore` から再オープンできることを検証します。
+#[cfg(feature = "sled_storage")]
+#[test]
+fn test_sled_storage() {
+  use sled_storage::SledChunkStore;
+  let tree = sled::Config::new().temporary(true).open().unwrap().open_tree("lmtht").unwrap();
+  let db = LMTHT::new(SledChunkStore::storage(tree.clone(), 16)).unwrap();
+  for i in 0..50u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  let root = db.root().unwrap();
+  drop(db);
+
+  let db = LMTHT::new(SledChunkStore::storage(tree, 16)).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..50u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+}
@@ -0,0 +1,189 @@
+//! ストレージに対する操作ごとに遅延を加えるラッパーです。
+//!
+//! [`SlowStorage`] は任意の [`Storage`] を包み、そのストレージから作成されたカーソルの読み込み、書き込み、シーク、
+//! 同期のたびに [`SlowStorageOptions`] で指定した時間だけスレッドを停止します。NFS や SD カードのような遅い媒体に
+//! ログを配置した場合にアプリケーションの処理がどのように振る舞うかを、そのような機器を用意することなく計測する
+//! ために使用します。
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use lmtht::{LMTHT, MemStorage};
+//! use lmtht::slow::{SlowStorage, SlowStorageOptions};
+//! let options = SlowStorageOptions { sync: Duration::from_millis(1), ..Default::default() };
+//! let db = LMTHT::new(SlowStorage::with_options(MemStorage::new(), options)).unwrap();
+//! ```
+//!
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::{lock2io, Capabilities, Cursor, IoCounts, Result, Storage};
+
+#[cfg(test)]
+mod test;
+
+/// [`SlowStorageOptions::seed`] のデフォルト値です。
+pub const DEFAULT_SLOW_STORAGE_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// [`SlowStorage`] が操作ごとに加える遅延です。
+#[derive(Clone, Debug)]
+pub struct SlowStorageOptions {
+  /// 1 回の読み込みに加える遅延です。
+  pub read: Duration,
+  /// 1 回の書き込みに加える遅延です。
+  pub write: Duration,
+  /// 1 回のシークに加える遅延です。
+  pub seek: Duration,
+  /// 1 回の同期に加える遅延です。
+  pub sync: Duration,
+  /// それぞれの遅延に加えるゆらぎの最大値です。操作ごとに 0 からこの値までの一様な時間が加算されます。
+  pub jitter: Duration,
+  /// ゆらぎを生成する疑似乱数の種です。同じ種を指定すると同じ操作の列に対して同じ遅延が加えられます。デフォルトは
+  /// [`DEFAULT_SLOW_STORAGE_SEED`] です。
+  pub seed: u64,
+}
+
+impl Default for SlowStorageOptions {
+  fn default() -> Self {
+    SlowStorageOptions {
+      read: Duration::ZERO,
+      write: Duration::ZERO,
+      seek: Duration::ZERO,
+      sync: Duration::ZERO,
+      jitter: Duration::ZERO,
+      seed: DEFAULT_SLOW_STORAGE_SEED,
+    }
+  }
+}
+
+/// 操作ごとに遅延を加えるストレージのラッパーです。
+pub struct SlowStorage<S: Storage> {
+  inner: S,
+  latency: Arc<Latency>,
+}
+
+struct Latency {
+  options: SlowStorageOptions,
+  /// すべてのカーソルで共有する xorshift の状態。
+  state: Mutex<u64>,
+}
+
+impl<S: Storage> SlowStorage<S> {
+  /// 指定されたストレージをすべての操作に `latency` の遅延を加えるように包みます。
+  pub fn new(inner: S, latency: Duration) -> SlowStorage<S> {
+    let options =
+      SlowStorageOptions { read: latency, write: latency, seek: latency, sync: latency, ..Default::default() };
+    Self::with_options(inner, options)
+  }
+
+  /// 指定されたストレージを `options` の遅延を加えるように包みます。
+  pub fn with_options(inner: S, options: SlowStorageOptions) -> SlowStorage<S> {
+    // xorshift の状態は 0 であってはならない
+    let state = Mutex::new(options.seed.max(1));
+    SlowStorage { inner, latency: Arc::new(Latency { options, state }) }
+  }
+
+  /// 包んでいるストレージを参照します。
+  pub fn get_ref(&self) -> &S {
+    &self.inner
+  }
+
+  /// このストレージが加える遅延を参照します。
+  pub fn options(&self) -> &SlowStorageOptions {
+    &self.latency.options
+  }
+}
+
+impl<S: Storage> Storage for SlowStorage<S> {
+  type Cursor = SlowCursor<S::Cursor>;
+  fn open(&self, writable: bool) -> Result<Self::Cursor> {
+    Ok(SlowCursor { inner: self.inner.open(writable)?, latency: self.latency.clone() })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.inner.capabilities()
+  }
+}
+
+impl Latency {
+  /// `base` にゆらぎを加えた時間だけスレッドを停止します。
+  fn wait(&self, base: Duration) -> io::Result<()> {
+    let jitter = self.options.jitter.as_nanos() as u64;
+    let delay = if jitter == 0 {
+      base
+    } else {
+      let mut state = lock2io(self.state.lock())?;
+      *state ^= *state << 13;
+      *state ^= *state >> 7;
+      *state ^= *state << 17;
+      base + Duration::from_nanos(*state % (jitter + 1))
+    };
+    if !delay.is_zero() {
+      sleep(delay);
+    }
+    Ok(())
+  }
+}
+
+/// [`SlowStorage`] が使用するカーソルです。
+pub struct SlowCursor<C: Cursor> {
+  inner: C,
+  latency: Arc<Latency>,
+}
+
+impl<C: Cursor> Cursor for SlowCursor<C> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.latency.wait(self.latency.options.sync)?;
+    self.inner.sync_data()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.latency.wait(self.latency.options.write)?;
+    self.inner.set_len(length)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.latency.wait(self.latency.options.seek)?;
+    self.inner.len()
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.latency.wait(self.latency.options.write)?;
+    self.inner.discard(position, length)
+  }
+
+  fn io_counts(&self) -> IoCounts {
+    self.inner.io_counts()
+  }
+
+  fn duplicate(&self) -> io::Result<Option<Self>> {
+    let inner = self.inner.duplicate()?;
+    Ok(inner.map(|inner| SlowCursor { inner, latency: self.latency.clone() }))
+  }
+}
+
+impl<C: Cursor> io::Seek for SlowCursor<C> {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    self.latency.wait(self.latency.options.seek)?;
+    self.inner.seek(pos)
+  }
+}
+
+impl<C: Cursor> io::Read for SlowCursor<C> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.latency.wait(self.latency.options.read)?;
+    self.inner.read(buf)
+  }
+}
+
+impl<C: Cursor> io::Write for SlowCursor<C> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.latency.wait(self.latency.options.write)?;
+    self.inner.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
@@ -0,0 +1,23 @@
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+/// `SlowStorage` が操作ごとに指定した遅延を加え、内容を変更せずに下位のストレージに委譲することを検証します。
+#[test]
+fn test_slow_storage() {
+  use crate::slow::{SlowStorage, SlowStorageOptions};
+
+  let options =
+    SlowStorageOptions { sync: Duration::from_millis(20), jitter: Duration::from_millis(5), ..Default::default() };
+  let db = LMTHT::new(SlowStorage::with_options(MemStorage::new(), options)).unwrap();
+  for i in 1..=5u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  let start = Instant::now();
+  db.sync().unwrap();
+  assert!(start.elapsed() >= Duration::from_millis(20));
+  let mut query = db.query().unwrap();
+  for i in 1..=5u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+}
@@ -0,0 +1,116 @@
+//! [SQLite](https://docs.rs/rusqlite) のテーブルにバイト列を保存するストレージです。
+//!
+//! 直列化されたバイト列は [`ChunkedStorage`] によって固定長のチャンクに分割され、チャンク番号を主キーとする BLOB
+//! として指定されたテーブルに保存されます。バイト列の長さはテーブル名に `_length` を付けたテーブルに保存されます。
+//! チャンクとバイト列の長さは 1 つのトランザクションで更新されるため、アプリケーションが既に使用している SQLite の
+//! データベースファイルに、同じ耐久性で LMTHT を同居させることができます。
+//!
+//! ```rust,no_run
+//! use lmtht::sqlite_storage::{SqliteChunkStore, DEFAULT_SQLITE_CHUNK_SIZE};
+//! use lmtht::LMTHT;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let conn = rusqlite::Connection::open("app.db")?;
+//! let db = LMTHT::new(SqliteChunkStore::storage(conn, "lmtht", DEFAULT_SQLITE_CHUNK_SIZE)?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::io;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::chunked::{ChunkStore, ChunkedStorage};
+use crate::{lock2io, Result};
+
+#[cfg(test)]
+mod test;
+
+/// [`SqliteChunkStore`] のチャンクサイズのデフォルト値です。
+pub const DEFAULT_SQLITE_CHUNK_SIZE: usize = 4 * 1024;
+
+/// SQLite のテーブルにチャンクを保存する [`ChunkStore`] です。
+pub struct SqliteChunkStore {
+  conn: Mutex<Connection>,
+  chunk_size: usize,
+  /// 引用符で囲まれたチャンクのテーブル名。
+  chunks: String,
+  /// 引用符で囲まれたバイト列の長さのテーブル名。
+  length: String,
+}
+
+impl SqliteChunkStore {
+  /// 指定されたデータベース接続のテーブル `table` にチャンクを保存する保存先を構築します。テーブルが存在しない場合は
+  /// 作成します。`chunk_size` は同じテーブルに対して常に同じ値を指定する必要があります。
+  pub fn new(conn: Connection, table: &str, chunk_size: usize) -> Result<SqliteChunkStore> {
+    assert!(chunk_size > 0);
+    let chunks = quote(table);
+    let length = quote(&format!("{}_length", table));
+    conn
+      .execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {}(k INTEGER PRIMARY KEY, chunk BLOB NOT NULL);\
+         CREATE TABLE IF NOT EXISTS {}(id INTEGER PRIMARY KEY CHECK (id = 0), length INTEGER NOT NULL);",
+        chunks, length
+      ))
+      .map_err(sqlite2io)?;
+    Ok(SqliteChunkStore { conn: Mutex::new(conn), chunk_size, chunks, length })
+  }
+
+  /// 指定されたデータベース接続を使用する LMTHT のストレージを構築します。
+  pub fn storage(conn: Connection, table: &str, chunk_size: usize) -> Result<ChunkedStorage<SqliteChunkStore>> {
+    Ok(ChunkedStorage::new(Self::new(conn, table, chunk_size)?))
+  }
+
+  fn set_length(&self, conn: &Connection, length: u64) -> io::Result<()> {
+    let sql = format!("INSERT OR REPLACE INTO {}(id, length) VALUES (0, ?1)", self.length);
+    conn.execute(&sql, params![length as i64]).map_err(sqlite2io)?;
+    Ok(())
+  }
+}
+
+impl ChunkStore for SqliteChunkStore {
+  fn chunk_size(&self) -> usize {
+    self.chunk_size
+  }
+
+  fn length(&self) -> io::Result<u64> {
+    let conn = lock2io(self.conn.lock())?;
+    let sql = format!("SELECT length FROM {} WHERE id = 0", self.length);
+    let length = conn.query_row(&sql, params![], |row| row.get::<_, i64>(0)).optional().map_err(sqlite2io)?;
+    Ok(length.unwrap_or(0) as u64)
+  }
+
+  fn get(&self, k: u64) -> io::Result<Option<Vec<u8>>> {
+    let conn = lock2io(self.conn.lock())?;
+    let sql = format!("SELECT chunk FROM {} WHERE k = ?1", self.chunks);
+    conn.query_row(&sql, params![k as i64], |row| row.get::<_, Vec<u8>>(0)).optional().map_err(sqlite2io)
+  }
+
+  fn put(&self, k: u64, chunk: &[u8], length: u64) -> io::Result<()> {
+    let mut conn = lock2io(self.conn.lock())?;
+    let tx = conn.transaction().map_err(sqlite2io)?;
+    let sql = format!("INSERT OR REPLACE INTO {}(k, chunk) VALUES (?1, ?2)", self.chunks);
+    tx.execute(&sql, params![k as i64, chunk]).map_err(sqlite2io)?;
+    self.set_length(&tx, length)?;
+    tx.commit().map_err(sqlite2io)
+  }
+
+  fn truncate(&self, k: u64, length: u64) -> io::Result<()> {
+    let mut conn = lock2io(self.conn.lock())?;
+    let tx = conn.transaction().map_err(sqlite2io)?;
+    let sql = format!("DELETE FROM {} WHERE k >= ?1", self.chunks);
+    tx.execute(&sql, params![k as i64]).map_err(sqlite2io)?;
+    self.set_length(&tx, length)?;
+    tx.commit().map_err(sqlite2io)
+  }
+}
+
+/// テーブル名を SQL の識別子として引用符で囲みます。
+fn quote(identifier: &str) -> String {
+  format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn sqlite2io(err: rusqlite::Error) -> io::Error {
+  io::Error::other(err)
+}
@@ -0,0 +1,38 @@
+use std::fs::remove_file;
+
+use crate::test::temp_file;
+use crate::*;
+
+/// SQLite のテーブルに保存した内容を再接続したデータベースから参照でき、バイト列の切り詰めがテーブルに反映される
+/// ことを検証します。
+#[cfg(feature = "sqlite_storage")]
+#[test]
+fn test_sqlite_storage() {
+  use chunked::ChunkStore;
+  use sqlite_storage::SqliteChunkStore;
+  let path = temp_file("lmtht-sqlite", ".db");
+  let storage = SqliteChunkStore::storage(rusqlite::Connection::open(&path).unwrap(), "lmtht", 16).unwrap();
+  let db = LMTHT::new(storage).unwrap();
+  for i in 0..50u8 {
+    db.append(&[i; 5]).unwrap();
+  }
+  let root = db.root().unwrap();
+  drop(db);
+
+  let storage = SqliteChunkStore::storage(rusqlite::Connection::open(&path).unwrap(), "lmtht", 16).unwrap();
+  let db = LMTHT::new(storage).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 0..50u8 {
+    assert_eq!(Some(vec![i; 5]), query.get(i as Index + 1).unwrap());
+  }
+  drop((query, db));
+
+  let store = SqliteChunkStore::new(rusqlite::Connection::open(&path).unwrap(), "lmtht", 16).unwrap();
+  store.truncate(2, 20).unwrap();
+  assert_eq!(20, store.length().unwrap());
+  assert!(store.get(1).unwrap().is_some());
+  assert!(store.get(2).unwrap().is_none());
+  drop(store);
+  remove_file(&path).unwrap();
+}
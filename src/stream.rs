@@ -0,0 +1,46 @@
+//! `async` フィーチャーで有効となる、LMTHT に保存されている値を非同期に読み出すストリームです。
+//!
+//! [`LMTHT::stream_from()`](crate::LMTHT::stream_from) で作成したストリームは既存の値を返した後に新しい値の追加を
+//! 待機するため、追記型のログを非同期のパイプライン (Kafka や WebSocket への転送など) に直接接続することができます。
+//!
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+use crate::{Cursor, Index, Query, Result, Value};
+
+#[cfg(test)]
+mod test;
+
+/// インデックスの順に値を返し、末尾に達した後は新しい値の追加を待機するストリームです。
+pub struct EntryStream<C: Cursor> {
+  query: Query<C>,
+  /// 次に返す値のインデックス。
+  next: Index,
+}
+
+impl<C: Cursor> EntryStream<C> {
+  pub(crate) fn new(query: Query<C>, next: Index) -> EntryStream<C> {
+    EntryStream { query, next }
+  }
+}
+
+impl<C: Cursor + Unpin> Stream for EntryStream<C> {
+  type Item = Result<Value>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    if this.next > this.query.n() {
+      // 次の値が追加されるまで待機
+      match this.query.latest.poll_for(this.next, cx.waker()) {
+        Poll::Ready(Some(gen)) => this.query.gen = gen,
+        Poll::Ready(None) => return Poll::Ready(None),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+    let i = this.next;
+    this.next += 1;
+    Poll::Ready(Some(this.query.get_value(i)))
+  }
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// stream_from() が既存の値を返した後に新しい値の追加でタスクを起床させることを検証します。
+#[cfg(feature = "async")]
+#[test]
+fn test_stream_from() {
+  use futures_core::Stream;
+  use std::pin::Pin;
+  use std::task::{Context, Poll, Wake, Waker};
+
+  struct Counter(AtomicUsize);
+  impl Wake for Counter {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+  let counter = Arc::new(Counter(AtomicUsize::new(0)));
+  let waker = Waker::from(counter.clone());
+  let mut cx = Context::from_waker(&waker);
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 1..=5 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut stream = db.stream_from(2).unwrap();
+  for i in 2..=5 {
+    match Pin::new(&mut stream).poll_next(&mut cx) {
+      Poll::Ready(Some(value)) => assert_eq!(Value::new(i, random_payload(PAYLOAD_SIZE, i)), value.unwrap()),
+      _ => panic!("the value {} should be ready", i),
+    }
+  }
+
+  // 末尾に達すると新しい値が追加されるまで待機する
+  assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+  assert_eq!(0, counter.0.load(Ordering::SeqCst));
+  db.append(&random_payload(PAYLOAD_SIZE, 6)).unwrap();
+  assert_eq!(1, counter.0.load(Ordering::SeqCst));
+  match Pin::new(&mut stream).poll_next(&mut cx) {
+    Poll::Ready(Some(value)) => assert_eq!(Value::new(6, random_payload(PAYLOAD_SIZE, 6)), value.unwrap()),
+    _ => panic!("the value 6 should be ready"),
+  }
+
+  // LMTHT が破棄されるとストリームは終了する
+  assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+  drop(db);
+  assert_eq!(2, counter.0.load(Ordering::SeqCst));
+  assert!(matches!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(None)));
+}
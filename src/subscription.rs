@@ -0,0 +1,58 @@
+//! LMTHT への値の追加を購読者に通知します。
+//!
+//! [`LMTHT::subscribe()`](crate::LMTHT::subscribe) で取得したチャネルには、以降に追加されたすべての値について
+//! [`Appended`] が追加の順序で配信されます。下流のインデクサやレプリケータは `n()` をポーリングすることなく追加を
+//! 検出することができます。
+//!
+//! 通知は書き込みを待たせないように容量の制限されたチャネルへ送信されます。受信が追いつかずチャネルが満杯になった
+//! 購読者は購読を打ち切られ、受信側はチャネルに残っている通知を受け取った後に切断を検出します。切断された購読者は
+//! 最後に受け取った通知の `i` 以降をクエリーで読み直した上で購読し直す必要があります。
+//!
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+use crate::{lock2io, Index, Node, Result};
+
+#[cfg(test)]
+mod test;
+
+/// 追加された 1 つの値を表す通知です。
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Appended {
+  /// 値を追加した後の木構造のルートノード。
+  pub root: Node,
+  /// 追加された値のインデックス。
+  pub i: Index,
+  /// 追加された値のバイトサイズ。
+  pub payload_size: usize,
+}
+
+/// 追加の通知を受け取る購読者の一覧です。
+pub(crate) struct Subscribers {
+  senders: Mutex<Vec<SyncSender<Appended>>>,
+}
+
+impl Subscribers {
+  pub fn new() -> Subscribers {
+    Subscribers { senders: Mutex::new(Vec::new()) }
+  }
+
+  /// 最大 `capacity` 個の未受信の通知を保持できる新しい購読者を登録し、通知を受け取るチャネルを返します。
+  pub fn subscribe(&self, capacity: usize) -> Result<Receiver<Appended>> {
+    let (sender, receiver) = sync_channel(capacity);
+    lock2io(self.senders.lock())?.push(sender);
+    Ok(receiver)
+  }
+
+  /// すべての購読者に追加を通知します。この操作は待機せず、失敗することもありません。受信側が破棄されている購読者と
+  /// チャネルが満杯になった購読者は一覧から取り除きます。
+  pub fn notify(&self, events: &[Appended]) {
+    let mut senders = self.senders.lock().unwrap_or_else(|err| err.into_inner());
+    senders.retain(|sender| {
+      events.iter().all(|event| match sender.try_send(*event) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+      })
+    });
+  }
+}
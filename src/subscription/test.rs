@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use std::thread::spawn;
+
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// 購読者が以降のすべての追加の通知を追加の順序で受け取ることを検証します。
+#[test]
+fn test_subscribe() {
+  let db = Arc::new(LMTHT::new(MemStorage::new()).unwrap());
+  db.append(&random_payload(PAYLOAD_SIZE, 0)).unwrap();
+  let subscription = db.subscribe(64).unwrap();
+  let consumer = spawn(move || subscription.iter().take(20).collect::<Vec<_>>());
+  let dropped = db.subscribe(64).unwrap();
+  drop(dropped);
+
+  let mut expected = Vec::new();
+  for i in 1..=10 {
+    let value = random_payload(PAYLOAD_SIZE + i as usize, i);
+    let root = db.append(&value).unwrap();
+    expected.push(Appended { root, i: i + 1, payload_size: value.len() });
+  }
+  let mut batch = db.begin_batch().unwrap();
+  for i in 11..=20 {
+    let value = random_payload(PAYLOAD_SIZE, i);
+    let root = batch.append(&value).unwrap();
+    expected.push(Appended { root, i: i + 1, payload_size: value.len() });
+  }
+  batch.commit().unwrap();
+  assert_eq!(expected, consumer.join().unwrap());
+
+  // コミットされなかった追加は通知されない
+  let subscription = db.subscribe(64).unwrap();
+  db.begin_batch().unwrap().append(&random_payload(PAYLOAD_SIZE, 0)).unwrap();
+  assert!(subscription.try_recv().is_err());
+}
+
+/// 受信が追いつかずチャネルが満杯になった購読者が、追加を失敗させることなく購読を打ち切られることを検証します。
+#[test]
+fn test_subscription_overflow() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let lagging = db.subscribe(4).unwrap();
+  let following = db.subscribe(4).unwrap();
+  for i in 1..=10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    assert_eq!(i, following.recv().unwrap().i);
+  }
+  assert_eq!(10, db.n());
+
+  // チャネルに残っている通知を受信した後に切断を検出する
+  assert_eq!((1..=4).collect::<Vec<_>>(), lagging.iter().map(|event| event.i).collect::<Vec<_>>());
+  assert!(lagging.try_recv().is_err());
+  db.append(&random_payload(PAYLOAD_SIZE, 11)).unwrap();
+  assert_eq!(11, following.recv().unwrap().i);
+}
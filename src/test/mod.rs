@@ -1,23 +1,28 @@
 use std::cmp::max;
 use std::env::temp_dir;
+use std::fs::{remove_file, OpenOptions};
 use std::hash::Hasher;
 use std::io;
-use std::io::{ErrorKind, Seek};
+use std::io::{ErrorKind, Read, Seek};
 use std::io::{SeekFrom, Write};
-use std::path::{MAIN_SEPARATOR, PathBuf};
-use std::thread::{JoinHandle, spawn};
+use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use highway::{HighwayBuilder, Key};
 use mt19937::MT19937;
 use rand::RngCore;
 
+use crate::error::Detail;
+use crate::model::{ceil_log2, range, NthGenHashTree};
 use crate::*;
-use crate::model::ceil_log2;
 
 #[test]
 fn test_multi_threaded_query() {
-  const N: u64 = 100;
+  const N: Index = 100;
   for n in 1..=N {
     let db = Arc::new(prepare_db(n, PAYLOAD_SIZE));
     let mut handles = Vec::<JoinHandle<()>>::with_capacity(10);
@@ -64,6 +69,13 @@ fn entry_serialization() -> Result<()> {
     let inodes = read_inodes(&mut cursor, 0)?;
     assert_eq!(expected.inodes, inodes);
 
+    // ペイロードを除いたエントリを読み出して同一かを確認
+    cursor.set_position(0);
+    let (meta, inodes) = read_entry_header(&mut cursor, 0, 0)?;
+    assert_eq!(expected.enode.meta, meta);
+    assert_eq!(expected.inodes, inodes);
+    assert_eq!(write_length as u64 - 4 - 8, cursor.position());
+
     // チェックサムによるチェックなし版から復元して元のエントリと同一かを確認
     cursor.set_position(0);
     let actual = read_entry_without_check(&mut cursor, 0, 0)?;
@@ -110,6 +122,54 @@ fn garbled_at_any_position() -> Result<()> {
   Ok(())
 }
 
+/// エントリが一度の `write_vectored()` で書き込まれること、および一度にすべてを書き込めない出力先に対しても同じ
+/// バイト列が書き込まれることを検証します。
+#[test]
+fn test_vectored_entry_write() -> Result<()> {
+  /// 一度の呼び出しで最大 `limit` バイトまでを書き込み、呼び出し回数を記録する出力先。
+  struct Limited {
+    buffer: Vec<u8>,
+    limit: usize,
+    calls: usize,
+  }
+  impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.write_vectored(&[io::IoSlice::new(buf)])
+    }
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+      self.calls += 1;
+      let mut length = 0;
+      for buf in bufs {
+        let size = buf.len().min(self.limit - length);
+        self.buffer.extend_from_slice(&buf[..size]);
+        length += size;
+      }
+      Ok(length)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  for entry in representative_entries(0) {
+    let mut expected = Vec::<u8>::new();
+    let length = write_entry(&mut expected, &entry)?;
+    assert_eq!(expected.len(), length);
+
+    let mut unlimited = Limited { buffer: Vec::new(), limit: usize::MAX, calls: 0 };
+    write_entry(&mut unlimited, &entry)?;
+    assert_eq!(expected, unlimited.buffer);
+    assert_eq!(1, unlimited.calls);
+
+    for limit in [1, 8, 9, length - 1] {
+      let mut limited = Limited { buffer: Vec::new(), limit, calls: 0 };
+      write_entry(&mut limited, &entry)?;
+      assert_eq!(expected, limited.buffer, "limit={}", limit);
+    }
+  }
+  Ok(())
+}
+
 #[test]
 fn test_bootstrap() {
   // 空のストレージを指定してファイル識別子が出力されることを確認
@@ -125,13 +185,13 @@ fn test_bootstrap() {
   assert_eq!(None, session.get(1).unwrap());
   assert_eq!(4, content.len());
   assert_eq!(&STORAGE_IDENTIFIER[..], &content[..3]);
-  assert_eq!(STORAGE_VERSION, content[3]);
+  assert_eq!(STORAGE_VERSION | INDEX_WIDTH, content[3]);
 
   // ストレージの末尾に存在するエントリをルートとして読み込んでいることを確認
   for entry in representative_entries(4) {
     let mut buffer = Vec::<u8>::with_capacity(4 * 1024);
     buffer.write_all(&STORAGE_IDENTIFIER).unwrap();
-    buffer.write_u8(STORAGE_VERSION).unwrap();
+    buffer.write_u8(STORAGE_VERSION | INDEX_WIDTH).unwrap();
     write_entry(&mut buffer, &entry).unwrap();
     let buffer = Arc::new(RwLock::new(buffer));
     let storage = MemStorage::with(buffer.clone());
@@ -141,12 +201,12 @@ fn test_bootstrap() {
   }
 }
 
-const PAYLOAD_SIZE: usize = 4;
+pub(crate) const PAYLOAD_SIZE: usize = 4;
 
 /// データを追加して取得します。
 #[test]
 fn test_append_and_get() {
-  const N: u64 = 100;
+  const N: Index = 100;
   for n in 1..=N {
     let db = prepare_db(n, PAYLOAD_SIZE);
     let mut query = db.query().unwrap();
@@ -168,7 +228,7 @@ fn test_append_and_get() {
 /// ハッシュ付き値参照で取得した値とハッシュ値の検証。
 #[test]
 fn test_get_values_with_hashes() {
-  const N: u64 = 100;
+  const N: Index = 100;
   for n in 1..=N {
     let db = prepare_db(n, PAYLOAD_SIZE);
     let mut query = db.query().unwrap();
@@ -213,11 +273,93 @@ fn test_get_values_with_hashes() {
   }
 }
 
+/// 中間ノードのキャッシュの容量にかかわらず同じ結果を参照できることを検証します。
+#[test]
+fn test_inode_cache_size() {
+  const N: Index = 50;
+  for inode_cache_size in [0, 1, 8, DEFAULT_INODE_CACHE_SIZE] {
+    let options = LMTHTOptions { inode_cache_size, ..Default::default() };
+    let db = LMTHT::with_options(MemStorage::new(), options).unwrap();
+    for i in 1..=N {
+      db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    }
+    let mut query = db.query().unwrap();
+    for i in 1..=N {
+      let values = query.get_with_hashes(i).unwrap().unwrap();
+      assert_eq!(db.root().unwrap(), values.root());
+      assert_eq!(random_payload(PAYLOAD_SIZE, i), values.values[0].value);
+      assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+    }
+  }
+}
+
+/// 読み込みと書き込みのバッファサイズにかかわらず同じストレージの内容と結果となることを検証します。
+#[test]
+fn test_buffer_size() {
+  const N: Index = 30;
+  let mut expected = None;
+  for (read_buffer_size, write_buffer_size) in [(0, 0), (1, 1), (16, 16), (DEFAULT_READ_BUFFER_SIZE, 0)] {
+    let buffer = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
+    let options = LMTHTOptions { read_buffer_size, write_buffer_size, ..Default::default() };
+    let db = LMTHT::with_options(MemStorage::with(buffer.clone()), options).unwrap();
+    for i in 1..=N {
+      db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    }
+    let mut query = db.query().unwrap();
+    for i in 1..=N {
+      assert_eq!(db.root().unwrap(), query.get_with_hashes(i).unwrap().unwrap().root());
+    }
+    let actual = buffer.read().unwrap().clone();
+    assert_eq!(expected.get_or_insert_with(|| actual.clone()), &actual);
+  }
+}
+
+/// 異なる世代の複数の `Query` がキャッシュを共有しても、それぞれの世代の値とルートハッシュを参照できることを検証します。
+#[test]
+fn test_node_cache_shared_by_queries() {
+  const N: Index = 40;
+  let options = LMTHTOptions { inode_cache_size: 4, position_cache_size: 4, path_cache_size: 4, ..Default::default() };
+  let db = LMTHT::with_options(MemStorage::new(), options).unwrap();
+  let mut queries = Vec::<(Node, Query<BufferedCursor<MemCursor>>)>::with_capacity(N as usize);
+  for n in 1..=N {
+    let root = db.append(&random_payload(PAYLOAD_SIZE, n)).unwrap();
+    queries.push((root, db.query().unwrap()));
+    for (root, query) in queries.iter_mut() {
+      for i in (1..=root.i).rev() {
+        assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+        assert_eq!(*root, query.get_with_hashes(i).unwrap().unwrap().root());
+      }
+      assert_eq!(None, query.get(root.i + 1).unwrap());
+    }
+  }
+}
+
+/// プールから取得したクエリーが再利用され、取得した時点の最新の世代を参照することを検証します。
+#[test]
+fn test_pooled_query() {
+  const N: Index = 20;
+  let options = LMTHTOptions { query_pool_size: 2, ..Default::default() };
+  let db = LMTHT::with_options(MemStorage::new(), options).unwrap();
+  for n in 1..=N {
+    let root = db.append(&random_payload(PAYLOAD_SIZE, n)).unwrap();
+    let mut queries = (0..3).map(|_| db.pooled_query().unwrap()).collect::<Vec<_>>();
+    for query in queries.iter_mut() {
+      assert_eq!(n, query.n());
+      for i in 1..=n {
+        assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+        assert_eq!(root, query.get_with_hashes(i).unwrap().unwrap().root());
+      }
+    }
+    drop(queries);
+    assert_eq!(2, db.query_pool.queries.lock().unwrap().len());
+  }
+}
+
 /// n 個の要素を持つ LMTHT を構築します。それぞれの要素は乱数で初期化された `payload_size` サイズの値を持ちます。
-fn prepare_db(n: u64, payload_size: usize) -> LMTHT<MemStorage> {
+pub(crate) fn prepare_db(n: Index, payload_size: usize) -> LMTHT<MemStorage> {
   let buffer = Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024)));
   let storage = MemStorage::with(buffer.clone());
-  let mut db = LMTHT::new(storage).unwrap();
+  let db = LMTHT::new(storage).unwrap();
 
   for i in 0..n {
     let value = random_payload(payload_size, i + 1);
@@ -231,7 +373,7 @@ fn prepare_db(n: u64, payload_size: usize) -> LMTHT<MemStorage> {
 }
 
 /// エントリの直列表現のチェックサムを検証します。
-fn verify_checksum(entry: &[u8]) {
+pub(crate) fn verify_checksum(entry: &[u8]) {
   let mut cursor = io::Cursor::new(entry);
 
   // エントリの直列化表現に記録されているチェックサムを参照
@@ -245,7 +387,7 @@ fn verify_checksum(entry: &[u8]) {
 }
 
 /// 指定されたバイナリデータに対するチェックサムを算出。
-fn checksum(bytes: &[u8]) -> u64 {
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
   let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
   hasher.write_all(bytes).unwrap();
   hasher.finish()
@@ -262,11 +404,12 @@ fn representative_entries(position: u64) -> Vec<Entry> {
   ]
 }
 
-fn enode(i: u64, position: u64, payload: Vec<u8>) -> ENode {
-  ENode { meta: MetaInfo { address: Address { i, j: 0, position }, hash: random_hash(position ^ i) }, payload }
+#[allow(clippy::unnecessary_cast)] // Index は feature によって u64 以外の型となる
+fn enode(i: Index, position: u64, payload: Vec<u8>) -> ENode {
+  ENode { meta: MetaInfo { address: Address { i, j: 0, position }, hash: random_hash(position ^ i as u64) }, payload }
 }
 
-fn inode(i: u64, j: u8, position: u64) -> INode {
+fn inode(i: Index, j: u8, position: u64) -> INode {
   INode {
     meta: MetaInfo { address: Address { i, j, position }, hash: random_hash(position ^ j as u64) },
     left: Address { i: i - 1, j: 0, position: max(position as i64 - 100, 0) as u64 },
@@ -274,7 +417,9 @@ fn inode(i: u64, j: u8, position: u64) -> INode {
   }
 }
 
-fn random_payload(length: usize, s: u64) -> Vec<u8> {
+#[allow(clippy::unnecessary_cast)] // Index は feature によって u64 以外の型となる
+pub(crate) fn random_payload(length: usize, s: Index) -> Vec<u8> {
+  let s = s as u64;
   let mut seed = [0u32; 2];
   seed[0] = ((s >> 0) & 0xFFFFFFFF) as u32;
   seed[1] = ((s >> 8) & 0xFFFFFFFF) as u32;
@@ -287,7 +432,7 @@ fn random_payload(length: usize, s: u64) -> Vec<u8> {
   bytes
 }
 
-fn random_hash(s: u64) -> Hash {
+pub(crate) fn random_hash(s: u64) -> Hash {
   let mut seed = [0u32; 2];
   seed[0] = ((s >> 0) & 0xFFFFFFFF) as u32;
   seed[1] = ((s >> 8) & 0xFFFFFFFF) as u32;
@@ -305,14 +450,895 @@ fn test_file_storage() {
   remove_file(file.to_path_buf()).expect(&format!("failed to remove temporary file: {}", file.to_string_lossy()));
 }
 
+/// ファイル記述子を共有するファイルストレージの適合テスト。
+#[test]
+fn test_shared_file_storage() {
+  let file = temp_file("lmtht-shared-storage", ".db");
+  verify_storage_spec(&FileStorage::new(&file)).expect("LMTHT compliance test filed");
+  remove_file(&file).unwrap();
+
+  // 複数のスレッドのクエリーが同じファイル記述子からシークに干渉されずに読み出せる
+  const N: Index = 50;
+  let file = temp_file("lmtht-shared-storage", ".db");
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  for i in 1..=N {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let db = Arc::new(db);
+  let handles = (0..8)
+    .map(|_| {
+      let db = db.clone();
+      spawn(move || {
+        let mut query = db.query().unwrap();
+        for i in 1..=N {
+          assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+          assert_eq!(db.root().unwrap(), query.get_with_hashes(i).unwrap().unwrap().root());
+        }
+      })
+    })
+    .collect::<Vec<JoinHandle<()>>>();
+  for handle in handles {
+    handle.join().unwrap();
+  }
+  drop(db);
+
+  // 再オープンして同じ内容を参照できる
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  assert_eq!(N, db.n());
+  assert_eq!(Some(random_payload(PAYLOAD_SIZE, N)), db.query().unwrap().get(N).unwrap());
+  drop(db);
+  remove_file(&file).unwrap();
+}
+
+/// 書き込み用にオープンした FileStorage が他の書き込みを排除することを検証します。
+#[test]
+fn test_file_storage_lock() {
+  let file = temp_file("lmtht-lock", ".db");
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  db.append(&random_payload(PAYLOAD_SIZE, 1)).unwrap();
+
+  // 同じファイルに対する別の書き込みはロックを獲得できない
+  assert!(matches!(LMTHT::new(FileStorage::new(&file)), Err(Detail::StorageLocked { .. })));
+  assert!(matches!(FileStorage::new(&file).open(true), Err(Detail::StorageLocked { .. })));
+  assert!(FileStorage::new(&file).open(false).is_ok());
+
+  // ロックを保持していたストレージを破棄すると書き込みが可能になる
+  drop(db);
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  assert_eq!(2, db.append(&random_payload(PAYLOAD_SIZE, 2)).unwrap().i);
+  drop(db);
+  remove_file(&file).unwrap();
+}
+
+/// パスをストレージとして書き込み用にオープンするとロックを獲得し、同じパスに対する別の書き込み用のオープンが失敗する
+/// ことを検証します。
+#[test]
+fn test_path_storage_lock() {
+  let file = temp_file("lmtht-path-lock", ".db");
+  let cursor = file.open(true).unwrap();
+  assert!(matches!(file.open(true), Err(Detail::StorageLocked { .. })));
+  assert!(matches!(FileStorage::new(&file).open(true), Err(Detail::StorageLocked { .. })));
+  assert!(file.open(false).is_ok());
+  drop(cursor);
+
+  // LMTHT は破棄されるまでロックを保持し、自身の書き込みは妨げられない
+  let sync_policy = SyncPolicy::GroupCommit { delay: Duration::from_millis(1) };
+  let db = LMTHT::with_options(file.clone(), LMTHTOptions { sync_policy, ..Default::default() }).unwrap();
+  for i in 1..=10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  db.wait_durable(10).unwrap();
+  assert!(matches!(file.open(true), Err(Detail::StorageLocked { .. })));
+  assert!(matches!(LMTHT::new(file.clone()), Err(Detail::StorageLocked { .. })));
+  let reader = LMTHT::with_options(file.clone(), LMTHTOptions { read_only: true, ..Default::default() }).unwrap();
+  assert_eq!(10, reader.n());
+  drop((reader, db));
+
+  let db = LMTHT::new(file.clone()).unwrap();
+  assert_eq!(11, db.append(&random_payload(PAYLOAD_SIZE, 11)).unwrap().i);
+  drop(db);
+  remove_file(&file).unwrap();
+}
+
+/// パスをラップしたストレージでも LMTHT が破棄されるまでロックを保持し、別の書き込み用のオープンが失敗することを
+/// 検証します。
+#[test]
+fn test_wrapped_path_storage_lock() {
+  use crate::cache::CachedStorage;
+  use crate::fault::FaultyStorage;
+  use crate::mirror::{MirrorPolicy, MirroredStorage};
+  use crate::quorum::QuorumStorage;
+  use crate::retry::{RetryPolicy, RetryStorage};
+  use crate::slow::SlowStorage;
+
+  fn assert_locked<S: Storage>(wrap: impl Fn() -> S) {
+    let db = LMTHT::new(wrap()).unwrap();
+    let n = db.append(&random_payload(PAYLOAD_SIZE, 1)).unwrap().i;
+    assert!(matches!(LMTHT::new(wrap()), Err(Detail::StorageLocked { .. })));
+    drop(db);
+    assert_eq!(n, LMTHT::new(wrap()).unwrap().n());
+  }
+
+  let (file, mirror) = (temp_file("lmtht-wrapped-lock", ".db"), temp_file("lmtht-wrapped-mirror", ".db"));
+  assert_locked(|| CachedStorage::new(file.clone()));
+  assert_locked(|| SlowStorage::new(file.clone(), Duration::ZERO));
+  assert_locked(|| FaultyStorage::new(file.clone()));
+  assert_locked(|| RetryStorage::new(file.clone(), RetryPolicy::default()));
+  remove_file(&file).unwrap();
+  drop((file.open(true).unwrap(), mirror.open(true).unwrap()));
+  assert_locked(|| MirroredStorage::new(file.clone(), mirror.clone(), MirrorPolicy::FailOnDivergence).unwrap());
+  remove_file(&file).unwrap();
+  remove_file(&mirror).unwrap();
+  drop((file.open(true).unwrap(), mirror.open(true).unwrap()));
+  assert_locked(|| QuorumStorage::new(vec![file.clone(), mirror.clone()], 2).unwrap());
+  remove_file(&file).unwrap();
+  remove_file(&mirror).unwrap();
+}
+
+/// ファイル領域を事前に確保しても論理的なファイルサイズと内容が変化しないことを検証します。
+#[test]
+fn test_file_storage_preallocation() {
+  const N: Index = 50;
+  let options = FileStorageOptions { preallocation_size: 1024 * 1024, ..Default::default() };
+  let file = temp_file("lmtht-preallocation", ".db");
+  verify_storage_spec(&FileStorage::with_options(&file, options.clone())).expect("LMTHT compliance test filed");
+  remove_file(&file).unwrap();
+
+  let file = temp_file("lmtht-preallocation", ".db");
+  let db = LMTHT::new(FileStorage::with_options(&file, options.clone())).unwrap();
+  for i in 1..=N {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let root = db.root();
+  drop(db);
+  assert!(std::fs::metadata(&file).unwrap().len() < options.preallocation_size);
+
+  // 再オープンして末尾のエントリを正しく参照できる
+  let db = LMTHT::new(FileStorage::with_options(&file, options)).unwrap();
+  assert_eq!(root, db.root());
+  assert_eq!(Some(random_payload(PAYLOAD_SIZE, N)), db.query().unwrap().get(N).unwrap());
+  drop(db);
+  remove_file(&file).unwrap();
+}
+
+/// ファイルストレージのカーソルが複数のバッファをまとめて指定位置に書き込めることを検証します。
+#[test]
+fn test_file_storage_write_vectored() {
+  let file = temp_file("lmtht-write-vectored", ".db");
+  let storage = FileStorage::new(&file);
+  let mut cursor = storage.open(true).unwrap();
+  cursor.write_all(&[0xFF; 8]).unwrap();
+  let bufs = [io::IoSlice::new(b"lmtht"), io::IoSlice::new(&[]), io::IoSlice::new(b"-"), io::IoSlice::new(b"vectored")];
+  cursor.seek(SeekFrom::Start(4)).unwrap();
+  assert_eq!(14, cursor.write_vectored(&bufs).unwrap());
+  assert_eq!(18, cursor.stream_position().unwrap());
+  cursor.flush().unwrap();
+  drop(cursor);
+
+  let mut bytes = Vec::new();
+  storage.open(false).unwrap().read_to_end(&mut bytes).unwrap();
+  assert_eq!(b"\xFF\xFF\xFF\xFFlmtht-vectored", &bytes[..]);
+  assert!(storage.open(false).unwrap().write_vectored(&bufs).is_err());
+  drop(storage);
+  remove_file(&file).unwrap();
+}
+
+/// 先読みの方法にかかわらずファイルストレージから同じ内容を読み出せることを検証します。
+#[test]
+fn test_file_storage_readahead() {
+  const N: Index = 100;
+  let strategies =
+    [Readahead::Advise { window: 4096 }, Readahead::Prefetch { window: 4096 }, Readahead::Prefetch { window: 7 }];
+  for readahead in strategies {
+    let options = FileStorageOptions { readahead, ..Default::default() };
+    let file = temp_file("lmtht-readahead", ".db");
+    verify_storage_spec(&FileStorage::with_options(&file, options.clone())).expect("LMTHT compliance test filed");
+    remove_file(&file).unwrap();
+
+    // 連続した読み込みと離れた位置へのシークが混在しても正しい内容を返す
+    let file = temp_file("lmtht-readahead", ".db");
+    let storage = FileStorage::with_options(&file, options.clone());
+    let expected = random_payload(64 * 1024, 0);
+    storage.open(true).unwrap().write_all(&expected).unwrap();
+    let mut cursor = storage.open(false).unwrap();
+    let mut rand = MT19937::new_with_slice_seed(&[0]);
+    for _ in 0..2000 {
+      if rand.next_u32() % 8 == 7 {
+        cursor.seek(SeekFrom::Start(rand.next_u32() as u64 % expected.len() as u64)).unwrap();
+      }
+      let position = cursor.stream_position().unwrap() as usize;
+      let mut buf = vec![0u8; (rand.next_u32() % 64) as usize];
+      let length = cursor.read(&mut buf).unwrap();
+      assert_eq!(&expected[position..position + length], &buf[..length], "{:?} at {}", readahead, position);
+    }
+    drop(cursor);
+    drop(storage);
+    remove_file(&file).unwrap();
+
+    // すべての値の抽出が先読みを有効にしても同じ結果となる
+    let file = temp_file("lmtht-readahead", ".db");
+    let db = LMTHT::new(FileStorage::with_options(&file, options)).unwrap();
+    for i in 1..=N {
+      db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    }
+    let root = db.root().unwrap();
+    let values = db.query().unwrap().get_values_with_hashes(root.i, root.j).unwrap().unwrap();
+    assert_eq!(N as usize, values.values.len());
+    for (k, value) in values.values.iter().enumerate() {
+      assert_eq!(random_payload(PAYLOAD_SIZE, k as Index + 1), value.value);
+    }
+    drop(db);
+    remove_file(&file).unwrap();
+  }
+}
+
 /// メモリーストレージの適合テスト
+/// 追加したエントリが同期方針に従ってストレージに同期されることを検証します。
+#[test]
+fn test_sync_policy() {
+  const N: usize = 10;
+  for (sync_policy, expected) in [
+    (SyncPolicy::Always, N),
+    (SyncPolicy::EveryNAppends(3), N / 3),
+    (SyncPolicy::Interval(Duration::ZERO), N),
+    (SyncPolicy::Interval(Duration::from_secs(3600)), 0),
+    (SyncPolicy::Manual, 0),
+  ] {
+    let storage = CountingStorage::default();
+    let syncs = storage.syncs.clone();
+    let options = LMTHTOptions { sync_policy, ..Default::default() };
+    let db = LMTHT::with_options(storage, options).unwrap();
+    for i in 1..=N {
+      db.append(&random_payload(PAYLOAD_SIZE, i as Index)).unwrap();
+    }
+    assert_eq!(expected, syncs.load(Ordering::SeqCst), "{:?}", sync_policy);
+    db.sync().unwrap();
+    assert_eq!(expected + 1, syncs.load(Ordering::SeqCst), "{:?}", sync_policy);
+  }
+
+  // グループコミットでは複数の追加がまとめて同期される
+  let storage = CountingStorage::default();
+  let syncs = storage.syncs.clone();
+  let sync_policy = SyncPolicy::GroupCommit { delay: Duration::from_millis(50) };
+  let options = LMTHTOptions { sync_policy, ..Default::default() };
+  let db = LMTHT::with_options(storage, options).unwrap();
+  db.wait_durable(0).unwrap();
+  for i in 1..=N {
+    db.append(&random_payload(PAYLOAD_SIZE, i as Index)).unwrap();
+  }
+  db.wait_durable(N as Index).unwrap();
+  let count = syncs.load(Ordering::SeqCst);
+  assert!((1..N).contains(&count), "{}", count);
+  assert!(matches!(db.wait_durable(N as Index + 1), Err(Detail::GenerationNotAppended { .. })));
+  db.append(&random_payload(PAYLOAD_SIZE, 0)).unwrap();
+  drop(db);
+  assert_eq!(count + 1, syncs.load(Ordering::SeqCst));
+}
+
+/// Arc で共有した LMTHT に複数のスレッドから同時に追加と参照を行えることを検証します。
+#[test]
+fn test_shared_append() {
+  const THREADS: Index = 4;
+  const N: Index = 50;
+  let db = Arc::new(LMTHT::new(MemStorage::new()).unwrap());
+  let writers = (0..THREADS)
+    .map(|t| {
+      let db = db.clone();
+      spawn(move || (0..N).map(|k| db.append(&random_payload(PAYLOAD_SIZE, t * N + k)).unwrap().i).collect::<Vec<_>>())
+    })
+    .collect::<Vec<_>>();
+  let reader = {
+    let db = db.clone();
+    spawn(move || {
+      // 参照は常に追加が完了した世代を対象とし、その世代までのすべての値を読み出すことができる
+      while db.n() < THREADS * N {
+        let mut query = db.query().unwrap();
+        for i in 1..=query.n() {
+          assert!(query.get(i).unwrap().is_some());
+        }
+      }
+    })
+  };
+  let mut indices = writers.into_iter().flat_map(|writer| writer.join().unwrap()).collect::<Vec<_>>();
+  reader.join().unwrap();
+  indices.sort_unstable();
+  assert_eq!((1..=THREADS * N).collect::<Vec<_>>(), indices);
+
+  let mut query = db.query().unwrap();
+  let mut values = (1..=db.n()).map(|i| query.get(i).unwrap().unwrap()).collect::<Vec<_>>();
+  let mut expected = (0..THREADS * N).map(|k| random_payload(PAYLOAD_SIZE, k)).collect::<Vec<_>>();
+  values.sort();
+  expected.sort();
+  assert_eq!(expected, values);
+}
+
+/// 読み込み専用の LMTHT が他の書き込みによる追加を検出して最新の世代を参照することを検証します。
+#[test]
+fn test_read_only_refresh() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let read_only = LMTHTOptions { read_only: true, ..Default::default() };
+  let auto_refresh = LMTHTOptions { read_only: true, auto_refresh: true, ..Default::default() };
+
+  // 空のストレージを読み込み専用でオープンしても何も書き込まない
+  let fixed = LMTHT::with_options(MemStorage::with(buffer.clone()), read_only.clone()).unwrap();
+  let reader = LMTHT::with_options(MemStorage::with(buffer.clone()), auto_refresh).unwrap();
+  assert!(buffer.read().unwrap().is_empty());
+  assert!(matches!(reader.append(&[0u8]), Err(Detail::ReadOnly)));
+  assert!(matches!(reader.sync(), Err(Detail::ReadOnly)));
+
+  let writer = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=10 {
+    writer.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    let mut query = reader.query().unwrap();
+    assert_eq!(i, query.n());
+    assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+    assert_eq!(writer.root(), reader.root());
+  }
+  assert_eq!(0, fixed.query().unwrap().n());
+
+  // 書き込み途中のバッチは破棄されず、コミットされるまで参照されない
+  let mut batch = writer.begin_batch().unwrap();
+  for i in 11..=13 {
+    batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  batch.commit().unwrap();
+  let complete = buffer.read().unwrap().clone();
+  let committed = writer.storage().buffer.read().unwrap().len();
+  buffer.write().unwrap().truncate(committed - 10);
+  let partial = LMTHT::with_options(MemStorage::with(buffer.clone()), read_only).unwrap();
+  assert_eq!(10, partial.n());
+  assert_eq!(committed - 10, buffer.read().unwrap().len());
+  assert_eq!(10, reader.query().unwrap().n());
+  *buffer.write().unwrap() = complete;
+  assert_eq!(13, reader.query().unwrap().n());
+  assert_eq!(writer.root(), reader.root());
+}
+
+/// reload() によって他の LMTHT が追加したエントリを明示的に取り込めることを検証します。
+#[test]
+fn test_reload() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let writer = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  let options = LMTHTOptions { read_only: true, ..Default::default() };
+  let reader = LMTHT::with_options(MemStorage::with(buffer.clone()), options).unwrap();
+  assert!(!reader.reload().unwrap());
+  assert!(!writer.reload().unwrap());
+  for i in 1..=10 {
+    writer.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    assert!(!writer.reload().unwrap());
+    assert_eq!(i - 1, reader.n());
+    assert_eq!(i - 1, reader.query().unwrap().n());
+    assert!(reader.reload().unwrap());
+    assert!(!reader.reload().unwrap());
+    assert_eq!(writer.root(), reader.root());
+    assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), reader.query().unwrap().get(i).unwrap());
+  }
+
+  // ストレージが縮小した場合は破損として扱う
+  buffer.write().unwrap().truncate(10);
+  assert!(matches!(reader.reload(), Err(Detail::DamagedStorage(_))));
+}
+
+/// 作成済みのクエリーが refresh() によって後から追加されたエントリを参照できることを検証します。
+#[test]
+fn test_query_refresh() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let mut query = db.query().unwrap();
+  assert!(!query.refresh());
+  for i in 1..=10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    assert_eq!(i - 1, query.n());
+    assert_eq!(None, query.get(i).unwrap());
+    assert!(query.refresh());
+    assert!(!query.refresh());
+    assert_eq!(i, query.n());
+    assert_eq!(Some(random_payload(PAYLOAD_SIZE, i)), query.get(i).unwrap());
+    assert_eq!(db.root().unwrap(), query.get_with_hashes(i).unwrap().unwrap().root());
+  }
+}
+
+/// follow() が既存の値を返した後に追加された値を待機して返し、LMTHT の破棄で終了することを検証します。
+#[test]
+fn test_follow() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 1..=5 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let mut query = db.query().unwrap();
+  let follower = spawn(move || query.follow(3).map(|value| value.unwrap()).collect::<Vec<_>>());
+  for i in 6..=20 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  drop(db);
+  let expected = (3..=20).map(|i| Value::new(i, random_payload(PAYLOAD_SIZE, i))).collect::<Vec<_>>();
+  assert_eq!(expected, follower.join().unwrap());
+}
+
+/// 封印したストレージが以降の追加を拒否し、再オープンしても封印されたルートノードを報告することを検証します。
+#[test]
+fn test_seal() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 1..=10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  let root = db.seal().unwrap();
+  assert_eq!(db.root(), root);
+  assert!(db.is_sealed());
+  assert!(matches!(db.append(&[0u8]), Err(Detail::Sealed { n: 10 })));
+  assert!(matches!(db.seal(), Err(Detail::Sealed { n: 10 })));
+  let sealed = buffer.read().unwrap().clone();
+  drop(db);
+
+  // 再オープンしても封印レコードは破棄されず、値も参照できる
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  assert!(db.is_sealed());
+  assert_eq!(root, db.root());
+  assert_eq!(Some(random_payload(PAYLOAD_SIZE, 7)), db.query().unwrap().get(7).unwrap());
+  assert!(matches!(db.begin_batch(), Err(Detail::Sealed { n: 10 })));
+  assert_eq!(sealed, *buffer.read().unwrap());
+  drop(db);
+
+  // 読み込み専用の LMTHT は再読み込みで封印を検出する
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let writer = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  let reader = LMTHT::builder(MemStorage::with(buffer.clone())).read_only(true).open().unwrap();
+  writer.append(&[1u8]).unwrap();
+  assert!(!reader.is_sealed());
+  writer.seal().unwrap();
+  assert!(reader.reload().unwrap());
+  assert!(reader.is_sealed());
+  assert_eq!(writer.root(), reader.root());
+
+  // 空の LMTHT も封印できる
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  assert_eq!(None, db.seal().unwrap());
+  drop(db);
+  let db = LMTHT::new(MemStorage::with(buffer)).unwrap();
+  assert!(db.is_sealed());
+  assert_eq!(0, db.n());
+}
+
+/// 信頼できるルートハッシュを指定したオープンが、そのルートを含まないストレージを拒否することを検証します。
+#[test]
+fn test_trusted_root() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  let (mut roots, mut ends) = (vec![], vec![buffer.read().unwrap().len()]);
+  for i in 1..=20 {
+    roots.push(db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap());
+    ends.push(buffer.read().unwrap().len());
+  }
+  let mut query = db.query().unwrap();
+  for root in roots.iter() {
+    assert_eq!(Some(*root), query.get_root(root.i).unwrap());
+  }
+  assert_eq!(None, query.get_root(0).unwrap());
+  assert_eq!(None, query.get_root(21).unwrap());
+  drop(query);
+  drop(db);
+
+  // 最新の世代と過去の世代はいずれもストレージと一致する
+  for root in [roots[19], roots[6], roots[0]] {
+    let db = LMTHT::builder(MemStorage::with(buffer.clone())).trusted_root(root.i, root.hash).open().unwrap();
+    assert_eq!(20, db.n());
+  }
+
+  // 異なるルートハッシュやストレージより新しい世代は一致しない
+  let open = |n: Index, hash: Hash| LMTHT::builder(MemStorage::with(buffer.clone())).trusted_root(n, hash).open();
+  assert!(matches!(open(7, roots[7].hash), Err(Detail::TrustedRootMismatch { n: 7, current: 20 })));
+  assert!(matches!(open(21, roots[19].hash), Err(Detail::TrustedRootMismatch { n: 21, current: 20 })));
+
+  // 世代 7 より後の値のハッシュ値を改ざんすると、世代 7 のエントリが一致していても現在のルートと整合しない
+  let original = buffer.read().unwrap().clone();
+  {
+    let mut bytes = buffer.write().unwrap();
+    let (begin, end) = (ends[7], ends[8]);
+    bytes[end - 4 - 8 - 1] ^= 0xFF;
+    let sum = checksum(&bytes[begin..end - 8]);
+    bytes[end - 8..end].copy_from_slice(&sum.to_le_bytes());
+  }
+  assert!(matches!(open(7, roots[6].hash), Err(Detail::TrustedRootMismatch { n: 7, current: 20 })));
+  assert!(open(20, roots[19].hash).is_ok());
+  *buffer.write().unwrap() = original;
+
+  // ストレージを過去の世代に巻き戻すと検出される
+  let rollback = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 1..=10 {
+    rollback.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  *buffer.write().unwrap() = rollback.storage().buffer.read().unwrap().clone();
+  assert!(matches!(open(20, roots[19].hash), Err(Detail::TrustedRootMismatch { n: 20, current: 10 })));
+}
+
+/// 追加前に算出したサイズが実際にストレージが増加したサイズと一致することを検証します。
+#[test]
+fn test_estimate_size() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let length = || db.storage().buffer.read().unwrap().len() as u64;
+  assert_eq!(length(), db.estimate_total_size(0, PAYLOAD_SIZE));
+  for i in 1..=300 {
+    let payload = random_payload(i as usize % 100, i);
+    let expected = length() + db.estimate_append_size(payload.len());
+    db.append(&payload).unwrap();
+    assert_eq!(expected, length(), "i={}", i);
+  }
+
+  for n in [1, 2, 3, 4, 5, 64, 100, 255, 256, 257] {
+    let db = LMTHT::new(MemStorage::new()).unwrap();
+    for i in 1..=n {
+      db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    }
+    assert_eq!(db.storage().buffer.read().unwrap().len() as u64, db.estimate_total_size(n, PAYLOAD_SIZE), "n={}", n);
+  }
+  #[cfg(not(feature = "small_index"))]
+  assert_eq!(u64::MAX, db.estimate_total_size(Index::MAX, MAX_PAYLOAD_SIZE));
+}
+
+/// 要素数がインデックスの最大値に達した LMTHT への追加がストレージを変更せずに失敗することを検証します。
+#[test]
+fn test_capacity_exhausted() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  assert_eq!(Index::MAX, db.remaining_capacity());
+  db.append(&[0u8]).unwrap();
+  assert_eq!(Index::MAX - 1, db.remaining_capacity());
+
+  // 最大のインデックスを持つエントリのみのストレージを作成する
+  let mut buffer = Vec::new();
+  buffer.extend_from_slice(&STORAGE_IDENTIFIER);
+  buffer.push(STORAGE_VERSION | INDEX_WIDTH);
+  let position = buffer.len() as u64;
+  let meta = MetaInfo::new(Address::new(Index::MAX, 0, position), Hash::hash(&[0u8]));
+  let mut inodes = model::NthGenHashTree::new(Index::MAX).inodes();
+  inodes.reverse();
+  let inodes = inodes
+    .iter()
+    .map(|n| {
+      let meta = MetaInfo::new(Address::new(n.node.i, n.node.j, position), meta.hash);
+      INode::new(meta, Address::new(n.left.i, n.left.j, 0), Address::new(n.right.i, n.right.j, position))
+    })
+    .collect();
+  let entry = Entry { enode: ENode { meta, payload: vec![0u8] }, inodes };
+  write_entry(&mut buffer, &entry).unwrap();
+  let buffer = Arc::new(RwLock::new(buffer));
+
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  assert_eq!(Index::MAX, db.n());
+  assert_eq!(0, db.remaining_capacity());
+  let before = buffer.read().unwrap().clone();
+  assert!(matches!(db.append(&[1u8]), Err(Detail::CapacityExhausted { n: Index::MAX })));
+  let root = db.root();
+  let mut batch = db.begin_batch().unwrap();
+  assert!(matches!(batch.append(&[1u8]), Err(Detail::CapacityExhausted { .. })));
+  assert_eq!(root, batch.commit().unwrap());
+  assert_eq!(before, *buffer.read().unwrap());
+}
+
+/// ヘッダに記録されたインデックスのビット幅がこのビルドと異なるストレージのオープンが失敗することを検証します。
+#[test]
+fn test_index_width() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  db.append(&[0u8]).unwrap();
+  drop(db);
+  assert_eq!(INDEX_WIDTH, buffer.read().unwrap()[3] & INDEX_WIDTH_MASK);
+  assert_eq!(INDEX_SIZE, index_size_of(buffer.read().unwrap()[3]));
+
+  for (width, actual) in [(0x00u8, 64u8), (0x40, 32), (0x80, 128), (0xC0, 0)] {
+    buffer.write().unwrap()[3] = STORAGE_VERSION | width;
+    let result = LMTHT::new(MemStorage::with(buffer.clone()));
+    if width == INDEX_WIDTH {
+      assert_eq!(1, result.unwrap().n());
+    } else {
+      match result {
+        Err(Detail::IncompatibleIndexSize { expected, actual: a }) => {
+          assert_eq!(INDEX_SIZE, expected);
+          assert_eq!(actual, a);
+        }
+        _ => panic!("storage with {}-bit indices must not be opened", actual),
+      }
+    }
+  }
+
+  // インデックス幅が一致していても新しいバージョンは拒否する
+  buffer.write().unwrap()[3] = (STORAGE_VERSION + 1) | INDEX_WIDTH;
+  assert!(matches!(LMTHT::new(MemStorage::with(buffer)), Err(Detail::IncompatibleVersion(0, 2))));
+}
+
+/// 異なるインデックスのビット幅のビルドが書き込んだストレージを、このビルドでオープンできないことを検証します。
+#[test]
+fn test_index_width_across_builds() {
+  // `small_index`、デフォルト、`large_index` の各ビルドで 16 バイトの値を 3 個追加したストレージ
+  let storages = [
+    (32u8, &include_bytes!("index32.db")[..]),
+    (64, &include_bytes!("index64.db")[..]),
+    (128, &include_bytes!("index128.db")[..]),
+  ];
+  for (size, bytes) in storages.iter() {
+    let buffer = Arc::new(RwLock::new(bytes.to_vec()));
+    let result = LMTHT::new(MemStorage::with(buffer.clone()));
+    if *size != INDEX_SIZE {
+      match result {
+        Err(Detail::IncompatibleIndexSize { expected, actual }) => {
+          assert_eq!(INDEX_SIZE, expected);
+          assert_eq!(*size, actual);
+        }
+        _ => panic!("storage written with {}-bit indices must not be opened", size),
+      }
+      assert_eq!(*bytes, &buffer.read().unwrap()[..]);
+    } else if cfg!(feature = "sha256") {
+      // 同じビット幅であれば参照できる (ストレージはデフォルトの SHA-256 で作成している)
+      let db = result.unwrap();
+      assert_eq!(3, db.n());
+      let mut query = db.query().unwrap();
+      for i in 1..=3 {
+        assert_eq!(Some(random_payload(16, i)), query.get(i).unwrap());
+      }
+    }
+  }
+}
+
+/// 直列化した証明から同じルートハッシュが算出でき、不正な直列化形式が拒否されることを検証します。
+#[test]
+fn test_proof_serialization() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  for i in 1..=10u8 {
+    db.append(&vec![i; i as usize]).unwrap();
+  }
+  let root = db.root().unwrap();
+  let mut query = db.query().unwrap();
+  for (i, j) in [(1, 0), (4, 2), (8, 1), (10, 0)] {
+    let proof = query.get_values_with_hashes(i, j).unwrap().unwrap();
+    let bytes = proof.to_bytes();
+    let restored = ValuesWithBranches::from_bytes(&bytes).unwrap();
+    assert_eq!(proof.values, restored.values);
+    assert_eq!(proof.branches, restored.branches);
+    assert_eq!(root, restored.root());
+
+    let invalid = core_io::ErrorKind::InvalidData;
+    assert_eq!(invalid, ValuesWithBranches::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err().kind);
+    assert_eq!(invalid, ValuesWithBranches::from_bytes(&[bytes.clone(), vec![0]].concat()).unwrap_err().kind);
+  }
+  let empty = [0u8; 8];
+  assert_eq!(core_io::ErrorKind::InvalidData, ValuesWithBranches::from_bytes(&empty).unwrap_err().kind);
+}
+
+/// 閾値を指定した `MemStorage` が閾値を超えた時点で一時ファイルに退避し、退避の前後に追加した値を参照できることを
+/// 検証します。
+#[test]
+fn test_mem_storage_spill() {
+  let db = LMTHT::new(MemStorage::with_spill_threshold(1024)).unwrap();
+  db.append(&[0u8; 16]).unwrap();
+  assert!(!db.storage().is_spilled());
+  for i in 1..100u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  assert!(db.storage().is_spilled());
+  let mut query = db.query().unwrap();
+  for i in 0..100u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index + 1).unwrap());
+  }
+  assert!(!MemStorage::new().is_spilled());
+}
+
+/// `TempStorage` が固有の一時ファイルを使用し、破棄された時点でそのファイルを削除することを検証します。
+#[test]
+fn test_temp_storage() {
+  let (a, b) = (TempStorage::new().unwrap(), TempStorage::new().unwrap());
+  assert_ne!(a.path(), b.path());
+  let path = a.path().to_path_buf();
+  let db = LMTHT::new(a).unwrap();
+  for i in 1..=10u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  db.sync().unwrap();
+  assert!(path.metadata().unwrap().len() > 0);
+  let mut query = db.query().unwrap();
+  for i in 1..=10u8 {
+    assert_eq!(Some(vec![i; 16]), query.get(i as Index).unwrap());
+  }
+  drop(query);
+  drop(db);
+  assert!(!path.exists());
+}
+
+/// カーソルの `len()` が位置を変更せずにストレージの長さを返し、ストレージがその機能を報告することを検証します。
+#[test]
+fn test_cursor_len_and_capabilities() {
+  let file = temp_file("cursor-len", ".db");
+  let storages: Vec<Box<dyn DynStorage + Send + Sync>> =
+    vec![Box::new(MemStorage::new()), Box::new(FileStorage::new(&file)), Box::new(TempStorage::new().unwrap())];
+  for storage in storages {
+    let mut cursor = storage.open_dyn(true).unwrap();
+    assert_eq!(0, cursor.len().unwrap());
+    cursor.write_all(&[1u8; 100]).unwrap();
+    cursor.seek(SeekFrom::Start(10)).unwrap();
+    assert_eq!(100, cursor.len().unwrap());
+    assert_eq!(10, cursor.stream_position().unwrap());
+    cursor.set_len(50).unwrap();
+    assert_eq!(50, cursor.len().unwrap());
+    assert!(storage.capabilities_dyn().writable && storage.capabilities_dyn().truncatable);
+  }
+  assert!(!MemStorage::new().capabilities().durable);
+  assert_eq!(Capabilities::ALL, FileStorage::new(&file).capabilities());
+  let _ = remove_file(&file);
+
+  // 書き込みバッファの内容を含めた長さを返す
+  let mut cursor = BufferedCursor::new(MemStorage::new().open(true).unwrap(), 16, 1024);
+  cursor.write_all(&[1u8; 100]).unwrap();
+  assert_eq!(100, cursor.len().unwrap());
+  assert_eq!(100, cursor.stream_position().unwrap());
+}
+
+/// 書き込み中の FileStorage のファイルを他のストレージが読み込み、名前を変更して置き換えられることを検証します。
+#[test]
+fn test_file_storage_sharing() {
+  let file = temp_file("lmtht-sharing", ".db");
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  for i in 1..=5u8 {
+    db.append(&[i; 16]).unwrap();
+  }
+  db.sync().unwrap();
+
+  // 書き込みのロックは他のストレージの読み込みを妨げない
+  let options = LMTHTOptions { read_only: true, ..Default::default() };
+  let reader = LMTHT::with_options(file.clone(), options).unwrap();
+  assert_eq!(Some(vec![3; 16]), reader.query().unwrap().get(3).unwrap());
+  drop(reader);
+
+  // 開いているファイルをバックアップのために移動し、別のファイルで置き換えることができる
+  let backup = temp_file("lmtht-sharing-backup", ".db");
+  replace_file(&file, &backup).unwrap();
+  std::fs::write(&file, b"").unwrap();
+  let replacement = temp_file("lmtht-sharing-replacement", ".db");
+  std::fs::copy(&backup, &replacement).unwrap();
+  replace_file(&replacement, &file).unwrap();
+  assert!(!replacement.exists());
+  assert_eq!(backup.metadata().unwrap().len(), file.metadata().unwrap().len());
+  drop(db);
+
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  assert_eq!(Some(vec![5; 16]), db.query().unwrap().get(5).unwrap());
+  drop(db);
+  remove_file(&file).unwrap();
+  remove_file(&backup).unwrap();
+}
+
+/// `prune_payloads()` が値のみを削除し、ルートハッシュや残した値の証明が変化しないことを検証します。
+#[test]
+fn test_prune_payloads() {
+  let file = temp_file("lmtht-prune", ".db");
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  for i in 1..=20u8 {
+    // 0 のみで構成された値は削除後も参照できる
+    let value = if i == 3 { vec![0u8; 32] } else { random_payload(256, i as Index) };
+    db.append(&value).unwrap();
+  }
+  let root = db.root().unwrap();
+  let length = file.metadata().unwrap().len();
+  let mut before = db.query().unwrap();
+  assert_eq!(Some(random_payload(256, 5)), before.get(5).unwrap());
+
+  let pruned = db.prune_payloads(10).unwrap();
+  assert_eq!(9 * 256, pruned);
+  assert_eq!(0, db.prune_payloads(10).unwrap());
+  assert_eq!(length, file.metadata().unwrap().len());
+  assert_eq!(Some(root), db.root());
+
+  let mut query = db.query().unwrap();
+  assert!(matches!(query.get(5), Err(Detail::PayloadPruned { i: 5 })));
+  assert!(matches!(query.get_with_hashes(10), Err(Detail::PayloadPruned { i: 10 })));
+  assert_eq!(Some(vec![0u8; 32]), query.get(3).unwrap());
+  assert_eq!(Some(random_payload(256, 11)), query.get(11).unwrap());
+  assert_eq!(root.hash, query.get_with_hashes(11).unwrap().unwrap().root().hash);
+  drop(query);
+
+  // 実行前に取得したクエリーもバッファに残っている削除前の値を返さない
+  assert!(matches!(before.get(5), Err(Detail::PayloadPruned { i: 5 })));
+  drop(before);
+
+  // 封印したストレージの値は削除できない
+  db.seal().unwrap();
+  assert!(matches!(db.prune_payloads(20), Err(Detail::Sealed { n: 20 })));
+  drop(db);
+
+  // 削除したエントリのチェックサムは再計算されている
+  let db = LMTHT::new(FileStorage::new(&file)).unwrap();
+  assert_eq!(Some(root), db.root());
+  let mut query = db.query().unwrap();
+  for i in 1..=20 {
+    assert!(query.get_root(i).unwrap().is_some());
+  }
+  assert!(matches!(query.get(1), Err(Detail::PayloadPruned { i: 1 })));
+  drop(query);
+  drop(db);
+  remove_file(&file).unwrap();
+}
+
+/// 許可された LMTHT のみが過去の世代まで切り詰めることができ、切り詰めた履歴がヘッダに記録されることを検証します。
+#[test]
+fn test_truncate_to() {
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  for i in 0..10 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  assert!(matches!(db.truncate_to(5), Err(Detail::TruncateNotAllowed)));
+  let mut batch = db.begin_batch().unwrap();
+  for i in 10..20 {
+    batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  batch.commit().unwrap();
+  let mut query = db.query().unwrap();
+  let roots = (0..=20).map(|n| query.get_root(n).unwrap()).collect::<Vec<_>>();
+  drop(query);
+  drop(db);
+
+  let db = LMTHT::builder(MemStorage::with(buffer.clone())).allow_truncate(true).open().unwrap();
+  assert!(!db.is_rewritten().unwrap());
+  assert!(matches!(db.truncate_to(21), Err(Detail::GenerationNotAppended { n: 21, current: 20 })));
+  assert_eq!(roots[20], db.truncate_to(20).unwrap());
+  assert!(!db.is_rewritten().unwrap());
+
+  // バッチの途中の世代まで切り詰めて、別の値を追加する
+  assert_eq!(roots[15], db.truncate_to(15).unwrap());
+  assert!(db.is_rewritten().unwrap());
+  assert_eq!((15, roots[15]), (db.n(), db.root()));
+  assert!(db.query().unwrap().get(16).unwrap().is_none());
+  db.append(b"corrected").unwrap();
+  let root = db.root();
+  drop(db);
+  let db = LMTHT::new(MemStorage::with(buffer.clone())).unwrap();
+  assert_eq!((16, root), (db.n(), db.root()));
+  assert!(db.is_rewritten().unwrap());
+  assert_eq!(b"corrected".to_vec(), db.query().unwrap().get(16).unwrap().unwrap());
+  assert_eq!(roots[15], db.query().unwrap().get_root(15).unwrap());
+  drop(db);
+
+  let db = LMTHT::builder(MemStorage::with(buffer.clone())).allow_truncate(true).open().unwrap();
+  assert_eq!(None, db.truncate_to(0).unwrap());
+  assert_eq!(4, buffer.read().unwrap().len());
+}
+
+/// 範囲の値を取得してルートハッシュを検証し、一致しないルートハッシュや範囲外の指定がエラーとなることを検証します。
+#[test]
+fn test_verify_range() {
+  use crate::error::Detail;
+
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let payloads = (1..=37).map(|s| random_payload(PAYLOAD_SIZE, s)).collect::<Vec<_>>();
+  for payload in payloads.iter() {
+    db.append(payload).unwrap();
+  }
+  let root = db.root_hash().unwrap();
+  let mut query = db.query().unwrap();
+  for (i0, i1) in [(1, 1), (1, 37), (5, 5), (3, 20), (16, 17), (9, 37), (37, 37)] {
+    let values = query.verify_range(i0..=i1, &root).unwrap();
+    assert_eq!((i1 - i0 + 1) as usize, values.len());
+    for (value, i) in values.iter().zip(i0..=i1) {
+      assert_eq!(i, value.i);
+      assert_eq!(payloads[i as usize - 1], value.value);
+    }
+  }
+
+  let other = Hash::hash(b"other");
+  assert!(matches!(query.verify_range(3..=20, &other), Err(Detail::RangeVerificationFailed { i0: 3, i1: 20 })));
+  assert!(matches!(query.verify_range(0..=3, &root), Err(Detail::InvalidRange { .. })));
+  assert!(matches!(query.verify_range(30..=38, &root), Err(Detail::InvalidRange { .. })));
+
+  // 過去の世代のルートハッシュはその世代のクエリでのみ検証できる
+  let old = query.get_root(20).unwrap().unwrap().hash;
+  assert!(query.verify_range(1..=20, &old).is_err());
+}
+
 #[test]
 fn test_memory_storage() {
   verify_storage_spec(&MemStorage::new()).expect("LMTHT compliance test filed");
 }
 
 /// 指定されたストレージが仕様に準拠していることを検証します。
-pub fn verify_storage_spec(storage: &dyn Storage) -> Result<()> {
+pub fn verify_storage_spec<S: Storage>(storage: &S) -> Result<()> {
   // 読み込み専用または書き込み用に (同時に) オープンできることを確認
   let mut writer = storage.open(true).expect("failed to open cursor as writable");
   let mut reader1 = storage.open(false).expect("failed to open cursor as read-only #1");
@@ -352,6 +1378,61 @@ pub fn verify_storage_spec(storage: &dyn Storage) -> Result<()> {
   Ok(())
 }
 
+/// 下位のカーソルに対する書き込みと同期の回数を記録するストレージです。
+pub struct CountingStorage {
+  pub storage: MemStorage,
+  pub writes: Arc<AtomicUsize>,
+  pub syncs: Arc<AtomicUsize>,
+}
+
+impl Default for CountingStorage {
+  fn default() -> Self {
+    CountingStorage { storage: MemStorage::new(), writes: Arc::default(), syncs: Arc::default() }
+  }
+}
+
+impl Storage for CountingStorage {
+  type Cursor = CountingCursor;
+  fn open(&self, writable: bool) -> Result<CountingCursor> {
+    Ok(CountingCursor { cursor: self.storage.open(writable)?, writes: self.writes.clone(), syncs: self.syncs.clone() })
+  }
+}
+
+pub struct CountingCursor {
+  cursor: MemCursor,
+  writes: Arc<AtomicUsize>,
+  syncs: Arc<AtomicUsize>,
+}
+
+impl Cursor for CountingCursor {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.syncs.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+  }
+}
+
+impl io::Read for CountingCursor {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.cursor.read(buf)
+  }
+}
+
+impl io::Write for CountingCursor {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.writes.fetch_add(1, Ordering::SeqCst);
+    self.cursor.write(buf)
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    self.cursor.flush()
+  }
+}
+
+impl io::Seek for CountingCursor {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    self.cursor.seek(pos)
+  }
+}
+
 /// 指定された接頭辞と接尾辞を持つ 0 バイトのテンポラリファイルをシステムのテンポラリディレクトリ上に作成します。
 /// 作成したファイルは呼び出し側で削除する必要があります。
 pub fn temp_file(prefix: &str, suffix: &str) -> PathBuf {
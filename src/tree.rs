@@ -0,0 +1,3618 @@
+//! ストレージに直列化したハッシュ木を読み書きする LMTHT 本体と、その保存先となるストレージの実装です。
+//!
+//! このモジュールは `std` feature が有効な場合にのみコンパイルされ、ファイルやスレッド、`std::io` を前提とする
+//! すべての項目を含みます。公開している項目はクレートのルートから参照します。
+//!
+use std::cmp::min;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::*;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut, RangeInclusive};
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, LockResult, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use highway::{HighwayBuilder, Key};
+
+use crate::archive::ArchiveCatalog;
+use crate::bloom::BloomFilter;
+use crate::checksum::HashRead;
+use crate::durability::{Durability, GroupCommit};
+use crate::error::Detail;
+use crate::error::Detail::*;
+use crate::hash_index::HashIndex;
+use crate::index::PositionIndex;
+use crate::key_index::KeyIndex;
+use crate::lru::Lru;
+use crate::metrics::{observe, CacheKind, MetricsSink, NoMetrics, OperationKind, SlowOperation};
+use crate::model::{range, NthGenHashTree, Path as ModelPath};
+use crate::record::header_length;
+use crate::retry::RetryPolicy;
+use crate::subscription::Subscribers;
+use crate::watermark::WatermarkStore;
+use crate::{Hash, Index, Node, Value, ValuesWithBranches, HASH_SIZE, INDEX_BYTES, INDEX_SIZE};
+
+pub use crate::appender::{Appender, Receipt, DEFAULT_APPENDER_BATCH_SIZE};
+pub use crate::batch::{Batch, Prepared};
+pub use crate::buffer::BufferedCursor;
+pub use crate::builder::LMTHTBuilder;
+pub use crate::conformance::self_test;
+pub use crate::hash_index::{AppendOutcome, DuplicatePolicy};
+pub use crate::proof::verify_batch;
+pub use crate::replica::{compare_roots, ReplicaComparison};
+pub use crate::subscription::Appended;
+
+/// lmtht クレートで使用する標準 Result。[`error::Detail`](crate::error::Detail) も参照。
+pub type Result<T> = std::result::Result<T, Detail>;
+
+/// ハッシュ木を保存する抽象化されたストレージです。read 用または read + write 用のカーソル参照を実装することで
+/// 任意のデバイスに直列化することができます。
+///
+/// カーソルの型は関連型として定義されるため、ストレージからの読み込みは動的ディスパッチを経由せずに行われます。
+/// ストレージをトレイトオブジェクトとして扱う必要がある場合は [`DynStorage`] を使用してください。
+pub trait Storage {
+  /// このストレージが使用するカーソルの型です。
+  type Cursor: Cursor + 'static;
+
+  /// このストレージに対する read または read + write 用のカーソルを作成します。
+  fn open(&self, writable: bool) -> Result<Self::Cursor>;
+
+  /// このストレージが提供する機能を参照します。デフォルトの実装は書き込みのみが可能で、切り詰めや永続化を行わない
+  /// ストレージとして [`Capabilities::default()`] を返します。
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::default()
+  }
+}
+
+/// [`Storage`] が提供する機能です。耐久性の方針や障害からの回復処理がストレージの種類によらず同じ判断を行うために
+/// 使用します。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+  /// 書き込み用のカーソルをオープンできるか。
+  pub writable: bool,
+  /// [`Cursor::set_len()`] でストレージを切り詰めることができるか。
+  pub truncatable: bool,
+  /// [`Cursor::sync_data()`] によって書き込んだ内容が電源断などの障害を越えて保持されるか。
+  pub durable: bool,
+}
+
+impl Default for Capabilities {
+  fn default() -> Self {
+    Capabilities { writable: true, truncatable: false, durable: false }
+  }
+}
+
+impl Capabilities {
+  /// 読み込み、書き込み、切り詰め、永続化のすべてが可能なストレージの機能です。
+  pub const ALL: Capabilities = Capabilities { writable: true, truncatable: true, durable: true };
+
+  /// 読み込みのみが可能なストレージの機能です。
+  pub const READ_ONLY: Capabilities = Capabilities { writable: false, truncatable: false, durable: false };
+}
+
+/// [`Storage`] をトレイトオブジェクトとして扱うためのアダプタです。すべての [`Storage`] 実装はこのトレイトを実装
+/// しており、カーソルは `Box<dyn Cursor>` として返されます。また `dyn DynStorage` 自体も [`Storage`] として使用する
+/// ことができます。
+pub trait DynStorage {
+  /// このストレージに対する read または read + write 用のカーソルを作成します。
+  fn open_dyn(&self, writable: bool) -> Result<Box<dyn Cursor>>;
+
+  /// このストレージが提供する機能を参照します。
+  fn capabilities_dyn(&self) -> Capabilities;
+}
+
+impl<S: Storage> DynStorage for S {
+  fn open_dyn(&self, writable: bool) -> Result<Box<dyn Cursor>> {
+    Ok(Box::new(self.open(writable)?))
+  }
+
+  fn capabilities_dyn(&self) -> Capabilities {
+    self.capabilities()
+  }
+}
+
+impl Storage for dyn DynStorage + Send + Sync {
+  type Cursor = Box<dyn Cursor>;
+  fn open(&self, writable: bool) -> Result<Box<dyn Cursor>> {
+    self.open_dyn(writable)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.capabilities_dyn()
+  }
+}
+
+/// ローカルファイルシステムのパスをストレージとして使用する実装です。
+///
+/// 書き込み用のオープンは [`FileStorage`] と同じ排他的なアドバイザリロックをオープンしたファイルに対して獲得し、他の
+/// プロセスや別のオープンがすでにロックを保持している場合は [`Detail::StorageLocked`] で失敗します。ロックはその
+/// ファイルと [`Cursor::duplicate()`] で複製したカーソルがすべて閉じられた時点で解放されます。LMTHT は書き込み用に
+/// オープンしたカーソルを保持し、以降の書き込みにはその複製を使用するため、LMTHT が破棄されるまでロックを保持します。
+impl<P: AsRef<Path>> Storage for P {
+  type Cursor = File;
+  fn open(&self, writable: bool) -> Result<File> {
+    let name = || self.as_ref().to_str().map(|s| s.to_string()).unwrap_or(self.as_ref().to_string_lossy().to_string());
+    let file = OpenOptions::new().read(true).write(writable).create(writable).open(self);
+    let file = match file {
+      Ok(file) => file,
+      Err(err) => return Err(Detail::FailedToOpenLocalFile { file: name(), message: err.to_string() }),
+    };
+    #[cfg(any(unix, windows))]
+    if writable {
+      match lock_exclusive(&file) {
+        Ok(true) => (),
+        Ok(false) => return Err(Detail::StorageLocked { file: name() }),
+        Err(err) => return Err(Detail::FailedToOpenLocalFile { file: name(), message: err.to_string() }),
+      }
+    }
+    Ok(file)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::ALL
+  }
+}
+
+/// ローカルファイルを一つのファイル記述子で共有するストレージです。
+///
+/// パスを [`Storage`] として使用した場合は [`Storage::open()`] のたびにファイルをオープンしますが、この実装は最初に
+/// オープンしたファイルを共有し、それぞれのカーソルは自身の位置を保持して位置指定の読み書き (`pread`/`pwrite` または
+/// `seek_read`/`seek_write`) を行います。このため多数の [`Query`] がシークによって互いに干渉することなく同時に読み
+/// 出すことができ、ファイル記述子の数が制限された環境でもクエリーの数に比例して記述子を消費しません。
+///
+/// 書き込み用のカーソルを最初にオープンした時点でファイルの排他的なアドバイザリロック (`flock`) を獲得し、ストレージ
+/// が破棄されるまで保持します。他のプロセスまたは別の `FileStorage` がすでにロックを保持している場合、オープンは
+/// [`Detail::StorageLocked`] で失敗します。これにより複数の書き込みが同じファイルの末尾に交互に追記してハッシュ木を
+/// 破損することを防ぎます。Windows 環境ではファイル末尾よりはるか後方の 1 バイトを `LockFileEx` でロックするため、
+/// 他のプロセスが読み込み用にファイルを開いて参照することは妨げられません。
+///
+/// Windows 環境ではすべての共有モード (`FILE_SHARE_READ`/`FILE_SHARE_WRITE`/`FILE_SHARE_DELETE`) を指定してファイル
+/// を開くため、書き込み中のファイルを他のプロセスが読み込んだり、バックアップやエクスポートのために名前を変更したり
+/// 削除することができます。
+///
+/// 位置指定の読み書きを行うため Unix と Windows 以外の環境 (`wasm32-unknown-unknown` など) では利用できません。
+#[cfg(any(unix, windows))]
+pub struct FileStorage {
+  path: PathBuf,
+  options: FileStorageOptions,
+  file: Mutex<Option<Arc<SharedFile>>>,
+}
+
+#[cfg(any(unix, windows))]
+impl FileStorage {
+  /// 指定されたパスのファイルを使用するストレージを構築します。ファイルは最初のカーソルをオープンした時点で
+  /// read + write 用にオープンされ、存在しない場合は作成されます。
+  pub fn new<P: AsRef<Path>>(path: P) -> FileStorage {
+    Self::with_options(path, FileStorageOptions::default())
+  }
+
+  /// 指定されたオプションでパスのファイルを使用するストレージを構築します。
+  pub fn with_options<P: AsRef<Path>>(path: P, options: FileStorageOptions) -> FileStorage {
+    FileStorage { path: path.as_ref().to_path_buf(), options, file: Mutex::new(None) }
+  }
+
+  /// このストレージが使用しているファイルのパスを参照します。
+  pub fn path(&self) -> &Path {
+    self.path.as_path()
+  }
+}
+
+#[cfg(any(unix, windows))]
+impl Storage for FileStorage {
+  type Cursor = FileCursor;
+  fn open(&self, writable: bool) -> Result<FileCursor> {
+    let mut file = lock2io(self.file.lock())?;
+    if file.is_none() {
+      let mut options = OpenOptions::new();
+      options.read(true).write(true).create(true).truncate(false);
+      #[cfg(windows)]
+      {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.share_mode(win32::FILE_SHARE_READ | win32::FILE_SHARE_WRITE | win32::FILE_SHARE_DELETE);
+      }
+      let shared = options.open(&self.path).and_then(|f| SharedFile::new(f, &self.options));
+      match shared {
+        Ok(shared) => *file = Some(Arc::new(shared)),
+        Err(err) => {
+          return Err(Detail::FailedToOpenLocalFile {
+            file: self.path.to_string_lossy().to_string(),
+            message: err.to_string(),
+          })
+        }
+      }
+    }
+    let file = file.as_ref().unwrap().clone();
+    if writable && !file.locked.load(Ordering::Acquire) {
+      match lock_exclusive(&file.file) {
+        Ok(true) => file.locked.store(true, Ordering::Release),
+        Ok(false) => return Err(Detail::StorageLocked { file: self.path.to_string_lossy().to_string() }),
+        Err(err) => {
+          return Err(Detail::FailedToOpenLocalFile {
+            file: self.path.to_string_lossy().to_string(),
+            message: err.to_string(),
+          })
+        }
+      }
+    }
+    Ok(FileCursor {
+      writable,
+      position: 0,
+      file,
+      last_read_end: None,
+      sequential: 0,
+      advised: 0,
+      prefetched: Vec::new(),
+      prefetch_start: 0,
+    })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::ALL
+  }
+}
+
+/// [`FileStorage`] の動作を調整するためのオプションです。
+#[cfg(any(unix, windows))]
+#[derive(Clone, Debug)]
+pub struct FileStorageOptions {
+  /// 書き込みに先立ってファイルの領域を確保する単位のバイトサイズです。書き込みが確保済みの領域を超える時点で
+  /// この単位に切り上げた領域をまとめて確保し、追記を続けるファイルの断片化とメタデータの更新を抑制します。
+  /// 領域の確保はファイルサイズを変更しない `fallocate(FALLOC_FL_KEEP_SIZE)` で行うため Linux 以外の環境や
+  /// 対応していないファイルシステムでは何も行いません。0 を指定した場合は事前に確保しません。デフォルトは
+  /// [`DEFAULT_PREALLOCATION_SIZE`] です。
+  pub preallocation_size: u64,
+  /// カーソルが連続した位置の読み込みを検出したときに行う先読みの方法です。[`LMTHT`] のすべての値の抽出や検証の
+  /// ようにエントリを先頭から順に読み出す処理で、エントリ単位の小さな読み込みが繰り返されることを抑制します。
+  /// デフォルトは [`Readahead::Disabled`] です。
+  pub readahead: Readahead,
+}
+
+/// [`FileStorageOptions::preallocation_size`] のデフォルト値です。
+#[cfg(any(unix, windows))]
+pub const DEFAULT_PREALLOCATION_SIZE: u64 = 0;
+
+#[cfg(any(unix, windows))]
+impl Default for FileStorageOptions {
+  fn default() -> Self {
+    FileStorageOptions { preallocation_size: DEFAULT_PREALLOCATION_SIZE, readahead: Readahead::Disabled }
+  }
+}
+
+/// [`FileStorage`] のカーソルが連続した読み込みを検出したときに行う先読みの方法です。
+///
+/// カーソルは直前の読み込みの終端から `window` バイト以内の前方への読み込みが [`SEQUENTIAL_READ_THRESHOLD`] 回
+/// 続いた時点で連続した読み込みとみなし、後方へのシークや離れた位置の読み込みが発生した時点で解除します。
+#[cfg(any(unix, windows))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Readahead {
+  /// 先読みを行いません。
+  Disabled,
+  /// 読み込み位置から `window` バイトの範囲を近く読み込む予定であることを OS に通知します
+  /// (`posix_fadvise(POSIX_FADV_WILLNEED)`)。Linux 以外の環境では何も行いません。
+  Advise { window: u64 },
+  /// 読み込み位置から `window` バイトをカーソル内のバッファに明示的に読み込み、以降の読み込みをバッファから
+  /// 返します。
+  Prefetch { window: u64 },
+}
+
+/// 連続した読み込みとみなすために必要な、前方への読み込みが続いた回数です。
+#[cfg(any(unix, windows))]
+pub const SEQUENTIAL_READ_THRESHOLD: u32 = 3;
+
+/// [`FileStorage`] のカーソル間で共有されるファイルとその領域の確保状況です。
+#[cfg(any(unix, windows))]
+struct SharedFile {
+  file: File,
+  preallocation_size: u64,
+  readahead: Readahead,
+  /// 確保済みであることがわかっている領域の末尾。
+  allocated: AtomicU64,
+  /// 書き込みのための排他ロックを獲得済みか。
+  locked: AtomicBool,
+}
+
+#[cfg(any(unix, windows))]
+impl SharedFile {
+  fn new(file: File, options: &FileStorageOptions) -> io::Result<SharedFile> {
+    let allocated = AtomicU64::new(file.metadata()?.len());
+    let locked = AtomicBool::new(false);
+    let (preallocation_size, readahead) = (options.preallocation_size, options.readahead);
+    Ok(SharedFile { file, preallocation_size, readahead, allocated, locked })
+  }
+
+  /// 指定された位置から読み込みます。
+  fn read_at(&self, buf: &mut [u8], position: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    return std::os::unix::fs::FileExt::read_at(&self.file, buf, position);
+    #[cfg(windows)]
+    return std::os::windows::fs::FileExt::seek_read(&self.file, buf, position);
+  }
+
+  /// 指定された範囲を近く読み込む予定であることを OS に通知します。
+  #[allow(unused_variables)]
+  fn advise(&self, position: u64, length: u64) {
+    #[cfg(target_os = "linux")]
+    {
+      use std::os::unix::io::AsRawFd;
+      let fd = self.file.as_raw_fd();
+      // 通知は性能上のヒントに過ぎないため失敗しても無視する
+      let _ =
+        unsafe { libc::posix_fadvise(fd, position as libc::off_t, length as libc::off_t, libc::POSIX_FADV_WILLNEED) };
+    }
+  }
+
+  /// `end` までの書き込みに備えてファイルの領域を確保します。
+  fn preallocate(&self, end: u64) -> io::Result<()> {
+    let allocated = self.allocated.load(Ordering::Acquire);
+    if self.preallocation_size == 0 || end <= allocated {
+      return Ok(());
+    }
+    let length = end.div_ceil(self.preallocation_size).saturating_mul(self.preallocation_size);
+    #[cfg(target_os = "linux")]
+    {
+      use std::os::unix::io::AsRawFd;
+      let fd = self.file.as_raw_fd();
+      let result = unsafe {
+        libc::fallocate(fd, libc::FALLOC_FL_KEEP_SIZE, allocated as libc::off_t, (length - allocated) as libc::off_t)
+      };
+      if result != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+          return Err(err);
+        }
+        // 領域の確保に対応していないファイルシステムでは以降の確保を行わない
+        self.allocated.store(u64::MAX, Ordering::Release);
+        return Ok(());
+      }
+    }
+    self.allocated.fetch_max(length, Ordering::AcqRel);
+    Ok(())
+  }
+}
+
+/// [`FileStorage`] が使用する、共有されたファイルに対して位置指定の読み書きを行うカーソルです。
+#[cfg(any(unix, windows))]
+pub struct FileCursor {
+  writable: bool,
+  position: u64,
+  file: Arc<SharedFile>,
+  /// 直前の読み込みの終端。
+  last_read_end: Option<u64>,
+  /// 前方への読み込みが続いた回数。
+  sequential: u32,
+  /// OS に先読みを通知した範囲の終端。
+  advised: u64,
+  /// [`Readahead::Prefetch`] で先読みした内容と、その先頭の位置。
+  prefetched: Vec<u8>,
+  prefetch_start: u64,
+}
+
+#[cfg(any(unix, windows))]
+impl FileCursor {
+  /// 現在の位置からの読み込みが連続した読み込みの一部であるかを判定し、必要であれば先読みを行います。
+  fn readahead(&mut self, length: usize) -> io::Result<()> {
+    let window = match self.file.readahead {
+      Readahead::Disabled => return Ok(()),
+      Readahead::Advise { window } | Readahead::Prefetch { window } => window,
+    };
+    self.sequential = match self.last_read_end {
+      Some(end) if self.position >= end && self.position - end <= window => self.sequential.saturating_add(1),
+      _ => 0,
+    };
+    if self.sequential < SEQUENTIAL_READ_THRESHOLD {
+      return Ok(());
+    }
+    match self.file.readahead {
+      Readahead::Advise { .. } if self.position + window > self.advised => {
+        let start = self.position.max(self.advised);
+        self.file.advise(start, self.position + window - start);
+        self.advised = self.position + window;
+      }
+      Readahead::Prefetch { .. } if (length as u64) < window && !self.is_prefetched(self.position) => {
+        self.prefetched.resize(window as usize, 0u8);
+        let length = match self.file.read_at(&mut self.prefetched, self.position) {
+          Ok(length) => length,
+          Err(err) => {
+            self.prefetched.clear();
+            return Err(err);
+          }
+        };
+        self.prefetched.truncate(length);
+        self.prefetch_start = self.position;
+      }
+      _ => (),
+    }
+    Ok(())
+  }
+
+  /// 指定された位置が先読みしたバッファに含まれているかを判定します。
+  fn is_prefetched(&self, position: u64) -> bool {
+    position >= self.prefetch_start && position < self.prefetch_start + self.prefetched.len() as u64
+  }
+}
+
+#[cfg(any(unix, windows))]
+impl Cursor for FileCursor {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.file.file.sync_data()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    self.prefetched.clear();
+    self.file.file.set_len(length)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    Ok(self.file.file.metadata()?.len())
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    self.prefetched.clear();
+    if punch_hole(&self.file.file, position, length)? {
+      self.position = position + length;
+      Ok(())
+    } else {
+      fill_zeros(self, position, length)
+    }
+  }
+}
+
+#[cfg(any(unix, windows))]
+impl io::Seek for FileCursor {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    let position = match pos {
+      io::SeekFrom::Start(position) => Some(position),
+      io::SeekFrom::End(position) => checked_add_signed(self.file.file.metadata()?.len(), position),
+      io::SeekFrom::Current(position) => checked_add_signed(self.position, position),
+    };
+    match position {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+#[cfg(any(unix, windows))]
+impl io::Read for FileCursor {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.readahead(buf.len())?;
+    let length = if self.is_prefetched(self.position) {
+      let offset = (self.position - self.prefetch_start) as usize;
+      let length = buf.len().min(self.prefetched.len() - offset);
+      buf[..length].copy_from_slice(&self.prefetched[offset..offset + length]);
+      length
+    } else {
+      self.file.read_at(buf, self.position)?
+    };
+    self.position += length as u64;
+    self.last_read_end = Some(self.position);
+    Ok(length)
+  }
+}
+
+#[cfg(any(unix, windows))]
+impl io::Write for FileCursor {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    self.file.preallocate(self.position + buf.len() as u64)?;
+    self.prefetched.clear();
+    #[cfg(unix)]
+    let length = std::os::unix::fs::FileExt::write_at(&self.file.file, buf, self.position)?;
+    #[cfg(windows)]
+    let length = std::os::windows::fs::FileExt::seek_write(&self.file.file, buf, self.position)?;
+    self.position += length as u64;
+    Ok(length)
+  }
+
+  #[cfg(target_os = "linux")]
+  fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    // IoSlice は iovec と ABI 互換であるため連結せずに位置指定の pwritev で出力する
+    let bufs = &bufs[..bufs.len().min(libc::UIO_MAXIOV as usize)];
+    let total = bufs.iter().map(|buf| buf.len() as u64).sum::<u64>();
+    self.file.preallocate(self.position + total)?;
+    self.prefetched.clear();
+    let fd = self.file.file.as_raw_fd();
+    let iov = bufs.as_ptr() as *const libc::iovec;
+    let length = unsafe { libc::pwritev(fd, iov, bufs.len() as libc::c_int, self.position as libc::off_t) };
+    if length < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    self.position += length as u64;
+    Ok(length as usize)
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+    // 位置指定の writev を使用できない環境では連結して一度の書き込みで出力する
+    match bufs.iter().filter(|buf| !buf.is_empty()).count() {
+      0 => Ok(0),
+      1 => self.write(bufs.iter().find(|buf| !buf.is_empty()).unwrap()),
+      _ => self.write(&bufs.iter().flat_map(|buf| buf.iter().copied()).collect::<Vec<u8>>()),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// ファイルの排他的なロックの獲得を試みます。他のファイル記述子がロックを保持している場合は待機せずに false を
+/// 返します。ロックはファイルが閉じられた時点で解放されます。
+#[cfg(any(unix, windows))]
+fn lock_exclusive(file: &File) -> io::Result<bool> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::io::AsRawFd;
+    let fd = file.as_raw_fd();
+    if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+      let err = io::Error::last_os_error();
+      return if err.kind() == io::ErrorKind::WouldBlock { Ok(false) } else { Err(err) };
+    }
+  }
+  #[cfg(windows)]
+  {
+    use std::os::windows::io::AsRawHandle;
+    // Windows のバイト範囲ロックは強制ロックであるため、読み込みを妨げないようにファイルの内容が存在しえない位置を
+    // ロックする
+    let (low, high) = (win32::LOCK_OFFSET as u32, (win32::LOCK_OFFSET >> 32) as u32);
+    let mut overlapped = win32::Overlapped { offset: low, offset_high: high, ..Default::default() };
+    let flags = win32::LOCKFILE_EXCLUSIVE_LOCK | win32::LOCKFILE_FAIL_IMMEDIATELY;
+    let handle = file.as_raw_handle();
+    if unsafe { win32::LockFileEx(handle, flags, 0, 1, 0, &mut overlapped) } == 0 {
+      let err = io::Error::last_os_error();
+      return if err.raw_os_error() == Some(win32::ERROR_LOCK_VIOLATION) { Ok(false) } else { Err(err) };
+    }
+  }
+  Ok(true)
+}
+
+/// `base` に符号付きの `offset` を加算します。結果が負またはオーバーフローする場合は `None` を返します。
+#[cfg(any(unix, windows))]
+#[inline]
+fn checked_add_signed(base: u64, offset: i64) -> Option<u64> {
+  if offset >= 0 {
+    base.checked_add(offset as u64)
+  } else {
+    base.checked_sub(offset.unsigned_abs())
+  }
+}
+
+/// メモリ上の領域をストレージとして使用する実装です。`drop()` された時点で記録していた内容が消滅するためテストや
+/// 調査での使用を想定しています。
+///
+/// [`MemStorage::with_spill_threshold()`] で構築した場合、内容が閾値を超えた時点で一時ファイルに退避し、以降は
+/// そのファイルに対して読み書きを行います。想定より大きなデータを扱ったテストや短命なツールがメモリを使い果たすこと
+/// を防ぎます。一時ファイルはストレージとすべてのカーソルが破棄された時点で削除されます。
+pub struct MemStorage {
+  pub(crate) buffer: Arc<RwLock<Vec<u8>>>,
+  spill: Option<Arc<Spill>>,
+}
+
+impl MemStorage {
+  /// 揮発性メモリを使用するストレージを構築します。
+  pub fn new() -> MemStorage {
+    Self::with(Arc::new(RwLock::new(Vec::<u8>::with_capacity(4 * 1024))))
+  }
+
+  /// 指定されたアトミック参照カウント/RWロック付きの可変バッファを使用するストレージを構築します。これは調査の目的で
+  /// 外部からストレージの内容を参照することを想定しています。
+  pub fn with(buffer: Arc<RwLock<Vec<u8>>>) -> MemStorage {
+    MemStorage { buffer, spill: None }
+  }
+
+  /// 内容が `threshold` バイトを超えた時点で一時ファイルに退避するストレージを構築します。
+  pub fn with_spill_threshold(threshold: usize) -> MemStorage {
+    let spill = Spill { threshold, file: Mutex::new(None) };
+    MemStorage { spill: Some(Arc::new(spill)), ..Self::new() }
+  }
+
+  /// 内容が一時ファイルに退避されているかを判定します。
+  pub fn is_spilled(&self) -> bool {
+    self.spill.as_ref().map(|spill| spill.file.lock().map(|file| file.is_some()).unwrap_or(false)).unwrap_or(false)
+  }
+}
+
+impl Storage for MemStorage {
+  type Cursor = MemCursor;
+  fn open(&self, writable: bool) -> Result<MemCursor> {
+    Ok(MemCursor { writable, position: 0, buffer: self.buffer.clone(), spill: self.spill.clone() })
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities { writable: true, truncatable: true, durable: false }
+  }
+}
+
+/// [`MemStorage`] の内容を退避する一時ファイルです。
+struct Spill {
+  threshold: usize,
+  file: Mutex<Option<TempFile>>,
+}
+
+impl Spill {
+  /// 内容の退避先をロックします。退避しない設定の場合は `None` を返します。ロックを保持している間は他のカーソルに
+  /// よって内容が退避されることはありません。
+  fn lock(spill: &Option<Arc<Spill>>) -> io::Result<Option<MutexGuard<'_, Option<TempFile>>>> {
+    match spill {
+      Some(spill) => Ok(Some(lock2io(spill.file.lock())?)),
+      None => Ok(None),
+    }
+  }
+}
+
+/// [`MemStorage`] が使用するカーソルです。
+pub struct MemCursor {
+  writable: bool,
+  position: usize,
+  buffer: Arc<RwLock<Vec<u8>>>,
+  spill: Option<Arc<Spill>>,
+}
+
+impl Cursor for MemCursor {
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let mut spill = Spill::lock(&self.spill)?;
+    if let Some(TempFile { file, .. }) = spill.as_mut().and_then(|file| file.as_mut()) {
+      return file.set_len(length);
+    }
+    lock2io(self.buffer.write())?.resize(length as usize, 0u8);
+    Ok(())
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    let mut spill = Spill::lock(&self.spill)?;
+    if let Some(TempFile { file, .. }) = spill.as_mut().and_then(|file| file.as_mut()) {
+      return Ok(file.metadata()?.len());
+    }
+    Ok(lock2io(self.buffer.read())?.len() as u64)
+  }
+}
+
+impl io::Seek for MemCursor {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    self.position = match pos {
+      io::SeekFrom::Start(position) => position as usize,
+      io::SeekFrom::End(position) => {
+        let mut spill = Spill::lock(&self.spill)?;
+        if let Some(TempFile { file, .. }) = spill.as_mut().and_then(|file| file.as_mut()) {
+          (file.metadata()?.len() as i64 + position) as usize
+        } else {
+          let mut buffer = lock2io(self.buffer.write())?;
+          let new_position = (buffer.len() as i64 + position) as usize;
+          while buffer.len() < new_position {
+            buffer.push(0u8);
+          }
+          new_position
+        }
+      }
+      io::SeekFrom::Current(position) => (self.position as i64 + position) as usize,
+    };
+    Ok(self.position as u64)
+  }
+}
+
+impl io::Read for MemCursor {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let mut spill = Spill::lock(&self.spill)?;
+    if let Some(TempFile { file, .. }) = spill.as_mut().and_then(|file| file.as_mut()) {
+      file.seek(SeekFrom::Start(self.position as u64))?;
+      let length = file.read(buf)?;
+      drop(spill);
+      self.position += length;
+      return Ok(length);
+    }
+    let buffer = lock2io(self.buffer.read())?;
+    let length = min(buf.len(), buffer.len().saturating_sub(self.position));
+    (&mut buf[..]).write_all(&buffer[self.position..self.position + length])?;
+    drop((buffer, spill));
+    self.position += length;
+    Ok(length)
+  }
+}
+
+impl io::Write for MemCursor {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if !self.writable {
+      return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+    }
+    let end = self.position + buf.len();
+    let mut spill = Spill::lock(&self.spill)?;
+    if let Some(spilled) = spill.as_deref_mut() {
+      let threshold = self.spill.as_ref().unwrap().threshold;
+      if spilled.is_none() && end > threshold {
+        // 閾値を超える書き込みの前にメモリ上の内容を一時ファイルに移す
+        let mut temp = TempFile::create("lmtht-spill")?;
+        let mut buffer = lock2io(self.buffer.write())?;
+        temp.file.write_all(&buffer)?;
+        *buffer = Vec::new();
+        *spilled = Some(temp);
+      }
+      if let Some(TempFile { file, .. }) = spilled.as_mut() {
+        file.seek(SeekFrom::Start(self.position as u64))?;
+        file.write_all(buf)?;
+        self.position = end;
+        return Ok(buf.len());
+      }
+    }
+
+    // 現在の位置から上書きし、必要であれば末尾を拡張する
+    let mut buffer = lock2io(self.buffer.write())?;
+    if buffer.len() < end {
+      buffer.resize(end, 0u8);
+    }
+    buffer[self.position..end].copy_from_slice(buf);
+    self.position = end;
+    Ok(buf.len())
+  }
+
+  fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+    let mut length = 0;
+    for buf in bufs {
+      length += self.write(buf)?;
+    }
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// 一時ディレクトリに作成した固有のファイルを使用し、破棄された時点でそのファイルを削除するストレージです。
+///
+/// [`MemStorage`] と異なり実際のファイルに対してシークや同期が行われるため、ファイルを前提とした振る舞いを確認する
+/// 結合テストや一時的な計算で後片付けを記述することなく使用できます。
+///
+/// ```rust
+/// use lmtht::{LMTHT, TempStorage};
+/// let db = LMTHT::new(TempStorage::new().unwrap()).unwrap();
+/// db.append(b"hello").unwrap();
+/// ```
+pub struct TempStorage {
+  temp: TempFile,
+}
+
+impl TempStorage {
+  /// 一時ディレクトリに新しい空のファイルを作成し、それを使用するストレージを構築します。
+  pub fn new() -> Result<TempStorage> {
+    match TempFile::create("lmtht") {
+      Ok(temp) => Ok(TempStorage { temp }),
+      Err(err) => Err(Detail::FailedToOpenLocalFile {
+        file: std::env::temp_dir().to_string_lossy().to_string(),
+        message: err.to_string(),
+      }),
+    }
+  }
+
+  /// このストレージが使用している一時ファイルのパスを参照します。
+  pub fn path(&self) -> &Path {
+    self.temp.path.as_path()
+  }
+}
+
+impl Storage for TempStorage {
+  type Cursor = File;
+  fn open(&self, writable: bool) -> Result<File> {
+    self.temp.path.open(writable)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::ALL
+  }
+}
+
+/// 破棄された時点で削除される一時ファイルです。
+struct TempFile {
+  path: PathBuf,
+  file: File,
+}
+
+impl TempFile {
+  /// 一時ディレクトリに `prefix` で始まる名前の新しいファイルを作成し、読み書き用にオープンします。
+  fn create(prefix: &str) -> io::Result<TempFile> {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    let dir = std::env::temp_dir();
+    loop {
+      let n = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+      let path = dir.join(format!("{}-{}-{}", prefix, std::process::id(), n));
+      match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+        Ok(file) => return Ok(TempFile { path, file }),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+        Err(err) => return Err(err),
+      }
+    }
+  }
+}
+
+impl Drop for TempFile {
+  fn drop(&mut self) {
+    let _ = remove_file(&self.path);
+  }
+}
+
+/// `LockResult` を `io::Result` に変換します。
+#[inline]
+pub(crate) fn lock2io<T>(result: LockResult<T>) -> io::Result<T> {
+  result.map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
+}
+
+/// `from` の名前を `to` に変更し、`to` が存在する場合は置き換えます。Windows 環境では他のプロセスが削除の共有モード
+/// を指定せずに開いているファイル (ウイルス対策ソフトやインデクサなど) は名前の変更も削除もできないため、共有違反
+/// またはアクセス拒否で失敗した場合は短い間隔で再試行します。
+pub(crate) fn replace_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+  #[cfg(windows)]
+  {
+    let mut wait = std::time::Duration::from_millis(10);
+    for _ in 0..win32::SHARING_VIOLATION_RETRIES {
+      match std::fs::rename(from.as_ref(), to.as_ref()) {
+        Err(err)
+          if matches!(err.raw_os_error(), Some(win32::ERROR_SHARING_VIOLATION) | Some(win32::ERROR_ACCESS_DENIED)) =>
+        {
+          std::thread::sleep(wait);
+          wait *= 2;
+        }
+        result => return result,
+      }
+    }
+  }
+  std::fs::rename(from, to)
+}
+
+/// Windows のファイル共有とロックに使用する Win32 API の定義です。
+#[cfg(windows)]
+#[allow(non_snake_case)]
+mod win32 {
+  use std::os::raw::c_void;
+
+  pub const FILE_SHARE_READ: u32 = 0x0000_0001;
+  pub const FILE_SHARE_WRITE: u32 = 0x0000_0002;
+  pub const FILE_SHARE_DELETE: u32 = 0x0000_0004;
+  pub const LOCKFILE_FAIL_IMMEDIATELY: u32 = 0x0000_0001;
+  pub const LOCKFILE_EXCLUSIVE_LOCK: u32 = 0x0000_0002;
+  pub const ERROR_ACCESS_DENIED: i32 = 5;
+  pub const ERROR_SHARING_VIOLATION: i32 = 32;
+  pub const ERROR_LOCK_VIOLATION: i32 = 33;
+
+  /// 書き込みの排他に使用するバイト範囲ロックの位置。ファイルの内容が存在しえない位置を使用する。
+  pub const LOCK_OFFSET: u64 = 0x7FFF_FFFF_FFFF_FFFE;
+
+  /// 共有違反で失敗した名前の変更を再試行する回数。
+  pub const SHARING_VIOLATION_RETRIES: usize = 8;
+
+  #[repr(C)]
+  pub struct Overlapped {
+    pub internal: usize,
+    pub internal_high: usize,
+    pub offset: u32,
+    pub offset_high: u32,
+    pub event: *mut c_void,
+  }
+
+  impl Default for Overlapped {
+    fn default() -> Self {
+      Overlapped { internal: 0, internal_high: 0, offset: 0, offset_high: 0, event: std::ptr::null_mut() }
+    }
+  }
+
+  #[link(name = "kernel32")]
+  extern "system" {
+    pub fn LockFileEx(
+      file: *mut c_void,
+      flags: u32,
+      reserved: u32,
+      length_low: u32,
+      length_high: u32,
+      overlapped: *mut Overlapped,
+    ) -> i32;
+  }
+}
+
+/// ストレージからデータの入出力を行うためのカーソルです。カーソルを保持する [`Query`] をスレッド間で受け渡す
+/// ことができるように `Send` である必要があります。
+pub trait Cursor: io::Seek + io::Read + io::Write + Send {
+  /// これまでに書き込んだ内容をストレージのデバイスに同期します。永続化の概念を持たないストレージのためのデフォルト
+  /// の実装は `flush()` のみを行います。
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.flush()
+  }
+
+  /// ストレージの長さを `length` バイトに切り詰めます。オープン時にコミットされていないエントリを破棄するために
+  /// 使用します。デフォルトの実装は [`io::ErrorKind::Unsupported`] を返します。
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    let _ = length;
+    Err(io::Error::new(io::ErrorKind::Unsupported, "this cursor cannot truncate the storage"))
+  }
+
+  /// ストレージの現在のバイトサイズを参照します。カーソルの位置は変更されません。デフォルトの実装は末尾へのシークで
+  /// 長さを取得した後に元の位置へ戻ります。
+  fn len(&mut self) -> io::Result<u64> {
+    let position = self.stream_position()?;
+    let length = self.seek(io::SeekFrom::End(0))?;
+    self.seek(io::SeekFrom::Start(position))?;
+    Ok(length)
+  }
+
+  /// ストレージが 1 バイトも保持していないかを判定します。
+  fn is_empty(&mut self) -> io::Result<bool> {
+    Ok(self.len()? == 0)
+  }
+
+  /// 位置 `position` から `length` バイトの範囲を 0 で置き換えます。ファイルのように領域を解放できるストレージでは
+  /// 範囲の領域を解放し、以降は 0 として読み込まれます。[`LMTHT::prune_payloads()`] が値を削除するために使用します。
+  /// デフォルトの実装は 0 を書き込みます。正常終了したカーソルは範囲の終端を指しています。
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    fill_zeros(self, position, length)
+  }
+
+  /// このカーソルがこれまでに下位のストレージに対して行ったシークの回数と読み込んだバイト数を参照します。遅い操作の
+  /// 詳細を報告するために使用します。計測を行わないカーソルのデフォルトの実装はすべて 0 を返します。
+  fn io_counts(&self) -> IoCounts {
+    IoCounts::default()
+  }
+
+  /// このカーソルと同じストレージを参照し、同じロックを共有する書き込み用のカーソルを複製します。LMTHT は書き込み用に
+  /// オープンしたカーソルが複製に対応している場合はそれを保持し、以降の書き込み用のカーソルを複製によって取得します。
+  /// 複製に対応しないカーソルのデフォルトの実装は `None` を返します。
+  fn duplicate(&self) -> io::Result<Option<Self>>
+  where
+    Self: Sized,
+  {
+    Ok(None)
+  }
+}
+
+/// カーソルが下位のストレージに対して行った入出力の回数とバイト数です。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoCounts {
+  /// シークの回数。
+  pub seeks: u64,
+  /// 読み込んだバイト数。
+  pub bytes_read: u64,
+}
+
+impl IoCounts {
+  /// `earlier` からこの時点までに増加した回数とバイト数を返します。
+  pub fn since(&self, earlier: &IoCounts) -> IoCounts {
+    IoCounts { seeks: self.seeks - earlier.seeks, bytes_read: self.bytes_read - earlier.bytes_read }
+  }
+}
+
+/// 位置 `position` から `length` バイトの範囲に 0 を書き込みます。
+fn fill_zeros<W: io::Write + io::Seek + ?Sized>(w: &mut W, position: u64, length: u64) -> io::Result<()> {
+  let zeros = [0u8; 8 * 1024];
+  w.seek(SeekFrom::Start(position))?;
+  let mut remaining = length;
+  while remaining > 0 {
+    let size = remaining.min(zeros.len() as u64) as usize;
+    w.write_all(&zeros[..size])?;
+    remaining -= size as u64;
+  }
+  Ok(())
+}
+
+/// ファイルの位置 `position` から `length` バイトの領域を解放します。解放した範囲は 0 として読み込まれます。領域の
+/// 解放は `fallocate(FALLOC_FL_PUNCH_HOLE)` で行うため、Linux 以外の環境や対応していないファイルシステムでは何も
+/// 行わずに false を返します。
+#[allow(unused_variables)]
+pub(crate) fn punch_hole(file: &File, position: u64, length: u64) -> io::Result<bool> {
+  #[cfg(target_os = "linux")]
+  {
+    use std::os::unix::io::AsRawFd;
+    let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+    if unsafe { libc::fallocate(file.as_raw_fd(), mode, position as libc::off_t, length as libc::off_t) } == 0 {
+      return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+      return Err(err);
+    }
+  }
+  Ok(false)
+}
+
+impl Cursor for File {
+  fn sync_data(&mut self) -> io::Result<()> {
+    File::sync_data(self)
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    File::set_len(self, length)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    Ok(self.metadata()?.len())
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    if punch_hole(self, position, length)? {
+      self.seek(SeekFrom::Start(position + length)).map(|_| ())
+    } else {
+      fill_zeros(self, position, length)
+    }
+  }
+
+  fn duplicate(&self) -> io::Result<Option<Self>> {
+    self.try_clone().map(Some)
+  }
+}
+
+impl Cursor for Box<dyn Cursor> {
+  fn sync_data(&mut self) -> io::Result<()> {
+    self.as_mut().sync_data()
+  }
+
+  fn set_len(&mut self, length: u64) -> io::Result<()> {
+    self.as_mut().set_len(length)
+  }
+
+  fn len(&mut self) -> io::Result<u64> {
+    self.as_mut().len()
+  }
+
+  fn discard(&mut self, position: u64, length: u64) -> io::Result<()> {
+    self.as_mut().discard(position, length)
+  }
+
+  fn io_counts(&self) -> IoCounts {
+    self.as_ref().io_counts()
+  }
+}
+
+/// ノード b_{i,j} を含むエントリがストレージ上のどこに位置するかを表します。
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub(crate) struct Address {
+  /// ハッシュ木のリスト構造上での位置。1 から開始し [`Index`] の最大値までの値を取ります。
+  pub i: Index,
+  /// このノードの高さ (最も遠い葉ノードまでの距離)。0 の場合、ノードが葉ノードであることを示しています。最大値は
+  /// [`INDEX_SIZE`] です。
+  pub j: u8,
+  /// このノードが格納されているエントリのストレージ先頭からの位置です。
+  pub position: u64,
+}
+
+impl Address {
+  pub fn new(i: Index, j: u8, position: u64) -> Address {
+    Address { i, j, position }
+  }
+}
+
+/// ハッシュ値を含む、ノード b_{i,j} の属性情報を表します。
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub(crate) struct MetaInfo {
+  pub address: Address,
+  pub hash: Hash,
+}
+
+impl MetaInfo {
+  pub fn new(address: Address, hash: Hash) -> MetaInfo {
+    MetaInfo { address, hash }
+  }
+}
+
+impl Display for MetaInfo {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&format!("Node({},{}@{}){}", self.address.i, self.address.j, self.address.position, self.hash.to_str()))
+  }
+}
+
+impl Node {
+  fn for_node(node: &MetaInfo) -> Node {
+    Self::new(node.address.i, node.address.j, node.hash.clone())
+  }
+}
+
+/// 左右の枝を持つ中間ノードを表します。
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub(crate) struct INode {
+  pub meta: MetaInfo,
+  /// 左枝のノード
+  pub left: Address,
+  /// 右枝のノード
+  pub right: Address,
+}
+
+impl INode {
+  pub fn new(meta: MetaInfo, left: Address, right: Address) -> INode {
+    INode { meta, left, right }
+  }
+}
+
+/// 値を持つ葉ノードを表します。
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) struct ENode {
+  pub meta: MetaInfo,
+  pub payload: Vec<u8>,
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub(crate) enum RootRef<'a> {
+  None,
+  INode(&'a INode),
+  ENode(&'a ENode),
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) struct Entry {
+  pub(crate) enode: ENode,
+  pub(crate) inodes: Vec<INode>,
+}
+
+// --------------------------------------------------------------------------
+
+/// HighwayHash でチェックサム用のハッシュ値を生成するためのキー (256-bit 固定値)。
+pub(crate) const CHECKSUM_HW64_KEY: [u64; 4] =
+  [0xFA5015F2E22BCFC6u64, 0xCE5A4ED9A4025C80, 0x16B9731717F6315E, 0x0F34D06AE93BD8E9];
+
+/// ペイロード (値) の最大バイトサイズを表す定数です。2GB (2,147,483,647 bytes) を表します。
+///
+/// トレイラーの offset 値を u32 にするためにはエントリの直列化表現を最大でも `u32::MAX` とする必要があります。
+/// したがって任意帳のペイロードは 2GB までとします。この定数はビットマスクとしても使用するため 1-bit の連続で
+/// 構成されている必要があります。
+///
+pub const MAX_PAYLOAD_SIZE: usize = 0x7FFFFFFF;
+
+/// ペイロード長の最上位ビットで、そのエントリがバッチの途中であり後続のエントリでコミットされることを表します。
+/// ペイロード長は [`MAX_PAYLOAD_SIZE`] を超えないため、このビットは値の長さとしては使用されません。
+pub(crate) const CONTINUED_FLAG: u32 = !(MAX_PAYLOAD_SIZE as u32);
+
+/// 直列化された 1 つの中間ノードのバイトサイズ (j, left.position, left.i, left.j, hash) です。
+pub(crate) const INODE_SIZE: usize = 1 + 8 + INDEX_BYTES + 1 + HASH_SIZE;
+
+/// 中間ノードとペイロードを除いたエントリのバイトサイズ (i, inode count, payload length, hash, offset, checksum)
+/// です。
+const ENTRY_OVERHEAD: usize = INDEX_BYTES + 1 + 4 + HASH_SIZE + 4 + 8;
+
+/// LMTHT ファイルの先頭に記録される 3 バイトの識別子を表す定数です。値は Unicode でのdeciduous tree 🌲 (U+1F332)
+/// に由来します。
+pub const STORAGE_IDENTIFIER: [u8; 3] = [0x01u8, 0xF3, 0x33];
+
+/// 識別子に続いて配置される、この実装におけるストレージフォーマットのバージョンです。現在は 1 を使用します。
+pub const STORAGE_VERSION: u8 = 1;
+
+/// [`LMTHT::seal()`] がストレージの末尾に書き込む封印レコードの終端に配置される識別子です。
+const SEAL_MARKER: [u8; 4] = *b"SEAL";
+
+/// 封印レコードのバイトサイズ (i, j, hash, checksum, marker) です。
+const SEAL_SIZE: usize = INDEX_BYTES + 1 + HASH_SIZE + 8 + SEAL_MARKER.len();
+
+/// ヘッダのバージョンのうち、ストレージに記録されているインデックスのビット幅を表す上位 2 ビットのマスクです。
+pub(crate) const INDEX_WIDTH_MASK: u8 = 0xC0;
+
+/// このビルドの [`INDEX_SIZE`] を表すヘッダのバージョンの上位 2 ビットです。64 ビットのインデックスは 0 で表すため
+/// インデックス幅を記録する以前のストレージと互換性があります。
+pub(crate) const INDEX_WIDTH: u8 = index_width(INDEX_SIZE);
+
+/// インデックスのビット幅 `bits` をヘッダのバージョンの上位 2 ビットの値に変換します。
+const fn index_width(bits: u8) -> u8 {
+  match bits {
+    32 => 0x40,
+    128 => 0x80,
+    _ => 0x00,
+  }
+}
+
+/// ヘッダのバージョン `version` に記録されているインデックスのビット幅を返します。未定義の値の場合は 0 です。
+pub(crate) fn index_size_of(version: u8) -> u8 {
+  match version & INDEX_WIDTH_MASK {
+    0x00 => 64,
+    0x40 => 32,
+    0x80 => 128,
+    _ => 0,
+  }
+}
+
+/// ヘッダのバージョンのうち、[`LMTHT::truncate_to()`] によって履歴が書き換えられたことを表すビットです。
+const REWRITTEN_FLAG: u8 = 0x20;
+
+/// ヘッダのバージョンのうち、すべての値を [`record`](crate::record) 形式で追加するストレージであることを表すビット
+/// です。
+const RECORDS_FLAG: u8 = 0x10;
+
+/// 使用しようとしているストレージと互換性があるかを確認します。
+pub(crate) fn is_version_compatible(version: u8) -> bool {
+  let flags = INDEX_WIDTH_MASK | REWRITTEN_FLAG | RECORDS_FLAG;
+  version & INDEX_WIDTH_MASK == INDEX_WIDTH && version & !flags <= STORAGE_VERSION
+}
+
+/// 入力ストリームから [`INDEX_SIZE`] ビットのインデックスを読み込みます。
+#[inline]
+pub(crate) fn read_index<R: io::Read + ?Sized>(r: &mut R) -> io::Result<Index> {
+  let mut buffer = [0u8; INDEX_BYTES];
+  r.read_exact(&mut buffer)?;
+  Ok(Index::from_le_bytes(buffer))
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) struct CacheInner {
+  last_entry: Entry,
+  /// `last_entry` の中間ノード。証明の生成ごとに複製しないよう共有可能な形式で保持しています。
+  pub(crate) last_inodes: Arc<[INode]>,
+  model: NthGenHashTree,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) struct Cache(pub(crate) Option<CacheInner>);
+
+impl Cache {
+  pub(crate) fn new(last_entry: Entry, model: NthGenHashTree) -> Self {
+    debug_assert_eq!(model.n(), last_entry.enode.meta.address.i);
+    let last_inodes = Arc::from(&last_entry.inodes[..]);
+    Cache(Some(CacheInner { last_entry, last_inodes, model }))
+  }
+  pub(crate) fn from_entry(last_entry: Option<Entry>) -> Self {
+    let inner = if let Some(last_entry) = last_entry {
+      let n = last_entry.enode.meta.address.i;
+      let model = NthGenHashTree::new(n);
+      let last_inodes = Arc::from(&last_entry.inodes[..]);
+      Some(CacheInner { last_entry, last_inodes, model })
+    } else {
+      None
+    };
+    Cache(inner)
+  }
+
+  fn last_entry(&self) -> Option<&Entry> {
+    if let Some(CacheInner { last_entry, .. }) = &self.0 {
+      Some(last_entry)
+    } else {
+      None
+    }
+  }
+
+  pub(crate) fn root(&self) -> Option<Node> {
+    self
+      .last_entry()
+      .map(|e| e.inodes.last().map(|i| &i.meta).unwrap_or(&e.enode.meta))
+      .map(|root| Node::new(root.address.i, root.address.j, root.hash))
+  }
+
+  fn root_ref<'a>(&self) -> RootRef {
+    self
+      .last_entry()
+      .map(|e| e.inodes.last().map(|i| RootRef::INode(i)).unwrap_or(RootRef::ENode(&e.enode)))
+      .unwrap_or(RootRef::None)
+  }
+
+  pub(crate) fn n(&self) -> Index {
+    self.last_entry().map(|e| e.enode.meta.address.i).unwrap_or(0)
+  }
+}
+
+/// ストレージから読み出したノードの情報を保持する LRU キャッシュです。[`LMTHT`] とそこから作成されたすべての
+/// [`Query`] で共有されます。
+///
+/// ストレージ上の位置をキーとしてエントリから読み出した `INode` を保持します。証明の生成では上位の中間ノードが繰り
+/// 返し参照されるため、それらの読み出しをストレージへのランダムアクセスなしに行うことができます。またインデックス i
+/// をキーとしてルートノードからの探索で得られたエントリの位置を保持します。追記のみが行われるストレージ上でエントリの
+/// 位置は変化しないため、これらはどの世代の [`Query`] からも参照することができます。
+///
+/// さらに世代 n とノード b_{i,j} の組をキーとして [`NthGenHashTree`] で算出したルートノードからの経路を保持します。
+/// 証明の要求が特定のインデックスに集中する場合に、同じ経路の算出を繰り返さないようにします。
+pub(crate) struct NodeCache {
+  inodes: Mutex<Lru<u64, Arc<[INode]>>>,
+  positions: Mutex<Lru<Index, u64>>,
+  paths: Mutex<Lru<(Index, Index, u8), Arc<ModelPath>>>,
+  /// キャッシュの参照や読み出しを通知するメトリクス。
+  pub(crate) metrics: Arc<dyn MetricsSink>,
+  /// 遅い操作として通知する所要時間の閾値。
+  slow_operation_threshold: Option<Duration>,
+}
+
+impl NodeCache {
+  fn new(options: &LMTHTOptions) -> NodeCache {
+    NodeCache {
+      inodes: Mutex::new(Lru::new(options.inode_cache_size)),
+      positions: Mutex::new(Lru::new(options.position_cache_size)),
+      paths: Mutex::new(Lru::new(options.path_cache_size)),
+      metrics: options.metrics.clone().unwrap_or_else(|| Arc::new(NoMetrics)),
+      slow_operation_threshold: options.slow_operation_threshold,
+    }
+  }
+
+  /// `start` に開始した操作が閾値を超えていればメトリクスに通知します。
+  pub(crate) fn report_if_slow(&self, kind: OperationKind, start: Instant, counts: IoCounts) {
+    if let Some(threshold) = self.slow_operation_threshold {
+      let elapsed = start.elapsed();
+      if elapsed >= threshold {
+        let operation = SlowOperation { kind, elapsed, seeks: counts.seeks, bytes_read: counts.bytes_read };
+        self.metrics.slow_operation(&operation);
+      }
+    }
+  }
+
+  /// キャッシュの参照結果をメトリクスに通知します。
+  fn count<T>(&self, cache: CacheKind, value: Option<T>) -> Option<T> {
+    if value.is_some() {
+      self.metrics.cache_hit(cache);
+    } else {
+      self.metrics.cache_miss(cache);
+    }
+    value
+  }
+
+  /// `position` に位置するエントリの `INode` を参照します。キャッシュに存在しない場合はカーソルをエントリの先頭に
+  /// 移動して読み込みます。カーソルの位置はキャッシュに存在したかどうかによって異なることに注意してください。
+  fn read_inodes<C>(&self, r: &mut C, position: u64) -> Result<Arc<[INode]>>
+  where
+    C: io::Read + io::Seek,
+  {
+    let cached = lock2io(self.inodes.lock())?.get(&position).cloned();
+    if let Some(inodes) = self.count(CacheKind::INodes, cached) {
+      return Ok(inodes);
+    }
+    self.metrics.seek();
+    r.seek(io::SeekFrom::Start(position))?;
+    let inodes = Arc::from(read_inodes(r, position)?);
+    self.put_inodes(position, &inodes)?;
+    Ok(inodes)
+  }
+
+  /// `position` に位置するエントリの `INode` を保存します。
+  pub(crate) fn put_inodes(&self, position: u64, inodes: &Arc<[INode]>) -> Result<()> {
+    lock2io(self.inodes.lock())?.put(position, inodes.clone());
+    Ok(())
+  }
+
+  /// i 番目のエントリの位置を参照します。
+  fn position(&self, i: Index) -> Result<Option<u64>> {
+    let cached = lock2io(self.positions.lock())?.get(&i).copied();
+    Ok(self.count(CacheKind::Positions, cached))
+  }
+
+  /// i 番目のエントリの位置を保存します。
+  pub(crate) fn put_position(&self, i: Index, position: u64) -> Result<()> {
+    lock2io(self.positions.lock())?.put(i, position);
+    Ok(())
+  }
+
+  /// ストレージ上の `position` 以降、あるいは世代 `n` より後のエントリに関するキャッシュを破棄します。これは
+  /// ストレージに出力されなかったエントリの位置が後に別のエントリで再利用されるためです。
+  pub(crate) fn forget_after(&self, position: u64, n: Index) -> Result<()> {
+    lock2io(self.inodes.lock())?.retain(|p, _| *p < position);
+    lock2io(self.positions.lock())?.retain(|i, p| *i <= n && *p < position);
+    Ok(())
+  }
+
+  /// 世代 `model` のルートノードから b_{i,j} までの経路を参照します。キャッシュに存在しない場合は算出して保存します。
+  /// b_{i,j} が `model` に含まれていない場合は `None` を返します。
+  fn path(&self, model: &NthGenHashTree, i: Index, j: u8) -> Result<Option<Arc<ModelPath>>> {
+    let key = (model.n(), i, j);
+    let cached = lock2io(self.paths.lock())?.get(&key).cloned();
+    if let Some(path) = self.count(CacheKind::Paths, cached) {
+      return Ok(Some(path));
+    }
+    let path = model.path_to(i, j).map(Arc::new);
+    if let Some(path) = &path {
+      lock2io(self.paths.lock())?.put(key, path.clone());
+    }
+    Ok(path)
+  }
+}
+
+/// [`LMTHT`] の動作を調整するためのオプションです。
+#[derive(Clone)]
+pub struct LMTHTOptions {
+  /// ストレージから読み出した中間ノードをキャッシュするエントリの最大数です。0 を指定した場合はキャッシュを
+  /// 使用しません。デフォルトは [`DEFAULT_INODE_CACHE_SIZE`] です。
+  pub inode_cache_size: usize,
+  /// ルートノードからの探索で得られたエントリの位置をキャッシュする最大数です。0 を指定した場合はキャッシュを使用
+  /// しません。デフォルトは [`DEFAULT_POSITION_CACHE_SIZE`] です。
+  pub position_cache_size: usize,
+  /// 世代とノードの組に対して算出したルートノードからの経路をキャッシュする最大数です。0 を指定した場合はキャッシュ
+  /// を使用しません。デフォルトは [`DEFAULT_PATH_CACHE_SIZE`] です。
+  pub path_cache_size: usize,
+  /// インデックス i からエントリの位置を参照する位置索引を保存するサイドカーのストレージです。指定した場合、
+  /// [`Query::get()`] はルートノードからの探索を行わず一度のシークで値を読み出します。位置索引が存在しないか
+  /// ハッシュ木と一致しない場合はオープン時に再構築されます。読み込み専用の LMTHT はサイドカーに書き込まず、一致
+  /// しない位置索引は使用せずにルートノードから探索します。デフォルトは `None` です。
+  pub position_index: Option<Arc<dyn DynStorage + Send + Sync>>,
+  /// true を指定した場合、オープン時にすべてのエントリの位置を読み出してメモリ上に位置索引を構築します。エントリ数
+  /// に比例したメモリ (1 エントリあたり 8 バイト) を使用する代わりにサイドカーなしで [`Query::get()`] を一度の
+  /// シークで行うことができます。[`LMTHTOptions::position_index`] が指定されている場合は無視されます。デフォルトは
+  /// `false` です。
+  pub in_memory_position_index: bool,
+  /// [`LMTHT::pooled_query()`] で再利用するために保持する [`Query`] の最大数です。0 を指定した場合はクエリーを
+  /// 再利用しません。デフォルトは [`DEFAULT_QUERY_POOL_SIZE`] です。
+  pub query_pool_size: usize,
+  /// ストレージから読み込む際に先読みするバッファのバイトサイズです。0 を指定した場合は読み込みをバッファリング
+  /// しません。デフォルトは [`DEFAULT_READ_BUFFER_SIZE`] です。
+  pub read_buffer_size: usize,
+  /// ストレージへ書き込む際にまとめて出力するバッファのバイトサイズです。0 を指定した場合は書き込みをバッファリング
+  /// しません。デフォルトは [`DEFAULT_WRITE_BUFFER_SIZE`] です。
+  pub write_buffer_size: usize,
+  /// 追加したエントリをストレージのデバイスに同期 (fsync) する契機です。デフォルトは [`SyncPolicy::Manual`] です。
+  pub sync_policy: SyncPolicy,
+  /// 追加や読み出し、キャッシュの参照などを通知する [`MetricsSink`] です。デフォルトは `None` で何も通知しません。
+  pub metrics: Option<Arc<dyn MetricsSink>>,
+  /// 追加、証明の生成、オープンの所要時間がこの閾値以上であった場合に [`MetricsSink::slow_operation()`] を呼び
+  /// 出します。劣化したディスクをチェックサムの検証に失敗するより前に検出するために使用します。デフォルトは `None`
+  /// で通知しません。
+  pub slow_operation_threshold: Option<Duration>,
+  /// true を指定した場合、ストレージを読み込み専用でオープンします。値の追加や同期は [`Detail::ReadOnly`] で失敗
+  /// し、オープン時にコミットされていない末尾のエントリを破棄することもありません。他のプロセスが書き込んでいる
+  /// ストレージを参照する場合に使用します。デフォルトは `false` です。
+  pub read_only: bool,
+  /// true を指定した場合、読み込み専用の LMTHT は [`LMTHT::query()`] のたびに [`LMTHT::reload()`] を行い、他の
+  /// プロセスによって追加されたエントリを最新の世代として参照します。書き込み可能な LMTHT では無視
+  /// されます。デフォルトは `false` です。
+  pub auto_refresh: bool,
+  /// 別の信頼できる場所に保存しておいた世代とルートハッシュの組を指定した場合、オープン時にストレージがその
+  /// ルートハッシュを持つ世代を含むことを [`LMTHT::verify_trusted_root()`] で検証し、一致しなければオープンに
+  /// 失敗します。ストレージのロールバックや差し替えを検出するために使用します。デフォルトは `None` です。
+  pub trusted_root: Option<(Index, Hash)>,
+  /// これまでに参照した最も新しい世代を記録する [`WatermarkStore`] です。指定した場合、記録されている世代より少ない
+  /// エントリしか持たないストレージのオープンや再読み込みは [`Detail::RolledBack`] で失敗します。追加や再読み込みの
+  /// たびに参照されるため、記録は短時間で終了する必要があります。デフォルトは `None` です。
+  pub watermark: Option<Arc<dyn WatermarkStore>>,
+  /// ストレージに対する読み込み、書き込み、シーク、同期が一時的なエラーで失敗した場合に再実行する
+  /// [`RetryPolicy`] です。ネットワーク越しのストレージを使用する場合に指定します。デフォルトは `None` で再実行
+  /// しません。
+  pub retry: Option<RetryPolicy>,
+  /// [`LMTHT::archive()`] でアーカイブした範囲を記録するサイドカーのストレージです。指定した場合、アーカイブした
+  /// 値の参照は [`Detail::Archived`] を返します。指定しない場合は [`LMTHT::archive()`] を使用できません。デフォルト
+  /// は `None` です。
+  pub archive_catalog: Option<Arc<dyn DynStorage + Send + Sync>>,
+  /// true を指定した場合、[`LMTHT::append_record()`] はタイムスタンプを持たないレコードに追加時の時刻を付加します。
+  /// 時刻は直前のレコードの時刻より前にならないよう補正されるため、[`Query::range_by_time()`] で時刻の範囲から
+  /// レコードを検索することができます。追加の方法に関わらず、タイムスタンプを持たない値は [`Detail::NoTimestamp`]、
+  /// 直前のレコードより前の時刻を持つ値は [`Detail::TimestampOutOfOrder`] で拒否されます。デフォルトは `false` です。
+  pub timestamps: bool,
+  /// true を指定した場合、すべての値を [`record`](crate::record) 形式で追加するストレージであることをヘッダに記録し、
+  /// レコード形式でない値の追加を [`Detail::InvalidRecord`] で拒否します。[`LMTHTOptions::timestamps`] や
+  /// [`LMTHTOptions::key_index`] を指定した場合は常に有効です。記録済みのストレージでは指定しなくても有効となり、
+  /// 記録されていないストレージには値を持たない場合にのみ記録できます。デフォルトは `false` です。
+  pub records: bool,
+  /// キーからそのキーを持つレコードのインデックスを参照するキー索引を保存するサイドカーのストレージです。指定した
+  /// 場合、[`LMTHT::append_with_key()`] で追加したレコードを [`Query::get_by_key()`] で参照することができます。キー
+  /// 索引が存在しないかハッシュ木と一致しない場合はオープン時に再構築されます。デフォルトは `None` です。
+  pub key_index: Option<Arc<dyn DynStorage + Send + Sync>>,
+  /// 値のハッシュ値からエントリのインデックスを参照するハッシュ索引を保存するサイドカーのストレージです。指定した
+  /// 場合、[`Query::find_by_hash()`] で値がハッシュ木に含まれているかを判定することができます。ハッシュ索引は
+  /// メモリ上にも保持されます。ハッシュ索引が存在しないかハッシュ木と一致しない場合はオープン時に再構築されます。
+  /// デフォルトは `None` です。
+  pub hash_index: Option<Arc<dyn DynStorage + Send + Sync>>,
+  /// すべての値のハッシュ値を登録したブルームフィルタを保存するサイドカーのストレージです。指定した場合、
+  /// [`Query::probably_contains()`] で値がハッシュ木に含まれていないことを木構造を探索せずに判定することができます。
+  /// ブルームフィルタはメモリ上にも保持されます。デフォルトは `None` です。
+  pub bloom_filter: Option<Arc<dyn DynStorage + Send + Sync>>,
+  /// ブルームフィルタのビット数です。含まれていない値を誤って含まれている可能性があると判定する確率は、エントリ
+  /// 数の 10 倍のビット数でおよそ 1% です。変更した場合、ブルームフィルタはオープン時に再構築されます。デフォルト
+  /// は [`DEFAULT_BLOOM_FILTER_BITS`] です。
+  pub bloom_filter_bits: u64,
+  /// 追加する値と同じ値がすでにハッシュ木に含まれている場合の扱いです。[`DuplicatePolicy::Allow`] 以外を指定する
+  /// 場合は [`LMTHTOptions::hash_index`] も指定する必要があります。同じイベントを繰り返し取り込む可能性のある
+  /// パイプラインで使用します。デフォルトは [`DuplicatePolicy::Allow`] です。
+  pub duplicates: DuplicatePolicy,
+  /// true を指定した場合、[`LMTHT::truncate_to()`] でストレージを過去の世代まで切り詰めることを許可します。切り詰め
+  /// はハッシュ木の履歴を書き換える操作であるため、誤って追加したデータを取り除く障害復旧の場合にのみ指定して
+  /// ください。デフォルトは `false` です。
+  pub allow_truncate: bool,
+}
+
+/// 追加したエントリをストレージのデバイスに同期する契機を指定します。
+///
+/// 同期していないエントリは OS やデバイスのキャッシュ上にのみ存在し、電源断などによって失われる可能性があります。
+/// 同期の頻度を下げると追加のスループットは向上しますが、障害時に失われる可能性のあるエントリが増えます。いずれの
+/// 方法でも [`LMTHT::sync()`] で明示的に同期することができます。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+  /// すべての追加のたびに同期します。
+  Always,
+  /// 前回の同期から指定された数のエントリを追加するたびに同期します。
+  EveryNAppends(u64),
+  /// 前回の同期から指定された時間が経過した後の追加で同期します。
+  Interval(Duration),
+  /// [`LMTHT::sync()`] が呼び出されたときのみ同期します。
+  Manual,
+  /// 追加は OS への書き込みが完了した時点で終了し、バックグラウンドのスレッドが複数の追加をまとめて同期します。
+  /// スレッドは未同期の追加を検出してから `delay` だけ後続の追加を待って同期を行います。特定の世代が同期される
+  /// まで待機するには [`LMTHT::wait_durable()`] を使用します。
+  GroupCommit { delay: Duration },
+}
+
+/// [`LMTHTOptions::inode_cache_size`] のデフォルト値です。
+pub const DEFAULT_INODE_CACHE_SIZE: usize = 1024;
+
+/// [`LMTHTOptions::position_cache_size`] のデフォルト値です。
+pub const DEFAULT_POSITION_CACHE_SIZE: usize = 4096;
+
+/// [`LMTHTOptions::path_cache_size`] のデフォルト値です。
+pub const DEFAULT_PATH_CACHE_SIZE: usize = 1024;
+
+/// [`LMTHTOptions::query_pool_size`] のデフォルト値です。
+pub const DEFAULT_QUERY_POOL_SIZE: usize = 16;
+
+/// [`LMTHTOptions::read_buffer_size`] のデフォルト値です。
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 4 * 1024;
+
+/// [`LMTHTOptions::write_buffer_size`] のデフォルト値です。
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// [`LMTHTOptions::bloom_filter_bits`] のデフォルト値です。1 MiB のビット列で約 80 万エントリまで 1% 程度の誤判定率
+/// を保ちます。
+pub const DEFAULT_BLOOM_FILTER_BITS: u64 = 8 * 1024 * 1024;
+
+impl Default for LMTHTOptions {
+  fn default() -> Self {
+    LMTHTOptions {
+      inode_cache_size: DEFAULT_INODE_CACHE_SIZE,
+      position_cache_size: DEFAULT_POSITION_CACHE_SIZE,
+      path_cache_size: DEFAULT_PATH_CACHE_SIZE,
+      position_index: None,
+      in_memory_position_index: false,
+      query_pool_size: DEFAULT_QUERY_POOL_SIZE,
+      read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+      write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+      sync_policy: SyncPolicy::Manual,
+      metrics: None,
+      slow_operation_threshold: None,
+      read_only: false,
+      auto_refresh: false,
+      trusted_root: None,
+      watermark: None,
+      retry: None,
+      archive_catalog: None,
+      timestamps: false,
+      records: false,
+      key_index: None,
+      hash_index: None,
+      bloom_filter: None,
+      bloom_filter_bits: DEFAULT_BLOOM_FILTER_BITS,
+      duplicates: DuplicatePolicy::Allow,
+      allow_truncate: false,
+    }
+  }
+}
+
+/// ストレージ上に直列化された Logarithmic Multi-Tier Hash Tree を表す木構造に対する操作を実装します。
+///
+/// 値の追加は `&self` で行うことができ、`Arc` で共有した LMTHT に複数のスレッドから追加と参照を行うことができます。
+/// 追加は内部のロックによって直列化されますが、参照は追加が完了した最新の世代を対象とするため追加によって
+/// ブロックされることはありません。
+pub struct LMTHT<S: Storage> {
+  pub(crate) storage: Box<S>,
+  /// 追加が完了している最新の世代。[`Query::refresh()`] のためにクエリーと共有する。
+  latest_cache: Arc<Latest>,
+  pub(crate) node_cache: Arc<NodeCache>,
+  pub(crate) position_index: Option<Arc<PositionIndex>>,
+  pub(crate) query_pool: QueryPool<BufferedCursor<S::Cursor>>,
+  read_buffer_size: usize,
+  write_buffer_size: usize,
+  sync_policy: SyncPolicy,
+  /// 追加を直列化するためのロックと、追加を行うスレッドが排他的に使用する状態。
+  pub(crate) writer: Mutex<Writer>,
+  /// 書き込み用のカーソルの複製元として保持しているカーソル。複製に対応したストレージでのみ使用する。
+  shared_cursor: Mutex<Option<S::Cursor>>,
+  durability: Arc<Durability>,
+  group_commit: Option<GroupCommit>,
+  read_only: bool,
+  auto_refresh: bool,
+  /// 最新の世代として読み込んだエントリのストレージ上の終端。
+  pub(crate) loaded_end: AtomicU64,
+  /// ストレージが封印されているか。
+  sealed: AtomicBool,
+  /// ストレージ上のエントリを書き換えた回数。カーソルはこの値が変化した場合に読み込みバッファを破棄する。
+  rewrites: Arc<AtomicU64>,
+  watermark: Option<Arc<dyn WatermarkStore>>,
+  retry: Option<RetryPolicy>,
+  pub(crate) archive_catalog: Option<Arc<ArchiveCatalog>>,
+  pub(crate) subscribers: Subscribers,
+  pub(crate) timestamps: bool,
+  /// すべての値をレコード形式で追加するか。ストレージのヘッダに記録されている場合はオープン時に有効となる。
+  pub(crate) records: bool,
+  /// 最後に追加したレコードのタイムスタンプ。0 の場合は未参照。
+  pub(crate) last_timestamp: AtomicU64,
+  pub(crate) key_index: Option<Arc<KeyIndex>>,
+  pub(crate) hash_index: Option<Arc<HashIndex>>,
+  pub(crate) bloom_filter: Option<Arc<BloomFilter>>,
+  pub(crate) duplicates: DuplicatePolicy,
+  allow_truncate: bool,
+}
+
+/// LMTHT への追加を行うスレッドがロックを獲得して使用する状態です。
+pub(crate) struct Writer {
+  /// 前回の同期以降に追加したエントリの数。
+  unsynced: u64,
+  /// 前回の同期の時刻。
+  last_sync: Instant,
+  /// 追加の途中で失敗し、ストレージやキャッシュ、索引の状態を元に戻せなかった場合のエラーメッセージ。
+  pub(crate) failure: Option<String>,
+}
+
+impl Writer {
+  /// 以前の追加の失敗によって状態が不確定となっている場合はエラーを返します。
+  pub(crate) fn check(&self) -> Result<()> {
+    match &self.failure {
+      Some(message) => Err(WriterPoisoned { message: message.clone() }),
+      None => Ok(()),
+    }
+  }
+}
+
+impl<S: Storage> LMTHT<S> {
+  /// 指定された [`Storage`] に直列化されたハッシュ木を保存する LMTHT を構築します。
+  ///
+  /// ストレージに [`std::path::Path`] や [`std::path::PathBuf`] のようなパスを指定したするとそのファイルに
+  /// 直列化されたハッシュ木を保存します。テストや検証目的ではメモリ上にハッシュ木を直列化する [`MemStorage`] を
+  /// 使用することができます。ストレージは抽象化されているため独自の [`Storage`] 実装を使用することができます。
+  ///
+  /// # Examples
+  ///
+  /// 以下はシステムのテンポラリディレクトリ上の `mbht-example.db` にハッシュ木を直列化する例です。
+  ///
+  /// ```rust
+  /// use lmtht::{LMTHT,Storage,Result};
+  /// use std::env::temp_dir;
+  /// use std::fs::remove_file;
+  /// use std::path::PathBuf;
+  ///
+  /// fn append_and_get(file: &PathBuf) -> Result<()>{
+  ///   let db = LMTHT::new(file)?;
+  ///   let root = db.append(&vec![0u8, 1, 2, 3])?;
+  ///   assert_eq!(Some(vec![0u8, 1, 2, 3]), db.query()?.get(root.i)?);
+  ///   Ok(())
+  /// }
+  ///
+  /// let mut path = temp_dir();
+  /// path.push("lmtht-example.db");
+  /// append_and_get(&path).expect("test failed");
+  /// remove_file(path.as_path()).unwrap();
+  /// ```
+  pub fn new(storage: S) -> Result<LMTHT<S>> {
+    Self::with_options(storage, LMTHTOptions::default())
+  }
+
+  /// 指定された [`Storage`] に対してオプションを段階的に指定して LMTHT をオープンするビルダーを返します。
+  pub fn builder(storage: S) -> LMTHTBuilder<S> {
+    LMTHTBuilder::new(storage)
+  }
+
+  /// 指定された [`Storage`] とオプションを使用する LMTHT を構築します。
+  pub fn with_options(storage: S, options: LMTHTOptions) -> Result<LMTHT<S>> {
+    if options.duplicates != DuplicatePolicy::Allow && options.hash_index.is_none() {
+      return Err(NoHashIndex);
+    }
+    let gen_cache = Arc::new(Cache::from_entry(None));
+    let node_cache = Arc::new(NodeCache::new(&options));
+    let position_index = match options.position_index {
+      Some(storage) => Some(Arc::new(PositionIndex::new(storage))),
+      None if options.in_memory_position_index => Some(Arc::new(PositionIndex::in_memory())),
+      None => None,
+    };
+    let query_pool = QueryPool::new(options.query_pool_size);
+    let records = options.records || options.timestamps || options.key_index.is_some();
+    let archive_catalog = options.archive_catalog.map(ArchiveCatalog::open).transpose()?.map(Arc::new);
+    let key_index = options.key_index.map(KeyIndex::open).transpose()?.map(Arc::new);
+    let hash_index = options.hash_index.map(HashIndex::open).transpose()?.map(Arc::new);
+    let bits = options.bloom_filter_bits;
+    let bloom_filter = options.bloom_filter.map(|s| BloomFilter::open(s, bits)).transpose()?.map(Arc::new);
+    let mut db = LMTHT {
+      storage: Box::new(storage),
+      latest_cache: Arc::new(Latest::new(gen_cache)),
+      node_cache,
+      position_index,
+      query_pool,
+      read_buffer_size: options.read_buffer_size,
+      write_buffer_size: options.write_buffer_size,
+      sync_policy: options.sync_policy,
+      writer: Mutex::new(Writer { unsynced: 0, last_sync: Instant::now(), failure: None }),
+      shared_cursor: Mutex::new(None),
+      durability: Arc::new(Durability::new(0)),
+      group_commit: None,
+      read_only: options.read_only,
+      auto_refresh: options.read_only && options.auto_refresh,
+      loaded_end: AtomicU64::new(0),
+      sealed: AtomicBool::new(false),
+      rewrites: Arc::new(AtomicU64::new(0)),
+      watermark: options.watermark,
+      retry: options.retry,
+      archive_catalog,
+      subscribers: Subscribers::new(),
+      timestamps: options.timestamps,
+      records,
+      last_timestamp: AtomicU64::new(0),
+      key_index,
+      hash_index,
+      bloom_filter,
+      duplicates: options.duplicates,
+      allow_truncate: options.allow_truncate,
+    };
+    if !db.read_only {
+      // オープンごとにロックを獲得するストレージでは LMTHT の破棄までロックを保持し、その複製で書き込む
+      let cursor = db.storage.open(true)?;
+      if cursor.duplicate()?.is_some() {
+        *lock2io(db.shared_cursor.lock())? = Some(cursor);
+      }
+    }
+    db.init()?;
+    if let Some((n, hash)) = options.trusted_root {
+      db.verify_trusted_root(n, &hash)?;
+    }
+    db.durability = Arc::new(Durability::new(db.n()));
+    if let (SyncPolicy::GroupCommit { delay }, false) = (db.sync_policy, db.read_only) {
+      let cursor = db.open_storage(true)?;
+      db.group_commit = Some(GroupCommit::start(cursor, db.durability.clone(), delay));
+    }
+    Ok(db)
+  }
+
+  /// 現在の木構造のルートノードを参照します。
+  pub fn root(&self) -> Option<Node> {
+    self.latest().root()
+  }
+
+  /// 木構造の現在の世代 (リストとして何個の要素を保持しているか) を返します。
+  pub fn n(&self) -> Index {
+    self.latest().n()
+  }
+
+  /// この LMTHT の現在の高さを参照します。ノードが一つも含まれていない場合は 0 を返します。
+  pub fn height(&self) -> u8 {
+    self.root().map(|root| root.j).unwrap_or(0)
+  }
+
+  /// この LMTHT のルートハッシュを参照します。一つのノードも含まれていない場合は `None` を返します。
+  pub fn root_hash(&self) -> Option<Hash> {
+    self.root().map(|root| root.hash)
+  }
+
+  /// この LMTHT に追加できる残りの値の数を参照します。要素数は [`Index`] の最大値までであり、`small_index`
+  /// feature を指定した場合は 2³²-1 個となります。
+  pub fn remaining_capacity(&self) -> Index {
+    Index::MAX - self.n()
+  }
+
+  /// 現在の世代に `payload_len` バイトの値を追加したときにストレージが増加するバイトサイズを算出します。これは
+  /// 追加されるエントリの中間ノード、ペイロード、およびトレイラーを含む正確な値です。
+  pub fn estimate_append_size(&self, payload_len: usize) -> u64 {
+    let inodes = NthGenHashTree::new(self.n().saturating_add(1)).inodes().len();
+    (ENTRY_OVERHEAD + inodes * INODE_SIZE + payload_len) as u64
+  }
+
+  /// 平均 `avg_payload` バイトの値を `n` 個追加した LMTHT のストレージのバイトサイズを算出します。エントリ i は
+  /// tz(i) + popcount(i) - 1 個の中間ノードを持つため、中間ノードの総数は 1..=n の popcount の総和から popcount(n)
+  /// を引いた値となります。算出結果が `u64` の範囲を超える場合は `u64::MAX` を返します。
+  pub fn estimate_total_size(&self, n: Index, avg_payload: usize) -> u64 {
+    let n = n as u128;
+    let bits = (0..128).fold(0u128, |sum, b| sum.saturating_add(sum_of_bit(n, b)));
+    let inodes = bits.saturating_sub(n.count_ones() as u128);
+    let header = STORAGE_IDENTIFIER.len() as u128 + 1;
+    let entries = n.saturating_mul((ENTRY_OVERHEAD + avg_payload) as u128);
+    let size = header.saturating_add(entries).saturating_add(inodes.saturating_mul(INODE_SIZE as u128));
+    size.min(u64::MAX as u128) as u64
+  }
+
+  pub fn storage(&self) -> &S {
+    self.storage.as_ref()
+  }
+
+  fn init(&mut self) -> Result<()> {
+    let start = Instant::now();
+    let mut cursor = self.open_cursor(!self.read_only)?;
+    let length = cursor.len()?;
+    match length {
+      0 if self.read_only => return self.record_watermark(0),
+      0 => {
+        // マジックナンバーの書き込み
+        cursor.write_all(&STORAGE_IDENTIFIER)?;
+        let records = if self.records { RECORDS_FLAG } else { 0 };
+        cursor.write_u8(STORAGE_VERSION | INDEX_WIDTH | records)?;
+        cursor.flush()?;
+      }
+      _ => {
+        check_header(&mut cursor, length)?;
+        self.reserve_records(&mut cursor, length)?;
+      }
+    }
+
+    let mut length = cursor.len()?;
+    let seal = read_seal(&mut cursor, length)?;
+    if let Some((start, _)) = seal {
+      // 封印レコードはコミット済みのエントリの後にのみ書き込まれる
+      length = start;
+    } else if length > 4 {
+      // コミットされていないバッチや書き込み途中で中断したエントリを破棄 (読み込み専用の場合は参照しないだけ)
+      let end = committed_end(&mut cursor, length, self.node_cache.metrics.as_ref())?;
+      if end != length && !self.read_only {
+        if let Err(err) = cursor.set_len(end) {
+          let msg = format!("The storage has uncommitted entries after {} that cannot be truncated: {}", end, err);
+          return Err(DamagedStorage(msg));
+        }
+      }
+      length = end;
+    }
+    self.load_tail(&mut cursor, length)?;
+    if let Some((_, root)) = seal {
+      self.check_seal(root)?;
+    }
+    self.node_cache.report_if_slow(OperationKind::Open, start, cursor.io_counts());
+    Ok(())
+  }
+
+  /// ヘッダにレコード形式のストレージであることが記録されていればレコード形式を有効にします。レコード形式を指定した
+  /// LMTHT では記録されていないストレージに記録しますが、すでに値を持つストレージの場合は
+  /// [`Detail::RecordsNotReserved`] を返します。
+  fn reserve_records(&mut self, cursor: &mut BufferedCursor<S::Cursor>, length: u64) -> Result<()> {
+    let position = STORAGE_IDENTIFIER.len() as u64;
+    cursor.seek(io::SeekFrom::Start(position))?;
+    let version = cursor.read_u8()?;
+    if version & RECORDS_FLAG != 0 {
+      self.records = true;
+    } else if self.records {
+      if length > position + 1 {
+        return Err(RecordsNotReserved);
+      } else if !self.read_only {
+        cursor.seek(io::SeekFrom::Start(position))?;
+        cursor.write_u8(version | RECORDS_FLAG)?;
+        cursor.flush()?;
+      }
+    }
+    Ok(())
+  }
+
+  /// ストレージ上の `end` で終わるエントリを最新の世代として読み込み、キャッシュを更新します。
+  fn load_tail(&self, cursor: &mut BufferedCursor<S::Cursor>, end: u64) -> Result<()> {
+    let tail = if end == 4 {
+      None
+    } else {
+      // 末尾のエントリを読み込み
+      cursor.seek(io::SeekFrom::Start(end))?;
+      back_to_safety(cursor, 4 + 8, "The first entry is corrupted.")?;
+      let offset = cursor.read_u32::<LittleEndian>()?;
+      back_to_safety(cursor, offset + 4, "The last entry is corrupted.")?;
+      let entry = observe(self.node_cache.metrics.as_ref(), read_entry(cursor, 0))?;
+      if cursor.stream_position()? != end {
+        // 壊れたストレージから読み込んだ offset が、たまたまどこかの正しいエントリ境界を指していた場合、正しく
+        // 読み込めるが結果となる位置は末尾と一致しない。
+        let msg = "The last entry is corrupted.".to_string();
+        return Err(DamagedStorage(msg));
+      }
+      Some(entry)
+    };
+
+    // 位置索引の検証と再構築
+    if let Some(position_index) = &self.position_index {
+      position_index.prepare(cursor, tail.as_ref(), self.read_only)?;
+    }
+
+    // キャッシュを更新
+    let new_cache = Cache::from_entry(tail);
+    self.set_latest(Arc::new(new_cache));
+    self.loaded_end.store(end, Ordering::Release);
+
+    // キー索引、ハッシュ索引、ブルームフィルタの検証と再構築
+    if let Some(key_index) = &self.key_index {
+      key_index.prepare(&mut self.new_query()?)?;
+    }
+    if let Some(hash_index) = &self.hash_index {
+      hash_index.prepare(&mut self.new_query()?)?;
+    }
+    if let Some(bloom_filter) = &self.bloom_filter {
+      bloom_filter.prepare(&mut self.new_query()?)?;
+    }
+
+    self.record_watermark(self.n())
+  }
+
+  /// ストレージの末尾から最新の世代を再び探索し、他のプロセスによって追加されたエントリを最新の世代として参照できる
+  /// ようにします。LMTHT を再構築することなく、アプリケーションが独自の間隔でストレージの変化を取り込むために使用
+  /// します。[`LMTHTOptions::auto_refresh`] を指定した読み込み専用の LMTHT ではクエリーのたびに自動的に行われます。
+  ///
+  /// コミットされていない末尾のエントリは参照されず、破棄もされません。
+  ///
+  /// # Returns
+  /// 新しいエントリを検出して最新の世代を更新した場合に true を返します。
+  pub fn reload(&self) -> Result<bool> {
+    let _writer = lock2io(self.writer.lock())?;
+    let mut cursor = self.open_cursor(false)?;
+    let length = cursor.len()?;
+    let loaded_end = self.loaded_end.load(Ordering::Acquire);
+    if length == loaded_end || length < 4 {
+      return Ok(false);
+    } else if length < loaded_end {
+      let msg = format!("The storage has shrunk from {} to {} bytes.", loaded_end, length);
+      return Err(DamagedStorage(msg));
+    }
+    if loaded_end < 4 {
+      check_header(&mut cursor, length)?;
+    }
+    let seal = read_seal(&mut cursor, length)?;
+    let end = match seal {
+      Some((start, _)) => start,
+      None => committed_end(&mut cursor, length, self.node_cache.metrics.as_ref())?,
+    };
+    let loaded = end > loaded_end.max(4);
+    if loaded {
+      self.load_tail(&mut cursor, end)?;
+    }
+    if let Some((_, root)) = seal {
+      self.check_seal(root)?;
+    }
+    Ok(loaded)
+  }
+
+  /// 現在の世代のルートノードを記録した封印レコードをストレージの末尾に書き込み、以降の追加を禁止します。締めた会計
+  /// 期間のように終端が確定したログに使用します。封印したストレージをオープンした LMTHT は追加に対して
+  /// [`Detail::Sealed`] を返し、[`LMTHT::root()`] は封印されたルートノードを返します。
+  ///
+  /// 封印レコードはこのメソッドが終了する前にストレージのデバイスに同期されます。
+  ///
+  /// # Returns
+  /// 封印されたルートノードを返します。空の LMTHT を封印した場合は `None` です。
+  pub fn seal(&self) -> Result<Option<Node>> {
+    self.check_writable()?;
+    let mut writer = lock2io(self.writer.lock())?;
+    writer.check()?;
+    self.check_unsealed()?;
+    self.durability.check()?;
+    let root = self.root();
+    let mut cursor = self.open_cursor(true)?;
+    cursor.seek(io::SeekFrom::Start(self.loaded_end.load(Ordering::Acquire)))?;
+    cursor.write_all(&seal_record(root.as_ref()))?;
+    cursor.flush()?;
+    self.sync_cursor(&mut writer, &mut cursor, self.n())?;
+    self.sealed.store(true, Ordering::Release);
+    Ok(root)
+  }
+
+  /// このストレージが [`LMTHT::seal()`] によって封印されているかを判定します。
+  pub fn is_sealed(&self) -> bool {
+    self.sealed.load(Ordering::Acquire)
+  }
+
+  /// ストレージを世代 `n` の終端まで物理的に切り詰め、世代 `n` のルートノードを返します。誤ったデータが追加された
+  /// ローカルのコピーを障害復旧のために巻き戻す操作であり、[`LMTHTOptions::allow_truncate`] を指定した LMTHT でのみ
+  /// 実行できます。
+  ///
+  /// 切り詰めの前にストレージのヘッダに履歴が書き換えられたことを記録し、以降は [`LMTHT::is_rewritten()`] で判定
+  /// することができます。世代 `n` がバッチの途中のエントリであっても、切り詰めた後の末尾のエントリはコミットを
+  /// 表すエントリとなります。[`LMTHTOptions::watermark`] に記録されている世代は `n` に戻され、索引はオープン時と
+  /// 同様に検証されて再構築されます。切り詰める前に取得した [`Query`] は切り詰めた範囲を参照し続ける可能性が
+  /// あります。
+  ///
+  /// # Errors
+  /// [`LMTHTOptions::allow_truncate`] が指定されていない場合は [`Detail::TruncateNotAllowed`] を、世代 `n` がまだ
+  /// 追加されていない場合は [`Detail::GenerationNotAppended`] を返します。
+  pub fn truncate_to(&self, n: Index) -> Result<Option<Node>> {
+    self.check_writable()?;
+    if !self.allow_truncate {
+      return Err(TruncateNotAllowed);
+    }
+    let mut writer = lock2io(self.writer.lock())?;
+    writer.check()?;
+    self.check_unsealed()?;
+    self.durability.check()?;
+    let current = self.n();
+    if n > current {
+      return Err(GenerationNotAppended { n, current });
+    } else if n == current {
+      return Ok(self.root());
+    }
+    let mut query = self.new_query()?;
+    let start = if n == 0 { None } else { query.entry_position(n)? };
+    let end = match query.entry_position(n + 1)? {
+      Some(end) => end,
+      None => return Err(DamagedStorage(format!("the entry {} is not found", n + 1))),
+    };
+    drop(query);
+
+    // 切り詰める前にヘッダに記録する
+    let mut cursor = self.open_cursor(true)?;
+    cursor.seek(io::SeekFrom::Start(STORAGE_IDENTIFIER.len() as u64))?;
+    let version = cursor.read_u8()?;
+    cursor.seek(io::SeekFrom::Start(STORAGE_IDENTIFIER.len() as u64))?;
+    cursor.write_u8(version | REWRITTEN_FLAG)?;
+    cursor.flush()?;
+    cursor.sync_data()?;
+
+    // 世代 n のエントリをコミットを表すエントリに書き換えてから切り詰める
+    if let Some(start) = start {
+      let mut entry = vec![0u8; (end - start) as usize];
+      cursor.seek(io::SeekFrom::Start(start))?;
+      cursor.read_exact(&mut entry)?;
+      set_continued(&mut entry, false);
+      cursor.seek(io::SeekFrom::Start(start))?;
+      cursor.write_all(&entry)?;
+      cursor.flush()?;
+    }
+    cursor.set_len(end)?;
+    cursor.sync_data()?;
+
+    // キャッシュと索引を切り詰めた世代に合わせる
+    self.node_cache.forget_after(end, n)?;
+    self.query_pool.clear()?;
+    self.rewrites.fetch_add(1, Ordering::AcqRel);
+    self.last_timestamp.store(0, Ordering::SeqCst);
+    if let Some(watermark) = &self.watermark {
+      watermark.store(n)?;
+    }
+    self.load_tail(&mut cursor, end)?;
+    self.durability.rewind(n)?;
+    writer.unsynced = 0;
+    Ok(self.root())
+  }
+
+  /// このストレージの履歴が [`LMTHT::truncate_to()`] によって書き換えられたことがあるかを判定します。
+  pub fn is_rewritten(&self) -> Result<bool> {
+    let mut cursor = self.open_cursor(false)?;
+    if cursor.len()? <= STORAGE_IDENTIFIER.len() as u64 {
+      return Ok(false);
+    }
+    cursor.seek(io::SeekFrom::Start(STORAGE_IDENTIFIER.len() as u64))?;
+    Ok(cursor.read_u8()? & REWRITTEN_FLAG != 0)
+  }
+
+  /// インデックス `up_to` までのエントリの値 (ペイロード) をストレージから削除し、ハッシュ値と中間ノードのみを
+  /// 残します。参照されなくなった古い値のためにストレージが際限なく増加することを抑制するために使用します。
+  /// `up_to` が現在の世代を超える場合は現在の世代までを対象とします。
+  ///
+  /// 削除した値は同じ長さの 0 で置き換えられ、エントリのチェックサムは置き換えた内容に対して再計算されます。
+  /// エントリの位置は変化しないため、ルートハッシュや削除していない値の証明はそのまま検証することができます。
+  /// 削除した範囲は [`Cursor::discard()`] によって解放されるため、Linux のファイルや [`SegmentedStorage`] では
+  /// ファイルシステム上の領域が解放されます。
+  ///
+  /// 削除した値の参照 ([`Query::get()`] や値を含む証明の生成) は [`Detail::PayloadPruned`] を返します。削除された
+  /// 値は 0 のみで構成され葉ノードのハッシュ値と一致しないことで判別されるため、0 のみで構成された値は削除されずに
+  /// 参照することができます。削除の途中で中断した場合は同じ `up_to` で再び実行してください。実行前に取得した
+  /// [`Query`] も削除後の内容を参照します。
+  ///
+  /// [`LMTHTOptions::records`] を指定したストレージでは、レコードの属性 (タグ、タイムスタンプ、キー、メタデータ) を
+  /// 残して本体のみを 0 で置き換えます。削除したレコードも [`Query::iter_tagged()`] や [`Query::range_by_time()`]、
+  /// [`Query::get_by_key()`] の対象となり、属性は [`Query::get_metadata()`] などで参照できます。
+  ///
+  /// [`SegmentedStorage`]: crate::segmented::SegmentedStorage
+  ///
+  /// # Returns
+  /// この呼び出しで削除した値のバイト数の合計を返します。
+  pub fn prune_payloads(&self, up_to: Index) -> Result<u64> {
+    self.check_writable()?;
+    let writer = lock2io(self.writer.lock())?;
+    writer.check()?;
+    self.check_unsealed()?;
+    self.durability.check()?;
+    let up_to = up_to.min(self.n());
+    let mut cursor = self.open_cursor(true)?;
+    let mut position = STORAGE_IDENTIFIER.len() as u64 + 1;
+    let mut pruned = 0u64;
+    for i in 1..=up_to {
+      cursor.seek(SeekFrom::Start(position))?;
+      let (meta, inodes) = read_entry_header(&mut cursor, position, i)?;
+      let length = cursor.stream_position()? - position + 4 + 8;
+      let head = (INDEX_BYTES + 1 + inodes.len() * INODE_SIZE + 4) as u64;
+      let payload_size = length - head - HASH_SIZE as u64 - 4 - 8;
+      let start = position;
+      position += length;
+      if payload_size == 0 {
+        continue;
+      }
+
+      // 削除済みのエントリは読み飛ばす (中断によってチェックサムが一致しないエントリは再び削除する)
+      let mut entry = vec![0u8; length as usize];
+      cursor.seek(SeekFrom::Start(start))?;
+      cursor.read_exact(&mut entry)?;
+      let (body, trailer) = entry.split_at_mut(length as usize - 8);
+      let payload = &mut body[head as usize..(head + payload_size) as usize];
+      // レコード形式のストレージでは属性を残して本体のみを削除する
+      let keep = if self.records { header_length(payload).unwrap_or(0) } else { 0 };
+      let zeroed = payload[keep..].iter().all(|b| *b == 0);
+      if keep == payload.len() || (zeroed && Hash::hash(payload) == meta.hash) {
+        continue;
+      }
+      payload[keep..].fill(0);
+      let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+      std::hash::Hasher::write(&mut hasher, body);
+      let checksum = std::hash::Hasher::finish(&hasher).to_le_bytes();
+      if zeroed && trailer[..] == checksum[..] {
+        continue;
+      }
+      let (keep, discarded) = (keep as u64, payload_size - keep as u64);
+      cursor.discard(start + head + keep, discarded)?;
+      cursor.seek(SeekFrom::Start(start + length - 8))?;
+      cursor.write_all(&checksum)?;
+      pruned += discarded;
+    }
+    cursor.flush()?;
+    cursor.sync_data()?;
+
+    // 実行前に取得したクエリーのカーソルも読み込みバッファに残っている削除前の値を破棄する
+    self.rewrites.fetch_add(1, Ordering::AcqRel);
+    Ok(pruned)
+  }
+
+  /// ストレージから読み込んだ封印レコードのルートノード `root` が末尾のエントリと一致することを確認し、この LMTHT
+  /// を封印された状態とします。
+  fn check_seal(&self, root: Option<Node>) -> Result<()> {
+    if root != self.root() {
+      let msg = format!(
+        "The sealed root {} doesn't match the last entry {}.",
+        root.map(|r| r.to_string()).unwrap_or_default(),
+        self.root().map(|r| r.to_string()).unwrap_or_default()
+      );
+      return Err(DamagedStorage(msg));
+    }
+    self.sealed.store(true, Ordering::Release);
+    Ok(())
+  }
+
+  /// 指定された値をこの LMTHT に追加します。
+  ///
+  /// # Returns
+  /// この操作によって更新されたルートノードを返します。このルートノードは新しい木構造のルートハッシュである
+  /// `hash` に加えて、ハッシュ木に含まれる要素数 `i`、ハッシュ木の高さ `j` を持ちます。
+  /// [`LMTHTOptions::duplicates`] に [`DuplicatePolicy::Skip`] を指定している場合、同じ値がすでに含まれていれば
+  /// 追加せずに現在のルートノードを返します。
+  ///
+  /// # Errors
+  /// 要素数がすでに [`Index`] の最大値に達している場合は [`Detail::CapacityExhausted`] を返します。この判定は
+  /// ストレージへの出力の前に行われるため、ストレージの内容は変更されません。
+  ///
+  pub fn append(&self, value: &[u8]) -> Result<Node> {
+    let mut batch = self.begin_batch()?;
+    let root = batch.append(value)?;
+    batch.commit()?;
+    Ok(root)
+  }
+
+  /// 複数の値の追加をメモリ上に蓄積するバッチを開始します。バッチに追加した値は [`Batch::commit()`] の時点で一度の
+  /// 連続した書き込みとしてストレージに出力され、この LMTHT から参照できるようになります。コミットせずにバッチを
+  /// `drop()` した場合、追加した値は破棄されます。
+  ///
+  /// 短時間に大量の値を追加する場合、値ごとに [`LMTHT::append()`] を呼び出すよりもストレージへの小さな書き込みを
+  /// 大幅に削減することができます。
+  ///
+  /// バッチは追加のためのロックを保持するため、バッチがコミットまたは破棄されるまで他のスレッドからの追加は待機
+  /// します。
+  pub fn begin_batch(&self) -> Result<Batch<'_, S>> {
+    self.check_writable()?;
+    let writer = lock2io(self.writer.lock())?;
+    writer.check()?;
+    self.check_unsealed()?;
+    self.durability.check()?;
+    let cursor = self.open_cursor(true)?;
+    Batch::new(self, writer, cursor)
+  }
+
+  /// 指定された値の追加をストレージに出力せずに準備します。
+  ///
+  /// 返値の [`Prepared::root()`] は追加後のルートノードを表しており、外部のシステムとこのルートノードを合意した後に
+  /// [`LMTHT::commit()`] で出力するか [`LMTHT::abort()`] で破棄します。準備している間も LMTHT の内容は変化しません。
+  pub fn prepare_append(&self, value: &[u8]) -> Result<Prepared> {
+    let mut batch = self.begin_batch()?;
+    batch.append(value)?;
+    batch.prepare()
+  }
+
+  /// [`LMTHT::prepare_append()`] で準備した追加をストレージに出力します。
+  ///
+  /// 準備した後にこの LMTHT に別の値が追加されている場合、準備したルートノードは無効となっているため
+  /// [`Detail::StalePreparedAppend`] を返します。
+  ///
+  /// # Returns
+  /// 追加後のルートノードを返します。これは [`Prepared::root()`] と同じです。
+  pub fn commit(&self, prepared: Prepared) -> Result<Node> {
+    self.check_writable()?;
+    let mut writer = lock2io(self.writer.lock())?;
+    writer.check()?;
+    self.check_unsealed()?;
+    self.durability.check()?;
+    if !prepared.is_based_on(&self.latest()) {
+      return Err(StalePreparedAppend { prepared: prepared.base_n(), current: self.n() });
+    }
+    let root = prepared.root();
+    let mut cursor = self.open_cursor(true)?;
+    crate::batch::write_staged(self, &mut writer, &mut cursor, prepared.into_staged())?;
+    Ok(root)
+  }
+
+  /// [`LMTHT::prepare_append()`] で準備した追加を破棄します。準備した追加はストレージに出力されていないため、
+  /// これは `prepared` を `drop()` することと同じです。
+  pub fn abort(&self, prepared: Prepared) {
+    drop(prepared);
+  }
+
+  /// 以降にこの LMTHT へ追加されるすべての値の通知を受け取るチャネルを返します。通知は追加がストレージに出力され
+  /// クエリーから参照できるようになった時点で、追加の順序で配信されます。受信側を `drop()` すると購読は終了します。
+  ///
+  /// チャネルは最大 `capacity` 個の未受信の通知を保持します。通知が `capacity` 個を超えて溜まった購読者は購読を
+  /// 打ち切られ、残りの通知を受信した後にチャネルが切断されます。通知の失敗によって追加が失敗することはありません。
+  pub fn subscribe(&self, capacity: usize) -> Result<Receiver<Appended>> {
+    self.subscribers.subscribe(capacity)
+  }
+
+  /// `cursor` に `appended` 個のエントリを書き込んで世代 `n` となったときに、同期方針に従ってストレージを同期します。
+  pub(crate) fn sync_if_needed(
+    &self,
+    writer: &mut Writer,
+    cursor: &mut BufferedCursor<S::Cursor>,
+    appended: u64,
+    n: Index,
+  ) -> Result<()> {
+    writer.unsynced += appended;
+    let sync = match self.sync_policy {
+      SyncPolicy::Always => true,
+      SyncPolicy::EveryNAppends(n) => writer.unsynced >= n,
+      SyncPolicy::Interval(interval) => writer.last_sync.elapsed() >= interval,
+      SyncPolicy::Manual | SyncPolicy::GroupCommit { .. } => false,
+    };
+    if sync {
+      self.sync_cursor(writer, cursor, n)
+    } else {
+      self.durability.written(n)
+    }
+  }
+
+  /// これまでに追加したすべてのエントリをストレージのデバイスに同期します。このメソッドが正常に終了した時点で
+  /// 追加済みのエントリは障害によって失われることはありません。
+  pub fn sync(&self) -> Result<()> {
+    self.check_writable()?;
+    let mut writer = lock2io(self.writer.lock())?;
+    let mut cursor = self.open_cursor(true)?;
+    self.sync_cursor(&mut writer, &mut cursor, self.n())
+  }
+
+  fn sync_cursor(&self, writer: &mut Writer, cursor: &mut BufferedCursor<S::Cursor>, n: Index) -> Result<()> {
+    cursor.sync_data()?;
+    writer.unsynced = 0;
+    writer.last_sync = Instant::now();
+    self.durability.durable(n)
+  }
+
+  /// 世代 `n` までのエントリがストレージのデバイスに同期されるまで待機します。
+  ///
+  /// [`SyncPolicy::GroupCommit`] ではバックグラウンドのスレッドによる同期を待機し、それ以外の同期方針では未同期で
+  /// あればこのメソッドが同期を行います。`n` がまだ追加されていない世代の場合は
+  /// [`GenerationNotAppended`](crate::error::Detail::GenerationNotAppended) を返します。
+  pub fn wait_durable(&self, n: Index) -> Result<()> {
+    if n > self.n() {
+      return Err(GenerationNotAppended { n, current: self.n() });
+    }
+    if self.group_commit.is_none() {
+      self.check_writable()?;
+      self.durability.check()?;
+      let mut cursor = self.open_cursor(true)?;
+      cursor.sync_data()?;
+      self.durability.durable(self.n())?;
+    }
+    self.durability.wait(n)
+  }
+
+  pub fn query(&self) -> Result<Query<BufferedCursor<S::Cursor>>> {
+    if self.auto_refresh {
+      self.reload()?;
+    }
+    self.new_query()
+  }
+
+  /// 最新の世代を対象とするクエリーを作成します。
+  fn new_query(&self) -> Result<Query<BufferedCursor<S::Cursor>>> {
+    let cursor = self.open_cursor(false)?;
+    let gen = self.latest();
+    let node_cache = self.node_cache.clone();
+    let index = self.open_position_index()?;
+    let latest = self.latest_cache.clone();
+    let archives = self.archive_catalog.clone();
+    let keys = self.key_index.clone();
+    let hashes = self.hash_index.clone();
+    let bloom = self.bloom_filter.clone();
+    Ok(Query { cursor, gen, latest, node_cache, index, archives, keys, hashes, bloom })
+  }
+
+  /// 再利用可能な [`Query`] を取得します。返値は [`Query`] として使用することができ、`drop()` された時点でこの
+  /// LMTHT のプールに返却されます。プールから取得したクエリーはその時点の最新の世代を対象とします。
+  ///
+  /// リクエストごとにクエリーを作成するサーバのように [`LMTHT::query()`] を頻繁に呼び出す場合、ストレージのカーソル
+  /// をオープンするコストを削減することができます。
+  pub fn pooled_query(&self) -> Result<PooledQuery<'_, BufferedCursor<S::Cursor>>> {
+    if self.auto_refresh {
+      self.reload()?;
+    }
+    let query = match self.query_pool.take()? {
+      Some(mut query) => {
+        query.gen = self.latest();
+        query
+      }
+      None => self.query()?,
+    };
+    Ok(PooledQuery { pool: &self.query_pool, query: Some(query) })
+  }
+
+  /// インデックス `i` 以降の値を順に返す [`futures_core::Stream`] を作成します。ストリームは既存の値をすべて返した
+  /// 後、新しい値が追加されるまで待機します。値は利用側がポーリングした時点で 1 つずつ読み出されるため、処理の遅い
+  /// 下流に対して読み出しが先行してメモリを消費することはありません。
+  #[cfg(feature = "async")]
+  pub fn stream_from(&self, i: Index) -> Result<crate::stream::EntryStream<BufferedCursor<S::Cursor>>> {
+    Ok(self.query()?.into_stream(i))
+  }
+
+  /// この LMTHT が読み込み専用でオープンされている場合はエラーを返します。
+  pub(crate) fn check_writable(&self) -> Result<()> {
+    if self.read_only {
+      Err(ReadOnly)
+    } else {
+      Ok(())
+    }
+  }
+
+  /// ストレージが世代 `n` でルートハッシュ `hash` を持つ木構造、またはそれを拡張した木構造であることを検証します。
+  /// 世代 `n` の完全二分木から算出したルートハッシュが `hash` と一致すること、さらにそれらの完全二分木と世代 `n`
+  /// より後の値を覆う完全二分木から現在の世代のルートハッシュを再構成できることを確認します。いずれかが一致しない
+  /// 場合やストレージの世代が `n` に満たない場合は [`Detail::TrustedRootMismatch`] を返します。世代 0 は空の木構造
+  /// を表し、すべてのストレージと一致します。
+  pub fn verify_trusted_root(&self, n: Index, hash: &Hash) -> Result<()> {
+    if n == 0 {
+      return Ok(());
+    }
+    let mut query = self.query()?;
+    let current = query.n();
+    let mismatch = || TrustedRootMismatch { n, current };
+    if n > current {
+      return Err(mismatch());
+    }
+
+    // 世代 n の完全二分木からルートハッシュを算出
+    let mut pbsts = Vec::<(u8, Hash)>::with_capacity(INDEX_SIZE as usize);
+    for node in NthGenHashTree::new(n).pbst_roots() {
+      pbsts.push((node.j, query.get_node_hash(node.i, node.j)?.ok_or_else(mismatch)?));
+    }
+    if fold_pbsts(&pbsts) != *hash {
+      return Err(mismatch());
+    }
+
+    // 世代 n より後の値を覆う完全二分木を順に連結して現在の世代の完全二分木を構成する
+    let one: Index = 1;
+    let mut base = n;
+    while base < current {
+      let mut j = 0u8;
+      while j + 1 < INDEX_SIZE && base & ((one << (j + 1)) - 1) == 0 && one << (j + 1) <= current - base {
+        j += 1;
+      }
+      let i = base + (one << j);
+      let mut pbst = (j, query.get_node_hash(i, j)?.ok_or_else(mismatch)?);
+      while let Some((left_j, left)) = pbsts.last().copied() {
+        if left_j != pbst.0 {
+          break;
+        }
+        pbsts.pop();
+        pbst = (left_j + 1, left.combine(&pbst.1));
+      }
+      pbsts.push(pbst);
+      base = i;
+    }
+    let root = query.get_root(current)?.ok_or_else(mismatch)?;
+    if fold_pbsts(&pbsts) != root.hash {
+      return Err(mismatch());
+    }
+    Ok(())
+  }
+
+  /// 世代 `n` を参照したことを [`LMTHTOptions::watermark`] に記録します。記録されている世代が `n` より新しい場合
+  /// はストレージがロールバックされているため [`Detail::RolledBack`] を返します。
+  pub(crate) fn record_watermark(&self, n: Index) -> Result<()> {
+    if let Some(watermark) = &self.watermark {
+      let recorded = watermark.load()?;
+      if recorded > n {
+        return Err(RolledBack { recorded, current: n });
+      } else if recorded < n {
+        watermark.store(n)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// この LMTHT が封印されている場合はエラーを返します。追加のためのロックを保持している状態で呼び出す必要が
+  /// あります。
+  fn check_unsealed(&self) -> Result<()> {
+    if self.is_sealed() {
+      Err(Sealed { n: self.n() })
+    } else {
+      Ok(())
+    }
+  }
+
+  /// 追加が完了している最新の世代を参照します。
+  pub(crate) fn latest(&self) -> Arc<Cache> {
+    self.latest_cache.get()
+  }
+
+  /// 最新の世代を更新します。追加のためのロックを保持している状態で呼び出す必要があります。
+  pub(crate) fn set_latest(&self, latest: Arc<Cache>) {
+    self.latest_cache.set(latest)
+  }
+
+  /// ストレージのカーソルをオプションで指定されたサイズのバッファ付きでオープンします。
+  pub(crate) fn open_cursor(&self, writable: bool) -> Result<BufferedCursor<S::Cursor>> {
+    let cursor = self.open_storage(writable)?;
+    let cursor = BufferedCursor::new(cursor, self.read_buffer_size, self.write_buffer_size);
+    Ok(cursor.with_retry(self.retry.clone()).with_rewrites(self.rewrites.clone()))
+  }
+
+  /// ストレージのカーソルをオープンします。書き込み用のカーソルは複製元のカーソルを保持している場合はその複製です。
+  fn open_storage(&self, writable: bool) -> Result<S::Cursor> {
+    if writable {
+      if let Some(cursor) = lock2io(self.shared_cursor.lock())?.as_ref() {
+        if let Some(cursor) = cursor.duplicate()? {
+          return Ok(cursor);
+        }
+      }
+    }
+    self.storage.open(writable)
+  }
+
+  fn open_position_index(&self) -> Result<Option<Box<dyn Cursor>>> {
+    match &self.position_index {
+      Some(position_index) => position_index.open(),
+      None => Ok(None),
+    }
+  }
+}
+
+impl<S: Storage> Drop for LMTHT<S> {
+  fn drop(&mut self) {
+    // 新しい世代を待機している Follow を終了させる
+    self.latest_cache.close();
+  }
+}
+
+/// LMTHT とクエリーが共有する最新の世代と、その更新を待機するための条件変数です。
+pub(crate) struct Latest {
+  state: Mutex<LatestState>,
+  changed: Condvar,
+}
+
+struct LatestState {
+  cache: Arc<Cache>,
+  /// LMTHT が破棄されたか。
+  closed: bool,
+  /// 新しい世代を待機している非同期タスク。
+  #[cfg(feature = "async")]
+  wakers: Vec<std::task::Waker>,
+}
+
+impl Latest {
+  fn new(cache: Arc<Cache>) -> Latest {
+    let state = LatestState {
+      cache,
+      closed: false,
+      #[cfg(feature = "async")]
+      wakers: Vec::new(),
+    };
+    Latest { state: Mutex::new(state), changed: Condvar::new() }
+  }
+
+  fn get(&self) -> Arc<Cache> {
+    self.lock().cache.clone()
+  }
+
+  fn set(&self, cache: Arc<Cache>) {
+    let mut state = self.lock();
+    state.cache = cache;
+    self.notify(&mut state);
+  }
+
+  /// LMTHT が破棄され、以降に世代が更新されないことを通知します。
+  fn close(&self) {
+    let mut state = self.lock();
+    state.closed = true;
+    self.notify(&mut state);
+  }
+
+  /// 最新の世代が `n` 以上となるまで待機します。世代が `n` に達する前に LMTHT が破棄された場合は `None` を返します。
+  fn wait_for(&self, n: Index) -> Option<Arc<Cache>> {
+    let mut state = self.lock();
+    loop {
+      if state.cache.n() >= n {
+        return Some(state.cache.clone());
+      } else if state.closed {
+        return None;
+      }
+      state = self.changed.wait(state).unwrap_or_else(|err| err.into_inner());
+    }
+  }
+
+  /// 最新の世代が `n` 以上であればそれを返します。まだ `n` に達していない場合は世代の更新時に `waker` で通知される
+  /// よう登録します。
+  #[cfg(feature = "async")]
+  pub(crate) fn poll_for(&self, n: Index, waker: &std::task::Waker) -> std::task::Poll<Option<Arc<Cache>>> {
+    let mut state = self.lock();
+    if state.cache.n() >= n {
+      std::task::Poll::Ready(Some(state.cache.clone()))
+    } else if state.closed {
+      std::task::Poll::Ready(None)
+    } else {
+      if !state.wakers.iter().any(|w| w.will_wake(waker)) {
+        state.wakers.push(waker.clone());
+      }
+      std::task::Poll::Pending
+    }
+  }
+
+  #[allow(unused_variables)]
+  fn notify(&self, state: &mut LatestState) {
+    self.changed.notify_all();
+    #[cfg(feature = "async")]
+    for waker in state.wakers.drain(..) {
+      waker.wake();
+    }
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, LatestState> {
+    self.state.lock().unwrap_or_else(|err| err.into_inner())
+  }
+}
+
+/// 再利用のために返却された [`Query`] を保持するプールです。
+pub(crate) struct QueryPool<C: Cursor> {
+  capacity: usize,
+  pub(crate) queries: Mutex<Vec<Query<C>>>,
+}
+
+impl<C: Cursor> QueryPool<C> {
+  fn new(capacity: usize) -> QueryPool<C> {
+    QueryPool { capacity, queries: Mutex::new(Vec::with_capacity(capacity)) }
+  }
+
+  fn take(&self) -> Result<Option<Query<C>>> {
+    Ok(lock2io(self.queries.lock())?.pop())
+  }
+
+  /// プールしているすべてのクエリーを破棄します。
+  fn clear(&self) -> Result<()> {
+    lock2io(self.queries.lock())?.clear();
+    Ok(())
+  }
+
+  /// 指定されたクエリーをプールに返却します。プールが容量に達している場合は破棄されます。
+  fn give_back(&self, query: Query<C>) {
+    if let Ok(mut queries) = self.queries.lock() {
+      if queries.len() < self.capacity {
+        queries.push(query);
+      }
+    }
+  }
+}
+
+/// [`LMTHT::pooled_query()`] で取得した [`Query`] です。`drop()` された時点でプールに返却されます。
+pub struct PooledQuery<'a, C: Cursor> {
+  pool: &'a QueryPool<C>,
+  query: Option<Query<C>>,
+}
+
+impl<'a, C: Cursor> Deref for PooledQuery<'a, C> {
+  type Target = Query<C>;
+  fn deref(&self) -> &Query<C> {
+    self.query.as_ref().unwrap()
+  }
+}
+
+impl<'a, C: Cursor> DerefMut for PooledQuery<'a, C> {
+  fn deref_mut(&mut self) -> &mut Query<C> {
+    self.query.as_mut().unwrap()
+  }
+}
+
+impl<'a, C: Cursor> Drop for PooledQuery<'a, C> {
+  fn drop(&mut self) {
+    if let Some(query) = self.query.take() {
+      self.pool.give_back(query);
+    }
+  }
+}
+
+pub struct Query<C: Cursor = Box<dyn Cursor>> {
+  pub(crate) cursor: C,
+  pub(crate) gen: Arc<Cache>,
+  /// クエリーを作成した LMTHT の最新の世代。
+  pub(crate) latest: Arc<Latest>,
+  pub(crate) node_cache: Arc<NodeCache>,
+  pub(crate) index: Option<Box<dyn Cursor>>,
+  /// 削除された値がアーカイブされているかを参照するためのカタログ。
+  archives: Option<Arc<ArchiveCatalog>>,
+  /// キーからインデックスを参照するためのキー索引。
+  pub(crate) keys: Option<Arc<KeyIndex>>,
+  /// 値のハッシュ値からインデックスを参照するためのハッシュ索引。
+  pub(crate) hashes: Option<Arc<HashIndex>>,
+  /// 値が含まれていないことを判定するためのブルームフィルタ。
+  pub(crate) bloom: Option<Arc<BloomFilter>>,
+}
+
+impl<C: Cursor> Query<C> {
+  /// このクエリーが対象としている木構造の世代を参照します。
+  pub fn n(&self) -> Index {
+    self.gen.n()
+  }
+
+  /// このクエリーの対象をクエリーを作成した LMTHT の最新の世代に更新します。ストレージのカーソルやキャッシュは
+  /// そのまま再利用されるため、長期間保持しているクエリーで追加されたエントリを参照するために使用できます。
+  ///
+  /// 他のプロセスが追加したエントリは [`LMTHT::reload()`] で LMTHT に取り込まれた後に参照できるようになります。
+  ///
+  /// # Returns
+  /// 対象の世代が更新された場合に true を返します。
+  pub fn refresh(&mut self) -> bool {
+    let latest = self.latest.get();
+    if Arc::ptr_eq(&latest, &self.gen) {
+      false
+    } else {
+      self.gen = latest;
+      true
+    }
+  }
+
+  /// インデックス `from` 以降の値を順に返すイテレータを作成します。イテレータは既存の値をすべて返した後、新しい値が
+  /// 追加されるまで待機してそれを返します (`tail -f` と同様)。クエリーを作成した LMTHT が破棄された時点でイテレータ
+  /// は終了します。
+  ///
+  /// 待機は同じ LMTHT への追加と [`LMTHT::reload()`] による更新で解除されます。他のプロセスによる追加を待機する
+  /// 場合は別のスレッドで定期的に [`LMTHT::reload()`] を呼び出してください。
+  pub fn follow(&mut self, from: Index) -> Follow<'_, C> {
+    Follow { query: self, next: from.max(1) }
+  }
+
+  /// 非同期に [`Query::follow()`] と同様の読み出しを行う [`EntryStream`](crate::stream::EntryStream) に変換します。
+  #[cfg(feature = "async")]
+  pub fn into_stream(self, from: Index) -> crate::stream::EntryStream<C> {
+    crate::stream::EntryStream::new(self, from.max(1))
+  }
+
+  /// このクエリーの世代に含まれているインデックス `i` の値を読み出します。
+  pub(crate) fn get_value(&mut self, i: Index) -> Result<Value> {
+    match self.get(i)? {
+      Some(value) => Ok(Value::new(i, value)),
+      None => inconsistency(format!("the entry i={} is not found in generation {}", i, self.n())),
+    }
+  }
+
+  /// 削除された値がアーカイブされている場合は [`Detail::PayloadPruned`] を [`Detail::Archived`] に置き換えます。
+  pub(crate) fn archived<T>(&self, result: Result<T>) -> Result<T> {
+    match (result, &self.archives) {
+      (Err(PayloadPruned { i }), Some(archives)) => match archives.find(i)? {
+        Some(range) => Err(Archived { i, from: range.from, to: range.to }),
+        None => Err(PayloadPruned { i }),
+      },
+      (result, _) => result,
+    }
+  }
+
+  /// 世代 `n`、つまり値 b_n を追加した時点の木構造のルートノードを取得します。範囲外の世代 (0 を含む) を指定した
+  /// 場合は `None` を返します。
+  pub fn get_root(&mut self, n: Index) -> Result<Option<Node>> {
+    if n == 0 || n > self.n() {
+      return Ok(None);
+    }
+    let gen = self.gen.as_ref();
+    let position = Self::get_entry_position(gen, &self.node_cache, &mut self.index, &mut self.cursor, n, false)?;
+    let position = match position {
+      Some((position, _)) => position,
+      None => return Ok(None),
+    };
+    self.cursor.seek(io::SeekFrom::Start(position))?;
+    let entry = read_entry(&mut self.cursor, n)?;
+    let root = entry.inodes.last().map(|inode| &inode.meta).unwrap_or(&entry.enode.meta);
+    Ok(Some(Node::for_node(root)))
+  }
+
+  /// 範囲外のインデックス (0 を含む) を指定した場合は `None` を返します。
+  pub fn get(&mut self, i: Index) -> Result<Option<Vec<u8>>> {
+    let start = Instant::now();
+    if let Some(node) = Self::get_node(self.gen.as_ref(), &self.node_cache, &mut self.index, &mut self.cursor, i, 0)? {
+      self.node_cache.metrics.seek();
+      self.cursor.seek(io::SeekFrom::Start(node.address.position))?;
+      let entry = read_entry_without_check(&mut self.cursor, node.address.position, node.address.i)?;
+      let Entry { enode: ENode { meta, payload }, .. } = entry;
+      self.archived(check_pruned(&meta, &payload))?;
+      self.node_cache.metrics.read(payload.len() as u64, start.elapsed());
+      Ok(Some(payload))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// 葉ノード b_i の値を中間ノードのハッシュ値付きで取得します。
+  #[inline]
+  pub fn get_with_hashes(&mut self, i: Index) -> Result<Option<ValuesWithBranches>> {
+    self.get_values_with_hashes(i, 0)
+  }
+
+  /// 指定されたノード b_{i,j} をルートとする部分木に含まれているすべての値 (葉ノード) を中間ノードのハッシュ値
+  /// 付きで取得します。この結果から算出されるルートハッシュを使用して、値のデータが破損や改ざんされていないことを
+  /// 検証することができます。
+  ///
+  /// # Returns
+  /// 返値には範囲に含まれる 1 個以上の値と、b_{i,j} への経路から分岐したノードが含まれています。ここで得られる
+  /// 値の範囲は [model::range(i,j)](range) を使って算出することができます。b_{i,j} をルートとする
+  /// [部分木が完全二分木](crate::model::is_pbst) の場合、返値の数は `1 << j` 個になります。完全二分木でない場合は
+  /// `1 << j` より少ない個数となります。
+  ///
+  /// `i` に 0 を含む範囲外のインデックスを指定した場合は `None` を返します。
+  ///
+  /// # Example
+  /// ```rust
+  /// use lmtht::{LMTHT, MemStorage, Hash};
+  /// use lmtht::model::{range, is_pbst};
+  ///
+  /// let db = LMTHT::new(MemStorage::new()).unwrap();
+  /// let mut latest_root_hash = Hash::hash(&vec![]);
+  /// for i in 0u32..100 {
+  ///   let current_root = db.append(&i.to_le_bytes()).unwrap();
+  ///   latest_root_hash = current_root.hash;
+  /// }
+  /// let mut query = db.query().unwrap();
+  /// let values = query.get_values_with_hashes(40, 3).unwrap().unwrap();
+  /// assert!(is_pbst(40, 3));
+  /// assert_eq!(1 << 3, values.values.len());
+  /// assert_eq!(*range(40, 3).start(), values.values[0].i);
+  /// assert_eq!(*range(40, 3).end(), values.values[(1 << 3) - 1].i);
+  /// assert_eq!(latest_root_hash, values.root().hash);
+  /// ```
+  ///
+  pub fn get_values_with_hashes(&mut self, i: Index, j: u8) -> Result<Option<ValuesWithBranches>> {
+    let (start, before) = (Instant::now(), self.cursor.io_counts());
+    let result = self.values_with_hashes(i, j);
+    let result = self.archived(result);
+    self.node_cache.report_if_slow(OperationKind::Proof, start, self.cursor.io_counts().since(&before));
+    result
+  }
+
+  /// 範囲 `range` に含まれるすべての値を取得し、それらが現在の世代のルートハッシュ `expected_root` の木構造に含ま
+  /// れていることを検証します。値は範囲を覆う完全二分木ごとに中間ノードのハッシュ値付きで取得され、それぞれから算出
+  /// したルートハッシュがすべて `expected_root` と一致した場合にのみインデックスの順に返されます。
+  ///
+  /// `expected_root` はこのクエリの世代 [`Query::n()`] のルートハッシュである必要があります。過去の世代の
+  /// ルートハッシュに対して検証する場合はその世代で取得したクエリを使用してください。
+  ///
+  /// # Errors
+  /// 範囲が空の場合や 1 から現在の世代までに含まれない場合は [`Detail::InvalidRange`] を、算出したルートハッシュが
+  /// `expected_root` と一致しない場合は [`Detail::RangeVerificationFailed`] を返します。
+  ///
+  /// # Example
+  /// ```rust
+  /// use lmtht::{LMTHT, MemStorage};
+  ///
+  /// let db = LMTHT::new(MemStorage::new()).unwrap();
+  /// for i in 0u32..100 {
+  ///   db.append(&i.to_le_bytes()).unwrap();
+  /// }
+  /// let root = db.root_hash().unwrap();
+  /// let values = db.query().unwrap().verify_range(10..=20, &root).unwrap();
+  /// assert_eq!(11, values.len());
+  /// assert_eq!(9u32.to_le_bytes().to_vec(), values[0].value);
+  /// ```
+  ///
+  pub fn verify_range(&mut self, range: RangeInclusive<Index>, expected_root: &Hash) -> Result<Vec<Value>> {
+    let (i0, i1, n) = (*range.start(), *range.end(), self.n());
+    if i0 == 0 || i0 > i1 || i1 > n {
+      return Err(InvalidRange { i0, i1, n });
+    }
+    let failed = || RangeVerificationFailed { i0, i1 };
+    let one: Index = 1;
+    let mut values = Vec::with_capacity(min(i1 - i0 + 1, 1024) as usize);
+    let mut base = i0 - 1;
+    while base < i1 {
+      // base の直後から始まり i1 を超えない最大の完全二分木 b_{base+2^j,j}
+      let mut j = 0u8;
+      while j + 1 < INDEX_SIZE && base & ((one << (j + 1)) - 1) == 0 && one << (j + 1) <= i1 - base {
+        j += 1;
+      }
+      let i = base + (one << j);
+      let proof = self.get_values_with_hashes(i, j)?.ok_or_else(failed)?;
+      let root = proof.root();
+      if root.i != n || root.hash != *expected_root || proof.values.len() as Index != one << j {
+        return Err(failed());
+      }
+      for (k, value) in proof.values.into_iter().enumerate() {
+        if value.i != base + 1 + k as Index {
+          return Err(failed());
+        }
+        values.push(value);
+      }
+      base = i;
+    }
+    Ok(values)
+  }
+
+  fn values_with_hashes(&mut self, i: Index, j: u8) -> Result<Option<ValuesWithBranches>> {
+    let (last_inodes, model) = if let Some(CacheInner { last_inodes, model, .. }) = &self.gen.0 {
+      if i == 0 || i > model.n() {
+        return Ok(None);
+      }
+      (last_inodes, model)
+    } else {
+      return Ok(None);
+    };
+    let root = match self.gen.root_ref() {
+      RootRef::INode(inode) => *inode,
+      RootRef::ENode(enode) => {
+        self.cursor.seek(SeekFrom::Start(enode.meta.address.position))?;
+        let Entry { enode: ENode { meta, payload }, .. } =
+          read_entry_without_check(&mut self.cursor, enode.meta.address.position, i)?;
+        check_pruned(&meta, &payload)?;
+        return Ok(Some(ValuesWithBranches { values: vec![Value { i, value: payload }], branches: vec![] }));
+      }
+      RootRef::None => return Ok(None),
+    };
+    let path = match self.node_cache.path(model, i, j)? {
+      Some(path) => path,
+      None => return Ok(None),
+    };
+    debug_assert_eq!(model.root().i, root.meta.address.i);
+    debug_assert_eq!(model.root().j, root.meta.address.j);
+
+    // 目的のノードまで経路を移動しながら分岐のハッシュ値を取得する
+    let mut prev = root;
+    let mut inodes = last_inodes.clone();
+    let mut branches = Vec::<Node>::with_capacity(INDEX_SIZE as usize);
+    for step in path.steps.iter().map(|s| s.step) {
+      // 左枝側のエントリの INode を読み込み (右枝側のノードは inodes に含まれている)
+      let left_inodes = self.node_cache.read_inodes(&mut self.cursor, prev.left.position)?;
+
+      // 左右どちらの枝が次のノードでどちらが分岐のノードかを判断
+      let (next, next_inodes, branch, branch_inodes) = if prev.left.i == step.i && prev.left.j == step.j {
+        (&prev.left, left_inodes, &prev.right, inodes)
+      } else {
+        debug_assert!(prev.right.i == step.i && prev.right.j == step.j);
+        (&prev.right, inodes, &prev.left, left_inodes)
+      };
+
+      // 分岐したノードのハッシュ値付きの情報を保存
+      if branch.j > 0 {
+        // INode として分岐したノードを参照して保存
+        if let Some(inode) = branch_inodes.iter().find(|n| n.meta.address.j == branch.j) {
+          debug_assert!(inode.meta.address == *branch);
+          branches.push(Node::for_node(&inode.meta));
+        } else {
+          return inconsistency(format!(
+            "in searching for b_{{{},{}}} in T_{}, branch inode b_{{{}, {}}} isn't included in {:?}",
+            i,
+            j,
+            self.n(),
+            branch.i,
+            branch.j,
+            branch_inodes
+          ));
+        }
+      } else {
+        // ENode として分岐したノードのハッシュ値をペイロードを読み込まずに保存
+        self.cursor.seek(SeekFrom::Start(branch.position))?;
+        let (meta, _) = read_entry_header(&mut self.cursor, branch.position, branch.i)?;
+        branches.push(Node::for_node(&meta));
+      }
+
+      if next.j == 0 {
+        debug_assert_eq!((i, j), (next.i, next.j), "branch={:?}", branch);
+        self.cursor.seek(SeekFrom::Start(next.position))?;
+        let Entry { enode: ENode { meta, payload }, .. } =
+          read_entry_without_check(&mut self.cursor, next.position, next.i)?;
+        check_pruned(&meta, &payload)?;
+        let values = vec![Value { i: next.i, value: payload }];
+        return Ok(Some(ValuesWithBranches::new(values, branches)));
+      }
+
+      // 次のノードに移動
+      if let Some(inode) = next_inodes.iter().find(|node| node.meta.address == *next) {
+        prev = *inode;
+        inodes = next_inodes;
+      } else {
+        return inconsistency(format!(
+          "in searching for ({},{}), the inode ({}, {}) on the route isn't included in {:?}",
+          i, j, next.i, next.j, next_inodes
+        ));
+      }
+    }
+
+    // 目的のノードに含まれている値を取得する
+    let values = self.get_values_belonging_to(&prev)?;
+    Ok(Some(ValuesWithBranches::new(values, branches)))
+  }
+
+  /// このクエリの世代に含まれるノード b_{i,j} のハッシュ値をストレージから参照します。
+  fn get_node_hash(&mut self, i: Index, j: u8) -> Result<Option<Hash>> {
+    let node = Self::get_node(self.gen.as_ref(), &self.node_cache, &mut self.index, &mut self.cursor, i, j)?;
+    Ok(node.map(|meta| meta.hash))
+  }
+
+  fn get_node(
+    gen: &Cache,
+    node_cache: &NodeCache,
+    index: &mut Option<Box<dyn Cursor>>,
+    cursor: &mut C,
+    i: Index,
+    j: u8,
+  ) -> Result<Option<MetaInfo>> {
+    if let Some((position, _)) = Self::get_entry_position(gen, node_cache, index, cursor, i, false)? {
+      if j == 0 {
+        cursor.seek(io::SeekFrom::Start(position))?;
+        let (meta, _) = read_entry_header(cursor, position, i)?;
+        Ok(Some(meta))
+      } else {
+        let inodes = node_cache.read_inodes(cursor, position)?;
+        Ok(inodes.iter().find(|inode| inode.meta.address.j == j).map(|inode| inode.meta))
+      }
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// 指定された `inode` をルートとする部分木に含まれているすべての値を参照します。読み出し用のカーソルは `inode`
+  /// の位置を指している必要はありません。
+  fn get_values_belonging_to(&mut self, inode: &INode) -> Result<Vec<Value>> {
+    // inode を左枝方向に葉に到達するまで移動
+    let mut mover = *inode;
+    while mover.left.j > 0 {
+      let inodes = self.node_cache.read_inodes(&mut self.cursor, mover.left.position)?;
+      mover = match inodes.iter().find(|node| node.meta.address.j == mover.left.j) {
+        Some(inode) => *inode,
+        None => panic!(),
+      };
+    }
+
+    let range = range(inode.meta.address.i, inode.meta.address.j);
+    let (i0, i1) = (*range.start(), *range.end());
+    let mut values = Vec::<Value>::with_capacity((i1 - i0) as usize);
+    let mut i = mover.left.i;
+    self.cursor.seek(SeekFrom::Start(mover.left.position))?;
+    while i <= i1 {
+      let Entry { enode: ENode { meta: node, payload }, .. } = read_entry_without_check_to_end(&mut self.cursor, i)?;
+      debug_assert!(node.address.i == i);
+      check_pruned(&node, &payload)?;
+      values.push(Value { i, value: payload });
+      i += 1;
+    }
+    Ok(values)
+  }
+
+  /// `i` 番目のエントリの位置を参照します。この検索は現在のルートノードを基準にした探索を行います。ただし位置索引
+  /// が使用可能で分岐のノードを必要としない場合は位置索引から参照します。
+  pub(crate) fn get_entry_position(
+    gen: &Cache,
+    node_cache: &NodeCache,
+    index: &mut Option<Box<dyn Cursor>>,
+    cursor: &mut C,
+    i: Index,
+    with_branch: bool,
+  ) -> Result<Option<(u64, Vec<MetaInfo>)>> {
+    if let Some(index) = index {
+      if !with_branch {
+        return if i == 0 || i > gen.n() { Ok(None) } else { Ok(Some((crate::index::lookup(index, i)?, vec![]))) };
+      }
+    }
+    if !with_branch && i != 0 && i <= gen.n() {
+      if let Some(position) = node_cache.position(i)? {
+        return Ok(Some((position, vec![])));
+      }
+    }
+    match &gen.root_ref() {
+      RootRef::INode(root) => {
+        let root = (*root).clone();
+        let result = search_entry_position(cursor, node_cache, &root, i, with_branch)?;
+        if let Some((position, _)) = &result {
+          node_cache.put_position(i, *position)?;
+        }
+        Ok(result)
+      }
+      RootRef::ENode(root) if root.meta.address.i == i => Ok(Some((root.meta.address.position, vec![]))),
+      _ => Ok(None),
+    }
+  }
+}
+
+/// [`Query::follow()`] で作成した、既存の値を返した後に新しい値の追加を待機するイテレータです。
+pub struct Follow<'a, C: Cursor> {
+  query: &'a mut Query<C>,
+  /// 次に返す値のインデックス。
+  next: Index,
+}
+
+impl<'a, C: Cursor> Iterator for Follow<'a, C> {
+  type Item = Result<Value>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.next > self.query.n() {
+      // 次の値が追加されるまで待機
+      self.query.gen = self.query.latest.wait_for(self.next)?;
+    }
+    let i = self.next;
+    self.next += 1;
+    Some(self.query.get_value(i))
+  }
+}
+
+/// 世代 `latest` の次に `position` へ追加する値のエントリを構築します。左枝側のノードは `cursor` から読み込みます。
+///
+/// # Returns
+/// 構築したエントリとその世代のモデル、および追加後のルートノードを返します。
+pub(crate) fn build_entry<C: Cursor>(
+  latest: &Cache,
+  node_cache: &NodeCache,
+  index: &mut Option<Box<dyn Cursor>>,
+  cursor: &mut C,
+  position: u64,
+  value: &[u8],
+) -> Result<(Entry, NthGenHashTree, Node)> {
+  if value.len() > MAX_PAYLOAD_SIZE {
+    return Err(TooLargePayload { size: value.len() });
+  }
+
+  // 葉ノードの構築
+  let i = match latest.n().checked_add(1) {
+    Some(i) => i,
+    None => return Err(CapacityExhausted { n: latest.n() }),
+  };
+  let hash = Hash::hash(value);
+  let enode = ENode { meta: MetaInfo::new(Address::new(i, 0, position), hash), payload: Vec::from(value) };
+
+  // 中間ノードの構築
+  let mut inodes = Vec::<INode>::with_capacity(INDEX_SIZE as usize);
+  let mut right_hash = enode.meta.hash;
+  let gen = NthGenHashTree::new(i);
+  let mut right_to_left_inodes = gen.inodes();
+  right_to_left_inodes.reverse();
+  for n in right_to_left_inodes.iter() {
+    debug_assert_eq!(i, n.node.i);
+    debug_assert_eq!(n.node.i, n.right.i);
+    debug_assert!(n.node.j >= n.right.j + 1);
+    debug_assert!(n.left.j >= n.right.j);
+    let left = Query::get_node(latest, node_cache, index, cursor, n.left.i, n.left.j)?;
+    if let Some(left) = left {
+      let right = Address::new(n.right.i, n.right.j, position);
+      let hash = left.hash.combine(&right_hash);
+      let node = MetaInfo::new(Address::new(n.node.i, n.node.j, position), hash);
+      let inode = INode::new(node, left.address, right);
+      inodes.push(inode);
+      right_hash = hash;
+    } else {
+      // 内部の木構造とストレージ上のデータが矛盾している
+      return inconsistency(format!("cannot find the node b_{{{},{}}}", n.left.i, n.left.j));
+    }
+  }
+
+  // 返値のための高さとルートハッシュを取得
+  let (j, root_hash) =
+    if let Some(inode) = inodes.last() { (inode.meta.address.j, inode.meta.hash) } else { (0u8, enode.meta.hash) };
+
+  Ok((Entry { enode, inodes }, gen, Node::new(i, j, root_hash)))
+}
+
+/// 指定されたカーソルの現在の位置からエントリを読み込みます。
+/// 正常終了時のカーソルは次のエントリを指しています。
+pub(crate) fn read_entry<C>(r: &mut C, i_expected: Index) -> Result<Entry>
+where
+  C: io::Read + io::Seek,
+{
+  let position = r.stream_position()?;
+  read_entry_from(r, position, i_expected)
+}
+
+/// 位置 `position` から始まるエントリを `r` から順に読み込み、トレイラーのオフセットとチェックサムを検証します。
+/// シークを行わないため、パイプのような逐次的な入力からエントリを読み込むために使用できます。
+pub(crate) fn read_entry_from(r: &mut dyn io::Read, position: u64, i_expected: Index) -> Result<Entry> {
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  let mut r = HashRead::new(r, &mut hasher);
+  let entry = read_entry_without_check(&mut r, position, i_expected)?;
+
+  // オフセットの検証
+  let offset = r.length();
+  let trailer_offset = r.read_u32::<LittleEndian>()?;
+  if offset != trailer_offset as u64 {
+    return Err(IncorrectEntryHeadOffset { expected: trailer_offset, actual: offset });
+  }
+
+  // チェックサムの検証
+  let checksum = r.finish();
+  let trailer_checksum = r.read_u64::<LittleEndian>()?;
+  if checksum != trailer_checksum {
+    let length = offset as u32 + 4 + 8;
+    return Err(ChecksumVerificationFailed { at: position, length, expected: trailer_checksum, actual: checksum });
+  }
+
+  Ok(entry)
+}
+
+/// 指定されたカーソルの現在の位置から checksum による検証なしでエントリを読み込みます。正常終了時のカーソルの位置は
+/// 次のエントリの戦闘を指しています。
+fn read_entry_without_check_to_end<C>(r: &mut C, i_expected: Index) -> Result<Entry>
+where
+  C: io::Read + io::Seek,
+{
+  let position = r.stream_position()?;
+  let entry = read_entry_without_check(r, position, i_expected)?;
+  r.seek(SeekFrom::Current(4 /* offset */ + 8 /* checksum */))?;
+  Ok(entry)
+}
+
+/// 指定されたカーソルの現在の位置からエントリを読み込みます。トレイラーの offset と checksum は読み込まれない
+/// ため、正常終了時のカーソルは offset の位置を指しています。
+pub(crate) fn read_entry_without_check(r: &mut dyn io::Read, position: u64, i_expected: Index) -> Result<Entry> {
+  let mut hash = [0u8; HASH_SIZE];
+
+  // 中間ノードの読み込み
+  let inodes = read_inodes(r, position)?;
+  let i = inodes.first().map(|inode| inode.meta.address.i).unwrap_or(1);
+  if i != i_expected && i_expected != 0 {
+    return Err(Detail::IncorrectNodeBoundary { at: position });
+  }
+
+  // 葉ノードの読み込み
+  let payload_size = r.read_u32::<LittleEndian>()? & MAX_PAYLOAD_SIZE as u32;
+  let mut payload = Vec::<u8>::with_capacity(payload_size as usize);
+  unsafe { payload.set_len(payload_size as usize) };
+  r.read_exact(&mut payload)?;
+  r.read_exact(&mut hash)?;
+  let enode = ENode { meta: MetaInfo::new(Address::new(i, 0, position), Hash::new(hash)), payload };
+
+  Ok(Entry { enode, inodes })
+}
+
+/// 指定されたカーソルの現在の位置からペイロードを除いたエントリを読み込みます。ペイロードはシークによって読み飛ばす
+/// ため、ハッシュ値のみを必要とする探索でペイロードの読み込みとメモリの確保を行いません。返値は葉ノードの属性情報と
+/// 中間ノードです。正常終了時のカーソルは [`read_entry_without_check()`] と同様に offset の位置を指しています。
+pub(crate) fn read_entry_header<C>(r: &mut C, position: u64, i_expected: Index) -> Result<(MetaInfo, Vec<INode>)>
+where
+  C: io::Read + io::Seek,
+{
+  let mut hash = [0u8; HASH_SIZE];
+
+  // 中間ノードの読み込み
+  let inodes = read_inodes(r, position)?;
+  let i = inodes.first().map(|inode| inode.meta.address.i).unwrap_or(1);
+  if i != i_expected && i_expected != 0 {
+    return Err(Detail::IncorrectNodeBoundary { at: position });
+  }
+
+  // ペイロードを読み飛ばして葉ノードのハッシュ値を読み込み
+  let payload_size = r.read_u32::<LittleEndian>()? & MAX_PAYLOAD_SIZE as u32;
+  r.seek(SeekFrom::Current(payload_size as i64))?;
+  r.read_exact(&mut hash)?;
+  let meta = MetaInfo::new(Address::new(i, 0, position), Hash::new(hash));
+
+  Ok((meta, inodes))
+}
+
+/// 指定されたカーソルの現在の位置をエントリの先頭としてすべての `INode` を読み込みます。正常終了した場合、カーソル
+/// 位置は最後の `INode` を読み込んだ直後を指しています。
+pub(crate) fn read_inodes(r: &mut dyn io::Read, position: u64) -> Result<Vec<INode>> {
+  let mut hash = [0u8; HASH_SIZE];
+  let i = read_index(r)?;
+  let inode_count = r.read_u8()?;
+  let mut right_j = 0u8;
+  let mut inodes = Vec::<INode>::with_capacity(inode_count as usize);
+  for _ in 0..inode_count as usize {
+    let j = (r.read_u8()? & (INDEX_SIZE - 1)) + 1; // 下位 6-bit のみを使用
+    let left_position = r.read_u64::<LittleEndian>()?;
+    let left_i = read_index(r)?;
+    let left_j = r.read_u8()?;
+    r.read_exact(&mut hash)?;
+    inodes.push(INode {
+      meta: MetaInfo::new(Address::new(i, j, position), Hash::new(hash)),
+      left: Address::new(left_i, left_j, left_position),
+      right: Address::new(i, right_j, position),
+    });
+    right_j = j;
+  }
+  Ok(inodes)
+}
+
+/// 左から順に並んだ完全二分木のルートハッシュを右から連結して木構造のルートハッシュを算出します。
+fn fold_pbsts(pbsts: &[(u8, Hash)]) -> Hash {
+  let mut pbsts = pbsts.iter().rev().map(|(_, hash)| *hash);
+  let last = pbsts.next().unwrap_or_else(|| Hash::hash(&[]));
+  pbsts.fold(last, |right, left| left.combine(&right))
+}
+
+/// 葉ノード `meta` の値 `payload` が [`LMTHT::prune_payloads()`] によって削除されている場合に
+/// [`Detail::PayloadPruned`] を返します。削除された値は 0 で置き換えられているためハッシュ値と一致しません。
+pub(crate) fn check_pruned(meta: &MetaInfo, payload: &[u8]) -> Result<()> {
+  // レコード形式のストレージで削除した値は属性を残している
+  let body = &payload[header_length(payload).unwrap_or(0)..];
+  if !body.is_empty() && body.iter().all(|b| *b == 0) && Hash::hash(payload) != meta.hash {
+    return Err(PayloadPruned { i: meta.address.i });
+  }
+  Ok(())
+}
+
+/// 指定されたカーソルにエントリを書き込みます。
+/// このエントリに対して書き込みが行われた長さを返します。
+pub(crate) fn write_entry(w: &mut dyn Write, e: &Entry) -> Result<usize> {
+  debug_assert!(e.enode.payload.len() <= MAX_PAYLOAD_SIZE);
+  debug_assert!(e.inodes.len() <= 0xFF);
+
+  // 中間ノードと葉ノードのペイロード長の直列化
+  let mut head = Vec::with_capacity(INDEX_BYTES + 1 + e.inodes.len() * INODE_SIZE + 4);
+  head.write_all(&e.enode.meta.address.i.to_le_bytes())?;
+  head.write_u8(e.inodes.len() as u8)?;
+  for i in &e.inodes {
+    debug_assert_eq!((i.meta.address.j - 1) & (INDEX_SIZE - 1), i.meta.address.j - 1);
+    head.write_u8((i.meta.address.j - 1) & (INDEX_SIZE - 1))?; // 下位 6-bit のみ保存
+    head.write_u64::<LittleEndian>(i.left.position)?;
+    head.write_all(&i.left.i.to_le_bytes())?;
+    head.write_u8(i.left.j)?;
+    head.write_all(&i.meta.hash.value)?;
+  }
+  head.write_u32::<LittleEndian>(e.enode.payload.len() as u32)?;
+
+  // 葉ノードのハッシュ値、エントリ先頭までのオフセット、チェックサムの直列化
+  let length = head.len() + e.enode.payload.len() + HASH_SIZE + 4 + 8;
+  let mut tail = Vec::with_capacity(HASH_SIZE + 4 + 8);
+  tail.write_all(&e.enode.meta.hash.value)?;
+  tail.write_u32::<LittleEndian>((length - 4 - 8) as u32)?;
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  for buf in [&head[..], &e.enode.payload, &tail] {
+    std::hash::Hasher::write(&mut hasher, buf);
+  }
+  tail.write_u64::<LittleEndian>(std::hash::Hasher::finish(&hasher))?;
+
+  // ペイロードを複製せずに一度の書き込みで出力
+  write_all_vectored(w, &[&head, &e.enode.payload, &tail])?;
+  Ok(length)
+}
+
+/// 指定されたバッファを連結した内容を `write_vectored()` で書き込みます。一度の呼び出しですべてを書き込めなかった
+/// 場合は残りを一つの連続したバッファに連結して書き込みます。
+fn write_all_vectored(w: &mut dyn Write, bufs: &[&[u8]]) -> io::Result<()> {
+  let length = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+  let slices = bufs.iter().map(|buf| io::IoSlice::new(buf)).collect::<Vec<_>>();
+  let written = loop {
+    match w.write_vectored(&slices) {
+      Ok(written) => break written,
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+      Err(err) => return Err(err),
+    }
+  };
+  if written < length {
+    let mut rest = Vec::with_capacity(length - written);
+    let mut skip = written;
+    for buf in bufs {
+      if skip >= buf.len() {
+        skip -= buf.len();
+      } else {
+        rest.extend_from_slice(&buf[skip..]);
+        skip = 0;
+      }
+    }
+    w.write_all(&rest)?;
+  }
+  Ok(())
+}
+
+/// `root` に指定された中間ノードを部分木構造のルートとして b_{i,*} に該当する葉ノードと中間ノードを含んでいる
+/// エントリのストレージ内での位置を取得します。該当するエントリが存在しない場合は `None` を返します。
+///
+/// `with_branch` に true を指定した場合、返値には `root` から検索対象のノードに至るまでの分岐先のハッシュ値を
+/// 持つノードが含まれます。これはハッシュツリーからハッシュ付きで値を参照するための動作です。false を指定した場合は
+/// 長さ 0 の `Vec` を返します。
+///
+fn search_entry_position<C>(
+  r: &mut C,
+  node_cache: &NodeCache,
+  root: &INode,
+  i: Index,
+  with_branch: bool,
+) -> Result<Option<(u64, Vec<MetaInfo>)>>
+where
+  C: io::Read + io::Seek,
+{
+  if root.meta.address.i == i {
+    // 指定されたルートノードが検索対象のノードの場合
+    return Ok(Some((root.meta.address.position, vec![])));
+  } else if i == 0 || i > root.meta.address.i {
+    // インデックス 0 の特殊値を持つノードは明示的に存在しない
+    return Ok(None);
+  }
+
+  let mut branches = Vec::<MetaInfo>::with_capacity(INDEX_SIZE as usize);
+  let mut mover = root.clone();
+  for _ in 0..INDEX_SIZE {
+    // 次のノードのアドレスを参照
+    let next = if i <= mover.left.i {
+      read_branch(r, node_cache, &mover.right, with_branch, &mut branches)?;
+      mover.left
+    } else if i <= mover.meta.address.i {
+      read_branch(r, node_cache, &mover.left, with_branch, &mut branches)?;
+      mover.right
+    } else {
+      // 有効範囲外
+      return Ok(None);
+    };
+
+    // 次のノードのアドレスが検索対象ならそのエントリの位置を返す
+    if next.i == i {
+      return Ok(Some((next.position, branches)));
+    }
+
+    // 末端に到達している場合は発見できなかったことを意味する
+    if next.j == 0 {
+      return Ok(None);
+    }
+
+    // b_{i,*} の中間ノードをロードして次の中間ノードを取得
+    mover = read_inode(r, node_cache, &next)?;
+  }
+
+  fn read_inode<C>(r: &mut C, node_cache: &NodeCache, addr: &Address) -> Result<INode>
+  where
+    C: io::Read + io::Seek,
+  {
+    debug_assert_ne!(0, addr.j);
+    let inodes = node_cache.read_inodes(r, addr.position)?;
+    let inode = inodes.iter().find(|inode| inode.meta.address.j == addr.j);
+    if let Some(inode) = inode {
+      Ok(inode.clone())
+    } else {
+      // 内部の木構造とストレージ上のデータが矛盾している
+      inconsistency(format!("entry i={} in storage doesn't contain an inode at specified level j={}", addr.i, addr.j))
+    }
+  }
+
+  fn read_branch<C>(
+    r: &mut C,
+    node_cache: &NodeCache,
+    addr: &Address,
+    with_branch: bool,
+    branches: &mut Vec<MetaInfo>,
+  ) -> Result<()>
+  where
+    C: io::Read + io::Seek,
+  {
+    if with_branch {
+      let branch = if addr.j == 0 {
+        r.seek(io::SeekFrom::Start(addr.position))?;
+        read_entry_header(r, addr.position, addr.i)?.0
+      } else {
+        read_inode(r, node_cache, &addr)?.meta
+      };
+      branches.push(branch);
+    }
+    Ok(())
+  }
+
+  // ストレージ上のデータのポインタが循環参照を起こしている
+  inconsistency(format!(
+    "The maximum hop count was exceeded before reaching node b_{} from node b_{{{},{}}}.\
+     The data on the storage probably have circular references.",
+    i, root.meta.address.i, root.meta.address.j
+  ))
+}
+
+/// 長さ `length` のストレージの先頭に LMTHT の識別子と互換性のあるバージョンが記録されていることを確認します。
+pub(crate) fn check_header<C: Read + Seek>(cursor: &mut C, length: u64) -> Result<()> {
+  if length < 4 {
+    return Err(FileIsNotContentsOfLMTHTree { message: "bad magic number" });
+  }
+  // マジックナンバーの確認
+  let mut buffer = [0u8; 4];
+  cursor.seek(io::SeekFrom::Start(0))?;
+  cursor.read_exact(&mut buffer)?;
+  if buffer[..3] != STORAGE_IDENTIFIER[..] {
+    return Err(FileIsNotContentsOfLMTHTree { message: "bad magic number" });
+  } else if buffer[3] & INDEX_WIDTH_MASK != INDEX_WIDTH {
+    return Err(IncompatibleIndexSize { expected: INDEX_SIZE, actual: index_size_of(buffer[3]) });
+  } else if !is_version_compatible(buffer[3]) {
+    let version = buffer[3] & !INDEX_WIDTH_MASK;
+    return Err(IncompatibleVersion(version >> 4, version & 0x0F));
+  }
+  Ok(())
+}
+
+/// ルートノード `root` を封印する封印レコードを直列化します。空の木構造の封印は i = 0 で表します。
+fn seal_record(root: Option<&Node>) -> Vec<u8> {
+  let mut record = Vec::with_capacity(SEAL_SIZE);
+  let (i, j, hash) = root.map(|r| (r.i, r.j, r.hash.value)).unwrap_or((0, 0, [0u8; HASH_SIZE]));
+  record.extend_from_slice(&i.to_le_bytes());
+  record.push(j);
+  record.extend_from_slice(&hash);
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, &record);
+  record.extend_from_slice(&std::hash::Hasher::finish(&hasher).to_le_bytes());
+  record.extend_from_slice(&SEAL_MARKER);
+  record
+}
+
+/// 長さ `length` のストレージの末尾に封印レコードが存在する場合、その先頭の位置と封印されたルートノードを返します。
+/// 識別子とチェックサムが一致しない末尾は封印レコードとはみなしません。
+pub(crate) fn read_seal<C: io::Read + io::Seek>(cursor: &mut C, length: u64) -> Result<Option<(u64, Option<Node>)>> {
+  let head = STORAGE_IDENTIFIER.len() as u64 + 1;
+  if length < head + SEAL_SIZE as u64 {
+    return Ok(None);
+  }
+  let start = length - SEAL_SIZE as u64;
+  let mut record = [0u8; SEAL_SIZE];
+  cursor.seek(SeekFrom::Start(start))?;
+  cursor.read_exact(&mut record)?;
+  let (body, trailer) = record.split_at(INDEX_BYTES + 1 + HASH_SIZE);
+  if trailer[8..] != SEAL_MARKER[..] {
+    return Ok(None);
+  }
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, body);
+  if std::hash::Hasher::finish(&hasher) != LittleEndian::read_u64(&trailer[..8]) {
+    return Ok(None);
+  }
+  let i = read_index(&mut &body[..INDEX_BYTES])?;
+  let root = if i == 0 {
+    None
+  } else {
+    let mut hash = [0u8; HASH_SIZE];
+    hash.copy_from_slice(&body[INDEX_BYTES + 1..]);
+    Some(Node::new(i, body[INDEX_BYTES], Hash::new(hash)))
+  };
+  Ok(Some((start, root)))
+}
+
+/// 1 から `n` までの整数のうち下位から `b` 番目のビットが 1 であるものの数を返します。
+fn sum_of_bit(n: u128, b: u32) -> u128 {
+  // 2^(b+1) ごとの周期のうち完全な周期では 2^b 個、残りの端数では 2^b 以上の部分が該当する
+  let half = 1u128 << b;
+  let full = n.checked_shr(b + 1).unwrap_or(0) << b;
+  let rest = n & (half | (half - 1));
+  full + if rest >= half { rest - half + 1 } else { 0 }
+}
+
+/// ストレージの末尾から、完全に書き込まれコミットされたエントリの終端を探します。
+///
+/// バッチで追加したエントリは最後のエントリを除いてペイロード長の最上位ビット ([`CONTINUED_FLAG`]) が設定されて
+/// おり、フラグのない最後のエントリがバッチのコミットを表します。破棄の対象となるのは末尾で書き込みが中断した
+/// 宣言された長さに満たないエントリと、コミットされていないバッチのエントリのみです。完全な長さを持つエントリが
+/// チェックサムの検証に失敗した場合や、コミット済みのバッチに読み込めないエントリがある場合は破損として
+/// [`Detail::DamagedStorage`] を返します。チェックサムの検証失敗は `metrics` に通知します。
+fn committed_end<C: io::Read + io::Seek>(cursor: &mut C, length: u64, metrics: &dyn MetricsSink) -> Result<u64> {
+  let head = STORAGE_IDENTIFIER.len() as u64 + 1;
+
+  // 中断した書き込みの残骸を遡って最後の完全なエントリの終端を探す
+  let mut end = length;
+  let mut last = None;
+  while end > head {
+    last = entry_ending_at(cursor, end, metrics)?;
+    if last.is_some() {
+      break;
+    }
+    end -= 1;
+  }
+  if end < length && !is_incomplete_entry(cursor, end, length)? {
+    return Err(DamagedStorage(format!("The entry at {} is corrupted.", end)));
+  }
+
+  // コミットされていないバッチのエントリ
+  while let Some((start, true)) = last {
+    end = start;
+    last = entry_ending_at(cursor, end, metrics)?;
+  }
+  if last.is_none() && end > head {
+    return Err(DamagedStorage(format!("The entry ending at {} is corrupted.", end)));
+  }
+
+  // コミットを表すエントリ: 同じバッチのエントリがすべて完全であることを確認
+  if let Some((start, false)) = last {
+    let mut mover = start;
+    while mover > head {
+      match entry_ending_at(cursor, mover, metrics)? {
+        Some((start, true)) => mover = start,
+        Some((_, false)) => break,
+        None => return Err(DamagedStorage(format!("The entry ending at {} is corrupted.", mover))),
+      }
+    }
+  }
+  Ok(end)
+}
+
+/// `end` で終わる完全なエントリが存在する場合、その先頭の位置とバッチの継続を表すフラグを返します。トレイラーが
+/// 指すエントリの構造が `end` で終わらない場合は `None` を返します。構造は完全でありながらチェックサムの検証に
+/// 失敗した場合は `metrics` に通知して [`Detail::DamagedStorage`] を返します。
+fn entry_ending_at<C: io::Read + io::Seek>(
+  cursor: &mut C,
+  end: u64,
+  metrics: &dyn MetricsSink,
+) -> Result<Option<(u64, bool)>> {
+  let head = STORAGE_IDENTIFIER.len() as u64 + 1;
+  if end < head + 4 + 8 {
+    return Ok(None);
+  }
+  cursor.seek(SeekFrom::Start(end - 4 - 8))?;
+  let offset = cursor.read_u32::<LittleEndian>()? as u64;
+  let start = match (end - 4 - 8).checked_sub(offset) {
+    Some(start) if start >= head => start,
+    _ => return Ok(None),
+  };
+  cursor.seek(SeekFrom::Start(start))?;
+  match observe(metrics, read_entry(cursor, 0)) {
+    Ok(_) if cursor.stream_position()? == end => (),
+    Ok(_) => return Ok(None),
+    Err(err @ ChecksumVerificationFailed { .. }) => {
+      return Err(DamagedStorage(format!("The entry at {} is corrupted: {}", start, err)));
+    }
+    Err(Detail::Io { source }) if source.kind() != io::ErrorKind::UnexpectedEof => return Err(source.into()),
+    Err(_) => return Ok(None),
+  }
+  cursor.seek(SeekFrom::Start(start))?;
+  let inode_count = {
+    cursor.seek(SeekFrom::Current(INDEX_BYTES as i64))?;
+    cursor.read_u8()? as i64
+  };
+  cursor.seek(SeekFrom::Current(inode_count * INODE_SIZE as i64))?;
+  let continued = cursor.read_u32::<LittleEndian>()? & CONTINUED_FLAG != 0;
+  Ok(Some((start, continued)))
+}
+
+/// `start` から `length` までの末尾が書き込みの途中で中断したエントリである場合に true を返します。エントリの
+/// ヘッダが宣言する長さが `length` を超えており、末尾のトレイラーが `start` を指していない場合に中断したものと
+/// みなします。
+fn is_incomplete_entry<C: io::Read + io::Seek>(cursor: &mut C, start: u64, length: u64) -> Result<bool> {
+  if length - start >= 4 + 8 {
+    cursor.seek(SeekFrom::Start(length - 4 - 8))?;
+    let offset = cursor.read_u32::<LittleEndian>()? as u64;
+    if (length - 4 - 8).checked_sub(offset) == Some(start) {
+      return Ok(false);
+    }
+  }
+  let header = (INDEX_BYTES + 1) as u64;
+  if length - start < header {
+    return Ok(true);
+  }
+  cursor.seek(SeekFrom::Start(start + INDEX_BYTES as u64))?;
+  let inodes = (cursor.read_u8()? as usize * INODE_SIZE) as u64;
+  if length - start < header + inodes + 4 {
+    return Ok(true);
+  }
+  cursor.seek(SeekFrom::Start(start + header + inodes))?;
+  let payload = (cursor.read_u32::<LittleEndian>()? & MAX_PAYLOAD_SIZE as u32) as u64;
+  Ok(start + ENTRY_OVERHEAD as u64 + inodes + payload > length)
+}
+
+/// 直列化されたエントリ `entry` のバッチの継続を表すフラグを設定し、チェックサムを再計算します。
+pub(crate) fn set_continued(entry: &mut [u8], continued: bool) {
+  let position = INDEX_BYTES + 1 + entry[INDEX_BYTES] as usize * INODE_SIZE;
+  let mut length = LittleEndian::read_u32(&entry[position..position + 4]) & MAX_PAYLOAD_SIZE as u32;
+  if continued {
+    length |= CONTINUED_FLAG;
+  }
+  LittleEndian::write_u32(&mut entry[position..position + 4], length);
+  let trailer = entry.len() - 8;
+  let mut hasher = HighwayBuilder::new(Key(CHECKSUM_HW64_KEY));
+  std::hash::Hasher::write(&mut hasher, &entry[..trailer]);
+  LittleEndian::write_u64(&mut entry[trailer..], std::hash::Hasher::finish(&hasher));
+}
+
+/// 指定されたカーソルを現在の位置から `distance` バイト前方に移動します。移動先がカーソルの先頭を超える場合は
+/// `if_err` をメッセージとしたエラーを発生します。
+#[inline]
+pub(crate) fn back_to_safety<C: io::Seek>(cursor: &mut C, distance: u32, if_err: &'static str) -> Result<u64> {
+  let from = cursor.stream_position()?;
+  let to = from - distance as u64;
+  if to < STORAGE_IDENTIFIER.len() as u64 + 1 {
+    Err(DamagedStorage(format!("{} (cannot move position from {} to {})", if_err, from, to)))
+  } else {
+    Ok(cursor.seek(io::SeekFrom::Current(-(distance as i64)))?)
+  }
+}
+
+/// panic_over_inconsistency が定義されている場合は panic して内部矛盾を検出した場所を知らせる。
+pub(crate) fn inconsistency<T>(msg: String) -> Result<T> {
+  #[cfg(feature = "panic_over_inconsistency")]
+  {
+    panic!("{}", msg)
+  }
+  #[cfg(not(feature = "panic_over_inconsistency"))]
+  {
+    Err(InternalStateInconsistency { message: msg })
+  }
+}
@@ -0,0 +1,113 @@
+//! serde で直列化した構造化データを値として追加、参照するための API です。
+//!
+//! 値の直列化形式は [`Codec`] で指定します。`typed_bincode` feature では [`Bincode`]、`typed_cbor` feature では
+//! [`Cbor`] を使用することができ、[`LMTHT::append_typed()`] と [`Query::get_typed()`] はいずれかを
+//! [`DefaultCodec`] として使用します。独自の形式を使用する場合は [`Codec`] を実装して
+//! [`LMTHT::append_typed_with()`] と [`Query::get_typed_with()`] に指定します。
+//!
+//! 直列化した値はそのままハッシュ木に保存されるため、証明の検証は直列化されたバイト列に対して行われます。同じ値を
+//! 検証するには追加したときと同じ [`Codec`] を使用する必要があります。
+//!
+//! ```rust,no_run
+//! use lmtht::LMTHT;
+//!
+//! # #[cfg(any(feature = "typed_bincode", feature = "typed_cbor"))]
+//! # fn main() -> lmtht::Result<()> {
+//! let db = LMTHT::new("events.db")?;
+//! db.append_typed(&("login", 1001u32))?;
+//! let event: Option<(String, u32)> = db.query()?.get_typed(1)?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "typed_bincode", feature = "typed_cbor")))]
+//! # fn main() {}
+//! ```
+//!
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::Detail;
+use crate::{Cursor, Index, Node, Query, Result, Storage, LMTHT};
+
+#[cfg(test)]
+mod test;
+
+/// 値を直列化、復元する形式です。
+pub trait Codec {
+  /// 値をバイト列に直列化します。
+  fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>>;
+
+  /// バイト列から値を復元します。
+  fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T>;
+}
+
+/// bincode で値を直列化する [`Codec`] です。
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+  fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|err| Detail::Serialization { message: err.to_string() })
+  }
+
+  fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+    bincode::deserialize(bytes).map_err(|err| Detail::Serialization { message: err.to_string() })
+  }
+}
+
+/// CBOR (RFC 8949) で値を直列化する [`Codec`] です。
+#[cfg(feature = "ciborium")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "ciborium")]
+impl Codec for Cbor {
+  fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes).map_err(|err| Detail::Serialization { message: err.to_string() })?;
+    Ok(bytes)
+  }
+
+  fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+    ciborium::de::from_reader(bytes).map_err(|err| Detail::Serialization { message: err.to_string() })
+  }
+}
+
+/// [`LMTHT::append_typed()`] と [`Query::get_typed()`] が使用する [`Codec`] です。`typed_bincode` feature が有効な
+/// 場合は [`Bincode`]、そうでなければ [`Cbor`] です。
+#[cfg(feature = "bincode")]
+pub type DefaultCodec = Bincode;
+
+/// [`LMTHT::append_typed()`] と [`Query::get_typed()`] が使用する [`Codec`] です。`typed_bincode` feature が有効な
+/// 場合は [`Bincode`]、そうでなければ [`Cbor`] です。
+#[cfg(all(feature = "ciborium", not(feature = "bincode")))]
+pub type DefaultCodec = Cbor;
+
+impl<S: Storage> LMTHT<S> {
+  /// 値を [`DefaultCodec`] で直列化して追加します。
+  #[cfg(any(feature = "bincode", feature = "ciborium"))]
+  pub fn append_typed<T: Serialize + ?Sized>(&self, value: &T) -> Result<Node> {
+    self.append_typed_with(&DefaultCodec::default(), value)
+  }
+
+  /// 値を指定された [`Codec`] で直列化して追加します。
+  pub fn append_typed_with<C: Codec, T: Serialize + ?Sized>(&self, codec: &C, value: &T) -> Result<Node> {
+    self.append(&codec.encode(value)?)
+  }
+}
+
+impl<C: Cursor> Query<C> {
+  /// インデックス `i` の値を [`DefaultCodec`] で復元して参照します。範囲外のインデックスを指定した場合は `None` を
+  /// 返します。
+  #[cfg(any(feature = "bincode", feature = "ciborium"))]
+  pub fn get_typed<T: DeserializeOwned>(&mut self, i: Index) -> Result<Option<T>> {
+    self.get_typed_with(&DefaultCodec::default(), i)
+  }
+
+  /// インデックス `i` の値を指定された [`Codec`] で復元して参照します。範囲外のインデックスを指定した場合は `None`
+  /// を返します。
+  pub fn get_typed_with<D: Codec, T: DeserializeOwned>(&mut self, codec: &D, i: Index) -> Result<Option<T>> {
+    self.get(i)?.map(|bytes| codec.decode(&bytes)).transpose()
+  }
+}
@@ -0,0 +1,30 @@
+use crate::error::Detail;
+use crate::*;
+
+/// 構造化データを直列化して追加し、同じ形式で復元できることを検証します。
+#[cfg(any(feature = "typed_bincode", feature = "typed_cbor"))]
+#[test]
+fn test_typed_values() {
+  let db = LMTHT::new(MemStorage::new()).unwrap();
+  let events = [("login".to_string(), 1001u32), ("logout".to_string(), 1001u32)];
+  for event in events.iter() {
+    db.append_typed(event).unwrap();
+  }
+  db.append(&[0xFFu8; 3]).unwrap();
+
+  let mut query = db.query().unwrap();
+  for (i, event) in events.iter().enumerate() {
+    assert_eq!(Some(event.clone()), query.get_typed::<(String, u32)>(i as Index + 1).unwrap());
+  }
+  assert_eq!(None, query.get_typed::<(String, u32)>(4).unwrap());
+  assert!(matches!(query.get_typed::<(String, u32)>(3), Err(Detail::Serialization { .. })));
+
+  #[cfg(all(feature = "typed_bincode", feature = "typed_cbor"))]
+  {
+    use crate::typed::{Bincode, Cbor};
+    let root = db.append_typed_with(&Cbor, &vec![1u8, 2, 3]).unwrap();
+    let mut query = db.query().unwrap();
+    assert_eq!(Some(vec![1u8, 2, 3]), query.get_typed_with(&Cbor, root.i).unwrap());
+    assert!(query.get_typed_with::<_, Vec<u8>>(&Bincode, root.i).is_err());
+  }
+}
@@ -0,0 +1,52 @@
+//! ブラウザなどの JavaScript 環境からサーバが提示した証明を検証するための wasm-bindgen API です。
+//!
+//! `wasm` feature を指定して `wasm32-unknown-unknown` 向けにビルドすると、以下の関数が JavaScript に公開されます。
+//! 証明はサーバ側で [`Query::get_values_with_hashes()`](crate::Query::get_values_with_hashes) の結果を
+//! `Proof::to_bytes()` で直列化したもので、[`ValuesWithBranches::to_bytes()`](crate::ValuesWithBranches::to_bytes)
+//! で直列化したバージョンを持たない形式の証明も受け付けます。ダッシュボードは別の経路で入手したルートハッシュに
+//! 対して証明を検証することで、サーバの主張をそのまま信用することなく値を表示することができます。
+//!
+//! ```js
+//! import { verifyProof } from "lmtht";
+//! if (!verifyProof(proof, rootHash)) {
+//!   throw new Error("the server returned a forged value");
+//! }
+//! ```
+//!
+use alloc::vec::Vec;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Proof, HASH_ALGORITHM};
+
+/// 直列化された証明 `proof` から算出したルートハッシュが `root_hash` と一致し、証明に記録された世代と高さが
+/// ルートノードと一致するかを検証します。
+///
+/// # Errors
+/// 証明の直列化形式が不正な場合、対応していないバージョンの場合、分岐ノードの並びが木構造として正しくない場合は
+/// 例外となります。
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(proof: &[u8], root_hash: &[u8]) -> Result<bool, JsValue> {
+  let proof = Proof::from_bytes(proof).map_err(|err| JsValue::from_str(err.message))?;
+  let root = proof.root().ok_or_else(|| JsValue::from_str("the branches of the proof are malformed"))?;
+  Ok(root.i == proof.n && root.j == proof.height && root.hash.value[..] == *root_hash)
+}
+
+/// 直列化された証明 `proof` から算出したルートハッシュを返します。
+///
+/// # Errors
+/// 証明の直列化形式が不正な場合、対応していないバージョンの場合、分岐ノードの並びが木構造として正しくない場合は
+/// 例外となります。
+#[wasm_bindgen(js_name = proofRootHash)]
+pub fn proof_root_hash(proof: &[u8]) -> Result<Vec<u8>, JsValue> {
+  let proof = Proof::from_bytes(proof).map_err(|err| JsValue::from_str(err.message))?;
+  let root = proof.root().ok_or_else(|| JsValue::from_str("the branches of the proof are malformed"))?;
+  Ok(root.hash.value.to_vec())
+}
+
+/// 証明の検証に使用するハッシュアルゴリズムの名前を返します。サーバと異なるアルゴリズムでビルドされていないことを
+/// 確認するために使用します。
+#[wasm_bindgen(js_name = hashAlgorithm)]
+pub fn hash_algorithm() -> JsValue {
+  JsValue::from_str(HASH_ALGORITHM)
+}
@@ -0,0 +1,21 @@
+//! ストレージの外部に保存した世代の記録によってストレージのロールバックを検出します。
+//!
+//! ストレージ自体に記録された情報だけでは、ファイルが過去の内容に差し替えられたり末尾が切り詰められたことを検出
+//! できません。[`LMTHTOptions::watermark`](crate::LMTHTOptions::watermark) に [`WatermarkStore`] を指定すると、
+//! LMTHT はこれまでに参照した最も新しい世代を記録し、それより少ないエントリしか持たないストレージに対して
+//! [`Detail::RolledBack`](crate::error::Detail::RolledBack) を返します。記録先はストレージとは別の信頼できる場所
+//! (TPM の単調カウンタや別のホストなど) である必要があります。
+//!
+use crate::{Index, Result};
+
+#[cfg(test)]
+mod test;
+
+/// LMTHT が参照した最も新しい世代を記録する、アプリケーションが提供する小さな記録領域です。
+pub trait WatermarkStore: Send + Sync {
+  /// 記録されている最も新しい世代を返します。まだ記録されていない場合は 0 を返します。
+  fn load(&self) -> Result<Index>;
+
+  /// 世代 `n` を記録します。`n` は常に [`WatermarkStore::load()`] が返す値より大きい値です。
+  fn store(&self, n: Index) -> Result<()>;
+}
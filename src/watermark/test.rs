@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::error::Detail;
+use crate::test::{random_payload, PAYLOAD_SIZE};
+use crate::*;
+
+/// 外部に記録した世代より古い内容に巻き戻されたストレージが拒否されることを検証します。
+#[test]
+fn test_watermark() {
+  struct Recorded(AtomicUsize);
+  impl watermark::WatermarkStore for Recorded {
+    fn load(&self) -> Result<Index> {
+      Ok(self.0.load(Ordering::SeqCst) as Index)
+    }
+    fn store(&self, n: Index) -> Result<()> {
+      assert!(n > self.load()?);
+      self.0.store(n as usize, Ordering::SeqCst);
+      Ok(())
+    }
+  }
+
+  let recorded = Arc::new(Recorded(AtomicUsize::new(0)));
+  let buffer = Arc::new(RwLock::new(Vec::new()));
+  let open = |buffer: &Arc<RwLock<Vec<u8>>>| {
+    let storage = MemStorage::with(buffer.clone());
+    LMTHT::builder(storage).watermark(recorded.clone()).open()
+  };
+  let db = open(&buffer).unwrap();
+  for i in 1..=5 {
+    db.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+    assert_eq!(i as usize, recorded.0.load(Ordering::SeqCst));
+  }
+  let snapshot = buffer.read().unwrap().clone();
+  let mut batch = db.begin_batch().unwrap();
+  for i in 6..=10 {
+    batch.append(&random_payload(PAYLOAD_SIZE, i)).unwrap();
+  }
+  batch.commit().unwrap();
+  assert_eq!(10, recorded.0.load(Ordering::SeqCst));
+  drop(db);
+  assert_eq!(10, open(&buffer).unwrap().n());
+
+  // 切り詰めや差し替え、削除されたストレージは記録された世代に満たない
+  let truncated = Arc::new(RwLock::new(snapshot));
+  assert!(matches!(open(&truncated), Err(Detail::RolledBack { recorded: 10, current: 5 })));
+  let empty = Arc::new(RwLock::new(Vec::new()));
+  assert!(matches!(open(&empty), Err(Detail::RolledBack { recorded: 10, current: 0 })));
+  assert_eq!(10, recorded.0.load(Ordering::SeqCst));
+}